@@ -0,0 +1,20 @@
+// Run with: cargo fuzz run compile fuzz/corpus/compile testcode
+// The test programs in testcode are the seed corpus.
+#![no_main]
+use std::thread;
+use libfuzzer_sys::fuzz_target;
+use menhir::COMPILER_STACK_SIZE;
+use menhir::fuzz::fuzz_compile;
+
+fuzz_target!(|data: &[u8]| {
+    let source = data.to_vec();
+    // Same stack size as the compiler itself, so deep nesting is reported as an error and not a stack overflow
+    let compiler = thread::Builder::new()
+        .stack_size(COMPILER_STACK_SIZE)
+        .spawn(move || {
+            let _ = fuzz_compile(&source);
+        })
+        .expect("Unable to start the compiler thread");
+
+    compiler.join().expect("The compiler panicked");
+});
@@ -20,28 +20,31 @@ echo "Current target triplet: ${triplet}"
 fail_count=0
 success_count=0
 
-for file in testcode/*.mhr; do
-	name=$(basename -s .mhr ${file})
-	echo "Testing ${name}"
-	if ! cargo run ${mode} -- build ${file} &> /tmp/compile_output.log; then
-		echo "*********************"
-		echo "  Compile failed"
-		cat /tmp/compile_output.log
-		echo "---------------------"
-		fail_count=$((fail_count + 1))
-	else
-		build/${triplet}/${name}/${name}
-		test_ret_value=$?
-		test_expected_ret_value=$(head -n 1 $file | cut -b 6-)
-		if [ "$test_ret_value" -ne "$test_expected_ret_value" ]; then
+# Every optimization level must give the same results
+for level in 0 1 2; do
+	for file in testcode/*.mhr; do
+		name=$(basename -s .mhr ${file})
+		echo "Testing ${name} (opt-level ${level})"
+		if ! cargo run ${mode} -- build --opt-level ${level} ${file} &> /tmp/compile_output.log; then
+			echo "*********************"
+			echo "  Compile failed"
+			cat /tmp/compile_output.log
+			echo "---------------------"
 			fail_count=$((fail_count + 1))
-			echo "  Run failed, expected $test_expected_ret_value, got $test_ret_value"
 		else
-			success_count=$((success_count + 1))
-			echo "  Run succeeded"
+			build/${triplet}/${name}/${name}
+			test_ret_value=$?
+			test_expected_ret_value=$(head -n 1 $file | cut -b 6-)
+			if [ "$test_ret_value" -ne "$test_expected_ret_value" ]; then
+				fail_count=$((fail_count + 1))
+				echo "  Run failed, expected $test_expected_ret_value, got $test_ret_value"
+			else
+				success_count=$((success_count + 1))
+				echo "  Run succeeded"
+			fi
 		fi
-	fi
-done 
+	done
+done
 
 echo "Tests:"
 echo "  fail:    ${fail_count}"
@@ -1,5 +1,5 @@
 use itertools::free::join;
-use ast::{Expression, Type, TreePrinter, prefix, StructPattern};
+use ast::{Cfg, Expression, Type, TreePrinter, prefix, StructPattern, ArrayPattern};
 use span::{Span};
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
@@ -7,6 +7,7 @@ pub enum BindingType
 {
     Name(String),
     Struct(StructPattern),
+    Array(ArrayPattern),
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
@@ -25,8 +26,11 @@ pub struct GlobalBinding
     pub mutable: bool,
     pub name: String,
     pub init: Expression,
-    pub typ: Type,
+    pub typ: Type, // Declared type, or Unknown when it has to be inferred from the initializer
     pub span: Span,
+    pub cfg: Option<Cfg>,
+    pub type_checked: bool,
+    pub doc: Option<String>,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
@@ -67,14 +71,17 @@ pub fn binding(bt: BindingType, init: Expression, mutable: bool, span: Span) ->
     }
 }
 
-pub fn global_binding(name: String, init: Expression, mutable: bool, span: Span) -> GlobalBinding
+pub fn global_binding(name: String, typ: Type, init: Expression, mutable: bool, span: Span) -> GlobalBinding
 {
     GlobalBinding{
         mutable: mutable,
         name: name,
         init: init,
-        typ: Type::Unknown,
+        typ: typ,
         span: span,
+        cfg: None,
+        type_checked: false,
+        doc: None,
     }
 }
 
@@ -98,8 +105,12 @@ impl TreePrinter for Binding
             },
 
             BindingType::Struct(ref s) => {
-                println!("{}struct binding {{{}}} =",
-                    p, join(s.bindings.iter(), ","));
+                println!("{}struct binding {}{{{}}} =",
+                    p, s.name, join(s.bindings.iter(), ","));
+            },
+
+            BindingType::Array(ref a) => {
+                println!("{}array binding [{} | {}] =", p, a.head, a.tail);
             },
         }
 
@@ -1,14 +1,149 @@
-use ast::{GenericMapping, Expression, Type, NameRef, TreePrinter, IntSize, func_type, prefix};
+use ast::{GenericMapping, Expression, Type, NameRef, TreePrinter, IntSize, func_type, optional_type, prefix};
 use span::{Span};
 
+// Functions provided by the compiler, which are called like regular functions
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Builtin
+{
+    WrappingAdd,
+    WrappingSub,
+    WrappingMul,
+    IsDigit,
+    IsAlpha,
+    IsWhitespace,
+    ToUpper,
+    ToLower,
+    CharToInt,
+    IntToChar,
+    Hash,
+    ApproxEq,
+    Min,
+    Max,
+    Abs,
+    Clamp,
+    Spawn,
+    Join,
+    Copy,
+    Fill,
+    CopyWithin,
+    Subslice,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin::WrappingAdd,
+    Builtin::WrappingSub,
+    Builtin::WrappingMul,
+    Builtin::IsDigit,
+    Builtin::IsAlpha,
+    Builtin::IsWhitespace,
+    Builtin::ToUpper,
+    Builtin::ToLower,
+    Builtin::CharToInt,
+    Builtin::IntToChar,
+    Builtin::Hash,
+    Builtin::ApproxEq,
+    Builtin::Min,
+    Builtin::Max,
+    Builtin::Abs,
+    Builtin::Clamp,
+    Builtin::Spawn,
+    Builtin::Join,
+    Builtin::Copy,
+    Builtin::Fill,
+    Builtin::CopyWithin,
+    Builtin::Subslice,
+];
+
+impl Builtin
+{
+    pub fn from_name(name: &str) -> Option<Builtin>
+    {
+        BUILTINS.iter().find(|b| b.name() == name).cloned()
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match *self
+        {
+            Builtin::WrappingAdd => "wrapping_add",
+            Builtin::WrappingSub => "wrapping_sub",
+            Builtin::WrappingMul => "wrapping_mul",
+            Builtin::IsDigit => "is_digit",
+            Builtin::IsAlpha => "is_alpha",
+            Builtin::IsWhitespace => "is_whitespace",
+            Builtin::ToUpper => "to_upper",
+            Builtin::ToLower => "to_lower",
+            Builtin::CharToInt => "char_to_int",
+            Builtin::IntToChar => "int_to_char",
+            Builtin::Hash => "hash",
+            Builtin::ApproxEq => "approx_eq",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+            Builtin::Abs => "abs",
+            Builtin::Clamp => "clamp",
+            Builtin::Spawn => "spawn",
+            Builtin::Join => "join",
+            Builtin::Copy => "copy",
+            Builtin::Fill => "fill",
+            Builtin::CopyWithin => "copy_within",
+            Builtin::Subslice => "subslice",
+        }
+    }
+
+    // Argument types and return type, None if the builtin works on any integer type,
+    // or in case of hash, on any hashable type, in case of approx_eq, on both float types,
+    // in case of min, max, abs and clamp, on any numeric type, and in case of copy, fill, copy_within and subslice, on any slice
+    pub fn signature(&self) -> Option<(Vec<Type>, Type)>
+    {
+        match *self
+        {
+            Builtin::WrappingAdd |
+            Builtin::WrappingSub |
+            Builtin::WrappingMul |
+            Builtin::Hash |
+            Builtin::ApproxEq |
+            Builtin::Min |
+            Builtin::Max |
+            Builtin::Abs |
+            Builtin::Clamp |
+            Builtin::Copy |
+            Builtin::Fill |
+            Builtin::CopyWithin |
+            Builtin::Subslice => None,
+            Builtin::IsDigit |
+            Builtin::IsAlpha |
+            Builtin::IsWhitespace => Some((vec![Type::Char], Type::Bool)),
+            Builtin::ToUpper |
+            Builtin::ToLower => Some((vec![Type::Char], Type::Char)),
+            Builtin::CharToInt => Some((vec![Type::Char], Type::UInt(IntSize::I32))),
+            Builtin::IntToChar => Some((vec![Type::UInt(IntSize::I32)], optional_type(Type::Char))),
+            // Lambdas cannot capture variables, so a spawned function only shares globals with the thread spawning it
+            Builtin::Spawn => Some((vec![func_type(Vec::new(), Type::Void)], Type::Thread)),
+            Builtin::Join => Some((vec![Type::Thread], Type::Void)),
+        }
+    }
+}
+
+// An argument passed by name: foo(width: 10), the type checker moves them into args in declaration order
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct NamedArgument
+{
+    pub name: String,
+    pub value: Expression,
+    pub span: Span,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Call
 {
     pub callee: NameRef,
     pub args: Vec<Expression>,
+    pub named_args: Vec<NamedArgument>,
     pub span: Span,
+    pub type_args: Vec<Type>,
     pub generic_args: GenericMapping,
     pub return_type: Type,
+    pub builtin: Option<Builtin>,
 }
 
 impl Call
@@ -18,9 +153,12 @@ impl Call
         Call{
             callee: callee,
             args: args,
+            named_args: Vec::new(),
             span: span,
+            type_args: Vec::new(),
             generic_args: GenericMapping::new(),
             return_type: Type::Unknown,
+            builtin: None,
         }
     }
 
@@ -40,5 +178,10 @@ impl TreePrinter for Call
         for a in &self.args {
             a.print(level + 1);
         }
+
+        for na in &self.named_args {
+            println!("{} {}: {}", p, na.name, na.span);
+            na.value.print(level + 2);
+        }
     }
 }
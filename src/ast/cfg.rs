@@ -0,0 +1,52 @@
+use std::fmt;
+use itertools::free::join;
+use target::Target;
+use span::Span;
+
+// Condition of a #[cfg(...)] attribute on a top level declaration
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub enum Cfg
+{
+    Option{key: String, value: String, span: Span},
+    Not(Box<Cfg>),
+    Any(Vec<Cfg>),
+    All(Vec<Cfg>),
+}
+
+pub const CFG_KEYS: &[&str] = &["target_os", "target_arch", "feature"];
+
+impl Cfg
+{
+    pub fn is_enabled(&self, target: &Target) -> bool
+    {
+        match *self
+        {
+            Cfg::Option{ref key, ref value, ..} => {
+                match &key[..]
+                {
+                    "target_os" => target.os() == value,
+                    "target_arch" => target.arch() == value,
+                    "feature" => target.features.contains(value),
+                    _ => false,
+                }
+            },
+            Cfg::Not(ref cfg) => !cfg.is_enabled(target),
+            Cfg::Any(ref cfgs) => cfgs.iter().any(|c| c.is_enabled(target)),
+            Cfg::All(ref cfgs) => cfgs.iter().all(|c| c.is_enabled(target)),
+        }
+    }
+}
+
+impl fmt::Display for Cfg
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
+    {
+        match *self
+        {
+            Cfg::Option{ref key, ref value, ..} => write!(f, "{} = \"{}\"", key, value),
+            Cfg::Not(ref cfg) => write!(f, "not({})", cfg),
+            Cfg::Any(ref cfgs) => write!(f, "any({})", join(cfgs.iter(), ", ")),
+            Cfg::All(ref cfgs) => write!(f, "all({})", join(cfgs.iter(), ", ")),
+        }
+    }
+}
@@ -5,7 +5,12 @@ use span::Span;
 pub enum CompilerCall
 {
     SizeOf(Type, Span),
-    Slice{data: Box<Expression>, len: Box<Expression>, typ: Type, span: Span}
+    AlignOf(Type, Span),
+    Slice{data: Box<Expression>, len: Box<Expression>, typ: Type, span: Span},
+    TypeName(Box<Expression>, Span),
+    // The message and the type name are constant strings, folded by the type checker
+    StaticAssert{cond: Box<Expression>, message: Box<Expression>, span: Span},
+    AssertType{expr: Box<Expression>, typ: Box<Expression>, span: Span}, // Only in test builds
 }
 
 
@@ -14,8 +19,12 @@ impl CompilerCall
     pub fn get_type(&self, int_size: IntSize) -> Type
     {
         match *self {
-            CompilerCall::SizeOf(_, _) => Type::UInt(int_size),
+            CompilerCall::SizeOf(_, _) |
+            CompilerCall::AlignOf(_, _) => Type::UInt(int_size),
             CompilerCall::Slice{ref typ, ..} => typ.clone(),
+            CompilerCall::TypeName(_, _) => Type::String,
+            CompilerCall::StaticAssert{..} |
+            CompilerCall::AssertType{..} => Type::Void,
         }
     }
 }
@@ -26,12 +35,27 @@ impl TreePrinter for CompilerCall
     {
         let p = prefix(level);
         match *self {
-            CompilerCall::SizeOf(ref typ, ref span) => println!("{}@size({}) (span: {})", p, typ, span),
+            CompilerCall::SizeOf(ref typ, ref span) => println!("{}@size_of({}) (span: {})", p, typ, span),
+            CompilerCall::AlignOf(ref typ, ref span) => println!("{}@align_of({}) (span: {})", p, typ, span),
             CompilerCall::Slice{ref data, ref len, ref typ, ref span} => {
                 println!("{}@slice (span: {}, type: {})", p, span, typ);
                 data.print(level + 1);
                 len.print(level + 1);
             }
+            CompilerCall::TypeName(ref e, ref span) => {
+                println!("{}@type_name (span: {})", p, span);
+                e.print(level + 1);
+            }
+            CompilerCall::StaticAssert{ref cond, ref message, ref span} => {
+                println!("{}@static_assert (span: {})", p, span);
+                cond.print(level + 1);
+                message.print(level + 1);
+            }
+            CompilerCall::AssertType{ref expr, ref typ, ref span} => {
+                println!("{}@assert_type (span: {})", p, span);
+                expr.print(level + 1);
+                typ.print(level + 1);
+            }
         }
     }
 }
\ No newline at end of file
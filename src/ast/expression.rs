@@ -1,4 +1,3 @@
-use std::error::Error;
 use span::Span;
 use ast::*;
 
@@ -10,6 +9,15 @@ pub struct ToOptional
     pub optional_type: Type,
 }
 
+// A value, or a pointer to one, used as a value of an interface type
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ToInterface
+{
+    pub inner: Expression,
+    pub interface_type: Type,
+    pub methods: Vec<String>, // Functions implementing the interface's functions in the same order, filled in by the type checker
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct TypeCast
 {
@@ -18,6 +26,15 @@ pub struct TypeCast
     pub span: Span,
 }
 
+// (expr : type) : expr must have the given type, or be convertible to it
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TypeAscription
+{
+    pub inner: Expression,
+    pub typ: Type,
+    pub span: Span,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Nil
 {
@@ -32,6 +49,24 @@ pub struct Return
     pub span: Span,
 }
 
+// _ = expr : evaluates expr and explicitly ignores its value
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Discard
+{
+    pub inner: Expression,
+    pub span: Span,
+}
+
+// expr? : the value of an Ok, or return the Err from the current function
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Propagate
+{
+    pub inner: Expression,
+    pub typ: Type,
+    pub return_type: Type,
+    pub span: Span,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Expression
 {
@@ -42,7 +77,9 @@ pub enum Expression
     Call(Box<Call>),
     NameRef(NameRef),
     Match(Box<MatchExpression>),
+    Is(Box<IsExpression>),
     If(Box<IfExpression>),
+    IfLet(Box<IfLetExpression>),
     Lambda(Box<Lambda>),
     Bindings(Box<BindingList>),
     StructInitializer(StructInitializer),
@@ -58,10 +95,14 @@ pub enum Expression
     Nil(Nil),
     OptionalToBool(Box<Expression>),
     ToOptional(Box<ToOptional>),
+    ToInterface(Box<ToInterface>),
     Cast(Box<TypeCast>),
+    TypeAscription(Box<TypeAscription>),
     CompilerCall(CompilerCall),
     IndexOperation(Box<IndexOperation>),
     Return(Box<Return>),
+    Propagate(Box<Propagate>),
+    Discard(Box<Discard>),
     Void,
 }
 
@@ -73,6 +114,15 @@ pub fn to_optional(e: Expression, typ: Type) -> Expression
     }))
 }
 
+pub fn to_interface(e: Expression, typ: Type) -> Expression
+{
+    Expression::ToInterface(Box::new(ToInterface{
+        inner: e,
+        interface_type: typ,
+        methods: Vec::new(),
+    }))
+}
+
 pub fn type_cast(e: Expression, dst_type: Type, span: Span) -> Expression
 {
     Expression::Cast(Box::new(TypeCast{
@@ -90,11 +140,58 @@ pub fn nil_expr(span: Span) -> Expression
     })
 }
 
+pub fn type_ascription(e: Expression, typ: Type, span: Span) -> Expression
+{
+    Expression::TypeAscription(Box::new(TypeAscription{
+        inner: e,
+        typ,
+        span,
+    }))
+}
+
 pub fn return_expr(expression: Expression, span: Span) -> Expression
 {
     Expression::Return(Box::new(Return{expression, span}))
 }
 
+pub fn discard(inner: Expression, span: Span) -> Expression
+{
+    Expression::Discard(Box::new(Discard{inner, span}))
+}
+
+pub fn propagate(inner: Expression, span: Span) -> Expression
+{
+    Expression::Propagate(Box::new(Propagate{
+        inner,
+        typ: Type::Unknown,
+        return_type: Type::Unknown,
+        span,
+    }))
+}
+
+// If typ is a Result, returns the index and the payload type of the Ok and the Err case
+pub fn result_cases(typ: &Type) -> Option<((usize, Type), (usize, Type))>
+{
+    let st = match *typ {
+        Type::Sum(ref st) => st,
+        _ => return None,
+    };
+
+    let case = |name: &str| {
+        let idx = st.cases.iter().position(|c| c.name.ends_with(name))?;
+        match st.cases[idx].typ {
+            Type::Struct(ref s) if s.members.len() == 1 => Some((idx, s.members[0].typ.clone())),
+            _ => None,
+        }
+    };
+
+    if st.cases.len() != 2 {
+        return None;
+    }
+
+    Some((case("Result::Ok")?, case("Result::Err")?))
+}
+
 pub fn nil_expr_with_type(span: Span, optional_inner_type: Type) -> Expression
 {
     Expression::Nil(Nil{
@@ -150,9 +247,11 @@ impl Expression
             Expression::Call(ref c) => c.span.clone(),
             Expression::NameRef(ref nr) => nr.span.clone(),
             Expression::Match(ref m) => m.span.clone(),
+            Expression::Is(ref i) => i.span.clone(),
             Expression::Lambda(ref l) => l.span.clone(),
             Expression::Bindings(ref l) => l.span.clone(),
             Expression::If(ref i) => i.span.clone(),
+            Expression::IfLet(ref i) => i.span.clone(),
             Expression::StructInitializer(ref si) => si.span.clone(),
             Expression::MemberAccess(ref sma) => sma.span.clone(),
             Expression::New(ref n) => n.span.clone(),
@@ -166,11 +265,19 @@ impl Expression
             Expression::Nil(ref nt) => nt.span.clone(),
             Expression::OptionalToBool(ref inner) => inner.span(),
             Expression::ToOptional(ref t) => t.inner.span(),
+            Expression::ToInterface(ref t) => t.inner.span(),
             Expression::Cast(ref t) => t.span.clone(),
-            Expression::CompilerCall(CompilerCall::SizeOf(_, ref span)) => span.clone(),
-            Expression::CompilerCall(CompilerCall::Slice{ref span, ..}) => span.clone(),
+            Expression::TypeAscription(ref t) => t.span.clone(),
+            Expression::CompilerCall(CompilerCall::SizeOf(_, ref span)) |
+            Expression::CompilerCall(CompilerCall::AlignOf(_, ref span)) => span.clone(),
+            Expression::CompilerCall(CompilerCall::Slice{ref span, ..}) |
+            Expression::CompilerCall(CompilerCall::TypeName(_, ref span)) |
+            Expression::CompilerCall(CompilerCall::StaticAssert{ref span, ..}) |
+            Expression::CompilerCall(CompilerCall::AssertType{ref span, ..}) => span.clone(),
             Expression::IndexOperation(ref iop) => iop.span.clone(),
             Expression::Return(ref r) => r.span.clone(),
+            Expression::Propagate(ref p) => p.span.clone(),
+            Expression::Discard(ref d) => d.span.clone(),
             Expression::Void => Span::default(),
         }
     }
@@ -186,9 +293,11 @@ impl Expression
             Expression::Call(ref c) => c.return_type.clone(),
             Expression::NameRef(ref nr) => nr.typ.clone(),
             Expression::Match(ref m) => m.typ.clone(),
+            Expression::Is(_) => Type::Bool,
             Expression::Lambda(ref l) => l.sig.get_type(),
             Expression::Bindings(ref l) => l.bindings.last().map(|b| b.typ.clone()).expect("Binding types are not known"),
             Expression::If(ref i) => i.typ.clone(),
+            Expression::IfLet(ref i) => i.typ.clone(),
             Expression::StructInitializer(ref si) => si.typ.clone(),
             Expression::MemberAccess(ref sma) => sma.typ.clone(),
             Expression::New(ref n) => n.typ.clone(),
@@ -199,358 +308,32 @@ impl Expression
             Expression::Nil(ref nt) => nt.typ.clone(),
             Expression::OptionalToBool(_) => Type::Bool,
             Expression::ToOptional(ref t) => optional_type(t.inner.get_type(int_size)),
+            Expression::ToInterface(ref t) => t.interface_type.clone(),
             Expression::Cast(ref t) => t.destination_type.clone(),
+            Expression::TypeAscription(ref t) => t.typ.clone(),
             Expression::CompilerCall(ref cc) => cc.get_type(int_size),
             Expression::IndexOperation(ref iop) => iop.typ.clone(),
             Expression::Return(ref r) => r.expression.get_type(int_size),
+            Expression::Propagate(ref p) => p.typ.clone(),
             Expression::Void |
+            Expression::Discard(_) |
             Expression::While(_) |
             Expression::Delete(_) |
             Expression::For(_) => Type::Void,
         }
     }
 
-    pub fn visit_mut<E, Op>(&mut self, op: &mut Op) -> Result<(), E>
-        where E: Error,
-              Op: FnMut(&mut Expression) -> Result<(), E>
-    {
-        op(self)?;
-        match *self
-        {
-            Expression::UnaryOp(ref mut uop) => {
-                uop.expression.visit_mut(op)
-            },
-
-            Expression::BinaryOp(ref mut bop) => {
-                bop.left.visit_mut(op)?;
-                bop.right.visit_mut(op)
-            },
-
-            Expression::Literal(Literal::Array(ref mut a)) => {
-                for el in &mut a.elements {
-                    el.visit_mut(op)?;
-                }
-                Ok(())
-            },
-
-            Expression::Call(ref mut call) => {
-                for a in &mut call.args {
-                    a.visit_mut(op)?;
-                }
-                Ok(())
-            },
-
-            Expression::Lambda(ref mut l) => {
-                l.expr.visit_mut(op)
-            },
-
-            Expression::Match(ref mut m) => {
-                m.target.visit_mut(op)?;
-                for c in &mut m.cases
-                {
-                    if let Pattern::Literal(Literal::Array(ref mut al)) = c.pattern {
-                        for el in &mut al.elements {
-                            el.visit_mut(op)?;
-                        }
-                    }
-                    c.to_execute.visit_mut(op)?;
-                }
-                Ok(())
-            },
-
-            Expression::Bindings(ref mut l) => {
-                for b in &mut l.bindings {
-                    b.init.visit_mut(op)?
-                }
-                Ok(())
-            },
-
-            Expression::Block(ref mut b) => {
-                for e in &mut b.expressions {
-                    e.visit_mut(op)?;
-                }
-                Ok(())
-            },
-
-            Expression::New(ref mut n) => {
-                n.inner.visit_mut(op)
-            },
-
-            Expression::Delete(ref mut d) => {
-                d.inner.visit_mut(op)
-            },
-
-            Expression::ArrayToSlice(ref mut ats) => {
-                ats.inner.visit_mut(op)
-            },
-
-            Expression::Return(ref mut r) => {
-                r.expression.visit_mut(op)
-            },
-
-            Expression::If(ref mut i) => {
-                i.condition.visit_mut(op)?;
-                i.on_true.visit_mut(op)?;
-                if let Some(ref mut e) = i.on_false {
-                    e.visit_mut(op)?;
-                }
-                Ok(())
-            }
-
-            Expression::StructInitializer(ref mut si) => {
-                for e in &mut si.member_initializers {
-                    e.visit_mut(op)?;
-                }
-                Ok(())
-            }
-
-            Expression::AddressOf(ref mut a) => {
-                a.inner.visit_mut(op)
-            }
-
-            Expression::Dereference(ref mut d) => {
-                d.inner.visit_mut(op)
-            }
-
-            Expression::While(ref mut w) => {
-                w.cond.visit_mut(op)?;
-                w.body.visit_mut(op)
-            }
-
-            Expression::Assign(ref mut a) => {
-                match a.left {
-                    AssignTarget::Dereference(ref mut d) => d.inner.visit_mut(op)?,
-                    AssignTarget::IndexOperation(ref mut iop) => {
-                        iop.target.visit_mut(op)?;
-                        iop.index_expr.visit_mut(op)?;
-                    },
-                    AssignTarget::MemberAccess(ref mut ma) => {
-                        ma.left.visit_mut(op)?;
-                        if let MemberAccessType::Call(ref mut call) = ma.right {
-                            for a in &mut call.args {
-                                a.visit_mut(op)?;
-                            }
-                        }
-                    }
-                    _ => (),
-                }
-                a.right.visit_mut(op)
-            }
-
-            Expression::For(ref mut f) => {
-                f.iterable.visit_mut(op)?;
-                f.body.visit_mut(op)
-            }
-
-            Expression::OptionalToBool(ref mut o) => {
-                o.visit_mut(op)
-            }
-
-            Expression::MemberAccess(ref mut ma) => {
-                ma.left.visit_mut(op)?;
-                if let MemberAccessType::Call(ref mut call) = ma.right {
-                    for a in &mut call.args {
-                        a.visit_mut(op)?;
-                    }
-                }
-                Ok(())
-            }
-
-            Expression::ToOptional(ref mut t) => {
-                t.inner.visit_mut(op)
-            }
-
-            Expression::Cast(ref mut c) => {
-                c.inner.visit_mut(op)
-            }
-
-            Expression::IndexOperation(ref mut iop) => {
-                iop.target.visit_mut(op)?;
-                iop.index_expr.visit_mut(op)
-            }
-
-            Expression::CompilerCall(CompilerCall::Slice{ref mut data, ref mut len, ..}) => {
-                data.visit_mut(op)?;
-                len.visit_mut(op)
-            }
-
-            Expression::Literal(_) |
-            Expression::Void |
-            Expression::CompilerCall(_) |
-            Expression::Nil(_) |
-            Expression::NameRef(_) => Ok(())
-        }
-    }
-
-    pub fn visit<E, Op>(&self, op: &mut Op) -> Result<(), E>
-        where E: Error,
-              Op: FnMut(&Expression) -> Result<(), E>
+    // Whether every way through the expression ends in a return, so it never has a value
+    pub fn diverges(&self) -> bool
     {
-        op(self)?;
         match *self
         {
-            Expression::UnaryOp(ref uop) => {
-                uop.expression.visit(op)
-            },
-
-            Expression::BinaryOp(ref bop) => {
-                bop.left.visit(op)?;
-                bop.right.visit(op)
-            },
-
-            Expression::Literal(Literal::Array(ref a)) => {
-                for el in &a.elements {
-                    el.visit(op)?;
-                }
-                Ok(())
-            },
-
-            Expression::Call(ref call) => {
-                for a in &call.args {
-                    a.visit(op)?;
-                }
-                Ok(())
-            },
-
-            Expression::Lambda(ref l) => {
-                l.expr.visit(op)
-            },
-
-            Expression::Match(ref m) => {
-                m.target.visit(op)?;
-                for c in &m.cases
-                    {
-                        if let Pattern::Literal(Literal::Array(ref al)) = c.pattern {
-                            for el in &al.elements {
-                                el.visit(op)?;
-                            }
-                        }
-                        c.to_execute.visit(op)?;
-                    }
-                Ok(())
-            },
-
-            Expression::Bindings(ref l) => {
-                for b in &l.bindings {
-                    b.init.visit(op)?
-                }
-                Ok(())
-            },
-
-            Expression::Block(ref b) => {
-                for e in &b.expressions {
-                    e.visit(op)?;
-                }
-                Ok(())
-            },
-
-            Expression::New(ref n) => {
-                n.inner.visit(op)
-            },
-
-            Expression::Delete(ref d) => {
-                d.inner.visit(op)
-            },
-
-            Expression::ArrayToSlice(ref ats) => {
-                ats.inner.visit(op)
-            },
-
-            Expression::Return(ref r) => {
-                r.expression.visit(op)
-            },
-
-            Expression::If(ref i) => {
-                i.condition.visit(op)?;
-                i.on_true.visit(op)?;
-                if let Some(ref e) = i.on_false {
-                    e.visit(op)?;
-                }
-                Ok(())
-            }
-
-            Expression::StructInitializer(ref si) => {
-                for e in &si.member_initializers {
-                    e.visit(op)?;
-                }
-                Ok(())
-            }
-
-            Expression::AddressOf(ref a) => {
-                a.inner.visit(op)
-            }
-
-            Expression::Dereference(ref d) => {
-                d.inner.visit(op)
-            }
-
-            Expression::While(ref w) => {
-                w.cond.visit(op)?;
-                w.body.visit(op)
-            }
-
-            Expression::Assign(ref a) => {
-                match a.left {
-                    AssignTarget::Dereference(ref d) => d.inner.visit(op)?,
-                    AssignTarget::IndexOperation(ref iop) => {
-                        iop.target.visit(op)?;
-                        iop.index_expr.visit(op)?;
-                    },
-                    AssignTarget::MemberAccess(ref ma) => {
-                        ma.left.visit(op)?;
-                        if let MemberAccessType::Call(ref call) = ma.right {
-                            for a in &call.args {
-                                a.visit(op)?;
-                            }
-                        }
-                    },
-                    _ => (),
-                }
-                a.right.visit(op)
-            }
-
-            Expression::For(ref f) => {
-                f.iterable.visit(op)?;
-                f.body.visit(op)
-            }
-
-            Expression::OptionalToBool(ref o) => {
-                o.visit(op)
-            }
-
-            Expression::MemberAccess(ref ma) => {
-                ma.left.visit(op)?;
-                if let MemberAccessType::Call(ref call) = ma.right {
-                    for a in &call.args {
-                        a.visit(op)?;
-                    }
-                }
-                Ok(())
-            }
-
-            Expression::ToOptional(ref t) => {
-                t.inner.visit(op)
-            }
-
-            Expression::Cast(ref c) => {
-                c.inner.visit(op)
-            }
-
-            Expression::IndexOperation(ref iop) => {
-                iop.target.visit(op)?;
-                iop.index_expr.visit(op)
-            }
-
-            Expression::CompilerCall(CompilerCall::Slice{ref data, ref len, ..}) => {
-                data.visit(op)?;
-                len.visit(op)
-            }
-
-            Expression::Literal(_) |
-            Expression::Void |
-            Expression::CompilerCall(_) |
-            Expression::Nil(_) |
-            Expression::NameRef(_) => Ok(())
+            Expression::Return(_) => true,
+            Expression::Block(ref b) => b.expressions.iter().any(|e| e.diverges()),
+            Expression::If(ref i) => i.on_true.diverges() && i.on_false.as_ref().map(|e| e.diverges()).unwrap_or(false),
+            Expression::IfLet(ref i) => i.on_true.diverges() && i.on_false.as_ref().map(|e| e.diverges()).unwrap_or(false),
+            Expression::Match(ref m) => !m.cases.is_empty() && m.cases.iter().all(|c| c.to_execute.diverges()),
+            _ => false,
         }
     }
 }
@@ -582,9 +365,11 @@ impl TreePrinter for Expression
             Expression::Call(ref c) => c.print(level),
             Expression::NameRef(ref nr) => nr.print(level),
             Expression::Match(ref m) => m.print(level),
+            Expression::Is(ref i) => i.print(level),
             Expression::Lambda(ref l) => l.print(level),
             Expression::Bindings(ref l) => l.print(level),
             Expression::If(ref i) => i.print(level),
+            Expression::IfLet(ref i) => i.print(level),
             Expression::StructInitializer(ref si) => si.print(level),
             Expression::MemberAccess(ref sma) => sma.print(level),
             Expression::New(ref n) => n.print(level),
@@ -607,10 +392,18 @@ impl TreePrinter for Expression
                 println!("{}to_optional (type: {})", p, t.optional_type);
                 t.inner.print(level + 1)
             },
+            Expression::ToInterface(ref t) => {
+                println!("{}to_interface (type: {})", p, t.interface_type);
+                t.inner.print(level + 1)
+            },
             Expression::Cast(ref t) => {
                 println!("{}cast to {} ({})", p, t.destination_type, t.span);
                 t.inner.print(level + 1)
             },
+            Expression::TypeAscription(ref t) => {
+                println!("{}type ascription {} ({})", p, t.typ, t.span);
+                t.inner.print(level + 1)
+            },
             Expression::CompilerCall(ref cc) => cc.print(level),
             Expression::IndexOperation(ref iop) => iop.print(level),
             Expression::Void => println!("{}void", p),
@@ -618,6 +411,14 @@ impl TreePrinter for Expression
                 println!("{}return", p);
                 r.expression.print(level + 1)
             }
+            Expression::Propagate(ref pr) => {
+                println!("{}propagate (type: {})", p, pr.typ);
+                pr.inner.print(level + 1)
+            }
+            Expression::Discard(ref d) => {
+                println!("{}discard ({})", p, d.span);
+                d.inner.print(level + 1)
+            }
         }
     }
 }
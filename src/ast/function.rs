@@ -1,13 +1,15 @@
-use ast::{Type, Expression, TreePrinter, prefix, func_type};
+use std::hash::{Hash, Hasher};
+use ast::{Cfg, Type, Expression, TreePrinter, prefix, func_type, variadic_func_type};
 use span::{Span};
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Argument
 {
     pub name: String,
     pub typ: Type,
     pub mutable: bool,
     pub span: Span,
+    pub default: Option<Expression>, // Passed when a call leaves out the argument, only trailing arguments can have one
 }
 
 impl Argument
@@ -19,16 +21,33 @@ impl Argument
             typ: typ,
             mutable: mutable,
             span: span,
+            default: None,
         }
     }
 }
 
+// Expressions are not hashable, arguments which only differ in their default value get the same hash
+impl Hash for Argument
+{
+    fn hash<H: Hasher>(&self, state: &mut H)
+    {
+        self.name.hash(state);
+        self.typ.hash(state);
+        self.mutable.hash(state);
+        self.span.hash(state);
+    }
+}
+
 impl TreePrinter for Argument
 {
     fn print(&self, level: usize)
     {
         let p = prefix(level);
         println!("{}{}: {} (span: {})", p, self.name, self.typ, self.span);
+        if let Some(ref default) = self.default {
+            println!("{} default:", p);
+            default.print(level + 2);
+        }
     }
 }
 
@@ -40,6 +59,10 @@ pub struct FunctionSignature
     pub args: Vec<Argument>,
     pub span: Span,
     pub typ: Type,
+    pub variadic: bool,
+    pub inline: bool, // Set by the inline attribute, calls to it are always inlined unless it is recursive
+    pub export: Option<String>, // Set by the export attribute, the symbol name C code calls the function by
+    pub link_name: Option<String>, // Set by the link_name attribute on an external function, the C symbol it is declared as
 }
 
 impl FunctionSignature
@@ -60,6 +83,10 @@ impl FunctionSignature
                 }).collect(),
                 span: Span::default(),
                 typ: typ.clone(),
+                variadic: ft.variadic,
+                inline: false,
+                export: None,
+                link_name: None,
             };
 
             Some(s)
@@ -70,10 +97,12 @@ impl FunctionSignature
 
     pub fn get_type(&self) -> Type
     {
-        func_type(
-            self.args.iter().map(|arg| arg.typ.clone()).collect(),
-            self.return_type.clone()
-        )
+        let args = self.args.iter().map(|arg| arg.typ.clone()).collect();
+        if self.variadic {
+            variadic_func_type(args, self.return_type.clone())
+        } else {
+            func_type(args, self.return_type.clone())
+        }
     }
 }
 
@@ -88,6 +117,9 @@ impl TreePrinter for FunctionSignature
         for a in &self.args {
             a.print(level + 2);
         }
+        if self.variadic {
+            println!("{}  ...", p);
+        }
     }
 }
 
@@ -100,6 +132,8 @@ pub struct Function
     pub span: Span,
     pub type_checked: bool,
     pub generics_resolved: bool,
+    pub cfg: Option<Cfg>,
+    pub doc: Option<String>,
 }
 
 impl Function
@@ -113,6 +147,8 @@ impl Function
             span: span,
             type_checked: false,
             generics_resolved: false,
+            cfg: None,
+            doc: None,
         }
     }
 
@@ -141,14 +177,36 @@ pub fn sig(name: &str, ret: Type, args: Vec<Argument>, span: Span) -> FunctionSi
         args: args,
         span: span,
         typ: Type::Unknown,
+        variadic: false,
+        inline: false,
+        export: None,
+        link_name: None,
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum LinkKind
+{
+    Static,
+    Dylib,
+}
+
+// A library named by a link attribute, which provides an external function
+#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+pub struct LinkLibrary
+{
+    pub name: String,
+    pub kind: LinkKind,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct ExternalFunction
 {
     pub sig: FunctionSignature,
     pub span: Span,
+    pub cfg: Option<Cfg>,
+    pub doc: Option<String>,
+    pub libraries: Vec<LinkLibrary>, // Set by link attributes, the libraries which have to be linked in for it
 }
 
 impl ExternalFunction
@@ -158,6 +216,9 @@ impl ExternalFunction
         ExternalFunction{
             sig: sig,
             span: span,
+            cfg: None,
+            doc: None,
+            libraries: Vec::new(),
         }
     }
 }
@@ -48,3 +48,64 @@ impl TreePrinter for IfExpression
         }
     }
 }
+
+// A binding of an if let expression, name is bound to the value inside the optional init
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct IfLetBinding
+{
+    pub name: String,
+    pub init: Expression,
+    pub inner_type: Type,
+    pub span: Span,
+}
+
+pub fn if_let_binding(name: String, init: Expression, span: Span) -> IfLetBinding
+{
+    IfLetBinding{
+        name: name,
+        init: init,
+        inner_type: Type::Unknown,
+        span: span,
+    }
+}
+
+// if let x = a, y = b: on_true else on_false, on_true is only executed when all bindings are not nil
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct IfLetExpression
+{
+    pub bindings: Vec<IfLetBinding>,
+    pub on_true: Expression,
+    pub on_false: Option<Expression>,
+    pub span: Span,
+    pub typ: Type,
+}
+
+pub fn if_let_expression(bindings: Vec<IfLetBinding>, on_true: Expression, on_false: Option<Expression>, span: Span) -> Expression
+{
+    Expression::IfLet(Box::new(IfLetExpression{
+        bindings: bindings,
+        on_true: on_true,
+        on_false: on_false,
+        span: span,
+        typ: Type::Unknown,
+    }))
+}
+
+impl TreePrinter for IfLetExpression
+{
+    fn print(&self, level: usize)
+    {
+        let p = prefix(level);
+        println!("{}if let ({}) (type {})", p, self.span, self.typ);
+        for b in &self.bindings {
+            println!("{} binding {} ({})", p, b.name, b.span);
+            b.init.print(level + 2);
+        }
+        println!("{} then", p);
+        self.on_true.print(level + 2);
+        if let Some(ref on_false) = self.on_false {
+            println!("{} else", p);
+            on_false.print(level + 2);
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::fmt;
 use std::rc::Rc;
 use itertools::join;
 use span::Span;
-use ast::Function;
+use ast::{Argument, Function};
 use super::{Type};
 
 
@@ -52,6 +52,8 @@ pub struct Symbol
     pub mutable: bool,
     pub span: Span,
     pub symbol_type: SymbolType,
+    pub args: Vec<Argument>, // The declared arguments of a function, used to pass arguments by name
+    pub export: Option<String>, // The symbol name of an exported function
 }
 
 impl Symbol
@@ -63,9 +65,23 @@ impl Symbol
             typ: typ.clone(),
             mutable: mutable,
             span: span.clone(),
-            symbol_type: symbol_type
+            symbol_type: symbol_type,
+            args: Vec::new(),
+            export: None,
         }
     }
+
+    pub fn with_args(mut self, args: &[Argument]) -> Symbol
+    {
+        self.args = args.to_vec();
+        self
+    }
+
+    pub fn with_export(mut self, export: &Option<String>) -> Symbol
+    {
+        self.export = export.clone();
+        self
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, HashMap};
+use itertools::join;
+use span::Span;
+use compileerror::{CompileResult, CompileError, type_error};
+use errorcodes::ErrorCode;
+use super::{TreePrinter, prefix};
+
+// A generic function instantiated with concrete types
+#[derive(Debug, Clone)]
+pub struct Instantiation
+{
+    pub name: String,    // The generic function name followed by the type arguments
+    pub generic: String, // The generic function
+    pub caller: String,  // The function which required the instance first
+    pub span: Span,      // The call in the caller
+    pub root: String,    // The instance at the start of the chain, required by a function which is not an instance
+    pub depth: usize,    // The length of the chain, 1 for a root
+}
+
+// Limits on how far the instantiation of a generic can go, to catch generics which keep on requiring new instances
+#[derive(Debug, Clone, Copy)]
+pub struct InstantiationLimits
+{
+    pub max_depth: usize,
+    pub max_per_root: usize,
+}
+
+impl Default for InstantiationLimits
+{
+    fn default() -> Self
+    {
+        InstantiationLimits{
+            max_depth: 64,
+            max_per_root: 10000,
+        }
+    }
+}
+
+// Which generic instances are required by which functions
+#[derive(Debug, Default)]
+pub struct InstantiationGraph
+{
+    pub limits: InstantiationLimits,
+    nodes: BTreeMap<String, Instantiation>,
+    edges: BTreeMap<(String, String), Span>, // (caller, instance) -> first call
+    per_root: HashMap<String, usize>,
+}
+
+impl InstantiationGraph
+{
+    pub fn len(&self) -> usize
+    {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Instantiation>
+    {
+        self.nodes.get(name)
+    }
+
+    pub fn instantiations(&self) -> impl Iterator<Item=&Instantiation>
+    {
+        self.nodes.values()
+    }
+
+    // All calls from a function to an instance, sorted on the caller and the instance
+    pub fn edges(&self) -> impl Iterator<Item=(&str, &str, &Span)>
+    {
+        self.edges.iter().map(|(&(ref from, ref to), span)| (from.as_str(), to.as_str(), span))
+    }
+
+    // The instances required by a function
+    pub fn required_by<'a>(&'a self, caller: &'a str) -> impl Iterator<Item=&'a str> + 'a
+    {
+        self.edges().filter(move |&(from, _, _)| from == caller).map(|(_, to, _)| to)
+    }
+
+    // The chain of functions which led to an instance, starting with the function which is not an instance
+    pub fn chain(&self, name: &str) -> Vec<&str>
+    {
+        let mut chain = Vec::new();
+        let mut current = self.nodes.get(name);
+        while let Some(node) = current {
+            chain.push(node.name.as_str());
+            current = self.nodes.get(&node.caller);
+            if current.is_none() {
+                chain.push(node.caller.as_str());
+            }
+        }
+        chain.reverse();
+        chain
+    }
+
+    fn limit_error(&self, msg: String, caller: &str, name: &str, span: &Span) -> CompileError
+    {
+        let mut chain = self.chain(caller);
+        if chain.is_empty() {
+            chain.push(caller);
+        }
+        chain.push(name);
+
+        let msg = format!("{}, the chain of instantiations is:\n  {}", msg, join(chain.iter(), "\n  "));
+        let mut errors = vec![ErrorCode::LimitExceeded.error(span, msg)];
+        if let Some(root) = self.nodes.get(caller).and_then(|c| self.nodes.get(&c.root)) {
+            errors.push(type_error(&root.span, format!("{} is instantiated here, in {}", root.name, root.caller)));
+        }
+        CompileError::Many(errors)
+    }
+
+    // Record that caller requires the instance name of generic, checking the limits when this is a new instance
+    pub fn add(&mut self, caller: &str, name: &str, generic: &str, span: &Span) -> CompileResult<()>
+    {
+        self.edges.entry((caller.into(), name.into())).or_insert_with(|| span.clone());
+        if self.nodes.contains_key(name) {
+            return Ok(());
+        }
+
+        let (root, depth) = match self.nodes.get(caller) {
+            Some(c) => (c.root.clone(), c.depth + 1),
+            None => (name.to_string(), 1),
+        };
+
+        if depth > self.limits.max_depth {
+            let msg = format!("Instantiating {} exceeds the limit of {} nested generic instantiations", name, self.limits.max_depth);
+            return Err(self.limit_error(msg, caller, name, span));
+        }
+
+        let count = *self.per_root.get(&root).unwrap_or(&0) + 1;
+        if count > self.limits.max_per_root {
+            let msg = format!("Instantiating {} exceeds the limit of {} generic instantiations required by {}", name, self.limits.max_per_root, root);
+            return Err(self.limit_error(msg, caller, name, span));
+        }
+
+        self.per_root.insert(root.clone(), count);
+        self.nodes.insert(name.into(), Instantiation{
+            name: name.into(),
+            generic: generic.into(),
+            caller: caller.into(),
+            span: span.clone(),
+            root,
+            depth,
+        });
+        Ok(())
+    }
+
+    // The functions which require instances, but which are not instances themselves
+    fn roots(&self) -> Vec<&str>
+    {
+        let mut roots: Vec<&str> = self.edges.keys()
+            .map(|&(ref from, _)| from.as_str())
+            .filter(|from| !self.nodes.contains_key(*from))
+            .collect();
+        roots.dedup();
+        roots
+    }
+
+    fn print_instance(&self, name: &str, level: usize)
+    {
+        let p = prefix(level);
+        for node in self.nodes.values().filter(|n| n.caller == name) {
+            println!("{}{} ({})", p, node.name, node.span);
+            self.print_instance(&node.name, level + 1);
+        }
+    }
+
+    // The graph in the graphviz dot format, every call to an instance is an edge labelled with the location of the call
+    pub fn to_dot(&self, name: &str) -> String
+    {
+        let mut dot = format!("digraph \"{}\" {{\n", escape(name));
+        for (from, to, span) in self.edges() {
+            dot.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{}\"];\n", escape(from), escape(to), escape(&span.to_string())));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn escape(s: &str) -> String
+{
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Prints every instance under the function which required it first
+impl TreePrinter for InstantiationGraph
+{
+    fn print(&self, level: usize)
+    {
+        let p = prefix(level);
+        for root in self.roots() {
+            println!("{}{}", p, root);
+            self.print_instance(root, level + 1);
+        }
+    }
+}
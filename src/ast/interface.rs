@@ -8,6 +8,7 @@ pub struct Interface
     pub functions: Vec<FunctionSignature>,
     pub typ: Type,
     pub span: Span,
+    pub cfg: Option<Cfg>,
 }
 
 pub fn interface(name: String, functions: Vec<FunctionSignature>, span: Span) -> Interface
@@ -17,6 +18,7 @@ pub fn interface(name: String, functions: Vec<FunctionSignature>, span: Span) ->
         functions: functions,
         typ: Type::Unknown,
         span: span,
+        cfg: None,
     }
 }
 
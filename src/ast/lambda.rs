@@ -1,5 +1,6 @@
 use ast::{Expression, Argument, TreePrinter, FunctionSignature, Type, prefix, sig, generic_type};
-use compileerror::{CompileResult, type_error_result};
+use compileerror::CompileResult;
+use errorcodes::ErrorCode;
 use span::Span;
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
@@ -8,14 +9,43 @@ pub struct Lambda
     pub sig: FunctionSignature,
     pub expr: Expression,
     pub span: Span,
+    pub name: Option<String>, // Local functions have a name, which is visible in their own body
+}
+
+pub const LAMBDA_NAME: &str = "lambda";
+
+// The name of the lambda or local function with the given ordinal in the enclosing function: outer::lambda.0
+pub fn nested_function_name(enclosing_name: &str, name: &str, ordinal: usize) -> String
+{
+    format!("{}::{}.{}", enclosing_name, name, ordinal)
+}
+
+pub fn is_anonymous_lambda_name(function_name: &str) -> bool
+{
+    function_name.rsplit("::").next()
+        .and_then(|last| last.strip_prefix(LAMBDA_NAME))
+        .and_then(|rest| rest.strip_prefix('.'))
+        .map(|ordinal| !ordinal.is_empty() && ordinal.chars().all(|c| c.is_ascii_digit()))
+        .unwrap_or(false)
 }
 
 pub fn lambda(args: Vec<Argument>, expr: Expression, span: Span) -> Expression
 {
     Expression::Lambda(Box::new(Lambda{
-        sig: sig("lambda", generic_type("$ret$"), args, span.clone()),
+        sig: sig(LAMBDA_NAME, generic_type("$ret$"), args, span.clone()),
         expr: expr,
         span: span,
+        name: None,
+    }))
+}
+
+pub fn local_function(name: &str, ret: Type, args: Vec<Argument>, expr: Expression, span: Span) -> Expression
+{
+    Expression::Lambda(Box::new(Lambda{
+        sig: sig(name, ret, args, span.clone()),
+        expr: expr,
+        span: span,
+        name: Some(name.into()),
     }))
 }
 
@@ -32,7 +62,7 @@ impl Lambda
         {
             Type::Func(ref ft) => {
                 if ft.args.len() != self.sig.args.len() {
-                    return type_error_result(&self.span,
+                    return ErrorCode::TypeMismatch.result(&self.span,
                         format!("Lambda expression has {} arguments, not {} arguments", self.sig.args.len(), ft.args.len()));
                 }
 
@@ -41,7 +71,7 @@ impl Lambda
                     if arg.typ.is_generic() {
                         arg.typ = arg_typ.clone();
                     } else if arg.typ != *arg_typ {
-                        return type_error_result(&self.span,
+                        return ErrorCode::TypeMismatch.result(&self.span,
                             format!("Type mismatch in lambda expression, argument {}, has type {} not {}",
                                 arg.name, arg.typ, arg_typ));
                     }
@@ -51,7 +81,7 @@ impl Lambda
                 self.sig.typ = typ.clone();
                 Ok(())
             },
-            _ => type_error_result(&self.span,
+            _ => ErrorCode::TypeMismatch.result(&self.span,
                 format!("Lambda expression does not match the type {}", typ)),
         }
     }
@@ -59,6 +89,7 @@ impl Lambda
     pub fn set_return_type(&mut self, return_type: Type)
     {
         self.sig.return_type = return_type;
+        self.sig.typ = self.sig.get_type();
     }
 }
 
@@ -67,7 +98,10 @@ impl TreePrinter for Lambda
     fn print(&self, level: usize)
     {
         let p = prefix(level);
-        println!("{}lambda ({})", p, self.span);
+        match self.name {
+            Some(ref name) => println!("{}local function {} ({})", p, name, self.span),
+            None => println!("{}lambda ({})", p, self.span),
+        }
         self.sig.print(level + 1);
         self.expr.print(level + 1);
     }
@@ -1,5 +1,5 @@
 use std::ops::Deref;
-use ast::{Type, ArrayLiteral, TreePrinter, FloatSize, IntSize, ptr_type, prefix};
+use ast::{Type, ArrayLiteral, TreePrinter, FloatSize, IntSize, ptr_type, slice_type, prefix};
 use span::Span;
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
@@ -11,6 +11,7 @@ pub enum Literal
     Char(Span, char),
     Float(Span, String, FloatSize), // Keep as string until we generate code, so we can compare it
     String(Span, String),
+    Bytes(Span, Vec<u8>), // b"...", a uint8[] which does not have to be valid UTF-8
     Array(ArrayLiteral),
     NullPtr(Span, Type),
 }
@@ -27,6 +28,7 @@ impl Literal
             Literal::Bool(_, _) => Type::Bool,
             Literal::Char(_, _) => Type::Char,
             Literal::String(_, _) => Type::String,
+            Literal::Bytes(_, _) => slice_type(Type::UInt(IntSize::I8)),
             Literal::Array(ref a) => a.array_type.clone(),
             Literal::NullPtr(_, ref typ) => ptr_type(typ.clone()),
         }
@@ -42,7 +44,8 @@ impl Literal
             Literal::Bool(ref span, _) |
             Literal::Char(ref span, _) |
             Literal::NullPtr(ref span, _) |
-            Literal::String(ref span, _) => span.clone(),
+            Literal::String(ref span, _) |
+            Literal::Bytes(ref span, _) => span.clone(),
             Literal::Array(ref a) => a.span.clone(),
         }
     }
@@ -52,8 +55,8 @@ impl Literal
         match (self, typ) {
             (&Literal::Int(ref span, value, _), &Type::Int(int_size)) => {
                 let target_bit_size = int_size.size_in_bits();
-                let target_min = -2i64.pow(target_bit_size - 1);
-                let target_max = (2u64.pow(target_bit_size - 1) - 1) as i64;
+                let target_min = i64::MIN >> (64 - target_bit_size);
+                let target_max = i64::MAX >> (64 - target_bit_size);
                 if value >= target_min && value <= target_max {
                     Some(Literal::Int(span.clone(), value, int_size))
                 } else {
@@ -72,7 +75,7 @@ impl Literal
 
             (&Literal::UInt(ref span, value, _), &Type::Int(int_size)) => {
                 let target_bit_size = int_size.size_in_bits();
-                if value < 2u64.pow(target_bit_size) {
+                if value <= u64::MAX >> (64 - target_bit_size) {
                     Some(Literal::Int(span.clone(), value as i64, int_size))
                 } else {
                     None
@@ -81,7 +84,7 @@ impl Literal
 
             (&Literal::UInt(ref span, value, _), &Type::UInt(int_size)) => {
                 let target_bit_size = int_size.size_in_bits();
-                if value < 2u64.pow(target_bit_size) {
+                if value <= u64::MAX >> (64 - target_bit_size) {
                     Some(Literal::UInt(span.clone(), value, int_size))
                 } else {
                     None
@@ -112,6 +115,20 @@ impl Literal
     }
 }
 
+// Printable ASCII as is, everything else as an escape code, like it is written in a byte string literal
+pub fn escape_bytes(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|&b| match b {
+        b'\n' => "\\n".to_string(),
+        b'\r' => "\\r".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\\' => "\\\\".to_string(),
+        b'"' => "\\\"".to_string(),
+        0x20..=0x7e => (b as char).to_string(),
+        _ => format!("\\x{:02x}", b),
+    }).collect()
+}
+
 impl TreePrinter for Literal
 {
     fn print(&self, level: usize)
@@ -125,6 +142,7 @@ impl TreePrinter for Literal
             Literal::Bool(ref s, v) => println!("{}bool {} ({})", p, v, s),
             Literal::Char(ref s, v) => println!("{}char {} ({})", p, v, s),
             Literal::String(ref s, ref v) => println!("{}string {} ({})", p, v, s),
+            Literal::Bytes(ref s, ref v) => println!("{}bytes {} ({})", p, escape_bytes(v), s),
             Literal::NullPtr(ref s, _) => println!("{}null ({})", p, s),
             Literal::Array(ref a) => {
                 println!("{}array ({})", p, a.span);
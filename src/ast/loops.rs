@@ -30,6 +30,9 @@ impl TreePrinter for WhileLoop
     }
 }
 
+// Name of the variable holding the iterator of a for loop over an iterator, it is not an identifier so it cannot clash with user variables
+pub const FOR_ITERATOR: &str = "for.iterator";
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ForLoop
 {
@@ -38,6 +41,17 @@ pub struct ForLoop
     pub iterable: Expression,
     pub body: Expression,
     pub span: Span,
+    // The call to the next member function of the iterator, strings, arrays and slices are indexed instead
+    pub next: Option<Expression>,
+}
+
+impl ForLoop
+{
+    // Nested loops each need their own iterator, so it is named after the position of the loop
+    pub fn iterator_name(&self) -> String
+    {
+        format!("{}@{}:{}", FOR_ITERATOR, self.span.start.line, self.span.start.offset)
+    }
 }
 
 pub fn for_loop(loop_variable: &str, iterable: Expression, body: Expression, span: Span) -> Expression
@@ -48,6 +62,7 @@ pub fn for_loop(loop_variable: &str, iterable: Expression, body: Expression, spa
         iterable: iterable,
         body: body,
         span: span,
+        next: None,
     }))
 }
 
@@ -59,6 +74,9 @@ impl TreePrinter for ForLoop
         let p = prefix(level);
         println!("{}for {} (span: {})", p, self.loop_variable, self.span);
         self.iterable.print(level + 1);
+        if let Some(ref next) = self.next {
+            next.print(level + 1);
+        }
         self.body.print(level + 1);
     }
 }
@@ -52,3 +52,32 @@ impl TreePrinter for MatchExpression
         }
     }
 }
+
+// x is Pattern, true when x matches the pattern, which cannot bind any names
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct IsExpression
+{
+    pub inner: Expression,
+    pub pattern: Pattern,
+    pub span: Span,
+}
+
+pub fn is_expression(inner: Expression, pattern: Pattern, span: Span) -> Expression
+{
+    Expression::Is(Box::new(IsExpression{
+        inner: inner,
+        pattern: pattern,
+        span: span,
+    }))
+}
+
+impl TreePrinter for IsExpression
+{
+    fn print(&self, level: usize)
+    {
+        let p = prefix(level);
+        println!("{}is ({})", p, self.span);
+        self.inner.print(level + 1);
+        self.pattern.print(level + 1);
+    }
+}
@@ -3,12 +3,14 @@ mod assign;
 mod bindings;
 mod block;
 mod call;
+mod cfg;
 mod compilercall;
 mod expression;
 mod function;
 mod heap;
 mod ifexpression;
 mod import;
+mod instantiations;
 mod indexoperation;
 mod interface;
 mod lambda;
@@ -25,18 +27,21 @@ mod structs;
 mod sumtype;
 mod typedeclaration;
 mod types;
+mod visitor;
 
 pub use self::arrays::*;
 pub use self::assign::*;
 pub use self::bindings::*;
 pub use self::block::*;
 pub use self::call::*;
+pub use self::cfg::*;
 pub use self::compilercall::*;
 pub use self::expression::*;
 pub use self::function::*;
 pub use self::heap::*;
 pub use self::ifexpression::*;
 pub use self::import::*;
+pub use self::instantiations::*;
 pub use self::indexoperation::*;
 pub use self::interface::*;
 pub use self::lambda::*;
@@ -53,7 +58,10 @@ pub use self::structs::*;
 pub use self::sumtype::*;
 pub use self::typedeclaration::*;
 pub use self::types::*;
+pub use self::visitor::*;
 
+// Maximum nesting depth of expressions, deeper expressions result in a compile error
+pub const MAX_NESTING_DEPTH: usize = 10_000;
 
 pub fn prefix(level: usize) -> String
 {
@@ -76,5 +84,8 @@ pub type GenericMapping = HashMap<Type, Type>;
 
 pub fn new_func_name(func_name: &str, generic_args: &GenericMapping) -> String
 {
-    format!("{}<{}>", func_name, join(generic_args.values(), ","))
+    // Sort on the generic parameters, so the name doesn't depend on the order of the hash map
+    let mut args: Vec<(String, &Type)> = generic_args.iter().map(|(param, arg)| (param.to_string(), arg)).collect();
+    args.sort_by(|a, b| a.0.cmp(&b.0));
+    format!("{}<{}>", func_name, join(args.iter().map(|&(_, arg)| arg), ","))
 }
@@ -1,8 +1,32 @@
 use std::collections::{HashMap, HashSet};
-use super::{Expression, Call, TreePrinter, TypeDeclaration, Import, ImportName, Symbol, SymbolType, GlobalBinding, Function, ExternalFunction, prefix};
+use super::{Expression, Call, Visitor, walk_expression, TreePrinter, TypeDeclaration, Import, ImportName, Symbol, SymbolType, GlobalBinding, Function, ExternalFunction, LinkLibrary, InstantiationGraph, prefix};
 use target::Target;
 use compileerror::CompileResult;
 
+// Collects the calls to functions from other modules
+struct ImportedCallFinder<'a>
+{
+    module: &'a Module,
+    target: &'a Target,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl<'a> Visitor for ImportedCallFinder<'a>
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
+        if let Expression::Call(ref call) = *e {
+            if self.module.is_imported_call(call) {
+                let typ = call.callee_type(self.target.int_size);
+                let symbol = Symbol::new(&call.callee.name, &typ, false, &call.span, SymbolType::External);
+                self.symbols.insert(call.callee.name.clone(), symbol);
+            }
+        }
+
+        walk_expression(self, e)
+    }
+}
+
 pub struct Module
 {
     pub name: String,
@@ -11,6 +35,7 @@ pub struct Module
     pub externals: HashMap<String, ExternalFunction>,
     pub types: HashMap<String, TypeDeclaration>,
     pub import_names: HashSet<ImportName>,
+    pub instantiations: InstantiationGraph,
     pub type_checked: bool,
 }
 
@@ -25,35 +50,29 @@ impl Module
             externals: HashMap::new(),
             types: HashMap::new(),
             import_names: HashSet::new(),
+            instantiations: InstantiationGraph::default(),
             type_checked: false,
         }
     }
 
     fn is_imported_call(&self, call: &Call) -> bool {
+        call.builtin.is_none() &&
         !self.functions.contains_key(&call.callee.name) &&
         !self.externals.contains_key(&call.callee.name)
     }
 
     fn get_imported_symbols(&self, target: &Target) -> HashMap<String, Symbol>
     {
-        let mut symbols = HashMap::new();
+        let mut finder = ImportedCallFinder{
+            module: self,
+            target,
+            symbols: HashMap::new(),
+        };
+
         for func in self.functions.values() {
-            let mut find_imported_calls = |e: &Expression| -> CompileResult<()> {
-                match *e {
-                    Expression::Call(ref call) if self.is_imported_call(call) => {
-                        let typ = call.callee_type(target.int_size);
-                        let symbol = Symbol::new(&call.callee.name, &typ, false, &call.span, SymbolType::External);
-                        symbols.insert(call.callee.name.clone(), symbol);
-                    }
-
-                    _ => (),
-                }
-                Ok(())
-            };
-
-            let _ = func.expression.visit(&mut find_imported_calls);
+            let _ = finder.visit_expression(&func.expression);
         }
-        symbols
+        finder.symbols
     }
 
     pub fn get_exported_symbols(&self, target: &Target) -> Import
@@ -64,14 +83,17 @@ impl Module
         }
 
         for (name, function) in &self.functions {
-            import.symbols.insert(name.clone(), Symbol::new(name, &function.sig.typ, false, &function.span, SymbolType::Normal));
+            import.symbols.insert(name.clone(), Symbol::new(name, &function.sig.typ, false, &function.span, SymbolType::Normal)
+                .with_args(&function.sig.args)
+                .with_export(&function.sig.export));
             if function.is_generic() {
                 import.generics.insert(name.clone(), function.clone());
             }
         }
 
         for (name, function) in &self.externals {
-            import.symbols.insert(name.clone(), Symbol::new(name, &function.sig.typ, false, &function.span, SymbolType::External));
+            import.symbols.insert(name.clone(), Symbol::new(name, &function.sig.typ, false, &function.span, SymbolType::External)
+                .with_export(&function.sig.link_name));
         }
 
         for (name, type_decl) in &self.types {
@@ -82,6 +104,21 @@ impl Module
         import.imported_symbols = self.get_imported_symbols(target);
         import
     }
+
+    // The libraries the external functions of this module need, without duplicates
+    pub fn link_libraries(&self) -> Vec<LinkLibrary>
+    {
+        let mut externals: Vec<&ExternalFunction> = self.externals.values().collect();
+        externals.sort_by(|a, b| a.sig.name.cmp(&b.sig.name));
+
+        let mut libraries: Vec<LinkLibrary> = Vec::new();
+        for lib in externals.iter().flat_map(|e| e.libraries.iter()) {
+            if !libraries.contains(lib) {
+                libraries.push(lib.clone());
+            }
+        }
+        libraries
+    }
 }
 
 impl TreePrinter for Module
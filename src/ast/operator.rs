@@ -20,6 +20,7 @@ pub enum BinaryOperator
     Or,
     Dot,
     As,
+    Is,
 }
 
 
@@ -44,6 +45,7 @@ impl fmt::Display for BinaryOperator
             BinaryOperator::Or => write!(fmt, "||"),
             BinaryOperator::Dot => write!(fmt, "."),
             BinaryOperator::As => write!(fmt, "as"),
+            BinaryOperator::Is => write!(fmt, "is"),
         }
     }
 }
@@ -54,7 +56,7 @@ impl BinaryOperator
     {
         match *self
         {
-            BinaryOperator::Dot | BinaryOperator::As => TOP_PRECEDENCE,
+            BinaryOperator::Dot | BinaryOperator::As | BinaryOperator::Is => TOP_PRECEDENCE,
             BinaryOperator::Mul | BinaryOperator::Div | BinaryOperator::Mod => TOP_PRECEDENCE - 100,
             BinaryOperator::Add | BinaryOperator::Sub => TOP_PRECEDENCE - 200,
             BinaryOperator::LessThan | BinaryOperator::GreaterThan | BinaryOperator::LessThanEquals |
@@ -1,4 +1,5 @@
 use std::fmt;
+use std::slice;
 use itertools::free::join;
 use span::Span;
 use ast::{TreePrinter, NameRef, Literal, Type, prefix};
@@ -62,6 +63,49 @@ pub struct OptionalPattern
     pub inner_type: Type,
 }
 
+// A name which is not an enum or sum type case, it binds the whole matched value
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BindingPattern
+{
+    pub name: String,
+    pub typ: Type,
+    pub span: Span,
+}
+
+// start..end or start..=end, both ends are integer or char literals
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RangePattern
+{
+    pub start: Literal,
+    pub end: Literal,
+    pub inclusive: bool,
+    pub span: Span,
+}
+
+impl RangePattern
+{
+    // The first and last value in the range, None when the range is empty
+    pub fn bounds(&self) -> Option<(i128, i128)>
+    {
+        let start = literal_value(&self.start)?;
+        let end = literal_value(&self.end)?;
+        let last = if self.inclusive {end} else {end - 1};
+        if start <= last {Some((start, last))} else {None}
+    }
+}
+
+// The value of an integer or char literal, which can be compared against other integer or char literals
+pub fn literal_value(lit: &Literal) -> Option<i128>
+{
+    match *lit
+    {
+        Literal::Int(_, v, _) => Some(i128::from(v)),
+        Literal::UInt(_, v, _) => Some(i128::from(v)),
+        Literal::Char(_, c) => Some(i128::from(c as u32)),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Pattern
 {
@@ -73,6 +117,9 @@ pub enum Pattern
     Any(Span),
     Nil(Span),
     Optional(OptionalPattern),
+    Range(RangePattern),
+    Binding(BindingPattern),
+    Or(Vec<Pattern>), // a | b, matches when one of the alternatives matches
 }
 
 impl Pattern
@@ -89,6 +136,33 @@ impl Pattern
             Pattern::Any(ref span) |
             Pattern::Nil(ref span) => span.clone(),
             Pattern::Optional(ref o) => o.span.clone(),
+            Pattern::Range(ref r) => r.span.clone(),
+            Pattern::Binding(ref b) => b.span.clone(),
+            Pattern::Or(ref alternatives) => match (alternatives.first(), alternatives.last()) {
+                (Some(first), Some(last)) => first.span().expanded(last.span().end),
+                _ => Span::default(),
+            },
+        }
+    }
+
+    // Patterns which match every value
+    pub fn is_catch_all(&self) -> bool
+    {
+        match *self
+        {
+            Pattern::Any(_) | Pattern::Binding(_) => true,
+            Pattern::Or(ref alternatives) => alternatives.iter().any(|a| a.is_catch_all()),
+            _ => false,
+        }
+    }
+
+    // The alternatives of an or-pattern, any other pattern is its only alternative
+    pub fn alternatives(&self) -> &[Pattern]
+    {
+        match *self
+        {
+            Pattern::Or(ref alternatives) => alternatives,
+            _ => slice::from_ref(self),
         }
     }
 }
@@ -117,6 +191,11 @@ pub fn struct_pattern(name: &str, bindings: Vec<StructPatternBinding>, typ: Type
     }
 }
 
+pub fn range_pattern(start: Literal, end: Literal, inclusive: bool, span: Span) -> Pattern
+{
+    Pattern::Range(RangePattern{start, end, inclusive, span})
+}
+
 pub fn optional_pattern(binding: String, span: Span) -> Pattern
 {
     Pattern::Optional(OptionalPattern{
@@ -126,6 +205,15 @@ pub fn optional_pattern(binding: String, span: Span) -> Pattern
     })
 }
 
+pub fn binding_pattern(name: &str, span: Span) -> Pattern
+{
+    Pattern::Binding(BindingPattern{
+        name: name.into(),
+        typ: Type::Unknown,
+        span: span,
+    })
+}
+
 impl TreePrinter for Pattern
 {
     fn print(&self, level: usize)
@@ -141,6 +229,18 @@ impl TreePrinter for Pattern
             Pattern::Any(ref span) => println!("{}any pattern ({})", p, span),
             Pattern::Nil(ref span) => println!("{}nil pattern ({})", p, span),
             Pattern::Optional(ref o) => println!("{}optional pattern {} ({})", p, o.binding, o.span),
+            Pattern::Range(ref r) => {
+                println!("{}range pattern{} ({})", p, if r.inclusive {" (inclusive)"} else {""}, r.span);
+                r.start.print(level + 1);
+                r.end.print(level + 1)
+            },
+            Pattern::Binding(ref b) => println!("{}binding pattern {} (span: {}, type: {})", p, b.name, b.span, b.typ),
+            Pattern::Or(ref alternatives) => {
+                println!("{}or pattern ({})", p, self.span());
+                for a in alternatives {
+                    a.print(level + 1);
+                }
+            },
         }
     }
 }
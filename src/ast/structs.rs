@@ -1,4 +1,4 @@
-use ast::{Expression, TreePrinter, GenericMapping, Type, prefix};
+use ast::{Cfg, Expression, TreePrinter, GenericMapping, Type, prefix};
 use span::{Span};
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
@@ -7,6 +7,7 @@ pub struct StructMemberDeclaration
     pub name: String,
     pub typ: Type,
     pub span: Span,
+    pub doc: Option<String>,
 }
 
 pub fn struct_member_declaration(name: &str, typ: Type, span: Span) -> StructMemberDeclaration
@@ -15,6 +16,7 @@ pub fn struct_member_declaration(name: &str, typ: Type, span: Span) -> StructMem
         name: name.into(),
         typ: typ,
         span: span,
+        doc: None,
     }
 }
 
@@ -23,8 +25,12 @@ pub struct StructDeclaration
 {
     pub name: String,
     pub members: Vec<StructMemberDeclaration>,
+    pub generic_params: Vec<Type>, // Explicitly declared ones: struct Foo<T, U>
     pub span: Span,
     pub typ: Type,
+    pub cfg: Option<Cfg>,
+    pub repr_c: bool, // Set by the repr(c) attribute, the struct can be passed by value to external functions
+    pub doc: Option<String>, // The ## comment lines in front of the declaration
 }
 
 pub fn struct_declaration(name: &str, members: Vec<StructMemberDeclaration>, span: Span) -> StructDeclaration
@@ -32,8 +38,12 @@ pub fn struct_declaration(name: &str, members: Vec<StructMemberDeclaration>, spa
     StructDeclaration{
         name: name.into(),
         members: members,
+        generic_params: Vec::new(),
         span: span,
         typ: Type::Unknown,
+        cfg: None,
+        repr_c: false,
+        doc: None,
     }
 }
 
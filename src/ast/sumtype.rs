@@ -1,4 +1,4 @@
-use ast::{TreePrinter, StructDeclaration, Type, prefix};
+use ast::{Cfg, TreePrinter, StructDeclaration, Type, prefix};
 use span::{Span};
 
 
@@ -9,6 +9,7 @@ pub struct SumTypeCaseDeclaration
     pub data: Option<StructDeclaration>,
     pub span: Span,
     pub typ: Type,
+    pub doc: Option<String>,
 }
 
 pub fn sum_type_case_decl(name: &str, data: Option<StructDeclaration>, span: Span) -> SumTypeCaseDeclaration
@@ -18,16 +19,34 @@ pub fn sum_type_case_decl(name: &str, data: Option<StructDeclaration>, span: Spa
         data: data,
         span: span,
         typ: Type::Unknown,
+        doc: None,
     }
 }
 
+const SUM_TYPE_CASE_CONSTRUCTOR_SUFFIX: &str = ".new";
+
+// Name of the compiler generated function which constructs a sum type case with data
+pub fn sum_type_case_constructor_name(case_name: &str) -> String
+{
+    format!("{}{}", case_name, SUM_TYPE_CASE_CONSTRUCTOR_SUFFIX)
+}
+
+// Also matches instantiations of generic constructors
+pub fn is_sum_type_case_constructor(function_name: &str) -> bool
+{
+    function_name.contains(SUM_TYPE_CASE_CONSTRUCTOR_SUFFIX)
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct SumTypeDeclaration
 {
     pub name: String,
     pub cases: Vec<SumTypeCaseDeclaration>,
+    pub generic_params: Vec<Type>, // Explicitly declared ones: enum Foo<T, U>
     pub span: Span,
     pub typ: Type,
+    pub cfg: Option<Cfg>,
+    pub doc: Option<String>,
 }
 
 pub fn sum_type_decl(name: &str, cases: Vec<SumTypeCaseDeclaration>, span: Span) -> SumTypeDeclaration
@@ -35,8 +54,11 @@ pub fn sum_type_decl(name: &str, cases: Vec<SumTypeCaseDeclaration>, span: Span)
     SumTypeDeclaration{
         name: name.into(),
         cases: cases,
+        generic_params: Vec::new(),
         span: span,
         typ: Type::Unknown,
+        cfg: None,
+        doc: None,
     }
 }
 
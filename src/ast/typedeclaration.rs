@@ -1,4 +1,4 @@
-use super::{Type, Interface, StructDeclaration, SumTypeDeclaration, TreePrinter};
+use super::{Cfg, Type, Interface, StructDeclaration, SumTypeDeclaration, TreePrinter};
 use span::Span;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -12,6 +12,26 @@ pub enum TypeDeclaration
 
 impl TypeDeclaration
 {
+    pub fn name(&self) -> &str
+    {
+        match *self
+        {
+            TypeDeclaration::Interface(ref i) => &i.name,
+            TypeDeclaration::Struct(ref sd) => &sd.name,
+            TypeDeclaration::Sum(ref s) => &s.name,
+        }
+    }
+
+    pub fn cfg(&self) -> Option<&Cfg>
+    {
+        match *self
+        {
+            TypeDeclaration::Interface(ref i) => i.cfg.as_ref(),
+            TypeDeclaration::Struct(ref sd) => sd.cfg.as_ref(),
+            TypeDeclaration::Sum(ref s) => s.cfg.as_ref(),
+        }
+    }
+
     pub fn span(&self) -> Span
     {
         match *self
@@ -23,6 +43,16 @@ impl TypeDeclaration
         }
     }
 
+    pub fn is_resolved(&self) -> bool
+    {
+        match *self
+        {
+            TypeDeclaration::Interface(ref i) => i.typ != Type::Unknown,
+            TypeDeclaration::Struct(ref sd) => sd.typ != Type::Unknown,
+            TypeDeclaration::Sum(ref s) => s.typ != Type::Unknown,
+        }
+    }
+
     pub fn get_type(&self) -> Type
     {
         match *self
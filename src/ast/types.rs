@@ -1,4 +1,6 @@
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::ops::Deref;
 use std::rc::Rc;
 use itertools::free::join;
@@ -71,6 +73,7 @@ pub struct StructType
 {
     pub name: String,
     pub members: Vec<StructMember>,
+    pub repr_c: bool,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
@@ -78,6 +81,7 @@ pub struct FuncType
 {
     pub args: Vec<Type>,
     pub return_type: Type,
+    pub variadic: bool, // Extra arguments may follow args, only allowed for external functions
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
@@ -85,6 +89,30 @@ pub struct ArrayType
 {
     pub element_type: Type,
     pub len: usize,
+    // Length given by @size_of, @align_of or a constant expression, len is only known once the type is resolved
+    pub len_of: Option<ArrayLength>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub enum ArrayLength
+{
+    SizeOf(Type),
+    AlignOf(Type),
+    Constant(Box<Expression>), // For example "abc".len, folded by the type resolver
+}
+
+impl Hash for ArrayLength
+{
+    fn hash<H: Hasher>(&self, state: &mut H)
+    {
+        mem::discriminant(self).hash(state);
+        match *self
+        {
+            ArrayLength::SizeOf(ref t) |
+            ArrayLength::AlignOf(ref t) => t.hash(state),
+            ArrayLength::Constant(_) => (),
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
@@ -187,6 +215,7 @@ pub enum Type
     Enum(Rc<EnumType>),
     Optional(Rc<Type>),
     Interface(Rc<InterfaceType>),
+    Thread,
 }
 
 #[derive(Debug,  Eq, PartialEq, Clone, Serialize, Deserialize)]
@@ -224,6 +253,18 @@ impl Type
         }
     }
 
+    // Types with member functions, which can implement an interface
+    pub fn can_have_methods(&self) -> bool
+    {
+        match *self
+        {
+            Type::Struct(ref st) => !st.name.is_empty(),
+            Type::Sum(_) |
+            Type::Enum(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn is_matchable(&self, other: &Type) -> bool
     {
         match (self, other)
@@ -241,7 +282,11 @@ impl Type
         match (self, from_type)
         {
             (&Type::Slice(ref st), &Type::Array(ref at)) if st.element_type == at.element_type => {
-                Some(array_to_slice(expr.clone(), expr.span()))
+                Some(Expression::ArrayToSlice(Box::new(ArrayToSlice{
+                    inner: expr.clone(),
+                    slice_type: self.clone(),
+                    span: expr.span(),
+                })))
             }
 
             (&Type::Optional(ref inner), _) if *inner.deref() == *from_type => {
@@ -256,6 +301,11 @@ impl Type
                 Some(nil_expr_with_type(expr.span(), inner.deref().clone()))
             }
 
+            // Whether the type really implements the interface, is checked by the type checker
+            (&Type::Interface(_), _) if !self.is_generic() && from_type.get_pointer_element_type().unwrap_or(from_type).can_have_methods() => {
+                Some(to_interface(expr.clone(), self.clone()))
+            }
+
             (&Type::Void, _) => {
                 Some(Expression::Block(Box::new(Block{
                     expressions: vec![
@@ -268,7 +318,9 @@ impl Type
             }
 
             (&Type::Pointer(ref to), &Type::Pointer(ref from)) => {
-                if *to.deref() == Type::Void {
+                if *from.deref() == Type::Unknown {
+                    None // null, which gets its type from the literal conversion
+                } else if *to.deref() == Type::Void {
                     Some(type_cast(expr.clone(), ptr_type(Type::Void), expr.span()))
                 } else if *from.deref() == Type::Void {
                     Some(type_cast(expr.clone(), self.clone(), expr.span()))
@@ -290,7 +342,9 @@ impl Type
         match (self, dst_type)
         {
             (&Type::Array(ref at), &Type::Slice(ref st)) => at.element_type == st.element_type,
-            (_, &Type::Optional(ref inner)) => *inner.deref() == *dst_type,
+            (_, &Type::Optional(ref inner)) => *inner.deref() == *self,
+            (&Type::Optional(_), &Type::Bool) => true,
+            (_, &Type::Interface(_)) => !dst_type.is_generic() && self.get_pointer_element_type().unwrap_or(self).can_have_methods(),
             _ => false,
         }
     }
@@ -314,7 +368,15 @@ impl Type
             Type::Float(_) => GENERAL_NUMERIC_OPERATORS.contains(&op),
             Type::Char=> COMPARISON_OPERATORS.contains(&op),
             Type::Bool => COMPARISON_OPERATORS.contains(&op) || op == BinaryOperator::And || op == BinaryOperator::Or,
-            Type::String | Type::Pointer(_) | Type::Optional(_) => op == BinaryOperator::Equals || op == BinaryOperator::NotEquals,
+            // Pointers are ordered by their address, which is what C code does with them, but there is no pointer arithmetic
+            Type::Pointer(_) => COMPARISON_OPERATORS.contains(&op),
+            Type::String | Type::Enum(_) | Type::Optional(_) => op == BinaryOperator::Equals || op == BinaryOperator::NotEquals,
+            // Byte slices are compared like strings, by their contents
+            Type::Slice(ref st) if st.element_type == Type::UInt(IntSize::I8) => op == BinaryOperator::Equals || op == BinaryOperator::NotEquals,
+            // Tuples are equal when all their members are
+            Type::Struct(ref st) if st.name.is_empty() =>
+                (op == BinaryOperator::Equals || op == BinaryOperator::NotEquals) &&
+                st.members.iter().all(|m| m.typ.is_binary_operator_supported(BinaryOperator::Equals)),
             _ => false,
         }
     }
@@ -330,7 +392,8 @@ impl Type
             Type::Struct(ref st) => st.members.iter().any(|m| m.typ.is_generic()),
             Type::Sum(ref st) => st.cases.iter().any(|c| c.typ.is_generic()),
             Type::Unresolved(ref ut) => ut.generic_args.iter().any(|t| t.is_generic()),
-            Type::Pointer(ref inner) => inner.is_generic(),
+            Type::Pointer(ref inner) |
+            Type::Optional(ref inner) => inner.is_generic(),
             Type::Interface(ref i) => !i.generic_args.is_empty(),
             _ => false,
         }
@@ -345,6 +408,57 @@ impl Type
         }
     }
 
+    pub fn is_integer(&self) -> bool
+    {
+        match *self
+        {
+            Type::Int(_) | Type::UInt(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_float(&self) -> bool
+    {
+        match *self
+        {
+            Type::Float(_) => true,
+            _ => false,
+        }
+    }
+
+    // Void has no values, so only a function can return it, and only a pointer can point to it
+    pub fn contains_void_value(&self) -> bool
+    {
+        match *self
+        {
+            Type::Void => true,
+            Type::Array(ref at) => at.element_type.contains_void_value(),
+            Type::Slice(ref st) => st.element_type.contains_void_value(),
+            Type::Optional(ref inner) => inner.contains_void_value(),
+            Type::Func(ref ft) =>
+                ft.args.iter().any(|a| a.contains_void_value()) ||
+                (ft.return_type != Type::Void && ft.return_type.contains_void_value()),
+            Type::Struct(ref st) => st.members.iter().any(|m| m.typ.contains_void_value()),
+            Type::Sum(ref st) => st.cases.iter().any(|c| c.typ.contains_void_value()),
+            _ => false,
+        }
+    }
+
+    // Types which can be passed to the hash builtin, and used as map keys
+    pub fn is_hashable(&self) -> bool
+    {
+        match *self
+        {
+            Type::Int(_) |
+            Type::UInt(_) |
+            Type::Char |
+            Type::Bool |
+            Type::Enum(_) |
+            Type::String => true,
+            _ => false,
+        }
+    }
+
     pub fn is_unknown(&self) -> bool
     {
         match *self
@@ -440,7 +554,27 @@ impl Type
             Type::Bool |
             Type::Pointer(_) |
             Type::Enum(_) |
-            Type::Func(_) => true,
+            Type::Func(_) |
+            Type::Thread => true,
+            _ => false,
+        }
+    }
+
+    // Types which have the same representation in C, so they can be members of a repr(c) struct
+    pub fn is_c_representable(&self) -> bool
+    {
+        match *self
+        {
+            Type::Int(_) |
+            Type::UInt(_) |
+            Type::Float(_) |
+            Type::Char |
+            Type::Bool |
+            Type::Pointer(_) => true,
+            // Nil is a null pointer, like in C
+            Type::Optional(ref inner) => inner.is_pointer(),
+            Type::Array(ref at) => at.element_type.is_c_representable(),
+            Type::Struct(ref st) => st.repr_c,
             _ => false,
         }
     }
@@ -465,6 +599,16 @@ pub fn func_type(args: Vec<Type>, ret: Type) -> Type
     Type::Func(Rc::new(FuncType{
         args: args,
         return_type: ret,
+        variadic: false,
+    }))
+}
+
+pub fn variadic_func_type(args: Vec<Type>, ret: Type) -> Type
+{
+    Type::Func(Rc::new(FuncType{
+        args: args,
+        return_type: ret,
+        variadic: true,
     }))
 }
 
@@ -472,7 +616,17 @@ pub fn array_type(element_type: Type, len: usize) -> Type
 {
     Type::Array(Rc::new(ArrayType{
         element_type: element_type,
-        len: len
+        len: len,
+        len_of: None,
+    }))
+}
+
+pub fn array_type_with_length_of(element_type: Type, len_of: ArrayLength) -> Type
+{
+    Type::Array(Rc::new(ArrayType{
+        element_type: element_type,
+        len: 0,
+        len_of: Some(len_of),
     }))
 }
 
@@ -495,7 +649,8 @@ pub fn string_type_representation(native_int_size: IntSize) -> StructType
         members: vec![
             struct_member("data", ptr_type(Type::UInt(IntSize::I8))),
             struct_member("len", Type::UInt(native_int_size))
-        ]
+        ],
+        repr_c: false,
     }
 }
 
@@ -528,6 +683,17 @@ pub fn struct_type(name: &str, members: Vec<StructMember>) -> Type
     Type::Struct(Rc::new(StructType{
         name: name.into(),
         members: members,
+        repr_c: false,
+    }))
+}
+
+// The same struct with other members, for example after filling in generic arguments
+pub fn struct_type_with_members(st: &StructType, members: Vec<StructMember>) -> Type
+{
+    Type::Struct(Rc::new(StructType{
+        name: st.name.clone(),
+        members: members,
+        repr_c: st.repr_c,
     }))
 }
 
@@ -609,9 +775,16 @@ impl fmt::Display for Type
                 } else {
                     write!(f, "{}<{}>", s.name, join(s.generic_args.iter(), ","))
                 },
-            Type::Array(ref at) => write!(f, "{}[{}]", at.element_type, at.len),
+            Type::Array(ref at) => match at.len_of {
+                Some(ArrayLength::SizeOf(ref t)) => write!(f, "{}[@size_of({})]", at.element_type, t),
+                Some(ArrayLength::AlignOf(ref t)) => write!(f, "{}[@align_of({})]", at.element_type, t),
+                Some(ArrayLength::Constant(_)) => write!(f, "{}[<constant>]", at.element_type),
+                None => write!(f, "{}[{}]", at.element_type, at.len),
+            },
             Type::Slice(ref at) => write!(f, "{}[]", at.element_type),
             Type::Generic(ref g) => write!(f, "${}", g),
+            Type::Func(ref ft) if ft.variadic && ft.args.is_empty() => write!(f, "(...) -> {}", ft.return_type),
+            Type::Func(ref ft) if ft.variadic => write!(f, "({}, ...) -> {}", join(ft.args.iter(), ", "), ft.return_type),
             Type::Func(ref ft) => write!(f, "({}) -> {}", join(ft.args.iter(), ", "), ft.return_type),
             Type::Struct(ref st) => write!(f, "{{{}}}", join(st.members.iter(), ", ")),
             Type::Sum(ref st) => write!(f, "{}", join(st.cases.iter().map(|m| &m.typ), " | ")),
@@ -619,6 +792,7 @@ impl fmt::Display for Type
             Type::Optional(ref inner) => write!(f, "?{}", inner),
             Type::Interface(ref i) => write!(f, "interface {}", i.name),
             Type::SelfType => write!(f, "Self"),
+            Type::Thread => write!(f, "thread"),
         }
     }
 }
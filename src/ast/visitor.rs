@@ -0,0 +1,680 @@
+use ast::*;
+use compileerror::CompileResult;
+
+/*
+    Visitors walk the AST, passes only need to override the nodes they care about,
+    and call the walk function to continue into the children of that node.
+*/
+pub trait Visitor
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
+        walk_expression(self, e)
+    }
+
+    fn visit_pattern(&mut self, p: &Pattern) -> CompileResult<()>
+    {
+        walk_pattern(self, p)
+    }
+
+    fn visit_binding(&mut self, b: &Binding) -> CompileResult<()>
+    {
+        walk_binding(self, b)
+    }
+
+    fn visit_type(&mut self, _t: &Type) -> CompileResult<()>
+    {
+        Ok(())
+    }
+}
+
+fn walk_call<V: Visitor + ?Sized>(v: &mut V, c: &Call) -> CompileResult<()>
+{
+    for a in &c.args {
+        v.visit_expression(a)?;
+    }
+    for na in &c.named_args {
+        v.visit_expression(&na.value)?;
+    }
+    Ok(())
+}
+
+fn walk_member_access<V: Visitor + ?Sized>(v: &mut V, ma: &MemberAccess) -> CompileResult<()>
+{
+    v.visit_expression(&ma.left)?;
+    match ma.right
+    {
+        MemberAccessType::Call(ref call) => walk_call(v, call),
+        MemberAccessType::Name(_) |
+        MemberAccessType::Property(_) => Ok(()),
+    }
+}
+
+fn walk_literal<V: Visitor + ?Sized>(v: &mut V, lit: &Literal) -> CompileResult<()>
+{
+    match *lit
+    {
+        Literal::Array(ref a) => {
+            for el in &a.elements {
+                v.visit_expression(el)?;
+            }
+            Ok(())
+        },
+
+        Literal::NullPtr(_, ref typ) => v.visit_type(typ),
+
+        Literal::Int(..) |
+        Literal::UInt(..) |
+        Literal::Bool(..) |
+        Literal::Char(..) |
+        Literal::Float(..) |
+        Literal::String(..) |
+        Literal::Bytes(..) => Ok(()),
+    }
+}
+
+pub fn walk_pattern<V: Visitor + ?Sized>(v: &mut V, p: &Pattern) -> CompileResult<()>
+{
+    match *p
+    {
+        Pattern::Literal(ref lit) => walk_literal(v, lit),
+        Pattern::Name(ref nr) => v.visit_type(&nr.typ),
+        Pattern::Struct(ref sp) => walk_struct_pattern(v, sp),
+        Pattern::Optional(ref op) => v.visit_type(&op.inner_type),
+        Pattern::Binding(ref b) => v.visit_type(&b.typ),
+        Pattern::Or(ref alternatives) => {
+            for a in alternatives {
+                v.visit_pattern(a)?;
+            }
+            Ok(())
+        },
+        Pattern::Array(_) |
+        Pattern::EmptyArray(_) |
+        Pattern::Any(_) |
+        Pattern::Nil(_) |
+        Pattern::Range(_) => Ok(()),
+    }
+}
+
+fn walk_struct_pattern<V: Visitor + ?Sized>(v: &mut V, sp: &StructPattern) -> CompileResult<()>
+{
+    for b in &sp.bindings {
+        v.visit_type(&b.typ)?;
+    }
+    v.visit_type(&sp.typ)
+}
+
+pub fn walk_binding<V: Visitor + ?Sized>(v: &mut V, b: &Binding) -> CompileResult<()>
+{
+    match b.binding_type
+    {
+        BindingType::Struct(ref sp) => walk_struct_pattern(v, sp)?,
+        BindingType::Name(_) |
+        BindingType::Array(_) => (),
+    }
+    v.visit_type(&b.typ)?;
+    v.visit_expression(&b.init)
+}
+
+// Visits every child of e, there is deliberately no catch all, so new expressions have to be added here
+pub fn walk_expression<V: Visitor + ?Sized>(v: &mut V, e: &Expression) -> CompileResult<()>
+{
+    match *e
+    {
+        Expression::UnaryOp(ref uop) => v.visit_expression(&uop.expression),
+
+        Expression::BinaryOp(ref bop) => {
+            v.visit_expression(&bop.left)?;
+            v.visit_expression(&bop.right)
+        },
+
+        Expression::Literal(ref lit) => walk_literal(v, lit),
+
+        Expression::Call(ref call) => walk_call(v, call),
+
+        Expression::Lambda(ref l) => {
+            for a in &l.sig.args {
+                v.visit_type(&a.typ)?;
+            }
+            if l.name.is_some() {
+                v.visit_type(&l.sig.return_type)?;
+            }
+            v.visit_expression(&l.expr)
+        },
+
+        Expression::Match(ref m) => {
+            v.visit_expression(&m.target)?;
+            for c in &m.cases {
+                v.visit_pattern(&c.pattern)?;
+                v.visit_expression(&c.to_execute)?;
+            }
+            Ok(())
+        },
+
+        Expression::Is(ref i) => {
+            v.visit_expression(&i.inner)?;
+            v.visit_pattern(&i.pattern)
+        },
+
+        Expression::Bindings(ref l) => {
+            for b in &l.bindings {
+                v.visit_binding(b)?;
+            }
+            Ok(())
+        },
+
+        Expression::Block(ref b) => {
+            for e in &b.expressions {
+                v.visit_expression(e)?;
+            }
+            Ok(())
+        },
+
+        Expression::New(ref n) => {
+            v.visit_type(&n.typ)?;
+            v.visit_expression(&n.inner)
+        },
+
+        Expression::Delete(ref d) => v.visit_expression(&d.inner),
+
+        Expression::ArrayToSlice(ref ats) => v.visit_expression(&ats.inner),
+
+        Expression::Return(ref r) => v.visit_expression(&r.expression),
+
+        Expression::Propagate(ref p) => v.visit_expression(&p.inner),
+
+        Expression::Discard(ref d) => v.visit_expression(&d.inner),
+
+        Expression::If(ref i) => {
+            v.visit_expression(&i.condition)?;
+            v.visit_expression(&i.on_true)?;
+            if let Some(ref e) = i.on_false {
+                v.visit_expression(e)?;
+            }
+            Ok(())
+        },
+
+        Expression::IfLet(ref i) => {
+            for b in &i.bindings {
+                v.visit_expression(&b.init)?;
+            }
+            v.visit_expression(&i.on_true)?;
+            if let Some(ref e) = i.on_false {
+                v.visit_expression(e)?;
+            }
+            Ok(())
+        },
+
+        Expression::StructInitializer(ref si) => {
+            for e in &si.member_initializers {
+                v.visit_expression(e)?;
+            }
+            Ok(())
+        },
+
+        Expression::AddressOf(ref a) => v.visit_expression(&a.inner),
+
+        Expression::Dereference(ref d) => v.visit_expression(&d.inner),
+
+        Expression::While(ref w) => {
+            v.visit_expression(&w.cond)?;
+            v.visit_expression(&w.body)
+        },
+
+        Expression::Assign(ref a) => {
+            match a.left
+            {
+                AssignTarget::Var(ref nr) => v.visit_type(&nr.typ)?,
+                AssignTarget::Dereference(ref d) => v.visit_expression(&d.inner)?,
+                AssignTarget::IndexOperation(ref iop) => {
+                    v.visit_expression(&iop.target)?;
+                    v.visit_expression(&iop.index_expr)?;
+                },
+                AssignTarget::MemberAccess(ref ma) => walk_member_access(v, ma)?,
+            }
+            v.visit_expression(&a.right)
+        },
+
+        Expression::For(ref f) => {
+            v.visit_expression(&f.iterable)?;
+            if let Some(ref next) = f.next {
+                v.visit_expression(next)?;
+            }
+            v.visit_expression(&f.body)
+        },
+
+        Expression::OptionalToBool(ref o) => v.visit_expression(o),
+
+        Expression::MemberAccess(ref ma) => walk_member_access(v, ma),
+
+        Expression::ToOptional(ref t) => v.visit_expression(&t.inner),
+        Expression::ToInterface(ref t) => v.visit_expression(&t.inner),
+
+        Expression::Cast(ref c) => {
+            v.visit_type(&c.destination_type)?;
+            v.visit_expression(&c.inner)
+        },
+
+        Expression::TypeAscription(ref t) => {
+            v.visit_type(&t.typ)?;
+            v.visit_expression(&t.inner)
+        },
+
+        Expression::IndexOperation(ref iop) => {
+            v.visit_expression(&iop.target)?;
+            v.visit_expression(&iop.index_expr)
+        },
+
+        Expression::CompilerCall(CompilerCall::Slice{ref data, ref len, ref typ, ..}) => {
+            v.visit_type(typ)?;
+            v.visit_expression(data)?;
+            v.visit_expression(len)
+        },
+
+        Expression::CompilerCall(CompilerCall::SizeOf(ref typ, _)) |
+        Expression::CompilerCall(CompilerCall::AlignOf(ref typ, _)) => v.visit_type(typ),
+
+        Expression::CompilerCall(CompilerCall::TypeName(ref e, _)) => v.visit_expression(e),
+
+        Expression::CompilerCall(CompilerCall::StaticAssert{ref cond, ..}) => v.visit_expression(cond),
+
+        Expression::CompilerCall(CompilerCall::AssertType{ref expr, ..}) => v.visit_expression(expr),
+
+        Expression::NameRef(ref nr) => v.visit_type(&nr.typ),
+
+        Expression::Nil(_) |
+        Expression::Void => Ok(()),
+    }
+}
+
+
+pub trait MutVisitor
+{
+    fn visit_expression_mut(&mut self, e: &mut Expression) -> CompileResult<()>
+    {
+        walk_expression_mut(self, e)
+    }
+
+    fn visit_pattern_mut(&mut self, p: &mut Pattern) -> CompileResult<()>
+    {
+        walk_pattern_mut(self, p)
+    }
+
+    fn visit_binding_mut(&mut self, b: &mut Binding) -> CompileResult<()>
+    {
+        walk_binding_mut(self, b)
+    }
+
+    fn visit_type_mut(&mut self, _t: &mut Type) -> CompileResult<()>
+    {
+        Ok(())
+    }
+}
+
+fn walk_call_mut<V: MutVisitor + ?Sized>(v: &mut V, c: &mut Call) -> CompileResult<()>
+{
+    for a in &mut c.args {
+        v.visit_expression_mut(a)?;
+    }
+    for na in &mut c.named_args {
+        v.visit_expression_mut(&mut na.value)?;
+    }
+    Ok(())
+}
+
+fn walk_member_access_mut<V: MutVisitor + ?Sized>(v: &mut V, ma: &mut MemberAccess) -> CompileResult<()>
+{
+    v.visit_expression_mut(&mut ma.left)?;
+    match ma.right
+    {
+        MemberAccessType::Call(ref mut call) => walk_call_mut(v, call),
+        MemberAccessType::Name(_) |
+        MemberAccessType::Property(_) => Ok(()),
+    }
+}
+
+fn walk_literal_mut<V: MutVisitor + ?Sized>(v: &mut V, lit: &mut Literal) -> CompileResult<()>
+{
+    match *lit
+    {
+        Literal::Array(ref mut a) => {
+            for el in &mut a.elements {
+                v.visit_expression_mut(el)?;
+            }
+            Ok(())
+        },
+
+        Literal::NullPtr(_, ref mut typ) => v.visit_type_mut(typ),
+
+        Literal::Int(..) |
+        Literal::UInt(..) |
+        Literal::Bool(..) |
+        Literal::Char(..) |
+        Literal::Float(..) |
+        Literal::String(..) |
+        Literal::Bytes(..) => Ok(()),
+    }
+}
+
+pub fn walk_pattern_mut<V: MutVisitor + ?Sized>(v: &mut V, p: &mut Pattern) -> CompileResult<()>
+{
+    match *p
+    {
+        Pattern::Literal(ref mut lit) => walk_literal_mut(v, lit),
+        Pattern::Name(ref mut nr) => v.visit_type_mut(&mut nr.typ),
+        Pattern::Struct(ref mut sp) => walk_struct_pattern_mut(v, sp),
+        Pattern::Optional(ref mut op) => v.visit_type_mut(&mut op.inner_type),
+        Pattern::Binding(ref mut b) => v.visit_type_mut(&mut b.typ),
+        Pattern::Or(ref mut alternatives) => {
+            for a in alternatives {
+                v.visit_pattern_mut(a)?;
+            }
+            Ok(())
+        },
+        Pattern::Array(_) |
+        Pattern::EmptyArray(_) |
+        Pattern::Any(_) |
+        Pattern::Nil(_) |
+        Pattern::Range(_) => Ok(()),
+    }
+}
+
+fn walk_struct_pattern_mut<V: MutVisitor + ?Sized>(v: &mut V, sp: &mut StructPattern) -> CompileResult<()>
+{
+    for b in &mut sp.bindings {
+        v.visit_type_mut(&mut b.typ)?;
+    }
+    v.visit_type_mut(&mut sp.typ)
+}
+
+pub fn walk_binding_mut<V: MutVisitor + ?Sized>(v: &mut V, b: &mut Binding) -> CompileResult<()>
+{
+    match b.binding_type
+    {
+        BindingType::Struct(ref mut sp) => walk_struct_pattern_mut(v, sp)?,
+        BindingType::Name(_) |
+        BindingType::Array(_) => (),
+    }
+    v.visit_type_mut(&mut b.typ)?;
+    v.visit_expression_mut(&mut b.init)
+}
+
+pub fn walk_expression_mut<V: MutVisitor + ?Sized>(v: &mut V, e: &mut Expression) -> CompileResult<()>
+{
+    match *e
+    {
+        Expression::UnaryOp(ref mut uop) => v.visit_expression_mut(&mut uop.expression),
+
+        Expression::BinaryOp(ref mut bop) => {
+            v.visit_expression_mut(&mut bop.left)?;
+            v.visit_expression_mut(&mut bop.right)
+        },
+
+        Expression::Literal(ref mut lit) => walk_literal_mut(v, lit),
+
+        Expression::Call(ref mut call) => walk_call_mut(v, call),
+
+        Expression::Lambda(ref mut l) => {
+            for a in &mut l.sig.args {
+                v.visit_type_mut(&mut a.typ)?;
+            }
+            if l.name.is_some() {
+                v.visit_type_mut(&mut l.sig.return_type)?;
+            }
+            v.visit_expression_mut(&mut l.expr)
+        },
+
+        Expression::Match(ref mut m) => {
+            v.visit_expression_mut(&mut m.target)?;
+            for c in &mut m.cases {
+                v.visit_pattern_mut(&mut c.pattern)?;
+                v.visit_expression_mut(&mut c.to_execute)?;
+            }
+            Ok(())
+        },
+
+        Expression::Is(ref mut i) => {
+            v.visit_expression_mut(&mut i.inner)?;
+            v.visit_pattern_mut(&mut i.pattern)
+        },
+
+        Expression::Bindings(ref mut l) => {
+            for b in &mut l.bindings {
+                v.visit_binding_mut(b)?;
+            }
+            Ok(())
+        },
+
+        Expression::Block(ref mut b) => {
+            for e in &mut b.expressions {
+                v.visit_expression_mut(e)?;
+            }
+            Ok(())
+        },
+
+        Expression::New(ref mut n) => {
+            v.visit_type_mut(&mut n.typ)?;
+            v.visit_expression_mut(&mut n.inner)
+        },
+
+        Expression::Delete(ref mut d) => v.visit_expression_mut(&mut d.inner),
+
+        Expression::ArrayToSlice(ref mut ats) => v.visit_expression_mut(&mut ats.inner),
+
+        Expression::Return(ref mut r) => v.visit_expression_mut(&mut r.expression),
+
+        Expression::Propagate(ref mut p) => v.visit_expression_mut(&mut p.inner),
+
+        Expression::Discard(ref mut d) => v.visit_expression_mut(&mut d.inner),
+
+        Expression::If(ref mut i) => {
+            v.visit_expression_mut(&mut i.condition)?;
+            v.visit_expression_mut(&mut i.on_true)?;
+            if let Some(ref mut e) = i.on_false {
+                v.visit_expression_mut(e)?;
+            }
+            Ok(())
+        },
+
+        Expression::IfLet(ref mut i) => {
+            for b in &mut i.bindings {
+                v.visit_expression_mut(&mut b.init)?;
+            }
+            v.visit_expression_mut(&mut i.on_true)?;
+            if let Some(ref mut e) = i.on_false {
+                v.visit_expression_mut(e)?;
+            }
+            Ok(())
+        },
+
+        Expression::StructInitializer(ref mut si) => {
+            for e in &mut si.member_initializers {
+                v.visit_expression_mut(e)?;
+            }
+            Ok(())
+        },
+
+        Expression::AddressOf(ref mut a) => v.visit_expression_mut(&mut a.inner),
+
+        Expression::Dereference(ref mut d) => v.visit_expression_mut(&mut d.inner),
+
+        Expression::While(ref mut w) => {
+            v.visit_expression_mut(&mut w.cond)?;
+            v.visit_expression_mut(&mut w.body)
+        },
+
+        Expression::Assign(ref mut a) => {
+            match a.left
+            {
+                AssignTarget::Var(ref mut nr) => v.visit_type_mut(&mut nr.typ)?,
+                AssignTarget::Dereference(ref mut d) => v.visit_expression_mut(&mut d.inner)?,
+                AssignTarget::IndexOperation(ref mut iop) => {
+                    v.visit_expression_mut(&mut iop.target)?;
+                    v.visit_expression_mut(&mut iop.index_expr)?;
+                },
+                AssignTarget::MemberAccess(ref mut ma) => walk_member_access_mut(v, ma)?,
+            }
+            v.visit_expression_mut(&mut a.right)
+        },
+
+        Expression::For(ref mut f) => {
+            v.visit_expression_mut(&mut f.iterable)?;
+            if let Some(ref mut next) = f.next {
+                v.visit_expression_mut(next)?;
+            }
+            v.visit_expression_mut(&mut f.body)
+        },
+
+        Expression::OptionalToBool(ref mut o) => v.visit_expression_mut(o),
+
+        Expression::MemberAccess(ref mut ma) => walk_member_access_mut(v, ma),
+
+        Expression::ToOptional(ref mut t) => v.visit_expression_mut(&mut t.inner),
+        Expression::ToInterface(ref mut t) => v.visit_expression_mut(&mut t.inner),
+
+        Expression::Cast(ref mut c) => {
+            v.visit_type_mut(&mut c.destination_type)?;
+            v.visit_expression_mut(&mut c.inner)
+        },
+
+        Expression::TypeAscription(ref mut t) => {
+            v.visit_type_mut(&mut t.typ)?;
+            v.visit_expression_mut(&mut t.inner)
+        },
+
+        Expression::IndexOperation(ref mut iop) => {
+            v.visit_expression_mut(&mut iop.target)?;
+            v.visit_expression_mut(&mut iop.index_expr)
+        },
+
+        Expression::CompilerCall(CompilerCall::Slice{ref mut data, ref mut len, ref mut typ, ..}) => {
+            v.visit_type_mut(typ)?;
+            v.visit_expression_mut(data)?;
+            v.visit_expression_mut(len)
+        },
+
+        Expression::CompilerCall(CompilerCall::SizeOf(ref mut typ, _)) |
+        Expression::CompilerCall(CompilerCall::AlignOf(ref mut typ, _)) => v.visit_type_mut(typ),
+
+        Expression::CompilerCall(CompilerCall::TypeName(ref mut e, _)) => v.visit_expression_mut(e),
+
+        Expression::CompilerCall(CompilerCall::StaticAssert{ref mut cond, ..}) => v.visit_expression_mut(cond),
+
+        Expression::CompilerCall(CompilerCall::AssertType{ref mut expr, ..}) => v.visit_expression_mut(expr),
+
+        Expression::NameRef(ref mut nr) => v.visit_type_mut(&mut nr.typ),
+
+        Expression::Nil(_) |
+        Expression::Void => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use parser::parse_str;
+    use target::Target;
+
+    #[derive(Default)]
+    struct Counter
+    {
+        expressions: usize,
+        name_refs: usize,
+        literals: usize,
+        patterns: usize,
+        bindings: usize,
+    }
+
+    impl Counter
+    {
+        fn count(&mut self, e: &Expression)
+        {
+            self.expressions += 1;
+            match *e
+            {
+                Expression::NameRef(_) => self.name_refs += 1,
+                Expression::Literal(_) => self.literals += 1,
+                _ => (),
+            }
+        }
+
+        fn totals(&self) -> (usize, usize, usize, usize, usize)
+        {
+            (self.expressions, self.name_refs, self.literals, self.patterns, self.bindings)
+        }
+    }
+
+    impl Visitor for Counter
+    {
+        fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+        {
+            self.count(e);
+            walk_expression(self, e)
+        }
+
+        fn visit_pattern(&mut self, p: &Pattern) -> CompileResult<()>
+        {
+            self.patterns += 1;
+            walk_pattern(self, p)
+        }
+
+        fn visit_binding(&mut self, b: &Binding) -> CompileResult<()>
+        {
+            self.bindings += 1;
+            walk_binding(self, b)
+        }
+    }
+
+    impl MutVisitor for Counter
+    {
+        fn visit_expression_mut(&mut self, e: &mut Expression) -> CompileResult<()>
+        {
+            self.count(e);
+            walk_expression_mut(self, e)
+        }
+
+        fn visit_pattern_mut(&mut self, p: &mut Pattern) -> CompileResult<()>
+        {
+            self.patterns += 1;
+            walk_pattern_mut(self, p)
+        }
+
+        fn visit_binding_mut(&mut self, b: &mut Binding) -> CompileResult<()>
+        {
+            self.bindings += 1;
+            walk_binding_mut(self, b)
+        }
+    }
+
+    #[test]
+    fn test_visitor_sees_every_node()
+    {
+        let target = Target::new(IntSize::I32, "");
+        let mut pkg = parse_str(r#"
+struct Point:
+    x: int
+    y: int
+
+fn main() -> int:
+    let p = Point{1, 2}
+    var total = 0
+    for i in [3, 4]:
+        total += i
+    match p:
+        Point{x, y} => x + y + total
+        _ => -1
+"#, "test", &target).expect("Parsing failed");
+
+        let func = pkg.modules.get_mut("test").unwrap().functions.get_mut("test::main").unwrap();
+        let mut counter = Counter::default();
+        counter.visit_expression(&func.expression).unwrap();
+        // block, struct initializer, for, array literal, assign, match, binary ops, unary op and bindings
+        assert_eq!(counter.totals(), (22, 5, 7, 2, 2));
+
+        let mut mut_counter = Counter::default();
+        mut_counter.visit_expression_mut(&mut func.expression).unwrap();
+        assert_eq!(mut_counter.totals(), counter.totals());
+    }
+}
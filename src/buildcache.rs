@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use bincode;
+
+use ast::Import;
+use bytecode::{ByteCodeModule, CompiledModule, OverflowMode, OptimizationLevel};
+use buildinfo::{BuildInfo, COMPILER_VERSION};
+use package::Package;
+use target::Target;
+
+// Bump this when the layout of a cache entry or of the bytecode changes
+const CACHE_FORMAT_VERSION: u32 = 10;
+
+pub const CACHE_DIRECTORY: &str = ".cobra-cache";
+
+// FNV-1a, unlike the hasher of HashMap its output does not change between builds of the compiler
+struct StableHasher
+{
+    state: u64,
+}
+
+impl StableHasher
+{
+    fn new() -> StableHasher
+    {
+        StableHasher{state: 0xcbf2_9ce4_8422_2325}
+    }
+}
+
+impl Hasher for StableHasher
+{
+    fn write(&mut self, bytes: &[u8])
+    {
+        for b in bytes {
+            self.state ^= u64::from(*b);
+            self.state = self.state.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64
+    {
+        self.state
+    }
+}
+
+pub fn digest(data: &[u8]) -> u64
+{
+    let mut hasher = StableHasher::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+// Follows the format version, so it can be read without deserializing the bytecode
+#[derive(Serialize, Deserialize)]
+struct CacheHeader
+{
+    build_info: BuildInfo,
+    module: String,
+    functions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry
+{
+    format_version: u32,    // Must stay the first member, so entries of every version can be recognized
+    header: CacheHeader,
+    key: u64,               // Hash of the build settings and the source of the module
+    imports: Vec<String>,   // The modules it imports, it is only valid when the ones in the package are valid too
+    exports: Rc<Import>,
+    bytecode: CompiledModule,
+}
+
+/*
+    Stores the bytecode of every module of a package, so an unchanged module does not have to be
+    parsed, type checked and compiled again. A module is loaded from the cache when its source and
+    the build settings are the same, and all the modules of the package it imports are loaded too.
+*/
+pub struct BuildCache
+{
+    directory: PathBuf,
+    settings: u64,
+    build_info: BuildInfo,
+    keys: HashMap<String, u64>,
+    entries: HashMap<String, CacheEntry>,
+    loaded: Vec<String>,
+    stored: Vec<String>,
+}
+
+impl BuildCache
+{
+    pub fn new(directory: &Path, target: &Target, optimization_level: OptimizationLevel, overflow_mode: OverflowMode, pkg: &Package) -> BuildCache
+    {
+        let mut hasher = StableHasher::new();
+        CACHE_FORMAT_VERSION.hash(&mut hasher);
+        COMPILER_VERSION.hash(&mut hasher);
+        target.triplet.hash(&mut hasher);
+        format!("{:?}", target.int_size).hash(&mut hasher);
+        let mut features: Vec<&String> = target.features.iter().collect();
+        features.sort();
+        features.hash(&mut hasher);
+        target.max_object_size.hash(&mut hasher);
+        format!("{:?}", optimization_level).hash(&mut hasher);
+        format!("{:?}", overflow_mode).hash(&mut hasher);
+        pkg.import_data.library_digests.hash(&mut hasher);
+
+        BuildCache{
+            directory: directory.to_owned(),
+            settings: hasher.finish(),
+            build_info: BuildInfo::new(target, optimization_level, overflow_mode),
+            keys: HashMap::new(),
+            entries: HashMap::new(),
+            loaded: Vec::new(),
+            stored: Vec::new(),
+        }
+    }
+
+    fn entry_path(&self, module_name: &str) -> PathBuf
+    {
+        self.directory.join(format!("{}.cache", module_name.replace("::", ".")))
+    }
+
+    fn source_key(&self, path: &Path) -> Option<u64>
+    {
+        let mut source = Vec::new();
+        File::open(path).and_then(|mut file| file.read_to_end(&mut source)).ok()?;
+        let mut hasher = StableHasher::new();
+        self.settings.hash(&mut hasher);
+        hasher.write(&source);
+        Some(hasher.finish())
+    }
+
+    // Any problem reading an entry, just means the module gets compiled again
+    fn read_entry(&self, module_name: &str) -> Option<CacheEntry>
+    {
+        let mut file = File::open(self.entry_path(module_name)).ok()?;
+        // The rest of an entry of another version can't be read, its lengths can be anything
+        let format_version: u32 = bincode::deserialize_from(&mut file, bincode::Infinite).ok()?;
+        if format_version != CACHE_FORMAT_VERSION {
+            return None;
+        }
+
+        file.seek(SeekFrom::Start(0)).ok()?;
+        bincode::deserialize_from(&mut file, bincode::Infinite).ok()
+    }
+
+    // Find the modules which can be loaded from the cache, and return the source files which still need to be parsed
+    pub fn load(&mut self, sources: &[(String, PathBuf)]) -> Vec<(String, PathBuf)>
+    {
+        for &(ref name, ref path) in sources {
+            let key = match self.source_key(path) {
+                Some(key) => key,
+                None => continue, // Parsing the file will report the error
+            };
+
+            self.keys.insert(name.clone(), key);
+            if let Some(entry) = self.read_entry(name) {
+                if entry.key == key {
+                    self.entries.insert(name.clone(), entry);
+                }
+            }
+        }
+
+        // Everything which imports a module that has to be compiled, must be compiled too
+        loop
+        {
+            let invalid: Vec<String> = self.entries.iter()
+                .filter(|&(_, entry)| entry.imports.iter().any(|i| self.keys.contains_key(i) && !self.entries.contains_key(i)))
+                .map(|(name, _)| name.clone())
+                .collect();
+            if invalid.is_empty() {
+                break;
+            }
+
+            for name in invalid {
+                self.entries.remove(&name);
+            }
+        }
+
+        sources.iter()
+            .filter(|&&(ref name, _)| !self.entries.contains_key(name))
+            .cloned()
+            .collect()
+    }
+
+    // Add the exports of the loaded modules to the package, and return their bytecode
+    pub fn take_loaded_modules(&mut self, pkg: &mut Package) -> Vec<CompiledModule>
+    {
+        let mut modules = Vec::new();
+        for (name, entry) in self.entries.drain() {
+            pkg.import_data.imports.insert(name.clone(), entry.exports);
+            modules.push(entry.bytecode);
+            self.loaded.push(name);
+        }
+        self.loaded.sort();
+        modules
+    }
+
+    fn store_module(&self, pkg: &Package, bc_mod: &ByteCodeModule, name: &str) -> Option<()>
+    {
+        let md = pkg.modules.get(name)?;
+        let mut global_names: Vec<String> = md.globals.keys().cloned().collect();
+        global_names.sort();
+        let mut imports: Vec<String> = md.import_names.iter().map(|i| i.to_namespace_string()).collect();
+        imports.sort();
+
+        let bytecode = bc_mod.compiled_module(name, &global_names)?;
+        let entry = CacheEntry{
+            format_version: CACHE_FORMAT_VERSION,
+            header: CacheHeader{
+                build_info: self.build_info.clone(),
+                module: name.into(),
+                functions: bytecode.functions.iter().map(|f| f.sig.name.clone()).collect(),
+            },
+            key: *self.keys.get(name)?,
+            imports,
+            exports: pkg.import_data.imports.get(name)?.clone(),
+            bytecode,
+        };
+
+        // Write to a temporary file first, so an interrupted build cannot leave a broken entry behind
+        let path = self.entry_path(name);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path).ok()?;
+        bincode::serialize_into(&mut file, &entry, bincode::Infinite).ok()?;
+        fs::rename(&tmp_path, &path).ok()
+    }
+
+    // Store the modules which were compiled, this must happen before the bytecode is optimized
+    pub fn store(&mut self, pkg: &Package, bc_mod: &ByteCodeModule)
+    {
+        if fs::create_dir_all(&self.directory).is_err() {
+            println!("Warning: cannot create the build cache directory {}", self.directory.to_string_lossy());
+            return;
+        }
+
+        let mut names: Vec<&String> = pkg.modules.keys().collect();
+        names.sort();
+        for name in names {
+            if self.store_module(pkg, bc_mod, name).is_some() {
+                self.stored.push(name.clone());
+            } else {
+                println!("Warning: failed to store module {} in the build cache", name);
+            }
+        }
+    }
+
+    pub fn loaded_modules(&self) -> &[String]
+    {
+        &self.loaded
+    }
+
+    #[cfg(test)]
+    pub fn stored_modules(&self) -> &[String]
+    {
+        &self.stored
+    }
+}
+
+// Describe a cache entry, only its header is read
+pub fn inspect_cache_entry<R: Read>(reader: &mut R) -> Result<String, String>
+{
+    let format_version: u32 = bincode::deserialize_from(reader, bincode::Infinite)
+        .map_err(|e| format!("Not a build cache entry: {}", e))?;
+    if format_version != CACHE_FORMAT_VERSION {
+        return Err(format!("Unsupported build cache format version {}, expecting version {}", format_version, CACHE_FORMAT_VERSION));
+    }
+
+    let header: CacheHeader = bincode::deserialize_from(reader, bincode::Infinite)
+        .map_err(|e| format!("Invalid build cache entry: {}", e))?;
+
+    let mut description = format!("Module: {}\nBuilt by: {}\nFunctions:\n", header.module, header.build_info);
+    for func in &header.functions {
+        description.push_str(&format!("  {}\n", func));
+    }
+    Ok(description)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::io::Write;
+    use ast::{IntSize, LinkKind, LinkLibrary};
+    use packagebuild::compile_package;
+    use timer::PassTimings;
+    use buildinfo::git_commit;
+    use testutil::TempDir;
+
+    struct Fixture
+    {
+        dir: TempDir,
+    }
+
+    impl Fixture
+    {
+        fn new(modules: &[(&str, &str)]) -> Fixture
+        {
+            let f = Fixture{dir: TempDir::new("menhir-cache")};
+            for &(name, src) in modules {
+                f.write(name, src);
+            }
+            f
+        }
+
+        fn write(&self, name: &str, src: &str)
+        {
+            self.dir.write(&format!("app/{}.mhr", name), src);
+        }
+
+        // Returns the compiled package, the modules loaded from the cache, and the modules stored in it
+        fn compile(&self, optimization_level: OptimizationLevel) -> (ByteCodeModule, Vec<String>, Vec<String>)
+        {
+            let target = Target::new(IntSize::I64, "x86_64-unknown-linux-gnu");
+            let mut pkg = Package::new("app");
+            let sources = pkg.source_files(&self.dir.join("app")).expect("Cannot find sources");
+            let mut cache = BuildCache::new(&self.dir.join(CACHE_DIRECTORY), &target, optimization_level, OverflowMode::Check, &pkg);
+            let bc_mod = compile_package(&mut pkg, &sources, Some(&mut cache), &target, OverflowMode::Check, &mut PassTimings::new())
+                .expect("Build failed");
+            (bc_mod, cache.loaded_modules().to_vec(), cache.stored_modules().to_vec())
+        }
+
+        fn build(&self, optimization_level: OptimizationLevel) -> (Vec<String>, Vec<String>)
+        {
+            let (_, loaded, stored) = self.compile(optimization_level);
+            (loaded, stored)
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String>
+    {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    const BASE: &str = "fn base() -> int: 6\n";
+    const TWICE: &str = "import app::base\n\nfn twice() -> int: base() * 2\n";
+    const TOTAL: &str = "import app::twice\n\nfn total() -> int: twice() + 1\n";
+
+    #[test]
+    fn test_unchanged_modules_are_loaded()
+    {
+        let f = Fixture::new(&[("base", BASE), ("twice", TWICE), ("total", TOTAL)]);
+        let all = names(&["app::base", "app::total", "app::twice"]);
+        assert_eq!(f.build(OptimizationLevel::Minimal), (Vec::new(), all.clone()));
+        assert_eq!(f.build(OptimizationLevel::Minimal), (all.clone(), Vec::new()));
+
+        // twice changes, so total which imports it has to be compiled again, base does not
+        f.write("twice", "import app::base\n\nfn twice() -> int: base() + base()\n");
+        assert_eq!(f.build(OptimizationLevel::Minimal), (names(&["app::base"]), names(&["app::total", "app::twice"])));
+        assert_eq!(f.build(OptimizationLevel::Minimal), (all.clone(), Vec::new()));
+
+        // Other build settings invalidate everything
+        assert_eq!(f.build(OptimizationLevel::Normal), (Vec::new(), all.clone()));
+        assert_eq!(f.build(OptimizationLevel::Normal), (all.clone(), Vec::new()));
+    }
+
+    #[test]
+    fn test_broken_entries_are_compiled_again()
+    {
+        let f = Fixture::new(&[("base", BASE), ("twice", TWICE), ("total", TOTAL)]);
+        f.build(OptimizationLevel::Minimal);
+
+        let mut file = File::create(f.dir.join(CACHE_DIRECTORY).join("app.total.cache")).expect("Cannot open cache entry");
+        file.write_all(b"garbage").expect("Cannot write cache entry");
+        assert_eq!(f.build(OptimizationLevel::Minimal), (names(&["app::base", "app::twice"]), names(&["app::total"])));
+    }
+
+    #[test]
+    fn test_entries_of_another_version_are_compiled_again()
+    {
+        let f = Fixture::new(&[("base", BASE), ("twice", TWICE)]);
+        f.build(OptimizationLevel::Minimal);
+
+        // Followed by a length no entry of this version can have
+        let mut file = File::create(f.dir.join(CACHE_DIRECTORY).join("app.twice.cache")).expect("Cannot open cache entry");
+        let old_version = bincode::serialize(&(CACHE_FORMAT_VERSION - 1), bincode::Infinite).expect("Cannot serialize version");
+        file.write_all(&old_version).expect("Cannot write cache entry");
+        file.write_all(&[0xff; 8]).expect("Cannot write cache entry");
+        assert_eq!(f.build(OptimizationLevel::Minimal), (names(&["app::base"]), names(&["app::twice"])));
+    }
+
+    #[test]
+    fn test_inspect_cache_entry()
+    {
+        let f = Fixture::new(&[("base", BASE), ("twice", TWICE)]);
+        f.build(OptimizationLevel::Normal);
+
+        let mut file = File::open(f.dir.join(CACHE_DIRECTORY).join("app.twice.cache")).expect("Cannot open cache entry");
+        let description = inspect_cache_entry(&mut file).expect("Cannot inspect cache entry");
+        assert!(description.starts_with("Module: app::twice\n"));
+        assert!(description.contains(&format!("Built by: menhir {} (commit {})", COMPILER_VERSION, git_commit())));
+        assert!(description.contains("target x86_64-unknown-linux-gnu, --opt-level 2, --release-overflow check\n"));
+        assert!(description.contains("\n  app::twice::twice\n"));
+        assert!(!description.contains("app::base::base"));
+
+        assert!(inspect_cache_entry(&mut &b"garbage"[..]).is_err());
+    }
+
+    #[test]
+    fn test_link_libraries()
+    {
+        let f = Fixture::new(&[
+            ("math", "#[link(name = \"m\")]\nextern fn sqrt(x: float) -> float\n\n#[link(name = \"m\")]\n#[link(name = \"z\", kind = \"static\")]\nextern fn cbrt(x: float) -> float\n"),
+            ("time", "#[link(name = \"rt\")]\n#[link(name = \"m\")]\nextern fn clock_gettime(clock: int32, ts: *void) -> int32\n"),
+        ]);
+
+        let expected = vec![
+            LinkLibrary{name: "m".into(), kind: LinkKind::Dylib},
+            LinkLibrary{name: "z".into(), kind: LinkKind::Static},
+            LinkLibrary{name: "rt".into(), kind: LinkKind::Dylib},
+        ];
+
+        let (bc_mod, loaded, _) = f.compile(OptimizationLevel::Minimal);
+        assert!(loaded.is_empty());
+        assert_eq!(bc_mod.libraries(), expected);
+
+        // The libraries of modules loaded from the cache are still linked in
+        let (bc_mod, loaded, _) = f.compile(OptimizationLevel::Minimal);
+        assert_eq!(loaded, names(&["app::math", "app::time"]));
+        assert_eq!(bc_mod.libraries(), expected);
+    }
+}
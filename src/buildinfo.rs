@@ -0,0 +1,70 @@
+use std::fmt;
+use bytecode::{OptimizationLevel, OverflowMode};
+use target::Target;
+
+pub const COMPILER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Set when building the compiler, for example: COBRA_GIT_COMMIT=$(git rev-parse --short HEAD) cargo build
+pub fn git_commit() -> &'static str
+{
+    option_env!("COBRA_GIT_COMMIT").unwrap_or("unknown")
+}
+
+// Which compiler produced an artifact and with which settings, it is stored in build cache entries,
+// exports files and object files, so a bug report can tell where a broken artifact comes from
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BuildInfo
+{
+    pub compiler_version: String,
+    pub git_commit: String,
+    pub target_triplet: String,
+    pub optimization_level: u8,
+    pub overflow_check: bool,
+    pub features: Vec<String>, // Sorted
+}
+
+impl BuildInfo
+{
+    pub fn new(target: &Target, optimization_level: OptimizationLevel, overflow_mode: OverflowMode) -> BuildInfo
+    {
+        let mut features: Vec<String> = target.features.iter().cloned().collect();
+        features.sort();
+        BuildInfo{
+            compiler_version: COMPILER_VERSION.into(),
+            git_commit: git_commit().into(),
+            target_triplet: target.triplet.clone(),
+            optimization_level: match optimization_level {
+                OptimizationLevel::Off => 0,
+                OptimizationLevel::Minimal => 1,
+                OptimizationLevel::Normal => 2,
+            },
+            overflow_check: overflow_mode == OverflowMode::Check,
+            features,
+        }
+    }
+
+    // The warning to give when an artifact comes from another version of the compiler
+    pub fn version_mismatch(&self, artifact: &str) -> Option<String>
+    {
+        if self.compiler_version == COMPILER_VERSION {
+            None
+        } else {
+            Some(format!("{} was built by version {} of the compiler, but this is version {}, rebuild it if it causes problems",
+                artifact, self.compiler_version, COMPILER_VERSION))
+        }
+    }
+}
+
+impl fmt::Display for BuildInfo
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "menhir {} (commit {}), target {}, --opt-level {}, --release-overflow {}",
+            self.compiler_version, self.git_commit, self.target_triplet, self.optimization_level,
+            if self.overflow_check {"check"} else {"wrap"})?;
+        for feature in &self.features {
+            write!(f, ", --cfg feature={}", feature)?;
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use ast::*;
 use target::{Target};
-use bytecode::{ByteCodeModule, ByteCodeFunction};
+use layout::layout_of;
+use bytecode::{ByteCodeModule, ByteCodeFunction, ByteCodeGlobal, CompiledModule, OverflowMode, verify_module};
 use compileerror::{CompileResult, type_error_result};
 use package::Package;
+use span::Span;
 use super::consteval::expr_to_const;
+use super::initorder::global_init_order;
+use super::mergelambdas::merge_identical_lambdas;
 use super::function::*;
 use super::instruction::*;
 
@@ -13,12 +17,15 @@ fn stack_alloc(func: &mut ByteCodeFunction, typ: &Type, name: Option<&str>) -> V
 {
     match name
     {
-        Some(n) => {
+        // _ discards the value, so it never becomes a named variable
+        Some(n) if n != "_" => {
+            // The type checker rejects bindings without a value
+            debug_assert_ne!(*typ, Type::Void, "Variable {} has type void", n);
             let var = Var::named(n, typ.clone());
             func.add_named_var(var.clone());
             var
         },
-        None => {
+        _ => {
             func.new_var(typ.clone())
         }
     }
@@ -36,11 +43,16 @@ fn get_dst(func: &mut ByteCodeFunction, typ: &Type) -> Var
 
 fn array_lit_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, a: &ArrayLiteral, dst: &Var, target: &Target)
 {
+    if a.elements.is_empty() {
+        // Nothing to store, but the array still has to be defined
+        func.add(Instruction::StackAlloc(dst.clone()));
+    }
+
     for (idx, element) in a.elements.iter().enumerate() {
         func.push_destination(None);
         let v = to_bc(bc_mod, func, element, target);
         func.pop_destination();
-        func.add(store_member_instr(dst, idx, v, target.int_size));
+        func.add(store_member_instr(dst, idx, v, target.int_size, &element.span()));
     }
 }
 
@@ -57,16 +69,611 @@ fn call_args_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c:
     args
 }
 
+// dst = lo <= c && c <= hi
+fn char_in_range(func: &mut ByteCodeFunction, dst: &Var, c: &Operand, lo: char, hi: char)
+{
+    let above_lo = stack_alloc(func, &Type::Bool, None);
+    let below_hi = stack_alloc(func, &Type::Bool, None);
+    func.add(binary_op_instr(&above_lo, BinaryOperator::GreaterThanEquals, c.clone(), Operand::const_char(lo)));
+    func.add(binary_op_instr(&below_hi, BinaryOperator::LessThanEquals, c.clone(), Operand::const_char(hi)));
+    func.add(binary_op_instr(dst, BinaryOperator::And, var_op(&above_lo), var_op(&below_hi)));
+}
+
+// Convert c to the other case if it lies in the range lo..hi, otherwise keep it as is
+fn change_case(func: &mut ByteCodeFunction, dst: &Var, c: Operand, lo: char, hi: char, op: BinaryOperator)
+{
+    let convert_bb = func.create_basic_block("change_case_convert");
+    let keep_bb = func.create_basic_block("change_case_keep");
+    let end_bb = func.create_basic_block("change_case_end");
+    let in_range = stack_alloc(func, &Type::Bool, None);
+    char_in_range(func, &in_range, &c, lo, hi);
+    func.add(branch_if_instr(&in_range, convert_bb, keep_bb));
+
+    func.set_current_bb(convert_bb);
+    let code = stack_alloc(func, &Type::UInt(IntSize::I32), None);
+    let converted_code = stack_alloc(func, &Type::UInt(IntSize::I32), None);
+    let converted = stack_alloc(func, &Type::Char, None);
+    func.add(Instruction::Cast{dst: code.clone(), src: c.clone()});
+    func.add(binary_op_instr(&converted_code, op, var_op(&code), Operand::const_uint(32, IntSize::I32)));
+    func.add(cast_instr(&converted, &converted_code));
+    func.add(store_instr(dst, &converted, &Span::default()));
+    func.add(Instruction::Branch(end_bb));
+
+    func.set_current_bb(keep_bb);
+    func.add(store_operand_instr(dst, c, &Span::default()));
+    func.add(Instruction::Branch(end_bb));
+    func.set_current_bb(end_bb);
+}
+
+// djb2 hash of the bytes of a string: h = h * 33 + byte
+fn hash_string(func: &mut ByteCodeFunction, dst: &Var, s: &Operand, target: &Target)
+{
+    let string = match *s {
+        Operand::Var(ref v) => v.clone(),
+        _ => {
+            let v = stack_alloc(func, &Type::String, None);
+            func.add(store_operand_instr(&v, s.clone(), &Span::default()));
+            v
+        }
+    };
+
+    let uint = |v| Operand::const_uint(v, target.int_size);
+    let data = stack_alloc(func, &ptr_type(Type::UInt(IntSize::I8)), None);
+    let len = stack_alloc(func, &target.native_uint_type, None);
+    let bytes = stack_alloc(func, &slice_type(Type::UInt(IntSize::I8)), None);
+    func.add(get_prop_instr(&data, &string, ByteCodeProperty::Data));
+    func.add(get_prop_instr(&len, &string, ByteCodeProperty::Len));
+    func.add(make_slice_instr(&bytes, data, len.clone()));
+
+    let index = stack_alloc(func, &target.native_uint_type, None);
+    func.add(store_operand_instr(&index, Operand::const_uint(0, target.int_size), &Span::default()));
+    func.add(store_operand_instr(dst, uint(5381), &Span::default()));
+
+    let cond_bb = func.create_basic_block("hash_cond");
+    let body_bb = func.create_basic_block("hash_body");
+    let end_bb = func.create_basic_block("hash_end");
+    func.add(Instruction::Branch(cond_bb));
+
+    func.set_current_bb(cond_bb);
+    let cmp = stack_alloc(func, &Type::Bool, None);
+    func.add(binary_op_instr(&cmp, BinaryOperator::LessThan, var_op(&index), var_op(&len)));
+    func.add(branch_if_instr(&cmp, body_bb, end_bb));
+
+    func.set_current_bb(body_bb);
+    let byte = stack_alloc(func, &Type::UInt(IntSize::I8), None);
+    let wide_byte = stack_alloc(func, &target.native_uint_type, None);
+    func.add(load_member_instr_with_var(&byte, &bytes, &index));
+    func.add(cast_instr(&wide_byte, &byte));
+    func.add(binary_op_instr(dst, BinaryOperator::Mul, var_op(dst), uint(33)));
+    func.add(binary_op_instr(dst, BinaryOperator::Add, var_op(dst), var_op(&wide_byte)));
+    func.add(binary_op_instr(&index, BinaryOperator::Add, var_op(&index), Operand::const_uint(1, target.int_size)));
+    func.add(Instruction::Branch(cond_bb));
+
+    func.set_current_bb(end_bb);
+}
+
+// dst = if x < 0: -x else x + 0.0, the addition turns -0.0 into 0.0
+fn float_abs(func: &mut ByteCodeFunction, dst: &Var, x: &Operand, float_size: FloatSize)
+{
+    let float_type = Type::Float(float_size);
+    let negative = stack_alloc(func, &Type::Bool, None);
+    let negated = stack_alloc(func, &float_type, None);
+    let positive = stack_alloc(func, &float_type, None);
+    func.add(binary_op_instr(&negative, BinaryOperator::LessThan, x.clone(), Operand::const_float(0.0, float_size)));
+    func.add(Instruction::UnaryOp{dst: negated.clone(), op: UnaryOperator::Sub, src: x.clone()});
+    func.add(binary_op_instr(&positive, BinaryOperator::Add, x.clone(), Operand::const_float(0.0, float_size)));
+    func.add(select_instr(dst, &negative, var_op(&negated), var_op(&positive)));
+}
+
+// dst = if x < 0: 0 - x else x, so abs of the smallest signed integer overflows
+fn int_abs(bc_mod: &ByteCodeModule, func: &mut ByteCodeFunction, dst: &Var, x: &Operand, int_size: IntSize, span: &Span)
+{
+    let negative = stack_alloc(func, &Type::Bool, None);
+    let negated = stack_alloc(func, &Type::Int(int_size), None);
+    let zero = Operand::const_int(0, int_size);
+    func.add(binary_op_instr(&negative, BinaryOperator::LessThan, x.clone(), zero.clone()));
+    if bc_mod.overflow_mode == OverflowMode::Check {
+        func.add(checked_binary_op_instr(&negated, BinaryOperator::Sub, zero, x.clone(), span));
+    } else {
+        func.add(binary_op_instr(&negated, BinaryOperator::Sub, zero, x.clone()));
+    }
+    func.add(select_instr(dst, &negative, var_op(&negated), x.clone()));
+}
+
+// dst = the smallest of a and b, or the largest when op is GreaterThan
+// Like the IEEE 754 minNum and maxNum operations, a NaN is only returned when both floats are NaN
+fn min_max(func: &mut ByteCodeFunction, dst: &Var, op: BinaryOperator, a: &Operand, b: &Operand)
+{
+    let take_a = stack_alloc(func, &Type::Bool, None);
+    func.add(binary_op_instr(&take_a, op, a.clone(), b.clone()));
+    if a.get_type().is_float() {
+        // x != x only holds for NaN, comparisons with NaN are not used, so the outcome doesn't depend on how they are done
+        let a_nan = stack_alloc(func, &Type::Bool, None);
+        let b_nan = stack_alloc(func, &Type::Bool, None);
+        let a_not_nan = stack_alloc(func, &Type::Bool, None);
+        let ordered_take_a = stack_alloc(func, &Type::Bool, None);
+        let take_a_or_b_nan = stack_alloc(func, &Type::Bool, None);
+        func.add(binary_op_instr(&a_nan, BinaryOperator::NotEquals, a.clone(), a.clone()));
+        func.add(binary_op_instr(&b_nan, BinaryOperator::NotEquals, b.clone(), b.clone()));
+        func.add(Instruction::UnaryOp{dst: a_not_nan.clone(), op: UnaryOperator::Not, src: var_op(&a_nan)});
+        func.add(binary_op_instr(&ordered_take_a, BinaryOperator::And, var_op(&a_not_nan), var_op(&take_a)));
+        func.add(binary_op_instr(&take_a_or_b_nan, BinaryOperator::Or, var_op(&b_nan), var_op(&ordered_take_a)));
+        func.add(select_instr(dst, &take_a_or_b_nan, a.clone(), b.clone()));
+    } else {
+        func.add(select_instr(dst, &take_a, a.clone(), b.clone()));
+    }
+}
+
+// Print the message on stderr and abort when invalid is true, code added after this only runs when it is false
+fn abort_if(func: &mut ByteCodeFunction, invalid: &Var, purpose: &str, msg: &str)
+{
+    let invalid_bb = func.create_basic_block(&format!("{}_invalid", purpose));
+    let valid_bb = func.create_basic_block(&format!("{}_valid", purpose));
+    func.add(branch_if_instr(invalid, invalid_bb, valid_bb));
+
+    func.set_current_bb(invalid_bb);
+    let msg_str = stack_alloc(func, &Type::String, None);
+    let msg_data = stack_alloc(func, &ptr_type(Type::UInt(IntSize::I8)), None);
+    func.add(store_operand_instr(&msg_str, Operand::const_string(msg.replace("%", "%%")), &Span::default()));
+    func.add(get_prop_instr(&msg_data, &msg_str, ByteCodeProperty::Data));
+    func.add(void_call_instr("dprintf", vec![Operand::const_int(2, IntSize::I32), var_op(&msg_data)], &Span::default()));
+    func.add(void_call_instr("abort", Vec::new(), &Span::default()));
+    func.add(Instruction::Branch(valid_bb));
+
+    func.set_current_bb(valid_bb);
+}
+
+// dst = min(max(v, lo), hi), lo > hi is a runtime error, and so is a NaN bound, a NaN value stays NaN
+fn clamp(func: &mut ByteCodeFunction, dst: &Var, v: &Operand, lo: &Operand, hi: &Operand, span: &Span)
+{
+    let typ = v.get_type();
+    let mut invalid = stack_alloc(func, &Type::Bool, None);
+    func.add(binary_op_instr(&invalid, BinaryOperator::GreaterThan, lo.clone(), hi.clone()));
+    if typ.is_float() {
+        for bound in &[lo, hi] {
+            let nan = stack_alloc(func, &Type::Bool, None);
+            let invalid_or_nan = stack_alloc(func, &Type::Bool, None);
+            func.add(binary_op_instr(&nan, BinaryOperator::NotEquals, (*bound).clone(), (*bound).clone()));
+            func.add(binary_op_instr(&invalid_or_nan, BinaryOperator::Or, var_op(&invalid), var_op(&nan)));
+            invalid = invalid_or_nan;
+        }
+    }
+
+    let msg = if typ.is_float() {
+        format!("{}: clamp: lower bound is greater than upper bound or NaN\n", span)
+    } else {
+        format!("{}: clamp: lower bound is greater than upper bound\n", span)
+    };
+    abort_if(func, &invalid, "clamp", &msg);
+
+    let at_least_lo = stack_alloc(func, &typ, None);
+    min_max(func, &at_least_lo, BinaryOperator::GreaterThan, v, lo);
+    if typ.is_float() {
+        let clamped = stack_alloc(func, &typ, None);
+        let nan = stack_alloc(func, &Type::Bool, None);
+        min_max(func, &clamped, BinaryOperator::LessThan, &var_op(&at_least_lo), hi);
+        func.add(binary_op_instr(&nan, BinaryOperator::NotEquals, v.clone(), v.clone()));
+        func.add(select_instr(dst, &nan, v.clone(), var_op(&clamped)));
+    } else {
+        min_max(func, dst, BinaryOperator::LessThan, &var_op(&at_least_lo), hi);
+    }
+}
+
+// dst = a == b || (|a - b| is finite && |a - b| <= epsilon * max(|a|, |b|, 1.0)), false if any argument is NaN
+// |a - b| <= epsilon * max(...) is checked against each of the three terms separately, which gives the same result
+fn approx_eq(func: &mut ByteCodeFunction, dst: &Var, a: &Operand, b: &Operand, epsilon: &Operand, float_size: FloatSize)
+{
+    let float_type = Type::Float(float_size);
+    let bool_op = |func: &mut ByteCodeFunction, op, left: Operand, right: Operand| {
+        let v = stack_alloc(func, &Type::Bool, None);
+        func.add(binary_op_instr(&v, op, left, right));
+        var_op(&v)
+    };
+
+    // x != x only holds for NaN
+    let a_nan = bool_op(func, BinaryOperator::NotEquals, a.clone(), a.clone());
+    let b_nan = bool_op(func, BinaryOperator::NotEquals, b.clone(), b.clone());
+    let epsilon_nan = bool_op(func, BinaryOperator::NotEquals, epsilon.clone(), epsilon.clone());
+    let any_nan = bool_op(func, BinaryOperator::Or, a_nan, b_nan);
+    let any_nan = bool_op(func, BinaryOperator::Or, any_nan, epsilon_nan);
+
+    let diff = stack_alloc(func, &float_type, None);
+    let abs_diff = stack_alloc(func, &float_type, None);
+    func.add(binary_op_instr(&diff, BinaryOperator::Sub, a.clone(), b.clone()));
+    float_abs(func, &abs_diff, &var_op(&diff), float_size);
+
+    let mut within_tolerance = bool_op(func, BinaryOperator::LessThanEquals, var_op(&abs_diff), epsilon.clone());
+    for x in &[a, b] {
+        let abs_x = stack_alloc(func, &float_type, None);
+        let tolerance = stack_alloc(func, &float_type, None);
+        float_abs(func, &abs_x, x, float_size);
+        func.add(binary_op_instr(&tolerance, BinaryOperator::Mul, epsilon.clone(), var_op(&abs_x)));
+        let within = bool_op(func, BinaryOperator::LessThanEquals, var_op(&abs_diff), var_op(&tolerance));
+        within_tolerance = bool_op(func, BinaryOperator::Or, within_tolerance, within);
+    }
+
+    // Without this, an infinity would be close to every finite number
+    let finite = bool_op(func, BinaryOperator::LessThan, var_op(&abs_diff), Operand::const_float(f64::INFINITY, float_size));
+    let close = bool_op(func, BinaryOperator::And, finite, within_tolerance);
+    let equal = bool_op(func, BinaryOperator::Equals, a.clone(), b.clone());
+    let equal_or_close = bool_op(func, BinaryOperator::Or, equal, close);
+    let not_nan = stack_alloc(func, &Type::Bool, None);
+    func.add(Instruction::UnaryOp{dst: not_nan.clone(), op: UnaryOperator::Not, src: any_nan});
+    func.add(binary_op_instr(dst, BinaryOperator::And, var_op(&not_nan), equal_or_close));
+}
+
+// Only the ASCII range is supported, other characters are never digits, letters or whitespace, and keep their case
+fn builtin_call_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c: &Call, builtin: Builtin, target: &Target) -> Var
+{
+    let dst = get_dst(func, &c.return_type);
+    let mut args = call_args_to_bc(bc_mod, func, c, None, target).into_iter();
+    match builtin
+    {
+        Builtin::IsDigit => {
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            char_in_range(func, &dst, &arg, '0', '9');
+        },
+
+        Builtin::IsAlpha => {
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            let lower = stack_alloc(func, &Type::Bool, None);
+            let upper = stack_alloc(func, &Type::Bool, None);
+            char_in_range(func, &lower, &arg, 'a', 'z');
+            char_in_range(func, &upper, &arg, 'A', 'Z');
+            func.add(binary_op_instr(&dst, BinaryOperator::Or, var_op(&lower), var_op(&upper)));
+        },
+
+        Builtin::IsWhitespace => {
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            let mut result = None;
+            for ws in &[' ', '\t', '\n', '\x0C', '\r'] {
+                let is_ws = stack_alloc(func, &Type::Bool, None);
+                func.add(binary_op_instr(&is_ws, BinaryOperator::Equals, arg.clone(), Operand::const_char(*ws)));
+                result = match result {
+                    None => Some(is_ws),
+                    Some(prev) => {
+                        let either = stack_alloc(func, &Type::Bool, None);
+                        func.add(binary_op_instr(&either, BinaryOperator::Or, var_op(&prev), var_op(&is_ws)));
+                        Some(either)
+                    }
+                };
+            }
+
+            let result = result.expect("Internal Compiler Error: no whitespace characters");
+            func.add(store_instr(&dst, &result, &c.span));
+        },
+
+        Builtin::ToUpper => {
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            change_case(func, &dst, arg, 'a', 'z', BinaryOperator::Sub);
+        },
+
+        Builtin::ToLower => {
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            change_case(func, &dst, arg, 'A', 'Z', BinaryOperator::Add);
+        },
+
+        Builtin::CharToInt => {
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            func.add(Instruction::Cast{dst: dst.clone(), src: arg});
+        },
+
+        Builtin::IntToChar => {
+            // Valid code points are 0..0xD7FF and 0xE000..0x10FFFF
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            let uint32 = |v| Operand::const_uint(v, IntSize::I32);
+            let below_surrogates = stack_alloc(func, &Type::Bool, None);
+            let above_surrogates = stack_alloc(func, &Type::Bool, None);
+            let below_max = stack_alloc(func, &Type::Bool, None);
+            let in_upper_range = stack_alloc(func, &Type::Bool, None);
+            let valid = stack_alloc(func, &Type::Bool, None);
+            func.add(binary_op_instr(&below_surrogates, BinaryOperator::LessThan, arg.clone(), uint32(0xD800)));
+            func.add(binary_op_instr(&above_surrogates, BinaryOperator::GreaterThanEquals, arg.clone(), uint32(0xE000)));
+            func.add(binary_op_instr(&below_max, BinaryOperator::LessThanEquals, arg.clone(), uint32(0x10FFFF)));
+            func.add(binary_op_instr(&in_upper_range, BinaryOperator::And, var_op(&above_surrogates), var_op(&below_max)));
+            func.add(binary_op_instr(&valid, BinaryOperator::Or, var_op(&below_surrogates), var_op(&in_upper_range)));
+
+            let valid_bb = func.create_basic_block("int_to_char_valid");
+            let invalid_bb = func.create_basic_block("int_to_char_invalid");
+            let end_bb = func.create_basic_block("int_to_char_end");
+            func.add(branch_if_instr(&valid, valid_bb, invalid_bb));
+
+            func.set_current_bb(valid_bb);
+            let ch = stack_alloc(func, &Type::Char, None);
+            func.add(Instruction::Cast{dst: ch.clone(), src: arg});
+            func.add(store_instr(&dst, &ch, &c.span));
+            func.add(Instruction::Branch(end_bb));
+
+            func.set_current_bb(invalid_bb);
+            func.add(Instruction::StoreNil(dst.clone()));
+            func.add(Instruction::Branch(end_bb));
+            func.set_current_bb(end_bb);
+        },
+
+        Builtin::Hash => {
+            // Strings use djb2, everything else is a multiplication with the golden ratio (Fibonacci hashing)
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            if arg.get_type() == Type::String {
+                hash_string(func, &dst, &arg, target);
+            } else {
+                let golden_ratio = match target.int_size {
+                    IntSize::I64 => 0x9e37_79b9_7f4a_7c15,
+                    _ => 0x9e37_79b9,
+                };
+                let value = stack_alloc(func, &target.native_uint_type, None);
+                func.add(Instruction::Cast{dst: value.clone(), src: arg});
+                func.add(binary_op_instr(&dst, BinaryOperator::Mul, var_op(&value), Operand::const_uint(golden_ratio, target.int_size)));
+            }
+        },
+
+        Builtin::ApproxEq => {
+            let a = args.next().expect("Internal Compiler Error: missing argument");
+            let b = args.next().expect("Internal Compiler Error: missing argument");
+            let epsilon = args.next().expect("Internal Compiler Error: missing argument");
+            let float_size = match a.get_type() {
+                Type::Float(float_size) => float_size,
+                _ => panic!("Internal Compiler Error: approx_eq expects float arguments"),
+            };
+            approx_eq(func, &dst, &a, &b, &epsilon, float_size);
+        },
+
+        Builtin::Min |
+        Builtin::Max => {
+            let a = args.next().expect("Internal Compiler Error: missing argument");
+            let b = args.next().expect("Internal Compiler Error: missing argument");
+            let op = if builtin == Builtin::Min {BinaryOperator::LessThan} else {BinaryOperator::GreaterThan};
+            min_max(func, &dst, op, &a, &b);
+        },
+
+        Builtin::Abs => {
+            let arg = args.next().expect("Internal Compiler Error: missing argument");
+            match arg.get_type() {
+                Type::Int(int_size) => int_abs(bc_mod, func, &dst, &arg, int_size, &c.span),
+                Type::Float(float_size) => float_abs(func, &dst, &arg, float_size),
+                _ => func.add(store_operand_instr(&dst, arg, &c.span)),
+            }
+        },
+
+        Builtin::Clamp => {
+            let v = args.next().expect("Internal Compiler Error: missing argument");
+            let lo = args.next().expect("Internal Compiler Error: missing argument");
+            let hi = args.next().expect("Internal Compiler Error: missing argument");
+            clamp(func, &dst, &v, &lo, &hi, &c.span);
+        },
+
+        Builtin::WrappingAdd |
+        Builtin::WrappingSub |
+        Builtin::WrappingMul => {
+            let op = match builtin {
+                Builtin::WrappingAdd => BinaryOperator::Add,
+                Builtin::WrappingSub => BinaryOperator::Sub,
+                _ => BinaryOperator::Mul,
+            };
+            let left = args.next().expect("Internal Compiler Error: missing argument");
+            let right = args.next().expect("Internal Compiler Error: missing argument");
+            func.add(binary_op_instr(&dst, op, left, right));
+        },
+
+        Builtin::Spawn |
+        Builtin::Join => panic!("Internal Compiler Error: {} is compiled by thread_call_to_bc", builtin.name()),
+
+        Builtin::Copy |
+        Builtin::Fill |
+        Builtin::CopyWithin |
+        Builtin::Subslice => panic!("Internal Compiler Error: {} is compiled by slice_call_to_bc", builtin.name()),
+    }
+
+    dst
+}
+
+// Threads are pthreads, which start in __menhir_thread_start, that calls the spawned function
+fn thread_call_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c: &Call, target: &Target) -> Option<Var>
+{
+    let arg = call_args_to_bc(bc_mod, func, c, None, target).pop().expect("Internal Compiler Error: missing argument");
+    let status = stack_alloc(func, &Type::Int(IntSize::I32), None);
+    let failed = stack_alloc(func, &Type::Bool, None);
+    if c.builtin == Some(Builtin::Spawn) {
+        let dst = get_dst(func, &Type::Thread);
+        let thread = stack_alloc(func, &Type::Thread, None);
+        let args = vec![
+            Operand::AddressOf(thread.clone()),
+            Operand::Const(Constant::NullPtr(Type::Void)),
+            Operand::Func("__menhir_thread_start".into()),
+            arg,
+        ];
+        func.add(call_instr(&status, "pthread_create", args, &c.span));
+        func.add(binary_op_instr(&failed, BinaryOperator::NotEquals, var_op(&status), Operand::const_int(0, IntSize::I32)));
+        abort_if(func, &failed, "spawn", &format!("{}: spawn: unable to start a thread\n", c.span));
+        func.add(store_instr(&dst, &thread, &c.span));
+        Some(dst)
+    } else {
+        let args = vec![arg, Operand::Const(Constant::NullPtr(Type::Void))];
+        func.add(call_instr(&status, "pthread_join", args, &c.span));
+        func.add(binary_op_instr(&failed, BinaryOperator::NotEquals, var_op(&status), Operand::const_int(0, IntSize::I32)));
+        abort_if(func, &failed, "join", &format!("{}: join: unable to join the thread\n", c.span));
+        None
+    }
+}
+
+// memmove(dst.data, src.data, count * element size), count must not be larger than the length of either slice.
+// Elements are copied as is, just like an assignment copies them, so this works for every element type.
+fn move_elements(func: &mut ByteCodeFunction, dst: &Var, src: &Var, count: &Var, target: &Target)
+{
+    let element_type = match dst.typ {
+        Type::Slice(ref st) => st.element_type.clone(),
+        _ => panic!("Internal Compiler Error: expecting a slice, not a {}", dst.typ),
+    };
+
+    let size = layout_of(&element_type, target).size;
+    let num_bytes = stack_alloc(func, &target.native_uint_type, None);
+    func.add(binary_op_instr(&num_bytes, BinaryOperator::Mul, var_op(count), Operand::const_uint(size as u64, target.int_size)));
+
+    let mut args = Vec::new();
+    for slice in &[dst, src] {
+        let data = stack_alloc(func, &ptr_type(element_type.clone()), None);
+        let void_data = stack_alloc(func, &ptr_type(Type::Void), None);
+        func.add(get_prop_instr(&data, slice, ByteCodeProperty::Data));
+        func.add(cast_instr(&void_data, &data));
+        args.push(var_op(&void_data));
+    }
+    args.push(var_op(&num_bytes));
+    func.add(void_call_instr("memmove", args, &Span::default()));
+}
+
+// Aborts with msg when any of the comparisons is true
+fn abort_if_any(func: &mut ByteCodeFunction, checks: Vec<(Operand, BinaryOperator, Operand)>, purpose: &str, msg: &str)
+{
+    let mut invalid: Option<Var> = None;
+    for (left, op, right) in checks {
+        let failed = stack_alloc(func, &Type::Bool, None);
+        func.add(binary_op_instr(&failed, op, left, right));
+        invalid = Some(match invalid {
+            Some(prev) => {
+                let either = stack_alloc(func, &Type::Bool, None);
+                func.add(binary_op_instr(&either, BinaryOperator::Or, var_op(&prev), var_op(&failed)));
+                either
+            },
+            None => failed,
+        });
+    }
+
+    let invalid = invalid.expect("Internal Compiler Error: no bounds checks");
+    abort_if(func, &invalid, purpose, msg);
+}
+
+// copy(dst, src) copies the elements both slices have room for, and returns how many that is,
+// fill(dst, value) stores value in every element, copy_within(s, from, to, count) copies count elements
+// starting at from to to, the two ranges can overlap, and must be inside the slice, and subslice(s, start, end)
+// returns the elements from start up to end, without copying them
+fn slice_call_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c: &Call, builtin: Builtin, target: &Target) -> Option<Var>
+{
+    let dst = if builtin == Builtin::Copy || builtin == Builtin::Subslice {Some(get_dst(func, &c.return_type))} else {None};
+    let args: Vec<Var> = call_args_to_bc(bc_mod, func, c, None, target).into_iter()
+        .map(|arg| match arg {
+            Operand::Var(v) => v,
+            _ => panic!("Internal Compiler Error: expecting a variable as argument"),
+        })
+        .collect();
+
+    let uint = |v| Operand::const_uint(v, target.int_size);
+    let slice = &args[0];
+    let len = stack_alloc(func, &target.native_uint_type, None);
+    func.add(get_prop_instr(&len, slice, ByteCodeProperty::Len));
+    match builtin
+    {
+        Builtin::Copy => {
+            let src_len = stack_alloc(func, &target.native_uint_type, None);
+            let count = stack_alloc(func, &target.native_uint_type, None);
+            func.add(get_prop_instr(&src_len, &args[1], ByteCodeProperty::Len));
+            min_max(func, &count, BinaryOperator::LessThan, &var_op(&len), &var_op(&src_len));
+            move_elements(func, slice, &args[1], &count, target);
+            if let Some(ref dst) = dst {
+                func.add(cast_instr(dst, &count));
+            }
+        },
+
+        Builtin::Fill => {
+            let index = stack_alloc(func, &target.native_uint_type, None);
+            func.add(store_operand_instr(&index, uint(0), &c.span));
+
+            let cond_bb = func.create_basic_block("fill_cond");
+            let body_bb = func.create_basic_block("fill_body");
+            let end_bb = func.create_basic_block("fill_end");
+            func.add(Instruction::Branch(cond_bb));
+
+            func.set_current_bb(cond_bb);
+            let cmp = stack_alloc(func, &Type::Bool, None);
+            func.add(binary_op_instr(&cmp, BinaryOperator::LessThan, var_op(&index), var_op(&len)));
+            func.add(branch_if_instr(&cmp, body_bb, end_bb));
+
+            func.set_current_bb(body_bb);
+            func.add(store_member_with_var_instr(slice.clone(), index.clone(), args[1].clone(), &c.span));
+            func.add(binary_op_instr(&index, BinaryOperator::Add, var_op(&index), uint(1)));
+            func.add(Instruction::Branch(cond_bb));
+
+            func.set_current_bb(end_bb);
+        },
+
+        Builtin::Subslice => {
+            let (start, end) = (&args[1], &args[2]);
+            let int_len = stack_alloc(func, &target.native_int_type, None);
+            func.add(cast_instr(&int_len, &len));
+            // Reversed bounds would give a negative count, which becomes a huge length as uint
+            let reversed = vec![(var_op(end), BinaryOperator::LessThan, var_op(start))];
+            abort_if_any(func, reversed, "subslice", &format!("{}: subslice: invalid range, start is larger than end\n", c.span));
+            let checks = vec![
+                (var_op(start), BinaryOperator::LessThan, Operand::const_int(0, target.int_size)),
+                (var_op(end), BinaryOperator::GreaterThan, var_op(&int_len)),
+            ];
+            abort_if_any(func, checks, "subslice", &format!("{}: subslice: range out of bounds\n", c.span));
+
+            let count = stack_alloc(func, &target.native_int_type, None);
+            let ustart = stack_alloc(func, &target.native_uint_type, None);
+            let ucount = stack_alloc(func, &target.native_uint_type, None);
+            func.add(binary_op_instr(&count, BinaryOperator::Sub, var_op(end), var_op(start)));
+            func.add(cast_instr(&ustart, start));
+            func.add(cast_instr(&ucount, &count));
+            if let Some(ref dst) = dst {
+                func.add(slice_instr(dst, slice, var_op(&ustart), var_op(&ucount)));
+            }
+        },
+
+        _ => {
+            let (from, to, count) = (&args[1], &args[2], &args[3]);
+            let int_len = stack_alloc(func, &target.native_int_type, None);
+            let room = stack_alloc(func, &target.native_int_type, None);
+            func.add(cast_instr(&int_len, &len));
+            func.add(binary_op_instr(&room, BinaryOperator::Sub, var_op(&int_len), var_op(count)));
+
+            // The subtraction can only wrap around when count is negative, and then the range is invalid anyway
+            let zero = Operand::const_int(0, target.int_size);
+            let checks = vec![
+                (var_op(from), BinaryOperator::LessThan, zero.clone()),
+                (var_op(to), BinaryOperator::LessThan, zero.clone()),
+                (var_op(count), BinaryOperator::LessThan, zero),
+                (var_op(count), BinaryOperator::GreaterThan, var_op(&int_len)),
+                (var_op(from), BinaryOperator::GreaterThan, var_op(&room)),
+                (var_op(to), BinaryOperator::GreaterThan, var_op(&room)),
+            ];
+
+            abort_if_any(func, checks, "copy_within", &format!("{}: copy_within: range out of bounds\n", c.span));
+
+            let mut uints = Vec::new();
+            for v in &[from, to, count] {
+                let u = stack_alloc(func, &target.native_uint_type, None);
+                func.add(cast_instr(&u, v));
+                uints.push(u);
+            }
+
+            let src_part = stack_alloc(func, &slice.typ, None);
+            let dst_part = stack_alloc(func, &slice.typ, None);
+            func.add(slice_instr(&src_part, slice, var_op(&uints[0]), var_op(&uints[2])));
+            func.add(slice_instr(&dst_part, slice, var_op(&uints[1]), var_op(&uints[2])));
+            move_elements(func, &dst_part, &src_part, &uints[2], target);
+        },
+    }
+
+    dst
+}
+
 fn call_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c: &Call, self_arg: Option<Var>, target: &Target) -> Option<Var>
 {
-    if let Type::Void = c.return_type {
+    if c.builtin == Some(Builtin::Spawn) || c.builtin == Some(Builtin::Join) {
+        thread_call_to_bc(bc_mod, func, c, target)
+    } else if let Some(builtin) = c.builtin {
+        match builtin
+        {
+            Builtin::Copy | Builtin::Fill | Builtin::CopyWithin | Builtin::Subslice => slice_call_to_bc(bc_mod, func, c, builtin, target),
+            _ => Some(builtin_call_to_bc(bc_mod, func, c, builtin, target)),
+        }
+    } else if let Type::Void = c.return_type {
         let args = call_args_to_bc(bc_mod, func, c, self_arg, target);
-        func.add(void_call_instr(&c.callee.name, args));
+        func.add(void_call_instr(&c.callee.name, args, &c.span));
         None
     } else {
         let dst = get_dst(func, &c.return_type);
         let args = call_args_to_bc(bc_mod, func, c, self_arg, target);
-        func.add(call_instr(&dst, &c.callee.name, args));
+        func.add(call_instr(&dst, &c.callee.name, args, &c.span));
         Some(dst)
     }
 }
@@ -77,7 +684,7 @@ fn struct_initializer_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunc
     let init_members = |bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, si: &StructInitializer, dst: &Var| {
         for (idx, expr) in si.member_initializers.iter().enumerate() {
             let v = to_bc(bc_mod, func, expr, target);
-            func.add(store_member_instr(dst, idx, v, target.int_size));
+            func.add(store_member_instr(dst, idx, v, target.int_size, &expr.span()));
         }
     };
 
@@ -122,32 +729,93 @@ fn block_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Blo
     }
 }
 
-fn add_struct_pattern_bindings(p: &StructPattern, struct_var: &Var, func: &mut ByteCodeFunction, target: &Target)
+// The range of instructions in a block which initialize a pattern binding
+struct PatternBinding
+{
+    var: Var,
+    bb: BasicBlockRef,
+    start: usize,
+    end: usize,
+}
+
+fn bind_pattern_var<F>(func: &mut ByteCodeFunction, bindings: &mut Vec<PatternBinding>, gen: F)
+    where F: FnOnce(&mut ByteCodeFunction) -> Var
 {
+    let bb = func.current_bb();
+    let start = func.blocks[&bb].instructions.len();
+    let var = gen(func);
+    let end = func.blocks[&bb].instructions.len();
+    bindings.push(PatternBinding{
+        var: var,
+        bb: bb,
+        start: start,
+        end: end,
+    });
+}
+
+// Bindings which are not used in the body of a match case don't need to be initialized.
+// The body consists of the match case block, and all blocks created after next_bb.
+fn remove_unused_bindings(func: &mut ByteCodeFunction, bindings: Vec<PatternBinding>, match_case_bb: BasicBlockRef, next_bb: BasicBlockRef)
+{
+    let unused: Vec<PatternBinding> = bindings.into_iter()
+        .filter(|b| {
+            let is_used = func.blocks.iter()
+                .filter(|&(bb, _)| *bb == match_case_bb || *bb > next_bb)
+                .any(|(bb, block)| {
+                    block.instructions.iter().enumerate().any(|(idx, instr)| {
+                        let initializes_binding = *bb == b.bb && idx >= b.start && idx < b.end;
+                        !initializes_binding && instr.vars().contains(&&b.var)
+                    })
+                });
+            !is_used
+        })
+        .collect();
+
+    // Remove them back to front, so the ranges of the other bindings stay valid
+    for b in unused.iter().rev() {
+        let block = func.blocks.get_mut(&b.bb).expect("Unknown block");
+        block.instructions.drain(b.start..b.end);
+    }
+}
+
+fn add_struct_pattern_bindings(p: &StructPattern, struct_var: &Var, func: &mut ByteCodeFunction, target: &Target) -> Vec<PatternBinding>
+{
+    let mut bindings = Vec::new();
     for (idx, b) in p.bindings.iter().enumerate() {
         if b.name == "_" {continue}
-        let v = stack_alloc(func, &b.typ, Some(&b.name));
-
-        match b.mode {
-            StructPatternBindingMode::Value=> {
-                func.add(load_member_instr(&v, struct_var, idx, target.int_size));
-            },
+        bind_pattern_var(func, &mut bindings, |func| {
+            let v = stack_alloc(func, &b.typ, Some(&b.name));
+            match b.mode {
+                StructPatternBindingMode::Value=> {
+                    func.add(load_member_instr(&v, struct_var, idx, target.int_size));
+                },
 
-            StructPatternBindingMode::Pointer => {
-                func.add(address_of_member_instr(&v, struct_var, idx, target.int_size));
+                StructPatternBindingMode::Pointer => {
+                    func.add(address_of_member_instr(&v, struct_var, idx, target.int_size));
+                }
             }
-        }
-
-        func.add_named_var(v);
+            v
+        });
     }
+    bindings
 }
 
 fn add_binding(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Binding, target: &Target)
 {
     match b.binding_type
     {
+        BindingType::Name(ref name) if name == "_" => {
+            func.push_destination(None);
+            expr_to_bc(bc_mod, func, &b.init, target);
+            func.pop_destination();
+        },
+
         BindingType::Name(ref name) => {
             let dst = stack_alloc(func, &b.typ, Some(name));
+            // Otherwise a mutable variable initialized with a computed value can't be assigned to later
+            if b.mutable && b.typ.pass_by_value() {
+                func.add(Instruction::StackAlloc(dst.clone()));
+            }
             func.push_destination(Some(dst));
             expr_to_bc(bc_mod, func, &b.init, target);
             func.pop_destination();
@@ -160,17 +828,36 @@ fn add_binding(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Bin
             add_struct_pattern_bindings(s, &dst, func, target);
             func.pop_destination();
         },
+
+        BindingType::Array(ref a) => {
+            let dst = stack_alloc(func, &b.typ, None);
+            func.push_destination(Some(dst.clone()));
+            expr_to_bc(bc_mod, func, &b.init, target);
+            func.pop_destination();
+
+            // Binding to an empty array is a runtime error
+            let bind_bb = func.create_basic_block("let_bind");
+            let empty_bb = func.create_basic_block("let_empty");
+            func.push_destination(None);
+            array_pattern_match_to_bc(func, a, &dst, bind_bb, empty_bb, target);
+            func.pop_destination();
+
+            func.set_current_bb(empty_bb);
+            func.add(void_call_instr("abort", Vec::new(), &b.span));
+            func.add(Instruction::Branch(bind_bb));
+            func.set_current_bb(bind_bb);
+        },
     }
 }
 
 fn name_ref_to_bc(func: &mut ByteCodeFunction, nr: &NameRef, target: &Target) -> Option<Var>
 {
     let add_name_ref = |func: &mut ByteCodeFunction, nr: &NameRef| {
-        let v = Var::named(&nr.name, nr.typ.clone());
+        let v = func.named_var(&nr.name, &nr.typ);
         match func.get_destination()
         {
             Some(var) => {
-                func.add(store_instr(&var, &v));
+                func.add(store_instr(&var, &v, &nr.span));
                 Some(var)
             },
             None => Some(v),
@@ -193,7 +880,7 @@ fn name_ref_to_bc(func: &mut ByteCodeFunction, nr: &NameRef, target: &Target) ->
             if let Some(idx) = et.index_of(&nr.name) {
                 // enums are integers
                 let dst = get_dst(func, &nr.typ);
-                func.add(store_operand_instr(&dst, Operand::const_uint(idx as u64, target.int_size)));
+                func.add(store_operand_instr(&dst, Operand::const_uint(idx as u64, target.int_size), &nr.span));
                 Some(dst)
             } else {
                 add_name_ref(func, nr)
@@ -204,10 +891,10 @@ fn name_ref_to_bc(func: &mut ByteCodeFunction, nr: &NameRef, target: &Target) ->
             match func.get_destination()
             {
                 Some(dst) => {
-                    func.add(store_func_instr(&dst, &nr.name));
+                    func.add(store_func_instr(&dst, &nr.name, &nr.span));
                     Some(dst)
                 },
-                None => Some(Var::named(&nr.name, nr.typ.clone())),
+                None => Some(func.named_var(&nr.name, &nr.typ)),
             }
         },
 
@@ -263,7 +950,7 @@ fn member_store_to_bc(func: &mut ByteCodeFunction, sma: &MemberAccess, val: Var,
 
     match sma.right {
         MemberAccessType::Name(ref field) => {
-            func.add(store_member_instr(&ptr, field.index, val, target.int_size));
+            func.add(store_member_instr(&ptr, field.index, val, target.int_size, &sma.span));
         }
 
         _ => panic!("Internal Compiler Error: Invalid member store"),
@@ -293,7 +980,7 @@ fn member_access_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction,
         },
 
         (&Type::Array(ref at), &MemberAccessType::Property(Property::Len)) => {
-            func.add(store_operand_instr(dst, Operand::const_uint(at.len as u64, target.int_size)))
+            func.add(store_operand_instr(dst, Operand::const_uint(at.len as u64, target.int_size), &sma.span))
         },
 
         (&Type::Slice(_), &MemberAccessType::Property(Property::Len)) |
@@ -312,12 +999,9 @@ fn member_access_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction,
     }
 }
 
-fn name_pattern_match_to_bc(
-    bc_mod: &mut ByteCodeModule,
+fn name_pattern_condition_to_bc(
     func: &mut ByteCodeFunction,
-    mc: &MatchCase,
     target: &Var,
-    match_end_bb: BasicBlockRef,
     match_case_bb: BasicBlockRef,
     next_bb: BasicBlockRef,
     nr: &NameRef,
@@ -343,8 +1027,6 @@ fn name_pattern_match_to_bc(
             panic!("Internal Compiler Error: Expression is not a valid match pattern");
         }
     }
-
-    match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, false, target_machine);
 }
 
 
@@ -373,47 +1055,90 @@ fn array_pattern_match_to_bc(
     seq: &Var,
     match_case_bb: BasicBlockRef,
     next_bb: BasicBlockRef,
-    target: &Target)
+    target: &Target) -> Vec<PatternBinding>
 {
-    let head_type = seq.typ.get_element_type().expect("Invalid array type");
-    let head = stack_alloc(func, &head_type, Some(&ap.head));
-    func.add(load_member_instr(&head, seq, 0, target.int_size));
+    let length = stack_alloc(func, &target.native_uint_type, None);
+    func.add(get_prop_instr(&length, seq, ByteCodeProperty::Len));
+    let cond = stack_alloc(func, &Type::Bool, None);
+    func.add(binary_op_instr(&cond, BinaryOperator::GreaterThan, var_op(&length), Operand::const_uint(0, target.int_size)));
+    func.add(branch_if_instr(&cond, match_case_bb, next_bb));
 
-    let tail = stack_alloc(func, &slice_type(head_type), Some(&ap.tail));
-    let tail_len = stack_alloc(func, &target.native_uint_type, None);
-    let seq_len = stack_alloc(func, &target.native_uint_type, None);
-    func.add(get_prop_instr(&seq_len, seq, ByteCodeProperty::Len));
-    func.add(binary_op_instr(&tail_len, BinaryOperator::Sub, var_op(&seq_len), Operand::const_uint(1, target.int_size)));
-    func.add(slice_instr(&tail, seq, Operand::const_uint(1, target.int_size), var_op(&tail_len)));
+    // Head and tail are only extracted when the array is not empty
+    func.set_current_bb(match_case_bb);
+    let mut bindings = Vec::new();
+    let head_type = seq.typ.get_element_type().expect("Invalid array type");
+    if ap.head != "_" {
+        bind_pattern_var(func, &mut bindings, |func| {
+            let head = stack_alloc(func, &head_type, Some(&ap.head));
+            func.add(load_member_instr(&head, seq, 0, target.int_size));
+            head
+        });
+    }
 
+    if ap.tail != "_" {
+        bind_pattern_var(func, &mut bindings, |func| {
+            let tail = stack_alloc(func, &slice_type(head_type.clone()), Some(&ap.tail));
+            let tail_len = stack_alloc(func, &target.native_uint_type, None);
+            func.add(binary_op_instr(&tail_len, BinaryOperator::Sub, var_op(&length), Operand::const_uint(1, target.int_size)));
+            func.add(slice_instr(&tail, seq, Operand::const_uint(1, target.int_size), var_op(&tail_len)));
+            tail
+        });
+    }
+    bindings
+}
 
+// The head of a string is its first code point, the tail is the remainder of the string
+fn string_pattern_match_to_bc(
+    func: &mut ByteCodeFunction,
+    ap: &ArrayPattern,
+    seq: &Var,
+    match_case_bb: BasicBlockRef,
+    next_bb: BasicBlockRef,
+    target: &Target) -> Vec<PatternBinding>
+{
     let length = stack_alloc(func, &target.native_uint_type, None);
     func.add(get_prop_instr(&length, seq, ByteCodeProperty::Len));
     let cond = stack_alloc(func, &Type::Bool, None);
     func.add(binary_op_instr(&cond, BinaryOperator::GreaterThan, var_op(&length), Operand::const_uint(0, target.int_size)));
     func.add(branch_if_instr(&cond, match_case_bb, next_bb));
+
+    // Decoding the first char is only valid on a non empty string
+    func.set_current_bb(match_case_bb);
+    let mut bindings = Vec::new();
+    if ap.head != "_" {
+        bind_pattern_var(func, &mut bindings, |func| {
+            let head = stack_alloc(func, &Type::Char, Some(&ap.head));
+            func.add(get_prop_instr(&head, seq, ByteCodeProperty::FirstChar));
+            head
+        });
+    }
+
+    if ap.tail != "_" {
+        bind_pattern_var(func, &mut bindings, |func| {
+            let head_len = stack_alloc(func, &target.native_uint_type, None);
+            func.add(get_prop_instr(&head_len, seq, ByteCodeProperty::FirstCharLen));
+            let tail_len = stack_alloc(func, &target.native_uint_type, None);
+            func.add(binary_op_instr(&tail_len, BinaryOperator::Sub, var_op(&length), var_op(&head_len)));
+            let tail = stack_alloc(func, &Type::String, Some(&ap.tail));
+            func.add(slice_instr(&tail, seq, var_op(&head_len), var_op(&tail_len)));
+            tail
+        });
+    }
+    bindings
 }
 
-fn struct_pattern_match_to_bc(
-    bc_mod: &mut ByteCodeModule,
+fn struct_pattern_condition_to_bc(
     func: &mut ByteCodeFunction,
-    mc: &MatchCase,
+    p: &StructPattern,
     target: &Var,
-    match_end_bb: BasicBlockRef,
     match_case_bb: BasicBlockRef,
     next_bb: BasicBlockRef,
-    p: &StructPattern,
     target_machine: &Target)
 {
-    func.push_destination(None);
     match p.typ
     {
         Type::Struct(_) => {
             func.add(Instruction::Branch(match_case_bb));
-            func.set_current_bb(match_case_bb);
-
-            func.push_scope();
-            add_struct_pattern_bindings(p, target, func, target_machine);
         },
         Type::Sum(ref st) => {
             let target_sum_type_index = stack_alloc(func, &target_machine.native_uint_type, None);
@@ -422,81 +1147,219 @@ fn struct_pattern_match_to_bc(
             let cond = stack_alloc(func, &Type::Bool, None);
             func.add(binary_op_instr(&cond, BinaryOperator::Equals, var_op(&target_sum_type_index), Operand::const_uint(idx as u64, target_machine.int_size)));
             func.add(branch_if_instr(&cond, match_case_bb, next_bb));
+        },
+        _ => panic!("Internal Compiler Error: Expression is not a valid match pattern"),
+    }
+}
 
-            func.set_current_bb(match_case_bb);
-
-            func.push_scope();
+// The struct the bindings of a struct pattern are taken from, for a sum type this is the struct of the matched case
+fn struct_pattern_source(func: &mut ByteCodeFunction, p: &StructPattern, target: &Var, target_machine: &Target) -> Var
+{
+    match p.typ
+    {
+        Type::Struct(_) => target.clone(),
+        Type::Sum(ref st) => {
+            let idx = st.index_of(&p.name).expect("Internal Compiler Error: cannot determine index of sum type case");
             let struct_ptr = stack_alloc(func, &ptr_type(st.cases[idx].typ.clone()), None);
             func.add(address_of_member_instr(&struct_ptr, target, idx, target_machine.int_size));
-
-            add_struct_pattern_bindings(p, &struct_ptr, func, target_machine);
+            struct_ptr
         },
         _ => panic!("Internal Compiler Error: Expression is not a valid match pattern"),
     }
+}
+
+fn struct_pattern_match_to_bc(
+    bc_mod: &mut ByteCodeModule,
+    func: &mut ByteCodeFunction,
+    mc: &MatchCase,
+    target: &Var,
+    match_end_bb: BasicBlockRef,
+    match_case_bb: BasicBlockRef,
+    next_bb: BasicBlockRef,
+    p: &StructPattern,
+    target_machine: &Target)
+{
+    func.push_destination(None);
+    struct_pattern_condition_to_bc(func, p, target, match_case_bb, next_bb, target_machine);
+    func.set_current_bb(match_case_bb);
+
+    func.push_scope();
+    let struct_var = struct_pattern_source(func, p, target, target_machine);
+    let bindings = add_struct_pattern_bindings(p, &struct_var, func, target_machine);
 
     func.pop_destination();
     match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, true, target_machine);
+    remove_unused_bindings(func, bindings, match_case_bb, next_bb);
 }
 
-fn match_case_to_bc(
+// Every alternative branches to the case when it matches, and to the next alternative when it doesn't.
+// A struct alternative binds its names in a scope of its own, and copies them to variables shared by all
+// alternatives, from which the names are bound again in the scope of the case.
+fn or_pattern_match_to_bc(
     bc_mod: &mut ByteCodeModule,
     func: &mut ByteCodeFunction,
     mc: &MatchCase,
+    alternatives: &[Pattern],
     target: &Var,
     match_end_bb: BasicBlockRef,
+    match_case_bb: BasicBlockRef,
+    next_bb: BasicBlockRef,
     target_machine: &Target)
 {
-    let match_case_bb = func.create_basic_block();
-    let next_bb = func.create_basic_block();
-
-    let add_literal_case = |bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, op: Operand| {
-        func.push_destination(None);
-        let cond = stack_alloc(func, &Type::Bool, None);
-        func.add(binary_op_instr(&cond, BinaryOperator::Equals, op, var_op(target)));
-        func.add(branch_if_instr(&cond, match_case_bb, next_bb));
-        func.pop_destination();
-        match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, false, target_machine);
+    func.push_destination(None);
+    let shared: Vec<(&StructPatternBinding, Var)> = match alternatives.first() {
+        Some(&Pattern::Struct(ref p)) => {
+            p.bindings.iter()
+                .filter(|b| b.name != "_")
+                .map(|b| {
+                    let var = func.new_var(b.typ.clone());
+                    func.add(Instruction::StackAlloc(var.clone()));
+                    (b, var)
+                })
+                .collect()
+        },
+        _ => Vec::new(),
     };
 
-    match mc.pattern
+    for (idx, alternative) in alternatives.iter().enumerate()
     {
-        Pattern::Literal(Literal::Int(_, v, int_size)) => {
-            add_literal_case(bc_mod, func, Operand::const_int(v, int_size));
-        },
+        let alternative_next_bb = if idx + 1 == alternatives.len() {
+            next_bb
+        } else {
+            func.create_basic_block("match_or")
+        };
 
-        Pattern::Literal(Literal::UInt(_, v, int_size)) => {
-            add_literal_case(bc_mod, func, Operand::const_uint(v, int_size));
-        },
+        match *alternative
+        {
+            Pattern::Struct(ref p) if !shared.is_empty() => {
+                let bind_bb = func.create_basic_block("match_or_bind");
+                struct_pattern_condition_to_bc(func, p, target, bind_bb, alternative_next_bb, target_machine);
+                func.set_current_bb(bind_bb);
+
+                func.push_scope();
+                let struct_var = struct_pattern_source(func, p, target, target_machine);
+                for binding in add_struct_pattern_bindings(p, &struct_var, func, target_machine) {
+                    if let Some(&(_, ref var)) = shared.iter().find(|&&(b, _)| b.name == binding.var.name) {
+                        func.add(store_instr(var, &binding.var, &mc.span));
+                    }
+                }
+                func.pop_scope();
+                func.add(Instruction::Branch(match_case_bb));
+            },
 
-        Pattern::Literal(Literal::Float(_, ref v, float_size)) => {
-            add_literal_case(bc_mod, func, float_op(v, float_size));
-        },
+            _ => pattern_condition_to_bc(bc_mod, func, alternative, target, match_case_bb, alternative_next_bb, target_machine),
+        }
 
-        Pattern::Literal(Literal::Bool(_, v)) => {
-            add_literal_case(bc_mod, func, Operand::const_bool(v));
-        },
+        func.set_current_bb(alternative_next_bb);
+    }
 
-        Pattern::Literal(Literal::Char(_, v)) => {
-            add_literal_case(bc_mod, func, Operand::const_char(v));
+    func.set_current_bb(match_case_bb);
+    func.push_scope();
+    for &(b, ref var) in &shared {
+        let v = stack_alloc(func, &b.typ, Some(&b.name));
+        func.add(store_instr(&v, var, &mc.span));
+    }
+
+    func.pop_destination();
+    match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, true, target_machine);
+}
+
+fn literal_operand(lit: &Literal) -> Operand
+{
+    match *lit
+    {
+        Literal::Int(_, v, int_size) => Operand::const_int(v, int_size),
+        Literal::UInt(_, v, int_size) => Operand::const_uint(v, int_size),
+        Literal::Char(_, v) => Operand::const_char(v),
+        _ => panic!("Internal Compiler Error: range pattern bounds must be integer or char literals"),
+    }
+}
+
+// Binds the whole match target to the name of a binding pattern, in a new scope which the match case has to end.
+// The binding can have the same name as a variable outside the match, so it gets a name of its own.
+fn bind_match_target(func: &mut ByteCodeFunction, bp: &BindingPattern, target: &Var)
+{
+    func.push_scope();
+    let binding = func.new_shadowing_var(&bp.name, bp.typ.clone());
+    // A match on a dereferenced struct matches on the pointer
+    if target.typ != bp.typ && target.typ.get_pointer_element_type() == Some(&bp.typ) {
+        func.add(load_instr(&binding, target));
+    } else {
+        func.add(store_instr(&binding, target, &bp.span));
+    }
+}
+
+// Branches to match_case_bb when a pattern which binds no names matches the target, and to next_bb when it doesn't.
+// For a struct pattern this is only the check, its bindings still have to be added.
+// The value a literal pattern is compared with
+fn literal_pattern_operand(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, lit: &Literal, target_machine: &Target) -> Operand
+{
+    match *lit
+    {
+        Literal::Int(_, v, int_size) => Operand::const_int(v, int_size),
+        Literal::UInt(_, v, int_size) => Operand::const_uint(v, int_size),
+        Literal::Float(_, ref v, float_size) => float_op(v, float_size),
+        Literal::Bool(_, v) => Operand::const_bool(v),
+        Literal::Char(_, v) => Operand::const_char(v),
+        Literal::NullPtr(_, ref inner_type) => Operand::Const(Constant::NullPtr(inner_type.clone())),
+        Literal::String(_, ref s) => Operand::const_string(&s[..]),
+        Literal::Bytes(_, ref b) => Operand::Const(Constant::Bytes(b.clone())),
+        Literal::Array(ref a) => {
+            let arr = func.new_var(a.array_type.clone());
+            array_lit_to_bc(bc_mod, func, a, &arr, target_machine);
+            var_op(&arr)
         },
+    }
+}
 
-        Pattern::Literal(Literal::NullPtr(_, ref inner_type)) => {
-            add_literal_case(bc_mod, func, Operand::Const(Constant::NullPtr(inner_type.clone())));
+// start <= target && target <= end, or target < end when the end is excluded
+fn range_pattern_condition_to_bc(func: &mut ByteCodeFunction, r: &RangePattern, target: &Var, cond: &Var)
+{
+    let end_op = if r.inclusive {BinaryOperator::LessThanEquals} else {BinaryOperator::LessThan};
+    let above_start = stack_alloc(func, &Type::Bool, None);
+    let below_end = stack_alloc(func, &Type::Bool, None);
+    func.add(binary_op_instr(&above_start, BinaryOperator::GreaterThanEquals, var_op(target), literal_operand(&r.start)));
+    func.add(binary_op_instr(&below_end, end_op, var_op(target), literal_operand(&r.end)));
+    func.add(binary_op_instr(cond, BinaryOperator::And, var_op(&above_start), var_op(&below_end)));
+}
+
+fn pattern_condition_to_bc(
+    bc_mod: &mut ByteCodeModule,
+    func: &mut ByteCodeFunction,
+    p: &Pattern,
+    target: &Var,
+    match_case_bb: BasicBlockRef,
+    next_bb: BasicBlockRef,
+    target_machine: &Target)
+{
+    func.push_destination(None);
+    match *p
+    {
+        Pattern::Literal(ref lit) => {
+            let op = literal_pattern_operand(bc_mod, func, lit, target_machine);
+            let cond = stack_alloc(func, &Type::Bool, None);
+            func.add(binary_op_instr(&cond, BinaryOperator::Equals, op, var_op(target)));
+            func.add(branch_if_instr(&cond, match_case_bb, next_bb));
         },
 
         Pattern::Name(ref nr) => {
-            name_pattern_match_to_bc(bc_mod, func, mc, target, match_end_bb, match_case_bb, next_bb, nr, target_machine)
+            name_pattern_condition_to_bc(func, target, match_case_bb, next_bb, nr, target_machine);
+        },
+
+        Pattern::Range(ref r) => {
+            let cond = stack_alloc(func, &Type::Bool, None);
+            range_pattern_condition_to_bc(func, r, target, &cond);
+            func.add(branch_if_instr(&cond, match_case_bb, next_bb));
         },
 
         Pattern::Any(_) => {
             func.add(Instruction::Branch(match_case_bb));
-            match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, false, target_machine);
         },
 
         Pattern::EmptyArray(_) => {
             match target.typ
             {
-                Type::Array(_) | Type::Slice(_) => {
+                Type::String | Type::Array(_) | Type::Slice(_) => {
                     let len = stack_alloc(func, &target_machine.native_uint_type, None);
                     let cond = stack_alloc(func, &Type::Bool, None);
                     func.add(get_prop_instr(&len, target, ByteCodeProperty::Len));
@@ -505,75 +1368,225 @@ fn match_case_to_bc(
                 },
                 _ => panic!("Internal Compiler Error: Match expression cannot be matched with an empty array pattern"),
             }
+        },
 
-            match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, false, target_machine);
+        Pattern::Nil(_) => {
+            let cond = stack_alloc(func, &Type::Bool, None);
+            func.add(load_optional_flag_instr(&cond, target));
+            func.add(branch_if_instr(&cond, next_bb, match_case_bb));
+        },
+
+        Pattern::Struct(ref sp) => {
+            struct_pattern_condition_to_bc(func, sp, target, match_case_bb, next_bb, target_machine);
+        },
+
+        Pattern::Array(_) |
+        Pattern::Binding(_) |
+        Pattern::Optional(_) |
+        Pattern::Or(_) => panic!("Internal Compiler Error: pattern binds names, so it needs more than a condition"),
+    }
+    func.pop_destination();
+}
+
+fn match_case_to_bc(
+    bc_mod: &mut ByteCodeModule,
+    func: &mut ByteCodeFunction,
+    mc: &MatchCase,
+    target: &Var,
+    match_end_bb: BasicBlockRef,
+    target_machine: &Target)
+{
+    let match_case_bb = func.create_basic_block("match_case");
+    let next_bb = func.create_basic_block("match_next");
+
+    match mc.pattern
+    {
+        Pattern::Binding(ref bp) => {
+            func.add(Instruction::Branch(match_case_bb));
+            func.set_current_bb(match_case_bb);
+            bind_match_target(func, bp, target);
+            match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, true, target_machine);
         },
 
         Pattern::Array(ref ap) => {
-            match target.typ
+            func.push_destination(None);
+            let bindings = match target.typ
             {
-                Type::Array(_) | Type::Slice(_) => {
-                    func.push_destination(None);
-                    array_pattern_match_to_bc(func, ap, target, match_case_bb, next_bb, target_machine);
-                    func.pop_destination();
-                },
+                Type::Array(_) | Type::Slice(_) => array_pattern_match_to_bc(func, ap, target, match_case_bb, next_bb, target_machine),
+                Type::String => string_pattern_match_to_bc(func, ap, target, match_case_bb, next_bb, target_machine),
                 _ => panic!("Internal Compiler Error: Match expression cannot be matched with an array pattern"),
-            }
+            };
+            func.pop_destination();
 
             match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, false, target_machine);
+            remove_unused_bindings(func, bindings, match_case_bb, next_bb);
         },
 
-        Pattern::Literal(Literal::Array(ref a)) => {
-            func.push_destination(None);
-            let arr = func.new_var(a.array_type.clone());
-            array_lit_to_bc(bc_mod, func, a, &arr, target_machine);
-            let cond = stack_alloc(func, &Type::Bool, None);
-            func.add(binary_op_instr(&cond, BinaryOperator::Equals, var_op(&arr), var_op(target)));
-            func.add(branch_if_instr(&cond, match_case_bb, next_bb));
-            func.pop_destination();
-            match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, false, target_machine);
+        Pattern::Struct(ref p) => {
+            struct_pattern_match_to_bc(bc_mod, func, mc, target, match_end_bb, match_case_bb, next_bb, p, target_machine);
         },
 
-        Pattern::Literal(Literal::String(_, ref s)) => {
-            func.push_destination(None);
+        Pattern::Optional(ref o) => {
             let cond = stack_alloc(func, &Type::Bool, None);
-            func.add(binary_op_instr(&cond, BinaryOperator::Equals, Operand::const_string(&s[..]), var_op(target)));
+            func.add(load_optional_flag_instr(&cond, target));
             func.add(branch_if_instr(&cond, match_case_bb, next_bb));
-            func.pop_destination();
+
+            func.set_current_bb(match_case_bb);
+            func.push_scope();
+
+            if o.binding != "_" {
+                let binding = stack_alloc(func, &o.inner_type, Some(&o.binding));
+                func.add(load_instr(&binding, target));
+            }
+            match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, true, target_machine);
+        },
+
+        Pattern::Or(ref alternatives) => {
+            or_pattern_match_to_bc(bc_mod, func, mc, alternatives, target, match_end_bb, match_case_bb, next_bb, target_machine);
+        },
+
+        _ => {
+            pattern_condition_to_bc(bc_mod, func, &mc.pattern, target, match_case_bb, next_bb, target_machine);
             match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, false, target_machine);
         },
+    }
+}
 
-        Pattern::Struct(ref p) => {
-            struct_pattern_match_to_bc(bc_mod, func, mc, target, match_end_bb, match_case_bb, next_bb, p, target_machine);
+// A match needs at least this many cases, before a jump table is worth it
+const MIN_SWITCH_CASES: usize = 4;
+
+// A match which only compares the same integer value against a table of constants
+struct SwitchPlan<'a>
+{
+    cases: Vec<(Constant, &'a MatchCase)>,
+    default: Option<&'a MatchCase>,
+}
+
+fn switch_case_value(pattern: &Pattern, target: &Target) -> Option<Constant>
+{
+    match *pattern
+    {
+        Pattern::Literal(Literal::Int(_, v, int_size)) => Some(Constant::Int(v, int_size)),
+        Pattern::Literal(Literal::UInt(_, v, int_size)) => Some(Constant::UInt(v, int_size)),
+        Pattern::Name(ref nr) => {
+            let idx = match nr.typ
+            {
+                Type::Enum(ref et) => et.index_of(&nr.name),
+                Type::Sum(ref st) => st.index_of(&nr.name),
+                _ => None,
+            };
+            idx.map(|idx| Constant::UInt(idx as u64, target.int_size))
         },
+        _ => None,
+    }
+}
+
+fn switch_key(c: &Constant) -> i128
+{
+    match *c
+    {
+        Constant::Int(v, _) => i128::from(v),
+        Constant::UInt(v, _) => i128::from(v),
+        _ => panic!("Internal Compiler Error: switch on a non integer constant"),
+    }
+}
+
+// Number of values a pattern's type can have, if it is small enough to cover them all
+fn number_of_cases(pattern: &Pattern) -> Option<usize>
+{
+    match *pattern
+    {
+        Pattern::Name(NameRef{typ: Type::Enum(ref et), ..}) => Some(et.cases.len()),
+        Pattern::Name(NameRef{typ: Type::Sum(ref st), ..}) => Some(st.cases.len()),
+        _ => None,
+    }
+}
+
+// Matches on dense integer literals, enum cases or sum type cases can use a switch instead of a chain of compares
+fn switch_plan<'a>(m: &'a MatchExpression, target: &Target) -> Option<SwitchPlan<'a>>
+{
+    let mut cases = Vec::new();
+    let mut keys = HashSet::new();
+    let mut default = None;
+    for mc in &m.cases
+    {
+        if mc.pattern.is_catch_all() {
+            // Everything after a wildcard or a binding is unreachable
+            default = Some(mc);
+            break;
+        }
+
+        let value = switch_case_value(&mc.pattern, target)?;
+        // Only the first case with a value can ever be reached
+        if keys.insert(switch_key(&value)) {
+            cases.push((value, mc));
+        }
+    }
+
+    if cases.len() < MIN_SWITCH_CASES {
+        return None;
+    }
+
+    let min = keys.iter().min().cloned()?;
+    let max = keys.iter().max().cloned()?;
+    if max - min >= 2 * cases.len() as i128 {
+        return None;
+    }
 
-        Pattern::Nil(_) => {
-            let cond = stack_alloc(func, &Type::Bool, None);
-            func.add(load_optional_flag_instr(&cond, target));
-            func.add(branch_if_instr(&cond, next_bb, match_case_bb));
-            match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, false, target_machine);
-        },
+    // When every case of an enum or sum type is covered, the last one can be the default
+    if default.is_none() && number_of_cases(&m.cases[0].pattern) == Some(cases.len()) {
+        default = cases.pop().map(|(_, mc)| mc);
+    }
 
-        Pattern::Optional(ref o) => {
-            let cond = stack_alloc(func, &Type::Bool, None);
-            func.add(load_optional_flag_instr(&cond, target));
-            func.add(branch_if_instr(&cond, match_case_bb, next_bb));
+    Some(SwitchPlan{cases, default})
+}
 
-            func.set_current_bb(match_case_bb);
-            func.push_scope();
+fn switch_to_bc(
+    bc_mod: &mut ByteCodeModule,
+    func: &mut ByteCodeFunction,
+    plan: &SwitchPlan,
+    target: &Var,
+    match_end_bb: BasicBlockRef,
+    target_machine: &Target)
+{
+    let target_type = target.typ.get_pointer_element_type().unwrap_or(&target.typ);
+    let cond = if let Type::Sum(_) = *target_type {
+        let sum_type_index = stack_alloc(func, &target_machine.native_uint_type, None);
+        func.add(get_prop_instr(&sum_type_index, target, ByteCodeProperty::SumTypeIndex));
+        sum_type_index
+    } else {
+        target.clone()
+    };
 
-            let binding = stack_alloc(func, &o.inner_type, Some(&o.binding));
-            func.add(load_instr(&binding, target));
-            func.add_named_var(binding);
-            match_case_body_to_bc(bc_mod, func, mc, match_case_bb, match_end_bb, next_bb, true, target_machine);
-        },
+    let case_bbs: Vec<BasicBlockRef> = plan.cases.iter().map(|_| func.create_basic_block("match_case")).collect();
+    let default_bb = if plan.default.is_some() {
+        func.create_basic_block("match_case")
+    } else {
+        match_end_bb
+    };
+
+    let table = plan.cases.iter().zip(case_bbs.iter()).map(|(&(ref c, _), &bb)| (c.clone(), bb)).collect();
+    func.add(switch_instr(&cond, table, default_bb));
+
+    let bodies = plan.cases.iter().map(|&(_, mc)| mc).zip(case_bbs.iter().cloned()).chain(plan.default.map(|mc| (mc, default_bb)));
+    for (mc, bb) in bodies {
+        func.set_current_bb(bb);
+        if let Pattern::Binding(ref bp) = mc.pattern {
+            bind_match_target(func, bp, target);
+            expr_to_bc(bc_mod, func, &mc.to_execute, target_machine);
+            func.pop_scope();
+        } else {
+            expr_to_bc(bc_mod, func, &mc.to_execute, target_machine);
+        }
+        func.add(Instruction::Branch(match_end_bb));
     }
 }
 
-fn match_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, m: &MatchExpression, target: &Target) -> Option<Var>
+// Matching on a dereferenced pointer to a struct or sum type, uses the pointer instead of a copy
+fn match_target_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, e: &Expression, target: &Target) -> Var
 {
     func.push_destination(None);
-    let target_var = match m.target {
+    let target_var = match *e {
         Expression::Dereference(ref de) => {
             let inner_type = de.inner.get_type(target.int_size);
             let v_inner_type = inner_type.get_pointer_element_type().expect("Dereference should be on a pointer type");
@@ -584,11 +1597,17 @@ fn match_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, m: &Mat
             }
         }
 
-        _ => to_bc(bc_mod, func, &m.target, target),
+        _ => to_bc(bc_mod, func, e, target),
     };
 
     func.pop_destination();
-    let match_end_bb = func.create_basic_block();
+    target_var
+}
+
+fn match_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, m: &MatchExpression, target: &Target) -> Option<Var>
+{
+    let target_var = match_target_to_bc(bc_mod, func, &m.target, target);
+    let match_end_bb = func.create_basic_block("match_end");
 
     let dst = if m.typ == Type::Void {
         None
@@ -601,8 +1620,14 @@ fn match_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, m: &Mat
 
     func.push_scope();
     func.push_destination(dst.clone());
-    for mc in &m.cases {
-        match_case_to_bc(bc_mod, func, mc, &target_var, match_end_bb, target);
+    match switch_plan(m, target)
+    {
+        Some(plan) => switch_to_bc(bc_mod, func, &plan, &target_var, match_end_bb, target),
+        None => {
+            for mc in &m.cases {
+                match_case_to_bc(bc_mod, func, mc, &target_var, match_end_bb, target);
+            }
+        },
     }
     func.pop_destination();
 
@@ -612,11 +1637,66 @@ fn match_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, m: &Mat
     dst
 }
 
+// Literal, range, nil and _ patterns compute the result directly, other patterns need a branch per alternative
+fn is_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, i: &IsExpression, target: &Target) -> Var
+{
+    let value = match_target_to_bc(bc_mod, func, &i.inner, target);
+    let dst = get_dst(func, &Type::Bool);
+    func.push_destination(None);
+    match i.pattern
+    {
+        Pattern::Literal(ref lit) => {
+            let op = literal_pattern_operand(bc_mod, func, lit, target);
+            func.add(binary_op_instr(&dst, BinaryOperator::Equals, op, var_op(&value)));
+        },
+
+        Pattern::Range(ref r) => {
+            range_pattern_condition_to_bc(func, r, &value, &dst);
+        },
+
+        Pattern::Any(_) => {
+            func.add(store_operand_instr(&dst, Operand::const_bool(true), &i.span));
+        },
+
+        Pattern::Optional(_) => {
+            func.add(load_optional_flag_instr(&dst, &value));
+        },
+
+        Pattern::Nil(_) => {
+            let flag = stack_alloc(func, &Type::Bool, None);
+            func.add(load_optional_flag_instr(&flag, &value));
+            func.add(unary_op_instr(&dst, UnaryOperator::Not, var_op(&flag)));
+        },
+
+        _ => {
+            let true_bb = func.create_basic_block("is_true");
+            let false_bb = func.create_basic_block("is_false");
+            let end_bb = func.create_basic_block("is_end");
+            let alternatives = i.pattern.alternatives();
+            for (idx, p) in alternatives.iter().enumerate() {
+                let next_bb = if idx + 1 == alternatives.len() {false_bb} else {func.create_basic_block("is_or")};
+                pattern_condition_to_bc(bc_mod, func, p, &value, true_bb, next_bb, target);
+                func.set_current_bb(next_bb);
+            }
+
+            func.set_current_bb(true_bb);
+            func.add(store_operand_instr(&dst, Operand::const_bool(true), &i.span));
+            func.add(Instruction::Branch(end_bb));
+            func.set_current_bb(false_bb);
+            func.add(store_operand_instr(&dst, Operand::const_bool(false), &i.span));
+            func.add(Instruction::Branch(end_bb));
+            func.set_current_bb(end_bb);
+        },
+    }
+    func.pop_destination();
+    dst
+}
+
 fn while_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, w: &WhileLoop, target: &Target)
 {
-    let cond_bb = func.create_basic_block();
-    let body_bb = func.create_basic_block();
-    let post_while_bb = func.create_basic_block();
+    let cond_bb = func.create_basic_block("while_cond");
+    let body_bb = func.create_basic_block("while_body");
+    let post_while_bb = func.create_basic_block("while_end");
 
     func.add(Instruction::Branch(cond_bb));
     func.set_current_bb(cond_bb);
@@ -629,8 +1709,48 @@ fn while_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, w: &Whi
     func.set_current_bb(post_while_bb);
 }
 
+// The iterator is stored in a variable, and next is called on it until it returns nil
+fn iterator_for_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, f: &ForLoop, next: &Expression, target: &Target)
+{
+    func.push_scope();
+    func.push_destination(None);
+    let iterable = to_bc(bc_mod, func, &f.iterable, target);
+    func.pop_destination();
+
+    let iterator = stack_alloc(func, &iterable.typ, Some(&f.iterator_name()));
+    func.add(store_instr(&iterator, &iterable, &f.span));
+    let loop_variable = stack_alloc(func, &f.loop_variable_type, Some(&f.loop_variable));
+
+    let cond_bb = func.create_basic_block("for_cond");
+    let body_bb = func.create_basic_block("for_body");
+    let post_for_bb = func.create_basic_block("for_end");
+
+    func.add(Instruction::Branch(cond_bb));
+    func.set_current_bb(cond_bb);
+    func.push_destination(None);
+    let element = to_bc(bc_mod, func, next, target);
+    func.pop_destination();
+    let cond = stack_alloc(func, &Type::Bool, None);
+    func.add(load_optional_flag_instr(&cond, &element));
+    func.add(branch_if_instr(&cond, body_bb, post_for_bb));
+
+    func.set_current_bb(body_bb);
+    func.add(load_instr(&loop_variable, &element));
+    func.push_destination(None);
+    expr_to_bc(bc_mod, func, &f.body, target);
+    func.pop_destination();
+    func.add(Instruction::Branch(cond_bb));
+
+    func.set_current_bb(post_for_bb);
+    func.pop_scope();
+}
+
 fn for_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, f: &ForLoop, target: &Target)
 {
+    if let Some(ref next) = f.next {
+        return iterator_for_to_bc(bc_mod, func, f, next, target);
+    }
+
     func.push_scope();
     func.push_destination(None);
     let iterable = to_bc(bc_mod, func, &f.iterable, target);
@@ -639,7 +1759,7 @@ fn for_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, f: &ForLo
     let loop_variable = stack_alloc(func, &f.loop_variable_type, Some(&f.loop_variable));
 
     let index = stack_alloc(func, &target.native_uint_type, None);
-    func.add(store_operand_instr(&index, Operand::const_uint(0, target.int_size)));
+    func.add(store_operand_instr(&index, Operand::const_uint(0, target.int_size), &f.span));
 
 
     let len = if let Type::Array(ref at) = iterable.typ {
@@ -650,9 +1770,9 @@ fn for_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, f: &ForLo
         var_op(&len)
     };
 
-    let cond_bb = func.create_basic_block();
-    let body_bb = func.create_basic_block();
-    let post_for_bb = func.create_basic_block();
+    let cond_bb = func.create_basic_block("for_cond");
+    let body_bb = func.create_basic_block("for_body");
+    let post_for_bb = func.create_basic_block("for_end");
 
     func.add(Instruction::Branch(cond_bb));
     func.set_current_bb(cond_bb);
@@ -678,10 +1798,80 @@ fn cast_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c: &Type
     func.push_destination(None);
     let inner = to_bc(bc_mod, func, &c.inner, target);
     func.pop_destination();
-    func.add(cast_instr(&dst, &inner));
+    if c.destination_type == Type::String {
+        // A byte slice has the same layout as a string, so just copy data and len
+        let data = stack_alloc(func, &ptr_type(Type::UInt(IntSize::I8)), None);
+        let len = stack_alloc(func, &target.native_uint_type, None);
+        func.add(get_prop_instr(&data, &inner, ByteCodeProperty::Data));
+        func.add(get_prop_instr(&len, &inner, ByteCodeProperty::Len));
+        func.add(make_slice_instr(&dst, data, len));
+    } else if let (&Type::Pointer(ref inner_type), &Type::Bool) = (&inner.typ, &c.destination_type) {
+        // A pointer is true when it is not null
+        let null = Operand::Const(Constant::NullPtr(inner_type.as_ref().clone()));
+        func.add(binary_op_instr(&dst, BinaryOperator::NotEquals, var_op(&inner), null));
+    } else {
+        func.add(cast_instr(&dst, &inner));
+    }
+    dst
+}
+
+fn to_interface_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, t: &ToInterface, target: &Target) -> Var
+{
+    let dst = get_dst(func, &t.interface_type);
+    func.push_destination(None);
+    let inner = to_bc(bc_mod, func, &t.inner, target);
+    func.pop_destination();
+
+    // A pointer is used as is, a value is copied, so the interface value points to its own copy
+    let data = match t.inner.get_type(target.int_size) {
+        Type::Pointer(_) => var_op(&inner),
+        typ => {
+            let data = stack_alloc(func, &typ, None);
+            func.add(store_instr(&data, &inner, &t.inner.span()));
+            Operand::AddressOf(data)
+        }
+    };
+
+    let methods = t.methods.iter().map(|m| Operand::Func(m.clone())).collect();
+    func.add(make_interface_instr(&dst, data, methods));
     dst
 }
 
+// Load the function from the vtable, and call it with the data pointer as self
+fn interface_call_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, sma: &MemberAccess, call: &Call, target: &Target) -> Option<Var>
+{
+    func.push_destination(None);
+    let mut obj = to_bc(bc_mod, func, &sma.left, target);
+    func.pop_destination();
+    if let Type::Pointer(inner) = obj.typ.clone() {
+        let iface = stack_alloc(func, &inner, None);
+        func.add(load_instr(&iface, &obj));
+        obj = iface;
+    }
+
+    let it = match obj.typ {
+        Type::Interface(ref it) => it.clone(),
+        _ => panic!("Internal Compiler Error: Member call on {}, which is not an interface", obj.typ),
+    };
+
+    let method_index = it.functions.iter()
+        .position(|f| f.name == call.callee.name)
+        .expect("Internal Compiler Error: Unknown interface function");
+    let method = &it.functions[method_index];
+    let arg_types = Some(ptr_type(Type::Void)).into_iter()
+        .chain(method.args.iter().skip(1).map(|a| a.typ.clone()))
+        .collect();
+    let method_var = stack_alloc(func, &func_type(arg_types, method.return_type.clone()), None);
+    func.add(load_interface_method_instr(&method_var, &obj, method_index));
+
+    let data = stack_alloc(func, &ptr_type(Type::Void), None);
+    func.add(get_prop_instr(&data, &obj, ByteCodeProperty::Data));
+
+    let mut method_call = call.clone();
+    method_call.callee.name = method_var.name.clone();
+    call_to_bc(bc_mod, func, &method_call, Some(data), target)
+}
+
 fn to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &Expression, target: &Target) -> Var
 {
     expr_to_bc(bc_mod, func, expr, target).expect("Expression must return a value")
@@ -695,10 +1885,11 @@ fn optional_compare_to_bc(
     equals: bool,
     inner_type: &Type)
 {
-    let set_to_true_bb = func.create_basic_block();
-    let set_to_false_bb = func.create_basic_block();
-    let compare_inner_bb = func.create_basic_block();
-    let end_bb = func.create_basic_block();
+    let set_to_true_bb = func.create_basic_block("optional_cmp_true");
+    let set_to_false_bb = func.create_basic_block("optional_cmp_false");
+    let compare_inner_bb = func.create_basic_block("optional_cmp_inner");
+    let compare_flags_bb = func.create_basic_block("optional_cmp_flags");
+    let end_bb = func.create_basic_block("optional_cmp_end");
 
     let l_is_ok = stack_alloc(func, &Type::Bool, None);
     let r_is_ok = stack_alloc(func, &Type::Bool, None);
@@ -706,8 +1897,13 @@ fn optional_compare_to_bc(
     func.add(load_optional_flag_instr(&l_is_ok, l));
     func.add(load_optional_flag_instr(&r_is_ok, r));
     func.add(binary_op_instr(&both_ok, BinaryOperator::And, var_op(&l_is_ok), var_op(&r_is_ok)));
-    func.add(branch_if_instr(&both_ok, compare_inner_bb, set_to_false_bb));
+    func.add(branch_if_instr(&both_ok, compare_inner_bb, compare_flags_bb));
 
+    // At least one of them is nil, so they are only equal if both are nil
+    func.set_current_bb(compare_flags_bb);
+    let same_flags = stack_alloc(func, &Type::Bool, None);
+    func.add(binary_op_instr(&same_flags, BinaryOperator::Equals, var_op(&l_is_ok), var_op(&r_is_ok)));
+    func.add(branch_if_instr(&same_flags, set_to_true_bb, set_to_false_bb));
 
     func.set_current_bb(compare_inner_bb);
     let l_inner = stack_alloc(func, inner_type, None);
@@ -719,16 +1915,69 @@ fn optional_compare_to_bc(
     func.add(branch_if_instr(&cmp, set_to_true_bb, set_to_false_bb));
 
     func.set_current_bb(set_to_true_bb);
-    func.add(store_operand_instr(dst, Operand::const_bool(equals)));
+    func.add(store_operand_instr(dst, Operand::const_bool(equals), &Span::default()));
     func.add(Instruction::Branch(end_bb));
 
     func.set_current_bb(set_to_false_bb);
-    func.add(store_operand_instr(dst, Operand::const_bool(!equals)));
+    func.add(store_operand_instr(dst, Operand::const_bool(!equals), &Span::default()));
     func.add(Instruction::Branch(end_bb));
 
     func.set_current_bb(end_bb);
 }
 
+fn equals_to_bc(func: &mut ByteCodeFunction, l: &Var, r: &Var, dst: &Var, typ: &Type, target: &Target)
+{
+    match *typ
+    {
+        Type::Optional(ref inner) => optional_compare_to_bc(func, l, r, dst, true, inner),
+        Type::Struct(ref st) if st.name.is_empty() => tuple_compare_to_bc(func, l, r, dst, true, st, target),
+        _ => func.add(binary_op_instr(dst, BinaryOperator::Equals, var_op(l), var_op(r))),
+    }
+}
+
+// Tuples are equal when all their members are equal
+fn tuple_compare_to_bc(
+    func: &mut ByteCodeFunction,
+    l: &Var,
+    r: &Var,
+    dst: &Var,
+    equals: bool,
+    st: &StructType,
+    target: &Target)
+{
+    let mut result: Option<Var> = None;
+    for (idx, member) in st.members.iter().enumerate()
+    {
+        let l_member = stack_alloc(func, &member.typ, None);
+        let r_member = stack_alloc(func, &member.typ, None);
+        if member.typ.pass_by_value() {
+            func.add(load_member_instr(&l_member, l, idx, target.int_size));
+            func.add(load_member_instr(&r_member, r, idx, target.int_size));
+        } else {
+            func.add(address_of_member_instr(&l_member, l, idx, target.int_size));
+            func.add(address_of_member_instr(&r_member, r, idx, target.int_size));
+        }
+
+        let cmp = stack_alloc(func, &Type::Bool, None);
+        equals_to_bc(func, &l_member, &r_member, &cmp, &member.typ, target);
+        result = Some(match result {
+            Some(prev) => {
+                let both = stack_alloc(func, &Type::Bool, None);
+                func.add(binary_op_instr(&both, BinaryOperator::And, var_op(&prev), var_op(&cmp)));
+                both
+            },
+            None => cmp,
+        });
+    }
+
+    match result
+    {
+        Some(ref result) if equals => func.add(store_instr(dst, result, &Span::default())),
+        Some(ref result) => func.add(unary_op_instr(dst, UnaryOperator::Not, var_op(result))),
+        None => func.add(store_operand_instr(dst, Operand::const_bool(equals), &Span::default())),
+    }
+}
+
 fn binary_op_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, op: &BinaryOp, target: &Target) -> Var
 {
     func.push_destination(None);
@@ -750,14 +1999,14 @@ fn binary_op_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, op:
 
             BinaryOperator::Or => {
                 let l_is_valid = stack_alloc(func, &Type::Bool, None);
-                let store_r_bb = func.create_basic_block();
-                let store_l_bb = func.create_basic_block();
-                let end_bb = func.create_basic_block();
+                let store_r_bb = func.create_basic_block("optional_or_right");
+                let store_l_bb = func.create_basic_block("optional_or_left");
+                let end_bb = func.create_basic_block("optional_or_end");
                 func.add(load_optional_flag_instr(&l_is_valid, &l));
                 func.add(branch_if_instr(&l_is_valid, store_l_bb, store_r_bb));
 
                 func.set_current_bb(store_r_bb);
-                func.add(store_instr(&dst, &r));
+                func.add(store_instr(&dst, &r, &op.span));
                 func.add(Instruction::Branch(end_bb));
 
                 func.set_current_bb(store_l_bb);
@@ -771,6 +2020,22 @@ fn binary_op_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, op:
             }
         },
 
+        Type::Struct(ref st) if st.name.is_empty() => {
+            tuple_compare_to_bc(func, &l, &r, &dst, op.operator == BinaryOperator::Equals, st, target);
+        },
+
+        Type::Int(_) | Type::UInt(_) if bc_mod.overflow_mode == OverflowMode::Check => match op.operator {
+            BinaryOperator::Add |
+            BinaryOperator::Sub |
+            BinaryOperator::Mul => {
+                func.add(checked_binary_op_instr(&dst, op.operator, var_op(&l), var_op(&r), &op.span));
+            },
+
+            _ => {
+                func.add(binary_op_instr(&dst, op.operator, var_op(&l), var_op(&r)));
+            }
+        },
+
         _ => {
             func.add(binary_op_instr(&dst, op.operator, var_op(&l), var_op(&r)));
         }
@@ -782,17 +2047,25 @@ fn binary_op_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, op:
 fn if_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, if_expr: &IfExpression, target: &Target) -> Var
 {
     let dst = get_dst(func, &if_expr.typ);
-    let true_bb = func.create_basic_block();
-    let end_bb = func.create_basic_block();
 
+    // The condition can create basic blocks of its own, they have to come before the end block,
+    // which may close the scope the if is in
     func.push_destination(None);
     let cond = to_bc(bc_mod, func, &if_expr.condition, target);
     func.pop_destination();
 
+    let true_bb = func.create_basic_block("if_true");
+    let end_bb = func.create_basic_block("if_end");
+
     func.push_destination(Some(dst.clone()));
 
     if let Some(ref on_false) = if_expr.on_false {
-        let false_bb = func.create_basic_block();
+        // Both branches write dst, so it needs to live in memory
+        if if_expr.typ != Type::Void && if_expr.typ.pass_by_value() {
+            func.add(Instruction::StackAlloc(dst.clone()));
+        }
+
+        let false_bb = func.create_basic_block("if_false");
         func.add(branch_if_instr(&cond, true_bb, false_bb));
         func.set_current_bb(false_bb);
         expr_to_bc(bc_mod, func, on_false, target);
@@ -810,6 +2083,71 @@ fn if_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, if_expr: &
     dst
 }
 
+fn if_let_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, if_let: &IfLetExpression, target: &Target) -> Var
+{
+    let dst = get_dst(func, &if_let.typ);
+    let end_bb = func.create_basic_block("if_end");
+    let false_bb = if if_let.on_false.is_some() {
+        // Both branches write dst, so it needs to live in memory
+        if if_let.typ != Type::Void && if_let.typ.pass_by_value() {
+            func.add(Instruction::StackAlloc(dst.clone()));
+        }
+        func.create_basic_block("if_false")
+    } else {
+        end_bb
+    };
+
+    // Checks after the first one are inside the scope, which has to be ended before going to the else part
+    let unbound_bb = if if_let.bindings.len() > 1 {
+        let current_bb = func.current_bb();
+        let unbound_bb = func.create_basic_block("if_let_unbound");
+        func.set_current_bb(unbound_bb);
+        func.add(Instruction::EndScope);
+        func.add(Instruction::Branch(false_bb));
+        func.set_current_bb(current_bb);
+        unbound_bb
+    } else {
+        false_bb
+    };
+
+    for (idx, b) in if_let.bindings.iter().enumerate()
+    {
+        func.push_destination(None);
+        let optional = to_bc(bc_mod, func, &b.init, target);
+        let cond = stack_alloc(func, &Type::Bool, None);
+        func.add(load_optional_flag_instr(&cond, &optional));
+        func.pop_destination();
+
+        let bind_bb = func.create_basic_block("if_let");
+        func.add(branch_if_instr(&cond, bind_bb, if idx == 0 {false_bb} else {unbound_bb}));
+        func.set_current_bb(bind_bb);
+        if idx == 0 {
+            // The scope starts after the first check, so the else part is outside of it
+            func.push_scope();
+        }
+
+        if b.name != "_" {
+            let binding = stack_alloc(func, &b.inner_type, Some(&b.name));
+            func.add(load_instr(&binding, &optional));
+        }
+    }
+
+    func.push_destination(Some(dst.clone()));
+    expr_to_bc(bc_mod, func, &if_let.on_true, target);
+    func.pop_scope();
+    func.add(Instruction::Branch(end_bb));
+
+    if let Some(ref on_false) = if_let.on_false {
+        func.set_current_bb(false_bb);
+        expr_to_bc(bc_mod, func, on_false, target);
+        func.add(Instruction::Branch(end_bb));
+    }
+
+    func.pop_destination();
+    func.set_current_bb(end_bb);
+    dst
+}
+
 fn assign_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, assign: &Assign, target: &Target)
 {
     // During type checking, other assigns, will be converted in a regular assign
@@ -819,8 +2157,8 @@ fn assign_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, assign
     let r = to_bc(bc_mod, func, &assign.right, target);
     match assign.left {
         AssignTarget::Var(ref nr) => {
-            let var = Var::named(&nr.name, nr.typ.clone());
-            func.add(store_instr(&var, &r));
+            let var = func.named_var(&nr.name, &nr.typ);
+            func.add(store_instr(&var, &r, &assign.span));
         },
 
         AssignTarget::MemberAccess(ref ma) => {
@@ -829,13 +2167,13 @@ fn assign_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, assign
 
         AssignTarget::Dereference(ref d) => {
             let var = to_bc(bc_mod, func, &d.inner, target);
-            func.add(store_instr(&var, &r));
+            func.add(store_instr(&var, &r, &assign.span));
         },
 
         AssignTarget::IndexOperation(ref iop) => {
             let tgt = to_bc(bc_mod, func, &iop.target, target);
             let idx = to_bc(bc_mod, func, &iop.index_expr, target);
-            func.add(store_member_with_var_instr(tgt, idx, r));
+            func.add(store_member_with_var_instr(tgt, idx, r, &assign.span));
         }
     }
 
@@ -844,9 +2182,12 @@ fn assign_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, assign
 
 fn dereference_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, d: &DereferenceExpression, target: &Target) -> Var
 {
+    // The pointer has another type than the destination
+    func.push_destination(None);
     let inner_var = to_bc(bc_mod, func, &d.inner, target);
+    func.pop_destination();
     let dst = get_dst(func, &d.typ);
-    func.add(store_operand_instr(&dst, Operand::Dereference(inner_var)));
+    func.add(store_operand_instr(&dst, Operand::Dereference(inner_var), &d.span));
     dst
 }
 
@@ -871,37 +2212,43 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
 
         Expression::Literal(Literal::Int(_, v, int_size)) => {
             let dst = get_dst(func, &Type::Int(int_size));
-            func.add(store_operand_instr(&dst, Operand::const_int(v, int_size)));
+            func.add(store_operand_instr(&dst, Operand::const_int(v, int_size), &expr.span()));
             Some(dst)
         },
 
         Expression::Literal(Literal::UInt(_, v, int_size)) => {
             let dst = get_dst(func, &Type::UInt(int_size));
-            func.add(store_operand_instr(&dst, Operand::const_uint(v, int_size)));
+            func.add(store_operand_instr(&dst, Operand::const_uint(v, int_size), &expr.span()));
             Some(dst)
         },
 
         Expression::Literal(Literal::Float(_, ref v_str, float_size)) => {
             let dst = get_dst(func, &Type::Float(float_size));
-            func.add(store_operand_instr(&dst, float_op(v_str, float_size)));
+            func.add(store_operand_instr(&dst, float_op(v_str, float_size), &expr.span()));
             Some(dst)
         },
 
         Expression::Literal(Literal::String(_, ref s))  => {
             let dst = get_dst(func, &string_type());
-            func.add(store_operand_instr(&dst, Operand::const_string(&s[..])));
+            func.add(store_operand_instr(&dst, Operand::const_string(&s[..]), &expr.span()));
+            Some(dst)
+        },
+
+        Expression::Literal(Literal::Bytes(_, ref b)) => {
+            let dst = get_dst(func, &slice_type(Type::UInt(IntSize::I8)));
+            func.add(store_operand_instr(&dst, Operand::Const(Constant::Bytes(b.clone())), &expr.span()));
             Some(dst)
         },
 
         Expression::Literal(Literal::Bool(_, v)) => {
             let dst = get_dst(func, &Type::Bool);
-            func.add(store_operand_instr(&dst, Operand::const_bool(v)));
+            func.add(store_operand_instr(&dst, Operand::const_bool(v), &expr.span()));
             Some(dst)
         },
 
         Expression::Literal(Literal::Char(_, v)) => {
             let dst = get_dst(func, &Type::Char);
-            func.add(store_operand_instr(&dst, Operand::const_char(v)));
+            func.add(store_operand_instr(&dst, Operand::const_char(v), &expr.span()));
             Some(dst)
         },
 
@@ -915,7 +2262,7 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
 
         Expression::Literal(Literal::NullPtr(_, ref inner_type)) => {
             let dst = get_dst(func, &ptr_type(inner_type.clone()));
-            func.add(store_operand_instr(&dst, Operand::Const(Constant::NullPtr(inner_type.clone()))));
+            func.add(store_operand_instr(&dst, Operand::Const(Constant::NullPtr(inner_type.clone())), &expr.span()));
             Some(dst)
         }
 
@@ -958,9 +2305,9 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
         },
 
         Expression::Lambda(ref l) => {
-            let lambda = func_to_bc(&l.sig, bc_mod, &l.expr, target);
+            let lambda = func_to_bc(&l.sig, l.name.as_ref().map(|n| &n[..]), bc_mod, &l.expr, target);
             let dst = get_dst(func, &l.sig.get_type());
-            func.add(store_func_instr(&dst, &lambda.sig.name));
+            func.add(store_func_instr(&dst, &lambda.sig.name, &l.span));
             bc_mod.functions.insert(l.sig.name.clone(), lambda);
             Some(dst)
         },
@@ -970,6 +2317,11 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
         },
 
         Expression::MemberAccess(ref sma) => {
+            // Only calls on interface values are still member calls at this point
+            if let MemberAccessType::Call(ref call) = sma.right {
+                return interface_call_to_bc(bc_mod, func, sma, call, target);
+            }
+
             let dst = if sma.typ.pass_by_value() {
                 get_dst(func, &sma.typ)
             } else {
@@ -984,13 +2336,23 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
             match_to_bc(bc_mod, func, m, target)
         },
 
+        Expression::Is(ref i) => {
+            Some(is_to_bc(bc_mod, func, i, target))
+        },
+
         Expression::If(ref i) => {
             Some(if_to_bc(bc_mod, func, i, target))
         },
 
+        Expression::IfLet(ref i) => {
+            Some(if_let_to_bc(bc_mod, func, i, target))
+        },
+
         Expression::ArrayToSlice(ref ats) => {
             let dst = get_dst(func, &ats.slice_type);
+            func.push_destination(None);
             let array_var = to_bc(bc_mod, func, &ats.inner, target);
+            func.pop_destination();
             let end = stack_alloc(func, &target.native_uint_type, None);
             func.add(get_prop_instr(&end, &array_var, ByteCodeProperty::Len));
             func.add(slice_instr(&dst, &array_var, Operand::const_uint(0, target.int_size), var_op(&end)));
@@ -1043,17 +2405,33 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
             func.push_destination(None);
             let inner = to_bc(bc_mod, func, &t.inner, target);
             func.pop_destination();
-            func.add(store_instr(&dst, &inner));
+            func.add(store_instr(&dst, &inner, &t.inner.span()));
             Some(dst)
         },
 
+        Expression::ToInterface(ref t) => {
+            Some(to_interface_to_bc(bc_mod, func, t, target))
+        },
+
         Expression::Cast(ref c) => {
             Some(cast_to_bc(bc_mod, func, c, target))
         },
 
+        Expression::TypeAscription(ref t) => {
+            expr_to_bc(bc_mod, func, &t.inner, target)
+        },
+
         Expression::CompilerCall(CompilerCall::SizeOf(ref typ, _)) => {
             let dst = get_dst(func, &target.native_uint_type);
-            func.add(store_operand_instr(&dst, Operand::SizeOf(typ.clone())));
+            let size = layout_of(typ, target).size;
+            func.add(store_operand_instr(&dst, Operand::const_uint(size as u64, target.int_size), &expr.span()));
+            Some(dst)
+        }
+
+        Expression::CompilerCall(CompilerCall::AlignOf(ref typ, _)) => {
+            let dst = get_dst(func, &target.native_uint_type);
+            let alignment = layout_of(typ, target).alignment;
+            func.add(store_operand_instr(&dst, Operand::const_uint(alignment as u64, target.int_size), &expr.span()));
             Some(dst)
         }
 
@@ -1067,17 +2445,36 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
             Some(dst)
         }
 
+        Expression::CompilerCall(CompilerCall::TypeName(..)) |
+        Expression::CompilerCall(CompilerCall::StaticAssert{..}) |
+        Expression::CompilerCall(CompilerCall::AssertType{..}) => {
+            panic!("Internal Compiler Error: @type_name, @static_assert and @assert_type should have been folded by the type checker")
+        }
+
         Expression::IndexOperation(ref iop) => {
+            let dst = get_dst(func, &iop.typ);
+            func.push_destination(None);
             let tgt = to_bc(bc_mod, func, &iop.target, target);
             let idx = to_bc(bc_mod, func, &iop.index_expr, target);
-            let dst = get_dst(func, &iop.typ);
+            func.pop_destination();
             func.add(load_member_instr_with_var(&dst, &tgt, &idx));
             Some(dst)
         }
 
         Expression::Return(ref r) => {
             func.push_destination(None);
-            if let Some(var) = expr_to_bc(bc_mod, func, &r.expression, target) {
+            let value = expr_to_bc(bc_mod, func, &r.expression, target).map(|var| {
+                if func.scope_depth() == 1 {
+                    return var;
+                }
+
+                // The value must outlive the scopes, which end before the return
+                let ret = func.new_function_scope_var(var.typ.clone());
+                func.add(store_instr(&ret, &var, &r.span));
+                ret
+            });
+            func.end_scopes_to(0);
+            if let Some(var) = value {
                 func.add(Instruction::Return(Operand::Var(var)));
             } else {
                 func.add(Instruction::ReturnVoid)
@@ -1085,12 +2482,71 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
             func.pop_destination();
             None
         }
+
+        Expression::Propagate(ref p) => {
+            Some(propagate_to_bc(bc_mod, func, p, target))
+        }
+
+        Expression::Discard(ref d) => {
+            func.push_destination(None);
+            expr_to_bc(bc_mod, func, &d.inner, target);
+            func.pop_destination();
+            None
+        }
     }
 }
 
-fn func_to_bc(sig: &FunctionSignature, bc_mod: &mut ByteCodeModule, expression: &Expression, target: &Target) -> ByteCodeFunction
+// Returns the Err case from the function, or continues with the value of the Ok case
+fn propagate_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, p: &Propagate, target: &Target) -> Var
+{
+    let ((ok_idx, _), (err_idx, err_type)) = result_cases(&p.inner.get_type(target.int_size)).expect("Internal Compiler Error: ? operator on a non Result type");
+    let ((_, _), (ret_err_idx, _)) = result_cases(&p.return_type).expect("Internal Compiler Error: ? operator in a function which does not return a Result");
+    let case_type = |typ: &Type, idx: usize| match *typ {
+        Type::Sum(ref st) => st.cases[idx].typ.clone(),
+        _ => panic!("Internal Compiler Error: Result is not a sum type"),
+    };
+
+    func.push_destination(None);
+    let result = to_bc(bc_mod, func, &p.inner, target);
+    let sum_type_index = stack_alloc(func, &target.native_uint_type, None);
+    func.add(get_prop_instr(&sum_type_index, &result, ByteCodeProperty::SumTypeIndex));
+    let is_err = stack_alloc(func, &Type::Bool, None);
+    func.add(binary_op_instr(&is_err, BinaryOperator::Equals, var_op(&sum_type_index), Operand::const_uint(err_idx as u64, target.int_size)));
+    func.pop_destination();
+
+    let err_bb = func.create_basic_block("propagate_err");
+    let ok_bb = func.create_basic_block("propagate_ok");
+    func.add(branch_if_instr(&is_err, err_bb, ok_bb));
+
+    func.set_current_bb(err_bb);
+    let err_ptr = stack_alloc(func, &ptr_type(case_type(&result.typ, err_idx)), None);
+    func.add(address_of_member_instr(&err_ptr, &result, err_idx, target.int_size));
+    let err = stack_alloc(func, &err_type, None);
+    func.add(load_member_instr(&err, &err_ptr, 0, target.int_size));
+
+    let ret = func.new_function_scope_var(p.return_type.clone());
+    func.add(set_prop_instr(&ret, ByteCodeProperty::SumTypeIndex, ret_err_idx));
+    let ret_err_ptr = stack_alloc(func, &ptr_type(case_type(&p.return_type, ret_err_idx)), None);
+    func.add(load_member_instr(&ret_err_ptr, &ret, ret_err_idx, target.int_size));
+    func.add(store_member_instr(&ret_err_ptr, 0, err, target.int_size, &p.span));
+    func.end_scopes_to(0);
+    func.add(ret_instr(&ret));
+
+    func.set_current_bb(ok_bb);
+    let ok_ptr = stack_alloc(func, &ptr_type(case_type(&result.typ, ok_idx)), None);
+    func.add(address_of_member_instr(&ok_ptr, &result, ok_idx, target.int_size));
+    let dst = get_dst(func, &p.typ);
+    func.add(load_member_instr(&dst, &ok_ptr, 0, target.int_size));
+    dst
+}
+
+// self_name is the name by which a local function calls itself
+fn func_to_bc(sig: &FunctionSignature, self_name: Option<&str>, bc_mod: &mut ByteCodeModule, expression: &Expression, target: &Target) -> ByteCodeFunction
 {
     let mut llfunc = ByteCodeFunction::new(sig, false);
+    if let Some(name) = self_name {
+        llfunc.add(store_func_instr(&Var::named(name, sig.typ.clone()), &sig.name, &sig.span));
+    }
     match expr_to_bc(bc_mod, &mut llfunc, expression, target)
     {
         Some(ref var) if var.typ != Type::Void => {
@@ -1099,6 +2555,11 @@ fn func_to_bc(sig: &FunctionSignature, bc_mod: &mut ByteCodeModule, expression:
             llfunc.add(ret_instr(var));
         },
 
+        _ if expression.diverges() => {
+            llfunc.pop_scope();
+            llfunc.remove_unreachable_blocks();
+        },
+
         _ => {
             llfunc.pop_scope();
             llfunc.add(Instruction::ReturnVoid);
@@ -1108,49 +2569,152 @@ fn func_to_bc(sig: &FunctionSignature, bc_mod: &mut ByteCodeModule, expression:
     llfunc
 }
 
-pub fn compile_to_byte_code(pkg: &Package, target: &Target) -> CompileResult<ByteCodeModule>
+fn module_init_to_bc(bc_mod: &mut ByteCodeModule, module_name: &str, globals: &[&GlobalBinding], target: &Target) -> ByteCodeFunction
+{
+    let init_sig = sig(&format!("{}::__module_init", module_name), Type::Void, Vec::new(), Span::default());
+    let mut init_func = ByteCodeFunction::new(&init_sig, false);
+    for global in globals {
+        init_func.push_destination(None);
+        let value = to_bc(bc_mod, &mut init_func, &global.init, target);
+        init_func.add(store_instr(&Var::named(&global.name, global.typ.clone()), &value, &global.span));
+        init_func.pop_destination();
+    }
+
+    init_func.pop_scope();
+    init_func.add(Instruction::ReturnVoid);
+    init_func
+}
+
+// Globals which are not initialized with a constant, are initialized at startup.
+// Every module gets an init function, which main calls before doing anything else.
+fn add_module_init_functions(bc_mod: &mut ByteCodeModule, pkg: &Package, target: &Target) -> CompileResult<()>
+{
+    let mut init_calls = Vec::new();
+    for (module_name, globals) in global_init_order(pkg)? {
+        let globals: Vec<&GlobalBinding> = globals.into_iter()
+            .filter(|g| bc_mod.globals[&g.name].init.is_none())
+            .collect();
+        if globals.is_empty() {
+            continue;
+        }
+
+        if !bc_mod.functions.contains_key(&bc_mod.main_function_name()) {
+            return type_error_result(&globals[0].span,
+                format!("Global {} must be initialized with a constant expression, there is no main function to initialize it at startup", globals[0].name));
+        }
+
+        let init_func = module_init_to_bc(bc_mod, module_name, &globals, target);
+        init_calls.push(void_call_instr(&init_func.sig.name, Vec::new(), &Span::default()));
+        bc_mod.functions.insert(init_func.sig.name.clone(), init_func);
+    }
+
+    let main_name = bc_mod.main_function_name();
+    if let Some(main) = bc_mod.functions.get_mut(&main_name) {
+        let entry = main.blocks.get_mut(&0).expect("main has no entry block");
+        for (idx, call) in init_calls.into_iter().enumerate() {
+            entry.instructions.insert(idx, call);
+        }
+    }
+
+    Ok(())
+}
+
+// The entry point always returns an exit code, a main which returns void exits with 0
+fn return_exit_code_from_main(bc_mod: &mut ByteCodeModule, target: &Target)
+{
+    let main_name = bc_mod.main_function_name();
+    if let Some(main) = bc_mod.functions.get_mut(&main_name).filter(|main| main.sig.return_type == Type::Void) {
+        main.sig.return_type = target.native_int_type.clone();
+        main.sig.typ = func_type(Vec::new(), target.native_int_type.clone());
+        let exit_code = Operand::const_int(0, target.int_size);
+        for instr in main.blocks.values_mut().flat_map(|bb| bb.instructions.iter_mut()) {
+            if *instr == Instruction::ReturnVoid {
+                *instr = Instruction::Return(exit_code.clone());
+            }
+        }
+    }
+}
+
+pub fn compile_to_byte_code(pkg: &Package, cached_modules: Vec<CompiledModule>, target: &Target, overflow_mode: OverflowMode) -> CompileResult<ByteCodeModule>
 {
     let mut ll_mod = ByteCodeModule{
         name: pkg.name.clone(),
         functions: HashMap::new(),
         globals: HashMap::new(),
         imported_functions: Vec::new(),
+        module_functions: HashMap::new(),
+        link_libraries: HashMap::new(),
+        overflow_mode,
+        build_info: None,
     };
 
+    // Modules loaded from the build cache are already compiled
+    for cm in cached_modules {
+        ll_mod.link_libraries.insert(cm.name, cm.link_libraries);
+        ll_mod.globals.extend(cm.globals);
+        for func in cm.functions {
+            ll_mod.functions.insert(func.sig.name.clone(), func);
+        }
+    }
 
     for md in pkg.modules.values() {
+        let functions_before: HashSet<String> = ll_mod.functions.keys().cloned().collect();
+        ll_mod.link_libraries.insert(md.name.clone(), md.link_libraries());
         for func in md.externals.values() {
             ll_mod.functions.insert(func.sig.name.clone(), ByteCodeFunction::new(&func.sig, true));
         }
 
         for global in md.globals.values() {
-            if let Some(cst) = expr_to_const(&global.init) {
-                ll_mod.globals.insert(global.name.clone(), cst);
-            } else {
-                return type_error_result(&global.span, format!("Global {} must be initialized with a constant expression", global.name));
-            }
+            // Constants, and arrays and structs of them, are initialized statically, everything else is done at startup
+            let init = match expr_to_const(&global.init) {
+                Some(c) if global.typ.pass_by_value() || c.get_type() == global.typ => Some(c),
+                _ => None,
+            };
+            ll_mod.globals.insert(global.name.clone(), ByteCodeGlobal{
+                typ: global.typ.clone(),
+                mutable: global.mutable,
+                init: init,
+            });
         }
 
         for func in md.functions.values() {
             if !func.is_generic() {
-                let new_func = func_to_bc(&func.sig, &mut ll_mod, &func.expression, target);
+                let new_func = func_to_bc(&func.sig, None, &mut ll_mod, &func.expression, target);
                 ll_mod.functions.insert(func.sig.name.clone(), new_func);
             }
         }
+
+        // Lambdas are compiled to functions too, so take everything which was added
+        let mut module_functions: Vec<String> = ll_mod.functions.keys()
+            .filter(|name| !functions_before.contains(*name))
+            .cloned()
+            .collect();
+        module_functions.sort();
+        merge_identical_lambdas(&mut ll_mod, &mut module_functions);
+        ll_mod.module_functions.insert(md.name.clone(), module_functions);
     }
 
+    return_exit_code_from_main(&mut ll_mod, target);
+    add_module_init_functions(&mut ll_mod, pkg, target)?;
 
     for import in pkg.import_data.imports.values() {
         for symbol in import.symbols.values() {
-            if let Some(s) = FunctionSignature::from_type(&symbol.name, &symbol.typ) {
+            if let Some(mut s) = FunctionSignature::from_type(&symbol.name, &symbol.typ) {
                 if ll_mod.functions.contains_key(&symbol.name) || symbol.typ.is_generic() {
                     continue;
                 }
+                s.export = symbol.export.clone();
                 ll_mod.imported_functions.push(ByteCodeFunction::new(&s, true));
             }
         }
     }
 
+    // Catch lowering bugs here, instead of as strange errors in the LLVM backend
+    if cfg!(debug_assertions) {
+        if let Err(errors) = verify_module(&ll_mod) {
+            panic!("Internal Compiler Error: invalid bytecode generated:\n{}", errors.join("\n"));
+        }
+    }
 
     Ok(ll_mod)
 }
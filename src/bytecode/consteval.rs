@@ -1,4 +1,4 @@
-use ast::{Expression, Literal, UnaryOperator, UnaryOp, BinaryOperator, BinaryOp, Block, IntSize};
+use ast::{Expression, Literal, UnaryOperator, UnaryOp, BinaryOperator, BinaryOp, Block, IntSize, StructInitializer, Type};
 use bytecode::Constant;
 
 fn lit_to_const(lit: &Literal) -> Option<Constant>
@@ -9,6 +9,7 @@ fn lit_to_const(lit: &Literal) -> Option<Constant>
         Literal::Bool(_, v) => Some(Constant::Bool(v)),
         Literal::Char(_, v) => Some(Constant::Char(v)),
         Literal::String(_, ref v) => Some(Constant::String(v.clone())),
+        Literal::Bytes(_, ref v) => Some(Constant::Bytes(v.clone())),
         Literal::NullPtr(_, ref inner_type) => Some(Constant::NullPtr(inner_type.clone())),
 
         Literal::Float(_, ref v, float_size) => {
@@ -18,6 +19,9 @@ fn lit_to_const(lit: &Literal) -> Option<Constant>
             }
         }
 
+        // The element type of an empty array is not known from its elements
+        Literal::Array(ref array_lit) if array_lit.elements.is_empty() => None,
+
         Literal::Array(ref array_lit) => {
             let mut elements = Vec::with_capacity(array_lit.elements.len());
             for e in &array_lit.elements {
@@ -55,6 +59,29 @@ fn unary_op_to_const(uop: &UnaryOp) -> Option<Constant>
     }
 }
 
+// Integer arithmetic whose result doesn't fit in its type is not folded, it is left to the runtime check
+fn int_to_const(v: Option<i64>, int_size: IntSize) -> Option<Constant>
+{
+    let v = try_opt!(v);
+    let bits = int_size.size_in_bits();
+    if bits == 64 || (v >= -(1 << (bits - 1)) && v < (1 << (bits - 1))) {
+        Some(Constant::Int(v, int_size))
+    } else {
+        None
+    }
+}
+
+fn uint_to_const(v: Option<u64>, int_size: IntSize) -> Option<Constant>
+{
+    let v = try_opt!(v);
+    let bits = int_size.size_in_bits();
+    if bits == 64 || v < (1 << bits) {
+        Some(Constant::UInt(v, int_size))
+    } else {
+        None
+    }
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(float_cmp))]
 fn binary_op_to_const(bop: &BinaryOp) -> Option<Constant>
 {
@@ -62,16 +89,16 @@ fn binary_op_to_const(bop: &BinaryOp) -> Option<Constant>
     let right = try_opt!(expr_to_const(&bop.right));
     
     match (bop.operator, left, right) {
-        (BinaryOperator::Add, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l + r, ls)),
-        (BinaryOperator::Add, Constant::UInt(l, ls), Constant::UInt(r, _)) => Some(Constant::UInt(l + r, ls)),
+        (BinaryOperator::Add, Constant::Int(l, ls), Constant::Int(r, _)) => int_to_const(l.checked_add(r), ls),
+        (BinaryOperator::Add, Constant::UInt(l, ls), Constant::UInt(r, _)) => uint_to_const(l.checked_add(r), ls),
         (BinaryOperator::Add, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l + r, ls)),
 
-        (BinaryOperator::Sub, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l - r, ls)),
-        (BinaryOperator::Sub, Constant::UInt(l, ls), Constant::UInt(r, _)) => Some(Constant::UInt(l - r, ls)),
+        (BinaryOperator::Sub, Constant::Int(l, ls), Constant::Int(r, _)) => int_to_const(l.checked_sub(r), ls),
+        (BinaryOperator::Sub, Constant::UInt(l, ls), Constant::UInt(r, _)) => uint_to_const(l.checked_sub(r), ls),
         (BinaryOperator::Sub, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l - r, ls)),
 
-        (BinaryOperator::Mul, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l * r, ls)),
-        (BinaryOperator::Mul, Constant::UInt(l, ls), Constant::UInt(r, _)) => Some(Constant::UInt(l * r, ls)),
+        (BinaryOperator::Mul, Constant::Int(l, ls), Constant::Int(r, _)) => int_to_const(l.checked_mul(r), ls),
+        (BinaryOperator::Mul, Constant::UInt(l, ls), Constant::UInt(r, _)) => uint_to_const(l.checked_mul(r), ls),
         (BinaryOperator::Mul, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l * r, ls)),
 
         (BinaryOperator::Div, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l / r, ls)),
@@ -107,6 +134,7 @@ fn binary_op_to_const(bop: &BinaryOp) -> Option<Constant>
         (BinaryOperator::Equals, Constant::Char(l), Constant::Char(r)) => Some(Constant::Bool(l == r)),
         (BinaryOperator::Equals, Constant::Bool(l), Constant::Bool(r)) => Some(Constant::Bool(l == r)),
         (BinaryOperator::Equals, Constant::String(ref l), Constant::String(ref r)) => Some(Constant::Bool(*l == *r)),
+        (BinaryOperator::Equals, Constant::Bytes(ref l), Constant::Bytes(ref r)) => Some(Constant::Bool(*l == *r)),
 
         (BinaryOperator::NotEquals, Constant::Int(l, _), Constant::Int(r, _)) => Some(Constant::Bool(l != r)),
         (BinaryOperator::NotEquals, Constant::UInt(l, _), Constant::UInt(r, _)) => Some(Constant::Bool(l != r)),
@@ -114,6 +142,7 @@ fn binary_op_to_const(bop: &BinaryOp) -> Option<Constant>
         (BinaryOperator::NotEquals, Constant::Char(l), Constant::Char(r)) => Some(Constant::Bool(l != r)),
         (BinaryOperator::NotEquals, Constant::Bool(l), Constant::Bool(r)) => Some(Constant::Bool(l != r)),
         (BinaryOperator::NotEquals, Constant::String(ref l), Constant::String(ref r)) => Some(Constant::Bool(*l != *r)),
+        (BinaryOperator::NotEquals, Constant::Bytes(ref l), Constant::Bytes(ref r)) => Some(Constant::Bool(*l != *r)),
 
         (BinaryOperator::And, Constant::Bool(l), Constant::Bool(r)) => Some(Constant::Bool(l && r)),
         (BinaryOperator::Or, Constant::Bool(l), Constant::Bool(r)) => Some(Constant::Bool(l || r)),
@@ -122,6 +151,20 @@ fn binary_op_to_const(bop: &BinaryOp) -> Option<Constant>
     }
 }
 
+// Sum type cases are initialized like structs, but only structs can be constants
+fn struct_initializer_to_const(si: &StructInitializer) -> Option<Constant>
+{
+    if let Type::Struct(_) = si.typ {
+        let mut members = Vec::with_capacity(si.member_initializers.len());
+        for e in &si.member_initializers {
+            members.push(try_opt!(expr_to_const(e)));
+        }
+        Some(Constant::Struct(si.typ.clone(), members))
+    } else {
+        None
+    }
+}
+
 fn block_to_const(block: &Block) -> Option<Constant>
 {
     let mut ret = Constant::Int(0, IntSize::I64);
@@ -151,7 +194,11 @@ pub fn expr_to_const(expr: &Expression) -> Option<Constant>
 
         Expression::Block(ref block) => {
             block_to_const(block)
-        }
+        },
+
+        Expression::StructInitializer(ref si) => {
+            struct_initializer_to_const(si)
+        },
 
         _ => None,
     }
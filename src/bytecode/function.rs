@@ -1,10 +1,10 @@
 use std::fmt;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use itertools::free::join;
 use ast::{Type, FunctionSignature};
 use bytecode::instruction::Instruction;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Var
 {
     pub name: String,
@@ -38,7 +38,7 @@ impl fmt::Display for Var
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Scope
 {
     named_vars: HashMap<String, Var>,
@@ -94,16 +94,17 @@ impl Scope
 
 pub type BasicBlockRef = usize;
 
-pub fn bb_name(bb: BasicBlockRef) -> String
+// The purpose says which construct created the block, for example match_case or while_body
+pub fn bb_name(bb: BasicBlockRef, purpose: &str) -> String
 {
     if bb == 0 {
         "entry".into()
     } else {
-        format!("block{}", bb)
+        format!("{}{}", purpose, bb)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BasicBlock
 {
     pub name: String,
@@ -122,8 +123,8 @@ impl BasicBlock
 
     pub fn add(&mut self, inst: Instruction)
     {
-        if inst.is_terminator() && self.instructions.last().map(|i| i.is_terminator()).unwrap_or(false) {
-            // Already a terminator drop this, this only happens with an early return
+        if self.instructions.last().map(|i| i.is_terminator()).unwrap_or(false) {
+            // Already a terminator, so this can never be executed, this only happens with an early return
             return;
         }
 
@@ -133,7 +134,8 @@ impl BasicBlock
 
 
 
-#[derive(Debug)]
+// Scopes and destinations are only used while generating the function, so they are not stored in the build cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ByteCodeFunction
 {
     pub sig: FunctionSignature,
@@ -142,7 +144,9 @@ pub struct ByteCodeFunction
     current_bb: usize,
     bb_counter: usize,
     var_counter: usize,
+    #[serde(skip)]
     scopes: Vec<Scope>,
+    #[serde(skip)]
     destinations: Vec<Option<Var>>,
 }
 
@@ -163,10 +167,10 @@ impl ByteCodeFunction
         };
 
         if !external {
-            let entry = f.create_basic_block();
+            let entry = f.create_basic_block("entry");
             f.set_current_bb(entry);
 
-            for arg in &sig.args {
+            for arg in sig.args.iter().filter(|arg| arg.name != "_") {
                 f.add_named_var(Var::named(&arg.name, arg.typ.clone()));
             }
         }
@@ -179,15 +183,27 @@ impl ByteCodeFunction
         self.blocks.get_mut(&idx).map(|bb| bb.add(inst));
     }
 
-    pub fn create_basic_block(&mut self) -> BasicBlockRef
+    pub fn create_basic_block(&mut self, purpose: &str) -> BasicBlockRef
     {
         let bb_ref = self.bb_counter;
         self.bb_counter += 1;
-        let name = bb_name(bb_ref);
+        let name = bb_name(bb_ref, purpose);
         self.blocks.insert(bb_ref, BasicBlock::new(name));
         bb_ref
     }
 
+    // For optimizations which restructure the function, block 0 must be the entry block
+    pub fn replace_blocks(&mut self, blocks: BTreeMap<BasicBlockRef, BasicBlock>)
+    {
+        self.bb_counter = blocks.keys().next_back().map(|bb_ref| bb_ref + 1).unwrap_or(0);
+        self.blocks = blocks;
+    }
+
+    pub fn current_bb(&self) -> BasicBlockRef
+    {
+        self.current_bb
+    }
+
     pub fn set_current_bb(&mut self, bb_ref: BasicBlockRef)
     {
         assert!(bb_ref < self.blocks.len());
@@ -203,6 +219,41 @@ impl ByteCodeFunction
         v
     }
 
+    // A variable declared at the start of the function's own scope, so it stays valid when the scopes
+    // around the current position end, for example to return a value from inside them
+    pub fn new_function_scope_var(&mut self, typ: Type) -> Var
+    {
+        let idx = self.var_counter;
+        self.var_counter += 1;
+        let v = Var::new(idx, typ);
+        let scope = self.scopes.first_mut().expect("Empty Scope Stack");
+        scope.add_named_var(v.clone());
+        let (insert_block, insert_position) = (scope.insert_block, scope.insert_position);
+        self.blocks.get_mut(&insert_block)
+            .expect("Unknown block")
+            .instructions
+            .insert(insert_position, Instruction::StackAlloc(v.clone()));
+        v
+    }
+
+    // A variable for a name, which gets a name of its own, so it can shadow variables with the same name
+    pub fn new_shadowing_var(&mut self, name: &str, typ: Type) -> Var
+    {
+        let idx = self.var_counter;
+        self.var_counter += 1;
+        let v = Var::named(&format!("{}${}", name, idx), typ);
+        let scope = self.scopes.last_mut().expect("Empty Scope Stack");
+        scope.named_vars.insert(name.into(), v.clone());
+        v
+    }
+
+    // The variable a name refers to, which is a shadowing variable if one is in scope
+    pub fn named_var(&self, name: &str, typ: &Type) -> Var
+    {
+        let shadowing = self.scopes.iter().rev().filter_map(|s| s.named_vars.get(name)).next();
+        Var::named(shadowing.map(|v| &v.name[..]).unwrap_or(name), typ.clone())
+    }
+
     pub fn push_scope(&mut self)
     {
         let idx = self.current_bb;
@@ -223,6 +274,26 @@ impl ByteCodeFunction
         }
     }
 
+    // Number of open scopes, including the function's own
+    pub fn scope_depth(&self) -> usize
+    {
+        self.scopes.len()
+    }
+
+    // Cleans up and ends the scopes above depth, innermost first, for code which leaves them early.
+    // They stay on the stack, because the code after the early exit is still in them.
+    // The function's own scope has no StartScope, so it only gets cleaned up.
+    pub fn end_scopes_to(&mut self, depth: usize)
+    {
+        for idx in (depth..self.scopes.len()).rev() {
+            let s = self.scopes[idx].clone();
+            s.cleanup(self);
+            if idx > 0 {
+                self.add(Instruction::EndScope);
+            }
+        }
+    }
+
     pub fn push_destination(&mut self, var: Option<Var>)
     {
         self.destinations.push(var);
@@ -247,6 +318,71 @@ impl ByteCodeFunction
         scope.add_named_var(var);
     }
 
+    // The blocks which can be reached from the entry block
+    pub fn reachable_blocks(&self) -> BTreeSet<BasicBlockRef>
+    {
+        let mut reachable = BTreeSet::new();
+        let mut work_list: VecDeque<BasicBlockRef> = self.blocks.keys().take(1).cloned().collect();
+        while let Some(bb_ref) = work_list.pop_front()
+        {
+            if !self.blocks.contains_key(&bb_ref) || !reachable.insert(bb_ref) {
+                continue;
+            }
+
+            for instr in &self.blocks[&bb_ref].instructions {
+                work_list.extend(instr.branch_targets());
+            }
+        }
+        reachable
+    }
+
+    // Every reachable block comes after a block branching to it, unreachable blocks are put at the end
+    pub fn blocks_in_reverse_postorder(&self) -> Vec<BasicBlockRef>
+    {
+        let successors = |bb_ref: BasicBlockRef| -> Vec<BasicBlockRef> {
+            let mut targets: Vec<BasicBlockRef> = self.blocks[&bb_ref].instructions.iter()
+                .flat_map(|instr| instr.branch_targets())
+                .filter(|target| self.blocks.contains_key(target))
+                .collect();
+            // Popped from the back, so the first target is visited first
+            targets.reverse();
+            targets
+        };
+
+        let mut visited = BTreeSet::new();
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+        let mut stack = Vec::new();
+        if let Some(&entry) = self.blocks.keys().next() {
+            visited.insert(entry);
+            stack.push((entry, successors(entry)));
+        }
+
+        while let Some((bb_ref, mut targets)) = stack.pop()
+        {
+            match targets.pop() {
+                Some(target) => {
+                    stack.push((bb_ref, targets));
+                    if visited.insert(target) {
+                        stack.push((target, successors(target)));
+                    }
+                },
+                None => postorder.push(bb_ref),
+            }
+        }
+
+        postorder.reverse();
+        postorder.extend(self.blocks.keys().filter(|bb_ref| !visited.contains(bb_ref)));
+        postorder
+    }
+
+    // When every path through a function returns early, the block it ends in is never reached,
+    // and it has no terminator or one which does not match the return type
+    pub fn remove_unreachable_blocks(&mut self)
+    {
+        let reachable = self.reachable_blocks();
+        self.blocks.retain(|bb_ref, _| reachable.contains(bb_ref));
+    }
+
     pub fn for_each_instruction<Func: FnMut(&Instruction) -> bool>(&self, mut f: Func)
     {
         for block in self.blocks.values() {
@@ -0,0 +1,348 @@
+// Golden tests compare the text of a ByteCodeModule or ByteCodeFunction with an expectation. Both are normalized
+// first, temporaries and basic blocks are renumbered per function in the order they appear, so a test doesn't
+// fail because an unrelated change shifted the numbering, and a failure shows a diff instead of the whole dump.
+use std::cmp;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Set it to rewrite the expectation files with the current output, instead of comparing with them
+pub const BLESS_VAR: &str = "MENHIR_BLESS";
+
+const DIFF_CONTEXT: usize = 3;
+
+fn is_function_header(line: &str) -> bool
+{
+    !line.starts_with(' ') && line.ends_with(':')
+}
+
+fn is_block_header(line: &str) -> bool
+{
+    line.starts_with(' ') && !line.starts_with("  ") && line.ends_with(':')
+}
+
+fn parse_number(s: &str) -> Option<usize>
+{
+    let digits: String = s.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+// Replaces the number after every occurrence of prefix, numbers get the next free one the first time they are seen
+fn renumber_after(line: &str, prefix: &str, numbers: &mut HashMap<usize, usize>) -> String
+{
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(idx) = rest.find(prefix) {
+        let after = &rest[idx + prefix.len()..];
+        result.push_str(&rest[..idx + prefix.len()]);
+        match parse_number(after) {
+            Some(n) => {
+                let next = numbers.len();
+                let canonical = *numbers.entry(n).or_insert(next);
+                result.push_str(&canonical.to_string());
+                rest = &after[n.to_string().len()..];
+            }
+            None => rest = after,
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Basic blocks are referred to by their number in br, brif and switch
+fn renumber_block_refs(line: &str, blocks: &HashMap<usize, usize>) -> String
+{
+    let block = |s: &str| parse_number(s).and_then(|n| blocks.get(&n)).map(|n| n.to_string());
+    let trimmed = line.trim_start();
+    if let Some(target) = trimmed.strip_prefix("br ") {
+        if let Some(n) = block(target) {
+            return format!("  br {}", n);
+        }
+    } else if trimmed.starts_with("brif ") {
+        if let Some(idx) = line.rfind(" ? ") {
+            let targets: Vec<Option<String>> = line[idx + 3..].split(" : ").map(|t| block(t.trim())).collect();
+            if let [Some(ref on_true), Some(ref on_false)] = targets[..] {
+                return format!("{} ? {} : {}", &line[..idx], on_true, on_false);
+            }
+        }
+    } else if trimmed.starts_with("switch ") {
+        let mut result = String::new();
+        let mut rest = line;
+        for separator in ["=> ", "default "].iter() {
+            while let Some(idx) = rest.find(separator) {
+                let after = &rest[idx + separator.len()..];
+                result.push_str(&rest[..idx + separator.len()]);
+                match parse_number(after) {
+                    Some(n) => {
+                        result.push_str(&block(after).unwrap_or_else(|| n.to_string()));
+                        rest = &after[n.to_string().len()..];
+                    }
+                    None => rest = after,
+                }
+            }
+        }
+        result.push_str(rest);
+        return result;
+    }
+    line.to_owned()
+}
+
+fn normalize_function(lines: &[&str], normalized: &mut Vec<String>)
+{
+    // Blocks are printed in the order of their numbers, but branches can refer to blocks further down
+    let mut blocks = HashMap::new();
+    for line in lines.iter().filter(|l| is_block_header(l)) {
+        let name = line.trim().trim_end_matches(':');
+        let number = if name == "entry" {Some(0)} else {parse_number(&name[name.trim_end_matches(|c: char| c.is_ascii_digit()).len()..])};
+        if let Some(n) = number {
+            let next = blocks.len();
+            blocks.entry(n).or_insert(next);
+        }
+    }
+
+    let mut temporaries = HashMap::new();
+    for line in lines {
+        let line = if is_block_header(line) {
+            let name = line.trim().trim_end_matches(':');
+            let purpose = name.trim_end_matches(|c: char| c.is_ascii_digit());
+            match parse_number(&name[purpose.len()..]).and_then(|n| blocks.get(&n)) {
+                Some(n) if name != "entry" => format!(" {}{}:", purpose, n),
+                _ => line.to_string(),
+            }
+        } else {
+            renumber_block_refs(line, &blocks)
+        };
+        normalized.push(renumber_after(&line, "$var", &mut temporaries));
+    }
+}
+
+// Renumbers the temporaries and basic blocks of every function in a dump, and drops trailing whitespace
+pub fn normalize(dump: &str) -> String
+{
+    let lines: Vec<&str> = dump.lines().map(|l| l.trim_end()).collect();
+    let mut normalized = Vec::new();
+    let mut idx = 0;
+    while idx < lines.len() {
+        if is_function_header(lines[idx]) {
+            let end = lines[idx + 1..].iter().position(|l| !l.starts_with(' ')).map_or(lines.len(), |p| idx + 1 + p);
+            normalized.push(lines[idx].to_owned());
+            normalize_function(&lines[idx + 1..end], &mut normalized);
+            idx = end;
+        } else {
+            normalized.push(lines[idx].to_owned());
+            idx += 1;
+        }
+    }
+
+    let mut result = normalized.join("\n");
+    result.push('\n');
+    result
+}
+
+#[derive(Debug, PartialEq)]
+enum Edit<'a>
+{
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+fn diff_lines<'a>(expected: &[&'a str], actual: &[&'a str]) -> Vec<Edit<'a>>
+{
+    // lcs[i][j] is the length of the longest common subsequence of expected[i..] and actual[j..]
+    let (n, m) = (expected.len(), actual.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {lcs[i + 1][j + 1] + 1} else {cmp::max(lcs[i + 1][j], lcs[i][j + 1])};
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            edits.push(Edit::Same(expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Edit::Removed(expected[i]));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(actual[j]));
+            j += 1;
+        }
+    }
+    edits
+}
+
+fn unified_diff(edits: &[Edit]) -> String
+{
+    let changed: Vec<usize> = edits.iter().enumerate()
+        .filter(|&(_, e)| !matches!(*e, Edit::Same(_)))
+        .map(|(idx, _)| idx)
+        .collect();
+    let old_lines = |edits: &[Edit]| edits.iter().filter(|e| !matches!(**e, Edit::Added(_))).count();
+    let new_lines = |edits: &[Edit]| edits.iter().filter(|e| !matches!(**e, Edit::Removed(_))).count();
+
+    let mut diff = String::from("--- expected\n+++ actual\n");
+    let mut idx = 0;
+    while idx < changed.len() {
+        let start = changed[idx].saturating_sub(DIFF_CONTEXT);
+        let mut end = changed[idx] + DIFF_CONTEXT + 1;
+        idx += 1;
+        // Changes close to each other share a hunk
+        while idx < changed.len() && changed[idx] <= end + DIFF_CONTEXT {
+            end = changed[idx] + DIFF_CONTEXT + 1;
+            idx += 1;
+        }
+
+        let end = cmp::min(end, edits.len());
+        let hunk = &edits[start..end];
+        diff.push_str(&format!("@@ -{},{} +{},{} @@\n",
+            old_lines(&edits[..start]) + 1, old_lines(hunk), new_lines(&edits[..start]) + 1, new_lines(hunk)));
+        for e in hunk {
+            match *e {
+                Edit::Same(line) => diff.push_str(&format!(" {}\n", line)),
+                Edit::Removed(line) => diff.push_str(&format!("-{}\n", line)),
+                Edit::Added(line) => diff.push_str(&format!("+{}\n", line)),
+            }
+        }
+    }
+    diff
+}
+
+// Which instruction differs first, and in which function and block it is
+fn first_difference(expected: &[&str], actual: &[&str]) -> String
+{
+    let mut function = "";
+    let mut block = "";
+    for (e, a) in expected.iter().zip(actual.iter()) {
+        if e != a {
+            return format!("first difference in function {}, block {}: expected `{}`, found `{}`", function, block, e.trim(), a.trim());
+        }
+
+        if is_function_header(e) {
+            function = e.split('(').next().unwrap_or(e);
+            block = "";
+        } else if is_block_header(e) {
+            block = e.trim().trim_end_matches(':');
+        }
+    }
+    format!("expected {} lines, found {}", expected.len(), actual.len())
+}
+
+// None when the normalized dumps are the same, otherwise where they differ and a diff
+pub fn ir_mismatch(actual: &str, expected: &str) -> Option<String>
+{
+    let actual = normalize(actual);
+    let expected = normalize(expected);
+    if actual == expected {
+        return None;
+    }
+
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    Some(format!("IR doesn't match the expectation, {}\n{}",
+        first_difference(&expected_lines, &actual_lines),
+        unified_diff(&diff_lines(&expected_lines, &actual_lines))))
+}
+
+pub fn assert_ir_matches(actual: &str, expected: &str)
+{
+    if let Some(msg) = ir_mismatch(actual, expected) {
+        panic!("{}", msg);
+    }
+}
+
+fn check_ir_file(actual: &str, path: &Path, bless: bool) -> Result<(), String>
+{
+    if bless {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+        }
+        return fs::write(path, normalize(actual)).map_err(|e| format!("Cannot write {}: {}", path.display(), e));
+    }
+
+    let expected = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read {}: {}, run the test with {}=1 to create it", path.display(), e, BLESS_VAR))?;
+    match ir_mismatch(actual, &expected) {
+        Some(msg) => Err(format!("{}: {}run the test with {}=1 if the change is intended", path.display(), msg, BLESS_VAR)),
+        None => Ok(()),
+    }
+}
+
+// Compares with an expectation file, which gets (re)written when the bless environment variable is set
+pub fn assert_ir_matches_file<P: AsRef<Path>>(actual: &str, path: P)
+{
+    if let Err(msg) = check_ir_file(actual, path.as_ref(), env::var_os(BLESS_VAR).is_some()) {
+        panic!("{}", msg);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::fs;
+    use testutil::TempDir;
+    use bytecode::test::generate_byte_code;
+    use super::{normalize, ir_mismatch, assert_ir_matches, check_ir_file};
+
+    const DUMP: &str = "test::f(a: int32) -> int32:
+ entry:
+  scope start
+  store ($var3: int32) (a: int32)
+  brif ($var4: bool) ? 2 : 5
+ if_true2:
+  store ($var7: int32) 1
+  br 5
+ if_end5:
+  ret ($var3: int32)
+";
+
+    #[test]
+    fn test_renumbering_is_ignored()
+    {
+        let renumbered = DUMP
+            .replace("$var3", "$var10").replace("$var4", "$var11").replace("$var7", "$var12")
+            .replace("if_true2", "if_true3").replace("? 2 : 5", "? 3 : 8").replace("br 5", "br 8").replace("if_end5", "if_end8");
+        assert_eq!(normalize(&renumbered), normalize(DUMP));
+        assert!(normalize(DUMP).contains("  brif ($var1: bool) ? 1 : 2\n if_true1:\n  store ($var2: int32) 1\n  br 2\n if_end2:"));
+        assert_ir_matches(&renumbered, DUMP);
+
+        // Temporaries are numbered per function, so they are the same in a function compiled twice
+        let src = "fn f(a: int) -> int: if a > 3: a * 2 else a\nfn main() -> int: f(4)";
+        let func = format!("{}", generate_byte_code(src, false).expect("Compilation failed").functions["test::f"]);
+        assert_ir_matches(&func.replace("$var", "$var1"), &func);
+    }
+
+    #[test]
+    fn test_mismatch_is_a_small_diff()
+    {
+        let changed = DUMP.replace("store ($var7: int32) 1", "store ($var7: int32) 2");
+        let msg = ir_mismatch(&changed, DUMP).expect("Expected a mismatch");
+        assert!(msg.starts_with("IR doesn't match the expectation, first difference in function test::f, block if_true1: \
+            expected `store ($var2: int32) 1`, found `store ($var2: int32) 2`\n"));
+        assert!(msg.contains("--- expected\n+++ actual\n@@ -4,7 +4,7 @@\n"));
+        assert!(msg.contains("\n-  store ($var2: int32) 1\n+  store ($var2: int32) 2\n"));
+        assert!(!msg.contains("scope start"));
+    }
+
+    #[test]
+    fn test_bless_writes_the_expectation()
+    {
+        let dir = TempDir::new("menhir-golden");
+        let path = dir.join("f.bc");
+        assert!(check_ir_file(DUMP, &path, false).unwrap_err().contains("MENHIR_BLESS=1 to create it"));
+
+        check_ir_file(DUMP, &path, true).expect("Bless failed");
+        assert_eq!(fs::read_to_string(&path).expect("Cannot read expectation"), normalize(DUMP));
+        assert!(check_ir_file(DUMP, &path, false).is_ok());
+
+        let changed = DUMP.replace("ret ($var3: int32)", "ret 0");
+        assert!(check_ir_file(&changed, &path, false).unwrap_err().contains("-  ret ($var0: int32)\n+  ret 0\n"));
+        check_ir_file(&changed, &path, true).expect("Bless failed");
+        assert!(check_ir_file(&changed, &path, false).is_ok());
+    }
+}
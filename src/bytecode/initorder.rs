@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+use ast::{Expression, Function, GlobalBinding, Module, Visitor, walk_expression};
+use compileerror::{CompileResult, type_error_result};
+use package::Package;
+
+// Collects the names of all functions and globals an expression refers to
+struct NameCollector
+{
+    names: Vec<String>,
+}
+
+impl Visitor for NameCollector
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
+        match *e
+        {
+            Expression::NameRef(ref nr) => self.names.push(nr.name.clone()),
+            Expression::Call(ref c) => self.names.push(c.callee.name.clone()),
+            _ => (),
+        }
+
+        walk_expression(self, e)
+    }
+}
+
+fn used_names(e: &Expression) -> Vec<String>
+{
+    let mut collector = NameCollector{names: Vec::new()};
+    let _ = collector.visit_expression(e);
+    collector.names
+}
+
+struct InitOrder<'a>
+{
+    globals: HashMap<&'a str, &'a GlobalBinding>,
+    functions: HashMap<&'a str, &'a Function>,
+    done: HashSet<&'a str>,
+    order: Vec<(&'a str, Vec<&'a GlobalBinding>)>,
+}
+
+impl<'a> InitOrder<'a>
+{
+    // The globals used by the initializer of a global, directly or through the functions it calls
+    fn dependencies(&self, global: &GlobalBinding) -> Vec<&'a GlobalBinding>
+    {
+        let mut deps: Vec<&'a GlobalBinding> = Vec::new();
+        let mut visited_functions = HashSet::new();
+        let mut todo = used_names(&global.init);
+        while let Some(name) = todo.pop() {
+            if let Some(g) = self.globals.get(name.as_str()) {
+                if !deps.iter().any(|d| d.name == g.name) {
+                    deps.push(g);
+                }
+            } else if let Some(f) = self.functions.get(name.as_str()) {
+                if visited_functions.insert(name) {
+                    todo.extend(used_names(&f.expression));
+                }
+            }
+        }
+
+        deps.sort_by(|a, b| a.name.cmp(&b.name));
+        deps
+    }
+
+    fn visit(&mut self, global: &'a GlobalBinding, path: &mut Vec<&'a str>) -> CompileResult<()>
+    {
+        if self.done.contains(global.name.as_str()) {
+            return Ok(());
+        }
+
+        if let Some(idx) = path.iter().position(|n| *n == global.name) {
+            let mut cycle = path[idx..].to_vec();
+            cycle.push(&global.name);
+            return type_error_result(&global.span, format!("Initialization cycle between globals: {}", cycle.join(" -> ")));
+        }
+
+        path.push(&global.name);
+        for dep in self.dependencies(global) {
+            self.visit(dep, path)?;
+        }
+        path.pop();
+
+        self.done.insert(&global.name);
+        self.order.last_mut().expect("No module to add the global to").1.push(global);
+        Ok(())
+    }
+}
+
+// Modules come after the modules they import
+fn module_order(pkg: &Package) -> Vec<&Module>
+{
+    fn visit<'a>(pkg: &'a Package, md: &'a Module, done: &mut HashSet<&'a str>, order: &mut Vec<&'a Module>)
+    {
+        if !done.insert(&md.name) {
+            return;
+        }
+
+        let mut imports: Vec<String> = md.import_names.iter().map(|i| i.to_namespace_string()).collect();
+        imports.sort();
+        for import in &imports {
+            if let Some(imported) = pkg.modules.get(import) {
+                visit(pkg, imported, done, order);
+            }
+        }
+
+        order.push(md);
+    }
+
+    let mut names: Vec<&String> = pkg.modules.keys().collect();
+    names.sort();
+
+    let mut done = HashSet::new();
+    let mut order = Vec::new();
+    for name in names {
+        visit(pkg, &pkg.modules[name], &mut done, &mut order);
+    }
+    order
+}
+
+/*
+    Determine in which order the globals of a package must be initialized, per module.
+    Modules are initialized after the modules they import, and a global after all the
+    globals its initializer uses, including the ones used by the functions it calls.
+*/
+pub fn global_init_order(pkg: &Package) -> CompileResult<Vec<(&str, Vec<&GlobalBinding>)>>
+{
+    let mut init_order = InitOrder{
+        globals: HashMap::new(),
+        functions: HashMap::new(),
+        done: HashSet::new(),
+        order: Vec::new(),
+    };
+
+    for md in pkg.modules.values() {
+        init_order.globals.extend(md.globals.iter().map(|(name, g)| (name.as_str(), g)));
+        init_order.functions.extend(md.functions.iter().map(|(name, f)| (name.as_str(), f)));
+    }
+
+    for md in module_order(pkg) {
+        init_order.order.push((&md.name, Vec::new()));
+        let mut globals: Vec<&GlobalBinding> = md.globals.values().collect();
+        globals.sort_by(|a, b| a.name.cmp(&b.name));
+        for global in globals {
+            init_order.visit(global, &mut Vec::new())?;
+        }
+    }
+
+    Ok(init_order.order)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use ast::IntSize;
+    use compileerror::{CompileError, ErrorData};
+    use parser::parse_str;
+    use span::{Pos, Span};
+    use target::Target;
+    use timer::PassTimings;
+
+    fn init_order(modules: &[(&str, &str)]) -> CompileResult<Vec<(String, Vec<String>)>>
+    {
+        let target = Target::new(IntSize::I32, "");
+        let mut pkg = parse_str(modules[0].1, modules[0].0, &target)?;
+        for &(name, src) in &modules[1..] {
+            let md = parse_str(src, name, &target)?;
+            pkg.modules.extend(md.modules);
+        }
+
+        pkg.type_check(&target, &mut PassTimings::new())?;
+        let order = global_init_order(&pkg)?;
+        Ok(order.into_iter()
+            .map(|(md, globals)| (md.to_string(), globals.into_iter().map(|g| g.name.clone()).collect()))
+            .collect())
+    }
+
+    #[test]
+    fn test_init_order_within_module()
+    {
+        let order = init_order(&[("test", r#"
+let total = sum()
+var counter = total * 2
+let a = 4
+let b = 5
+
+fn sum() -> int: a + b
+"#)]).unwrap();
+
+        assert_eq!(order, vec![
+            ("test".to_string(), vec!["test::a".to_string(), "test::b".into(), "test::total".into(), "test::counter".into()]),
+        ]);
+    }
+
+    #[test]
+    fn test_init_order_follows_imports()
+    {
+        let order = init_order(&[
+            ("test", r#"
+import test::lib
+
+let x = lib_value() + 1
+
+fn main() -> int: x
+"#),
+            ("test::lib", r#"
+let y = 6
+
+fn lib_value() -> int: y * 7
+"#),
+        ]).unwrap();
+
+        assert_eq!(order, vec![
+            ("test::lib".to_string(), vec!["test::lib::y".to_string()]),
+            ("test".to_string(), vec!["test::x".to_string()]),
+        ]);
+    }
+
+    #[test]
+    fn test_init_cycle()
+    {
+        let err = init_order(&[("test", r#"
+let a = get_b() + 1
+let b = a * 2
+
+fn get_b() -> int: b
+"#)]).unwrap_err();
+
+        assert_eq!(err, CompileError::Type(ErrorData::new(
+            &Span::new("", Pos::new(2, 5), Pos::new(2, 19)),
+            "Initialization cycle between globals: test::a -> test::b -> test::a")));
+    }
+}
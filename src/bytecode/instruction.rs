@@ -1,15 +1,18 @@
 use std::fmt;
 use itertools::free::join;
-use ast::{UnaryOperator, BinaryOperator, Type, IntSize, FloatSize, ptr_type, array_type};
+use ast::{UnaryOperator, BinaryOperator, Type, IntSize, FloatSize, ptr_type, array_type, slice_type, escape_bytes};
 use bytecode::function::{BasicBlockRef, Var};
+use span::Span;
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ByteCodeProperty
 {
     Len,
     Data,
     SumTypeIndex,
+    FirstChar,      // First code point of a UTF-8 string
+    FirstCharLen,   // Number of bytes of the first code point of a UTF-8 string
 }
 
 impl fmt::Display for ByteCodeProperty
@@ -21,11 +24,13 @@ impl fmt::Display for ByteCodeProperty
             ByteCodeProperty::Data => write!(f, "data"),
             ByteCodeProperty::Len => write!(f, "len"),
             ByteCodeProperty::SumTypeIndex => write!(f, "sum_type_index"),
+            ByteCodeProperty::FirstChar => write!(f, "first_char"),
+            ByteCodeProperty::FirstCharLen => write!(f, "first_char_len"),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Constant
 {
     Int(i64, IntSize),
@@ -33,9 +38,11 @@ pub enum Constant
     Float(f64, FloatSize),
     Char(char),
     String(String),
+    Bytes(Vec<u8>),
     Bool(bool),
     Array(Vec<Constant>),
     NullPtr(Type),
+    Struct(Type, Vec<Constant>), // The struct type and the value of every member
 }
 
 impl Constant
@@ -49,11 +56,13 @@ impl Constant
             Constant::Float(_, float_size) => Type::Float(float_size),
             Constant::Char(_) => Type::Char,
             Constant::String(_) => Type::String,
+            Constant::Bytes(_) => slice_type(Type::UInt(IntSize::I8)),
             Constant::Bool(_) => Type::Bool,
             Constant::NullPtr(ref typ) => ptr_type(typ.clone()),
             Constant::Array(ref members) => {
                 array_type(members[0].get_type(), members.len())
-            }
+            },
+            Constant::Struct(ref typ, _) => typ.clone(),
         }
     }
 }
@@ -69,15 +78,17 @@ impl fmt::Display for Constant
             Constant::Float(v, float_size) => write!(f, "(float{} {})", float_size, v),
             Constant::Char(v) => write!(f, "(char {})", v),
             Constant::String(ref v) => write!(f, "(string {})", v),
+            Constant::Bytes(ref v) => write!(f, "(bytes {})", escape_bytes(v)),
             Constant::Bool(v) => write!(f, "(bool {})", v),
             Constant::Array(ref m) => write!(f, "[{}]", join(m.iter(), ", ")),
             Constant::NullPtr(_) => write!(f, "null"),
+            Constant::Struct(_, ref m) => write!(f, "{{{}}}", join(m.iter(), ", ")),
         }
     }
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Operand
 {
     Var(Var),
@@ -85,7 +96,6 @@ pub enum Operand
     Dereference(Var),
     Const(Constant),
     Func(String),
-    SizeOf(Type),
 }
 
 impl Operand
@@ -120,7 +130,7 @@ impl Operand
         Operand::Const(Constant::String(s.into()))
     }
 
-    pub fn get_type(&self, int_size: IntSize) -> Type
+    pub fn get_type(&self) -> Type
     {
         match *self
         {
@@ -129,7 +139,6 @@ impl Operand
             Operand::Dereference(ref var) => var.typ.get_pointer_element_type().expect("Dereference on a non pointer").clone(),
             Operand::Const(ref c) => c.get_type(),
             Operand::Func(_) => Type::Unknown,
-            Operand::SizeOf(_) => Type::UInt(int_size),
         }
     }
 }
@@ -145,7 +154,6 @@ impl fmt::Display for Operand
             Operand::Dereference(ref var) => write!(f, "*{}", var),
             Operand::Const(ref c) => write!(f, "{}", c),
             Operand::Func(ref func) => write!(f, "(func {})", func),
-            Operand::SizeOf(ref typ) => write!(f, "@size({})", typ),
         }
     }
 }
@@ -165,22 +173,33 @@ pub fn float_op(fstr: &str, float_size: FloatSize) -> Operand
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Instruction
 {
-    Store{dst: Var, src: Operand},
+    // Stores, calls and bound arguments copy values, their span is the code which does the copy
+    Store{dst: Var, src: Operand, span: Span},
     Load{dst: Var, ptr: Var},
     LoadMember{dst: Var, obj: Var, member_index: Operand},
-    StoreMember{obj: Var, member_index: Operand, src: Operand},
+    StoreMember{obj: Var, member_index: Operand, src: Operand, span: Span},
     AddressOf{dst: Var, obj: Var},
     AddressOfMember{dst: Var, obj: Var, member_index: Operand},
     GetProperty{dst: Var, obj: Var, prop: ByteCodeProperty},
     SetProperty{obj: Var, prop: ByteCodeProperty, val: usize},
     UnaryOp{dst: Var, op: UnaryOperator, src: Operand},
     BinaryOp{dst: Var, op: BinaryOperator, left: Operand, right: Operand},
-    Call{dst: Option<Var>, func: String, args: Vec<Operand>},
+    // Integer + - or *, which is a runtime error when the result doesn't fit in the destination type
+    CheckedBinaryOp{dst: Var, op: BinaryOperator, left: Operand, right: Operand, span: Span},
+    // dst = if cond: on_true else on_false, without branching
+    Select{dst: Var, cond: Operand, on_true: Operand, on_false: Operand},
+    Call{dst: Option<Var>, func: String, args: Vec<Operand>, span: Span},
+    // Binds the argument of an inlined function, like a call binds the arguments of the called function
+    BindArgument{dst: Var, src: Operand, mutable: bool, span: Span},
     Slice{dst: Var, src: Var, start: Operand, len: Operand},
     MakeSlice{dst: Var, data: Var, len: Var},
+    // Interface value pointing to data, with a vtable holding the methods in the order of the interface's functions
+    MakeInterface{dst: Var, data: Operand, methods: Vec<Operand>},
+    // Function pointer to a method from the vtable of an interface value
+    LoadInterfaceMethod{dst: Var, obj: Var, method_index: usize},
     Cast{dst: Var, src: Operand},
     LoadOptionalFlag{dst: Var, obj: Var},
     StoreNil(Var),
@@ -192,6 +211,7 @@ pub enum Instruction
     ReturnVoid,
     Branch(BasicBlockRef),
     BranchIf{cond: Operand, on_true: BasicBlockRef, on_false: BasicBlockRef},
+    Switch{cond: Operand, cases: Vec<(Constant, BasicBlockRef)>, default: BasicBlockRef},
     Delete(Var),
 }
 
@@ -202,34 +222,200 @@ impl Instruction
         match *self {
             Instruction::Branch(_) |
             Instruction::BranchIf{..} |
+            Instruction::Switch{..} |
             Instruction::ReturnVoid |
             Instruction::Return(_) => true,
             _ => false
         }
     }
+
+    // The blocks a terminator can continue with
+    pub fn branch_targets(&self) -> Vec<BasicBlockRef>
+    {
+        match *self
+        {
+            Instruction::Branch(bb) => vec![bb],
+            Instruction::BranchIf{on_true, on_false, ..} => vec![on_true, on_false],
+            Instruction::Switch{ref cases, default, ..} => cases.iter().map(|&(_, bb)| bb).chain(Some(default)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // All operands read by the instruction
+    pub fn operands(&self) -> Vec<&Operand>
+    {
+        match *self
+        {
+            Instruction::Store{ref src, ..} |
+            Instruction::UnaryOp{ref src, ..} |
+            Instruction::Cast{ref src, ..} |
+            Instruction::BindArgument{ref src, ..} |
+            Instruction::Return(ref src) |
+            Instruction::BranchIf{cond: ref src, ..} |
+            Instruction::Switch{cond: ref src, ..} => vec![src],
+            Instruction::LoadMember{ref member_index, ..} |
+            Instruction::AddressOfMember{ref member_index, ..} => vec![member_index],
+            Instruction::StoreMember{ref member_index, ref src, ..} => vec![member_index, src],
+            Instruction::BinaryOp{ref left, ref right, ..} |
+            Instruction::CheckedBinaryOp{ref left, ref right, ..} => vec![left, right],
+            Instruction::Slice{ref start, ref len, ..} => vec![start, len],
+            Instruction::Select{ref cond, ref on_true, ref on_false, ..} => vec![cond, on_true, on_false],
+            Instruction::Call{ref args, ..} => args.iter().collect(),
+            Instruction::MakeInterface{ref data, ref methods, ..} => Some(data).into_iter().chain(methods.iter()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Same as operands, but mutable
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand>
+    {
+        match *self
+        {
+            Instruction::Store{ref mut src, ..} |
+            Instruction::UnaryOp{ref mut src, ..} |
+            Instruction::Cast{ref mut src, ..} |
+            Instruction::BindArgument{ref mut src, ..} |
+            Instruction::Return(ref mut src) |
+            Instruction::BranchIf{cond: ref mut src, ..} |
+            Instruction::Switch{cond: ref mut src, ..} => vec![src],
+            Instruction::LoadMember{ref mut member_index, ..} |
+            Instruction::AddressOfMember{ref mut member_index, ..} => vec![member_index],
+            Instruction::StoreMember{ref mut member_index, ref mut src, ..} => vec![member_index, src],
+            Instruction::BinaryOp{ref mut left, ref mut right, ..} |
+            Instruction::CheckedBinaryOp{ref mut left, ref mut right, ..} => vec![left, right],
+            Instruction::Slice{ref mut start, ref mut len, ..} => vec![start, len],
+            Instruction::Select{ref mut cond, ref mut on_true, ref mut on_false, ..} => vec![cond, on_true, on_false],
+            Instruction::Call{ref mut args, ..} => args.iter_mut().collect(),
+            Instruction::MakeInterface{ref mut data, ref mut methods, ..} => Some(data).into_iter().chain(methods.iter_mut()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // All variables the instruction refers to, read or written
+    pub fn vars(&self) -> Vec<&Var>
+    {
+        let mut vars = match *self
+        {
+            Instruction::Store{ref dst, ..} |
+            Instruction::UnaryOp{ref dst, ..} |
+            Instruction::BinaryOp{ref dst, ..} |
+            Instruction::CheckedBinaryOp{ref dst, ..} |
+            Instruction::Select{ref dst, ..} |
+            Instruction::Cast{ref dst, ..} |
+            Instruction::BindArgument{ref dst, ..} |
+            Instruction::MakeInterface{ref dst, ..} => vec![dst],
+            Instruction::Load{ref dst, ptr: ref obj} |
+            Instruction::LoadMember{ref dst, ref obj, ..} |
+            Instruction::AddressOf{ref dst, ref obj} |
+            Instruction::AddressOfMember{ref dst, ref obj, ..} |
+            Instruction::GetProperty{ref dst, ref obj, ..} |
+            Instruction::Slice{ref dst, src: ref obj, ..} |
+            Instruction::LoadOptionalFlag{ref dst, ref obj} |
+            Instruction::LoadInterfaceMethod{ref dst, ref obj, ..} => vec![dst, obj],
+            Instruction::MakeSlice{ref dst, ref data, ref len} => vec![dst, data, len],
+            Instruction::Call{ref dst, ..} => dst.iter().collect(),
+            Instruction::StoreMember{ref obj, ..} |
+            Instruction::SetProperty{ref obj, ..} |
+            Instruction::StoreNil(ref obj) |
+            Instruction::StackAlloc(ref obj) |
+            Instruction::HeapAlloc(ref obj) |
+            Instruction::Delete(ref obj) => vec![obj],
+            Instruction::StartScope |
+            Instruction::EndScope |
+            Instruction::Return(_) |
+            Instruction::ReturnVoid |
+            Instruction::Branch(_) |
+            Instruction::BranchIf{..} |
+            Instruction::Switch{..} => Vec::new(),
+        };
+
+        for op in self.operands() {
+            match *op {
+                Operand::Var(ref v) |
+                Operand::AddressOf(ref v) |
+                Operand::Dereference(ref v) => vars.push(v),
+                Operand::Const(_) |
+                Operand::Func(_) => (),
+            }
+        }
+        vars
+    }
+
+    // Same as vars, but mutable
+    pub fn vars_mut(&mut self) -> Vec<&mut Var>
+    {
+        let (mut vars, operands) = match *self
+        {
+            Instruction::Store{ref mut dst, ref mut src, ..} |
+            Instruction::UnaryOp{ref mut dst, ref mut src, ..} |
+            Instruction::Cast{ref mut dst, ref mut src} |
+            Instruction::BindArgument{ref mut dst, ref mut src, ..} => (vec![dst], vec![src]),
+            Instruction::BinaryOp{ref mut dst, ref mut left, ref mut right, ..} |
+            Instruction::CheckedBinaryOp{ref mut dst, ref mut left, ref mut right, ..} => (vec![dst], vec![left, right]),
+            Instruction::Select{ref mut dst, ref mut cond, ref mut on_true, ref mut on_false} => (vec![dst], vec![cond, on_true, on_false]),
+            Instruction::Load{ref mut dst, ptr: ref mut obj} |
+            Instruction::AddressOf{ref mut dst, ref mut obj} |
+            Instruction::GetProperty{ref mut dst, ref mut obj, ..} |
+            Instruction::LoadOptionalFlag{ref mut dst, ref mut obj} |
+            Instruction::LoadInterfaceMethod{ref mut dst, ref mut obj, ..} => (vec![dst, obj], Vec::new()),
+            Instruction::LoadMember{ref mut dst, ref mut obj, ref mut member_index} |
+            Instruction::AddressOfMember{ref mut dst, ref mut obj, ref mut member_index} => (vec![dst, obj], vec![member_index]),
+            Instruction::Slice{ref mut dst, ref mut src, ref mut start, ref mut len} => (vec![dst, src], vec![start, len]),
+            Instruction::MakeSlice{ref mut dst, ref mut data, ref mut len} => (vec![dst, data, len], Vec::new()),
+            Instruction::Call{ref mut dst, ref mut args, ..} => (dst.iter_mut().collect(), args.iter_mut().collect()),
+            Instruction::MakeInterface{ref mut dst, ref mut data, ref mut methods} => (vec![dst], Some(data).into_iter().chain(methods.iter_mut()).collect()),
+            Instruction::StoreMember{ref mut obj, ref mut member_index, ref mut src, ..} => (vec![obj], vec![member_index, src]),
+            Instruction::SetProperty{ref mut obj, ..} |
+            Instruction::StoreNil(ref mut obj) |
+            Instruction::StackAlloc(ref mut obj) |
+            Instruction::HeapAlloc(ref mut obj) |
+            Instruction::Delete(ref mut obj) => (vec![obj], Vec::new()),
+            Instruction::Return(ref mut src) |
+            Instruction::BranchIf{cond: ref mut src, ..} |
+            Instruction::Switch{cond: ref mut src, ..} => (Vec::new(), vec![src]),
+            Instruction::StartScope |
+            Instruction::EndScope |
+            Instruction::ReturnVoid |
+            Instruction::Branch(_) => (Vec::new(), Vec::new()),
+        };
+
+        for op in operands {
+            match *op {
+                Operand::Var(ref mut v) |
+                Operand::AddressOf(ref mut v) |
+                Operand::Dereference(ref mut v) => vars.push(v),
+                Operand::Const(_) |
+                Operand::Func(_) => (),
+            }
+        }
+        vars
+    }
 }
 
-pub fn store_instr(dst: &Var, src: &Var) -> Instruction
+pub fn store_instr(dst: &Var, src: &Var, span: &Span) -> Instruction
 {
     Instruction::Store{
         dst: dst.clone(),
         src: Operand::Var(src.clone()),
+        span: span.clone(),
     }
 }
 
-pub fn store_operand_instr(dst: &Var, op: Operand) -> Instruction
+pub fn store_operand_instr(dst: &Var, op: Operand, span: &Span) -> Instruction
 {
     Instruction::Store{
         dst: dst.clone(),
         src: op,
+        span: span.clone(),
     }
 }
 
-pub fn store_func_instr(dst: &Var, func: &str) -> Instruction
+pub fn store_func_instr(dst: &Var, func: &str, span: &Span) -> Instruction
 {
     Instruction::Store{
         dst: dst.clone(),
         src: Operand::Func(func.into()),
+        span: span.clone(),
     }
 }
 
@@ -259,21 +445,23 @@ pub fn load_member_instr(dst: &Var, obj: &Var, member_index: usize, int_size: In
     }
 }
 
-pub fn store_member_instr(obj: &Var, member_index: usize, src: Var, int_size: IntSize) -> Instruction
+pub fn store_member_instr(obj: &Var, member_index: usize, src: Var, int_size: IntSize, span: &Span) -> Instruction
 {
     Instruction::StoreMember{
         obj: obj.clone(),
         member_index: Operand::const_uint(member_index as u64, int_size),
         src: Operand::Var(src),
+        span: span.clone(),
     }
 }
 
-pub fn store_member_with_var_instr(obj: Var, member_index: Var, src: Var) -> Instruction
+pub fn store_member_with_var_instr(obj: Var, member_index: Var, src: Var, span: &Span) -> Instruction
 {
     Instruction::StoreMember{
         obj: obj,
         member_index: Operand::Var(member_index),
         src: Operand::Var(src),
+        span: span.clone(),
     }
 }
 
@@ -319,6 +507,27 @@ pub fn binary_op_instr(dst: &Var, op: BinaryOperator, left: Operand, right: Oper
     }
 }
 
+pub fn checked_binary_op_instr(dst: &Var, op: BinaryOperator, left: Operand, right: Operand, span: &Span) -> Instruction
+{
+    Instruction::CheckedBinaryOp{
+        dst: dst.clone(),
+        op: op,
+        left: left,
+        right: right,
+        span: span.clone(),
+    }
+}
+
+pub fn select_instr(dst: &Var, cond: &Var, on_true: Operand, on_false: Operand) -> Instruction
+{
+    Instruction::Select{
+        dst: dst.clone(),
+        cond: Operand::Var(cond.clone()),
+        on_true: on_true,
+        on_false: on_false,
+    }
+}
+
 pub fn branch_if_instr(cond: &Var, on_true: BasicBlockRef, on_false: BasicBlockRef) -> Instruction
 {
     Instruction::BranchIf{
@@ -328,21 +537,32 @@ pub fn branch_if_instr(cond: &Var, on_true: BasicBlockRef, on_false: BasicBlockR
     }
 }
 
-pub fn call_instr(dst: &Var, func: &str, args: Vec<Operand>) -> Instruction
+pub fn switch_instr(cond: &Var, cases: Vec<(Constant, BasicBlockRef)>, default: BasicBlockRef) -> Instruction
+{
+    Instruction::Switch{
+        cond: var_op(cond),
+        cases: cases,
+        default: default,
+    }
+}
+
+pub fn call_instr(dst: &Var, func: &str, args: Vec<Operand>, span: &Span) -> Instruction
 {
     Instruction::Call{
         dst: Some(dst.clone()),
         func: func.into(),
-        args: args
+        args: args,
+        span: span.clone(),
     }
 }
 
-pub fn void_call_instr(func: &str, args: Vec<Operand>) -> Instruction
+pub fn void_call_instr(func: &str, args: Vec<Operand>, span: &Span) -> Instruction
 {
     Instruction::Call{
         dst: None,
         func: func.into(),
-        args: args
+        args: args,
+        span: span.clone(),
     }
 }
 
@@ -391,6 +611,24 @@ pub fn cast_instr(dst: &Var, src: &Var) -> Instruction
     }
 }
 
+pub fn make_interface_instr(dst: &Var, data: Operand, methods: Vec<Operand>) -> Instruction
+{
+    Instruction::MakeInterface{
+        dst: dst.clone(),
+        data,
+        methods
+    }
+}
+
+pub fn load_interface_method_instr(dst: &Var, obj: &Var, method_index: usize) -> Instruction
+{
+    Instruction::LoadInterfaceMethod{
+        dst: dst.clone(),
+        obj: obj.clone(),
+        method_index
+    }
+}
+
 pub fn load_optional_flag_instr(dst: &Var, obj: &Var) -> Instruction
 {
     Instruction::LoadOptionalFlag{
@@ -405,7 +643,7 @@ impl fmt::Display for Instruction
     {
         match *self
         {
-            Instruction::Store{ref dst, ref src} => {
+            Instruction::Store{ref dst, ref src, ..} => {
                 writeln!(f, "  store {} {}", dst, src)
             },
 
@@ -417,7 +655,7 @@ impl fmt::Display for Instruction
                 writeln!(f, "  loadm {} {}.{}", dst, obj, member_index)
             },
 
-            Instruction::StoreMember{ref obj, ref member_index, ref src} => {
+            Instruction::StoreMember{ref obj, ref member_index, ref src, ..} => {
                 writeln!(f, "  storem {}.{} {}", obj, member_index, src)
             },
 
@@ -445,7 +683,15 @@ impl fmt::Display for Instruction
                 writeln!(f, "  bop {} {} {} {}", dst, op, left, right)
             },
 
-            Instruction::Call{ref dst, ref func, ref args} => {
+            Instruction::CheckedBinaryOp{ref dst, ref op, ref left, ref right, ..} => {
+                writeln!(f, "  cbop {} {} {} {}", dst, op, left, right)
+            },
+
+            Instruction::Select{ref dst, ref cond, ref on_true, ref on_false} => {
+                writeln!(f, "  select {} {} ? {} : {}", dst, cond, on_true, on_false)
+            },
+
+            Instruction::Call{ref dst, ref func, ref args, ..} => {
                 match *dst {
                     Some(ref dst) => writeln!(f, "  call {} {} {}", dst, func, join(args.iter(), " ")),
                     None => writeln!(f, "  call {} {}", func, join(args.iter(), " ")),
@@ -456,6 +702,10 @@ impl fmt::Display for Instruction
                 writeln!(f, "  cast {} {}", dst, src)
             },
 
+            Instruction::BindArgument{ref dst, ref src, ..} => {
+                writeln!(f, "  bindarg {} {}", dst, src)
+            },
+
             Instruction::StackAlloc(ref var) => {
                 writeln!(f, "  salloc {}", var)
             },
@@ -488,6 +738,11 @@ impl fmt::Display for Instruction
                 writeln!(f, "  brif {} ? {} : {} ", cond, on_true, on_false)
             },
 
+            Instruction::Switch{ref cond, ref cases, ref default} => {
+                let cases = join(cases.iter().map(|&(ref c, bb)| format!("{} => {}", c, bb)), ", ");
+                writeln!(f, "  switch {} [{}] default {}", cond, cases, default)
+            },
+
             Instruction::Delete(ref var) => {
                 writeln!(f, "  delete {}", var)
             },
@@ -500,6 +755,14 @@ impl fmt::Display for Instruction
                 writeln!(f, "  mkslice {} {} {}", dst, data, len)
             }
 
+            Instruction::MakeInterface{ref dst, ref data, ref methods} => {
+                writeln!(f, "  mkiface {} {} [{}]", dst, data, join(methods.iter(), ", "))
+            }
+
+            Instruction::LoadInterfaceMethod{ref dst, ref obj, method_index} => {
+                writeln!(f, "  loadim {} {}.{}", dst, obj, method_index)
+            }
+
             Instruction::LoadOptionalFlag{ref dst, ref obj} => {
                 writeln!(f, "  loadoptf {} {}", dst, obj)
             }
@@ -0,0 +1,393 @@
+// Description of the bytecode instructions and of how they are serialized in the build cache and in export libraries.
+// Bytecode is written by bincode, so an instruction is its variant index (the opcode) as a little endian u32,
+// followed by its operands in the order they are declared.
+#[cfg(test)]
+use bytecode::instruction::Instruction;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OperandKind
+{
+    Var,
+    OptionalVar,
+    Operand,
+    Operands,
+    Constant,
+    Func,
+    Block,
+    Cases,
+    Property,
+    UnaryOperator,
+    BinaryOperator,
+    Index,
+    Bool,
+    Span,
+}
+
+pub const OPERAND_KINDS: &[OperandKind] = &[
+    OperandKind::Var,
+    OperandKind::OptionalVar,
+    OperandKind::Operand,
+    OperandKind::Operands,
+    OperandKind::Constant,
+    OperandKind::Func,
+    OperandKind::Block,
+    OperandKind::Cases,
+    OperandKind::Property,
+    OperandKind::UnaryOperator,
+    OperandKind::BinaryOperator,
+    OperandKind::Index,
+    OperandKind::Bool,
+    OperandKind::Span,
+];
+
+impl OperandKind
+{
+    pub fn name(&self) -> &'static str
+    {
+        match *self
+        {
+            OperandKind::Var => "var",
+            OperandKind::OptionalVar => "optional_var",
+            OperandKind::Operand => "operand",
+            OperandKind::Operands => "operands",
+            OperandKind::Constant => "constant",
+            OperandKind::Func => "func",
+            OperandKind::Block => "block",
+            OperandKind::Cases => "cases",
+            OperandKind::Property => "property",
+            OperandKind::UnaryOperator => "unary_operator",
+            OperandKind::BinaryOperator => "binary_operator",
+            OperandKind::Index => "index",
+            OperandKind::Bool => "bool",
+            OperandKind::Span => "span",
+        }
+    }
+
+    // Strings are a u64 length followed by UTF-8 bytes, enums a u32 variant index followed by the fields of the variant
+    pub fn encoding(&self) -> &'static str
+    {
+        match *self
+        {
+            OperandKind::Var => "A variable: its name as a string, followed by its type as a type enum",
+            OperandKind::OptionalVar => "A u8, 0 when there is no variable, or 1 followed by a var",
+            OperandKind::Operand => "A u32 variant: 0 a var, 1 the address of a var, 2 the value a var points to, \
+                each followed by a var, 3 followed by a constant, or 4 followed by a function name as a string",
+            OperandKind::Operands => "A u64 count, followed by that many operands",
+            OperandKind::Constant => "A u32 variant: 0 int (i64 and int size), 1 uint (u64 and int size), 2 float (f64 and float size), \
+                3 char (u32 code point), 4 string, 5 bool (u8), 6 array (u64 count and constants), 7 null pointer (type pointed to), \
+                8 struct (the struct type, u64 count and constants)",
+            OperandKind::Func => "The name of a function as a string",
+            OperandKind::Block => "The index of a basic block of the function as a u64",
+            OperandKind::Cases => "A u64 count, followed by that many pairs of a constant and a block",
+            OperandKind::Property => "A u32 variant: 0 len, 1 data, 2 sum_type_index, 3 first_char, 4 first_char_len",
+            OperandKind::UnaryOperator => "A u32 variant: 0 !, 1 -",
+            OperandKind::BinaryOperator => "A u32 variant: 0 +, 1 -, 2 *, 3 /, 4 %, 5 <, 6 >, 7 <=, 8 >=, 9 ==, 10 !=, 11 &&, 12 ||, 13 ., 14 as",
+            OperandKind::Index => "A u64",
+            OperandKind::Bool => "A u8, 0 for false and 1 for true",
+            OperandKind::Span => "A source location: the file name as a string, then the start and end line and offset, each a u64",
+        }
+    }
+}
+
+pub struct InstructionInfo
+{
+    pub opcode: u32,
+    pub variant: &'static str,
+    pub mnemonic: &'static str,  // As shown in bytecode dumps
+    pub operands: &'static [(&'static str, OperandKind)],
+    pub description: &'static str,
+}
+
+// The opcode is the index of the variant, which is what bincode writes in front of the operands.
+// There is no catch all, so the tests don't build when an instruction is added without giving it an opcode here,
+// and they fail without an entry in INSTRUCTIONS.
+#[cfg(test)]
+fn opcode(instr: &Instruction) -> u32
+{
+    match *instr
+    {
+        Instruction::Store{..} => 0,
+        Instruction::Load{..} => 1,
+        Instruction::LoadMember{..} => 2,
+        Instruction::StoreMember{..} => 3,
+        Instruction::AddressOf{..} => 4,
+        Instruction::AddressOfMember{..} => 5,
+        Instruction::GetProperty{..} => 6,
+        Instruction::SetProperty{..} => 7,
+        Instruction::UnaryOp{..} => 8,
+        Instruction::BinaryOp{..} => 9,
+        Instruction::CheckedBinaryOp{..} => 10,
+        Instruction::Select{..} => 11,
+        Instruction::Call{..} => 12,
+        Instruction::BindArgument{..} => 13,
+        Instruction::Slice{..} => 14,
+        Instruction::MakeSlice{..} => 15,
+        Instruction::MakeInterface{..} => 16,
+        Instruction::LoadInterfaceMethod{..} => 17,
+        Instruction::Cast{..} => 18,
+        Instruction::LoadOptionalFlag{..} => 19,
+        Instruction::StoreNil(_) => 20,
+        Instruction::StackAlloc(_) => 21,
+        Instruction::HeapAlloc(_) => 22,
+        Instruction::StartScope => 23,
+        Instruction::EndScope => 24,
+        Instruction::Return(_) => 25,
+        Instruction::ReturnVoid => 26,
+        Instruction::Branch(_) => 27,
+        Instruction::BranchIf{..} => 28,
+        Instruction::Switch{..} => 29,
+        Instruction::Delete(_) => 30,
+    }
+}
+
+#[cfg(test)]
+fn instruction_info(instr: &Instruction) -> &'static InstructionInfo
+{
+    &INSTRUCTIONS[opcode(instr) as usize]
+}
+
+// Indexed by opcode
+pub const INSTRUCTIONS: &[InstructionInfo] = &[
+    InstructionInfo{
+        opcode: 0, variant: "Store", mnemonic: "store",
+        operands: &[("dst", OperandKind::Var), ("src", OperandKind::Operand), ("span", OperandKind::Span)],
+        description: "Copy src into dst, span is the code which does the copy",
+    },
+    InstructionInfo{
+        opcode: 1, variant: "Load", mnemonic: "load",
+        operands: &[("dst", OperandKind::Var), ("ptr", OperandKind::Var)],
+        description: "Copy the value ptr points to into dst, for an optional ptr it is the value inside the optional",
+    },
+    InstructionInfo{
+        opcode: 2, variant: "LoadMember", mnemonic: "loadm",
+        operands: &[("dst", OperandKind::Var), ("obj", OperandKind::Var), ("member_index", OperandKind::Operand)],
+        description: "Copy the member or element at member_index of the struct, array or slice obj into dst",
+    },
+    InstructionInfo{
+        opcode: 3, variant: "StoreMember", mnemonic: "storem",
+        operands: &[("obj", OperandKind::Var), ("member_index", OperandKind::Operand), ("src", OperandKind::Operand),
+            ("span", OperandKind::Span)],
+        description: "Copy src into the member or element at member_index of the struct, array or slice obj, span is the code which does the copy",
+    },
+    InstructionInfo{
+        opcode: 4, variant: "AddressOf", mnemonic: "addr",
+        operands: &[("dst", OperandKind::Var), ("obj", OperandKind::Var)],
+        description: "Store a pointer to obj in dst",
+    },
+    InstructionInfo{
+        opcode: 5, variant: "AddressOfMember", mnemonic: "addrm",
+        operands: &[("dst", OperandKind::Var), ("obj", OperandKind::Var), ("member_index", OperandKind::Operand)],
+        description: "Store a pointer to the member or element at member_index of obj in dst",
+    },
+    InstructionInfo{
+        opcode: 6, variant: "GetProperty", mnemonic: "getp",
+        operands: &[("dst", OperandKind::Var), ("obj", OperandKind::Var), ("prop", OperandKind::Property)],
+        description: "Store a property of obj in dst: the length or data pointer of a string, array or slice, \
+            the case index of a sum type, or the first code point of a string and its length in bytes",
+    },
+    InstructionInfo{
+        opcode: 7, variant: "SetProperty", mnemonic: "setp",
+        operands: &[("obj", OperandKind::Var), ("prop", OperandKind::Property), ("val", OperandKind::Index)],
+        description: "Set a property of obj to val, used to set the case index of a sum type",
+    },
+    InstructionInfo{
+        opcode: 8, variant: "UnaryOp", mnemonic: "uop",
+        operands: &[("dst", OperandKind::Var), ("op", OperandKind::UnaryOperator), ("src", OperandKind::Operand)],
+        description: "Store the result of applying op to src in dst",
+    },
+    InstructionInfo{
+        opcode: 9, variant: "BinaryOp", mnemonic: "bop",
+        operands: &[("dst", OperandKind::Var), ("op", OperandKind::BinaryOperator), ("left", OperandKind::Operand), ("right", OperandKind::Operand)],
+        description: "Store the result of left op right in dst, integer arithmetic wraps around",
+    },
+    InstructionInfo{
+        opcode: 10, variant: "CheckedBinaryOp", mnemonic: "cbop",
+        operands: &[("dst", OperandKind::Var), ("op", OperandKind::BinaryOperator), ("left", OperandKind::Operand), ("right", OperandKind::Operand),
+            ("span", OperandKind::Span)],
+        description: "Integer +, - or * like bop, but the program aborts with an error pointing at span when the result does not fit in dst",
+    },
+    InstructionInfo{
+        opcode: 11, variant: "Select", mnemonic: "select",
+        operands: &[("dst", OperandKind::Var), ("cond", OperandKind::Operand), ("on_true", OperandKind::Operand), ("on_false", OperandKind::Operand)],
+        description: "Store on_true in dst if the bool cond is true, otherwise on_false, without branching",
+    },
+    InstructionInfo{
+        opcode: 12, variant: "Call", mnemonic: "call",
+        operands: &[("dst", OperandKind::OptionalVar), ("func", OperandKind::Func), ("args", OperandKind::Operands), ("span", OperandKind::Span)],
+        description: "Call the function or the function pointer variable named func with args, and store the result in dst if there is one, span is the call",
+    },
+    InstructionInfo{
+        opcode: 13, variant: "BindArgument", mnemonic: "bindarg",
+        operands: &[("dst", OperandKind::Var), ("src", OperandKind::Operand), ("mutable", OperandKind::Bool), ("span", OperandKind::Span)],
+        description: "Bind an argument of an inlined function to dst, like a call binds the arguments of the function it calls, span is the inlined call",
+    },
+    InstructionInfo{
+        opcode: 14, variant: "Slice", mnemonic: "slice",
+        operands: &[("dst", OperandKind::Var), ("src", OperandKind::Var), ("start", OperandKind::Operand), ("len", OperandKind::Operand)],
+        description: "Store the slice of len elements of the array or slice src, starting at start, in dst",
+    },
+    InstructionInfo{
+        opcode: 15, variant: "MakeSlice", mnemonic: "mkslice",
+        operands: &[("dst", OperandKind::Var), ("data", OperandKind::Var), ("len", OperandKind::Var)],
+        description: "Store the slice or string with data pointer data and length len in dst",
+    },
+    InstructionInfo{
+        opcode: 16, variant: "MakeInterface", mnemonic: "mkiface",
+        operands: &[("dst", OperandKind::Var), ("data", OperandKind::Operand), ("methods", OperandKind::Operands)],
+        description: "Store an interface value pointing to data in dst, with a vtable holding the functions in methods, \
+            in the order of the functions of the interface",
+    },
+    InstructionInfo{
+        opcode: 17, variant: "LoadInterfaceMethod", mnemonic: "loadim",
+        operands: &[("dst", OperandKind::Var), ("obj", OperandKind::Var), ("method_index", OperandKind::Index)],
+        description: "Store the function pointer at method_index in the vtable of the interface value obj in dst",
+    },
+    InstructionInfo{
+        opcode: 18, variant: "Cast", mnemonic: "cast",
+        operands: &[("dst", OperandKind::Var), ("src", OperandKind::Operand)],
+        description: "Convert src to the type of dst, between numeric types or between pointer types",
+    },
+    InstructionInfo{
+        opcode: 19, variant: "LoadOptionalFlag", mnemonic: "loadoptf",
+        operands: &[("dst", OperandKind::Var), ("obj", OperandKind::Var)],
+        description: "Store whether the optional obj holds a value in the bool dst",
+    },
+    InstructionInfo{
+        opcode: 20, variant: "StoreNil", mnemonic: "storenil",
+        operands: &[("var", OperandKind::Var)],
+        description: "Set the optional var to nil",
+    },
+    InstructionInfo{
+        opcode: 21, variant: "StackAlloc", mnemonic: "salloc",
+        operands: &[("var", OperandKind::Var)],
+        description: "Allocate var on the stack, so it can be written more than once and its address can be taken",
+    },
+    InstructionInfo{
+        opcode: 22, variant: "HeapAlloc", mnemonic: "halloc",
+        operands: &[("var", OperandKind::Var)],
+        description: "Allocate memory for a value of the type of var with malloc, var becomes a pointer to it",
+    },
+    InstructionInfo{
+        opcode: 23, variant: "StartScope", mnemonic: "scope start",
+        operands: &[],
+        description: "Start a scope, variables declared after it are no longer used after the matching scope end",
+    },
+    InstructionInfo{
+        opcode: 24, variant: "EndScope", mnemonic: "scope end",
+        operands: &[],
+        description: "End the innermost scope",
+    },
+    InstructionInfo{
+        opcode: 25, variant: "Return", mnemonic: "ret",
+        operands: &[("value", OperandKind::Operand)],
+        description: "Return value from the function",
+    },
+    InstructionInfo{
+        opcode: 26, variant: "ReturnVoid", mnemonic: "ret void",
+        operands: &[],
+        description: "Return from a function without a return value",
+    },
+    InstructionInfo{
+        opcode: 27, variant: "Branch", mnemonic: "br",
+        operands: &[("block", OperandKind::Block)],
+        description: "Continue with block",
+    },
+    InstructionInfo{
+        opcode: 28, variant: "BranchIf", mnemonic: "brif",
+        operands: &[("cond", OperandKind::Operand), ("on_true", OperandKind::Block), ("on_false", OperandKind::Block)],
+        description: "Continue with on_true if the bool cond is true, otherwise with on_false",
+    },
+    InstructionInfo{
+        opcode: 29, variant: "Switch", mnemonic: "switch",
+        operands: &[("cond", OperandKind::Operand), ("cases", OperandKind::Cases), ("default", OperandKind::Block)],
+        description: "Continue with the block of the case whose constant equals the integer cond, or with default if there is none",
+    },
+    InstructionInfo{
+        opcode: 30, variant: "Delete", mnemonic: "delete",
+        operands: &[("var", OperandKind::Var)],
+        description: "Free the memory var points to, which was allocated by halloc",
+    },
+];
+
+#[cfg(test)]
+mod test
+{
+    use bincode;
+    use ast::{BinaryOperator, UnaryOperator, IntSize, Type};
+    use bytecode::function::Var;
+    use bytecode::instruction::*;
+    use span::{Span, Pos};
+    use super::{INSTRUCTIONS, opcode, instruction_info};
+
+    // One of every instruction, in the order of their declaration
+    fn all_instructions() -> Vec<Instruction>
+    {
+        let v = Var::named("v", Type::Int(IntSize::I32));
+        let op = var_op(&v);
+        let span = Span::new("test.mhr", Pos::new(1, 1), Pos::new(1, 5));
+        vec![
+            Instruction::Store{dst: v.clone(), src: op.clone(), span: span.clone()},
+            Instruction::Load{dst: v.clone(), ptr: v.clone()},
+            Instruction::LoadMember{dst: v.clone(), obj: v.clone(), member_index: op.clone()},
+            Instruction::StoreMember{obj: v.clone(), member_index: op.clone(), src: op.clone(), span: span.clone()},
+            Instruction::AddressOf{dst: v.clone(), obj: v.clone()},
+            Instruction::AddressOfMember{dst: v.clone(), obj: v.clone(), member_index: op.clone()},
+            Instruction::GetProperty{dst: v.clone(), obj: v.clone(), prop: ByteCodeProperty::Len},
+            Instruction::SetProperty{obj: v.clone(), prop: ByteCodeProperty::SumTypeIndex, val: 1},
+            Instruction::UnaryOp{dst: v.clone(), op: UnaryOperator::Sub, src: op.clone()},
+            Instruction::BinaryOp{dst: v.clone(), op: BinaryOperator::Add, left: op.clone(), right: op.clone()},
+            Instruction::CheckedBinaryOp{dst: v.clone(), op: BinaryOperator::Mul, left: op.clone(), right: op.clone(), span: span.clone()},
+            Instruction::Select{dst: v.clone(), cond: Operand::const_bool(true), on_true: op.clone(), on_false: op.clone()},
+            Instruction::Call{dst: Some(v.clone()), func: "f".into(), args: vec![op.clone()], span: span.clone()},
+            Instruction::BindArgument{dst: v.clone(), src: op.clone(), mutable: false, span: span},
+            Instruction::Slice{dst: v.clone(), src: v.clone(), start: op.clone(), len: op.clone()},
+            Instruction::MakeSlice{dst: v.clone(), data: v.clone(), len: v.clone()},
+            Instruction::MakeInterface{dst: v.clone(), data: op.clone(), methods: vec![Operand::Func("f".into())]},
+            Instruction::LoadInterfaceMethod{dst: v.clone(), obj: v.clone(), method_index: 0},
+            Instruction::Cast{dst: v.clone(), src: op.clone()},
+            Instruction::LoadOptionalFlag{dst: v.clone(), obj: v.clone()},
+            Instruction::StoreNil(v.clone()),
+            Instruction::StackAlloc(v.clone()),
+            Instruction::HeapAlloc(v.clone()),
+            Instruction::StartScope,
+            Instruction::EndScope,
+            Instruction::Return(op.clone()),
+            Instruction::ReturnVoid,
+            Instruction::Branch(1),
+            Instruction::BranchIf{cond: Operand::const_bool(true), on_true: 1, on_false: 2},
+            Instruction::Switch{cond: op, cases: vec![(Constant::Int(1, IntSize::I32), 1)], default: 2},
+            Instruction::Delete(v),
+        ]
+    }
+
+    #[test]
+    fn test_table_covers_every_instruction()
+    {
+        let instructions = all_instructions();
+        assert_eq!(instructions.len(), INSTRUCTIONS.len());
+        for (idx, (instr, info)) in instructions.iter().zip(INSTRUCTIONS.iter()).enumerate() {
+            assert_eq!(info.opcode as usize, idx);
+            assert_eq!(opcode(instr), info.opcode);
+            assert!(format!("{:?}", instr).starts_with(info.variant));
+            assert!(format!("{}", instr).trim().starts_with(info.mnemonic));
+            assert!(!info.description.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_opcodes_match_serializer()
+    {
+        for instr in all_instructions() {
+            let bytes = bincode::serialize(&instr, bincode::Infinite).expect("Serialization failed");
+            let written = bytes[..4].iter().rev().fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+            assert_eq!(written, instruction_info(&instr).opcode);
+
+            let read: Instruction = bincode::deserialize(&bytes).expect("Deserialization failed");
+            assert_eq!(read, instr);
+        }
+
+        // A branch is its opcode followed by the block index as a u64
+        let bytes = bincode::serialize(&Instruction::Branch(5), bincode::Infinite).expect("Serialization failed");
+        assert_eq!(bytes, vec![27, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
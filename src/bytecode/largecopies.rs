@@ -0,0 +1,148 @@
+use ast::Type;
+use bytecode::{ByteCodeModule, RETURN_VALUE_ARG};
+use bytecode::function::ByteCodeFunction;
+use bytecode::instruction::{Instruction, Operand};
+use compileerror::print_message;
+use layout::layout_of;
+use span::Span;
+use target::Target;
+
+// Copies are only explicit in the bytecode, a value built in its destination is never copied,
+// but using an aggregate as the value of an argument, a store or a return copies all of it
+fn copied_type(op: &Operand) -> Option<Type>
+{
+    match *op
+    {
+        Operand::Var(_) | Operand::Dereference(_) | Operand::Const(_) => Some(op.get_type()).filter(|t| !t.pass_by_value()),
+        Operand::AddressOf(_) | Operand::Func(_) => None,
+    }
+}
+
+fn copies<'a>(instr: &'a Instruction, func: &'a ByteCodeFunction) -> Vec<(Type, String, &'a Span)>
+{
+    match *instr
+    {
+        Instruction::Call{ref func, ref args, ref span, ..} =>
+            args.iter().filter_map(copied_type).map(|t| (t, format!("to pass it by value to {}", func), span)).collect(),
+        Instruction::BindArgument{ref src, ref span, ..} =>
+            copied_type(src).map(|t| (t, "to pass it by value to an inlined function".into(), span)).into_iter().collect(),
+        Instruction::Store{ref dst, ref src, ref span} if dst.name == RETURN_VALUE_ARG =>
+            copied_type(src).map(|t| (t, "to return it".into(), span)).into_iter().collect(),
+        // A return has no span of its own
+        Instruction::Return(ref src) =>
+            copied_type(src).map(|t| (t, "to return it".into(), &func.sig.span)).into_iter().collect(),
+        Instruction::Store{ref src, ref span, ..} =>
+            copied_type(src).map(|t| (t, "to store it".into(), span)).into_iter().collect(),
+        Instruction::StoreMember{ref src, ref span, ..} =>
+            copied_type(src).map(|t| (t, "to store it in a member or element".into(), span)).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn function_large_copies(func: &ByteCodeFunction, threshold: usize, target: &Target, found: &mut Vec<(Span, String)>)
+{
+    func.for_each_instruction(|instr: &Instruction| {
+        for (typ, what, span) in copies(instr, func) {
+            let size = layout_of(&typ, target).size;
+            let msg = format!("Warning: {} copies a value of type {} ({} bytes) {}, use a pointer or a slice to avoid the copy", func.sig.name, typ, size, what);
+            // Inlining can duplicate the instructions of a copy, which should still be reported once
+            let copy = (span.clone(), msg);
+            if size > threshold && !found.contains(&copy) {
+                found.push(copy);
+            }
+        }
+        true
+    });
+}
+
+// The implicit copies of values larger than threshold bytes, with the span of the code doing the copy
+fn large_copies(module: &ByteCodeModule, threshold: usize, target: &Target) -> Vec<(Span, String)>
+{
+    let mut found = Vec::new();
+    for func in module.sorted_functions().into_iter().filter(|f| !f.external && f.sig.span.file != "prelude") {
+        function_large_copies(func, threshold, target, &mut found);
+    }
+    found
+}
+
+pub fn warn_large_copies(module: &ByteCodeModule, threshold: usize, target: &Target)
+{
+    for (span, msg) in large_copies(module, threshold, target) {
+        print_message(&msg, &span);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use ast::IntSize;
+    use bytecode::test::generate_byte_code;
+    use target::Target;
+    use super::large_copies;
+
+    // The line of each warning and its message
+    fn large_copy_warnings(src: &str, threshold: usize) -> Vec<(usize, String)>
+    {
+        let m = generate_byte_code(src, false).expect("Compilation failed");
+        large_copies(&m, threshold, &Target::new(IntSize::I32, "")).into_iter().map(|(span, msg)| (span.start.line, msg)).collect()
+    }
+
+    fn with_array(code: &str) -> String
+    {
+        let elements: Vec<&str> = (0..128).map(|_| "0.5").collect();
+        code.replace("ARRAY", &format!("[{}]", elements.join(", ")))
+    }
+
+    #[test]
+    fn test_large_argument_copy()
+    {
+        let src = with_array(r#"
+fn first(v: float64[128]) -> float64: v[0]
+
+fn main() -> int:
+    let v = ARRAY
+    if first(v) > 0.0: 0 else 1
+"#);
+        let warnings = large_copy_warnings(&src, 256);
+        assert_eq!(warnings, vec![
+            (6, "Warning: test::main copies a value of type float64[128] (1024 bytes) to pass it by value to test::first, use a pointer or a slice to avoid the copy".to_owned()),
+        ]);
+
+        assert_eq!(large_copy_warnings(&src, 1023).len(), 1);
+        assert!(large_copy_warnings(&src, 1024).is_empty());
+    }
+
+    #[test]
+    fn test_construction_in_place_is_not_a_copy()
+    {
+        let src = with_array(r#"
+fn main() -> int:
+    let v = ARRAY
+    if v[0] > 0.0: 0 else 1
+"#);
+        assert!(large_copy_warnings(&src, 256).is_empty());
+
+        let src = with_array(r#"
+fn main() -> int:
+    let v = ARRAY
+    let w = v
+    if w[0] > 0.0: 0 else 1
+"#);
+        assert_eq!(large_copy_warnings(&src, 256), vec![
+            (4, "Warning: test::main copies a value of type float64[128] (1024 bytes) to store it, use a pointer or a slice to avoid the copy".to_owned()),
+        ]);
+    }
+    #[test]
+    fn test_each_copy_is_reported_at_its_own_line()
+    {
+        let src = with_array(r#"
+fn main() -> int:
+    let v = ARRAY
+    let w = v
+    let x = v
+    if w[0] > x[0]: 0 else 1
+"#);
+        let lines: Vec<usize> = large_copy_warnings(&src, 256).into_iter().map(|(line, _)| line).collect();
+        assert_eq!(lines, vec![4, 5]);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use ast::is_anonymous_lambda_name;
+use bytecode::{ByteCodeModule, ByteCodeFunction, Operand};
+
+// Lambdas which only differ in their name and source location can share one function.
+// Lambdas cannot capture variables, once they can, the captured variables have to be compared as well.
+fn same_lambda(a: &ByteCodeFunction, b: &ByteCodeFunction) -> bool
+{
+    let arg_names = |f: &ByteCodeFunction| f.sig.args.iter().map(|arg| (arg.name.clone(), arg.mutable)).collect::<Vec<_>>();
+    a.sig.typ == b.sig.typ && arg_names(a) == arg_names(b) && a.blocks == b.blocks
+}
+
+// Maps each lambda which is the same as another lambda with a smaller name on that lambda
+fn find_duplicates(bc_mod: &ByteCodeModule, function_names: &[String]) -> HashMap<String, String>
+{
+    let mut lambdas: Vec<&String> = function_names.iter().filter(|name| is_anonymous_lambda_name(name)).collect();
+    lambdas.sort();
+
+    let mut duplicates = HashMap::new();
+    for (idx, name) in lambdas.iter().enumerate() {
+        if duplicates.contains_key(*name) {
+            continue;
+        }
+
+        for other in &lambdas[idx + 1..] {
+            if !duplicates.contains_key(*other) && same_lambda(&bc_mod.functions[*name], &bc_mod.functions[*other]) {
+                duplicates.insert((*other).clone(), (*name).clone());
+            }
+        }
+    }
+    duplicates
+}
+
+// Removes the duplicate lambdas of a module, and points the references to them at the lambda which is kept.
+// Lambdas containing lambdas only become the same after the inner ones are merged, so this is repeated until nothing changes.
+pub fn merge_identical_lambdas(bc_mod: &mut ByteCodeModule, function_names: &mut Vec<String>)
+{
+    loop {
+        let duplicates = find_duplicates(bc_mod, function_names);
+        if duplicates.is_empty() {
+            return;
+        }
+
+        function_names.retain(|name| !duplicates.contains_key(name));
+        for name in duplicates.keys() {
+            bc_mod.functions.remove(name);
+        }
+
+        for name in function_names.iter() {
+            if let Some(func) = bc_mod.functions.get_mut(name) {
+                func.for_each_instruction_mut(|instr| {
+                    for op in instr.operands_mut() {
+                        if let Operand::Func(ref mut func_name) = *op {
+                            if let Some(kept) = duplicates.get(func_name) {
+                                *func_name = kept.clone();
+                            }
+                        }
+                    }
+                    true
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use bytecode::test::generate_byte_code;
+
+    fn lambda_names(src: &str) -> Vec<String>
+    {
+        let bc_mod = generate_byte_code(src, false).expect("Compilation failed");
+        let mut names: Vec<String> = bc_mod.functions.keys().filter(|name| name.contains("lambda")).cloned().collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_identical_lambdas_are_merged()
+    {
+        let names = lambda_names(r#"
+fn apply(f: fn(bool) -> bool, x: bool) -> bool: f(x)
+
+fn main() -> int:
+    let a = apply(fn(x) -> !x, true)
+    let b = apply(fn(x) -> !x, false)
+    if a || b: 1 else 0
+"#);
+        assert_eq!(names, vec!["test::main::lambda.0".to_owned()]);
+    }
+
+    #[test]
+    fn test_different_lambdas_are_kept()
+    {
+        let names = lambda_names(r#"
+fn apply(f: fn(bool) -> bool, x: bool) -> bool: f(x)
+
+fn main() -> int:
+    let a = apply(fn(x) -> !x, true)
+    let b = apply(fn(y) -> y, false)
+    let c = apply(fn(x) -> x && true, false)
+    if a || b || c: 1 else 0
+"#);
+        assert_eq!(names, vec!["test::main::lambda.0".to_owned(), "test::main::lambda.1".to_owned(), "test::main::lambda.2".to_owned()]);
+    }
+
+    #[test]
+    fn test_lambda_names_are_deterministic()
+    {
+        let src = r#"
+fn apply(f: fn(bool) -> bool, x: bool) -> bool: f(x)
+
+fn other() -> bool: apply(fn(x) -> !x, true)
+
+fn main() -> int:
+    let a = apply(fn(y) -> y, true)
+    if a && other(): 1 else 0
+"#;
+        let first = generate_byte_code(src, false).expect("Compilation failed").to_string();
+        let second = generate_byte_code(src, false).expect("Compilation failed").to_string();
+        assert_eq!(first, second);
+        assert!(first.contains("test::other::lambda.0"));
+        assert!(first.contains("test::main::lambda.0"));
+    }
+}
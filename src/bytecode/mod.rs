@@ -1,16 +1,43 @@
 mod compiler;
 mod consteval;
 mod function;
+mod initorder;
 mod instruction;
+mod isa;
+mod largecopies;
+mod mergelambdas;
 mod optimizer;
+mod verifier;
 
 use std::fmt;
 use std::collections::HashMap;
+use ast::{Type, LinkLibrary};
+use buildinfo::BuildInfo;
 
 pub use self::instruction::*;
 pub use self::function::*;
+pub use self::isa::{InstructionInfo, INSTRUCTIONS, OPERAND_KINDS};
 pub use self::compiler::{compile_to_byte_code};
-pub use self::optimizer::{OptimizationLevel, optimize_module};
+pub use self::optimizer::{OptimizationLevel, optimize_module, eliminate_unused_functions, RETURN_VALUE_ARG};
+pub use self::verifier::verify_module;
+pub use self::largecopies::warn_large_copies;
+
+// What happens when the result of integer + - or * doesn't fit in its type
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum OverflowMode
+{
+    Check,  // Runtime error
+    Wrap,   // Wrap around to the width of the type
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ByteCodeGlobal
+{
+    pub typ: Type,
+    pub mutable: bool,
+    // None if the global is initialized at startup, by the __module_init function of its module
+    pub init: Option<Constant>,
+}
 
 #[derive(Debug)]
 pub struct ByteCodeModule
@@ -18,7 +45,24 @@ pub struct ByteCodeModule
     pub name: String,
     pub functions: HashMap<String, ByteCodeFunction>,
     pub imported_functions: Vec<ByteCodeFunction>,
-    pub globals: HashMap<String, Constant>,
+    pub globals: HashMap<String, ByteCodeGlobal>,
+    // The functions compiled for each module of the package, modules loaded from the build cache are not in here
+    pub module_functions: HashMap<String, Vec<String>>,
+    // The libraries the external functions of each module need, including the modules loaded from the build cache
+    pub link_libraries: HashMap<String, Vec<LinkLibrary>>,
+    pub overflow_mode: OverflowMode,
+    // Embedded in the generated code, when set
+    pub build_info: Option<BuildInfo>,
+}
+
+// The bytecode of a single module, which is what the build cache stores
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompiledModule
+{
+    pub name: String,
+    pub functions: Vec<ByteCodeFunction>,
+    pub globals: Vec<(String, ByteCodeGlobal)>,
+    pub link_libraries: Vec<LinkLibrary>,
 }
 
 impl ByteCodeModule
@@ -32,19 +76,60 @@ impl ByteCodeModule
     {
         self.functions.get(name)
     }
+
+    // Sorted by name, so dumps and generated code do not depend on the order of the hash map
+    pub fn sorted_functions(&self) -> Vec<&ByteCodeFunction>
+    {
+        let mut functions: Vec<&ByteCodeFunction> = self.functions.values().collect();
+        functions.sort_by(|a, b| a.sig.name.cmp(&b.sig.name));
+        functions
+    }
+
+    // Copy the bytecode of one module, before it gets optimized
+    pub fn compiled_module(&self, module_name: &str, global_names: &[String]) -> Option<CompiledModule>
+    {
+        let function_names = try_opt!(self.module_functions.get(module_name));
+        Some(CompiledModule{
+            name: module_name.into(),
+            functions: function_names.iter().map(|name| self.functions[name].clone()).collect(),
+            globals: global_names.iter().map(|name| (name.clone(), self.globals[name].clone())).collect(),
+            link_libraries: self.link_libraries.get(module_name).cloned().unwrap_or_default(),
+        })
+    }
+
+    // The libraries which have to be linked in, ordered by module name and without duplicates
+    pub fn libraries(&self) -> Vec<LinkLibrary>
+    {
+        let mut module_names: Vec<&String> = self.link_libraries.keys().collect();
+        module_names.sort();
+
+        let mut libraries: Vec<LinkLibrary> = Vec::new();
+        for lib in module_names.into_iter().flat_map(|name| self.link_libraries[name].iter()) {
+            if !libraries.contains(lib) {
+                libraries.push(lib.clone());
+            }
+        }
+        libraries
+    }
 }
 
 impl fmt::Display for ByteCodeModule
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
     {
-        for (name, value) in &self.globals {
-            writeln!(f, "glob {} = {}", name, value)?;
+        let mut globals: Vec<(&String, &ByteCodeGlobal)> = self.globals.iter().collect();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, glob) in globals {
+            match glob.init
+            {
+                Some(ref value) => writeln!(f, "glob {} = {}", name, value)?,
+                None => writeln!(f, "glob {}: {}", name, glob.typ)?,
+            }
         }
 
         writeln!(f, " ")?;
 
-        for func in self.functions.values() {
+        for func in self.sorted_functions() {
             func.fmt(f)?;
             writeln!(f, " ")?;
         }
@@ -52,19 +137,34 @@ impl fmt::Display for ByteCodeModule
     }
 }
 
+#[cfg(test)]
+mod golden;
+
 #[cfg(test)]
 pub mod test
 {
     use compileerror::CompileResult;
     use parser::{parse_str};
-    use bytecode::{ByteCodeModule, compile_to_byte_code};
+    use bytecode::{ByteCodeModule, OverflowMode, compile_to_byte_code};
+    use bytecode::golden::assert_ir_matches_file;
     use ast::{TreePrinter, IntSize};
     use target::Target;
+    use timer::PassTimings;
 
     pub fn generate_byte_code(prog: &str, dump: bool) -> CompileResult<ByteCodeModule>
     {
-        let target = Target::new(IntSize::I32, "");
-        let mut pkg = parse_str(prog, "test", &target)?;
+        generate_byte_code_with_overflow_mode(prog, dump, OverflowMode::Check)
+    }
+
+    pub fn generate_byte_code_with_overflow_mode(prog: &str, dump: bool, overflow_mode: OverflowMode) -> CompileResult<ByteCodeModule>
+    {
+        generate_byte_code_for_target(prog, dump, overflow_mode, &Target::new(IntSize::I32, ""))
+    }
+
+    // Byte code which is passed to a backend has to be generated for the target of that backend
+    pub fn generate_byte_code_for_target(prog: &str, dump: bool, overflow_mode: OverflowMode, target: &Target) -> CompileResult<ByteCodeModule>
+    {
+        let mut pkg = parse_str(prog, "test", target)?;
 
         if dump {
             println!("Before type check");
@@ -72,7 +172,7 @@ pub mod test
             println!("-----------------");
         }
 
-        pkg.type_check(&target)?;
+        pkg.type_check(target, &mut PassTimings::new())?;
 
         if dump {
             println!("After type check");
@@ -80,7 +180,7 @@ pub mod test
             println!("-----------------");
         }
 
-        let bc_mod = compile_to_byte_code(&pkg, &target)?;
+        let bc_mod = compile_to_byte_code(&pkg, Vec::new(), target, overflow_mode)?;
         if dump {
             println!("ByteCode:");
             println!("{}", bc_mod);
@@ -89,6 +189,351 @@ pub mod test
 
         Ok(bc_mod)
     }
-}
 
+    // The dumps of some functions of a module, to compare with a golden file
+    fn function_dumps(m: &ByteCodeModule, names: &[&str]) -> String
+    {
+        names.iter().map(|name| format!("{}", m.get_function(name).expect("Unknown function"))).collect()
+    }
+
+    #[test]
+    fn test_deeply_nested_expression()
+    {
+        use std::thread;
+        use itertools::free::join;
+        use COMPILER_STACK_SIZE;
+
+        let compiler = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(|| {
+            let sum = join((0..5000).map(|_| "1"), " + ");
+            let m = generate_byte_code(&format!("fn main() -> int: {}", sum), false);
+            assert!(m.is_ok());
+
+            let sum = join((0..20_000).map(|_| "1"), " + ");
+            let m = generate_byte_code(&format!("fn main() -> int: {}", sum), false);
+            assert!(m.is_err());
+        });
+
+        compiler.expect("Unable to start thread").join().expect("Compilation crashed");
+    }
 
+    #[test]
+    fn test_return_on_every_path()
+    {
+        use bytecode::{eliminate_unused_functions, verify_module};
+        use bytecode::instruction::Instruction;
+
+        let mut m = generate_byte_code(r#"
+fn sign(x: int) -> int:
+    if x < 0:
+        return -1
+    else
+        match x:
+            0 => return 0
+            _ => return 1
+
+fn apply(x: int, f: fn(int) -> int) -> int: f(x)
+
+fn main() -> int: sign(-4) + apply(5, fn(x) -> if x > 3: return x * 2 else return x)
+"#, false).expect("Compilation failed");
+        eliminate_unused_functions(&mut m);
+        assert_eq!(verify_module(&m), Ok(()));
+
+        // The end blocks of the if and the match are never reached, so they are gone instead of ending in a ret void
+        for func in m.functions.values() {
+            assert!(func.blocks.values().all(|bb| !bb.instructions.contains(&Instruction::ReturnVoid)), "{}", func);
+        }
+    }
+
+    #[test]
+    fn test_return_from_inside_scopes()
+    {
+        use bytecode::instruction::{Instruction, Operand};
+
+        let m = generate_byte_code(r#"
+fn first(v: ?int) -> ?int:
+    if let x = v:
+        if x > 0:
+            return x * 2
+    nil
+
+fn main() -> int:
+    match first(4):
+        ?x => x
+        nil => 0
+"#, false).expect("Compilation failed");
+
+        // The scopes end before the return, so the returned value is declared at the start of the function
+        let func = m.get_function("test::first").expect("first must exist");
+        let entry = func.blocks.values().next().expect("No entry block");
+        let mut returns = 0;
+        for bb in func.blocks.values() {
+            for pair in bb.instructions.windows(2) {
+                if let (&Instruction::EndScope, &Instruction::Return(Operand::Var(ref var))) = (&pair[0], &pair[1]) {
+                    assert!(entry.instructions.contains(&Instruction::StackAlloc(var.clone())), "{}", func);
+                    returns += 1;
+                }
+            }
+        }
+        assert_eq!(returns, 1);
+    }
+
+    #[test]
+    fn test_overflow_mode()
+    {
+        let prog = r#"
+fn f(a: int, b: int) -> int: (a + b) * (a - b) / wrapping_add(a, b)
+fn main() -> int: f(4, 3)
+"#;
+        use bytecode::eliminate_unused_functions;
+
+        // Get rid of the prelude functions, so only the cbops of f are counted
+        let mut checked = generate_byte_code_with_overflow_mode(prog, false, OverflowMode::Check).expect("Compilation failed");
+        eliminate_unused_functions(&mut checked);
+        let checked = format!("{}", checked);
+        assert_eq!(checked.matches("cbop").count(), 3);
+
+        let wrapped = generate_byte_code_with_overflow_mode(prog, false, OverflowMode::Wrap).expect("Compilation failed");
+        let wrapped = format!("{}", wrapped);
+        assert!(!wrapped.contains("cbop"));
+    }
+
+    #[test]
+    fn test_abs_overflow_mode()
+    {
+        let prog = r#"
+fn f(a: int, b: uint) -> int: abs(a) + abs(b) as int
+fn main() -> int: f(-4, 3u)
+"#;
+        use bytecode::eliminate_unused_functions;
+
+        // Negating the smallest int overflows, so only the signed abs has a cbop next to the one of the addition
+        let mut checked = generate_byte_code_with_overflow_mode(prog, false, OverflowMode::Check).expect("Compilation failed");
+        eliminate_unused_functions(&mut checked);
+        let checked = format!("{}", checked);
+        assert_eq!(checked.matches("cbop").count(), 2);
+        assert_eq!(checked.matches("select").count(), 1);
+
+        let wrapped = generate_byte_code_with_overflow_mode(prog, false, OverflowMode::Wrap).expect("Compilation failed");
+        let wrapped = format!("{}", wrapped);
+        assert!(!wrapped.contains("cbop"));
+    }
+
+    #[test]
+    fn test_static_global_data()
+    {
+        use bincode;
+        use ast::array_type;
+        use bytecode::{ByteCodeGlobal, Constant};
+
+        let m = generate_byte_code(r#"
+struct Point:
+    x: int
+    y: int
+
+let ORIGIN = Point{0, 0}
+let CORNERS = [Point{1, 2}, Point{3, 4}]
+var names = ["a", "b"]
+let total = sum()
+
+fn sum() -> int: CORNERS[0].x + CORNERS[1].y
+
+fn main() -> int: total + ORIGIN.x
+"#, false).expect("Compilation failed");
+
+        let point_type = m.globals["test::ORIGIN"].typ.clone();
+        let point = |x, y| Constant::Struct(point_type.clone(), vec![Constant::Int(x, IntSize::I32), Constant::Int(y, IntSize::I32)]);
+        assert_eq!(m.globals["test::ORIGIN"].init, Some(point(0, 0)));
+        assert_eq!(m.globals["test::CORNERS"].init, Some(Constant::Array(vec![point(1, 2), point(3, 4)])));
+        assert_eq!(m.globals["test::names"].init, Some(Constant::Array(vec![Constant::String("a".into()), Constant::String("b".into())])));
+
+        // Only total is initialized at startup
+        assert_eq!(m.globals["test::total"].init, None);
+        let init = format!("{}", m.functions["test::__module_init"]);
+        assert!(init.contains("test::total"));
+        assert!(!init.contains("test::CORNERS"));
+
+        // Build caches store the constants with the globals
+        let mut data = Vec::new();
+        bincode::serialize_into(&mut data, &m.globals["test::CORNERS"], bincode::Infinite).expect("Serialization failed");
+        let global: ByteCodeGlobal = bincode::deserialize_from(&mut &data[..], bincode::Infinite).expect("Deserialization failed");
+        assert_eq!(global.init, m.globals["test::CORNERS"].init);
+        assert_eq!(global.typ, array_type(point_type.clone(), 2));
+    }
+
+    #[test]
+    fn test_byte_strings()
+    {
+        use bytecode::{Constant, eliminate_unused_functions, verify_module};
+
+        let mut m = generate_byte_code(r#"
+let MAGIC = b"\x7fELF"
+
+fn is_elf(header: bytes) -> bool: subslice(header, 0, 4) == MAGIC
+
+fn main() -> int: if is_elf(b"\x7fELF\x02"): 0 else 1
+"#, false).expect("Compilation failed");
+        eliminate_unused_functions(&mut m);
+        assert_eq!(verify_module(&m), Ok(()));
+
+        assert_eq!(m.globals["test::MAGIC"].init, Some(Constant::Bytes(vec![0x7f, b'E', b'L', b'F'])));
+        assert!(format!("{}", m.functions["test::is_elf"]).contains("subslice: range out of bounds"));
+        assert!(format!("{}", m.functions["test::is_elf"]).contains("subslice: invalid range, start is larger than end"));
+        assert!(format!("{}", m.functions["test::main"]).contains("(bytes \\x7fELF\\x02)"));
+    }
+
+    #[test]
+    fn test_void_main_returns_exit_code()
+    {
+        use ast::Type;
+        use bytecode::instruction::{Instruction, Operand};
+
+        let m = generate_byte_code(r#"
+fn main() -> void:
+    var x = 5
+    if x > 3:
+        return
+    x += 1
+"#, false).expect("Compilation failed");
+
+        let main = &m.functions["test::main"];
+        assert_eq!(main.sig.return_type, Type::Int(IntSize::I32));
+        let returns: Vec<&Instruction> = main.blocks.values()
+            .flat_map(|bb| bb.instructions.iter())
+            .filter(|instr| match **instr {
+                Instruction::Return(_) | Instruction::ReturnVoid => true,
+                _ => false,
+            })
+            .collect();
+        assert_eq!(returns.len(), 2);
+        assert!(returns.iter().all(|instr| **instr == Instruction::Return(Operand::const_int(0, IntSize::I32))));
+    }
+
+    #[test]
+    fn test_pattern_bindings_in_match_case()
+    {
+        let m = generate_byte_code(r#"
+fn sum(v: int[]) -> int:
+    match v:
+        [] => 0
+        [head | tail] => head + sum(tail)
+
+fn first(v: int[]) -> int:
+    match v:
+        [head | tail] => head
+        _ => 0
+
+fn main() -> int: sum([1, 2]) + first([3])
+"#, false).expect("Compilation failed");
+
+        // Head and tail are extracted in the match case block, not before the length check, and first never computes its unused tail
+        assert_ir_matches_file(&function_dumps(&m, &["test::sum", "test::first"]), "testcode/golden/match_bindings.bc");
+    }
+
+    #[test]
+    fn test_discard_bindings()
+    {
+        let m = generate_byte_code(r#"
+fn third(_: int, _: bool, c: int) -> int: c
+
+fn count(v: int[]) -> int:
+    var n = 0
+    for _ in v:
+        n += 1
+    n
+
+fn rest(v: int[]) -> int:
+    match v:
+        [_ | tail] => count(tail)
+        _ => 0
+
+fn has(o: ?int) -> int:
+    match o:
+        ?_ => 1
+        nil => 0
+
+fn main() -> int:
+    let _ = third(1, true, 2)
+    third(1, false, count([1, 2])) + rest([1, 2]) + has(nil)
+"#, false).expect("Compilation failed");
+
+        // A binding named _ evaluates its value, but never gets a variable
+        for name in &["test::third", "test::count", "test::rest", "test::has", "test::main"] {
+            let func = m.get_function(name).expect("Unknown function");
+            let mut has_discard_var = false;
+            func.for_each_instruction(|instr| {
+                has_discard_var = instr.vars().iter().any(|v| v.name == "_");
+                !has_discard_var
+            });
+            assert!(!has_discard_var, "{} has a variable named _", name);
+        }
+    }
+
+    #[test]
+    fn test_dense_match_uses_switch()
+    {
+        use itertools::free::join;
+        use bytecode::eliminate_unused_functions;
+
+        let cases: Vec<String> = (0..20).map(|i| format!("C{}", i)).collect();
+        let prog = format!(r#"
+enum Big:
+    {}
+
+fn big(b: Big) -> int:
+    match b:
+        {}
+
+fn sparse(i: int) -> int:
+    match i:
+        1 => 1
+        100 => 2
+        1000 => 3
+        10000 => 4
+        _ => 0
+
+fn letter(c: char) -> int:
+    match c:
+        'a' => 1
+        'b' => 2
+        'c' => 3
+        'd' => 4
+        _ => 0
+
+fn main() -> int: big(C7) + sparse(100) + letter('c')
+"#,
+            join(cases.iter(), "\n    "),
+            join(cases.iter().enumerate().map(|(i, c)| format!("{} => {}", c, i)), "\n        "));
+
+        let mut m = generate_byte_code(&prog, false).expect("Compilation failed");
+        eliminate_unused_functions(&mut m);
+
+        // big is a single switch, sparse values, and patterns which are not integer constants, keep the chain of compares
+        assert_ir_matches_file(&function_dumps(&m, &["test::big", "test::sparse", "test::letter"]), "testcode/golden/match_switch.bc");
+    }
+
+    #[test]
+    fn test_is_pattern_lowering()
+    {
+        let m = generate_byte_code(r#"
+enum Color:
+    Red
+    Green
+    Blue
+
+fn is_five(x: int) -> bool: x is 5
+fn equals_five(x: int) -> bool: x == 5
+fn is_digit(c: char) -> bool: c is '0'..='9'
+fn is_warm(c: Color) -> bool: c is Red | Green
+
+fn main() -> int:
+    if is_five(5) && equals_five(5) && is_digit('3') && is_warm(Red): 0 else 1
+"#, false).expect("Compilation failed");
+
+        let blocks = |name: &str| m.get_function(name).expect("Unknown function").blocks.len();
+
+        // A literal or range pattern is a compare, without any branches
+        assert_eq!(blocks("test::is_five"), blocks("test::equals_five"));
+        assert_eq!(blocks("test::is_digit"), blocks("test::equals_five"));
+        assert!(blocks("test::is_warm") > blocks("test::equals_five"));
+    }
+}
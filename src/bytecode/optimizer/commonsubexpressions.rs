@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+use bytecode::function::{BasicBlock, ByteCodeFunction, Var};
+use bytecode::instruction::{Instruction, Operand};
+
+// Variables written to by an instruction, including objects modified in place
+fn written_vars(instr: &Instruction) -> Vec<&Var>
+{
+    match *instr
+    {
+        Instruction::Store{ref dst, ..} |
+        Instruction::Load{ref dst, ..} |
+        Instruction::LoadMember{ref dst, ..} |
+        Instruction::AddressOf{ref dst, ..} |
+        Instruction::AddressOfMember{ref dst, ..} |
+        Instruction::GetProperty{ref dst, ..} |
+        Instruction::UnaryOp{ref dst, ..} |
+        Instruction::BinaryOp{ref dst, ..} |
+        Instruction::CheckedBinaryOp{ref dst, ..} |
+        Instruction::Select{ref dst, ..} |
+        Instruction::Slice{ref dst, ..} |
+        Instruction::MakeSlice{ref dst, ..} |
+        Instruction::MakeInterface{ref dst, ..} |
+        Instruction::LoadInterfaceMethod{ref dst, ..} |
+        Instruction::Cast{ref dst, ..} |
+        Instruction::BindArgument{ref dst, ..} |
+        Instruction::LoadOptionalFlag{ref dst, ..} => vec![dst],
+
+        Instruction::Call{ref dst, ..} => dst.iter().collect(),
+
+        Instruction::StoreMember{ref obj, ..} |
+        Instruction::SetProperty{ref obj, ..} => vec![obj],
+
+        Instruction::StoreNil(ref v) |
+        Instruction::StackAlloc(ref v) |
+        Instruction::HeapAlloc(ref v) |
+        Instruction::Delete(ref v) => vec![v],
+
+        Instruction::StartScope |
+        Instruction::EndScope |
+        Instruction::Return(_) |
+        Instruction::ReturnVoid |
+        Instruction::Branch(_) |
+        Instruction::BranchIf{..} |
+        Instruction::Switch{..} => Vec::new(),
+    }
+}
+
+fn address_of_operand(op: &Operand) -> Option<&Var>
+{
+    if let Operand::AddressOf(ref v) = *op {
+        Some(v)
+    } else {
+        None
+    }
+}
+
+// Variables which might be modified through a pointer, or which live in memory
+fn address_taken_vars(instr: &Instruction) -> Vec<&Var>
+{
+    match *instr
+    {
+        Instruction::Store{ref src, ..} |
+        Instruction::UnaryOp{ref src, ..} |
+        Instruction::Cast{ref src, ..} |
+        Instruction::BindArgument{ref src, ..} |
+        Instruction::StoreMember{ref src, ..} |
+        Instruction::Return(ref src) |
+        Instruction::BranchIf{cond: ref src, ..} |
+        Instruction::Switch{cond: ref src, ..} |
+        Instruction::MakeInterface{data: ref src, ..} => address_of_operand(src).into_iter().collect(),
+
+        Instruction::BinaryOp{ref left, ref right, ..} |
+        Instruction::CheckedBinaryOp{ref left, ref right, ..} =>
+            address_of_operand(left).into_iter().chain(address_of_operand(right)).collect(),
+
+        Instruction::Select{ref cond, ref on_true, ref on_false, ..} =>
+            address_of_operand(cond).into_iter().chain(address_of_operand(on_true)).chain(address_of_operand(on_false)).collect(),
+
+        Instruction::Call{ref args, ..} => args.iter().filter_map(address_of_operand).collect(),
+
+        Instruction::AddressOf{ref obj, ..} |
+        Instruction::AddressOfMember{ref obj, ..} |
+        Instruction::Slice{src: ref obj, ..} |
+        Instruction::MakeSlice{data: ref obj, ..} |
+        Instruction::StackAlloc(ref obj) |
+        Instruction::HeapAlloc(ref obj) => vec![obj],
+
+        _ => Vec::new(),
+    }
+}
+
+// Instructions which compute a value without side effects, returns the destination and a key describing the computation
+fn pure_expression(instr: &Instruction) -> Option<(&Var, String)>
+{
+    match *instr
+    {
+        Instruction::Store{ref dst, src: Operand::Const(ref c), ..} =>
+            Some((dst, format!("const {} {}", dst.typ, c))),
+        Instruction::Load{ref dst, ref ptr} =>
+            Some((dst, format!("load {} {}", dst.typ, ptr))),
+        Instruction::LoadMember{ref dst, ref obj, ref member_index} =>
+            Some((dst, format!("loadm {} {}.{}", dst.typ, obj, member_index))),
+        Instruction::GetProperty{ref dst, ref obj, ref prop} =>
+            Some((dst, format!("getp {} {}.{}", dst.typ, obj, prop))),
+        Instruction::LoadOptionalFlag{ref dst, ref obj} =>
+            Some((dst, format!("loadoptf {} {}", dst.typ, obj))),
+        Instruction::UnaryOp{ref dst, ref op, ref src} =>
+            Some((dst, format!("uop {} {} {}", dst.typ, op, src))),
+        Instruction::BinaryOp{ref dst, ref op, ref left, ref right} =>
+            Some((dst, format!("bop {} {} {} {}", dst.typ, op, left, right))),
+        // If the first one didn't overflow, the second one won't either
+        Instruction::CheckedBinaryOp{ref dst, ref op, ref left, ref right, ..} =>
+            Some((dst, format!("cbop {} {} {} {}", dst.typ, op, left, right))),
+        Instruction::Select{ref dst, ref cond, ref on_true, ref on_false} =>
+            Some((dst, format!("select {} {} {} {}", dst.typ, cond, on_true, on_false))),
+        Instruction::Cast{ref dst, ref src} =>
+            Some((dst, format!("cast {} {}", dst.typ, src))),
+        _ => None,
+    }
+}
+
+fn rename_var(v: &mut Var, renames: &HashMap<String, Var>)
+{
+    if let Some(replacement) = renames.get(&v.name) {
+        *v = replacement.clone();
+    }
+}
+
+fn rename_operand(op: &mut Operand, renames: &HashMap<String, Var>)
+{
+    match *op
+    {
+        Operand::Var(ref mut v) |
+        Operand::AddressOf(ref mut v) |
+        Operand::Dereference(ref mut v) => rename_var(v, renames),
+        _ => (),
+    }
+}
+
+fn rename_vars(instr: &mut Instruction, renames: &HashMap<String, Var>)
+{
+    match *instr
+    {
+        Instruction::Store{ref mut dst, ref mut src, ..} |
+        Instruction::UnaryOp{ref mut dst, ref mut src, ..} |
+        Instruction::Cast{ref mut dst, ref mut src} |
+        Instruction::BindArgument{ref mut dst, ref mut src, ..} => {
+            rename_var(dst, renames);
+            rename_operand(src, renames);
+        }
+
+        Instruction::Load{ref mut dst, ptr: ref mut obj} |
+        Instruction::AddressOf{ref mut dst, ref mut obj} |
+        Instruction::GetProperty{ref mut dst, ref mut obj, ..} |
+        Instruction::LoadOptionalFlag{ref mut dst, ref mut obj} |
+        Instruction::LoadInterfaceMethod{ref mut dst, ref mut obj, ..} => {
+            rename_var(dst, renames);
+            rename_var(obj, renames);
+        }
+
+        Instruction::LoadMember{ref mut dst, ref mut obj, ref mut member_index} |
+        Instruction::AddressOfMember{ref mut dst, ref mut obj, ref mut member_index} => {
+            rename_var(dst, renames);
+            rename_var(obj, renames);
+            rename_operand(member_index, renames);
+        }
+
+        Instruction::StoreMember{ref mut obj, ref mut member_index, ref mut src, ..} => {
+            rename_var(obj, renames);
+            rename_operand(member_index, renames);
+            rename_operand(src, renames);
+        }
+
+        Instruction::BinaryOp{ref mut dst, ref mut left, ref mut right, ..} |
+        Instruction::CheckedBinaryOp{ref mut dst, ref mut left, ref mut right, ..} => {
+            rename_var(dst, renames);
+            rename_operand(left, renames);
+            rename_operand(right, renames);
+        }
+
+        Instruction::Select{ref mut dst, ref mut cond, ref mut on_true, ref mut on_false} => {
+            rename_var(dst, renames);
+            rename_operand(cond, renames);
+            rename_operand(on_true, renames);
+            rename_operand(on_false, renames);
+        }
+
+        Instruction::Call{ref mut dst, ref mut args, ..} => {
+            if let Some(ref mut dst) = *dst {
+                rename_var(dst, renames);
+            }
+
+            for a in args {
+                rename_operand(a, renames);
+            }
+        }
+
+        Instruction::Slice{ref mut dst, ref mut src, ref mut start, ref mut len} => {
+            rename_var(dst, renames);
+            rename_var(src, renames);
+            rename_operand(start, renames);
+            rename_operand(len, renames);
+        }
+
+        Instruction::MakeSlice{ref mut dst, ref mut data, ref mut len} => {
+            rename_var(dst, renames);
+            rename_var(data, renames);
+            rename_var(len, renames);
+        }
+
+        Instruction::MakeInterface{ref mut dst, ref mut data, ref mut methods} => {
+            rename_var(dst, renames);
+            rename_operand(data, renames);
+            for m in methods {
+                rename_operand(m, renames);
+            }
+        }
+
+        Instruction::SetProperty{obj: ref mut v, ..} |
+        Instruction::StoreNil(ref mut v) |
+        Instruction::StackAlloc(ref mut v) |
+        Instruction::HeapAlloc(ref mut v) |
+        Instruction::Delete(ref mut v) => rename_var(v, renames),
+
+        Instruction::Return(ref mut op) |
+        Instruction::BranchIf{cond: ref mut op, ..} |
+        Instruction::Switch{cond: ref mut op, ..} => rename_operand(op, renames),
+
+        Instruction::StartScope |
+        Instruction::EndScope |
+        Instruction::ReturnVoid |
+        Instruction::Branch(_) => (),
+    }
+}
+
+// Variables which are only assigned once, and never accessed through a pointer.
+// The value of these cannot change once computed, so they can be reused.
+// Mutable globals already have a value when the function is entered, so they never qualify.
+fn single_assignment_vars(func: &ByteCodeFunction, mutable_globals: &HashSet<String>) -> HashMap<String, bool>
+{
+    let mut assignments: HashMap<String, usize> = mutable_globals.iter()
+        .map(|name| (name.clone(), 1))
+        .collect();
+    let mut address_taken = Vec::new();
+    func.for_each_instruction(|instr| {
+        for v in written_vars(instr) {
+            *assignments.entry(v.name.clone()).or_insert(0) += 1;
+        }
+
+        for v in address_taken_vars(instr) {
+            address_taken.push(v.name.clone());
+        }
+        true
+    });
+
+    assignments.into_iter()
+        .map(|(name, count)| {
+            let single = count == 1 && !address_taken.contains(&name);
+            (name, single)
+        })
+        .collect()
+}
+
+fn may_write_memory(instr: &Instruction) -> bool
+{
+    match *instr
+    {
+        Instruction::StoreMember{..} |
+        Instruction::SetProperty{..} |
+        Instruction::StoreNil(_) |
+        Instruction::Call{..} |
+        Instruction::Delete(_) |
+        Instruction::HeapAlloc(_) |
+        Instruction::StackAlloc(_) |
+        Instruction::Slice{..} |
+        Instruction::MakeSlice{..} |
+        Instruction::MakeInterface{..} |
+        Instruction::StartScope |
+        Instruction::EndScope => true,
+        _ => false,
+    }
+}
+
+fn eliminate_in_block(bb: &mut BasicBlock, single_assignment: &HashMap<String, bool>, renames: &mut HashMap<String, Var>)
+{
+    let is_single_assignment = |v: &Var| single_assignment.get(&v.name).cloned().unwrap_or(false);
+    let mut available: HashMap<String, Var> = HashMap::new();
+    let mut idx = 0;
+    while idx < bb.instructions.len() {
+        rename_vars(&mut bb.instructions[idx], renames);
+
+        let duplicate = match pure_expression(&bb.instructions[idx]) {
+            Some((dst, ref key)) if is_single_assignment(dst) => {
+                match available.get(key) {
+                    Some(original) => {
+                        renames.insert(dst.name.clone(), original.clone());
+                        true
+                    }
+                    None => {
+                        available.insert(key.clone(), dst.clone());
+                        false
+                    }
+                }
+            }
+
+            _ => {
+                // Anything else might change a value we depend on, so forget everything.
+                // Only initializing a single assignment variable is harmless.
+                let instr = &bb.instructions[idx];
+                if !written_vars(instr).into_iter().all(&is_single_assignment) || may_write_memory(instr) {
+                    available.clear();
+                }
+                false
+            }
+        };
+
+        if duplicate {
+            bb.instructions.remove(idx);
+        } else {
+            idx += 1;
+        }
+    }
+}
+
+// Local common subexpression elimination, a pure computation which is repeated in the same basic block
+// is replaced by the result of the first one.
+pub fn eliminate_common_subexpressions(func: &mut ByteCodeFunction, mutable_globals: &HashSet<String>)
+{
+    let single_assignment = single_assignment_vars(func, mutable_globals);
+    let mut renames = HashMap::new();
+    for bb in func.blocks.values_mut() {
+        eliminate_in_block(bb, &single_assignment, &mut renames);
+    }
+
+    if !renames.is_empty() {
+        // Uses in other blocks
+        for bb in func.blocks.values_mut() {
+            for instr in &mut bb.instructions {
+                rename_vars(instr, &renames);
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use bytecode::function::{BasicBlock, BasicBlockRef, ByteCodeFunction};
 use bytecode::instruction::Instruction;
 
@@ -33,6 +34,16 @@ fn replace_branch_target(func: &mut ByteCodeFunction, to_replace: BasicBlockRef,
                     *on_false = replacement
                 }
             },
+            Instruction::Switch{ref mut cases, ref mut default, ..} => {
+                for &mut (_, ref mut bb_ref) in cases.iter_mut() {
+                    if *bb_ref == to_replace {
+                        *bb_ref = replacement;
+                    }
+                }
+                if *default == to_replace {
+                    *default = replacement;
+                }
+            },
             _ => (),
         }
         true
@@ -52,7 +63,18 @@ pub fn remove_empty_blocks(func: &mut ByteCodeFunction)
         func.blocks.remove(&to_replace);
     }
 
-    for &(to_replace, replacement) in &candidates {
+    // An empty block can branch to another empty block, so follow the chain to the first block which remains
+    let replacements: HashMap<BasicBlockRef, BasicBlockRef> = candidates.iter().cloned().collect();
+    for &(to_replace, mut replacement) in &candidates {
+        let mut steps = 0;
+        while let Some(next) = replacements.get(&replacement) {
+            replacement = *next;
+            steps += 1;
+            if steps > replacements.len() {
+                break;
+            }
+        }
+
         replace_branch_target(func, to_replace, replacement);
     }
 
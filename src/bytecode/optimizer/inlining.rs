@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use ast::{Argument, Type};
+use bytecode::{ByteCodeModule, ByteCodeGlobal};
+use bytecode::function::{BasicBlock, BasicBlockRef, ByteCodeFunction, Var, bb_name};
+use bytecode::instruction::{Instruction, Operand, var_op};
+use compileerror::print_message;
+use super::OptimizationLevel;
+
+// Functions with at most this many instructions are inlined without an inline attribute
+const INLINE_SIZE_LIMIT: usize = 16;
+// No more calls are inlined into a function once it has this many instructions
+const MAX_FUNCTION_SIZE: usize = 4096;
+
+// Copy of the body of a function which calls get replaced with
+struct InlineCandidate
+{
+    args: Vec<Argument>,
+    return_type: Type,
+    blocks: Vec<BasicBlock>,
+    block_refs: Vec<BasicBlockRef>,
+}
+
+impl InlineCandidate
+{
+    fn new(func: &ByteCodeFunction) -> InlineCandidate
+    {
+        InlineCandidate{
+            args: func.sig.args.clone(),
+            return_type: func.sig.return_type.clone(),
+            blocks: func.blocks.values().cloned().collect(),
+            block_refs: func.blocks.keys().cloned().collect(),
+        }
+    }
+}
+
+fn instruction_count(func: &ByteCodeFunction) -> usize
+{
+    func.blocks.values().map(|bb| bb.instructions.len()).sum()
+}
+
+fn direct_calls(func: &ByteCodeFunction) -> Vec<String>
+{
+    let mut calls = Vec::new();
+    func.for_each_instruction(|instr: &Instruction| {
+        if let Instruction::Call{ref func, ..} = *instr {
+            calls.push(func.clone());
+        }
+        true
+    });
+    calls
+}
+
+// Whether the function can reach itself through direct calls
+fn is_recursive(module: &ByteCodeModule, func: &ByteCodeFunction) -> bool
+{
+    let mut visited = HashSet::new();
+    let mut to_visit = direct_calls(func);
+    while let Some(name) = to_visit.pop() {
+        if name == func.sig.name {
+            return true;
+        }
+
+        if visited.insert(name.clone()) {
+            if let Some(callee) = module.get_function(&name) {
+                to_visit.extend(direct_calls(callee));
+            }
+        }
+    }
+
+    false
+}
+
+fn is_function_type(typ: &Type) -> bool
+{
+    if let Type::Func(_) = *typ {true} else {false}
+}
+
+// Functions which create or call function values, for example lambdas
+fn uses_function_values(func: &ByteCodeFunction) -> bool
+{
+    if func.sig.args.iter().any(|arg| is_function_type(&arg.typ)) {
+        return true;
+    }
+
+    let mut uses_func = false;
+    func.for_each_instruction(|instr: &Instruction| {
+        uses_func = instr.vars().iter().any(|v| is_function_type(&v.typ)) ||
+            instr.operands().iter().any(|op| if let Operand::Func(_) = **op {true} else {false});
+        !uses_func
+    });
+    uses_func
+}
+
+fn can_inline(module: &ByteCodeModule, func: &ByteCodeFunction, lvl: OptimizationLevel) -> bool
+{
+    if func.external || func.sig.name == module.main_function_name() || uses_function_values(func) {
+        return false;
+    }
+
+    if !func.sig.inline && (lvl != OptimizationLevel::Normal || instruction_count(func) > INLINE_SIZE_LIMIT) {
+        return false;
+    }
+
+    if is_recursive(module, func) {
+        if func.sig.inline {
+            print_message(&format!("Warning: recursive function {} cannot be inlined", func.sig.name), &func.sig.span);
+        }
+        return false;
+    }
+
+    true
+}
+
+// Block names end with their number, which changes when blocks are moved around
+fn block_purpose(name: &str) -> &str
+{
+    name.trim_end_matches(|c: char| c.is_ascii_digit())
+}
+
+fn remap_branch_targets(instr: &mut Instruction, refs: &HashMap<BasicBlockRef, BasicBlockRef>)
+{
+    match *instr
+    {
+        Instruction::Branch(ref mut bb_ref) => *bb_ref = refs[bb_ref],
+        Instruction::BranchIf{ref mut on_true, ref mut on_false, ..} => {
+            *on_true = refs[on_true];
+            *on_false = refs[on_false];
+        },
+        Instruction::Switch{ref mut cases, ref mut default, ..} => {
+            for &mut (_, ref mut bb_ref) in cases.iter_mut() {
+                *bb_ref = refs[bb_ref];
+            }
+            *default = refs[default];
+        },
+        _ => (),
+    }
+}
+
+// Replace the call at call_idx in call_bb with the body of the callee.
+// The callee's blocks are placed between the part of call_bb before the call and the part after it,
+// so they are generated in the same order as the blocks of a nested expression would be.
+fn inline_call(
+    func: &mut ByteCodeFunction,
+    call_bb: BasicBlockRef,
+    call_idx: usize,
+    callee: &InlineCandidate,
+    site: usize,
+    globals: &HashMap<String, ByteCodeGlobal>)
+{
+    let old_blocks = mem::replace(&mut func.blocks, BTreeMap::new());
+    let call_pos = old_blocks.keys().position(|bb_ref| *bb_ref == call_bb).expect("Unknown basic block");
+    let callee_start = call_pos + 1;
+    let continue_bb = callee_start + callee.blocks.len();
+
+    let caller_refs: HashMap<BasicBlockRef, BasicBlockRef> = old_blocks.keys()
+        .enumerate()
+        .map(|(pos, bb_ref)| (*bb_ref, if pos <= call_pos {pos} else {pos + callee.blocks.len() + 1}))
+        .collect();
+    let callee_refs: HashMap<BasicBlockRef, BasicBlockRef> = callee.block_refs.iter()
+        .enumerate()
+        .map(|(pos, bb_ref)| (*bb_ref, callee_start + pos))
+        .collect();
+
+    // Every variable of the callee gets a name which is unique in the caller
+    let suffix = format!(".inline{}", site);
+    let rename = |v: &mut Var| if !globals.contains_key(&v.name) {
+        v.name.push_str(&suffix);
+    };
+
+    let mut blocks = BTreeMap::new();
+    for (bb_ref, mut bb) in old_blocks {
+        let new_ref = caller_refs[&bb_ref];
+        bb.name = bb_name(new_ref, block_purpose(&bb.name));
+        if bb_ref != call_bb {
+            for instr in &mut bb.instructions {
+                remap_branch_targets(instr, &caller_refs);
+            }
+            blocks.insert(new_ref, bb);
+            continue;
+        }
+
+        let mut tail = bb.instructions.split_off(call_idx);
+        let (dst, args, span) = match tail.remove(0) {
+            Instruction::Call{dst, args, span, ..} => (dst, args, span),
+            _ => panic!("Internal Compiler Error: Expecting a call instruction"),
+        };
+
+        // The arguments and the result are outside the callee's scope, because the arguments
+        // can be the first use of a variable of the caller, and the result is needed after it
+        let result = dst.as_ref().map(|_| Var::named(&format!("$result{}", suffix), callee.return_type.clone()));
+        if let Some(ref result) = result {
+            bb.instructions.push(Instruction::StackAlloc(result.clone()));
+        }
+
+        for (arg, src) in callee.args.iter().zip(args) {
+            let mut dst = Var::named(&arg.name, arg.typ.clone());
+            rename(&mut dst);
+            bb.instructions.push(Instruction::BindArgument{dst, src, mutable: arg.mutable, span: span.clone()});
+        }
+        bb.instructions.push(Instruction::StartScope);
+        bb.instructions.push(Instruction::Branch(callee_start));
+        blocks.insert(new_ref, bb);
+
+        for (pos, callee_bb) in callee.blocks.iter().enumerate() {
+            let purpose = if pos == 0 {"inline"} else {block_purpose(&callee_bb.name)};
+            let mut inlined_bb = BasicBlock::new(bb_name(callee_start + pos, purpose));
+            for instr in &callee_bb.instructions {
+                let mut instr = instr.clone();
+                for v in instr.vars_mut() {
+                    rename(v);
+                }
+
+                match instr
+                {
+                    Instruction::Return(src) => {
+                        if let Some(ref result) = result {
+                            inlined_bb.add(Instruction::Store{dst: result.clone(), src, span: span.clone()});
+                        }
+                        inlined_bb.add(Instruction::Branch(continue_bb));
+                    },
+
+                    Instruction::ReturnVoid => inlined_bb.add(Instruction::Branch(continue_bb)),
+
+                    mut instr => {
+                        remap_branch_targets(&mut instr, &callee_refs);
+                        inlined_bb.add(instr);
+                    },
+                }
+            }
+            blocks.insert(callee_start + pos, inlined_bb);
+        }
+
+        let mut continue_block = BasicBlock::new(bb_name(continue_bb, "inline_end"));
+        continue_block.add(Instruction::EndScope);
+        if let (Some(dst), Some(result)) = (dst, result) {
+            continue_block.add(Instruction::Store{dst, src: var_op(&result), span});
+        }
+
+        for mut instr in tail {
+            remap_branch_targets(&mut instr, &caller_refs);
+            continue_block.add(instr);
+        }
+        blocks.insert(continue_bb, continue_block);
+    }
+
+    func.replace_blocks(blocks);
+}
+
+fn find_inlinable_call(func: &ByteCodeFunction, candidates: &HashMap<String, InlineCandidate>) -> Option<(BasicBlockRef, usize, String)>
+{
+    for (bb_ref, bb) in &func.blocks {
+        for (idx, instr) in bb.instructions.iter().enumerate() {
+            if let Instruction::Call{ref func, ..} = *instr {
+                if candidates.contains_key(func) {
+                    return Some((*bb_ref, idx, func.clone()));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// Replace calls to small functions and functions with the inline attribute by the body of the function.
+// Recursive functions and calls through function pointers are never inlined.
+pub fn inline_functions(module: &mut ByteCodeModule, lvl: OptimizationLevel)
+{
+    let candidates: HashMap<String, InlineCandidate> = module.functions.values()
+        .filter(|func| can_inline(module, func, lvl))
+        .map(|func| (func.sig.name.clone(), InlineCandidate::new(func)))
+        .collect();
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    let globals = &module.globals;
+    for func in module.functions.values_mut() {
+        if func.external {
+            continue;
+        }
+
+        // Inlined bodies can contain calls which can be inlined as well, recursion cannot happen,
+        // so this stops when there is nothing left to inline or the function is too large.
+        let mut site = 0;
+        while instruction_count(func) < MAX_FUNCTION_SIZE {
+            match find_inlinable_call(func, &candidates)
+            {
+                Some((call_bb, call_idx, name)) => {
+                    inline_call(func, call_bb, call_idx, &candidates[&name], site, globals);
+                    site += 1;
+                },
+                None => break,
+            }
+        }
+    }
+}
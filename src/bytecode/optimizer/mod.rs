@@ -1,42 +1,50 @@
 use bytecode::{ByteCodeModule};
-use bytecode::function::{ByteCodeFunction};
 
 mod emptyblocks;
 mod unusedfunctions;
 mod returnvalueoptimization;
+mod commonsubexpressions;
+mod inlining;
+mod passmanager;
 
-use self::emptyblocks::remove_empty_blocks;
-use self::unusedfunctions::eliminate_unused_functions;
-use self::returnvalueoptimization::return_value_optimization;
+pub use self::unusedfunctions::eliminate_unused_functions;
+pub use self::returnvalueoptimization::RETURN_VALUE_ARG;
+use self::passmanager::PassManager;
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum OptimizationLevel
 {
-    Minimal,
-    Normal,
+    Off,        // -O0: only what code generation needs
+    Minimal,    // -O1: cheap passes
+    Normal,     // -O2: all passes
 }
 
-pub fn optimize_function(func: &mut ByteCodeFunction, _lvl: OptimizationLevel)
+impl OptimizationLevel
 {
-    remove_empty_blocks(func);
+    pub fn parse(lvl: &str) -> Option<OptimizationLevel>
+    {
+        match lvl
+        {
+            "0" => Some(OptimizationLevel::Off),
+            "1" => Some(OptimizationLevel::Minimal),
+            "2" => Some(OptimizationLevel::Normal),
+            _ => None,
+        }
+    }
 }
 
 pub fn optimize_module(module: &mut ByteCodeModule, lvl: OptimizationLevel)
 {
-    eliminate_unused_functions(module);
-    return_value_optimization(module);
-    for func in module.functions.values_mut() {
-        if !func.external {
-            optimize_function(func, lvl);
-        }
-    }
+    PassManager::for_level(lvl).run(module);
 }
 
 #[cfg(test)]
 mod test
 {
     use super::*;
+    use super::emptyblocks::remove_empty_blocks;
     use bytecode::test::generate_byte_code;
+    use bytecode::verify_module;
     use bytecode::instruction::Instruction;
     use bytecode::function::ByteCodeFunction;
     use ast::{sig, Type};
@@ -47,15 +55,15 @@ mod test
     {
         let func_sig = sig("foo", Type::Void, vec![], Span::default());
         let mut func = ByteCodeFunction::new(&func_sig, false);
-        let bb1 = func.create_basic_block();
-        let bb2 = func.create_basic_block();
+        let bb1 = func.create_basic_block("block");
+        let bb2 = func.create_basic_block("block");
         func.add(Instruction::Branch(bb1));
         func.set_current_bb(bb1);
         func.add(Instruction::Branch(bb2));
         func.set_current_bb(bb2);
         func.add(Instruction::ReturnVoid);
 
-        optimize_function(&mut func, OptimizationLevel::Normal);
+        remove_empty_blocks(&mut func);
         assert!(func.blocks.get(&bb1).is_none());
         assert!(func.blocks.get(&bb2).is_some());
 
@@ -74,10 +82,263 @@ mod test
         assert!(m.get_function("test::bar").is_some());
         assert!(m.get_function("test::main").is_some());
 
-        optimize_module(&mut m, OptimizationLevel::Normal);
+        eliminate_unused_functions(&mut m);
 
         assert!(m.get_function("test::foo").is_none());
         assert!(m.get_function("test::bar").is_some());
         assert!(m.get_function("test::main").is_some());
     }
+
+    #[test]
+    fn test_function_used_as_value_is_kept()
+    {
+        let mut m = generate_byte_code(r#"
+            struct Op:
+                f: fn(int) -> int
+
+            fn double(a: int) -> int: a * 2
+            fn triple(a: int) -> int: a * 3
+            fn quad(a: int) -> int: a * 4
+            fn unused(a: int) -> int: a * 5
+            fn pick() -> fn(int) -> int: quad
+            fn apply(f: fn(int) -> int, a: int) -> int: f(a)
+            fn main() -> int:
+                let op = Op{triple}
+                let f = pick()
+                apply(double, 4) + f(2)
+        "#, false).expect("Parsing succeeded");
+
+        eliminate_unused_functions(&mut m);
+
+        assert!(m.get_function("test::double").is_some());
+        assert!(m.get_function("test::triple").is_some());
+        assert!(m.get_function("test::quad").is_some());
+        assert!(m.get_function("test::apply").is_some());
+        assert!(m.get_function("test::unused").is_none());
+    }
+
+    #[test]
+    fn test_function_elimination_without_main()
+    {
+        let mut m = generate_byte_code(r#"
+            fn foo() -> int: 6
+            fn bar() -> int: 7
+        "#, false).expect("Parsing succeeded");
+
+        eliminate_unused_functions(&mut m);
+
+        assert!(m.get_function("test::foo").is_some());
+        assert!(m.get_function("test::bar").is_some());
+    }
+
+    fn count_instructions(func: &ByteCodeFunction, name: &str) -> usize
+    {
+        let mut count = 0;
+        func.for_each_instruction(|instr: &Instruction| {
+            if format!("{}", instr).trim().starts_with(name) {
+                count += 1;
+            }
+            true
+        });
+        count
+    }
+
+    #[test]
+    fn test_common_subexpression_elimination()
+    {
+        let mut m = generate_byte_code(r#"
+struct Vec2:
+    x: int
+    y: int
+
+fn len2(a: Vec2) -> int: a.x * a.x + a.y * a.y
+
+fn main() -> int: len2(Vec2{3, 4})
+        "#, false).expect("Parsing succeeded");
+
+        optimize_module(&mut m, OptimizationLevel::Normal);
+        let func = m.get_function("test::len2").expect("len2 must exist");
+        assert!(count_instructions(func, "loadm") == 2);
+        assert!(count_instructions(func, "cbop") == 3);
+    }
+
+    #[test]
+    fn test_common_subexpression_elimination_blocked_by_call()
+    {
+        let mut m = generate_byte_code(r#"
+struct Vec2:
+    x: int
+    y: int
+
+fn reset(var v: *Vec2) -> int:
+    v.x = 0
+    0
+
+fn foo(a: *Vec2) -> int: a.x + reset(a) + a.x
+
+fn main() -> int:
+    var v = Vec2{3, 4}
+    foo(&v)
+        "#, false).expect("Parsing succeeded");
+
+        optimize_module(&mut m, OptimizationLevel::Normal);
+        let func = m.get_function("test::foo").expect("foo must exist");
+        assert!(count_instructions(func, "loadm") == 2);
+    }
+
+    #[test]
+    fn test_common_subexpression_elimination_blocked_by_global_store()
+    {
+        let mut m = generate_byte_code(r#"
+var counter = 3
+
+fn bump() -> int:
+    let a = counter * 2
+    counter = 5
+    let b = counter * 2
+    a + b
+
+fn main() -> int: bump()
+        "#, false).expect("Parsing succeeded");
+
+        optimize_module(&mut m, OptimizationLevel::Normal);
+        let func = m.get_function("test::bump").expect("bump must exist");
+        assert!(count_instructions(func, "cbop") == 3);
+    }
+
+    fn calls(func: &ByteCodeFunction, callee: &str) -> usize
+    {
+        let mut count = 0;
+        func.for_each_instruction(|instr: &Instruction| {
+            if let Instruction::Call{ref func, ..} = *instr {
+                if func == callee {
+                    count += 1;
+                }
+            }
+            true
+        });
+        count
+    }
+
+    #[test]
+    fn test_inline_small_functions()
+    {
+        let mut m = generate_byte_code(r#"
+struct Vec2:
+    x: int
+    y: int
+
+fn Vec2.x(self) -> int: self.x
+
+fn add(a: Vec2, b: Vec2) -> Vec2: Vec2{a.x + b.x, a.y + b.y}
+
+fn main() -> int:
+    let v = add(Vec2{1, 2}, Vec2{3, 4})
+    v.x()
+        "#, false).expect("Parsing succeeded");
+
+        optimize_module(&mut m, OptimizationLevel::Normal);
+        let func = m.get_function("test::main").expect("main must exist");
+        assert!(!format!("{}", func).contains("call"));
+        assert_eq!(count_instructions(func, "bindarg"), 4);
+        assert!(verify_module(&m).is_ok());
+    }
+
+    #[test]
+    fn test_inline_attribute()
+    {
+        let prog = r#"
+#[inline]
+fn clamp(v: int, lo: int, hi: int) -> int:
+    if v < lo:
+        return lo
+    if v > hi:
+        return hi
+    v
+
+fn wrap(v: int, lo: int, hi: int) -> int:
+    let range = hi - lo + 1
+    var offset = (v - lo) % range
+    if offset < 0:
+        offset = offset + range
+    let wrapped = lo + offset
+    if wrapped > hi:
+        return hi
+    wrapped
+
+fn main() -> int: clamp(5, 0, 3) + wrap(5, 0, 3)
+        "#;
+
+        // Only functions with the inline attribute are inlined without optimization
+        let mut m = generate_byte_code(prog, false).expect("Parsing succeeded");
+        optimize_module(&mut m, OptimizationLevel::Minimal);
+        let func = m.get_function("test::main").expect("main must exist");
+        assert_eq!(calls(func, "test::clamp"), 0);
+        assert_eq!(calls(func, "test::wrap"), 1);
+        assert!(verify_module(&m).is_ok());
+
+        // Above the size limit, so wrap is still called
+        let mut m = generate_byte_code(prog, false).expect("Parsing succeeded");
+        optimize_module(&mut m, OptimizationLevel::Normal);
+        let func = m.get_function("test::main").expect("main must exist");
+        assert_eq!(calls(func, "test::clamp"), 0);
+        assert_eq!(calls(func, "test::wrap"), 1);
+    }
+
+    #[test]
+    fn test_recursive_functions_are_not_inlined()
+    {
+        let mut m = generate_byte_code(r#"
+#[inline]
+fn fac(n: int) -> int: if n <= 1: 1 else n * fac(n - 1)
+
+fn is_even(n: int) -> bool: if n == 0: true else is_odd(n - 1)
+fn is_odd(n: int) -> bool: if n == 0: false else is_even(n - 1)
+
+fn main() -> int: if is_even(4): fac(4) else 0
+        "#, false).expect("Parsing succeeded");
+
+        optimize_module(&mut m, OptimizationLevel::Normal);
+        let func = m.get_function("test::main").expect("main must exist");
+        assert_eq!(calls(func, "test::fac"), 1);
+        assert_eq!(calls(func, "test::is_even"), 1);
+        let fac = m.get_function("test::fac").expect("fac must exist");
+        assert_eq!(calls(fac, "test::fac"), 1);
+    }
+
+    #[test]
+    fn test_parse_optimization_level()
+    {
+        assert_eq!(OptimizationLevel::parse("0"), Some(OptimizationLevel::Off));
+        assert_eq!(OptimizationLevel::parse("2"), Some(OptimizationLevel::Normal));
+        assert_eq!(OptimizationLevel::parse("3"), None);
+    }
+
+    #[test]
+    fn test_no_optimization()
+    {
+        let prog = r#"
+struct Vec2:
+    x: int
+    y: int
+
+#[inline]
+fn len2(a: Vec2) -> int: a.x * a.x + a.y * a.y
+
+fn main() -> int: len2(Vec2{3, 4})
+        "#;
+
+        let mut m = generate_byte_code(prog, false).expect("Parsing succeeded");
+        optimize_module(&mut m, OptimizationLevel::Off);
+        assert!(verify_module(&m).is_ok());
+        assert_eq!(calls(m.get_function("test::main").expect("main must exist"), "test::len2"), 1);
+        let func = m.get_function("test::len2").expect("len2 must exist");
+        assert_eq!(count_instructions(func, "loadm"), 4);
+
+        let mut m = generate_byte_code(prog, false).expect("Parsing succeeded");
+        optimize_module(&mut m, OptimizationLevel::Normal);
+        assert_eq!(calls(m.get_function("test::main").expect("main must exist"), "test::len2"), 0);
+        let func = m.get_function("test::len2").expect("len2 must exist");
+        assert_eq!(count_instructions(func, "loadm"), 2);
+    }
 }
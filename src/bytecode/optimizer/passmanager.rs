@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use bytecode::{ByteCodeModule};
+use bytecode::function::{ByteCodeFunction};
+use super::OptimizationLevel;
+use super::emptyblocks::remove_empty_blocks;
+use super::returnvalueoptimization::return_value_optimization;
+use super::commonsubexpressions::eliminate_common_subexpressions;
+use super::inlining::inline_functions;
+
+// Function passes get the names of the mutable globals, which a call can change
+enum PassKind
+{
+    Module(fn(&mut ByteCodeModule)),
+    Function(fn(&mut ByteCodeFunction, &HashSet<String>)),
+}
+
+// The ordered list of passes run on a bytecode module
+pub struct PassManager
+{
+    passes: Vec<PassKind>,
+}
+
+fn inline_attribute_functions(module: &mut ByteCodeModule)
+{
+    inline_functions(module, OptimizationLevel::Minimal);
+}
+
+fn inline_small_functions(module: &mut ByteCodeModule)
+{
+    inline_functions(module, OptimizationLevel::Normal);
+}
+
+impl PassManager
+{
+    fn new() -> PassManager
+    {
+        PassManager{
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn for_level(lvl: OptimizationLevel) -> PassManager
+    {
+        let mut pm = PassManager::new();
+        // The code generator expects aggregates to be returned through a pointer, so this is needed at every level
+        pm.add_module_pass(return_value_optimization);
+        match lvl
+        {
+            OptimizationLevel::Off => (),
+            OptimizationLevel::Minimal => {
+                pm.add_module_pass(inline_attribute_functions);
+                pm.add_function_pass(|func, _| remove_empty_blocks(func));
+            },
+            OptimizationLevel::Normal => {
+                pm.add_module_pass(inline_small_functions);
+                pm.add_function_pass(|func, _| remove_empty_blocks(func));
+                pm.add_function_pass(eliminate_common_subexpressions);
+            },
+        }
+        pm
+    }
+
+    pub fn add_module_pass(&mut self, pass: fn(&mut ByteCodeModule))
+    {
+        self.passes.push(PassKind::Module(pass));
+    }
+
+    pub fn add_function_pass(&mut self, pass: fn(&mut ByteCodeFunction, &HashSet<String>))
+    {
+        self.passes.push(PassKind::Function(pass));
+    }
+
+    pub fn run(&self, module: &mut ByteCodeModule)
+    {
+        let mutable_globals: HashSet<String> = module.globals.iter()
+            .filter(|&(_, glob)| glob.mutable)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for pass in &self.passes {
+            match *pass
+            {
+                PassKind::Module(f) => f(module),
+                PassKind::Function(f) => {
+                    for func in module.functions.values_mut() {
+                        if !func.external {
+                            f(func, &mutable_globals);
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
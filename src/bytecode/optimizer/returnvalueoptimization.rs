@@ -11,20 +11,30 @@ use bytecode::{
     Operand,
 };
 
+// Functions returning an aggregate get a pointer to the return value as extra last argument
+pub const RETURN_VALUE_ARG: &str = "$ret";
+
+fn rvo_needed_for_type(typ: &Type) -> bool
+{
+    !typ.pass_by_value() && *typ != Type::Void
+}
+
 fn rvo_needed(func: &ByteCodeFunction) -> bool
 {
-    !func.sig.return_type.pass_by_value() && func.sig.return_type != Type::Void
+    rvo_needed_for_type(&func.sig.return_type)
 }
 
 fn rvo_func(func: &mut ByteCodeFunction)
 {
     let return_type_arg = ptr_type(mem::replace(&mut func.sig.return_type, Type::Void));
-    func.sig.args.push(Argument::new("$ret", return_type_arg.clone(), true, Span::default()));
+    func.sig.args.push(Argument::new(RETURN_VALUE_ARG, return_type_arg.clone(), true, Span::default()));
 
+    // A return has no span of its own, so the store of the return value gets the one of the function
+    let span = func.sig.span.clone();
     func.replace_instruction(|instr: &Instruction| {
         if let Instruction::Return(ref operand) = *instr {
             vec![
-                store_operand_instr(&Var::named("$ret", return_type_arg.clone()), operand.clone()),
+                store_operand_instr(&Var::named(RETURN_VALUE_ARG, return_type_arg.clone()), operand.clone(), &span),
                 Instruction::ReturnVoid
             ]
         } else {
@@ -37,12 +47,14 @@ fn rvo_replace_calls(bc_func: &mut ByteCodeFunction, rvo_calls: &[String])
 {
     bc_func.replace_instruction(|instr: &Instruction| {
         match *instr {
-            Instruction::Call{ref dst, ref func, ref args} if rvo_calls.contains(func) => {
+            // Calls through function pointers always use RVO when the return type needs it
+            Instruction::Call{ref dst, ref func, ref args, ref span}
+                if rvo_calls.contains(func) || dst.as_ref().map(|d| rvo_needed_for_type(&d.typ)).unwrap_or(false) => {
                 if let Some(ref dst) = *dst {
                     let mut new_args = args.clone();
                     new_args.push(Operand::AddressOf(dst.clone()));
                     vec![
-                        void_call_instr(func, new_args)
+                        void_call_instr(func, new_args, span)
                     ]
                 } else {
                     Vec::new()
@@ -1,8 +1,9 @@
 use std::collections::HashSet;
 use bytecode::{ByteCodeModule};
-use bytecode::function::{ByteCodeFunction};
+use bytecode::function::{ByteCodeFunction, Var};
 use bytecode::instruction::{Instruction, Operand};
 use compileerror::print_message;
+use ast::{Type, is_sum_type_case_constructor};
 
 
 // Find all calls recursively, and remove them from the unused_calls HashSet
@@ -15,24 +16,24 @@ fn find_used_calls(module: &ByteCodeModule, func: &ByteCodeFunction, unused_call
     };
 
     func.for_each_instruction(|instr: &Instruction| {
-        match *instr
-        {
-            Instruction::Call{ref func, ..} => {
-                handle_func(func);
-            }
+        if let Instruction::Call{ref func, ..} = *instr {
+            handle_func(func);
+        }
 
-            Instruction::Store{ref src, ..} => {
-                if let Operand::Func(ref func) = *src {
-                    handle_func(func);
-                }
+        // Functions used as a value, they can be called from anywhere the value ends up
+        for op in instr.operands() {
+            match *op {
+                Operand::Func(ref func) => handle_func(func),
+                Operand::Var(Var{ref name, typ: Type::Func(_)}) => handle_func(name),
+                _ => (),
             }
-
-            _ => ()
         }
         true
     })
 }
 
+// Remove all functions which cannot be reached from main or an exported function, modules without a main function are left alone.
+// External functions are kept as long as something still calls them.
 pub fn eliminate_unused_functions(module: &mut ByteCodeModule)
 {
     let mut unused_calls: HashSet<String> = module.functions.keys().cloned().collect();
@@ -45,10 +46,18 @@ pub fn eliminate_unused_functions(module: &mut ByteCodeModule)
         return;
     }
 
+    // Exported functions can be called from C, so they are used even when main doesn't call them
+    for func in module.functions.values().filter(|func| func.sig.export.is_some()) {
+        unused_calls.remove(&func.sig.name);
+        find_used_calls(module, func, &mut unused_calls);
+        find_used_calls(module, func, &mut unused_imported);
+    }
+
 
     for call in &unused_calls {
         module.functions
             .get(call)
+            .filter(|func| !func.external && !is_sum_type_case_constructor(&func.sig.name) && func.sig.span.file != "prelude")
             .map(|func| print_message(&format!("Warning: unused function {}", func.sig.name), &func.sig.span));
         module.functions.remove(call);
     }
@@ -0,0 +1,519 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use itertools::free::join;
+use bytecode::ByteCodeModule;
+use bytecode::function::{BasicBlockRef, ByteCodeFunction, Var};
+use bytecode::instruction::{Instruction, Operand};
+
+fn operand_var(op: &Operand) -> Option<&Var>
+{
+    match *op
+    {
+        Operand::Var(ref v) |
+        Operand::Dereference(ref v) => Some(v),
+        Operand::AddressOf(_) |
+        Operand::Const(_) |
+        Operand::Func(_) => None,
+    }
+}
+
+// Variables are allocated on first use, so anything which writes to a variable or takes its address defines it
+fn defined_vars(instr: &Instruction) -> Vec<&Var>
+{
+    let mut vars: Vec<&Var> = instr.operands().into_iter()
+        .filter_map(|op| if let Operand::AddressOf(ref v) = *op {Some(v)} else {None})
+        .collect();
+
+    match *instr
+    {
+        Instruction::Store{ref dst, ..} |
+        Instruction::Load{ref dst, ..} |
+        Instruction::LoadMember{ref dst, ..} |
+        Instruction::GetProperty{ref dst, ..} |
+        Instruction::UnaryOp{ref dst, ..} |
+        Instruction::BinaryOp{ref dst, ..} |
+        Instruction::CheckedBinaryOp{ref dst, ..} |
+        Instruction::Select{ref dst, ..} |
+        Instruction::Slice{ref dst, ..} |
+        Instruction::MakeSlice{ref dst, ..} |
+        Instruction::MakeInterface{ref dst, ..} |
+        Instruction::LoadInterfaceMethod{ref dst, ..} |
+        Instruction::Cast{ref dst, ..} |
+        Instruction::BindArgument{ref dst, ..} |
+        Instruction::LoadOptionalFlag{ref dst, ..} |
+        Instruction::StackAlloc(ref dst) |
+        Instruction::HeapAlloc(ref dst) |
+        Instruction::StoreMember{obj: ref dst, ..} |
+        Instruction::SetProperty{obj: ref dst, ..} |
+        Instruction::StoreNil(ref dst) => vars.push(dst),
+
+        Instruction::AddressOf{ref dst, ref obj} |
+        Instruction::AddressOfMember{ref dst, ref obj, ..} => {
+            vars.push(dst);
+            vars.push(obj);
+        },
+
+        Instruction::Call{dst: Some(ref dst), ..} => vars.push(dst),
+        _ => (),
+    }
+
+    vars
+}
+
+// Variables whose value is read by an instruction
+fn used_vars(instr: &Instruction) -> Vec<&Var>
+{
+    let mut vars: Vec<&Var> = instr.operands().into_iter().filter_map(operand_var).collect();
+    match *instr
+    {
+        Instruction::Load{ref ptr, ..} => vars.push(ptr),
+        Instruction::LoadMember{ref obj, ..} |
+        Instruction::GetProperty{ref obj, ..} |
+        Instruction::LoadOptionalFlag{ref obj, ..} |
+        Instruction::LoadInterfaceMethod{ref obj, ..} |
+        Instruction::Delete(ref obj) => vars.push(obj),
+        Instruction::Slice{ref src, ..} => vars.push(src),
+        Instruction::MakeSlice{ref data, ref len, ..} => {
+            vars.push(data);
+            vars.push(len);
+        },
+        _ => (),
+    }
+
+    vars
+}
+
+fn verify_blocks(func: &ByteCodeFunction, errors: &mut Vec<String>)
+{
+    match func.blocks.keys().next()
+    {
+        Some(&0) => (),
+        Some(&bb) => errors.push(format!("{}: entry block is {}, expected 0", func.sig.name, bb)),
+        None => errors.push(format!("{}: function has no blocks", func.sig.name)),
+    }
+
+    for (bb_ref, bb) in &func.blocks
+    {
+        match bb.instructions.iter().position(|i| i.is_terminator())
+        {
+            None => errors.push(format!("{}: block {} has no terminator", func.sig.name, bb.name)),
+            Some(idx) if idx + 1 != bb.instructions.len() => {
+                errors.push(format!("{}: block {} has instructions after its terminator", func.sig.name, bb.name))
+            },
+            _ => (),
+        }
+
+        for instr in &bb.instructions {
+            for target in instr.branch_targets() {
+                if !func.blocks.contains_key(&target) {
+                    errors.push(format!("{}: block {} branches to unknown block {}", func.sig.name, bb_ref, target));
+                }
+            }
+        }
+    }
+}
+
+// A variable must be defined on every path to each of its uses, unreachable blocks are not checked
+fn verify_vars(func: &ByteCodeFunction, globals: &HashSet<String>, errors: &mut Vec<String>)
+{
+    let reachable = func.reachable_blocks();
+    let entry = match reachable.iter().next()
+    {
+        Some(&entry) => entry,
+        None => return,
+    };
+
+    let mut predecessors: BTreeMap<BasicBlockRef, Vec<BasicBlockRef>> = BTreeMap::new();
+    for bb_ref in &reachable {
+        for instr in &func.blocks[bb_ref].instructions {
+            for target in instr.branch_targets() {
+                predecessors.entry(target).or_default().push(*bb_ref);
+            }
+        }
+    }
+
+    let mut arguments: HashSet<String> = func.sig.args.iter().map(|a| a.name.clone()).collect();
+    arguments.extend(globals.iter().cloned());
+
+    let defined_in_block = |bb_ref: &BasicBlockRef, mut defined: HashSet<String>| {
+        for instr in &func.blocks[bb_ref].instructions {
+            defined.extend(defined_vars(instr).into_iter().map(|v| v.name.clone()));
+        }
+        defined
+    };
+
+    // Iterate until the variables defined at the end of each block no longer change. A block which has
+    // not been visited yet defines everything, so the first pass over a loop doesn't remove anything.
+    let mut defined_at_end: BTreeMap<BasicBlockRef, HashSet<String>> = BTreeMap::new();
+    let mut changed = true;
+    while changed
+    {
+        changed = false;
+        for bb_ref in &reachable
+        {
+            let at_start = match defined_at_start(*bb_ref, entry, &arguments, &predecessors, &defined_at_end)
+            {
+                Some(at_start) => at_start,
+                None => continue,
+            };
+            let at_end = defined_in_block(bb_ref, at_start);
+            if defined_at_end.get(bb_ref) != Some(&at_end) {
+                defined_at_end.insert(*bb_ref, at_end);
+                changed = true;
+            }
+        }
+    }
+
+    for bb_ref in &reachable
+    {
+        let bb = &func.blocks[bb_ref];
+        let mut defined = defined_at_start(*bb_ref, entry, &arguments, &predecessors, &defined_at_end).unwrap_or_default();
+        for instr in &bb.instructions
+        {
+            for v in used_vars(instr).into_iter().filter(|v| !defined.contains(&v.name)) {
+                // Name a block it can be reached from without being defined
+                let undefined_from = predecessors.get(bb_ref)
+                    .and_then(|preds| preds.iter().find(|pred| !defined_at_end[pred].contains(&v.name)));
+                match undefined_from
+                {
+                    Some(pred) if *bb_ref != entry => {
+                        errors.push(format!("{}: variable {} is used in block {}, but it is not defined on the path from block {}",
+                            func.sig.name, v.name, bb.name, func.blocks[pred].name));
+                    },
+                    _ => {
+                        errors.push(format!("{}: variable {} is used in block {} before it is defined", func.sig.name, v.name, bb.name));
+                    },
+                }
+            }
+
+            defined.extend(defined_vars(instr).into_iter().map(|v| v.name.clone()));
+        }
+    }
+}
+
+// Variables defined at the start of a block, are the ones defined at the end of all its predecessors.
+// None means none of the predecessors has been visited yet.
+fn defined_at_start(
+    bb_ref: BasicBlockRef,
+    entry: BasicBlockRef,
+    arguments: &HashSet<String>,
+    predecessors: &BTreeMap<BasicBlockRef, Vec<BasicBlockRef>>,
+    defined_at_end: &BTreeMap<BasicBlockRef, HashSet<String>>) -> Option<HashSet<String>>
+{
+    let mut defined: Option<HashSet<String>> = None;
+    if bb_ref == entry {
+        defined = Some(HashSet::new());
+    } else {
+        for vars in predecessors.get(&bb_ref).into_iter().flatten().filter_map(|pred| defined_at_end.get(pred)) {
+            defined = Some(match defined
+            {
+                Some(d) => d.intersection(vars).cloned().collect(),
+                None => vars.clone(),
+            });
+        }
+    }
+
+    defined.map(|mut defined| {
+        defined.extend(arguments.iter().cloned());
+        defined
+    })
+}
+
+fn describe_path(func: &ByteCodeFunction, path: &[BasicBlockRef]) -> String
+{
+    join(path.iter().map(|bb_ref| &func.blocks[bb_ref].name), " -> ")
+}
+
+// Along every path, scopes are ended in the reverse order they are started, a return may only happen
+// when all scopes have been ended, and all paths into a block have the same number of scopes open
+fn verify_scopes(func: &ByteCodeFunction, errors: &mut Vec<String>)
+{
+    // The number of open scopes at the start of each reachable block, and the first path found to it
+    let mut open_at_start: BTreeMap<BasicBlockRef, (usize, Vec<BasicBlockRef>)> = BTreeMap::new();
+    let mut work_list = VecDeque::new();
+    if func.blocks.contains_key(&0) {
+        open_at_start.insert(0, (0, vec![0]));
+        work_list.push_back(0);
+    }
+
+    while let Some(bb_ref) = work_list.pop_front()
+    {
+        let (mut open, path) = open_at_start[&bb_ref].clone();
+        for instr in &func.blocks[&bb_ref].instructions
+        {
+            match *instr
+            {
+                Instruction::StartScope => open += 1,
+                Instruction::EndScope if open == 0 => {
+                    errors.push(format!("{}: scope ended while no scope is open, along {}", func.sig.name, describe_path(func, &path)));
+                },
+                Instruction::EndScope => open -= 1,
+                Instruction::Return(_) |
+                Instruction::ReturnVoid if open > 0 => {
+                    errors.push(format!("{}: return with {} scope(s) still open, along {}", func.sig.name, open, describe_path(func, &path)));
+                },
+                _ => (),
+            }
+
+            for target in instr.branch_targets().into_iter().filter(|t| func.blocks.contains_key(t))
+            {
+                let mut target_path = path.clone();
+                target_path.push(target);
+                match open_at_start.get(&target)
+                {
+                    Some(&(target_open, ref first_path)) if target_open != open => {
+                        errors.push(format!("{}: block {} is entered with {} open scope(s) along {}, and with {} along {}",
+                            func.sig.name, func.blocks[&target].name,
+                            target_open, describe_path(func, first_path),
+                            open, describe_path(func, &target_path)));
+                    },
+                    Some(_) => (),
+                    None => {
+                        open_at_start.insert(target, (open, target_path));
+                        work_list.push_back(target);
+                    },
+                }
+            }
+        }
+    }
+}
+
+pub fn verify_function(func: &ByteCodeFunction, globals: &HashSet<String>) -> Result<(), Vec<String>>
+{
+    if func.external {
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+    verify_blocks(func, &mut errors);
+    verify_vars(func, globals, &mut errors);
+    verify_scopes(func, &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn verify_module(module: &ByteCodeModule) -> Result<(), Vec<String>>
+{
+    // Functions can be referred to by name, when used as a value
+    let globals: HashSet<String> = module.globals.keys()
+        .chain(module.functions.keys())
+        .chain(module.imported_functions.iter().map(|f| &f.sig.name))
+        .cloned()
+        .collect();
+    let mut errors = Vec::new();
+    for func in module.functions.values() {
+        if let Err(e) = verify_function(func, &globals) {
+            errors.extend(e);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::test::generate_byte_code;
+    use bytecode::instruction::{ret_instr, branch_if_instr};
+    use ast::{sig, Type, IntSize};
+    use span::Span;
+
+    fn empty_function() -> ByteCodeFunction
+    {
+        let func_sig = sig("foo", Type::Int(IntSize::I32), vec![], Span::default());
+        ByteCodeFunction::new(&func_sig, false)
+    }
+
+    fn verify(func: &ByteCodeFunction) -> Vec<String>
+    {
+        verify_function(func, &HashSet::new()).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn test_valid_module()
+    {
+        let m = generate_byte_code(r#"
+            fn foo(x: int) -> int:
+                match x:
+                    0 => 1
+                    _ => x * 2
+
+            fn main() -> int:
+                var i = 0
+                while i < 10:
+                    i += foo(i)
+                if i > 5: i else 0
+        "#, false).expect("Compilation failed");
+
+        assert_eq!(verify_module(&m), Ok(()));
+        assert!(m.get_function("test::foo").unwrap().blocks.values().any(|bb| bb.name.starts_with("match_case")));
+    }
+
+    #[test]
+    fn test_unknown_branch_target()
+    {
+        let mut func = empty_function();
+        func.add(Instruction::Branch(5));
+        assert_eq!(verify(&func), vec!["foo: block 0 branches to unknown block 5"]);
+    }
+
+    #[test]
+    fn test_missing_terminator()
+    {
+        let mut func = empty_function();
+        let bb = func.create_basic_block("if_end");
+        func.add(Instruction::Branch(bb));
+        assert_eq!(verify(&func), vec!["foo: block if_end1 has no terminator"]);
+    }
+
+    #[test]
+    fn test_instruction_after_terminator()
+    {
+        let mut func = empty_function();
+        func.add(Instruction::Return(Operand::const_int(1, IntSize::I32)));
+        // The function drops instructions added after a terminator, so add it to the block directly
+        func.blocks.get_mut(&0).unwrap().instructions.push(Instruction::StartScope);
+        assert_eq!(verify(&func), vec!["foo: block entry has instructions after its terminator"]);
+    }
+
+    #[test]
+    fn test_entry_block()
+    {
+        let mut func = empty_function();
+        let bb = func.create_basic_block("while_body");
+        func.set_current_bb(bb);
+        func.add(Instruction::Return(Operand::const_int(1, IntSize::I32)));
+        func.blocks.remove(&0);
+        assert_eq!(verify(&func), vec!["foo: entry block is 1, expected 0"]);
+    }
+
+    #[test]
+    fn test_use_before_definition()
+    {
+        let mut func = empty_function();
+        let v = Var::named("v", Type::Int(IntSize::I32));
+        let cond = Var::named("cond", Type::Bool);
+        let def_bb = func.create_basic_block("let_bind");
+        let ret_bb = func.create_basic_block("let_end");
+        func.add(Instruction::Store{dst: cond.clone(), src: Operand::const_bool(true), span: Span::default()});
+        func.add(branch_if_instr(&cond, def_bb, ret_bb));
+        func.set_current_bb(def_bb);
+        func.add(Instruction::Store{dst: v.clone(), src: Operand::const_int(1, IntSize::I32), span: Span::default()});
+        func.add(Instruction::Branch(ret_bb));
+        func.set_current_bb(ret_bb);
+        func.add(ret_instr(&v));
+        // Defined along one path is not enough
+        assert_eq!(verify(&func), vec!["foo: variable v is used in block let_end2, but it is not defined on the path from block entry"]);
+
+        // Defined along every path
+        let mut func = empty_function();
+        let def_bb = func.create_basic_block("let_bind");
+        let else_bb = func.create_basic_block("let_else");
+        let ret_bb = func.create_basic_block("let_end");
+        func.add(Instruction::Store{dst: cond.clone(), src: Operand::const_bool(true), span: Span::default()});
+        func.add(branch_if_instr(&cond, def_bb, else_bb));
+        func.set_current_bb(def_bb);
+        func.add(Instruction::Store{dst: v.clone(), src: Operand::const_int(1, IntSize::I32), span: Span::default()});
+        func.add(Instruction::Branch(ret_bb));
+        func.set_current_bb(else_bb);
+        func.add(Instruction::Store{dst: v.clone(), src: Operand::const_int(2, IntSize::I32), span: Span::default()});
+        func.add(Instruction::Branch(ret_bb));
+        func.set_current_bb(ret_bb);
+        func.add(ret_instr(&v));
+        assert_eq!(verify(&func), Vec::<String>::new());
+
+        let mut func = empty_function();
+        func.add(ret_instr(&v));
+        assert_eq!(verify(&func), vec!["foo: variable v is used in block entry before it is defined"]);
+    }
+
+    #[test]
+    fn test_unbalanced_scopes()
+    {
+        // A scope started on one path into a block, but not on the other
+        let mut func = empty_function();
+        let cond = Var::named("cond", Type::Bool);
+        let case_bb = func.create_basic_block("match_case");
+        let end_bb = func.create_basic_block("match_end");
+        func.add(Instruction::Store{dst: cond.clone(), src: Operand::const_bool(true), span: Span::default()});
+        func.add(branch_if_instr(&cond, case_bb, end_bb));
+        func.set_current_bb(case_bb);
+        func.add(Instruction::StartScope);
+        func.add(Instruction::Branch(end_bb));
+        func.set_current_bb(end_bb);
+        func.add(Instruction::Return(Operand::const_int(1, IntSize::I32)));
+        assert_eq!(verify(&func), vec![
+            "foo: block match_end2 is entered with 0 open scope(s) along entry -> match_end2, and with 1 along entry -> match_case1 -> match_end2",
+        ]);
+
+        let mut func = empty_function();
+        func.add(Instruction::StartScope);
+        func.add(Instruction::Return(Operand::const_int(1, IntSize::I32)));
+        assert_eq!(verify(&func), vec!["foo: return with 1 scope(s) still open, along entry"]);
+
+        let mut func = empty_function();
+        let bb = func.create_basic_block("match_end");
+        func.add(Instruction::Branch(bb));
+        func.set_current_bb(bb);
+        func.add(Instruction::EndScope);
+        func.add(Instruction::Return(Operand::const_int(1, IntSize::I32)));
+        assert_eq!(verify(&func), vec!["foo: scope ended while no scope is open, along entry -> match_end1"]);
+    }
+
+    #[test]
+    fn test_balanced_scopes_with_early_returns()
+    {
+        let m = generate_byte_code(r#"
+            fn first_positive(xs: int[]) -> int:
+                for x in xs:
+                    match x:
+                        n => if n > 0:
+                            return n
+                0
+
+            fn sum(a: ?int, b: ?int) -> int:
+                if let x = a, y = b:
+                    x + y
+                else
+                    0
+
+            fn main() -> int: first_positive([-1, 2]) + sum(1, 2)
+        "#, false).expect("Compilation failed");
+
+        assert_eq!(verify_module(&m), Ok(()));
+    }
+
+    #[test]
+    fn test_early_exit_ends_the_scopes_it_leaves()
+    {
+        let mut func = empty_function();
+        let cond = Var::named("cond", Type::Bool);
+        func.add(Instruction::Store{dst: cond.clone(), src: Operand::const_bool(true), span: Span::default()});
+        func.push_scope();
+        func.push_scope();
+        let exit_bb = func.create_basic_block("exit");
+        let continue_bb = func.create_basic_block("continue");
+        func.add(branch_if_instr(&cond, exit_bb, continue_bb));
+
+        func.set_current_bb(exit_bb);
+        func.end_scopes_to(0);
+        func.add(Instruction::Return(Operand::const_int(1, IntSize::I32)));
+        assert_eq!(func.blocks[&exit_bb].instructions.iter().filter(|i| **i == Instruction::EndScope).count(), 2);
+
+        // The scopes are still open after the early exit
+        func.set_current_bb(continue_bb);
+        assert_eq!(func.scope_depth(), 3);
+        func.pop_scope();
+        func.pop_scope();
+        func.pop_scope();
+        func.add(Instruction::Return(Operand::const_int(2, IntSize::I32)));
+        assert_eq!(verify(&func), Vec::<String>::new());
+    }
+}
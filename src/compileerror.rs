@@ -1,18 +1,22 @@
 use std::error::Error;
 use std::convert::From;
 use std::iter::repeat;
-use std::fs::File;
+use std::cmp;
+use std::fs;
 use std::io;
-use std::io::BufRead;
 use std::fmt;
+use itertools::free::join;
 use ast::Type;
-use span::Span;
+use doc::json_string;
+use errorcodes::ErrorCode;
+use span::{Span, TAB_WIDTH};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ErrorData
 {
     pub span: Span,
     pub msg: String,
+    pub code: Option<ErrorCode>,
 }
 
 impl ErrorData
@@ -22,6 +26,25 @@ impl ErrorData
         ErrorData{
             span: span.clone(),
             msg: msg.into(),
+            code: None,
+        }
+    }
+
+    pub fn with_code<S: Into<String>>(span: &Span, msg: S, code: ErrorCode) -> ErrorData
+    {
+        ErrorData{
+            span: span.clone(),
+            msg: msg.into(),
+            code: Some(code),
+        }
+    }
+
+    // The message as it is printed, with the code to look up with menhir explain
+    pub fn message(&self) -> String
+    {
+        match self.code {
+            Some(code) => format!("{} [{}]", self.msg, code.code()),
+            None => self.msg.clone(),
         }
     }
 }
@@ -30,7 +53,7 @@ impl fmt::Display for ErrorData
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
     {
-        writeln!(f, "{}: {}", self.span, self.msg)
+        writeln!(f, "{}: {}", self.span, self.message())
     }
 }
 
@@ -57,7 +80,7 @@ impl CompileError
             CompileError::IO(ref msg) => println!("{}", msg),
             CompileError::Parse(ref ed) |
             CompileError::Type(ref ed) |
-            CompileError::UnknownName(ref ed) => print_message(&ed.msg, &ed.span),
+            CompileError::UnknownName(ref ed) => print_message(&ed.message(), &ed.span),
             CompileError::UnknownType(ref name, ref typ) => println!("{} has unknown type, expecting {}", name, typ),
             CompileError::Many(ref errors) => {
                 for e in errors {
@@ -66,6 +89,41 @@ impl CompileError
             }
         }
     }
+
+    fn json_diagnostics(&self, out: &mut Vec<String>)
+    {
+        let (kind, ed) = match *self
+        {
+            CompileError::Parse(ref ed) => ("parse", ed),
+            CompileError::Type(ref ed) => ("type", ed),
+            CompileError::UnknownName(ref ed) => ("unknown_name", ed),
+            CompileError::Many(ref errors) => {
+                for e in errors {
+                    e.json_diagnostics(out);
+                }
+                return;
+            },
+            _ => {
+                let msg = self.to_string();
+                out.push(format!("{{\"kind\": \"other\", \"code\": null, \"message\": {}, \"file\": null}}", json_string(msg.trim_end())));
+                return;
+            },
+        };
+
+        let code = ed.code.map(|c| json_string(c.code())).unwrap_or_else(|| "null".into());
+        out.push(format!("{{\"kind\": \"{}\", \"code\": {}, \"message\": {}, \"file\": {}, \
+            \"start\": {{\"line\": {}, \"column\": {}}}, \"end\": {{\"line\": {}, \"column\": {}}}}}",
+            kind, code, json_string(&ed.msg), json_string(&ed.span.file),
+            ed.span.start.line, ed.span.start.offset, ed.span.end.line, ed.span.end.offset));
+    }
+
+    // All errors as a JSON array, for editors and other tools
+    pub fn to_json(&self) -> String
+    {
+        let mut diagnostics = Vec::new();
+        self.json_diagnostics(&mut diagnostics);
+        format!("[{}]", join(diagnostics, ", "))
+    }
 }
 
 impl Error for CompileError
@@ -95,44 +153,76 @@ impl fmt::Display for CompileError
     }
 }
 
-pub fn print_message(msg: &str, span: &Span)
+// A line as it gets printed, with its tabs expanded
+fn expand_tabs(line: &str) -> String
+{
+    let mut expanded = String::new();
+    for c in line.chars()
+    {
+        if c == '\t' {
+            let width = TAB_WIDTH - expanded.chars().count() % TAB_WIDTH;
+            expanded.extend(repeat(' ').take(width));
+        } else {
+            expanded.push(c);
+        }
+    }
+    expanded
+}
+
+// The printed column of the character at offset in a line, starting at 0, so carets end up under the right character
+fn printed_column(line: &str, offset: usize) -> usize
+{
+    let prefix: String = line.chars().take(offset.saturating_sub(1)).collect();
+    expand_tabs(&prefix).chars().count()
+}
+
+// The lines of source around span, with carets under the part of them span covers
+pub fn source_excerpt(source: &str, span: &Span) -> String
 {
     fn repeat_string(s: &str, count: usize) -> String
     {
         repeat(s).take(count).collect()
     }
 
+    let source = source.trim_start_matches('\u{feff}');
     let prefix = "| ";
-    println!("{}: {}", span, msg);
-    if let Ok(file) = File::open(&span.file) {
-        let start_line = if span.start.line >= 4 {span.start.line - 4} else {0};
-        let reader = io::BufReader::new(file);
-
-        for (idx, line) in reader.lines().enumerate().skip(start_line)
+    let start_line = if span.start.line >= 4 {span.start.line - 4} else {0};
+    let mut excerpt = String::new();
+    for (idx, line) in source.lines().enumerate().skip(start_line)
+    {
+        let line_idx = idx + 1;
+        let line_width = expand_tabs(line).chars().count();
+        excerpt.push_str(&format!("{:>4} {}{}\n", line_idx, prefix, expand_tabs(line)));
+        if line_idx == span.start.line
         {
-            let line = line.unwrap();
-            let line_idx = idx + 1;
-            println!("{:>4} {}{}", line_idx, prefix, line);
-            if line_idx == span.start.line
-            {
-                let end = if line_idx == span.end.line {span.end.offset} else {line.len()};
-                let carets = repeat_string("^", end - span.start.offset + 1);
-                let whitespace = repeat_string(" ", span.start.offset - 1);
-                println!("     {}{}{}", prefix, whitespace, carets);
-            }
-            else if line_idx == span.end.line
-            {
-                let carets = repeat_string("^", span.end.offset);
-                println!("     {}{}", prefix, carets);
-            }
-            else if line_idx > span.start.line && line_idx < span.end.line && !line.is_empty()
-            {
-                let carets = repeat_string("^", line.len());
-                println!("     {}{}", prefix, carets);
-            }
-
-            if line_idx >= span.end.line + 3 {break;}
+            let start = printed_column(line, span.start.offset);
+            let end = if line_idx == span.end.line {printed_column(line, span.end.offset + 1)} else {line_width};
+            let carets = repeat_string("^", cmp::max(end.saturating_sub(start), 1));
+            let whitespace = repeat_string(" ", start);
+            excerpt.push_str(&format!("     {}{}{}\n", prefix, whitespace, carets));
+        }
+        else if line_idx == span.end.line
+        {
+            let carets = repeat_string("^", printed_column(line, span.end.offset + 1));
+            excerpt.push_str(&format!("     {}{}\n", prefix, carets));
         }
+        else if line_idx > span.start.line && line_idx < span.end.line && !line.is_empty()
+        {
+            let carets = repeat_string("^", line_width);
+            excerpt.push_str(&format!("     {}{}\n", prefix, carets));
+        }
+
+        if line_idx >= span.end.line + 3 {break;}
+    }
+    excerpt
+}
+
+pub fn print_message(msg: &str, span: &Span)
+{
+    println!("{}: {}", span, msg);
+    // Invalid UTF-8 is already reported by the lexer, it should not prevent printing the lines around an error
+    if let Ok(data) = fs::read(&span.file) {
+        print!("{}", source_excerpt(&String::from_utf8_lossy(&data), span));
     }
 }
 
@@ -153,16 +243,6 @@ pub fn type_error<Msg: Into<String>>(span: &Span, msg: Msg) -> CompileError
     CompileError::Type(ErrorData::new(span, msg))
 }
 
-pub fn unknown_name<Msg: Into<String>>(span: &Span, msg: Msg) -> CompileError
-{
-    CompileError::UnknownName(ErrorData::new(span, msg))
-}
-
-pub fn unknown_name_result<T, Msg: Into<String>>(span: &Span, msg: Msg) -> CompileResult<T>
-{
-    Err(CompileError::UnknownName(ErrorData::new(span, msg)))
-}
-
 pub fn unknown_type_result<T>(name: &str, typ: &Type) -> CompileResult<T>
 {
     Err(CompileError::UnknownType(name.into(), typ.clone()))
@@ -184,3 +264,62 @@ impl From<String> for CompileError
     }
 }
 
+
+#[cfg(test)]
+mod tests
+{
+    use super::{source_excerpt, type_error, CompileError};
+    use errorcodes::ErrorCode;
+    use span::{Span, Pos};
+
+    #[test]
+    fn test_source_excerpt_with_tabs()
+    {
+        let source = "fn main() -> int:\n\tlet x = foo\n\tx\n";
+        let span = Span::new("", Pos::new(2, 10), Pos::new(2, 12));
+        assert_eq!(source_excerpt(source, &span),
+"   1 | fn main() -> int:
+   2 |     let x = foo
+     |             ^^^
+   3 |     x
+");
+    }
+
+    #[test]
+    fn test_source_excerpt_with_bom_and_crlf()
+    {
+        let source = "\u{feff}fn main() -> int:\r\n    bar\r\n";
+        let span = Span::new("", Pos::new(2, 5), Pos::new(2, 7));
+        assert_eq!(source_excerpt(source, &span),
+"   1 | fn main() -> int:
+   2 |     bar
+     |     ^^^
+");
+    }
+
+    #[test]
+    fn test_error_code_in_message()
+    {
+        let span = Span::new("a.mhr", Pos::new(3, 9), Pos::new(3, 9));
+        let err = ErrorCode::MatchArmTypeMismatch.error(&span, "Expressions in match statements must return the same type");
+        assert_eq!(err.to_string(), "a.mhr:3:9 -> 3:9: Expressions in match statements must return the same type [E0006]\n");
+        assert_eq!(type_error(&span, "Something is wrong").to_string(), "a.mhr:3:9 -> 3:9: Something is wrong\n");
+    }
+
+    #[test]
+    fn test_errors_as_json()
+    {
+        let span = Span::new("a.mhr", Pos::new(3, 9), Pos::new(3, 10));
+        let err = CompileError::Many(vec![
+            ErrorCode::UnknownName.error(&span, "Unknown name \"x\""),
+            type_error(&span, "Something is wrong"),
+            CompileError::Other("No main function found".into()),
+        ]);
+        assert_eq!(err.to_json(), concat!(
+            r#"[{"kind": "unknown_name", "code": "E0003", "message": "Unknown name \"x\"", "file": "a.mhr", "#,
+            r#""start": {"line": 3, "column": 9}, "end": {"line": 3, "column": 10}}, "#,
+            r#"{"kind": "type", "code": null, "message": "Something is wrong", "file": "a.mhr", "#,
+            r#""start": {"line": 3, "column": 9}, "end": {"line": 3, "column": 10}}, "#,
+            r#"{"kind": "other", "code": null, "message": "No main function found", "file": null}]"#));
+    }
+}
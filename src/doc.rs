@@ -0,0 +1,423 @@
+// Documentation of the declarations in a module, generated from the ## comments in front of them
+use std::path::Path;
+use itertools::free::join;
+use ast::{Module, Type, TypeDeclaration, FunctionSignature, StructDeclaration, is_sum_type_case_constructor};
+use bytecode::{InstructionInfo, INSTRUCTIONS, OPERAND_KINDS};
+use compileerror::CompileResult;
+use package::Package;
+use span::Span;
+use target::Target;
+use timer::PassTimings;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DocFormat
+{
+    Markdown,
+    Json,
+}
+
+pub struct DocOptions
+{
+    pub format: DocFormat,
+    pub document_private: bool,
+}
+
+// A struct member or a sum type case
+struct DocMember
+{
+    name: String,
+    signature: String,
+    doc: Option<String>,
+}
+
+struct DocItem
+{
+    kind: &'static str,
+    name: String,
+    signature: String,
+    doc: Option<String>,
+    members: Vec<DocMember>,
+    span: Span,
+}
+
+struct ModuleDoc
+{
+    name: String,
+    items: Vec<DocItem>,
+}
+
+// Names are shown without the namespace of the module being documented
+fn local_name(name: &str, namespace: &str) -> String
+{
+    let prefix = format!("{}::", namespace);
+    name.strip_prefix(&prefix[..]).unwrap_or(name).into()
+}
+
+// Like the Display implementation of Type, but structs, sum types and enums are shown by name instead of by their contents
+fn type_name(typ: &Type, namespace: &str) -> String
+{
+    match *typ
+    {
+        Type::Pointer(ref inner) => format!("*{}", type_name(inner, namespace)),
+        Type::Optional(ref inner) => format!("?{}", type_name(inner, namespace)),
+        Type::Array(ref at) => format!("{}[{}]", type_name(&at.element_type, namespace), at.len),
+        Type::Slice(ref st) => format!("{}[]", type_name(&st.element_type, namespace)),
+        Type::Func(ref ft) => {
+            let mut args: Vec<String> = ft.args.iter().map(|a| type_name(a, namespace)).collect();
+            if ft.variadic {
+                args.push("...".into());
+            }
+            format!("({}) -> {}", args.join(", "), type_name(&ft.return_type, namespace))
+        },
+        Type::Struct(ref st) if !st.name.is_empty() => local_name(&st.name, namespace),
+        Type::Struct(ref st) => format!("{{{}}}", join(st.members.iter().map(|m| {
+            if m.name.is_empty() {
+                type_name(&m.typ, namespace)
+            } else {
+                format!("{}: {}", m.name, type_name(&m.typ, namespace))
+            }
+        }), ", ")),
+        Type::Sum(ref st) => local_name(&st.name, namespace),
+        Type::Enum(ref et) => local_name(&et.name, namespace),
+        Type::Unresolved(ref ut) => local_name(&ut.name, namespace),
+        _ => typ.to_string(),
+    }
+}
+
+fn function_signature(keyword: &str, sig: &FunctionSignature, namespace: &str) -> String
+{
+    let mut args: Vec<String> = sig.args.iter()
+        .map(|a| format!("{}{}: {}", if a.mutable {"var "} else {""}, a.name, type_name(&a.typ, namespace)))
+        .collect();
+    if sig.variadic {
+        args.push("...".into());
+    }
+
+    let mut signature = format!("{} {}({})", keyword, local_name(&sig.name, namespace), args.join(", "));
+    if sig.return_type != Type::Void {
+        signature.push_str(&format!(" -> {}", type_name(&sig.return_type, namespace)));
+    }
+    signature
+}
+
+fn generic_params(params: &[Type]) -> String
+{
+    if params.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", join(params.iter().map(|p| p.to_string().trim_start_matches('$').to_owned()), ", "))
+    }
+}
+
+// The declared member types may still be unresolved, the type of the struct has the resolved ones
+fn struct_members(sd: &StructDeclaration, namespace: &str) -> Vec<DocMember>
+{
+    let resolved = match sd.typ {
+        Type::Struct(ref st) => Some(st),
+        _ => None,
+    };
+
+    sd.members.iter().enumerate().map(|(idx, m)| {
+        let typ = resolved.and_then(|st| st.members.get(idx)).map(|sm| &sm.typ).unwrap_or(&m.typ);
+        DocMember{
+            name: m.name.clone(),
+            signature: format!("{}: {}", m.name, type_name(typ, namespace)),
+            doc: m.doc.clone(),
+        }
+    }).collect()
+}
+
+fn document_module(module: &Module, options: &DocOptions) -> ModuleDoc
+{
+    let namespace = &module.name;
+    let mut items = Vec::new();
+
+    // The prelude is added to every module, generic instances and sum type case constructors are generated by the compiler
+    let is_generated = |span: &Span, name: &str| span.file == "prelude" || name.contains('<') || is_sum_type_case_constructor(name);
+
+    for f in module.functions.values() {
+        if is_generated(&f.span, &f.sig.name) || (!f.public && !options.document_private) {
+            continue;
+        }
+
+        items.push(DocItem{
+            kind: "fn",
+            name: local_name(&f.sig.name, namespace),
+            signature: function_signature("fn", &f.sig, namespace),
+            doc: f.doc.clone(),
+            members: Vec::new(),
+            span: f.span.clone(),
+        });
+    }
+
+    for ext in module.externals.values() {
+        if is_generated(&ext.span, &ext.sig.name) {
+            continue;
+        }
+
+        items.push(DocItem{
+            kind: "extern",
+            name: ext.sig.name.clone(),
+            signature: function_signature("extern fn", &ext.sig, namespace),
+            doc: ext.doc.clone(),
+            members: Vec::new(),
+            span: ext.span.clone(),
+        });
+    }
+
+    for g in module.globals.values() {
+        let name = local_name(&g.name, namespace);
+        items.push(DocItem{
+            kind: if g.mutable {"var"} else {"let"},
+            signature: format!("{} {}: {}", if g.mutable {"var"} else {"let"}, name, type_name(&g.typ, namespace)),
+            name: name,
+            doc: g.doc.clone(),
+            members: Vec::new(),
+            span: g.span.clone(),
+        });
+    }
+
+    for td in module.types.values() {
+        if is_generated(&td.span(), td.name()) {
+            continue;
+        }
+
+        let name = local_name(td.name(), namespace);
+        match *td
+        {
+            TypeDeclaration::Struct(ref sd) => items.push(DocItem{
+                kind: "struct",
+                signature: format!("{}struct {}{}", if sd.repr_c {"#[repr(c)] "} else {""}, name, generic_params(&sd.generic_params)),
+                name: name,
+                doc: sd.doc.clone(),
+                members: struct_members(sd, namespace),
+                span: sd.span.clone(),
+            }),
+
+            TypeDeclaration::Sum(ref st) => items.push(DocItem{
+                kind: "enum",
+                signature: format!("enum {}{}", name, generic_params(&st.generic_params)),
+                name: name,
+                doc: st.doc.clone(),
+                members: st.cases.iter().map(|c| {
+                    let case_name = c.name.rsplit("::").next().unwrap_or(&c.name).to_owned();
+                    let signature = match c.data {
+                        Some(ref sd) => format!("{}{{{}}}", case_name, join(struct_members(sd, namespace).iter().map(|m| &m.signature), ", ")),
+                        None => case_name.clone(),
+                    };
+                    DocMember{name: case_name, signature: signature, doc: c.doc.clone()}
+                }).collect(),
+                span: st.span.clone(),
+            }),
+
+            TypeDeclaration::Interface(_) => (),
+        }
+    }
+
+    items.sort_by(|a, b| (&a.span.file, a.span.start).cmp(&(&b.span.file, b.span.start)));
+    ModuleDoc{name: namespace.clone(), items: items}
+}
+
+fn to_markdown(doc: &ModuleDoc) -> String
+{
+    let mut md = format!("# {}\n", doc.name);
+    for item in &doc.items {
+        md.push_str(&format!("\n## {} {}\n\n```\n{}\n```\n", item.kind, item.name, item.signature));
+        if let Some(ref text) = item.doc {
+            md.push_str(&format!("\n{}\n", text));
+        }
+
+        if !item.members.is_empty() {
+            md.push('\n');
+            for m in &item.members {
+                match m.doc {
+                    Some(ref text) => md.push_str(&format!("* `{}`: {}\n", m.signature, text.replace('\n', " "))),
+                    None => md.push_str(&format!("* `{}`\n", m.signature)),
+                }
+            }
+        }
+    }
+    md
+}
+
+pub fn json_string(s: &str) -> String
+{
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_doc(doc: &Option<String>) -> String
+{
+    doc.as_ref().map(|d| json_string(d)).unwrap_or_else(|| "null".into())
+}
+
+fn to_json(doc: &ModuleDoc) -> String
+{
+    let items = doc.items.iter().map(|item| {
+        let members = item.members.iter().map(|m| {
+            format!("{{\"name\": {}, \"signature\": {}, \"doc\": {}}}", json_string(&m.name), json_string(&m.signature), json_doc(&m.doc))
+        });
+        format!("{{\"kind\": {}, \"name\": {}, \"signature\": {}, \"doc\": {}, \"members\": [{}]}}",
+            json_string(item.kind), json_string(&item.name), json_string(&item.signature), json_doc(&item.doc), join(members, ", "))
+    });
+    format!("{{\"module\": {}, \"items\": [{}]}}", json_string(&doc.name), join(items, ", "))
+}
+
+pub fn document(module: &Module, options: &DocOptions) -> String
+{
+    let doc = document_module(module, options);
+    match options.format
+    {
+        DocFormat::Markdown => to_markdown(&doc),
+        DocFormat::Json => to_json(&doc),
+    }
+}
+
+// Type checks the file, or the package in the directory, so the signatures show the resolved types
+pub fn document_file(path: &Path, target: &Target, options: &DocOptions) -> CompileResult<String>
+{
+    let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut pkg = Package::new(&name);
+    let files = pkg.source_files(path)?;
+    pkg.parse_files(&files, target)?;
+    pkg.type_check(target, &mut PassTimings::new())?;
+
+    let mut modules: Vec<&Module> = pkg.modules.values().collect();
+    modules.sort_by(|a, b| a.name.cmp(&b.name));
+    let docs: Vec<String> = modules.iter().map(|m| document(m, options)).collect();
+    Ok(match options.format
+    {
+        DocFormat::Markdown => docs.join("\n"),
+        DocFormat::Json => format!("[{}]", docs.join(", ")),
+    })
+}
+
+fn instruction_operands(info: &InstructionInfo) -> String
+{
+    join(info.operands.iter().map(|&(name, kind)| format!("{}: {}", name, kind.name())), ", ")
+}
+
+fn instructions_to_markdown() -> String
+{
+    let mut md = String::from("# Bytecode instructions\n\n\
+        Bytecode is serialized with bincode: an instruction is its opcode as a little endian u32, followed by its operands in order. \
+        Strings are a u64 length followed by UTF-8 bytes, enums a u32 variant index followed by the fields of the variant.\n");
+    for info in INSTRUCTIONS {
+        md.push_str(&format!("\n## {} {}\n\n`{}` ({})\n\n{}\n", info.opcode, info.mnemonic, info.variant, instruction_operands(info), info.description));
+    }
+
+    md.push_str("\n# Operand encoding\n\n");
+    for kind in OPERAND_KINDS {
+        md.push_str(&format!("* `{}`: {}\n", kind.name(), kind.encoding()));
+    }
+    md
+}
+
+fn instructions_to_json() -> String
+{
+    let kinds = OPERAND_KINDS.iter().map(|kind| {
+        format!("{{\"name\": {}, \"encoding\": {}}}", json_string(kind.name()), json_string(kind.encoding()))
+    });
+    let instructions = INSTRUCTIONS.iter().map(|info| {
+        let operands = info.operands.iter().map(|&(name, kind)| {
+            format!("{{\"name\": {}, \"kind\": {}}}", json_string(name), json_string(kind.name()))
+        });
+        format!("{{\"opcode\": {}, \"variant\": {}, \"mnemonic\": {}, \"operands\": [{}], \"description\": {}}}",
+            info.opcode, json_string(info.variant), json_string(info.mnemonic), join(operands, ", "), json_string(info.description))
+    });
+    format!("{{\"encoding\": \"bincode\", \"opcode\": \"u32\", \"operand_kinds\": [{}], \"instructions\": [{}]}}",
+        join(kinds, ", "), join(instructions, ", "))
+}
+
+// Reference of the bytecode instruction set, and of how it is stored in the build cache and export libraries
+pub fn document_instructions(format: DocFormat) -> String
+{
+    match format
+    {
+        DocFormat::Markdown => instructions_to_markdown(),
+        DocFormat::Json => instructions_to_json(),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use ast::IntSize;
+    use parser::parse_str;
+    use target::Target;
+    use timer::PassTimings;
+    use super::{DocFormat, DocOptions, document, document_instructions};
+
+    fn document_str(src: &str, format: DocFormat) -> String
+    {
+        let target = Target::new(IntSize::I64, "");
+        let mut pkg = match parse_str(src, "test", &target) {
+            Ok(pkg) => pkg,
+            Err(e) => panic!("Parsing failed: {}", e),
+        };
+        pkg.type_check(&target, &mut PassTimings::new()).expect("Type checking failed");
+        document(&pkg.modules["test"], &DocOptions{format: format, document_private: false})
+    }
+
+    const SOURCE: &str = r#"
+## A point
+struct Point:
+    ## Horizontal "position"
+    x: int
+    y: int
+
+## Length of a vector
+fn length(p: *Point) -> int: p.x + p.y
+"#;
+
+    #[test]
+    fn test_json_output()
+    {
+        assert_eq!(document_str(SOURCE, DocFormat::Json), concat!(
+            r#"{"module": "test", "items": ["#,
+            r#"{"kind": "struct", "name": "Point", "signature": "struct Point", "doc": "A point", "members": ["#,
+            r#"{"name": "x", "signature": "x: int64", "doc": "Horizontal \"position\""}, "#,
+            r#"{"name": "y", "signature": "y: int64", "doc": null}]}, "#,
+            r#"{"kind": "fn", "name": "length", "signature": "fn length(p: *Point) -> int64", "doc": "Length of a vector", "members": []}"#,
+            r#"]}"#));
+    }
+
+    #[test]
+    fn test_markdown_output()
+    {
+        let md = document_str(SOURCE, DocFormat::Markdown);
+        assert!(md.starts_with("# test\n\n## struct Point\n\n```\nstruct Point\n```\n\nA point\n"));
+        assert!(md.contains("* `x: int64`: Horizontal \"position\"\n* `y: int64`\n"));
+        assert!(md.contains("## fn length\n\n```\nfn length(p: *Point) -> int64\n```\n\nLength of a vector\n"));
+    }
+
+    #[test]
+    fn test_instructions_json()
+    {
+        use bytecode::INSTRUCTIONS;
+
+        let json = document_instructions(DocFormat::Json);
+        assert!(json.starts_with(r#"{"encoding": "bincode", "opcode": "u32", "operand_kinds": [{"name": "var", "encoding": "#));
+        assert_eq!(json.matches(r#""opcode": "#).count(), INSTRUCTIONS.len() + 1);
+        assert_eq!(json.matches(r#""description": """#).count(), 0);
+        for info in INSTRUCTIONS {
+            assert!(json.contains(&format!(r#"{{"opcode": {}, "variant": "{}", "mnemonic": "{}", "operands": ["#, info.opcode, info.variant, info.mnemonic)));
+        }
+        assert!(json.contains(r#"{"opcode": 27, "variant": "Branch", "mnemonic": "br", "operands": [{"name": "block", "kind": "block"}], "description": "#));
+
+        let md = document_instructions(DocFormat::Markdown);
+        assert!(md.contains("\n## 0 store\n\n`Store` (dst: var, src: operand, span: span)\n"));
+    }
+}
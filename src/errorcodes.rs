@@ -0,0 +1,635 @@
+// Stable codes for the errors of the parser and the type checker. Every code has a longer explanation, which
+// `menhir explain <code>` prints, with an example which gives the error and the same example with the mistake fixed.
+// Codes are never renumbered or reused, so they can be looked up in the explanations of older compilers.
+use compileerror::{CompileError, CompileResult, ErrorData};
+use span::Span;
+
+// Which kind of CompileError an error with a code is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind
+{
+    Parse,
+    Type,
+    UnknownName,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explanation
+{
+    pub code: &'static str,
+    pub kind: ErrorKind,
+    pub title: &'static str,
+    pub text: &'static str,
+    pub example: &'static str, // Gives the error
+    pub fix: &'static str,     // The example without the error
+}
+
+macro_rules! error_codes {
+    ($($name:ident = $code:expr, $kind:ident, $title:expr, $text:expr, $example:expr, $fix:expr;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum ErrorCode
+        {
+            $($name,)*
+        }
+
+        pub const ERROR_CODES: &[ErrorCode] = &[$(ErrorCode::$name,)*];
+
+        impl ErrorCode
+        {
+            pub fn explanation(self) -> Explanation
+            {
+                match self
+                {
+                    $(ErrorCode::$name => Explanation{
+                        code: $code,
+                        kind: ErrorKind::$kind,
+                        title: $title,
+                        text: $text,
+                        example: $example,
+                        fix: $fix,
+                    },)*
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    UnexpectedToken = "E0001", Parse, "unexpected token",
+r#"
+The parser found a token where the grammar does not allow one. The error points at the token, but the mistake
+is often just before it: a missing colon before the body of a function or a block, a missing comma or an
+unbalanced parenthesis.
+"#,
+r#"
+fn main() -> int 0
+"#,
+r#"
+fn main() -> int: 0
+"#;
+
+    InvalidLiteral = "E0002", Parse, "invalid literal",
+r#"
+A literal cannot be read. Character literals contain exactly one character, use a string for more than one.
+Numbers must fit in 64 bits, and escape codes in byte strings are \xNN with two hexadecimal digits.
+"#,
+r#"
+fn main() -> int:
+    let c = 'ab'
+    0
+"#,
+r#"
+fn main() -> int:
+    let c = 'a'
+    0
+"#;
+
+    UnknownName = "E0003", UnknownName, "unknown name",
+r#"
+A name is used which is not declared: a variable, function, type or struct member. Names are case sensitive,
+the error suggests similar names when there are any. Names from other modules need an import.
+"#,
+r#"
+fn area(width: int) -> int: width * height
+"#,
+r#"
+fn area(width: int, height: int) -> int: width * height
+"#;
+
+    TypeMismatch = "E0004", Type, "type mismatch",
+r#"
+An expression has another type than the one it is used as, and it cannot be converted to it. Conversions are
+only done when no information is lost, such as from a literal to a type it fits in or from an array to a slice.
+Change the expression, or convert it explicitly with `as`.
+"#,
+r#"
+let limit: int = "ten"
+"#,
+r#"
+let limit: int = 10
+"#;
+
+    IfBranchTypeMismatch = "E0005", Type, "if branches have different types",
+r#"
+The value of an if expression is the value of the branch which is taken, so the then and the else part must
+have the same type. A branch can be nil when the other one has a value, the if is then an optional. An if
+without an else part has no value, so its then part must not have one either.
+"#,
+r#"
+fn describe(n: int) -> string: if n > 0: "positive" else 0
+"#,
+r#"
+fn describe(n: int) -> string: if n > 0: "positive" else "not positive"
+"#;
+
+    MatchArmTypeMismatch = "E0006", Type, "match cases have different types",
+r#"
+The value of a match expression is the value of the case which matches, so the expressions of all cases must
+have the same type. A case which returns from the function has no value, and does not count.
+"#,
+r#"
+fn describe(n: int) -> string:
+    match n:
+        0 => "zero"
+        _ => n
+"#,
+r#"
+fn describe(n: int) -> string:
+    match n:
+        0 => "zero"
+        _ => "not zero"
+"#;
+
+    IncompleteMatch = "E0007", Type, "incomplete pattern match",
+r#"
+A match expression must handle every value of the expression it matches. Add the missing cases, or a last
+case with _ or a name, which matches all remaining values.
+"#,
+r#"
+fn describe(ready: bool) -> string:
+    match ready:
+        true => "ready"
+"#,
+r#"
+fn describe(ready: bool) -> string:
+    match ready:
+        true => "ready"
+        false => "waiting"
+"#;
+
+    DuplicatePattern = "E0008", Type, "duplicate pattern",
+r#"
+A value is matched by more than one case of a match expression, so the later cases can never be taken. This
+is also the case when a pattern with _ or a name, which matches everything, is not the last one.
+"#,
+r#"
+fn describe(ready: bool) -> string:
+    match ready:
+        true => "ready"
+        true => "done"
+        false => "waiting"
+"#,
+r#"
+fn describe(ready: bool) -> string:
+    match ready:
+        true => "ready"
+        false => "waiting"
+"#;
+
+    InvalidPattern = "E0009", Type, "invalid pattern",
+r#"
+A pattern cannot match the expression it is matched against. Literals must have the type of the expression,
+struct and tuple patterns need one binding per member, ranges must not be empty, and the alternatives of an
+or-pattern must bind the same names with the same types.
+"#,
+r#"
+fn describe(n: int) -> string:
+    match n:
+        "zero" => "nothing"
+        _ => "something"
+"#,
+r#"
+fn describe(n: int) -> string:
+    match n:
+        0 => "nothing"
+        _ => "something"
+"#;
+
+    CannotInferType = "E0010", Type, "cannot infer type",
+r#"
+The type checker cannot work out a type. The generic parameters of a call are inferred from the arguments,
+so every argument with the same parameter must have the same type. Generic parameters which only appear in
+the return type, empty array literals and lambdas which are not passed where their type is known need a type
+annotation.
+"#,
+r#"
+fn pick(a: $T, b: $T) -> $T: a
+
+fn main() -> int:
+    let x = pick(1, "one")
+    0
+"#,
+r#"
+fn pick(a: $T, b: $T) -> $T: a
+
+fn main() -> int:
+    let x = pick(1, 2)
+    0
+"#;
+
+    WrongArgumentCount = "E0011", Type, "wrong number of arguments",
+r#"
+A function is called with more or fewer arguments than it has parameters. Only parameters with a default
+value can be left out. The error lists the parameters which did not get an argument.
+"#,
+r#"
+fn add(a: int, b: int) -> int: a + b
+
+fn main() -> int: add(1)
+"#,
+r#"
+fn add(a: int, b: int) -> int: a + b
+
+fn main() -> int: add(1, 2)
+"#;
+
+    InvalidNamedArgument = "E0012", Type, "invalid named argument",
+r#"
+A named argument must be the name of a parameter of the function, and every parameter gets at most one
+argument, by position or by name. Externals, builtins and function values only take positional arguments.
+"#,
+r#"
+fn area(width: int, height: int) -> int: width * height
+
+fn main() -> int: area(width: 2, depth: 3)
+"#,
+r#"
+fn area(width: int, height: int) -> int: width * height
+
+fn main() -> int: area(width: 2, height: 3)
+"#;
+
+    ImmutableModification = "E0013", Type, "modification of an immutable value",
+r#"
+Bindings declared with let cannot be changed, and neither can the elements and members of values which are
+not mutable. Declare the binding with var to be able to change it.
+"#,
+r#"
+let total = 6
+
+fn reset():
+    total = 0
+"#,
+r#"
+var total = 6
+
+fn reset():
+    total = 0
+"#;
+
+    UnsupportedOperator = "E0014", Type, "unsupported operator",
+r#"
+An operator is used on a type which does not support it, or on operands of different types. Arithmetic needs
+numbers, logical operators need bools. Structs and sum types support an operator when they have the function
+which implements it, for example op_add for + and op_equals for ==.
+"#,
+r#"
+fn negate(s: string) -> string: -s
+"#,
+r#"
+fn negate(n: int) -> int: -n
+"#;
+
+    InvalidCast = "E0015", Type, "invalid cast",
+r#"
+A cast with `as` can convert between numeric types, between pointers and void pointers, from an array to a
+pointer to its elements and from a byte slice to a string. Other types need a function which converts them.
+"#,
+r#"
+fn main() -> int:
+    let s = [1, 2] as string
+    0
+"#,
+r#"
+fn main() -> int:
+    let n = 2.5 as int
+    0
+"#;
+
+    NoValue = "E0016", Type, "expression has no value",
+r#"
+An expression of type void, such as a call to a function without a return type, is used as a value. void has
+no values, so it can also not be the type of an argument, a member or a global.
+"#,
+r#"
+fn tick():
+    _ = 1
+
+fn main() -> int:
+    let x = tick()
+    0
+"#,
+r#"
+fn tick():
+    _ = 1
+
+fn main() -> int:
+    tick()
+    0
+"#;
+
+    UnusedValue = "E0017", Type, "unused value",
+r#"
+The value of an expression in a block is not used, which is usually a mistake, for example a comparison which
+was meant to be an assignment. Only the last expression of a block is its value. Discard a value on purpose
+with _ = expression.
+"#,
+r#"
+fn main() -> int:
+    let x = 3
+    x == 3
+    x
+"#,
+r#"
+fn main() -> int:
+    let x = 3
+    _ = x == 3
+    x
+"#;
+
+    StaticAssertionFailed = "E0018", Type, "static assertion failed",
+r#"
+The condition of a @static_assert is false. The condition must be a constant which the compiler can evaluate,
+in generic functions it is checked for every instance, so it can check the types the function is used with.
+"#,
+r#"
+fn main() -> int:
+    @static_assert(@type_name(4) == "string", "4 must be a string")
+    0
+"#,
+r#"
+fn main() -> int:
+    @static_assert(@type_name(4) == "int32", "4 must be an int32")
+    0
+"#;
+
+    TypeAssertionFailed = "E0019", Type, "type assertion failed",
+r#"
+The expression of an @assert_type does not have the type named in it. Type assertions are only allowed in test
+builds, which are compiled with --cfg feature=test.
+"#,
+r#"
+fn main() -> int:
+    @assert_type(1, "string")
+    0
+"#,
+r#"
+fn main() -> int:
+    @assert_type(1, "int32")
+    0
+"#;
+
+    NotCallable = "E0020", Type, "not callable",
+r#"
+Something is called which is not a function, or a member function is called which the type does not have.
+Only functions, function values and types with a create function can be called.
+"#,
+r#"
+fn main() -> int:
+    let x = 5
+    x(1)
+"#,
+r#"
+fn main() -> int:
+    let x = 5
+    x + 1
+"#;
+
+    NoSuchMember = "E0021", Type, "no such member",
+r#"
+A member is accessed which the type does not have. Tuple members have no names, they are accessed by
+position, starting at .0.
+"#,
+r#"
+fn main() -> int:
+    let t = (1, true)
+    t.2
+"#,
+r#"
+fn main() -> int:
+    let t = (1, true)
+    t.0
+"#;
+
+    NotIterable = "E0022", Type, "not iterable",
+r#"
+A for loop can iterate over arrays, slices, strings and ranges, and over types which have an iterate member
+function returning an iterator, or a next member function returning an optional.
+"#,
+r#"
+struct Point{x: int, y: int}
+
+fn main():
+    for p in Point{1, 2}:
+        _ = p
+"#,
+r#"
+struct Point{x: int, y: int}
+
+fn main():
+    for p in [Point{1, 2}, Point{3, 4}]:
+        _ = p
+"#;
+
+    InvalidBuiltinArgument = "E0023", Type, "invalid argument of a builtin function",
+r#"
+A builtin function is called with an argument of a type it does not support, for example max with a bool
+or hash with a float.
+"#,
+r#"
+fn biggest(a: bool, b: bool) -> bool: max(a, b)
+"#,
+r#"
+fn biggest(a: int, b: int) -> int: max(a, b)
+"#;
+
+    InvalidTryOperator = "E0024", Type, "invalid use of the ? operator",
+r#"
+The ? operator returns the error of a Result from the function it is used in, so it can only be applied to a
+Result, in a function which returns a Result with the same error type.
+"#,
+r#"
+fn parse(s: string) -> Result<int, string>: Err{s}
+
+fn twice(s: string) -> int:
+    let n = parse(s)?
+    n * 2
+"#,
+r#"
+fn parse(s: string) -> Result<int, string>: Err{s}
+
+fn twice(s: string) -> Result<int, string>:
+    let n = parse(s)?
+    Ok{n * 2}
+"#;
+
+    InvalidCInterface = "E0025", Type, "invalid C interface",
+r#"
+Exported functions are called from C and external functions are C functions, so their arguments and return
+values must have a C representation: integers, floats, chars, bools, pointers and structs with #[repr(c)].
+Exported functions cannot be generic, and symbol names must be valid C identifiers.
+"#,
+r#"
+#[export]
+fn length(s: string) -> int: s.len as int
+"#,
+r#"
+#[export]
+fn twice(n: int) -> int: n * 2
+"#;
+
+    ObjectTooLarge = "E0026", Type, "object too large",
+r#"
+Values are stored on the stack or in globals, so every value must fit in the maximum object size, which is
+1 GiB unless it is changed with --max-object-size. Only arrays can make a type that large, use a slice to
+refer to a large number of elements instead.
+"#,
+r#"
+fn first(values: int64[1000000000000]) -> int64: values[0]
+"#,
+r#"
+fn first(values: int64[]) -> int64: values[0]
+"#;
+
+    Redefinition = "E0027", Parse, "redefinition",
+r#"
+Two declarations in a module have the same name. Names are looked up without their module prefix, so a function,
+a global and a type cannot share a name either, and neither can two generic parameters of the same declaration.
+Rename one of them.
+"#,
+r#"
+fn area(width: int) -> int: width * width
+fn area(width: int, height: int) -> int: width * height
+"#,
+r#"
+fn square_area(width: int) -> int: width * width
+fn area(width: int, height: int) -> int: width * height
+"#;
+
+    InvalidAttribute = "E0028", Parse, "invalid attribute",
+r#"
+An attribute is unknown, has invalid arguments, or is put before a declaration it does not apply to. inline and
+export are for functions, repr(c) is for structs, and link and link_name are for external functions.
+"#,
+r#"
+#[inline]
+struct Point:
+    x: int
+    y: int
+"#,
+r#"
+#[repr(c)]
+struct Point:
+    x: int
+    y: int
+"#;
+
+    InvalidMainFunction = "E0029", Type, "invalid main function",
+r#"
+An executable starts at its main function, which takes no arguments, and returns its exit code as an int or
+a uint, or returns nothing to exit with 0. main cannot be generic, and command line arguments are not supported.
+"#,
+r#"
+fn main(code: int) -> int: code
+"#,
+r#"
+fn main() -> int: 0
+"#;
+
+    LimitExceeded = "E0030", Type, "compiler limit exceeded",
+r#"
+The code goes beyond one of the limits of the compiler: expressions nested too deeply, or generic functions which
+keep on requiring new instances of themselves, for example with a type which grows with every call. The error
+shows the chain of instances, change the function so it calls itself with the same types.
+"#,
+r#"
+fn nest(x: $T, n: int) -> int:
+    if n == 0:
+        return 0
+    nest([x], n - 1)
+
+fn main() -> int: nest(1, 3)
+"#,
+r#"
+fn nest(x: $T, n: int) -> int:
+    if n == 0:
+        return 0
+    nest(x, n - 1)
+
+fn main() -> int: nest(1, 3)
+"#;
+}
+
+impl ErrorCode
+{
+    pub fn code(self) -> &'static str
+    {
+        self.explanation().code
+    }
+
+    // Codes are accepted in lower case, and without the E
+    pub fn lookup(code: &str) -> Option<ErrorCode>
+    {
+        let code = code.trim().to_uppercase();
+        ERROR_CODES.iter()
+            .cloned()
+            .find(|c| c.code() == code || c.code()[1..] == code)
+    }
+
+    pub fn error<Msg: Into<String>>(self, span: &Span, msg: Msg) -> CompileError
+    {
+        let ed = ErrorData::with_code(span, msg, self);
+        match self.explanation().kind
+        {
+            ErrorKind::Parse => CompileError::Parse(ed),
+            ErrorKind::Type => CompileError::Type(ed),
+            ErrorKind::UnknownName => CompileError::UnknownName(ed),
+        }
+    }
+
+    pub fn result<T, Msg: Into<String>>(self, span: &Span, msg: Msg) -> CompileResult<T>
+    {
+        Err(self.error(span, msg))
+    }
+
+    // What menhir explain prints
+    pub fn explain(self) -> String
+    {
+        let indent = |code: &str| -> String {
+            code.trim().lines().map(|l| if l.is_empty() {String::new()} else {format!("    {}", l)}).collect::<Vec<_>>().join("\n")
+        };
+
+        let e = self.explanation();
+        format!("{}: {}\n\n{}\n\nThis gives the error:\n\n{}\n\nFixed:\n\n{}\n",
+            e.code, e.title, e.text.trim(), indent(e.example), indent(e.fix))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::collections::HashSet;
+    use super::{ErrorCode, ERROR_CODES};
+
+    #[test]
+    fn test_codes_are_unique()
+    {
+        let mut seen = HashSet::new();
+        for &code in ERROR_CODES {
+            let c = code.code();
+            assert!(c.len() == 5 && c.starts_with('E') && c[1..].chars().all(|d| d.is_ascii_digit()), "Invalid code {}", c);
+            assert!(seen.insert(c), "Code {} is used twice", c);
+            assert_eq!(ErrorCode::lookup(c), Some(code));
+        }
+    }
+
+    #[test]
+    fn test_lookup()
+    {
+        assert_eq!(ErrorCode::lookup("E0006"), Some(ErrorCode::MatchArmTypeMismatch));
+        assert_eq!(ErrorCode::lookup("e0006"), Some(ErrorCode::MatchArmTypeMismatch));
+        assert_eq!(ErrorCode::lookup("0006"), Some(ErrorCode::MatchArmTypeMismatch));
+        assert_eq!(ErrorCode::lookup("E9999"), None);
+        assert_eq!(ErrorCode::lookup(""), None);
+    }
+
+    #[test]
+    fn test_explain()
+    {
+        let text = ErrorCode::MatchArmTypeMismatch.explain();
+        assert!(text.starts_with("E0006: match cases have different types\n\nThe value of a match expression"));
+        assert!(text.contains("This gives the error:\n\n    fn describe(n: int) -> string:\n        match n:\n"));
+        assert!(text.contains("Fixed:\n\n    fn describe(n: int) -> string:\n"));
+        assert!(text.ends_with("        _ => \"not zero\"\n"));
+    }
+}
@@ -4,11 +4,13 @@ use std::fmt;
 use ast::{Import};
 use llvmbackend::OutputType;
 use bincode;
+use buildinfo::BuildInfo;
 use package::Package;
 
 #[derive(Serialize, Deserialize)]
 pub struct ExportLibrary
 {
+    pub build_info: BuildInfo, // Must stay the first member, so it can be read without the imports
     pub name: String,
     pub imports: Vec<Rc<Import>>,
     pub output_type: OutputType,
@@ -16,9 +18,10 @@ pub struct ExportLibrary
 
 impl ExportLibrary
 {
-    pub fn new(pkg: &Package, output_type: OutputType) -> ExportLibrary
+    pub fn new(pkg: &Package, output_type: OutputType, build_info: BuildInfo) -> ExportLibrary
     {
         ExportLibrary{
+            build_info,
             name: pkg.name.clone(),
             output_type,
             imports: pkg.import_data.imports.values().cloned().collect(),
@@ -31,6 +34,13 @@ impl ExportLibrary
             .map_err(|e| format!("Deserialization error: {}", e))
     }
 
+    // Only reads the start of an exports file, so this also works for files of other compiler versions
+    pub fn load_build_info<R: io::Read>(reader: &mut R) -> Result<BuildInfo, String>
+    {
+        bincode::deserialize_from(reader, bincode::Infinite)
+            .map_err(|e| format!("Deserialization error: {}", e))
+    }
+
     pub fn save<W: io::Write>(&self, writer: &mut W) -> Result<(), String>
     {
         bincode::serialize_into(writer, self, bincode::Infinite)
@@ -55,6 +65,7 @@ impl fmt::Display for ExportLibrary
     {
         writeln!(f, "Name: {}", self.name)?;
         writeln!(f, "Type: {}", self.output_type)?;
+        writeln!(f, "Built by: {}", self.build_info)?;
         for import in &self.imports {
             write!(f, "{}", import)?;
         }
@@ -0,0 +1,136 @@
+// Entry point for fuzzers: runs everything up to and including type checking on a source file.
+// Malformed input must result in an error, a panic is a bug which should be fixed.
+use ast::IntSize;
+use compileerror::CompileResult;
+use parser::parse_str;
+use target::Target;
+use timer::PassTimings;
+
+
+pub fn fuzz_compile(source: &[u8]) -> CompileResult<()>
+{
+    // The test programs are written for x86-64, some of them pass structs by value to the C library
+    let target = Target::new(IntSize::I64, "x86_64-pc-linux-gnu");
+    let mut pkg = parse_str(source, "fuzz", &target)?;
+    pkg.type_check(&target, &mut PassTimings::new())
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use std::fs;
+    use std::panic;
+    use std::thread;
+    use super::fuzz_compile;
+    use COMPILER_STACK_SIZE;
+
+    // Words and single punctuation characters, good enough to approximate the tokens of a program
+    fn token_ranges(source: &str) -> Vec<(usize, usize)>
+    {
+        let mut ranges = Vec::new();
+        let mut word_start = None;
+        for (idx, c) in source.char_indices() {
+            let is_word_char = c.is_alphanumeric() || c == '_';
+            if let Some(start) = word_start {
+                if is_word_char {
+                    continue;
+                }
+                ranges.push((start, idx));
+                word_start = None;
+            }
+
+            if is_word_char {
+                word_start = Some(idx);
+            } else if !c.is_whitespace() {
+                ranges.push((idx, idx + c.len_utf8()));
+            }
+        }
+
+        if let Some(start) = word_start {
+            ranges.push((start, source.len()));
+        }
+        ranges
+    }
+
+    // Deterministic, so failures can be reproduced
+    struct XorShift(u64);
+
+    impl XorShift
+    {
+        fn next(&mut self, max: usize) -> usize
+        {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % max as u64) as usize
+        }
+    }
+
+    fn check_no_panic(name: &str, source: String)
+    {
+        let result = panic::catch_unwind(|| {
+            let _ = fuzz_compile(source.as_bytes());
+        });
+        assert!(result.is_ok(), "Compiling {} panicked, source:\n{}", name, source);
+    }
+
+    fn corpus() -> Vec<(String, String)>
+    {
+        let mut programs = Vec::new();
+        for entry in fs::read_dir("testcode").expect("Cannot read testcode directory") {
+            let path = entry.expect("Invalid directory entry").path();
+            if path.extension().map(|ext| ext == "mhr").unwrap_or(false) {
+                let source = fs::read_to_string(&path).expect("Cannot read test program");
+                programs.push((path.to_string_lossy().into_owned(), source));
+            }
+        }
+        programs.sort();
+        programs
+    }
+
+    #[test]
+    fn test_corpus_compiles()
+    {
+        let fuzzer = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(|| {
+            for (name, source) in corpus() {
+                assert!(fuzz_compile(source.as_bytes()).is_ok(), "{} does not compile", name);
+            }
+        });
+
+        fuzzer.expect("Unable to start thread").join().expect("Fuzzing crashed");
+    }
+
+    #[test]
+    fn test_mutated_corpus_does_not_panic()
+    {
+        let fuzzer = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(|| {
+            let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+            for (name, source) in corpus() {
+                let tokens = token_ranges(&source);
+                for _ in 0..8 {
+                    let (start, end) = tokens[rng.next(tokens.len())];
+                    let deleted = format!("{}{}", &source[..start], &source[end..]);
+                    check_no_panic(&format!("{} without {:?}", name, &source[start..end]), deleted);
+
+                    let (start, end) = tokens[rng.next(tokens.len())];
+                    let duplicated = format!("{}{}", &source[..end], &source[start..]);
+                    check_no_panic(&format!("{} with {:?} twice", name, &source[start..end]), duplicated);
+                }
+            }
+        });
+
+        fuzzer.expect("Unable to start thread").join().expect("Fuzzing crashed");
+    }
+
+    #[test]
+    fn test_malformed_input()
+    {
+        assert!(fuzz_compile(&[0x66, 0x6e, 0x20, 0xff, 0xfe]).is_err());
+        assert!(fuzz_compile(b"fn main() -> int: 99999999999999999999999999").is_err());
+        assert!(fuzz_compile(b"fn main() -> int: [1; 99999999999][0]").is_err());
+        assert!(fuzz_compile(b"type X = int").is_err());
+        assert!(fuzz_compile(b"fn main() -> int: 5u").is_ok());
+        assert!(fuzz_compile(b"fn main() -> uint64: 18446744073709551615u").is_ok());
+    }
+}
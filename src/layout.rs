@@ -0,0 +1,571 @@
+use ast::{Type, IntSize, FloatSize, SumType, ptr_type, string_type_representation};
+use target::Target;
+
+// How a sum type is represented in memory
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SumTypeLayout
+{
+    pub tag_size: usize,
+    pub payload_offset: usize,
+    pub payload_size: usize,
+    // None of the cases carry data, so the sum type is just its tag
+    pub enum_as_int: bool,
+    // The case with a pointer-like payload, when the only other case is empty,
+    // there is no tag then, a null pointer in the payload means it is the empty case
+    pub niche_case: Option<usize>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Layout
+{
+    pub size: usize,
+    pub alignment: usize,
+    // Offsets of the members of a struct, or the flag and value of an optional
+    pub offsets: Vec<usize>,
+    pub sum_type: Option<SumTypeLayout>,
+}
+
+impl Layout
+{
+    fn scalar(size: usize, alignment: usize) -> Layout
+    {
+        Layout{
+            size,
+            alignment,
+            offsets: Vec::new(),
+            sum_type: None,
+        }
+    }
+}
+
+fn round_up(value: usize, alignment: usize) -> usize
+{
+    match value % alignment {
+        0 => value,
+        rest => value + alignment - rest,
+    }
+}
+
+fn int_size_in_bytes(int_size: IntSize) -> usize
+{
+    int_size.size_in_bits() as usize / 8
+}
+
+// Primitives are aligned to their size, but never more than the size of a pointer
+fn primitive_layout(size: usize, target: &Target) -> Layout
+{
+    Layout::scalar(size, size.min(int_size_in_bytes(target.int_size)))
+}
+
+// Members are placed in order at the next offset matching their alignment, like a C compiler does
+fn struct_layout<'a, I>(member_types: I, target: &Target) -> Layout
+    where I: Iterator<Item=&'a Type>
+{
+    let mut offsets = Vec::new();
+    let mut size = 0;
+    let mut alignment = 1;
+    for typ in member_types {
+        let l = layout_of(typ, target);
+        size = round_up(size, l.alignment);
+        offsets.push(size);
+        size += l.size;
+        alignment = alignment.max(l.alignment);
+    }
+
+    Layout{
+        size: round_up(size, alignment),
+        alignment,
+        offsets,
+        sum_type: None,
+    }
+}
+
+// Pointers and strings can be null, which is used to represent nil or an empty sum type case without a tag
+pub fn has_null_niche(typ: &Type) -> bool
+{
+    match *typ
+    {
+        Type::Pointer(_) | Type::String => true,
+        Type::Struct(ref st) => st.members.len() == 1 && has_null_niche(&st.members[0].typ),
+        _ => false,
+    }
+}
+
+// A sum type with two cases, of which one has a single pointer-like member and the other no data
+pub fn sum_type_niche_case(st: &SumType) -> Option<usize>
+{
+    if st.cases.len() != 2 {
+        return None;
+    }
+
+    let is_struct = |idx: usize| if let Type::Struct(_) = st.cases[idx].typ {true} else {false};
+    match (is_struct(0), is_struct(1))
+    {
+        (true, false) if has_null_niche(&st.cases[0].typ) => Some(0),
+        (false, true) if has_null_niche(&st.cases[1].typ) => Some(1),
+        _ => None,
+    }
+}
+
+// The tag comes first, followed by the payload of the largest case
+fn sum_type_layout(st: &SumType, target: &Target) -> Layout
+{
+    if let Some(idx) = sum_type_niche_case(st) {
+        let payload = layout_of(&st.cases[idx].typ, target);
+        return Layout{
+            size: payload.size,
+            alignment: payload.alignment,
+            offsets: Vec::new(),
+            sum_type: Some(SumTypeLayout{
+                tag_size: 0,
+                payload_offset: 0,
+                payload_size: payload.size,
+                enum_as_int: false,
+                niche_case: Some(idx),
+            }),
+        };
+    }
+
+    let tag = primitive_layout(int_size_in_bytes(target.int_size), target);
+    let mut payload_size = 0;
+    let mut payload_alignment = 1;
+    let mut enum_as_int = true;
+    for c in &st.cases {
+        if let Type::Struct(_) = c.typ {
+            let l = layout_of(&c.typ, target);
+            payload_size = payload_size.max(l.size);
+            payload_alignment = payload_alignment.max(l.alignment);
+            enum_as_int = false;
+        }
+    }
+
+    let alignment = tag.alignment.max(payload_alignment);
+    let payload_offset = round_up(tag.size, payload_alignment);
+    Layout{
+        size: round_up(payload_offset + payload_size, alignment),
+        alignment,
+        offsets: Vec::new(),
+        sum_type: Some(SumTypeLayout{
+            tag_size: tag.size,
+            payload_offset,
+            payload_size,
+            enum_as_int,
+            niche_case: None,
+        }),
+    }
+}
+
+pub fn layout_of(typ: &Type, target: &Target) -> Layout
+{
+    let ptr_size = int_size_in_bytes(target.int_size);
+    match *typ
+    {
+        Type::Void => Layout::scalar(0, 1),
+        Type::Bool => Layout::scalar(1, 1),
+        Type::Char => primitive_layout(4, target),
+        Type::Int(int_size) | Type::UInt(int_size) => primitive_layout(int_size_in_bytes(int_size), target),
+        Type::Float(FloatSize::F32) => primitive_layout(4, target),
+        Type::Float(FloatSize::F64) => primitive_layout(8, target),
+        // A pthread_t, which is an unsigned long or a pointer
+        Type::Pointer(_) | Type::Func(_) | Type::Enum(_) | Type::Thread => primitive_layout(ptr_size, target),
+        Type::String => {
+            let st = string_type_representation(target.int_size);
+            struct_layout(st.members.iter().map(|m| &m.typ), target)
+        },
+        Type::Slice(ref st) => {
+            struct_layout([ptr_type(st.element_type.clone()), target.native_uint_type.clone()].iter(), target)
+        },
+        Type::Array(ref at) => {
+            let l = layout_of(&at.element_type, target);
+            Layout::scalar(l.size * at.len, l.alignment)
+        },
+        Type::Struct(ref st) => struct_layout(st.members.iter().map(|m| &m.typ), target),
+        // Nil is a null pointer, so there is no flag
+        Type::Optional(ref inner) if has_null_niche(inner) => Layout{offsets: Vec::new(), ..layout_of(inner, target)},
+        Type::Optional(ref inner) => struct_layout([Type::Bool, inner.as_ref().clone()].iter(), target),
+        Type::Sum(ref st) => sum_type_layout(st, target),
+        Type::Interface(_) => {
+            struct_layout([ptr_type(Type::Void), ptr_type(ptr_type(Type::Void))].iter(), target)
+        },
+        Type::Generic(_) |
+        Type::Unresolved(_) |
+        Type::Unknown |
+        Type::SelfType => panic!("Internal Compiler Error: Cannot determine the layout of type {}", typ),
+    }
+}
+
+// The size of a type without any padding, or None if it does not fit in a usize
+fn unpadded_size(typ: &Type, target: &Target) -> Option<usize>
+{
+    match *typ
+    {
+        Type::Array(ref at) => unpadded_size(&at.element_type, target)?.checked_mul(at.len),
+        Type::Struct(ref st) => st.members.iter().try_fold(0usize, |size, m| size.checked_add(unpadded_size(&m.typ, target)?)),
+        Type::Sum(ref st) => {
+            let payload = st.cases.iter()
+                .filter(|c| if let Type::Struct(_) = c.typ {true} else {false})
+                .try_fold(0usize, |size, c| Some(size.max(unpadded_size(&c.typ, target)?)))?;
+            let tag = if sum_type_niche_case(st).is_some() {0} else {int_size_in_bytes(target.int_size)};
+            payload.checked_add(tag)
+        },
+        Type::Optional(ref inner) => unpadded_size(inner, target)?.checked_add(if has_null_niche(inner) {0} else {1}),
+        _ => Some(layout_of(typ, target).size),
+    }
+}
+
+// The size of a type, or None if it is larger than max_size. layout_of overflows on huge arrays, so it is
+// only used once the size without padding is known to be within max_size, padding can at most multiply that
+// by the largest alignment, and max_size is far enough below usize::MAX for that.
+pub fn size_within(typ: &Type, max_size: usize, target: &Target) -> Option<usize>
+{
+    if unpadded_size(typ, target)? > max_size {
+        return None;
+    }
+
+    Some(layout_of(typ, target).size).filter(|&size| size <= max_size)
+}
+
+// Part of a struct which is passed in a single register by the x86-64 System V calling convention
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum EightByte
+{
+    Int(usize), // General purpose register, with the number of bytes used
+    Float,
+    TwoFloats,
+    Double,
+}
+
+// How an argument or return value of an external function is passed by the C calling convention
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum CPassing
+{
+    // Scalars, and everything which is passed by pointer anyway
+    Direct,
+    // A repr(c) struct split over one or two registers
+    Registers(Vec<EightByte>),
+    // A repr(c) struct copied to the stack, or returned through a hidden pointer argument
+    Memory,
+}
+
+const SYSV_INT_REGISTERS: usize = 6;
+const SYSV_SSE_REGISTERS: usize = 8;
+
+pub fn is_x86_64_sysv(target: &Target) -> bool
+{
+    target.arch() == "x86_64" && target.os() != "windows"
+}
+
+// Offsets and sizes of all scalars in a type, and whether they are floats
+fn scalars_of(typ: &Type, offset: usize, target: &Target, scalars: &mut Vec<(usize, usize, bool)>)
+{
+    match *typ
+    {
+        Type::Struct(ref st) => {
+            let l = layout_of(typ, target);
+            for (m, member_offset) in st.members.iter().zip(l.offsets.iter()) {
+                scalars_of(&m.typ, offset + member_offset, target, scalars);
+            }
+        },
+        Type::Array(ref at) => {
+            let element_size = layout_of(&at.element_type, target).size;
+            for idx in 0..at.len {
+                scalars_of(&at.element_type, offset + idx * element_size, target, scalars);
+            }
+        },
+        Type::Float(_) => scalars.push((offset, layout_of(typ, target).size, true)),
+        _ => scalars.push((offset, layout_of(typ, target).size, false)),
+    }
+}
+
+// Structs of at most 16 bytes are passed in registers, one per eightbyte,
+// an eightbyte containing only floats goes in an SSE register, otherwise in a general purpose one
+fn struct_eightbytes(typ: &Type, target: &Target) -> Option<Vec<EightByte>>
+{
+    let size = layout_of(typ, target).size;
+    if size == 0 || size > 16 {
+        return None;
+    }
+
+    let mut scalars = Vec::new();
+    scalars_of(typ, 0, target, &mut scalars);
+    let eightbytes = (0..(size + 7) / 8).map(|idx| {
+        let start = idx * 8;
+        let bytes = (size - start).min(8);
+        let in_eightbyte: Vec<_> = scalars.iter().filter(|&&(offset, _, _)| offset >= start && offset < start + 8).collect();
+        if in_eightbyte.iter().any(|&&(_, _, is_float)| !is_float) {
+            EightByte::Int(bytes)
+        } else if in_eightbyte.iter().any(|&&(_, scalar_size, _)| scalar_size == 8) {
+            EightByte::Double
+        } else if bytes > 4 {
+            EightByte::TwoFloats
+        } else {
+            EightByte::Float
+        }
+    }).collect();
+    Some(eightbytes)
+}
+
+fn is_c_struct(typ: &Type) -> bool
+{
+    match *typ {
+        Type::Struct(ref st) => st.repr_c,
+        _ => false,
+    }
+}
+
+// How the arguments and the return value of an external function are passed on x86-64 System V,
+// a struct which doesn't fit in the remaining registers is passed on the stack
+pub fn c_calling_convention(arg_types: &[Type], return_type: &Type, target: &Target) -> (Vec<CPassing>, CPassing)
+{
+    let mut int_registers = SYSV_INT_REGISTERS;
+    let mut sse_registers = SYSV_SSE_REGISTERS;
+    let ret = if is_c_struct(return_type) {
+        match struct_eightbytes(return_type, target) {
+            Some(eightbytes) => CPassing::Registers(eightbytes),
+            None => {
+                // The hidden pointer to the return value is the first argument
+                int_registers -= 1;
+                CPassing::Memory
+            },
+        }
+    } else {
+        CPassing::Direct
+    };
+
+    let args = arg_types.iter().map(|typ| {
+        if !is_c_struct(typ) {
+            match *typ {
+                Type::Float(_) => sse_registers = sse_registers.saturating_sub(1),
+                _ => int_registers = int_registers.saturating_sub(1),
+            }
+            return CPassing::Direct;
+        }
+
+        match struct_eightbytes(typ, target) {
+            Some(eightbytes) => {
+                let num_int = eightbytes.iter().filter(|eb| match **eb {EightByte::Int(_) => true, _ => false}).count();
+                let num_sse = eightbytes.len() - num_int;
+                if num_int <= int_registers && num_sse <= sse_registers {
+                    int_registers -= num_int;
+                    sse_registers -= num_sse;
+                    CPassing::Registers(eightbytes)
+                } else {
+                    CPassing::Memory
+                }
+            },
+            None => CPassing::Memory,
+        }
+    }).collect();
+
+    (args, ret)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::rc::Rc;
+    use super::*;
+    use ast::*;
+
+    fn mixed_struct() -> Type
+    {
+        struct_type("Mixed", vec![
+            struct_member("a", Type::UInt(IntSize::I8)),
+            struct_member("b", Type::Int(IntSize::I64)),
+            struct_member("c", Type::Int(IntSize::I16)),
+            struct_member("d", ptr_type(Type::Int(IntSize::I32))),
+            struct_member("e", Type::Bool),
+        ])
+    }
+
+    #[test]
+    fn test_struct_layout_64_bit()
+    {
+        let target = Target::new(IntSize::I64, "");
+        assert_eq!(layout_of(&mixed_struct(), &target), Layout{
+            size: 40,
+            alignment: 8,
+            offsets: vec![0, 8, 16, 24, 32],
+            sum_type: None,
+        });
+    }
+
+    #[test]
+    fn test_struct_layout_32_bit()
+    {
+        let target = Target::new(IntSize::I32, "");
+        assert_eq!(layout_of(&mixed_struct(), &target), Layout{
+            size: 24,
+            alignment: 4,
+            offsets: vec![0, 4, 12, 16, 20],
+            sum_type: None,
+        });
+    }
+
+    #[test]
+    fn test_empty_struct_layout()
+    {
+        let target = Target::new(IntSize::I64, "");
+        assert_eq!(layout_of(&struct_type("Empty", Vec::new()), &target), Layout::scalar(0, 1));
+    }
+
+    #[test]
+    fn test_sum_type_layout()
+    {
+        let target = Target::new(IntSize::I32, "");
+        let typ = sum_type("Shape", vec![
+            sum_type_case("Shape::Point", Type::UInt(IntSize::I32)),
+            sum_type_case("Shape::Circle", struct_type("Shape::Circle", vec![
+                struct_member("radius", Type::Float(FloatSize::F64)),
+            ])),
+            sum_type_case("Shape::Name", struct_type("Shape::Name", vec![
+                struct_member("name", Type::String),
+                struct_member("short", Type::Bool),
+            ])),
+        ]);
+
+        assert_eq!(layout_of(&typ, &target), Layout{
+            size: 16,
+            alignment: 4,
+            offsets: Vec::new(),
+            sum_type: Some(SumTypeLayout{
+                tag_size: 4,
+                payload_offset: 4,
+                payload_size: 12,
+                enum_as_int: false,
+                niche_case: None,
+            }),
+        });
+
+        let target = Target::new(IntSize::I64, "");
+        let l = layout_of(&typ, &target);
+        assert_eq!(l.size, 32);
+        assert_eq!(l.sum_type.map(|s| s.payload_offset), Some(8));
+    }
+
+    #[test]
+    fn test_sum_type_without_payload_layout()
+    {
+        let target = Target::new(IntSize::I64, "");
+        let typ = sum_type("Color", vec![
+            sum_type_case("Color::Red", Type::UInt(IntSize::I64)),
+            sum_type_case("Color::Green", Type::UInt(IntSize::I64)),
+        ]);
+
+        assert_eq!(layout_of(&typ, &target), Layout{
+            size: 8,
+            alignment: 8,
+            offsets: Vec::new(),
+            sum_type: Some(SumTypeLayout{
+                tag_size: 8,
+                payload_offset: 8,
+                payload_size: 0,
+                enum_as_int: true,
+                niche_case: None,
+            }),
+        });
+    }
+
+    #[test]
+    fn test_optional_layout()
+    {
+        let target = Target::new(IntSize::I64, "");
+        assert_eq!(layout_of(&optional_type(ptr_type(Type::Int(IntSize::I32))), &target), Layout::scalar(8, 8));
+        assert_eq!(layout_of(&optional_type(Type::String), &target).size, 16);
+
+        // Without a niche, the flag comes first
+        assert_eq!(layout_of(&optional_type(Type::Int(IntSize::I32)), &target), Layout{
+            size: 8,
+            alignment: 4,
+            offsets: vec![0, 4],
+            sum_type: None,
+        });
+    }
+
+    #[test]
+    fn test_size_within()
+    {
+        let target = Target::new(IntSize::I64, "");
+        assert_eq!(size_within(&array_type(mixed_struct(), 4), 160, &target), Some(160));
+        assert_eq!(size_within(&array_type(mixed_struct(), 4), 159, &target), None);
+
+        // Padding is only added when the size without it fits, so the size computation cannot overflow
+        let huge = array_type(array_type(Type::Int(IntSize::I64), 1 << 16), 1 << 48);
+        assert_eq!(size_within(&huge, usize::MAX >> 8, &target), None);
+        let flags = array_type(optional_type(array_type(Type::Bool, 0)), 1 << 62);
+        assert_eq!(size_within(&array_type(flags, 4), 1 << 40, &target), None);
+    }
+
+    #[test]
+    fn test_sum_type_niche_layout()
+    {
+        let target = Target::new(IntSize::I64, "");
+        let node = struct_type("List::Node", vec![struct_member("next", ptr_type(Type::Int(IntSize::I32)))]);
+        let typ = sum_type("List", vec![
+            sum_type_case("List::End", Type::UInt(IntSize::I64)),
+            sum_type_case("List::Node", node.clone()),
+        ]);
+
+        assert_eq!(layout_of(&typ, &target), Layout{
+            size: 8,
+            alignment: 8,
+            offsets: Vec::new(),
+            sum_type: Some(SumTypeLayout{
+                tag_size: 0,
+                payload_offset: 0,
+                payload_size: 8,
+                enum_as_int: false,
+                niche_case: Some(1),
+            }),
+        });
+
+        // Two cases with data need the tag
+        let typ = sum_type("Either", vec![
+            sum_type_case("Either::Left", node.clone()),
+            sum_type_case("Either::Right", node),
+        ]);
+        assert_eq!(layout_of(&typ, &target).sum_type.map(|s| s.niche_case), Some(None));
+    }
+
+    fn c_struct(members: Vec<Type>) -> Type
+    {
+        Type::Struct(Rc::new(StructType{
+            name: "C".into(),
+            members: members.into_iter().map(|t| struct_member("", t)).collect(),
+            repr_c: true,
+        }))
+    }
+
+    #[test]
+    fn test_c_calling_convention()
+    {
+        let target = Target::new(IntSize::I64, "x86_64-pc-linux-gnu");
+        let pair = c_struct(vec![Type::Int(IntSize::I32), Type::Int(IntSize::I32)]);
+        let floats = c_struct(vec![Type::Float(FloatSize::F32), Type::Float(FloatSize::F32), Type::Float(FloatSize::F64)]);
+        let mixed = c_struct(vec![Type::Int(IntSize::I32), Type::Float(FloatSize::F32)]);
+        let bytes = c_struct(vec![Type::UInt(IntSize::I8); 3]);
+        let big = c_struct(vec![Type::Int(IntSize::I64); 3]);
+
+        let (args, ret) = c_calling_convention(&[pair.clone(), floats.clone(), mixed.clone(), bytes.clone()], &pair, &target);
+        assert_eq!(ret, CPassing::Registers(vec![EightByte::Int(8)]));
+        assert_eq!(args, vec![
+            CPassing::Registers(vec![EightByte::Int(8)]),
+            CPassing::Registers(vec![EightByte::TwoFloats, EightByte::Double]),
+            CPassing::Registers(vec![EightByte::Int(8)]),
+            CPassing::Registers(vec![EightByte::Int(3)]),
+        ]);
+
+        // The hidden return value pointer takes the first register
+        let (args, ret) = c_calling_convention(&[big.clone(), Type::Int(IntSize::I64)], &big, &target);
+        assert_eq!(ret, CPassing::Memory);
+        assert_eq!(args, vec![CPassing::Memory, CPassing::Direct]);
+
+        // Five registers are taken, so a struct needing two goes on the stack
+        let mut arg_types = vec![Type::Int(IntSize::I64); 5];
+        arg_types.push(c_struct(vec![Type::Int(IntSize::I64); 2]));
+        arg_types.push(pair.clone());
+        let (args, _) = c_calling_convention(&arg_types, &Type::Void, &target);
+        assert_eq!(args[5], CPassing::Memory);
+        assert_eq!(args[6], CPassing::Registers(vec![EightByte::Int(8)]));
+    }
+}
@@ -0,0 +1,59 @@
+extern crate llvm_sys as llvm;
+extern crate libc;
+extern crate itertools;
+extern crate uuid;
+extern crate toml;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate bincode;
+extern crate time;
+extern crate either;
+
+macro_rules! try_opt {
+    ($e:expr) =>(
+        match $e {
+            Some(v) => v,
+            None => return None,
+        }
+    )
+}
+
+
+mod ast;
+mod compileerror;
+mod errorcodes;
+mod bytecode;
+mod buildcache;
+mod buildinfo;
+mod exportlibrary;
+mod parser;
+mod typechecker;
+mod span;
+mod llvmbackend;
+mod target;
+mod layout;
+mod timer;
+mod package;
+mod packagebuild;
+mod doc;
+#[cfg(test)]
+mod testutil;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+
+pub use compileerror::{CompileResult, CompileError};
+pub use errorcodes::ErrorCode;
+pub use llvmbackend::{OutputType, EmitKind, TargetMachine, llvm_init, llvm_shutdown};
+pub use packagebuild::{PackageData, BuildOptions, InstantiationDump};
+pub use ast::{InstantiationGraph, InstantiationLimits};
+pub use bytecode::{OverflowMode, OptimizationLevel};
+pub use exportlibrary::ExportLibrary;
+pub use buildinfo::{BuildInfo, COMPILER_VERSION};
+pub use buildcache::inspect_cache_entry;
+pub use doc::{DocFormat, DocOptions, document_file, document_instructions};
+pub use typechecker::{TypeMap, TypedSpan};
+pub use target::DEFAULT_MAX_OBJECT_SIZE;
+pub use parser::{lex, Token, TokenKind};
+
+pub const COMPILER_STACK_SIZE: usize = 512 * 1024 * 1024;
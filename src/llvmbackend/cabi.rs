@@ -0,0 +1,217 @@
+use std::ffi::CString;
+use std::ptr;
+use std::rc::Rc;
+use libc::c_uint;
+use llvm::LLVMLinkage;
+use llvm::core::*;
+use llvm::prelude::*;
+
+use ast::*;
+use bytecode::RETURN_VALUE_ARG;
+use layout::{CPassing, EightByte, layout_of, c_calling_convention};
+use super::context::Context;
+use super::function::{function_type, gen_function_sig};
+use super::symboltable::FunctionInstance;
+
+fn is_c_struct(typ: &Type) -> bool
+{
+    match *typ {
+        Type::Struct(ref st) => st.repr_c,
+        _ => false,
+    }
+}
+
+// The arguments and return type the external function was declared with, before return value optimization
+fn declared_signature(sig: &FunctionSignature) -> (&[Argument], Type)
+{
+    match sig.args.last() {
+        Some(arg) if arg.name == RETURN_VALUE_ARG => {
+            let return_type = arg.typ.get_pointer_element_type().expect("Internal Compiler Error: Return value argument must be a pointer");
+            (&sig.args[..sig.args.len() - 1], return_type.clone())
+        },
+        _ => (&sig.args[..], sig.return_type.clone()),
+    }
+}
+
+unsafe fn eightbyte_type(ctx: &Context, eb: EightByte) -> LLVMTypeRef
+{
+    match eb {
+        EightByte::Int(bytes) => LLVMIntTypeInContext(ctx.context, (bytes * 8) as c_uint),
+        EightByte::Float => LLVMFloatTypeInContext(ctx.context),
+        EightByte::TwoFloats => LLVMVectorType(LLVMFloatTypeInContext(ctx.context), 2),
+        EightByte::Double => LLVMDoubleTypeInContext(ctx.context),
+    }
+}
+
+// Packed, because every eightbyte but the last one takes exactly 8 bytes
+unsafe fn eightbytes_type(ctx: &Context, eightbytes: &[EightByte], packed: bool) -> LLVMTypeRef
+{
+    let mut types: Vec<_> = eightbytes.iter().map(|eb| eightbyte_type(ctx, *eb)).collect();
+    LLVMStructTypeInContext(ctx.context, types.as_mut_ptr(), types.len() as c_uint, packed as LLVMBool)
+}
+
+unsafe fn set_alignment(value: LLVMValueRef, typ: &Type, ctx: &Context) -> LLVMValueRef
+{
+    LLVMSetAlignment(value, layout_of(typ, &ctx.target_machine.target).alignment.min(8) as c_uint);
+    value
+}
+
+// Load the eightbytes of a struct, to pass them in registers
+unsafe fn load_eightbytes(ctx: &Context, ptr: LLVMValueRef, typ: &Type, eightbytes: &[EightByte], c_args: &mut Vec<LLVMValueRef>)
+{
+    let parts_type = eightbytes_type(ctx, eightbytes, true);
+    let parts = LLVMBuildBitCast(ctx.builder, ptr, LLVMPointerType(parts_type, 0), cstr!("parts"));
+    for idx in 0..eightbytes.len() {
+        let part_ptr = LLVMBuildStructGEP(ctx.builder, parts, idx as c_uint, cstr!("part_ptr"));
+        c_args.push(set_alignment(LLVMBuildLoad(ctx.builder, part_ptr, cstr!("part")), typ, ctx));
+    }
+}
+
+// Store a struct returned in registers
+unsafe fn store_eightbytes(ctx: &Context, ret: LLVMValueRef, ptr: LLVMValueRef, typ: &Type, eightbytes: &[EightByte])
+{
+    let parts_type = eightbytes_type(ctx, eightbytes, true);
+    let parts = LLVMBuildBitCast(ctx.builder, ptr, LLVMPointerType(parts_type, 0), cstr!("parts"));
+    for idx in 0..eightbytes.len() {
+        let part_ptr = LLVMBuildStructGEP(ctx.builder, parts, idx as c_uint, cstr!("part_ptr"));
+        let part = LLVMBuildExtractValue(ctx.builder, ret, idx as c_uint, cstr!("part"));
+        set_alignment(LLVMBuildStore(ctx.builder, part, part_ptr), typ, ctx);
+    }
+}
+
+// External functions taking or returning repr(c) structs by value follow the x86-64 System V calling convention.
+// They are called through an internal function with the normal signature, which converts between the two.
+pub unsafe fn gen_external_function_sig(ctx: &mut Context, sig: &FunctionSignature)
+{
+    let (args, return_type) = declared_signature(sig);
+    if !args.iter().any(|a| is_c_struct(&a.typ)) && !is_c_struct(&return_type) {
+        gen_function_sig(ctx, sig, sig.link_name.as_ref().map(|symbol| &symbol[..]));
+        return;
+    }
+
+    let arg_types: Vec<Type> = args.iter().map(|a| a.typ.clone()).collect();
+    let (arg_passing, ret_passing) = c_calling_convention(&arg_types, &return_type, &ctx.target_machine.target);
+
+    let mut c_arg_types = Vec::new();
+    let c_return_type = match ret_passing {
+        CPassing::Direct => ctx.resolve_type(&return_type),
+        CPassing::Registers(ref eightbytes) if eightbytes.len() == 1 => eightbyte_type(ctx, eightbytes[0]),
+        CPassing::Registers(ref eightbytes) => eightbytes_type(ctx, eightbytes, false),
+        CPassing::Memory => {
+            c_arg_types.push(LLVMPointerType(ctx.resolve_type(&return_type), 0));
+            LLVMVoidTypeInContext(ctx.context)
+        },
+    };
+
+    for (arg, passing) in args.iter().zip(arg_passing.iter()) {
+        match *passing {
+            CPassing::Direct => {
+                let llvm_type = ctx.resolve_type(&arg.typ);
+                c_arg_types.push(if arg.typ.pass_by_value() {llvm_type} else {LLVMPointerType(llvm_type, 0)});
+            },
+            CPassing::Registers(ref eightbytes) => c_arg_types.extend(eightbytes.iter().map(|eb| eightbyte_type(ctx, *eb))),
+            CPassing::Memory => panic!("Internal Compiler Error: Structs passed on the stack to external functions are not supported"),
+        }
+    }
+
+    let c_function_type = LLVMFunctionType(c_return_type, c_arg_types.as_mut_ptr(), c_arg_types.len() as c_uint, 0);
+    let c_name = CString::new(sig.link_name.as_ref().unwrap_or(&sig.name).as_bytes()).expect("Invalid string");
+    let c_function = LLVMAddFunction(ctx.module, c_name.as_ptr(), c_function_type);
+
+    let wrapper_name = CString::new(format!("{}$c_abi", sig.name)).expect("Invalid string");
+    let wrapper = LLVMAddFunction(ctx.module, wrapper_name.as_ptr(), function_type(ctx, sig));
+    LLVMSetLinkage(wrapper, LLVMLinkage::LLVMInternalLinkage);
+    let entry_bb = LLVMAppendBasicBlockInContext(ctx.context, wrapper, cstr!("entry"));
+    LLVMPositionBuilderAtEnd(ctx.builder, entry_bb);
+
+    let ret_ptr = if return_type != sig.return_type {LLVMGetParam(wrapper, args.len() as c_uint)} else {ptr::null_mut()};
+    let mut c_args = Vec::with_capacity(c_arg_types.len());
+    if ret_passing == CPassing::Memory {
+        c_args.push(ret_ptr);
+    }
+
+    for (idx, (arg, passing)) in args.iter().zip(arg_passing.iter()).enumerate() {
+        let param = LLVMGetParam(wrapper, idx as c_uint);
+        match *passing {
+            CPassing::Registers(ref eightbytes) => load_eightbytes(ctx, param, &arg.typ, eightbytes, &mut c_args),
+            _ => c_args.push(param),
+        }
+    }
+
+    let ret = LLVMBuildCall(ctx.builder, c_function, c_args.as_mut_ptr(), c_args.len() as c_uint, cstr!(""));
+    match ret_passing {
+        CPassing::Direct if return_type == Type::Void => {LLVMBuildRetVoid(ctx.builder);},
+        CPassing::Direct if return_type == sig.return_type => {LLVMBuildRet(ctx.builder, ret);},
+        CPassing::Direct => {
+            // An aggregate which isn't a repr(c) struct, like a string, is stored as it is
+            LLVMBuildStore(ctx.builder, ret, ret_ptr);
+            LLVMBuildRetVoid(ctx.builder);
+        },
+        CPassing::Registers(ref eightbytes) if eightbytes.len() == 1 => {
+            let part_ptr = LLVMBuildBitCast(ctx.builder, ret_ptr, LLVMPointerType(eightbyte_type(ctx, eightbytes[0]), 0), cstr!("part_ptr"));
+            set_alignment(LLVMBuildStore(ctx.builder, ret, part_ptr), &return_type, ctx);
+            LLVMBuildRetVoid(ctx.builder);
+        },
+        CPassing::Registers(ref eightbytes) => {
+            store_eightbytes(ctx, ret, ret_ptr, &return_type, eightbytes);
+            LLVMBuildRetVoid(ctx.builder);
+        },
+        CPassing::Memory => {LLVMBuildRetVoid(ctx.builder);},
+    }
+
+    ctx.add_function(Rc::new(FunctionInstance::new(&sig.name, wrapper, sig.return_type.clone(), sig.get_type())));
+}
+
+#[cfg(test)]
+mod tests
+{
+    use bytecode::{OverflowMode, OptimizationLevel, compile_to_byte_code, optimize_module};
+    use parser::parse_str;
+    use timer::PassTimings;
+    use llvmbackend::{llvm_init, llvm_code_generation};
+
+    fn generate_ir(code: &str) -> String
+    {
+        let target_machine = llvm_init().expect("Cannot create llvm target machine");
+        let target = &target_machine.target;
+        let mut pkg = parse_str(code, "test", target).expect("Parsing failed");
+        pkg.type_check(target, &mut PassTimings::new()).expect("Type check failed");
+        let mut bc_mod = compile_to_byte_code(&pkg, Vec::new(), target, OverflowMode::Check).expect("Compiling to bytecode failed");
+        optimize_module(&mut bc_mod, OptimizationLevel::Minimal);
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        ctx.ir_string()
+    }
+
+    #[test]
+    fn test_c_struct_passing()
+    {
+        let ir = generate_ir(r#"
+#[repr(c)]
+struct Pair:
+    a: int32
+    b: int32
+
+#[repr(c)]
+struct Triple:
+    a: int64
+    b: int64
+    c: int64
+
+extern fn swap(p: Pair) -> Pair
+extern fn widen(p: Pair, n: int32) -> Triple
+
+fn main() -> int:
+    let p = swap(Pair{1 as int32, 2 as int32})
+    let t = widen(p, 3 as int32)
+    t.c
+"#);
+        if !ir.contains("x86_64") {
+            return;
+        }
+
+        // Small structs are passed and returned in registers, bigger ones are returned through a hidden first argument
+        assert!(ir.contains("declare i64 @swap(i64)"));
+        assert!(ir.contains("declare void @widen(<{ i64, i64, i64 }>*, i64, i32)"));
+        assert!(ir.contains("define internal void @\"swap$c_abi\"(<{ i32, i32 }>* %0, <{ i32, i32 }>* %1)"));
+    }
+}
@@ -4,7 +4,11 @@ use std::fs::DirBuilder;
 use std::ptr;
 use llvm::prelude::*;
 use llvm::core::*;
+use llvm::target::LLVMSetModuleDataLayout;
 use ast::{Type, ptr_type};
+use bytecode::BasicBlockRef;
+use compileerror::{CompileResult, CompileError};
+use layout::layout_of;
 use super::CodeGenOptions;
 use super::symboltable::{SymbolTable, FunctionInstance, VariableInstance};
 use super::target::TargetMachine;
@@ -12,7 +16,8 @@ use super::valueref::ValueRef;
 
 
 
-struct StackFrame
+#[derive(Clone)]
+pub struct StackFrame
 {
     pub symbols: SymbolTable,
     pub current_function: LLVMValueRef,
@@ -36,7 +41,10 @@ pub struct Context<'a>
     pub module: LLVMModuleRef,
     pub builder: LLVMBuilderRef,
     pub target_machine: &'a TargetMachine,
-    name: String,
+    pub name: String,
+    pub stack_check: bool,
+    pub threads: bool,
+    pub lowering: Option<(BasicBlockRef, usize)>, // Block and index of the instruction being generated, for error reports
     stack: Vec<StackFrame>,
 }
 
@@ -47,12 +55,19 @@ impl<'a> Context<'a>
         unsafe {
             let context_name = CString::new(module_name).expect("Invalid module name");
             let context = LLVMContextCreate();
+            let module = LLVMModuleCreateWithNameInContext(context_name.as_ptr(), context);
+            let triplet = CString::new(&target_machine.target.triplet[..]).expect("Invalid target triplet");
+            LLVMSetTarget(module, triplet.as_ptr());
+            LLVMSetModuleDataLayout(module, target_machine.target_data);
             Ok(Context::<'a> {
                 context: context,
-                module: LLVMModuleCreateWithNameInContext(context_name.as_ptr(), context),
+                module: module,
                 builder: LLVMCreateBuilderInContext(context),
                 target_machine: target_machine,
                 name: module_name.into(),
+                stack_check: false,
+                threads: false,
+                lowering: None,
                 stack: vec![StackFrame::new(ptr::null_mut())],
             })
         }
@@ -82,7 +97,8 @@ impl<'a> Context<'a>
     pub fn stack_alloc(&mut self, name: &str, typ: &Type) -> LLVMValueRef
     {
         unsafe {
-            let typ = self.resolve_type(typ);
+            let llvm_type = self.resolve_type(typ);
+            let alignment = layout_of(typ, &self.target_machine.target).alignment;
             let func = self.get_current_function();
             let entry_bb = LLVMGetEntryBasicBlock(func);
             let current_bb = LLVMGetInsertBlock(self.builder);
@@ -90,7 +106,9 @@ impl<'a> Context<'a>
             LLVMPositionBuilder(self.builder, entry_bb, LLVMGetFirstInstruction(entry_bb));
 
             let name = CString::new(name).expect("Invalid string");
-            let alloc = LLVMBuildAlloca(self.builder, typ, name.as_ptr());
+            let alloc = LLVMBuildAlloca(self.builder, llvm_type, name.as_ptr());
+            // Our struct types are packed, so LLVM doesn't know their alignment
+            LLVMSetAlignment(alloc, alignment as u32);
             LLVMPositionBuilderAtEnd(self.builder, current_bb); // Position the builder where it was before
             alloc
         }
@@ -115,9 +133,26 @@ impl<'a> Context<'a>
             return vi.value.clone();
         }
 
+        // Functions can be used by name as a value
+        if let Type::Func(_) = *typ {
+            if let Some(fi) = self.get_function(name) {
+                return ValueRef::new(fi.function, fi.typ.clone());
+            }
+        }
+
+        // A variable stored in several blocks is allocated by the first one which is generated, the other blocks
+        // start with a copy of the scopes of a block branching to them, so it has to be in the scope of the function
         let val = self.stack_alloc(name, typ);
         let ret = ValueRef::new(val, ptr_type(typ.clone()));
-        self.set_variable(name, ret.clone());
+        let var = Rc::new(VariableInstance{
+            value: ret.clone(),
+            name: name.into(),
+        });
+        self.stack.iter_mut()
+            .rev()
+            .find(|sf| !sf.current_function.is_null())
+            .expect("Not in a function")
+            .symbols.add_variable(var);
         ret
     }
 
@@ -149,6 +184,23 @@ impl<'a> Context<'a>
         self.stack.pop();
     }
 
+    pub fn stack_depth(&self) -> usize
+    {
+        self.stack.len()
+    }
+
+    // The frames above depth, so they can be restored when generating another block
+    pub fn save_stack(&self, depth: usize) -> Vec<StackFrame>
+    {
+        self.stack[depth..].to_vec()
+    }
+
+    pub fn restore_stack(&mut self, depth: usize, frames: Vec<StackFrame>)
+    {
+        self.stack.truncate(depth);
+        self.stack.extend(frames);
+    }
+
     pub fn get_current_function(&self) -> LLVMValueRef
     {
         for sf in self.stack.iter().rev()
@@ -171,16 +223,39 @@ impl<'a> Context<'a>
 
     pub fn dump_module(&self)
     {
+        // LLVMDumpModule writes to stderr, which gets mixed up with the lines around it
         println!("LLVM IR: {}", self.name);
-        // Dump the module as IR to stdout.
+        print!("{}", self.ir_string());
+        println!("----------------------");
+    }
+
+
+    // The module as LLVM IR text
+    pub fn ir_string(&self) -> String
+    {
+        use std::ffi::CStr;
         unsafe {
-            LLVMDumpModule(self.module);
+            let ir = LLVMPrintModuleToString(self.module);
+            let ret = CStr::from_ptr(ir).to_string_lossy().into_owned();
+            LLVMDisposeMessage(ir);
+            ret
         }
-        println!("----------------------");
     }
 
+    // The IR of a single function, also when it is only partially generated
+    pub fn function_ir_string(&self, name: &str) -> Option<String>
+    {
+        use std::ffi::CStr;
+        self.get_function(name).map(|fi| unsafe {
+            let ir = LLVMPrintValueToString(fi.function);
+            let ret = CStr::from_ptr(ir).to_string_lossy().into_owned();
+            LLVMDisposeMessage(ir);
+            ret
+        })
+    }
 
-    pub unsafe fn gen_object_file(&self, opts: &CodeGenOptions) -> Result<String, String>
+    // Steps which need to happen before any output file is written
+    pub unsafe fn prepare_output(&self, opts: &CodeGenOptions) -> Result<(), String>
     {
         if opts.optimize {
             self.optimize()?;
@@ -193,15 +268,53 @@ impl<'a> Context<'a>
         DirBuilder::new()
             .recursive(true)
             .create(&opts.build_dir)
-            .map_err(|e| format!("Unable to create directory for {}: {}", opts.build_dir, e))?;
-
+            .map_err(|e| format!("Unable to create directory for {}: {}", opts.build_dir, e))
+    }
 
+    pub unsafe fn gen_object_file(&self, opts: &CodeGenOptions) -> Result<String, String>
+    {
+        self.prepare_output(opts)?;
         let obj_file_name = format!("{}/{}.mhr.o", opts.build_dir, self.name);
         println!("  Building {}", obj_file_name);
         self.target_machine.emit_to_file(self.module, &obj_file_name)?;
         Ok(obj_file_name)
     }
 
+    pub fn gen_ir_file(&self, path: &str) -> CompileResult<()>
+    {
+        use libc::c_char;
+        use std::ffi::CStr;
+
+        let file_name = CString::new(path).map_err(|_| format!("Invalid file name {}", path))?;
+        unsafe {
+            let mut error_message: *mut c_char = ptr::null_mut();
+            if LLVMPrintModuleToFile(self.module, file_name.as_ptr(), &mut error_message) != 0 {
+                let msg = if error_message.is_null() {
+                    String::from("unknown error")
+                } else {
+                    let msg = CStr::from_ptr(error_message).to_string_lossy().into_owned();
+                    LLVMDisposeMessage(error_message);
+                    msg
+                };
+                return Err(CompileError::Other(format!("Unable to write LLVM IR to {}: {}", path, msg)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn gen_bitcode_file(&self, path: &str) -> CompileResult<()>
+    {
+        use llvm::bit_writer::LLVMWriteBitcodeToFile;
+
+        let file_name = CString::new(path).map_err(|_| format!("Invalid file name {}", path))?;
+        unsafe {
+            if LLVMWriteBitcodeToFile(self.module, file_name.as_ptr()) != 0 {
+                return Err(CompileError::Other(format!("Unable to write LLVM bitcode to {}", path)));
+            }
+        }
+        Ok(())
+    }
+
     unsafe fn optimize(&self) -> Result<(), String>
     {
         use llvm::transforms::pass_manager_builder::*;
@@ -1,7 +1,9 @@
 use std::ffi::{CString};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
+use std::ptr;
 use libc;
+use llvm::{LLVMIntPredicate, LLVMLinkage, LLVMCallConv};
 use llvm::core::*;
 use llvm::prelude::*;
 
@@ -12,9 +14,18 @@ use super::symboltable::FunctionInstance;
 use super::context::Context;
 use super::instructions::*;
 use super::valueref::ValueRef;
+use super::types::native_llvm_int_type;
 
+// Filled in by main, functions compare the stack pointer against it
+const STACK_LIMIT: &str = "__menhir_stack_limit";
+// The default stack size on linux is 8 MiB, leave some room for the C library and the startup code
+const STACK_CHECK_SIZE: u64 = 7 * 1024 * 1024;
+// Called by pthread_create, and calls the function passed to spawn
+const THREAD_START: &str = "__menhir_thread_start";
 
-pub unsafe fn gen_function_sig(ctx: &mut Context, sig: &FunctionSignature, name_override: Option<&str>)
+
+// Aggregates are passed by pointer, and after return value optimization returned through a pointer argument
+pub unsafe fn function_type(ctx: &Context, sig: &FunctionSignature) -> LLVMTypeRef
 {
     let ret_type = ctx.resolve_type(&sig.return_type);
     let mut arg_types: Vec<_> = sig.args.iter().map(|arg|{
@@ -26,7 +37,12 @@ pub unsafe fn gen_function_sig(ctx: &mut Context, sig: &FunctionSignature, name_
         }
     }).collect();
 
-    let function_type = LLVMFunctionType(ret_type, arg_types.as_mut_ptr(), arg_types.len() as libc::c_uint, 0);
+    LLVMFunctionType(ret_type, arg_types.as_mut_ptr(), arg_types.len() as libc::c_uint, sig.variadic as LLVMBool)
+}
+
+pub unsafe fn gen_function_sig(ctx: &mut Context, sig: &FunctionSignature, name_override: Option<&str>)
+{
+    let function_type = function_type(ctx, sig);
     let llvm_name = name_override.unwrap_or(&sig.name);
     let cstring = CString::new(llvm_name.as_bytes()).expect("Invalid string");
     let name = cstring.as_ptr();
@@ -35,45 +51,102 @@ pub unsafe fn gen_function_sig(ctx: &mut Context, sig: &FunctionSignature, name_
     ctx.add_function(Rc::new(fi));
 }
 
+// Exported functions are called from C under their symbol name, so they don't get the namespaced name
+pub unsafe fn gen_exported_function_sig(ctx: &mut Context, sig: &FunctionSignature, symbol: &str)
+{
+    gen_function_sig(ctx, sig, Some(symbol));
+    let fi = ctx.get_function(&sig.name).expect("Internal Compiler Error: Unknown function");
+    LLVMSetLinkage(fi.function, LLVMLinkage::LLVMExternalLinkage);
+    LLVMSetFunctionCallConv(fi.function, LLVMCallConv::LLVMCCallConv as libc::c_uint);
+}
+
 pub unsafe fn gen_function_ptr(ctx: &mut Context, name: &str, func_ptr: LLVMValueRef, return_type: Type, typ: Type)
 {
     let fi = FunctionInstance::new(name, func_ptr, return_type, typ);
     ctx.add_function(Rc::new(fi));
 }
 
-pub unsafe fn gen_function(ctx: &mut Context, func: &ByteCodeFunction)
+// Check the stack pointer against the stack limit, and abort with the name of the function
+// when it is below it. Main sets the limit instead, because it's the first function to run.
+unsafe fn gen_stack_check(ctx: &Context, name: &str, is_main: bool, check_bb: LLVMBasicBlockRef, entry_bb: LLVMBasicBlockRef)
+{
+    LLVMPositionBuilderAtEnd(ctx.builder, check_bb);
+    let native_int = native_llvm_int_type(ctx.context, ctx.target_machine);
+    // The address of a stack slot of this function, is as good as the stack pointer
+    let marker = LLVMBuildAlloca(ctx.builder, LLVMInt8TypeInContext(ctx.context), cstr!("stack_marker"));
+    let sp = LLVMBuildPtrToInt(ctx.builder, marker, native_int, cstr!("sp"));
+    let stack_limit_name = CString::new(STACK_LIMIT).expect("Invalid string");
+    let stack_limit = LLVMGetNamedGlobal(ctx.module, stack_limit_name.as_ptr());
+
+    if is_main {
+        let limit = LLVMBuildSub(ctx.builder, sp, LLVMConstInt(native_int, STACK_CHECK_SIZE, 0), cstr!("limit"));
+        LLVMBuildStore(ctx.builder, limit, stack_limit);
+        LLVMBuildBr(ctx.builder, entry_bb);
+        return;
+    }
+
+    let limit = LLVMBuildLoad(ctx.builder, stack_limit, cstr!("limit"));
+    let overflowed = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, sp, limit, cstr!("overflowed"));
+    let overflow_bb = LLVMAppendBasicBlockInContext(ctx.context, LLVMGetBasicBlockParent(check_bb), cstr!("stack_overflow"));
+    LLVMBuildCondBr(ctx.builder, overflowed, overflow_bb, entry_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, overflow_bb);
+    let name = CString::new(name).expect("Invalid function name");
+    let stack_overflow = ctx.get_function("__stack_overflow").expect("__stack_overflow not found");
+    let mut args = vec![LLVMBuildGlobalStringPtr(ctx.builder, name.as_ptr(), cstr!("function_name"))];
+    LLVMBuildCall(ctx.builder, stack_overflow.function, args.as_mut_ptr(), args.len() as libc::c_uint, cstr!(""));
+    LLVMBuildUnreachable(ctx.builder);
+}
+
+// Make an argument value available under the name of the argument
+pub unsafe fn bind_argument(ctx: &mut Context, name: &str, typ: &Type, mutable: bool, var: LLVMValueRef)
 {
-    let fi = ctx.get_function(&func.sig.name).expect("Internal Compiler Error: Unknown function");
+    match *typ
+    {
+        Type::Func(ref ft) => {
+            gen_function_ptr(ctx, name, var, ft.return_type.clone(), typ.clone());
+            ctx.set_variable(name, ValueRef::new(var, typ.clone()));
+        },
+
+        _ => {
+            if typ.pass_by_value() {
+                if mutable && !typ.is_pointer() {
+                    // To make it mutable, copy the argument into a local variable
+                    // and use that instead
+                    let argcopy = ctx.stack_alloc("argcopy", typ);
+                    LLVMBuildStore(ctx.builder, var, argcopy);
+                    ctx.set_variable(name, ValueRef::new(argcopy, ptr_type(typ.clone())));
+                } else {
+                    ctx.set_variable(name, ValueRef::new(var, typ.clone()));
+                }
+            } else {
+                ctx.set_variable(name, ValueRef::new(var, ptr_type(typ.clone())));
+            }
+        },
+    }
+}
+
+pub unsafe fn gen_function(ctx: &mut Context, func: &ByteCodeFunction, is_main: bool) -> Result<(), String>
+{
+    let fi = ctx.get_function(&func.sig.name).ok_or_else(|| format!("Unknown function {}", func.sig.name))?;
+    // The stack check has to be the entry block, so stack allocations end up before it
+    let check_bb = if ctx.stack_check {
+        Some(LLVMAppendBasicBlockInContext(ctx.context, fi.function, cstr!("stack_check")))
+    } else {
+        None
+    };
+
     let entry_bb = LLVMAppendBasicBlockInContext(ctx.context, fi.function, cstr!("entry"));
+    if let Some(check_bb) = check_bb {
+        gen_stack_check(ctx, &func.sig.name, is_main, check_bb, entry_bb);
+    }
     LLVMPositionBuilderAtEnd(ctx.builder, entry_bb);
 
     ctx.push_stack(fi.function);
 
-    for (i, arg) in func.sig.args.iter().enumerate() {
+    for (i, arg) in func.sig.args.iter().enumerate().filter(|&(_, arg)| arg.name != "_") {
         let var = LLVMGetParam(fi.function, i as libc::c_uint);
-        match arg.typ
-        {
-            Type::Func(ref ft) => {
-                gen_function_ptr(ctx, &arg.name, var, ft.return_type.clone(), arg.typ.clone());
-                ctx.set_variable(&arg.name, ValueRef::new(var, arg.typ.clone()));
-            },
-
-            _ => {
-                if arg.typ.pass_by_value() {
-                    if arg.mutable && !arg.typ.is_pointer() {
-                        // To make it mutable, copy the argument into a local variable
-                        // and use that instead
-                        let argcopy = LLVMBuildAlloca(ctx.builder, ctx.resolve_type(&arg.typ), cstr!("argcopy"));
-                        LLVMBuildStore(ctx.builder, var, argcopy);
-                        ctx.set_variable(&arg.name, ValueRef::new(argcopy, ptr_type(arg.typ.clone())));
-                    } else {
-                        ctx.set_variable(&arg.name, ValueRef::new(var, arg.typ.clone()));
-                    }
-                } else {
-                    ctx.set_variable(&arg.name, ValueRef::new(var, ptr_type(arg.typ.clone())));
-                }
-            },
-        }
+        bind_argument(ctx, &arg.name, &arg.typ, arg.mutable, var);
     }
 
     let mut blocks = HashMap::new();
@@ -87,15 +160,36 @@ pub unsafe fn gen_function(ctx: &mut Context, func: &ByteCodeFunction)
         }
     }
 
-    for (bb_ref, block) in &func.blocks {
-        let bb = blocks.get(bb_ref).expect("Unknown basic block");
+    // A block starts with the scopes of the first generated block which branches to it, otherwise the scopes
+    // ended before an early return would also be ended for the blocks after it. Blocks are generated in reverse
+    // postorder, so that block comes first, no matter how the blocks are numbered.
+    let depth = ctx.stack_depth();
+    let mut scopes_at_start = HashMap::new();
+    let mut generated = HashSet::new();
+    for bb_ref in func.blocks_in_reverse_postorder() {
+        // Unreachable blocks have no block branching to them, so they only have the scope of the function
+        let frames = scopes_at_start.remove(&bb_ref).unwrap_or_default();
+        ctx.restore_stack(depth, frames);
+        generated.insert(bb_ref);
+
+        let bb = blocks.get(&bb_ref).expect("Unknown basic block");
         LLVMPositionBuilderAtEnd(ctx.builder, *bb);
-        for inst in &block.instructions {
-            gen_instruction(ctx, inst, &blocks);
+        for (idx, inst) in func.blocks[&bb_ref].instructions.iter().enumerate() {
+            ctx.lowering = Some((bb_ref, idx));
+            gen_instruction(ctx, inst, &blocks)?;
+            for target in inst.branch_targets() {
+                if !generated.contains(&target) {
+                    scopes_at_start.entry(target).or_insert_with(|| ctx.save_stack(depth));
+                }
+            }
         }
     }
 
+    ctx.lowering = None;
+    ctx.restore_stack(depth, Vec::new());
+
     ctx.pop_stack();
+    Ok(())
 }
 
 pub unsafe fn add_libc_functions(ctx: &mut Context)
@@ -113,4 +207,144 @@ pub unsafe fn add_libc_functions(ctx: &mut Context)
     );
 
     gen_function_sig(ctx, &memcpy_sig, None);
-}
\ No newline at end of file
+
+    // memmove, used by copy and copy_within
+    let memmove_sig = sig(
+        "memmove",
+        ptr_type(Type::Void),
+        vec![
+            Argument::new("dst", ptr_type(Type::Void), false, Span::default()),
+            Argument::new("src", ptr_type(Type::Void), false, Span::default()),
+            Argument::new("size", ctx.target_machine.target.native_uint_type.clone(), false, Span::default())
+        ],
+        Span::default()
+    );
+
+    gen_function_sig(ctx, &memmove_sig, None);
+
+    // memcmp
+    let memcmp_sig = sig(
+        "memcmp",
+        Type::Int(IntSize::I32),
+        vec![
+            Argument::new("a", ptr_type(Type::Void), false, Span::default()),
+            Argument::new("b", ptr_type(Type::Void), false, Span::default()),
+            Argument::new("size", ctx.target_machine.target.native_uint_type.clone(), false, Span::default())
+        ],
+        Span::default()
+    );
+
+    gen_function_sig(ctx, &memcmp_sig, None);
+
+    // abort, used for runtime errors
+    let abort_sig = sig("abort", Type::Void, Vec::new(), Span::default());
+    gen_function_sig(ctx, &abort_sig, None);
+
+    // dprintf, used to report runtime errors on stderr
+    let mut dprintf_sig = sig(
+        "dprintf",
+        Type::Int(IntSize::I32),
+        vec![
+            Argument::new("fd", Type::Int(IntSize::I32), false, Span::default()),
+            Argument::new("fmt", ptr_type(Type::UInt(IntSize::I8)), false, Span::default()),
+        ],
+        Span::default()
+    );
+    dprintf_sig.variadic = true;
+    gen_function_sig(ctx, &dprintf_sig, None);
+}
+
+// The stack limit and the function called when the stack overflows
+pub unsafe fn add_stack_check_functions(ctx: &mut Context)
+{
+    let native_int = native_llvm_int_type(ctx.context, ctx.target_machine);
+    let stack_limit_name = CString::new(STACK_LIMIT).expect("Invalid string");
+    let stack_limit = LLVMAddGlobal(ctx.module, native_int, stack_limit_name.as_ptr());
+    // Shared between all modules, and zero (so never hit) until main has set it
+    LLVMSetLinkage(stack_limit, LLVMLinkage::LLVMWeakAnyLinkage);
+    // Every thread has its own stack, so also its own limit
+    LLVMSetThreadLocal(stack_limit, 1);
+    LLVMSetInitializer(stack_limit, LLVMConstInt(native_int, 0, 0));
+
+    let stack_overflow_sig = sig(
+        "__stack_overflow",
+        Type::Void,
+        vec![Argument::new("name", ptr_type(Type::UInt(IntSize::I8)), false, Span::default())],
+        Span::default()
+    );
+    gen_function_sig(ctx, &stack_overflow_sig, None);
+
+    let stack_overflow = ctx.get_function("__stack_overflow").expect("__stack_overflow not found");
+    LLVMSetLinkage(stack_overflow.function, LLVMLinkage::LLVMInternalLinkage);
+    let entry_bb = LLVMAppendBasicBlockInContext(ctx.context, stack_overflow.function, cstr!("entry"));
+    LLVMPositionBuilderAtEnd(ctx.builder, entry_bb);
+    let dprintf = ctx.get_function("dprintf").expect("dprintf not found");
+    let abort = ctx.get_function("abort").expect("abort not found");
+    let mut dprintf_args = vec![
+        LLVMConstInt(LLVMInt32TypeInContext(ctx.context), 2, 0), // stderr
+        LLVMBuildGlobalStringPtr(ctx.builder, cstr!("stack overflow in function %s\n"), cstr!("stack_overflow_msg")),
+        LLVMGetParam(stack_overflow.function, 0),
+    ];
+    LLVMBuildCall(ctx.builder, dprintf.function, dprintf_args.as_mut_ptr(), dprintf_args.len() as libc::c_uint, cstr!(""));
+    LLVMBuildCall(ctx.builder, abort.function, ptr::null_mut(), 0, cstr!(""));
+    LLVMBuildUnreachable(ctx.builder);
+}
+
+// Threads are created with pthread_create, which calls __menhir_thread_start, that calls the function passed to spawn.
+// The start function has the signature pthread_create expects, and sets the stack limit of the new thread.
+pub unsafe fn add_thread_functions(ctx: &mut Context)
+{
+    let spawned_type = func_type(Vec::new(), Type::Void);
+    let start_type = func_type(vec![spawned_type.clone()], ptr_type(Type::Void));
+
+    // The argument of the start function is passed as a void pointer, and the function pointer is passed as is
+    let pthread_create_sig = sig(
+        "pthread_create",
+        Type::Int(IntSize::I32),
+        vec![
+            Argument::new("thread", ptr_type(Type::Thread), false, Span::default()),
+            Argument::new("attr", ptr_type(Type::Void), false, Span::default()),
+            Argument::new("start", start_type, false, Span::default()),
+            Argument::new("arg", spawned_type.clone(), false, Span::default()),
+        ],
+        Span::default()
+    );
+    gen_function_sig(ctx, &pthread_create_sig, None);
+
+    let pthread_join_sig = sig(
+        "pthread_join",
+        Type::Int(IntSize::I32),
+        vec![
+            Argument::new("thread", Type::Thread, false, Span::default()),
+            Argument::new("retval", ptr_type(Type::Void), false, Span::default()),
+        ],
+        Span::default()
+    );
+    gen_function_sig(ctx, &pthread_join_sig, None);
+
+    let thread_start_sig = sig(
+        THREAD_START,
+        ptr_type(Type::Void),
+        vec![Argument::new("func", spawned_type, false, Span::default())],
+        Span::default()
+    );
+    gen_function_sig(ctx, &thread_start_sig, None);
+
+    let thread_start = ctx.get_function(THREAD_START).expect("__menhir_thread_start not found");
+    LLVMSetLinkage(thread_start.function, LLVMLinkage::LLVMInternalLinkage);
+    let check_bb = if ctx.stack_check {
+        Some(LLVMAppendBasicBlockInContext(ctx.context, thread_start.function, cstr!("stack_check")))
+    } else {
+        None
+    };
+
+    let entry_bb = LLVMAppendBasicBlockInContext(ctx.context, thread_start.function, cstr!("entry"));
+    if let Some(check_bb) = check_bb {
+        // Like main, the start function is the first function to run on its stack
+        gen_stack_check(ctx, THREAD_START, true, check_bb, entry_bb);
+    }
+
+    LLVMPositionBuilderAtEnd(ctx.builder, entry_bb);
+    LLVMBuildCall(ctx.builder, LLVMGetParam(thread_start.function, 0), ptr::null_mut(), 0, cstr!(""));
+    LLVMBuildRet(ctx.builder, LLVMConstNull(ctx.resolve_type(&ptr_type(Type::Void))));
+}
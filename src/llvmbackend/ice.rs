@@ -0,0 +1,92 @@
+// Internal compiler errors in the code generation, these are bugs in the compiler, so the state of the
+// function being generated is written to a file which can be attached to a bug report
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::Once;
+use bytecode::ByteCodeFunction;
+use super::context::Context;
+
+// Set to 1 to add a backtrace of the compiler to the error of a panic
+pub const BACKTRACE_VAR: &str = "COBRA_ICE_BACKTRACE";
+
+thread_local! {
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static PANIC_MSG: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+fn backtrace_enabled() -> bool
+{
+    env::var(BACKTRACE_VAR).map(|v| v == "1").unwrap_or(false)
+}
+
+// Panics inside catch_ice are recorded instead of printed, all other panics go to the previous hook
+fn install_hook()
+{
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(|c| c.get()) {
+                let payload = info.payload().downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "panic without message".into());
+                let mut msg = match info.location() {
+                    Some(location) => format!("{} (panicked at {})", payload, location),
+                    None => payload,
+                };
+                if backtrace_enabled() {
+                    msg = format!("{}\n{}", msg, Backtrace::force_capture());
+                }
+                PANIC_MSG.with(|m| *m.borrow_mut() = Some(msg));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+// Run f, turning a panic into an error
+pub fn catch_ice<F>(f: F) -> Result<(), String>
+    where F: FnOnce() -> Result<(), String>
+{
+    install_hook();
+    let was_catching = CATCHING.with(|c| c.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(was_catching));
+
+    match result {
+        Ok(r) => r,
+        Err(_) => Err(PANIC_MSG.with(|m| m.borrow_mut().take()).unwrap_or_else(|| "panic without message".into())),
+    }
+}
+
+fn dump_file_name(func_name: &str) -> String
+{
+    let name: String = func_name.chars().map(|c| if c.is_alphanumeric() || c == '_' {c} else {'_'}).collect();
+    format!("menhir-ice-{}-{}.txt", name, process::id())
+}
+
+// Write the error, the byte code of the function and the IR generated so far to a file, and return the error message
+pub fn report_ice(ctx: &Context, func: &ByteCodeFunction, error: &str) -> String
+{
+    let name = &func.sig.name;
+    let location = ctx.lowering
+        .and_then(|(bb_ref, idx)| func.blocks.get(&bb_ref).map(|bb| (bb, idx)))
+        .and_then(|(bb, idx)| bb.instructions.get(idx).map(|inst| format!(" at `{}` in block {}", inst.to_string().trim(), bb.name)))
+        .unwrap_or_default();
+
+    let ir = ctx.function_ir_string(name).unwrap_or_else(|| "<not available>".into());
+    let dump = format!("error:\n{}\n\nbytecode:\n{}\n\nllvm ir:\n{}\n", error, func, ir);
+    let path = env::temp_dir().join(dump_file_name(name));
+    let dumped = match fs::write(&path, dump) {
+        Ok(()) => format!("state dumped to {}", path.display()),
+        Err(e) => format!("unable to dump state to {}: {}", path.display(), e),
+    };
+
+    format!("internal compiler error while compiling `{}`{}: {}, {}", name, location, error, dumped)
+}
@@ -6,8 +6,9 @@ use llvm::*;
 use llvm::core::*;
 use llvm::prelude::*;
 use bytecode::*;
-use ast::{Type, UnaryOperator, BinaryOperator, ptr_type};
-use super::function::gen_function_ptr;
+use span::Span;
+use ast::{Type, IntSize, FloatSize, UnaryOperator, BinaryOperator, ptr_type};
+use super::function::{gen_function_ptr, bind_argument};
 use super::valueref::ValueRef;
 use super::context::Context;
 use super::types::native_llvm_int_type;
@@ -27,9 +28,13 @@ pub unsafe fn const_bool(ctx: &Context, v: bool) -> LLVMValueRef
     LLVMConstInt(LLVMInt1TypeInContext(ctx.context), if v {1} else {0}, 0)
 }
 
-pub unsafe fn const_float(ctx: &Context, v: f64) -> LLVMValueRef
+pub unsafe fn const_float(ctx: &Context, v: f64, float_size: FloatSize) -> LLVMValueRef
 {
-    LLVMConstReal(LLVMDoubleTypeInContext(ctx.context), v)
+    match float_size
+    {
+        FloatSize::F32 => LLVMConstReal(LLVMFloatTypeInContext(ctx.context), v),
+        FloatSize::F64 => LLVMConstReal(LLVMDoubleTypeInContext(ctx.context), v),
+    }
 }
 
 pub unsafe fn const_char(ctx: &Context, c: char) -> LLVMValueRef
@@ -79,18 +84,13 @@ pub unsafe fn get_operand(ctx: &mut Context, operand: &Operand) -> ValueRef
         }
 
         Operand::Const(ref c) => ValueRef::from_const(ctx, c),
-
-        Operand::SizeOf(ref typ) => {
-            let llvm_type = ctx.resolve_type(typ);
-            ValueRef::new(LLVMSizeOf(llvm_type), ctx.target_machine.target.native_uint_type.clone())
-        }
     }
 }
 
 pub unsafe fn copy(ctx: &Context, dst: LLVMValueRef, src: LLVMValueRef, typ: LLVMTypeRef)
 {
     let func = ctx.get_function("memcpy").expect("memcpy not found");
-    let void_ptr_type = LLVMPointerType(LLVMVoidTypeInContext(ctx.context), 0);
+    let void_ptr_type = LLVMPointerType(LLVMInt8TypeInContext(ctx.context), 0);
     let mut args = vec![
         LLVMBuildBitCast(ctx.builder, dst, void_ptr_type, cstr!("dst_cast")),
         LLVMBuildBitCast(ctx.builder, src, void_ptr_type, cstr!("src_cast")),
@@ -99,21 +99,34 @@ pub unsafe fn copy(ctx: &Context, dst: LLVMValueRef, src: LLVMValueRef, typ: LLV
     LLVMBuildCall(ctx.builder, func.function, args.as_mut_ptr(), args.len() as c_uint, cstr!("ac"));
 }
 
+// C default argument promotions for the extra arguments of a variadic function:
+// floats are passed as doubles, and integers smaller then an int as an int
+unsafe fn promote_variadic_arg(ctx: &Context, arg: LLVMValueRef, typ: &Type) -> LLVMValueRef
+{
+    let int32 = LLVMInt32TypeInContext(ctx.context);
+    match *typ
+    {
+        Type::Float(FloatSize::F32) => LLVMBuildFPExt(ctx.builder, arg, LLVMDoubleTypeInContext(ctx.context), cstr!("vararg")),
+        Type::Int(IntSize::I8) | Type::Int(IntSize::I16) => LLVMBuildSExt(ctx.builder, arg, int32, cstr!("vararg")),
+        Type::UInt(IntSize::I8) | Type::UInt(IntSize::I16) | Type::Bool => LLVMBuildZExt(ctx.builder, arg, int32, cstr!("vararg")),
+        _ => arg,
+    }
+}
+
 unsafe fn get_function_arg(ctx: &mut Context, operand: &Operand) -> LLVMValueRef
 {
     match *operand
     {
         Operand::Var(ref v) => {
             let src = ctx.get_variable(&v.name, &v.typ);
-            if !src.typ.is_pointer() {
+            if !src.typ.is_pointer() || v.typ == src.typ {
+                // Not stored in memory, so we already have the value
                 return src.value
             }
 
             let inner_type = src.typ.get_pointer_element_type().expect("Expecting pointer type here");
             if inner_type.pass_by_value() {
                 src.load(ctx)
-            } else if v.typ == src.typ {
-                src.value
             } else {
                 let dst = ctx.stack_alloc("argcopy", inner_type);
                 copy(ctx, dst, src.value, ctx.resolve_type(inner_type));
@@ -145,11 +158,44 @@ unsafe fn gen_unary_op(ctx: &mut Context, dst: &Var, operator: UnaryOperator, sr
     ctx.set_variable(&dst.name, ValueRef::new(result, dst.typ.clone()))
 }
 
+// Strings and byte slices are equal if they have the same length and the same bytes
+unsafe fn gen_string_equals(ctx: &Context, left: LLVMValueRef, right: LLVMValueRef) -> LLVMValueRef
+{
+    let left_len = LLVMBuildLoad(ctx.builder, LLVMBuildStructGEP(ctx.builder, left, 1, cstr!("left_len_ptr")), cstr!("left_len"));
+    let right_len = LLVMBuildLoad(ctx.builder, LLVMBuildStructGEP(ctx.builder, right, 1, cstr!("right_len_ptr")), cstr!("right_len"));
+    let same_len = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, left_len, right_len, cstr!("same_len"));
+
+    // Compare nothing when the lengths differ, so memcmp never reads past the end of the shortest string
+    let num_bytes = LLVMBuildSelect(ctx.builder, same_len, left_len, const_uint(ctx, 0), cstr!("num_bytes"));
+    let left_data = LLVMBuildLoad(ctx.builder, LLVMBuildStructGEP(ctx.builder, left, 0, cstr!("left_data_ptr")), cstr!("left_data"));
+    let right_data = LLVMBuildLoad(ctx.builder, LLVMBuildStructGEP(ctx.builder, right, 0, cstr!("right_data_ptr")), cstr!("right_data"));
+
+    let memcmp = ctx.get_function("memcmp").expect("memcmp not found");
+    let void_ptr_type = LLVMPointerType(LLVMInt8TypeInContext(ctx.context), 0);
+    let mut args = vec![
+        LLVMBuildBitCast(ctx.builder, left_data, void_ptr_type, cstr!("left_cast")),
+        LLVMBuildBitCast(ctx.builder, right_data, void_ptr_type, cstr!("right_cast")),
+        num_bytes
+    ];
+    let cmp = LLVMBuildCall(ctx.builder, memcmp.function, args.as_mut_ptr(), args.len() as c_uint, cstr!("cmp"));
+    let zero = LLVMConstInt(LLVMInt32TypeInContext(ctx.context), 0, 0);
+    let same_data = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, cmp, zero, cstr!("same_data"));
+    LLVMBuildAnd(ctx.builder, same_len, same_data, cstr!("string_eq"))
+}
+
+// The value of an operand, when it has the type of the operand, it isn't stored in memory,
+// so a pointer, like null or the result of a call, must not be loaded from
+unsafe fn get_operand_value(ctx: &mut Context, operand: &Operand) -> LLVMValueRef
+{
+    let vr = get_operand(ctx, operand);
+    if vr.typ == operand.get_type() {vr.value} else {vr.load(ctx)}
+}
+
 unsafe fn gen_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left: &Operand, right: &Operand)
 {
-    let left_type = left.get_type(ctx.target_machine.target.int_size);
-    let left = get_operand(ctx, left).load(ctx);
-    let right = get_operand(ctx, right).load(ctx);
+    let left_type = left.get_type();
+    let left = get_operand_value(ctx, left);
+    let right = get_operand_value(ctx, right);
 
     let value = match (op, left_type)
     {
@@ -176,21 +222,25 @@ unsafe fn gen_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left:
         (BinaryOperator::LessThan, Type::UInt(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, left, right, cstr!("bop")),
         (BinaryOperator::LessThan, Type::Float(_)) => LLVMBuildFCmp(ctx.builder, LLVMRealPredicate::LLVMRealULT, left, right, cstr!("bop")),
         (BinaryOperator::LessThan, Type::Char) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, left, right, cstr!("bop")),
+        (BinaryOperator::LessThan, Type::Pointer(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, left, right, cstr!("bop")),
 
         (BinaryOperator::GreaterThan, Type::Int(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntSGT, left, right, cstr!("bop")),
         (BinaryOperator::GreaterThan, Type::UInt(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGT, left, right, cstr!("bop")),
         (BinaryOperator::GreaterThan, Type::Float(_)) => LLVMBuildFCmp(ctx.builder, LLVMRealPredicate::LLVMRealUGT, left, right, cstr!("bop")),
         (BinaryOperator::GreaterThan, Type::Char) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGT, left, right, cstr!("bop")),
+        (BinaryOperator::GreaterThan, Type::Pointer(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGT, left, right, cstr!("bop")),
 
         (BinaryOperator::LessThanEquals, Type::Int(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntSLE, left, right, cstr!("bop")),
         (BinaryOperator::LessThanEquals, Type::UInt(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULE, left, right, cstr!("bop")),
         (BinaryOperator::LessThanEquals, Type::Float(_)) => LLVMBuildFCmp(ctx.builder, LLVMRealPredicate::LLVMRealULE, left, right, cstr!("bop")),
         (BinaryOperator::LessThanEquals, Type::Char) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULE, left, right, cstr!("bop")),
+        (BinaryOperator::LessThanEquals, Type::Pointer(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULE, left, right, cstr!("bop")),
 
         (BinaryOperator::GreaterThanEquals, Type::Int(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntSGE, left, right, cstr!("bop")),
         (BinaryOperator::GreaterThanEquals, Type::UInt(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGE, left, right, cstr!("bop")),
         (BinaryOperator::GreaterThanEquals, Type::Float(_)) => LLVMBuildFCmp(ctx.builder, LLVMRealPredicate::LLVMRealUGE, left, right, cstr!("bop")),
         (BinaryOperator::GreaterThanEquals, Type::Char) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGE, left, right, cstr!("bop")),
+        (BinaryOperator::GreaterThanEquals, Type::Pointer(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGE, left, right, cstr!("bop")),
 
         (BinaryOperator::Equals, Type::Int(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, left, right, cstr!("bop")),
         (BinaryOperator::Equals, Type::UInt(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, left, right, cstr!("bop")),
@@ -198,6 +248,9 @@ unsafe fn gen_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left:
         (BinaryOperator::Equals, Type::Char) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, left, right, cstr!("bop")),
         (BinaryOperator::Equals, Type::Bool) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, left, right, cstr!("bop")),
         (BinaryOperator::Equals, Type::Enum(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, left, right, cstr!("bop")),
+        (BinaryOperator::Equals, Type::Pointer(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, left, right, cstr!("bop")),
+        (BinaryOperator::Equals, Type::String) |
+        (BinaryOperator::Equals, Type::Slice(_)) => gen_string_equals(ctx, left, right),
 
         (BinaryOperator::NotEquals, Type::Int(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntNE, left, right, cstr!("bop")),
         (BinaryOperator::NotEquals, Type::UInt(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntNE, left, right, cstr!("bop")),
@@ -205,6 +258,9 @@ unsafe fn gen_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left:
         (BinaryOperator::NotEquals, Type::Char) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntNE, left, right, cstr!("bop")),
         (BinaryOperator::NotEquals, Type::Bool) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntNE, left, right, cstr!("bop")),
         (BinaryOperator::NotEquals, Type::Enum(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntNE, left, right, cstr!("bop")),
+        (BinaryOperator::NotEquals, Type::Pointer(_)) => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntNE, left, right, cstr!("bop")),
+        (BinaryOperator::NotEquals, Type::String) |
+        (BinaryOperator::NotEquals, Type::Slice(_)) => LLVMBuildNot(ctx.builder, gen_string_equals(ctx, left, right), cstr!("bop")),
 
         (BinaryOperator::And, Type::Bool) => LLVMBuildAnd(ctx.builder, left, right, cstr!("bop")),
         (BinaryOperator::Or, Type::Bool) => LLVMBuildOr(ctx.builder, left, right, cstr!("bop")),
@@ -216,16 +272,106 @@ unsafe fn gen_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left:
     ctx.set_variable(&dst.name, ValueRef::new(value, dst.typ.clone()));
 }
 
+unsafe fn get_overflow_intrinsic(ctx: &Context, name: &str, int_type: LLVMTypeRef) -> LLVMValueRef
+{
+    let name = CString::new(name).expect("Invalid intrinsic name");
+    let func = LLVMGetNamedFunction(ctx.module, name.as_ptr());
+    if !func.is_null() {
+        return func;
+    }
+
+    let mut member_types = vec![int_type, LLVMInt1TypeInContext(ctx.context)];
+    let ret_type = LLVMStructTypeInContext(ctx.context, member_types.as_mut_ptr(), member_types.len() as c_uint, 0);
+    let mut arg_types = vec![int_type, int_type];
+    let func_type = LLVMFunctionType(ret_type, arg_types.as_mut_ptr(), arg_types.len() as c_uint, 0);
+    LLVMAddFunction(ctx.module, name.as_ptr(), func_type)
+}
+
+// Integer + - and *, which report the operands and abort when the result doesn't fit in the type
+unsafe fn gen_checked_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left: &Operand, right: &Operand, span: &Span)
+{
+    let left_type = left.get_type();
+    let left = get_operand(ctx, left).load(ctx);
+    let right = get_operand(ctx, right).load(ctx);
+
+    let (signed, int_size) = match left_type {
+        Type::Int(int_size) => (true, int_size),
+        Type::UInt(int_size) => (false, int_size),
+        _ => panic!("Operator {} cannot overflow on type {}", op, left_type),
+    };
+
+    let op_name = match op {
+        BinaryOperator::Add => "add",
+        BinaryOperator::Sub => "sub",
+        BinaryOperator::Mul => "mul",
+        _ => panic!("Operator {} cannot overflow on type {}", op, left_type),
+    };
+
+    let int_type = ctx.resolve_type(&left_type);
+    let intrinsic_name = format!("llvm.{}{}.with.overflow.i{}", if signed {"s"} else {"u"}, op_name, int_size.size_in_bits());
+    let intrinsic = get_overflow_intrinsic(ctx, &intrinsic_name, int_type);
+    let mut args = vec![left, right];
+    let result = LLVMBuildCall(ctx.builder, intrinsic, args.as_mut_ptr(), args.len() as c_uint, cstr!("cbop"));
+    let value = LLVMBuildExtractValue(ctx.builder, result, 0, cstr!("cbop_value"));
+    let overflowed = LLVMBuildExtractValue(ctx.builder, result, 1, cstr!("cbop_overflow"));
+
+    let current_function = ctx.get_current_function();
+    let overflow_bb = LLVMAppendBasicBlockInContext(ctx.context, current_function, cstr!("overflow"));
+    let no_overflow_bb = LLVMAppendBasicBlockInContext(ctx.context, current_function, cstr!("no_overflow"));
+    LLVMBuildCondBr(ctx.builder, overflowed, overflow_bb, no_overflow_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, overflow_bb);
+    let int64 = LLVMInt64TypeInContext(ctx.context);
+    let (fmt_spec, left64, right64) = if signed {
+        ("%lld", LLVMBuildSExt(ctx.builder, left, int64, cstr!("left")), LLVMBuildSExt(ctx.builder, right, int64, cstr!("right")))
+    } else {
+        ("%llu", LLVMBuildZExt(ctx.builder, left, int64, cstr!("left")), LLVMBuildZExt(ctx.builder, right, int64, cstr!("right")))
+    };
+
+    let msg = format!("{}: integer overflow: {} {} {} does not fit in {}\n", span.to_string().replace("%", "%%"), fmt_spec, op, fmt_spec, left_type);
+    let msg = CString::new(msg).expect("Invalid overflow message");
+    let dprintf = ctx.get_function("dprintf").expect("dprintf not found");
+    let abort = ctx.get_function("abort").expect("abort not found");
+    let mut dprintf_args = vec![
+        LLVMConstInt(LLVMInt32TypeInContext(ctx.context), 2, 0), // stderr
+        LLVMBuildGlobalStringPtr(ctx.builder, msg.as_ptr(), cstr!("overflow_msg")),
+        left64,
+        right64,
+    ];
+    LLVMBuildCall(ctx.builder, dprintf.function, dprintf_args.as_mut_ptr(), dprintf_args.len() as c_uint, cstr!(""));
+    LLVMBuildCall(ctx.builder, abort.function, ptr::null_mut(), 0, cstr!(""));
+    LLVMBuildUnreachable(ctx.builder);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, no_overflow_bb);
+    ctx.set_variable(&dst.name, ValueRef::new(value, dst.typ.clone()));
+}
+
 unsafe fn gen_cast(ctx: &mut Context, dst: &Var, src: &Operand)
 {
     let operand = get_operand(ctx, src);
-    let src_type = src.get_type(ctx.target_machine.target.int_size);
+    let src_type = src.get_type();
     let casted = match (&dst.typ, &src_type)
     {
-        (&Type::UInt(_), &Type::Int(_)) |
-        (&Type::Int(_), &Type::UInt(_)) =>
+        (&Type::Int(_), &Type::Int(_)) |
+        (&Type::UInt(_), &Type::Int(_)) =>
             LLVMBuildIntCast(ctx.builder, operand.load(ctx), ctx.resolve_type(&dst.typ), cstr!("cast_to_int")),
 
+        // Unsigned values are zero extended, otherwise a byte of 255 would become -1
+        (&Type::Int(_), &Type::UInt(_)) |
+        (&Type::UInt(_), &Type::Char) |
+        (&Type::Char, &Type::UInt(_)) |
+        (&Type::UInt(_), &Type::UInt(_)) |
+        (&Type::UInt(_), &Type::Bool) |
+        (&Type::UInt(_), &Type::Enum(_)) => {
+            let value = operand.load(ctx);
+            let dst_type = ctx.resolve_type(&dst.typ);
+            if LLVMGetIntTypeWidth(LLVMTypeOf(value)) > LLVMGetIntTypeWidth(dst_type) {
+                LLVMBuildTrunc(ctx.builder, value, dst_type, cstr!("cast_to_int"))
+            } else {
+                LLVMBuildZExtOrBitCast(ctx.builder, value, dst_type, cstr!("cast_to_int"))
+            }
+        },
+
         (&Type::Int(_), &Type::Float(_)) =>
             LLVMBuildFPToSI(ctx.builder, operand.load(ctx), ctx.resolve_type(&dst.typ), cstr!("cast_to_int")),
 
@@ -250,12 +396,34 @@ unsafe fn gen_cast(ctx: &mut Context, dst: &Var, src: &Operand)
     ctx.set_variable(&dst.name, ValueRef::new(casted, dst.typ.clone()));
 }
 
-pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &HashMap<BasicBlockRef, LLVMBasicBlockRef>)
+// One constant vtable per concrete type and interface, holding the methods as i8* in the order of the interface's functions
+unsafe fn gen_vtable(ctx: &mut Context, concrete_type: &Type, interface_type: &Type, methods: &[Operand]) -> LLVMValueRef
+{
+    let void_ptr_type = LLVMPointerType(LLVMInt8TypeInContext(ctx.context), 0);
+    let name = CString::new(format!("{}${}$vtable", concrete_type.name(), interface_type.name())).expect("Invalid string");
+    let mut vtable = LLVMGetNamedGlobal(ctx.module, name.as_ptr());
+    if vtable.is_null() {
+        let mut slots: Vec<LLVMValueRef> = methods.iter()
+            .map(|m| LLVMConstBitCast(get_operand(ctx, m).value, void_ptr_type))
+            .collect();
+        vtable = LLVMAddGlobal(ctx.module, LLVMArrayType(void_ptr_type, slots.len() as c_uint), name.as_ptr());
+        LLVMSetLinkage(vtable, LLVMLinkage::LLVMInternalLinkage);
+        LLVMSetGlobalConstant(vtable, 1);
+        LLVMSetInitializer(vtable, LLVMConstArray(void_ptr_type, slots.as_mut_ptr(), slots.len() as c_uint));
+    }
+
+    LLVMConstBitCast(vtable, LLVMPointerType(void_ptr_type, 0))
+}
+
+// Inconsistencies in the bytecode are internal compiler errors, they are reported instead of crashing in LLVM
+pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &HashMap<BasicBlockRef, LLVMBasicBlockRef>) -> Result<(), String>
 {
+    let block = |bbref: &BasicBlockRef| blocks.get(bbref).cloned().ok_or_else(|| format!("Unknown basic block {}", bbref));
+
     //print!(">> {}", instr);
     match *instr
     {
-        Instruction::Store{ref dst, ref src} => {
+        Instruction::Store{ref dst, ref src, ..} => {
             let vr = get_operand(ctx, src);
             let dst_var = ctx.get_variable(&dst.name, &dst.typ);
             dst_var.store(ctx, &vr);
@@ -267,7 +435,9 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
         Instruction::Load{ref dst, ref ptr} => {
             let src_var = ctx.get_variable(&ptr.name, &ptr.typ);
             let val = src_var.load(ctx);
-            ctx.set_variable(&dst.name, ValueRef::new(val, dst.typ.clone()));
+            // Loading something which is not passed by value, results in a pointer to it
+            let typ = if dst.typ.pass_by_value() {dst.typ.clone()} else {ptr_type(dst.typ.clone())};
+            ctx.set_variable(&dst.name, ValueRef::new(val, typ));
         }
 
         Instruction::LoadMember{ref dst, ref obj, ref member_index} |
@@ -277,7 +447,7 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
             ctx.set_variable(&dst.name, member_ptr);
         }
 
-        Instruction::StoreMember{ref obj, ref member_index, ref src} => {
+        Instruction::StoreMember{ref obj, ref member_index, ref src, ..} => {
             let src_val = get_operand(ctx, src);
             let obj_var = ctx.get_variable(&obj.name, &obj.typ);
             obj_var.store_member(ctx, member_index, &src_val);
@@ -307,10 +477,43 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
             gen_binary_op(ctx, dst, *op, left, right);
         }
 
-        Instruction::Call{ref dst, ref func, ref args} => {
-            let func = ctx.get_function(func).expect("Unknown function");
+        Instruction::CheckedBinaryOp{ref dst, ref op, ref left, ref right, ref span} => {
+            gen_checked_binary_op(ctx, dst, *op, left, right, span);
+        }
+
+        Instruction::Select{ref dst, ref cond, ref on_true, ref on_false} => {
+            let cond = get_operand(ctx, cond).load(ctx);
+            let on_true = get_operand(ctx, on_true).load(ctx);
+            let on_false = get_operand(ctx, on_false).load(ctx);
+            let value = LLVMBuildSelect(ctx.builder, cond, on_true, on_false, cstr!("select"));
+            ctx.set_variable(&dst.name, ValueRef::new(value, dst.typ.clone()));
+        }
+
+        Instruction::Call{ref dst, ref func, ref args, ..} => {
+            let func = ctx.get_function(func).ok_or_else(|| format!("Unknown function {}", func))?;
+            let fn_type = LLVMGetElementType(LLVMTypeOf(func.function));
+            if LLVMGetTypeKind(fn_type) == LLVMTypeKind::LLVMFunctionTypeKind {
+                let num_params = LLVMCountParamTypes(fn_type) as usize;
+                if args.len() < num_params || (LLVMIsFunctionVarArg(fn_type) == 0 && args.len() > num_params) {
+                    return Err(format!("Function {} takes {} arguments, but is called with {}", func.name, num_params, args.len()));
+                }
+            }
+
+            let num_fixed_args = match func.typ {
+                Type::Func(ref ft) if ft.variadic => ft.args.len(),
+                _ => args.len(),
+            };
+
             let mut func_args = args.iter()
-                .map(|a| get_function_arg(ctx, a))
+                .enumerate()
+                .map(|(idx, a)| {
+                    let arg = get_function_arg(ctx, a);
+                    if idx < num_fixed_args {
+                        arg
+                    } else {
+                        promote_variadic_arg(ctx, arg, &a.get_type())
+                    }
+                })
                 .collect::<Vec<_>>();
 
             if let Some(ref dst) = *dst {
@@ -337,6 +540,25 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
             dst_var.create_slice(ctx, &data_var, &len_var);
         }
 
+        Instruction::MakeInterface{ref dst, ref data, ref methods} => {
+            let data_type = data.get_type();
+            let concrete_type = data_type.get_pointer_element_type().ok_or_else(|| format!("Interface data must be a pointer, not a {}", data_type))?;
+            let vtable = gen_vtable(ctx, concrete_type, &dst.typ, methods);
+            let data_var = get_operand(ctx, data);
+            let dst_var = ctx.get_variable(&dst.name, &dst.typ);
+            dst_var.create_interface(ctx, &data_var, vtable);
+        }
+
+        Instruction::LoadInterfaceMethod{ref dst, ref obj, method_index} => {
+            let obj_var = ctx.get_variable(&obj.name, &obj.typ);
+            let method = obj_var.load_interface_method(ctx, method_index);
+            let func_ptr = LLVMBuildBitCast(ctx.builder, method, ctx.resolve_type(&dst.typ), cstr!("method_ptr"));
+            ctx.set_variable(&dst.name, ValueRef::new(func_ptr, dst.typ.clone()));
+            if let Type::Func(ref ft) = dst.typ {
+                gen_function_ptr(ctx, &dst.name, func_ptr, ft.return_type.clone(), dst.typ.clone());
+            }
+        }
+
         Instruction::LoadOptionalFlag{ref dst, ref obj} => {
             let obj_var = ctx.get_variable(&obj.name, &obj.typ);
             let opt_flag = obj_var.load_optional_flag(ctx);
@@ -348,6 +570,11 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
             dst_var.store_nil(ctx);
         }
 
+        Instruction::BindArgument{ref dst, ref src, mutable, ..} => {
+            let arg = get_function_arg(ctx, src);
+            bind_argument(ctx, &dst.name, &dst.typ, mutable, arg);
+        }
+
         Instruction::Cast{ref dst, ref src} => {
             gen_cast(ctx, dst, src);
         }
@@ -372,7 +599,12 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
         }
 
         Instruction::Return(ref operand) => {
-            LLVMBuildRet(ctx.builder, get_operand(ctx, operand).load(ctx));
+            let value = if operand.get_type().pass_by_value() {
+                get_function_arg(ctx, operand)
+            } else {
+                get_operand(ctx, operand).load(ctx)
+            };
+            LLVMBuildRet(ctx.builder, value);
         }
 
         Instruction::ReturnVoid => {
@@ -380,18 +612,28 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
         }
 
         Instruction::Branch(ref bbref) => {
-            let llvm_bb = blocks.get(bbref).expect("Unknown basic block");
-            LLVMBuildBr(ctx.builder, *llvm_bb);
+            LLVMBuildBr(ctx.builder, block(bbref)?);
         }
 
         Instruction::BranchIf{ref cond, ref on_true, ref on_false} => {
-            let on_true_bb = blocks.get(on_true).expect("Unknown basic block");
-            let on_false_bb = blocks.get(on_false).expect("Unknown basic block");
-            LLVMBuildCondBr(ctx.builder, get_operand(ctx, cond).load(ctx), *on_true_bb, *on_false_bb);
+            let on_true_bb = block(on_true)?;
+            let on_false_bb = block(on_false)?;
+            LLVMBuildCondBr(ctx.builder, get_operand(ctx, cond).load(ctx), on_true_bb, on_false_bb);
+        }
+
+        Instruction::Switch{ref cond, ref cases, ref default} => {
+            let default_bb = block(default)?;
+            let value = get_operand(ctx, cond).load(ctx);
+            let switch = LLVMBuildSwitch(ctx.builder, value, default_bb, cases.len() as c_uint);
+            for &(ref c, ref bbref) in cases {
+                LLVMAddCase(switch, ValueRef::from_const(ctx, c).value, block(bbref)?);
+            }
         }
 
         Instruction::Delete(ref var) => {
             LLVMBuildFree(ctx.builder, ctx.get_variable(&var.name, &var.typ).value);
         }
     }
+
+    Ok(())
 }
@@ -31,6 +31,7 @@ extern "C" fn resolve_symbol(name: *const libc::c_char, jit_stack: *mut libc::c_
 
         match CStr::from_ptr(name).to_str().unwrap() {
             "memcpy" => libc::memcpy as u64,
+            "memmove" => libc::memmove as u64,
             _ => 0,
         }
     }
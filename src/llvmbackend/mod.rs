@@ -9,8 +9,10 @@ macro_rules! cstr {
 }
 
 
+mod cabi;
 mod context;
 mod function;
+mod ice;
 mod instructions;
 mod symboltable;
 mod target;
@@ -28,16 +30,22 @@ mod jit;
 use std::ffi::CString;
 use std::process::{Output, Command};
 use std::fmt;
+use libc::{c_char, c_uint};
 use llvm::LLVMLinkage;
 use llvm::core::*;
 
-use bytecode::{ByteCodeModule, Constant};
+use ast::{ptr_type, LinkKind, LinkLibrary};
+use bytecode::{ByteCodeModule, ByteCodeGlobal, Instruction};
+use buildinfo::BuildInfo;
+use compileerror::CompileResult;
 pub use self::target::TargetMachine;
 use self::valueref::ValueRef;
-use self::function::{gen_function, gen_function_sig, add_libc_functions};
+use self::function::{gen_function, gen_function_sig, gen_exported_function_sig, add_libc_functions, add_stack_check_functions, add_thread_functions};
+use self::cabi::gen_external_function_sig;
 use self::context::Context;
+use self::ice::{catch_ice, report_ice};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum OutputType
 {
     #[serde(rename = "binary")]
@@ -68,6 +76,15 @@ impl fmt::Display for OutputType
     }
 }
 
+// What the backend writes out, an object file is linked into the final binary or library
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EmitKind
+{
+    Object,
+    LlvmIr,
+    LlvmBitcode,
+}
+
 pub struct CodeGenOptions
 {
     pub build_dir: String,
@@ -113,42 +130,101 @@ pub fn llvm_shutdown()
     }
 }
 
-unsafe fn gen_global(ctx: &mut Context, glob_name: &str, glob_value: &Constant)
+unsafe fn gen_global(ctx: &mut Context, glob_name: &str, glob: &ByteCodeGlobal)
 {
-    let v = ValueRef::from_const(ctx, glob_value);
     let name = CString::new(glob_name.as_bytes()).expect("Invalid string");
-    let glob = LLVMAddGlobal(ctx.module, ctx.resolve_type(&v.typ), name.as_ptr());
-    LLVMSetLinkage(glob, LLVMLinkage::LLVMExternalLinkage);
-    LLVMSetInitializer(glob, v.value);
-    ctx.set_variable(glob_name, v);
+    let typ = ctx.resolve_type(&glob.typ);
+    let llvm_glob = LLVMAddGlobal(ctx.module, typ, name.as_ptr());
+    LLVMSetLinkage(llvm_glob, LLVMLinkage::LLVMExternalLinkage);
+    match glob.init
+    {
+        // No code is needed to build arrays and structs of constants, they are static data
+        Some(ref value) => {
+            LLVMSetInitializer(llvm_glob, ValueRef::const_data(ctx, value));
+            // Arrays can be converted to slices, through which they can be written, so only simple values go in read only memory
+            LLVMSetGlobalConstant(llvm_glob, if glob.mutable || !glob.typ.pass_by_value() {0} else {1});
+        },
+        // Filled in by the __module_init function of the module
+        None => LLVMSetInitializer(llvm_glob, LLVMConstNull(typ)),
+    }
+    ctx.set_variable(glob_name, ValueRef::new(llvm_glob, ptr_type(glob.typ.clone())));
+}
+
+// A constant string, which ends up in the symbol table of the object file, so it can be found with nm or strings
+unsafe fn gen_build_info(ctx: &mut Context, build_info: &BuildInfo)
+{
+    let info = build_info.to_string();
+    let char_type = LLVMInt8TypeInContext(ctx.context);
+    let glob = LLVMAddGlobal(ctx.module, LLVMArrayType(char_type, (info.len() + 1) as c_uint), cstr!("cobra.build-info"));
+    // Libraries have their own, so the linker has to pick one
+    LLVMSetLinkage(glob, LLVMLinkage::LLVMWeakAnyLinkage);
+    LLVMSetGlobalConstant(glob, 1);
+    LLVMSetInitializer(glob, LLVMConstStringInContext(ctx.context, info.as_ptr() as *const c_char, info.len() as c_uint, 0));
 }
 
-pub fn llvm_code_generation<'a>(bc_mod: &ByteCodeModule, target_machine: &'a TargetMachine) -> Result<Context<'a>, String>
+// Spawn and join are calls to pthread functions, modules which don't use them are not linked with pthreads
+fn uses_threads(bc_mod: &ByteCodeModule) -> bool
+{
+    bc_mod.functions.values().any(|func| {
+        let mut found = false;
+        func.for_each_instruction(|instr: &Instruction| {
+            if let Instruction::Call{ref func, ..} = *instr {
+                found = func == "pthread_create" || func == "pthread_join";
+            }
+            !found
+        });
+        found
+    })
+}
+
+pub fn llvm_code_generation<'a>(bc_mod: &ByteCodeModule, target_machine: &'a TargetMachine, stack_check: bool) -> Result<Context<'a>, String>
 {
     let mut ctx = Context::new(&bc_mod.name, target_machine)?;
+    ctx.stack_check = stack_check;
+    ctx.threads = uses_threads(bc_mod);
 
     unsafe {
         add_libc_functions(&mut ctx);
+        if stack_check {
+            add_stack_check_functions(&mut ctx);
+        }
+
+        if ctx.threads {
+            add_thread_functions(&mut ctx);
+        }
 
         for func in &bc_mod.imported_functions {
-            gen_function_sig(&mut ctx, &func.sig, None);
+            // An exported function in another module is only known by its symbol name
+            gen_function_sig(&mut ctx, &func.sig, func.sig.export.as_ref().map(|symbol| &symbol[..]));
         }
 
-        for (glob_name, glob_val) in &bc_mod.globals {
+        let mut globals: Vec<_> = bc_mod.globals.iter().collect();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        for (glob_name, glob_val) in globals {
            gen_global(&mut ctx, glob_name, glob_val);
         }
 
-        for func in bc_mod.functions.values() {
+        if let Some(ref build_info) = bc_mod.build_info {
+            gen_build_info(&mut ctx, build_info);
+        }
+
+        let functions = bc_mod.sorted_functions();
+        for func in &functions {
             if func.sig.name == bc_mod.main_function_name() {
                 gen_function_sig(&mut ctx, &func.sig, Some("main"));
+            } else if func.external {
+                gen_external_function_sig(&mut ctx, &func.sig);
+            } else if let Some(ref symbol) = func.sig.export {
+                gen_exported_function_sig(&mut ctx, &func.sig, symbol);
             } else {
                 gen_function_sig(&mut ctx, &func.sig, None);
             }
         }
 
-        for func in bc_mod.functions.values() {
+        for func in &functions {
             if !func.external {
-                gen_function(&mut ctx, func);
+                let is_main = func.sig.name == bc_mod.main_function_name();
+                catch_ice(|| gen_function(&mut ctx, func, is_main)).map_err(|e| report_ice(&ctx, func, &e))?;
             }
         }
 
@@ -168,6 +244,27 @@ pub struct LinkerFlags
 
 impl LinkerFlags
 {
+    pub fn add_library_path(&mut self, path: &str)
+    {
+        if !self.linker_paths.iter().any(|p| p == path) {
+            self.linker_paths.push(path.into());
+        }
+    }
+
+    // A library named by a link attribute, static ones are searched for in the library paths just like shared ones
+    pub fn add_library(&mut self, lib: &LinkLibrary)
+    {
+        let (libs, name) = match lib.kind
+        {
+            LinkKind::Dylib => (&mut self.linker_shared_libs, lib.name.clone()),
+            LinkKind::Static => (&mut self.linker_static_libs, format!("-l:lib{}.a", lib.name)),
+        };
+
+        if !libs.contains(&name) {
+            libs.push(name);
+        }
+    }
+
     pub fn add_flags(&self, cmd: &mut Command)
     {
         for path in &self.linker_paths {
@@ -184,6 +281,29 @@ impl LinkerFlags
     }
 }
 
+// Write the module as LLVM IR text or bitcode, returns the name of the file
+pub fn emit_llvm_file(ctx: &Context, opts: &CodeGenOptions, kind: EmitKind) -> CompileResult<String>
+{
+    let extension = match kind {
+        EmitKind::LlvmIr => "ll",
+        EmitKind::LlvmBitcode => "bc",
+        EmitKind::Object => return unsafe { Ok(ctx.gen_object_file(opts)?) },
+    };
+
+    unsafe {
+        ctx.prepare_output(opts)?;
+    }
+
+    let file_name = format!("{}/{}.{}", opts.build_dir, ctx.name, extension);
+    println!("  Building {}", file_name);
+    if kind == EmitKind::LlvmIr {
+        ctx.gen_ir_file(&file_name)?;
+    } else {
+        ctx.gen_bitcode_file(&file_name)?;
+    }
+    Ok(file_name)
+}
+
 pub fn link(ctx: &Context, opts: &CodeGenOptions, linker_flags: &LinkerFlags) -> Result<(), String>
 {
     let obj_file = unsafe{
@@ -197,6 +317,9 @@ pub fn link(ctx: &Context, opts: &CodeGenOptions, linker_flags: &LinkerFlags) ->
             let mut cmd = Command::new("gcc");
             cmd.arg("-o").arg(&output_file_path).arg(obj_file);
             linker_flags.add_flags(&mut cmd);
+            if ctx.threads {
+                cmd.arg("-pthread");
+            }
             cmd
         },
 
@@ -210,6 +333,9 @@ pub fn link(ctx: &Context, opts: &CodeGenOptions, linker_flags: &LinkerFlags) ->
             let mut cmd = Command::new("gcc");
             cmd.arg("-shared").arg("-o").arg(&output_file_path).arg(obj_file);
             linker_flags.add_flags(&mut cmd);
+            if ctx.threads {
+                cmd.arg("-pthread");
+            }
             cmd
         }
     };
@@ -228,3 +354,428 @@ pub fn link(ctx: &Context, opts: &CodeGenOptions, linker_flags: &LinkerFlags) ->
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test
+{
+    use std::env;
+    use std::fs;
+    use bytecode::test::{generate_byte_code, generate_byte_code_for_target};
+    use ast::Type;
+    use bytecode::{ByteCodeModule, Instruction, OptimizationLevel, OverflowMode, Var, optimize_module, eliminate_unused_functions, void_call_instr};
+    use buildinfo::{BuildInfo, COMPILER_VERSION};
+    use span::Span;
+    use super::{llvm_init, llvm_code_generation};
+
+    const PROGRAM: &str = r#"
+fn add(a: int, b: int) -> int: a + b
+
+fn main() -> int:
+    add(4, 5)
+"#;
+
+    // The byte code is generated for a 32 bit target, so leave out the unused prelude functions
+    fn generate_program() -> ByteCodeModule
+    {
+        let mut bc_mod = generate_byte_code(PROGRAM, false).expect("Compile failed");
+        eliminate_unused_functions(&mut bc_mod);
+        bc_mod
+    }
+
+    #[test]
+    fn test_ir_string()
+    {
+        let bc_mod = generate_program();
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+
+        let ir = ctx.ir_string();
+        assert!(ir.contains(&format!("target triple = \"{}\"", target_machine.target.triplet)));
+        assert!(ir.contains("define i32 @main()"));
+        assert!(ir.contains("define i32 @\"test::add\"(i32"));
+    }
+
+    #[test]
+    fn test_gen_ir_file()
+    {
+        let bc_mod = generate_program();
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+
+        let path = env::temp_dir().join("menhir_test_gen_ir_file.ll");
+        let path = path.to_str().expect("Invalid path");
+        ctx.gen_ir_file(path).expect("Unable to write IR");
+        let ir = fs::read_to_string(path).expect("Unable to read IR");
+        let _ = fs::remove_file(path);
+        assert_eq!(ir, ctx.ir_string());
+
+        assert!(ctx.gen_ir_file("/nonexistent/dir/test.ll").is_err());
+    }
+
+    #[test]
+    fn test_stack_check()
+    {
+        let bc_mod = generate_program();
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        let ir = ctx.ir_string();
+        assert!(!ir.contains("__menhir_stack_limit"));
+        assert!(!ir.contains("stack_check:"));
+
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, true).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+        let ir = ctx.ir_string();
+        assert!(ir.contains("@__menhir_stack_limit = weak thread_local global"));
+        assert!(ir.contains("define internal void @__stack_overflow(i8*"));
+        assert!(ir.contains("%limit = sub"));
+        assert!(ir.contains("icmp ult"));
+        assert!(ir.contains("call void @__stack_overflow"));
+        assert!(ir.contains("c\"test::add\\00\""));
+    }
+
+    #[test]
+    fn test_build_info()
+    {
+        let mut bc_mod = generate_program();
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(!ctx.ir_string().contains("cobra.build-info"));
+
+        let build_info = BuildInfo::new(&target_machine.target, OptimizationLevel::Normal, OverflowMode::Wrap);
+        bc_mod.build_info = Some(build_info.clone());
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+        let ir = ctx.ir_string();
+        assert!(ir.contains("@cobra.build-info = weak constant"));
+        assert!(ir.contains(&format!("c\"{}\\00\"", build_info)));
+        assert!(ir.contains(&format!("menhir {} (commit", COMPILER_VERSION)));
+        assert!(ir.contains("--opt-level 2, --release-overflow wrap"));
+    }
+
+    #[test]
+    fn test_variadic_argument_promotion()
+    {
+        let prog = r#"
+extern fn printf(fmt: *uint8, ...) -> int32
+
+fn main() -> int:
+    let f = 3 as float32
+    let d = 2.5
+    let i8 = -1 as int8
+    let i16 = -2 as int16
+    let u8 = 3 as uint8
+    let u16 = 4 as uint16
+    printf("%f %f %d %d %u %u %d %d\n".data, f, d, i8, i16, u8, u16, true, 5) as int
+"#;
+        let mut bc_mod = generate_byte_code(prog, false).expect("Compilation failed");
+        eliminate_unused_functions(&mut bc_mod);
+
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+
+        // float32 is passed as a double, integers smaller than an int32 as an int32, the others as they are
+        let ir = ctx.ir_string();
+        assert!(ir.contains("declare i32 @printf(i8*, ...)"));
+        assert_eq!(ir.matches(" = fpext float ").count(), 1);
+        assert_eq!(ir.matches(" = sext i8 ").count(), 1);
+        assert_eq!(ir.matches(" = sext i16 ").count(), 1);
+        assert_eq!(ir.matches(" = zext i8 ").count(), 1);
+        assert_eq!(ir.matches(" = zext i16 ").count(), 1);
+        assert_eq!(ir.matches(" = zext i1 ").count(), 1);
+
+        let call = ir.lines().find(|l| l.contains("call i32 (i8*, ...) @printf(")).expect("No call to printf");
+        let args = &call[call.find("@printf(").expect("No arguments") + 8..call.len() - 1];
+        let arg_types: Vec<&str> = args.split(", ").map(|a| a.split(' ').next().unwrap_or("")).collect();
+        assert_eq!(arg_types, vec!["i8*", "double", "double", "i32", "i32", "i32", "i32", "i32", "i32"]);
+    }
+
+    #[test]
+    fn test_static_global_data()
+    {
+        use itertools::free::join;
+
+        let entries = join((0..100).map(|i| format!("Entry{{{}, \"entry {}\"}}", i, i)), ", ");
+        let prog = format!("struct Entry:\n    key: int\n    name: string\n\nlet TABLE = [{}]\n\nfn main() -> int: TABLE[42].key + TABLE[99].name.len as int\n", entries);
+        let mut bc_mod = generate_byte_code(&prog, false).expect("Compilation failed");
+        eliminate_unused_functions(&mut bc_mod);
+
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+
+        // The table is a single global, there is no code building it at startup
+        let ir = ctx.ir_string();
+        assert_eq!(ir.matches("@\"test::TABLE\" = global [100 x").count(), 1);
+        assert!(!ir.contains("__module_init"));
+        assert!(ir.matches("store ").count() < 10);
+        assert!(ir.contains("c\"entry 99\\00\""));
+    }
+
+    #[test]
+    fn test_exported_functions()
+    {
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let mut bc_mod = generate_byte_code(r#"
+#[export("menhir_add")]
+fn add(a: int32, b: int32) -> int32: a + b
+
+#[export]
+fn negate(b: bool) -> bool: !b
+
+fn main() -> int: 0
+"#, false).expect("Compilation failed");
+
+        // Exported functions are kept, even though main doesn't call them
+        eliminate_unused_functions(&mut bc_mod);
+        optimize_module(&mut bc_mod, OptimizationLevel::Minimal);
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        let ir = ctx.ir_string();
+
+        // External linkage and the C calling convention are the defaults, so they don't show up in the IR
+        assert!(ir.contains("define i32 @menhir_add(i32"));
+        assert!(ir.contains("define i1 @negate(i1"));
+        assert!(!ir.contains("test::add"));
+        assert!(!ir.contains("test::negate"));
+    }
+
+    #[test]
+    fn test_slice_copy()
+    {
+        // Slices have lengths of the native size, so the byte code must be generated for the same target
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let mut bc_mod = generate_byte_code_for_target(r#"
+fn main() -> int:
+    var a = [1, 2, 3, 4]
+    var b = [0, 0, 0]
+    let n = copy(b, a)
+    copy_within(a, 0, 1, 3)
+    n + b[2]
+"#, false, OverflowMode::Check, &target_machine.target).expect("Compilation failed");
+
+        eliminate_unused_functions(&mut bc_mod);
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+        let ir = ctx.ir_string();
+
+        // Both copies move the bytes in one call, instead of looping over the elements
+        assert_eq!(ir.matches("call i8* @memmove(").count(), 2);
+    }
+
+    #[test]
+    fn test_dereference_of_a_pattern_binding()
+    {
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let mut bc_mod = generate_byte_code(r#"
+struct Holder:
+    ptr: *int
+
+fn value(h: Holder) -> int:
+    match h:
+        Holder{p} => *p
+
+fn main() -> int:
+    let x = 20
+    value(Holder{&x})
+"#, false).expect("Compilation failed");
+
+        eliminate_unused_functions(&mut bc_mod);
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+    }
+
+    #[test]
+    fn test_if_let_with_multiple_bindings()
+    {
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let mut bc_mod = generate_byte_code(r#"
+fn half(v: int) -> ?int:
+    if v % 2 == 0:
+        v / 2
+    else
+        nil
+
+fn add_halves(a: int, b: int) -> int:
+    if let x = half(a), y = half(b):
+        x + y
+    else
+        -1
+
+fn main() -> int:
+    add_halves(4, 6)
+"#, false).expect("Compilation failed");
+
+        // The block for a missing second value is only branched to from a block numbered after it,
+        // and the optional results have to be returned through an argument, like in a build
+        eliminate_unused_functions(&mut bc_mod);
+        optimize_module(&mut bc_mod, OptimizationLevel::Normal);
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+    }
+
+    #[test]
+    fn test_external_link_names()
+    {
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let mut bc_mod = generate_byte_code(r#"
+#[link(name = "m")]
+#[link_name = "sqrt"]
+extern fn square_root(x: double) -> double
+
+fn main() -> int:
+    if square_root(16.0) > 3.0: 0 else 1
+"#, false).expect("Compilation failed");
+
+        eliminate_unused_functions(&mut bc_mod);
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        let ir = ctx.ir_string();
+
+        // The function is declared and called under its link_name, the name from the source is gone
+        assert!(ir.contains("declare double @sqrt(double"));
+        assert!(ir.contains("call double @sqrt("));
+        assert!(!ir.contains("square_root"));
+    }
+
+    #[test]
+    fn test_interface_vtables()
+    {
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let mut bc_mod = generate_byte_code(r#"
+interface Shape:
+    fn area(self) -> int
+
+struct Rect:
+    width: int
+    height: int
+
+struct Square:
+    side: int
+
+fn Rect.area(self) -> int: self.width * self.height
+
+fn Square.area(self) -> int: self.side * self.side
+
+fn area(s: Shape) -> int: s.area()
+
+fn main() -> int: area(Rect{2, 3}) + area(Square{2}) + area(Square{3})
+"#, false).expect("Compilation failed");
+        eliminate_unused_functions(&mut bc_mod);
+
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        let ir = ctx.ir_string();
+
+        // One vtable per type and interface, no matter how many conversions there are
+        assert_eq!(ir.matches("@\"test::Rect$test::Shape$vtable\" = internal constant [1 x i8*]").count(), 1);
+        assert_eq!(ir.matches("@\"test::Square$test::Shape$vtable\" = internal constant [1 x i8*]").count(), 1);
+    }
+
+    // Insert a broken instruction at the start of test::add and return the error of the code generation
+    fn internal_compiler_error(inst: Instruction) -> String
+    {
+        let mut bc_mod = generate_program();
+        bc_mod.functions.get_mut("test::add").expect("No add function")
+            .blocks.get_mut(&0).expect("No entry block")
+            .instructions.insert(0, inst);
+
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        llvm_code_generation(&bc_mod, &target_machine, false).err().expect("Code generation should fail")
+    }
+
+    fn read_dump(msg: &str) -> String
+    {
+        let path = &msg[msg.find("state dumped to ").expect("No dump file") + "state dumped to ".len()..];
+        let dump = fs::read_to_string(path).expect("Unable to read dump file");
+        let _ = fs::remove_file(path);
+        dump
+    }
+
+    #[test]
+    fn test_internal_compiler_error()
+    {
+        let msg = internal_compiler_error(void_call_instr("test::missing", vec![], &Span::default()));
+        assert!(msg.starts_with("internal compiler error while compiling `test::add` at `call test::missing` in block entry: "));
+        assert!(msg.contains("Unknown function test::missing"));
+        let dump = read_dump(&msg);
+        assert!(dump.contains("Unknown function test::missing"));
+        assert!(dump.contains("bytecode:\n"));
+        assert!(dump.contains("call test::missing"));
+        assert!(dump.contains("llvm ir:\n"));
+        assert!(dump.contains("@\"test::add\""));
+
+        let msg = internal_compiler_error(void_call_instr("test::add", vec![], &Span::default()));
+        assert!(msg.contains("Function test::add takes 2 arguments, but is called with 0"));
+        read_dump(&msg);
+
+        // Panics are caught as well
+        let msg = internal_compiler_error(Instruction::StackAlloc(Var::named("broken", Type::Unknown)));
+        assert!(msg.starts_with("internal compiler error while compiling `test::add`"));
+        assert!(msg.contains("all types must be known before code generation"));
+        read_dump(&msg);
+    }
+
+    #[test]
+    fn test_threads()
+    {
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let ctx = llvm_code_generation(&generate_program(), &target_machine, false).expect("Code generation failed");
+        assert!(!ctx.threads);
+        assert!(!ctx.ir_string().contains("pthread"));
+
+        let mut bc_mod = generate_byte_code(r#"
+var done = false
+
+fn work():
+    done = true
+
+fn main() -> int:
+    let t = spawn(work)
+    join(t)
+    if done: 1 else 0
+"#, false).expect("Compile failed");
+        eliminate_unused_functions(&mut bc_mod);
+
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+        assert!(ctx.threads);
+        let ir = ctx.ir_string();
+        assert!(ir.contains("define internal i8* @__menhir_thread_start(void ()*"));
+        assert!(ir.contains("call i32 @pthread_create("));
+        assert!(ir.contains("* @__menhir_thread_start, void ()*"));
+        assert!(ir.contains("call i32 @pthread_join("));
+        assert!(ir.contains("label %spawn_invalid"));
+        assert!(ir.contains("label %join_invalid"));
+
+        // Spawned threads set their own stack limit
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, true).expect("Code generation failed");
+        assert!(ctx.verify().is_ok());
+        let ir = ctx.ir_string();
+        let start = ir.find("define internal i8* @__menhir_thread_start").expect("No thread start function");
+        let end = start + ir[start..].find("\n}\n").expect("Unterminated function");
+        assert!(ir[start..end].contains("@__menhir_stack_limit"));
+        assert!(ir[start..end].contains("%limit = sub"));
+    }
+
+    // Needs llvm-dis in the PATH, run with cargo test --features llvm-tools
+    #[cfg(feature = "llvm-tools")]
+    #[test]
+    fn test_bitcode_roundtrip()
+    {
+        use std::process::Command;
+
+        let bc_mod = generate_program();
+        let target_machine = llvm_init().expect("Unable to initialize LLVM");
+        let ctx = llvm_code_generation(&bc_mod, &target_machine, false).expect("Code generation failed");
+
+        let path = env::temp_dir().join("menhir_test_bitcode_roundtrip.bc");
+        let path = path.to_str().expect("Invalid path");
+        ctx.gen_bitcode_file(path).expect("Unable to write bitcode");
+        let output = Command::new("llvm-dis").arg("-o").arg("-").arg(path).output().expect("Unable to run llvm-dis");
+        let _ = fs::remove_file(path);
+        assert!(output.status.success());
+
+        let ir = String::from_utf8(output.stdout).expect("Invalid output from llvm-dis");
+        assert!(ir.contains("define i32 @\"test::add\"(i32"));
+        assert!(ir.contains("target triple"));
+    }
+}
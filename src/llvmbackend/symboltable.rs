@@ -32,6 +32,7 @@ pub struct VariableInstance
     pub name: String,
 }
 
+#[derive(Clone)]
 pub struct SymbolTable
 {
     vars: HashMap<String, Rc<VariableInstance>>,
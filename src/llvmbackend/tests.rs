@@ -34,7 +34,7 @@ impl Test
         }
     }
 
-    pub fn run(&self, dump: bool, target_machine: &TargetMachine) -> Result<i64, String>
+    pub fn run(&self, dump: bool, lvl: OptimizationLevel, target_machine: &TargetMachine) -> Result<i64, String>
     {
         let mut bc_mod = match generate_byte_code(&self.code, dump)
         {
@@ -42,8 +42,8 @@ impl Test
             Err(e) => return Err(format!("Compile error: {}", e)),
         };
 
-        optimize_module(&mut bc_mod, OptimizationLevel::Normal);
-        let mut ctx = llvm_code_generation(&bc_mod, target_machine)?;
+        optimize_module(&mut bc_mod, lvl);
+        let mut ctx = llvm_code_generation(&bc_mod, target_machine, false)?;
         unsafe {
             let jit = JIT::new()?;
             let llvm_module = mem::replace(&mut ctx.module, ptr::null_mut());
@@ -53,10 +53,10 @@ impl Test
 }
 
 
-fn run_test(prog: &Path, dump: bool, target_machine: &TargetMachine) -> Result<i64, String>
+fn run_test(prog: &Path, dump: bool, lvl: OptimizationLevel, target_machine: &TargetMachine) -> Result<i64, String>
 {
     let test = Test::load(prog);
-    let ret = test.run(dump, target_machine)?;
+    let ret = test.run(dump, lvl, target_machine)?;
     if ret != test.ret {
         Err(format!("Return value doesn't match: {}, expecting {}", ret, test.ret))
     } else {
@@ -75,10 +75,13 @@ fn run_tests_in_directory(dir: fs::ReadDir, target_machine: &TargetMachine) -> u
 
     for path in &paths {
         if path.extension().unwrap_or_default() == "mhr" {
-            let r = run_test(&path, false, target_machine);
-            println!("{}: {:?}", path.file_stem().unwrap().to_str().unwrap(), r);
-            if !r.is_ok()  {
-                failures += 1;
+            // Every optimization level must give the same result
+            for lvl in &[OptimizationLevel::Off, OptimizationLevel::Minimal, OptimizationLevel::Normal] {
+                let r = run_test(&path, false, *lvl, target_machine);
+                println!("{} ({:?}): {:?}", path.file_stem().unwrap().to_str().unwrap(), lvl, r);
+                if !r.is_ok()  {
+                    failures += 1;
+                }
             }
         }
     }
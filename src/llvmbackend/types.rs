@@ -1,10 +1,12 @@
-use std::ptr;
+use std::rc::Rc;
 use libc::*;
 use llvm::core::*;
 use llvm::prelude::*;
 
 use super::target::TargetMachine;
 use ast::*;
+use layout::{layout_of, has_null_niche};
+use target::Target;
 
 unsafe fn string_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine) -> LLVMTypeRef
 {
@@ -29,35 +31,47 @@ unsafe fn array_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMac
 
 unsafe fn sum_type_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine, st: &SumType) -> LLVMTypeRef
 {
-    let mut member_types = vec![native_llvm_int_type(context, target_machine)]; // first entry is the tag
-
-    // Calculate the biggest type
-    let mut largest_type = ptr::null_mut();
-    for c in &st.cases {
-        let case_typ = to_llvm_type(context, target_machine, &c.typ);
-        if largest_type.is_null() || target_machine.size_of_type(case_typ) > target_machine.size_of_type(largest_type) {
-            largest_type = case_typ;
-        }
+    let layout = layout_of(&Type::Sum(Rc::new(st.clone())), &target_machine.target);
+    let sl = layout.sum_type.as_ref().expect("Internal Compiler Error: Sum type without a sum type layout");
+    if let Some(idx) = sl.niche_case {
+        // Just the payload, a null pointer in it means the other case
+        return to_llvm_type(context, target_machine, &st.cases[idx].typ);
     }
 
-    // Use the largest type, we will cast to the other case types
-    member_types.push(largest_type);
-    LLVMStructTypeInContext(context, member_types.as_mut_ptr(), member_types.len() as c_uint, 0)
+    // The tag, followed by the payload as bytes, we will cast to the case types
+    let members = [
+        (native_llvm_int_type(context, target_machine), 0, sl.tag_size),
+        (LLVMArrayType(LLVMInt8TypeInContext(context), sl.payload_size as c_uint), sl.payload_offset, sl.payload_size),
+    ];
+    padded_struct_type(context, &members, layout.size)
 }
 
 unsafe fn func_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine, ft: &FuncType) -> LLVMTypeRef
 {
-    let mut llvm_arg_types = Vec::with_capacity(ft.args.len());
+    // Must match the signature of functions after return value optimization
+    let mut llvm_arg_types = Vec::with_capacity(ft.args.len() + 1);
     for arg in &ft.args {
-        llvm_arg_types.push(to_llvm_type(context, target_machine, arg));
+        let llvm_type = to_llvm_type(context, target_machine, arg);
+        if arg.pass_by_value() {
+            llvm_arg_types.push(llvm_type);
+        } else {
+            llvm_arg_types.push(LLVMPointerType(llvm_type, 0));
+        }
     }
 
+    let return_type = if ft.return_type.pass_by_value() || ft.return_type == Type::Void {
+        to_llvm_type(context, target_machine, &ft.return_type)
+    } else {
+        llvm_arg_types.push(LLVMPointerType(to_llvm_type(context, target_machine, &ft.return_type), 0));
+        LLVMVoidTypeInContext(context)
+    };
+
     LLVMPointerType(
         LLVMFunctionType(
-            to_llvm_type(context, target_machine, &ft.return_type),
+            return_type,
             llvm_arg_types.as_mut_ptr(),
-            ft.args.len() as c_uint,
-            0
+            llvm_arg_types.len() as c_uint,
+            ft.variadic as LLVMBool
         ),
         0
     )
@@ -65,21 +79,98 @@ unsafe fn func_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMach
 
 unsafe fn struct_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine, st: &StructType) -> LLVMTypeRef
 {
-    let mut llvm_member_types = Vec::with_capacity(st.members.len());
-    for m in &st.members {
-        llvm_member_types.push(to_llvm_type(context, target_machine, &m.typ));
-    }
-    LLVMStructTypeInContext(context, llvm_member_types.as_mut_ptr(), llvm_member_types.len() as c_uint, 0)
+    let member_types: Vec<Type> = st.members.iter().map(|m| m.typ.clone()).collect();
+    aggregate_to_llvm_type(context, target_machine, &member_types)
 }
 
 unsafe fn optional_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine, inner: &Type) -> LLVMTypeRef
 {
-    let inner = to_llvm_type(context, target_machine, inner);
-    let mut member_types = vec![
-        LLVMInt1TypeInContext(context),  // nil or not
-        inner,
-    ];
-    LLVMStructTypeInContext(context, member_types.as_mut_ptr(), member_types.len() as c_uint, 0)
+    if has_null_niche(inner) {
+        // nil is a null pointer
+        return to_llvm_type(context, target_machine, inner);
+    }
+
+    // nil or not, followed by the value
+    aggregate_to_llvm_type(context, target_machine, &[Type::Bool, inner.clone()])
+}
+
+unsafe fn aggregate_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine, member_types: &[Type]) -> LLVMTypeRef
+{
+    let target = &target_machine.target;
+    let layout = layout_of(&aggregate_type(member_types), target);
+    let members: Vec<_> = member_types.iter()
+        .zip(layout.offsets.iter())
+        .map(|(typ, offset)| (to_llvm_type(context, target_machine, typ), *offset, layout_of(typ, target).size))
+        .collect();
+    padded_struct_type(context, &members, layout.size)
+}
+
+fn aggregate_type(member_types: &[Type]) -> Type
+{
+    struct_type("", member_types.iter().map(|t| struct_member("", t.clone())).collect())
+}
+
+// Padding needed in front of each member, and at the end, given the offset and size of each member
+fn member_padding(members: &[(usize, usize)], size: usize) -> (Vec<usize>, usize)
+{
+    let mut end = 0;
+    let mut padding = Vec::with_capacity(members.len());
+    for &(offset, member_size) in members {
+        padding.push(offset - end);
+        end = offset + member_size;
+    }
+    (padding, size - end)
+}
+
+// Packed struct type with explicit padding, so LLVM uses exactly the layout we computed
+unsafe fn padded_struct_type(context: LLVMContextRef, members: &[(LLVMTypeRef, usize, usize)], size: usize) -> LLVMTypeRef
+{
+    let offsets_and_sizes: Vec<_> = members.iter().map(|&(_, offset, size)| (offset, size)).collect();
+    let (padding, tail_padding) = member_padding(&offsets_and_sizes, size);
+    let padding_type = |n: usize| LLVMArrayType(LLVMInt8TypeInContext(context), n as c_uint);
+
+    let mut llvm_member_types = Vec::with_capacity(members.len() * 2 + 1);
+    for (&(typ, _, _), &pad) in members.iter().zip(padding.iter()) {
+        if pad > 0 {
+            llvm_member_types.push(padding_type(pad));
+        }
+        llvm_member_types.push(typ);
+    }
+
+    if tail_padding > 0 {
+        llvm_member_types.push(padding_type(tail_padding));
+    }
+
+    LLVMStructTypeInContext(context, llvm_member_types.as_mut_ptr(), llvm_member_types.len() as c_uint, 1)
+}
+
+fn padded_member_index(members: &[(usize, usize)], size: usize, index: usize) -> c_uint
+{
+    let (padding, _) = member_padding(members, size);
+    let padding_fields = padding[..index + 1].iter().filter(|&&p| p > 0).count();
+    (index + padding_fields) as c_uint
+}
+
+// Index of a member in the LLVM type of a struct, optional or sum type (where the tag is 0 and the payload is 1)
+pub fn llvm_member_index(typ: &Type, index: usize, target: &Target) -> c_uint
+{
+    let layout = layout_of(typ, target);
+    let member_types = match *typ {
+        Type::Struct(ref st) => st.members.iter().map(|m| m.typ.clone()).collect(),
+        Type::Optional(ref inner) => vec![Type::Bool, inner.as_ref().clone()],
+        Type::Sum(_) => {
+            let sl = layout.sum_type.as_ref().expect("Internal Compiler Error: Sum type without a sum type layout");
+            let members = [(0, sl.tag_size), (sl.payload_offset, sl.payload_size)];
+            return padded_member_index(&members, layout.size, index);
+        },
+        _ => return index as c_uint,
+    };
+
+    let members: Vec<_> = member_types.iter()
+        .zip(layout.offsets.iter())
+        .map(|(t, offset)| (*offset, layout_of(t, target).size))
+        .collect();
+    padded_member_index(&members, layout.size, index)
 }
 
 pub unsafe fn native_llvm_int_type(context: LLVMContextRef, target_machine: &TargetMachine) -> LLVMTypeRef
@@ -102,10 +193,12 @@ pub unsafe fn to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachi
         Type::Int(IntSize::I16) | Type::UInt(IntSize::I16) => LLVMInt16TypeInContext(context),
         Type::Char | Type::Int(IntSize::I32) | Type::UInt(IntSize::I32) => LLVMInt32TypeInContext(context),
         Type::Int(IntSize::I64) | Type::UInt(IntSize::I64) => LLVMInt64TypeInContext(context),
-        Type::Enum(_) => native_llvm_int_type(context, target_machine),
+        Type::Enum(_) | Type::Thread => native_llvm_int_type(context, target_machine),
         Type::Bool => LLVMInt1TypeInContext(context),
         Type::Float(FloatSize::F32) => LLVMFloatTypeInContext(context),
         Type::Float(FloatSize::F64) => LLVMDoubleTypeInContext(context),
+        // LLVM has no void pointers, i8* is used instead
+        Type::Pointer(ref inner) if **inner == Type::Void => LLVMPointerType(LLVMInt8TypeInContext(context), 0),
         Type::Pointer(ref inner) => LLVMPointerType(to_llvm_type(context, target_machine, inner), 0),
         Type::Array(ref at) => array_to_llvm_type(context, target_machine, at),
         Type::Slice(ref st) => slice_to_llvm_type(context, target_machine, st),
@@ -118,6 +211,7 @@ pub unsafe fn to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachi
         Type::Unresolved(_) => panic!("Internal Compiler Error: All types must be resolved before code generation"),
         Type::Unknown => panic!("Internal Compiler Error: all types must be known before code generation"),
         Type::SelfType => panic!("Internal Compiler Error: self type must be known at this point"),
-        Type::Interface(_) => panic!("Internal Compiler Error: interface type must be known at this point")
+        // Pointer to the value, and pointer to the vtable
+        Type::Interface(_) => aggregate_to_llvm_type(context, target_machine, &[ptr_type(Type::Void), ptr_type(ptr_type(Type::Void))]),
     }
 }
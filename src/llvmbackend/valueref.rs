@@ -1,11 +1,14 @@
-use libc::{c_char, c_uint};
-use llvm::LLVMLinkage;
+use std::ptr;
+use libc::{c_char, c_uint, c_ulonglong};
+use llvm::{LLVMLinkage, LLVMIntPredicate};
 use llvm::core::*;
 use llvm::prelude::*;
 
 use ast::*;
 use bytecode::{ByteCodeProperty, Operand, Constant};
 use super::context::Context;
+use super::types::llvm_member_index;
+use layout::{layout_of, has_null_niche};
 use super::instructions::{const_uint, const_int, const_bool, const_float, const_char, copy, get_operand};
 
 
@@ -30,34 +33,90 @@ impl ValueRef
     pub unsafe fn from_const(ctx: &Context, cst: &Constant) -> ValueRef
     {
         match *cst {
-            Constant::String(ref s) => ValueRef::const_string(ctx, s),
-            Constant::Int(v, int_size) => ValueRef::new(const_int(ctx, v), Type::Int(int_size)),
-            Constant::UInt(v, int_size) => ValueRef::new(const_uint(ctx, v), Type::UInt(int_size)),
-            Constant::Float(v, float_size) => ValueRef::new(const_float(ctx, v), Type::Float(float_size)),
+            Constant::String(ref s) => ValueRef::const_string(ctx, s.as_bytes(), Type::String),
+            Constant::Bytes(ref b) => ValueRef::const_string(ctx, b, cst.get_type()),
+            Constant::Int(v, int_size) => {
+                let typ = Type::Int(int_size);
+                ValueRef::new(LLVMConstInt(ctx.resolve_type(&typ), v as c_ulonglong, 1), typ)
+            },
+            Constant::UInt(v, int_size) => {
+                let typ = Type::UInt(int_size);
+                ValueRef::new(LLVMConstInt(ctx.resolve_type(&typ), v as c_ulonglong, 0), typ)
+            },
+            Constant::Float(v, float_size) => ValueRef::new(const_float(ctx, v, float_size), Type::Float(float_size)),
             Constant::Char(v) => ValueRef::new(const_char(ctx, v), Type::Char),
             Constant::Bool(v) => ValueRef::new(const_bool(ctx, v), Type::Bool),
             Constant::Array(ref elements) => ValueRef::const_array(ctx, elements),
-            Constant::NullPtr(ref typ) => ValueRef::new(LLVMConstNull(ctx.resolve_type(typ)), ptr_type(typ.clone())),
+            Constant::NullPtr(ref typ) => {
+                let typ = ptr_type(typ.clone());
+                ValueRef::new(LLVMConstNull(ctx.resolve_type(&typ)), typ)
+            },
+            Constant::Struct(ref typ, _) => {
+                let glob = LLVMAddGlobal(ctx.module, ctx.resolve_type(typ), cstr!("struct_constant"));
+                LLVMSetLinkage(glob, LLVMLinkage::LLVMInternalLinkage);
+                LLVMSetGlobalConstant(glob, 1);
+                LLVMSetInitializer(glob, ValueRef::const_data(ctx, cst));
+                ValueRef::new(glob, typ.clone())
+            },
         }
     }
 
+    // The value of a constant as static data, which can initialize a global, unlike from_const this emits no instructions
+    pub unsafe fn const_data(ctx: &Context, cst: &Constant) -> LLVMValueRef
+    {
+        match *cst {
+            Constant::String(ref s) => {
+                let mut members = [ValueRef::string_data(ctx, s.as_bytes()), const_uint(ctx, s.len() as u64)];
+                LLVMConstNamedStruct(ctx.resolve_type(&Type::String), members.as_mut_ptr(), members.len() as c_uint)
+            },
+            Constant::Bytes(ref b) => {
+                let mut members = [ValueRef::string_data(ctx, b), const_uint(ctx, b.len() as u64)];
+                LLVMConstNamedStruct(ctx.resolve_type(&cst.get_type()), members.as_mut_ptr(), members.len() as c_uint)
+            },
+            Constant::Array(ref elements) => {
+                let element_type = ctx.resolve_type(&elements[0].get_type());
+                let mut data: Vec<_> = elements.iter().map(|e| ValueRef::const_data(ctx, e)).collect();
+                LLVMConstArray(element_type, data.as_mut_ptr(), data.len() as c_uint)
+            },
+            Constant::Struct(ref typ, ref members) => {
+                let llvm_type = ctx.resolve_type(typ);
+                let num_fields = LLVMCountStructElementTypes(llvm_type);
+                let mut field_types = vec![ptr::null_mut(); num_fields as usize];
+                LLVMGetStructElementTypes(llvm_type, field_types.as_mut_ptr());
+
+                // Padding fields are zero
+                let mut fields: Vec<_> = field_types.iter().map(|&t| LLVMConstNull(t)).collect();
+                for (idx, m) in members.iter().enumerate() {
+                    fields[llvm_member_index(typ, idx, &ctx.target_machine.target) as usize] = ValueRef::const_data(ctx, m);
+                }
+                LLVMConstNamedStruct(llvm_type, fields.as_mut_ptr(), num_fields)
+            },
+            _ => ValueRef::from_const(ctx, cst).value,
+        }
+    }
 
-    unsafe fn const_string(ctx: &Context, s: &str) -> ValueRef
+    // Pointer to the bytes of a string or byte string, in an internal global
+    unsafe fn string_data(ctx: &Context, s: &[u8]) -> LLVMValueRef
     {
         let char_type = LLVMInt8TypeInContext(ctx.context);
         let glob = LLVMAddGlobal(ctx.module, LLVMArrayType(char_type, (s.len() + 1) as c_uint), cstr!("str_constant"));
         LLVMSetLinkage(glob, LLVMLinkage::LLVMInternalLinkage);
-        let const_string = LLVMConstStringInContext(ctx.context, s.as_bytes().as_ptr() as *const c_char, s.len() as c_uint, 0);
+        let const_string = LLVMConstStringInContext(ctx.context, s.as_ptr() as *const c_char, s.len() as c_uint, 0);
         LLVMSetInitializer(glob, const_string);
+        LLVMConstBitCast(glob, LLVMPointerType(char_type, 0))
+    }
 
 
+    // A string or a byte string, which is a uint8 slice
+    unsafe fn const_string(ctx: &Context, s: &[u8], typ: Type) -> ValueRef
+    {
         let ret = ValueRef::new(
-            LLVMBuildAlloca(ctx.builder, ctx.resolve_type(&Type::String), cstr!("str")),
-            Type::String
+            LLVMBuildAlloca(ctx.builder, ctx.resolve_type(&typ), cstr!("str")),
+            typ
         );
 
         let string_data_ptr = ret.slice_data_ptr(ctx);
-        LLVMBuildStore(ctx.builder, LLVMBuildBitCast(ctx.builder, glob, LLVMPointerType(char_type, 0), cstr!("str_ptr")), string_data_ptr);
+        LLVMBuildStore(ctx.builder, ValueRef::string_data(ctx, s), string_data_ptr);
 
         let string_len_ptr = ret.slice_len_ptr(ctx);
         LLVMBuildStore(ctx.builder, const_uint(ctx, s.len() as u64), string_len_ptr);
@@ -79,7 +138,7 @@ impl ValueRef
 
         let mut array_data = Vec::with_capacity(elements.len());
         for e in elements {
-            array_data.push(ValueRef::from_const(ctx, e).value);
+            array_data.push(ValueRef::const_data(ctx, e));
         }
 
         let const_array = LLVMConstArray(element_type, array_data.as_mut_ptr(), array_data.len() as c_uint);
@@ -94,12 +153,24 @@ impl ValueRef
         let element_type = self.typ.get_pointer_element_type().unwrap_or_else(|| panic!("Store not allowed on type {}", self.typ));
         match *element_type
         {
+            // Without a flag, an optional is stored like its value
+            Type::Optional(ref inner) if has_null_niche(inner) => {
+                let inner_ptr_type = ptr_type(inner.as_ref().clone());
+                let src = if val.typ.is_pointer_to_optional() {
+                    ValueRef::new(val.value, inner_ptr_type.clone())
+                } else {
+                    val.clone()
+                };
+                ValueRef::new(self.value, inner_ptr_type).store(ctx, &src);
+            },
+
             Type::Optional(ref inner) => {
+                let data_index = llvm_member_index(element_type, 1, &ctx.target_machine.target);
                 let dst_opt_flag_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 0, cstr!("dst_opt_flag_ptr"));
-                let dst_data_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 1, cstr!("dst_data_ptr"));
+                let dst_data_ptr = LLVMBuildStructGEP(ctx.builder, self.value, data_index, cstr!("dst_data_ptr"));
                 if val.typ.is_pointer_to_optional() {
                     let src_opt_flag_ptr = LLVMBuildStructGEP(ctx.builder, val.value, 0, cstr!("src_opt_flag_ptr"));
-                    let src_data_ptr = LLVMBuildStructGEP(ctx.builder, val.value, 1, cstr!("src_data_ptr"));
+                    let src_data_ptr = LLVMBuildStructGEP(ctx.builder, val.value, data_index, cstr!("src_data_ptr"));
                     LLVMBuildStore(ctx.builder, LLVMBuildLoad(ctx.builder, src_opt_flag_ptr, cstr!("src_is_nil")), dst_opt_flag_ptr);
                     if inner.pass_by_value() {
                         LLVMBuildStore(ctx.builder, LLVMBuildLoad(ctx.builder, src_data_ptr, cstr!("src_data")), dst_data_ptr);
@@ -141,8 +212,13 @@ impl ValueRef
         if let Some(element_type) = self.typ.get_pointer_element_type() {
             match *element_type
             {
+                Type::Optional(ref inner_type) if has_null_niche(inner_type) => {
+                    ValueRef::new(self.value, ptr_type(inner_type.as_ref().clone())).load(ctx)
+                },
+
                 Type::Optional(ref inner_type) => unsafe {
-                    let inner_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 1, cstr!("inner_ptr"));
+                    let inner_index = llvm_member_index(element_type, 1, &ctx.target_machine.target);
+                    let inner_ptr = LLVMBuildStructGEP(ctx.builder, self.value, inner_index, cstr!("inner_ptr"));
                     if inner_type.pass_by_value() {
                         LLVMBuildLoad(ctx.builder, inner_ptr, cstr!("inner"))
                     } else {
@@ -173,6 +249,7 @@ impl ValueRef
             Type::Sum(_) |
             Type::Func(_) |
             Type::Optional(_) |
+            Type::Interface(_) |
             Type::Pointer(_) |
             Type::String => self.clone(),
 
@@ -185,6 +262,14 @@ impl ValueRef
         let typ = self.typ.get_pointer_element_type().unwrap_or(&self.typ);
         match *typ
         {
+            Type::Optional(ref inner) if has_null_niche(inner) => unsafe {
+                let ptr = LLVMBuildLoad(ctx.builder, null_niche_ptr(ctx, self.value, inner), cstr!("ptr"));
+                ValueRef::new(
+                    LLVMBuildIsNotNull(ctx.builder, ptr, cstr!("is_nil")),
+                    Type::Bool
+                )
+            },
+
             Type::Optional(_) => unsafe {
                 let opt_flag_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 0, cstr!("opt_flag_ptr"));
                 ValueRef::new(
@@ -202,6 +287,10 @@ impl ValueRef
         let element_type = self.typ.get_pointer_element_type().unwrap_or_else(|| panic!("storenil not allowed on type {}", self.typ));
         match *element_type
         {
+            Type::Optional(ref inner) if has_null_niche(inner) => unsafe {
+                store_null(ctx, null_niche_ptr(ctx, self.value, inner));
+            },
+
             Type::Optional(_) => unsafe {
                 let opt_flag_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 0, cstr!("opt_flag_ptr"));
                 LLVMBuildStore(ctx.builder, const_bool(ctx, false), opt_flag_ptr);
@@ -245,8 +334,9 @@ impl ValueRef
                     _ => panic!("Struct member access has to be through an integer"),
                 };
 
+                let llvm_index = llvm_member_index(element_type, index, &ctx.target_machine.target);
                 ValueRef::new(
-                    LLVMBuildStructGEP(ctx.builder, self.value, index as c_uint, cstr!("member")),
+                    LLVMBuildStructGEP(ctx.builder, self.value, llvm_index, cstr!("member")),
                     ptr_type(st.members[index].typ.clone())
                 )
             },
@@ -258,7 +348,12 @@ impl ValueRef
                     _ => panic!("Sum type member access has to be through an integer"),
                 };
 
-                let st_data_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 1, cstr!("st_data_ptr"));
+                let st_data_ptr = if is_niche_sum_type(ctx, element_type) {
+                    self.value
+                } else {
+                    let payload_index = llvm_member_index(element_type, 1, &ctx.target_machine.target);
+                    LLVMBuildStructGEP(ctx.builder, self.value, payload_index, cstr!("st_data_ptr"))
+                };
                 let case_type = &st.cases[index].typ;
                 let type_to_cast_to = LLVMPointerType(ctx.resolve_type(case_type), 0);
                 ValueRef::new(
@@ -337,7 +432,24 @@ impl ValueRef
                 )
             },
 
-            (&Type::Sum(_), ByteCodeProperty::SumTypeIndex) => unsafe {
+            (&Type::Interface(_), ByteCodeProperty::Data) => unsafe {
+                let data_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 0, cstr!("iface_data_ptr"));
+                ValueRef::new(
+                    LLVMBuildLoad(ctx.builder, data_ptr, cstr!("data")),
+                    ptr_type(Type::Void),
+                )
+            },
+
+            (&Type::Sum(ref st), ByteCodeProperty::SumTypeIndex) => unsafe {
+                if let Some(idx) = sum_type_niche_case_index(ctx, element_type) {
+                    let payload = LLVMBuildLoad(ctx.builder, null_niche_ptr(ctx, self.value, &st.cases[idx].typ), cstr!("payload"));
+                    let has_payload = LLVMBuildIsNotNull(ctx.builder, payload, cstr!("has_payload"));
+                    return ValueRef::new(
+                        LLVMBuildSelect(ctx.builder, has_payload, const_uint(ctx, idx as u64), const_uint(ctx, 1 - idx as u64), cstr!("sti")),
+                        native_uint_type,
+                    );
+                }
+
                 let sti_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 0, cstr!("sti_ptr"));
                 ValueRef::new(
                     LLVMBuildLoad(ctx.builder, sti_ptr, cstr!("sti")),
@@ -345,6 +457,15 @@ impl ValueRef
                 )
             },
 
+            (&Type::String, ByteCodeProperty::FirstChar) => unsafe {
+                ValueRef::new(self.first_char(ctx), Type::Char)
+            },
+
+            (&Type::String, ByteCodeProperty::FirstCharLen) => unsafe {
+                let first_byte = self.string_byte(ctx, 0);
+                ValueRef::new(self.first_char_len(ctx, first_byte), native_uint_type)
+            },
+
             _ => panic!("Get property not allowed")
         }
     }
@@ -356,9 +477,17 @@ impl ValueRef
 
         match (element_type, prop)
         {
-            (&Type::Sum(_), ByteCodeProperty::SumTypeIndex) => unsafe {
-                let sti_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 0, cstr!("sti_ptr"));
-                LLVMBuildStore(ctx.builder, const_uint(ctx, value as u64), sti_ptr);
+            (&Type::Sum(ref st), ByteCodeProperty::SumTypeIndex) => unsafe {
+                match sum_type_niche_case_index(ctx, element_type)
+                {
+                    // The payload is stored afterwards, so only the empty case has to be set
+                    Some(idx) if idx == value => (),
+                    Some(idx) => store_null(ctx, null_niche_ptr(ctx, self.value, &st.cases[idx].typ)),
+                    None => {
+                        let sti_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 0, cstr!("sti_ptr"));
+                        LLVMBuildStore(ctx.builder, const_uint(ctx, value as u64), sti_ptr);
+                    },
+                }
             },
 
             _ => panic!("Set property not allowed")
@@ -375,6 +504,59 @@ impl ValueRef
         LLVMBuildStructGEP(ctx.builder, self.value, 1, cstr!("slice_len_ptr"))
     }
 
+    // Load a byte of a string as a 32 bit integer, the index is clamped to the last byte of the string,
+    // so this never reads past the end of the string. The string should not be empty.
+    unsafe fn string_byte(&self, ctx: &Context, index: u64) -> LLVMValueRef
+    {
+        let len = LLVMBuildLoad(ctx.builder, self.slice_len_ptr(ctx), cstr!("len"));
+        let last = LLVMBuildSub(ctx.builder, len, const_uint(ctx, 1), cstr!("last"));
+        let in_bounds = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, const_uint(ctx, index), len, cstr!("in_bounds"));
+        let mut idx = LLVMBuildSelect(ctx.builder, in_bounds, const_uint(ctx, index), last, cstr!("idx"));
+        let data = LLVMBuildLoad(ctx.builder, self.slice_data_ptr(ctx), cstr!("data"));
+        let byte_ptr = LLVMBuildGEP(ctx.builder, data, &mut idx, 1, cstr!("byte_ptr"));
+        let byte = LLVMBuildLoad(ctx.builder, byte_ptr, cstr!("byte"));
+        LLVMBuildZExt(ctx.builder, byte, LLVMInt32TypeInContext(ctx.context), cstr!("byte32"))
+    }
+
+    // Number of bytes in the UTF-8 sequence starting with first_byte, limited by the length of the string
+    unsafe fn first_char_len(&self, ctx: &Context, first_byte: LLVMValueRef) -> LLVMValueRef
+    {
+        let c32 = |v: u64| LLVMConstInt(LLVMInt32TypeInContext(ctx.context), v, 0);
+        let lt = |v: u64| LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, first_byte, c32(v), cstr!("lt"));
+        let n = LLVMBuildSelect(ctx.builder, lt(0xF0), const_uint(ctx, 3), const_uint(ctx, 4), cstr!("n"));
+        let n = LLVMBuildSelect(ctx.builder, lt(0xE0), const_uint(ctx, 2), n, cstr!("n"));
+        let n = LLVMBuildSelect(ctx.builder, lt(0x80), const_uint(ctx, 1), n, cstr!("n"));
+        let len = LLVMBuildLoad(ctx.builder, self.slice_len_ptr(ctx), cstr!("len"));
+        let fits = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULE, n, len, cstr!("fits"));
+        LLVMBuildSelect(ctx.builder, fits, n, len, cstr!("char_len"))
+    }
+
+    // Decode the first code point of a non empty UTF-8 string
+    unsafe fn first_char(&self, ctx: &Context) -> LLVMValueRef
+    {
+        let c32 = |v: u64| LLVMConstInt(LLVMInt32TypeInContext(ctx.context), v, 0);
+        let bits = |byte: LLVMValueRef, mask: u64, shift: u64| {
+            let masked = LLVMBuildAnd(ctx.builder, byte, c32(mask), cstr!("masked"));
+            LLVMBuildShl(ctx.builder, masked, c32(shift), cstr!("shifted"))
+        };
+        let or = |a: LLVMValueRef, b: LLVMValueRef| LLVMBuildOr(ctx.builder, a, b, cstr!("or"));
+
+        let b0 = self.string_byte(ctx, 0);
+        let b1 = self.string_byte(ctx, 1);
+        let b2 = self.string_byte(ctx, 2);
+        let b3 = self.string_byte(ctx, 3);
+
+        let c2 = or(bits(b0, 0x1F, 6), bits(b1, 0x3F, 0));
+        let c3 = or(or(bits(b0, 0x0F, 12), bits(b1, 0x3F, 6)), bits(b2, 0x3F, 0));
+        let c4 = or(or(or(bits(b0, 0x07, 18), bits(b1, 0x3F, 12)), bits(b2, 0x3F, 6)), bits(b3, 0x3F, 0));
+
+        let n = self.first_char_len(ctx, b0);
+        let is = |v: u64| LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, n, const_uint(ctx, v), cstr!("is"));
+        let c = LLVMBuildSelect(ctx.builder, is(3), c3, c4, cstr!("c"));
+        let c = LLVMBuildSelect(ctx.builder, is(2), c2, c, cstr!("c"));
+        LLVMBuildSelect(ctx.builder, is(1), b0, c, cstr!("c"))
+    }
+
     pub unsafe fn create_slice_from_array(&self, ctx: &mut Context, array: &ValueRef, start: &Operand, len: &Operand)
     {
         let inner_type = array.typ.get_pointer_element_type()
@@ -390,6 +572,15 @@ impl ValueRef
                 LLVMBuildStore(ctx.builder, get_operand(ctx, len).load(ctx), len_ptr);
             }
 
+            Type::String => {
+                // start and len are in bytes
+                let data = LLVMBuildLoad(ctx.builder, array.slice_data_ptr(ctx), cstr!("data"));
+                let mut start = get_operand(ctx, start).load(ctx);
+                let start_ptr = LLVMBuildGEP(ctx.builder, data, &mut start, 1, cstr!("start_ptr"));
+                LLVMBuildStore(ctx.builder, start_ptr, self.slice_data_ptr(ctx));
+                LLVMBuildStore(ctx.builder, get_operand(ctx, len).load(ctx), self.slice_len_ptr(ctx));
+            }
+
             _ =>  panic!("Expecting an array type, not a {}", self.typ),
         }
     }
@@ -401,4 +592,54 @@ impl ValueRef
         LLVMBuildStore(ctx.builder, data.value, data_ptr);
         LLVMBuildStore(ctx.builder, len.load(ctx), len_ptr);
     }
+
+    pub unsafe fn create_interface(&self, ctx: &Context, data: &ValueRef, vtable: LLVMValueRef)
+    {
+        let void_ptr_type = LLVMPointerType(LLVMInt8TypeInContext(ctx.context), 0);
+        let data_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 0, cstr!("iface_data_ptr"));
+        let vtable_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 1, cstr!("iface_vtable_ptr"));
+        LLVMBuildStore(ctx.builder, LLVMBuildBitCast(ctx.builder, data.load(ctx), void_ptr_type, cstr!("data")), data_ptr);
+        LLVMBuildStore(ctx.builder, vtable, vtable_ptr);
+    }
+
+    // Load the function pointer in a slot of the vtable of an interface value, as an i8*
+    pub unsafe fn load_interface_method(&self, ctx: &Context, index: usize) -> LLVMValueRef
+    {
+        let vtable_ptr = LLVMBuildStructGEP(ctx.builder, self.value, 1, cstr!("iface_vtable_ptr"));
+        let vtable = LLVMBuildLoad(ctx.builder, vtable_ptr, cstr!("vtable"));
+        let mut index = const_uint(ctx, index as u64);
+        let slot = LLVMBuildGEP(ctx.builder, vtable, &mut index, 1, cstr!("slot"));
+        LLVMBuildLoad(ctx.builder, slot, cstr!("method"))
+    }
+}
+
+fn sum_type_niche_case_index(ctx: &Context, typ: &Type) -> Option<usize>
+{
+    layout_of(typ, &ctx.target_machine.target).sum_type.and_then(|sl| sl.niche_case)
+}
+
+fn is_niche_sum_type(ctx: &Context, typ: &Type) -> bool
+{
+    sum_type_niche_case_index(ctx, typ).is_some()
+}
+
+// Pointer to the pointer which is null when a value of a type with a null niche is nil, or the empty case
+unsafe fn null_niche_ptr(ctx: &Context, ptr: LLVMValueRef, typ: &Type) -> LLVMValueRef
+{
+    match *typ
+    {
+        Type::Pointer(_) => ptr,
+        Type::String => LLVMBuildStructGEP(ctx.builder, ptr, 0, cstr!("data_ptr")),
+        Type::Struct(ref st) => {
+            let member_ptr = LLVMBuildStructGEP(ctx.builder, ptr, 0, cstr!("member_ptr"));
+            null_niche_ptr(ctx, member_ptr, &st.members[0].typ)
+        },
+        _ => panic!("Internal Compiler Error: type {} has no null niche", typ),
+    }
+}
+
+unsafe fn store_null(ctx: &Context, ptr: LLVMValueRef)
+{
+    let null = LLVMConstNull(LLVMGetElementType(LLVMTypeOf(ptr)));
+    LLVMBuildStore(ctx.builder, null, ptr);
 }
@@ -1,62 +1,143 @@
-extern crate llvm_sys as llvm;
-extern crate libc;
-extern crate itertools;
+extern crate menhir;
 #[macro_use]
 extern crate clap;
-extern crate uuid;
-extern crate toml;
-#[macro_use]
-extern crate serde_derive;
-extern crate serde;
-extern crate bincode;
-extern crate time;
-extern crate either;
-
-macro_rules! try_opt {
-    ($e:expr) =>(
-        match $e {
-            Some(v) => v,
-            None => return None,
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process::exit;
+use std::thread;
+use std::path::{Path, PathBuf};
+use clap::{Arg, ArgMatches, SubCommand};
+
+use menhir::{CompileResult, CompileError, ErrorCode, OutputType, EmitKind, TargetMachine, llvm_init, llvm_shutdown};
+use menhir::{PackageData, BuildOptions, InstantiationDump, InstantiationLimits, OverflowMode, OptimizationLevel, ExportLibrary, COMPILER_STACK_SIZE, DEFAULT_MAX_OBJECT_SIZE};
+use menhir::inspect_cache_entry;
+use menhir::{DocFormat, DocOptions, document_file, document_instructions};
+
+// --cfg feature=foo enables the declarations marked with #[cfg(feature = "foo")]
+fn cfg_features(matches: &ArgMatches) -> CompileResult<HashSet<String>>
+{
+    let mut features = HashSet::new();
+    for cfg in matches.values_of("CFG").into_iter().flatten() {
+        match cfg.find('=') {
+            Some(idx) if &cfg[..idx] == "feature" => {
+                features.insert(cfg[idx + 1..].trim_matches('"').to_owned());
+            },
+            _ => return Err(CompileError::Other(format!("Invalid cfg option {}, expecting feature=<name>", cfg))),
         }
-    )
+    }
+    Ok(features)
+}
+
+// The limit keeps padding from overflowing the size computations of huge types
+const MAX_OBJECT_SIZE_LIMIT: usize = 1 << 40;
+
+fn max_object_size(matches: &ArgMatches) -> CompileResult<usize>
+{
+    match matches.value_of("MAX_OBJECT_SIZE") {
+        None => Ok(DEFAULT_MAX_OBJECT_SIZE),
+        Some(size) => size.parse::<usize>().ok().filter(|&s| s > 0 && s <= MAX_OBJECT_SIZE_LIMIT)
+            .ok_or_else(|| CompileError::Other(format!("Invalid object size {}, expecting a number of bytes up to {}", size, MAX_OBJECT_SIZE_LIMIT))),
+    }
 }
 
+fn build_target_machine(matches: &ArgMatches) -> CompileResult<TargetMachine>
+{
+    let mut target_machine = llvm_init()?;
+    target_machine.target.features = cfg_features(matches)?;
+    target_machine.target.max_object_size = max_object_size(matches)?;
+    Ok(target_machine)
+}
 
-mod ast;
-mod compileerror;
-mod bytecode;
-mod exportlibrary;
-mod parser;
-mod typechecker;
-mod span;
-mod llvmbackend;
-mod target;
-mod timer;
-mod package;
-mod packagebuild;
+fn overflow_mode(matches: &ArgMatches) -> OverflowMode
+{
+    match matches.value_of("RELEASE_OVERFLOW") {
+        Some("wrap") => OverflowMode::Wrap,
+        _ => OverflowMode::Check,
+    }
+}
 
-use std::fs::File;
-use std::process::exit;
-use std::path::PathBuf;
-use clap::ArgMatches;
+// -O is the same as --opt-level 2, without either of them the cheap optimizations are done
+fn optimization_level(matches: &ArgMatches) -> CompileResult<OptimizationLevel>
+{
+    match matches.value_of("OPT_LEVEL") {
+        None if matches.is_present("OPTIMIZE") => Ok(OptimizationLevel::Normal),
+        None => Ok(OptimizationLevel::Minimal),
+        Some(lvl) => OptimizationLevel::parse(lvl)
+            .ok_or_else(|| CompileError::Other(format!("Invalid optimization level {}, expecting 0, 1 or 2", lvl))),
+    }
+}
+
+fn dump_instantiations(matches: &ArgMatches) -> Option<InstantiationDump>
+{
+    match matches.value_of("DUMP_INSTANTIATIONS") {
+        Some("dot") => Some(InstantiationDump::Dot),
+        Some(_) => Some(InstantiationDump::Tree),
+        None => None,
+    }
+}
+
+fn parse_limit(matches: &ArgMatches, name: &str, default: usize) -> CompileResult<usize>
+{
+    match matches.value_of(name) {
+        None => Ok(default),
+        Some(limit) => limit.parse::<usize>().ok().filter(|&l| l > 0)
+            .ok_or_else(|| CompileError::Other(format!("Invalid instantiation limit {}, expecting a positive number", limit))),
+    }
+}
+
+fn instantiation_limits(matches: &ArgMatches) -> CompileResult<InstantiationLimits>
+{
+    let default = InstantiationLimits::default();
+    Ok(InstantiationLimits{
+        max_depth: parse_limit(matches, "INSTANTIATION_DEPTH", default.max_depth)?,
+        max_per_root: parse_limit(matches, "INSTANTIATION_COUNT", default.max_per_root)?,
+    })
+}
 
-use compileerror::{CompileResult};
-use llvmbackend::{OutputType, llvm_init, llvm_shutdown};
-use packagebuild::{PackageData, BuildOptions};
-use exportlibrary::ExportLibrary;
+fn warn_large_copy(matches: &ArgMatches) -> CompileResult<Option<usize>>
+{
+    match matches.value_of("WARN_LARGE_COPY") {
+        None => Ok(None),
+        Some(size) => size.parse::<usize>().map(Some)
+            .map_err(|_| CompileError::Other(format!("Invalid copy size {}, expecting a number of bytes", size))),
+    }
+}
 
+fn emit_kind(matches: &ArgMatches) -> CompileResult<EmitKind>
+{
+    match matches.value_of("EMIT") {
+        None | Some("obj") => Ok(EmitKind::Object),
+        Some("llvm-ir") => Ok(EmitKind::LlvmIr),
+        Some("llvm-bc") => Ok(EmitKind::LlvmBitcode),
+        Some(other) => Err(CompileError::Other(format!("Invalid emit option {}, expecting obj, llvm-ir or llvm-bc", other))),
+    }
+}
 
 fn build_command(matches: &ArgMatches, dump_flags: &str) -> CompileResult<i32>
 {
     let input_file = matches.value_of("INPUT_FILE").expect("No input file given");
     let build_options = BuildOptions{
-        optimize: matches.is_present("OPTIMIZE"),
+        optimization_level: optimization_level(matches)?,
+        overflow_mode: overflow_mode(matches),
+        keep_all_functions: matches.is_present("KEEP_ALL_FUNCTIONS"),
+        stack_check: matches.is_present("STACK_CHECK"),
+        emit: emit_kind(matches)?,
         dump_flags: dump_flags.into(),
-        target_machine: llvm_init()?,
+        dump_instantiations: dump_instantiations(matches),
+        instantiation_limits: instantiation_limits(matches)?,
+        target_machine: build_target_machine(matches)?,
         sources_directory: String::new(),
         import_directories: matches.value_of("IMPORTS")
             .map(|dirs| dirs.split(',').map(PathBuf::from).collect())
             .unwrap_or_else(Vec::new),
+        library_directories: matches.value_of("LIBRARY_PATH")
+            .map(|dirs| dirs.split(',').map(PathBuf::from).collect())
+            .unwrap_or_else(Vec::new),
+        use_cache: !matches.is_present("NO_CACHE"),
+        ignore_version: matches.is_present("IGNORE_VERSION"),
+        warn_large_copy: warn_large_copy(matches)?,
     };
 
     let output_type = match matches.value_of("LIB") {
@@ -66,7 +147,10 @@ fn build_command(matches: &ArgMatches, dump_flags: &str) -> CompileResult<i32>
     };
 
     let pkg = PackageData::single_file(&input_file, output_type)?;
-    pkg.build(&build_options)?;
+    let timings = pkg.build(&build_options)?;
+    if matches.is_present("TIMINGS") {
+        timings.print();
+    }
     Ok(0)
 }
 
@@ -81,15 +165,30 @@ fn build_package_command(matches: &ArgMatches, dump_flags: &str) -> CompileResul
 
     let pkg = PackageData::load(package_toml)?;
     let build_options = BuildOptions{
-        optimize: matches.is_present("OPTIMIZE"),
+        optimization_level: optimization_level(matches)?,
+        overflow_mode: overflow_mode(matches),
+        keep_all_functions: matches.is_present("KEEP_ALL_FUNCTIONS"),
+        stack_check: matches.is_present("STACK_CHECK"),
+        emit: emit_kind(matches)?,
         dump_flags: dump_flags.into(),
-        target_machine: llvm_init()?,
+        dump_instantiations: dump_instantiations(matches),
+        instantiation_limits: instantiation_limits(matches)?,
+        target_machine: build_target_machine(matches)?,
         sources_directory: "src".into(),
         import_directories: matches.value_of("IMPORTS")
             .map(|dirs| dirs.split(',').map(PathBuf::from).collect())
             .unwrap_or_else(Vec::new),
+        library_directories: matches.value_of("LIBRARY_PATH")
+            .map(|dirs| dirs.split(',').map(PathBuf::from).collect())
+            .unwrap_or_else(Vec::new),
+        use_cache: !matches.is_present("NO_CACHE"),
+        ignore_version: matches.is_present("IGNORE_VERSION"),
+        warn_large_copy: warn_large_copy(matches)?,
     };
-    pkg.build(&build_options)?;
+    let timings = pkg.build(&build_options)?;
+    if matches.is_present("TIMINGS") {
+        timings.print();
+    }
     Ok(0)
 }
 
@@ -102,35 +201,167 @@ fn exports_command(matches: &ArgMatches) -> CompileResult<i32>
     Ok(0)
 }
 
+// Prints which compiler built a build cache entry or an exports file, and what is in it
+fn inspect_command(matches: &ArgMatches) -> CompileResult<i32>
+{
+    let input_file = matches.value_of("INPUT_FILE").expect("No input file given");
+    let path = Path::new(input_file);
+    let mut file = File::open(path)?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("cache") => print!("{}", inspect_cache_entry(&mut file)?),
+        Some("exports") => {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            let build_info = ExportLibrary::load_build_info(&mut &data[..])?;
+            // The rest of the file can only be read by the version of the compiler which wrote it
+            match ExportLibrary::load(&mut &data[..]) {
+                Ok(lib) => println!("{}", lib),
+                Err(e) => println!("Built by: {}\nWarning: {}", build_info, e),
+            }
+        },
+        _ => return Err(CompileError::Other(format!("Cannot inspect {}, expecting a .cache or a .exports file", input_file))),
+    }
+    Ok(0)
+}
+
+fn doc_command(matches: &ArgMatches) -> CompileResult<i32>
+{
+    let input_file = matches.value_of("INPUT_FILE").expect("No input file given");
+    let format = match matches.value_of("FORMAT") {
+        None | Some("markdown") => DocFormat::Markdown,
+        Some("json") => DocFormat::Json,
+        Some(other) => return Err(CompileError::Other(format!("Invalid format {}, expecting markdown or json", other))),
+    };
+
+    let options = DocOptions{
+        format: format,
+        document_private: matches.is_present("DOCUMENT_PRIVATE"),
+    };
+    let target_machine = llvm_init()?;
+    let doc = document_file(Path::new(input_file), &target_machine.target, &options)?;
+    match matches.value_of("OUTPUT_FILE") {
+        // Warnings and timings are printed on stdout, so a file is the way to get only the documentation
+        Some(output_file) => File::create(output_file)?.write_all(doc.as_bytes())?,
+        None => println!("{}", doc),
+    }
+    Ok(0)
+}
+
+fn dump_isa_command(matches: &ArgMatches) -> CompileResult<i32>
+{
+    let format = match matches.value_of("FORMAT") {
+        None | Some("md") => DocFormat::Markdown,
+        Some("json") => DocFormat::Json,
+        Some(other) => return Err(CompileError::Other(format!("Invalid format {}, expecting md or json", other))),
+    };
+    println!("{}", document_instructions(format));
+    Ok(0)
+}
+
+fn explain_command(matches: &ArgMatches) -> CompileResult<i32>
+{
+    let code = matches.value_of("CODE").expect("No error code given");
+    match ErrorCode::lookup(code) {
+        Some(code) => print!("{}", code.explain()),
+        None => return Err(CompileError::Other(format!("Unknown error code {}, error codes look like E0001", code))),
+    }
+    Ok(0)
+}
+
 fn run() -> CompileResult<i32>
 {
     let app = clap_app!(cobrac =>
         (version: "0.1")
         (author: "Joris Guisson <joris.guisson@gmail.com>")
         (about: "Nomad language compiler")
-        (@arg DUMP: -d --dump +takes_value "Dump internal compiler state for debug purposes. Argument can be all, ast, bytecode or ir. A comma separated list of these values is also supported.")
+        (@arg DUMP: -d --dump +takes_value "Dump internal compiler state for debug purposes. Argument can be all, ast, types, bytecode or ir. A comma separated list of these values is also supported.")
         (@arg TARGET_TRIPLET: -t --triplet "Print the default target triplet of the current system, and exit")
         (@subcommand build =>
             (about: "Build a menhir file")
             (@arg INPUT_FILE: +required "File to build")
             (@arg OUTPUT_FILE: -o --output +takes_value "Name of binary to create (by default input file without the extensions)")
-            (@arg OPTIMIZE: -O --optimize "Optimize the code")
+            (@arg OPTIMIZE: -O --optimize "Optimize the code, the same as --opt-level 2")
+            (@arg OPT_LEVEL: --("opt-level") +takes_value "Optimization level: 0 (only what code generation needs), 1 (cheap passes, the default) or 2 (all passes and LLVM optimization)")
+            (@arg KEEP_ALL_FUNCTIONS: --("keep-all-functions") "Don't remove functions which are never called from main")
+            (@arg STACK_CHECK: --("stack-check") "Check the stack size at the start of every function, and abort with an error instead of crashing on a stack overflow")
+            (@arg EMIT: --emit +takes_value "What to generate: obj (the default, which gets linked), llvm-ir or llvm-bc")
+            (@arg RELEASE_OVERFLOW: --("release-overflow") +takes_value possible_value[check wrap] "What integer overflow does: check (a runtime error, the default) or wrap")
             (@arg IMPORTS: -I --imports +takes_value "Directory to look for imports, use a comma separated list for more then one.")
+            (@arg LIBRARY_PATH: --("library-path") +takes_value "Directory to look for libraries named by link attributes, use a comma separated list for more then one.")
             (@arg LIB: -l --lib +takes_value possible_value[static shared] "Create a library, type of library must be pass")
+            (@arg TIMINGS: --timings "Print the time spent in each compiler pass")
+            (@arg CFG: --cfg +takes_value +multiple number_of_values(1) "Enable a conditional compilation option, for example feature=foo")
+            (@arg NO_CACHE: --("no-cache") "Compile every module, instead of loading the unchanged ones from the build cache")
+            (@arg IGNORE_VERSION: --("ignore-version") "Don't warn about libraries built by another version of the compiler")
+            (@arg WARN_LARGE_COPY: --("warn-large-copy") +takes_value "Warn about implicit copies of values larger than this number of bytes, for example --warn-large-copy=256")
+            (@arg DUMP_INSTANTIATIONS: --("dump-instantiations") +takes_value possible_value[tree dot] "Show which generic instances are required by which functions: tree (printed) or dot (a graphviz file per module in the build directory)")
+            (@arg INSTANTIATION_DEPTH: --("max-instantiation-depth") +takes_value "Maximum length of a chain of generic instances requiring each other, 64 by default")
+            (@arg INSTANTIATION_COUNT: --("max-instantiations") +takes_value "Maximum number of instances a generic instance can require, directly or indirectly, 10000 by default")
+            (@arg MAX_OBJECT_SIZE: --("max-object-size") +takes_value "Largest value in bytes a type can have, values are stored on the stack or in globals, 1073741824 (1 GiB) by default")
+            (@arg ERROR_FORMAT: --("error-format") +takes_value possible_value[text json] "How errors are printed: text (the default) or json")
         )
         (@subcommand buildpkg =>
             (about: "Build a menhir package.")
             (@arg PACKAGE_TOML: -p --package +takes_value "Specify the package.toml file. If not specified, menhir will look in the current directory for one.")
-            (@arg OPTIMIZE: -O --optimize "Optimize the code")
+            (@arg OPTIMIZE: -O --optimize "Optimize the code, the same as --opt-level 2")
+            (@arg OPT_LEVEL: --("opt-level") +takes_value "Optimization level: 0 (only what code generation needs), 1 (cheap passes, the default) or 2 (all passes and LLVM optimization)")
+            (@arg KEEP_ALL_FUNCTIONS: --("keep-all-functions") "Don't remove functions which are never called from main")
+            (@arg STACK_CHECK: --("stack-check") "Check the stack size at the start of every function, and abort with an error instead of crashing on a stack overflow")
+            (@arg EMIT: --emit +takes_value "What to generate: obj (the default, which gets linked), llvm-ir or llvm-bc")
+            (@arg RELEASE_OVERFLOW: --("release-overflow") +takes_value possible_value[check wrap] "What integer overflow does: check (a runtime error, the default) or wrap")
             (@arg IMPORTS: -I --imports +takes_value "Directory to look for imports, use a comma separated list for more then one.")
+            (@arg LIBRARY_PATH: --("library-path") +takes_value "Directory to look for libraries named by link attributes, use a comma separated list for more then one.")
+            (@arg TIMINGS: --timings "Print the time spent in each compiler pass")
+            (@arg CFG: --cfg +takes_value +multiple number_of_values(1) "Enable a conditional compilation option, for example feature=foo")
+            (@arg NO_CACHE: --("no-cache") "Compile every module, instead of loading the unchanged ones from the build cache")
+            (@arg IGNORE_VERSION: --("ignore-version") "Don't warn about libraries built by another version of the compiler")
+            (@arg WARN_LARGE_COPY: --("warn-large-copy") +takes_value "Warn about implicit copies of values larger than this number of bytes, for example --warn-large-copy=256")
+            (@arg DUMP_INSTANTIATIONS: --("dump-instantiations") +takes_value possible_value[tree dot] "Show which generic instances are required by which functions: tree (printed) or dot (a graphviz file per module in the build directory)")
+            (@arg INSTANTIATION_DEPTH: --("max-instantiation-depth") +takes_value "Maximum length of a chain of generic instances requiring each other, 64 by default")
+            (@arg INSTANTIATION_COUNT: --("max-instantiations") +takes_value "Maximum number of instances a generic instance can require, directly or indirectly, 10000 by default")
+            (@arg MAX_OBJECT_SIZE: --("max-object-size") +takes_value "Largest value in bytes a type can have, values are stored on the stack or in globals, 1073741824 (1 GiB) by default")
+            (@arg ERROR_FORMAT: --("error-format") +takes_value possible_value[text json] "How errors are printed: text (the default) or json")
+        )
+        (@subcommand doc =>
+            (about: "Print the documentation of the declarations in a menhir file, or in the package in a directory")
+            (@arg INPUT_FILE: +required "File or directory to document")
+            (@arg FORMAT: -f --format +takes_value possible_value[markdown json] "Output format: markdown (the default) or json")
+            (@arg DOCUMENT_PRIVATE: --("document-private") "Also document declarations which are not public")
+            (@arg OUTPUT_FILE: -o --output +takes_value "File to write the documentation to, instead of printing it")
+        )
+        (@subcommand explain =>
+            (about: "Print the explanation of an error code, with an example of the error and how to fix it")
+            (@arg CODE: +required "Error code, for example E0006")
         )
         (@subcommand exports =>
             (about: "List the exported symbols in an exports file")
             (@arg EXPORTS_FILE: +required "Exports file")
         )
+        (@subcommand inspect =>
+            (about: "Print which compiler and build settings produced a build cache entry (.cache) or an exports file (.exports), and what is in it")
+            (@arg INPUT_FILE: +required "File to inspect")
+        )
+    )
+    // clap_app! only takes identifiers as subcommand names
+    .subcommand(SubCommand::with_name("dump-isa")
+        .about("Print a reference of the bytecode instructions, and of how they are encoded in build caches and export libraries")
+        .arg(Arg::with_name("FORMAT").short("f").long("format").takes_value(true).possible_values(&["md", "json"])
+            .help("Output format: md (markdown, the default) or json"))
     );
 
     let matches = app.get_matches();
+    let json_errors = matches.subcommand().1.and_then(|m| m.value_of("ERROR_FORMAT")) == Some("json");
+    match run_command(&matches) {
+        Err(ref e) if json_errors => {
+            println!("{}", e.to_json());
+            Ok(-1)
+        },
+        result => result,
+    }
+}
+
+fn run_command(matches: &ArgMatches) -> CompileResult<i32>
+{
     let dump_flags = matches.value_of("DUMP").unwrap_or("");
 
     if matches.is_present("TARGET_TRIPLET") {
@@ -141,8 +372,16 @@ fn run() -> CompileResult<i32>
         build_command(matches, dump_flags)
     } else if let Some(matches) = matches.subcommand_matches("buildpkg") {
         build_package_command(matches, dump_flags)
+    } else if let Some(matches) = matches.subcommand_matches("doc") {
+        doc_command(matches)
+    } else if let Some(matches) = matches.subcommand_matches("dump-isa") {
+        dump_isa_command(matches)
+    } else if let Some(matches) = matches.subcommand_matches("explain") {
+        explain_command(matches)
     } else if let Some(matches) = matches.subcommand_matches("exports") {
         exports_command(matches)
+    } else if let Some(matches) = matches.subcommand_matches("inspect") {
+        inspect_command(matches)
     } else {
         println!("{}", matches.usage());
         Ok(1)
@@ -152,16 +391,22 @@ fn run() -> CompileResult<i32>
 
 fn main()
 {
-    match run()
-    {
-        Ok(ret) => {
+    // The compiler recurses over nested expressions, so give it a big stack
+    let compiler = thread::Builder::new()
+        .stack_size(COMPILER_STACK_SIZE)
+        .spawn(|| {
+            let ret = match run()
+            {
+                Ok(ret) => ret,
+                Err(e) => {
+                    e.print();
+                    -1
+                },
+            };
             llvm_shutdown();
-            exit(ret)
-        },
-        Err(e) => {
-            e.print();
-            llvm_shutdown();
-            exit(-1);
-        },
-    }
+            ret
+        })
+        .expect("Unable to start the compiler thread");
+
+    exit(compiler.join().unwrap_or(-1))
 }
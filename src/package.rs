@@ -1,17 +1,20 @@
 use std::rc::Rc;
 use std::io::Read;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 use either::Either;
 
-use ast::{Module, Import, ImportMap, TreePrinter, prefix};
+use ast::{Module, Import, ImportMap, InstantiationLimits, TreePrinter, prefix};
 use llvmbackend::{LinkerFlags, OutputType};
 use compileerror::{CompileResult, CompileError, type_error};
 use exportlibrary::ExportLibrary;
-use parser::parse_file;
+use buildcache::digest;
+use parser::{parse_file, is_identifier};
 use target::Target;
-use typechecker::type_check_module;
+use typechecker::{type_check_module, check_exported_functions, check_external_functions, check_main_function, no_main_function_error};
+use bytecode::ByteCodeModule;
+use timer::PassTimings;
 use span::Span;
 
 type MissingImportsMap = HashMap<String, Span>;
@@ -20,6 +23,7 @@ pub struct ImportData
 {
     pub imports: HashMap<String, Rc<Import>>,
     pub libraries: Vec<ExportLibrary>,
+    pub library_digests: Vec<u64>, // Hashes of the exports files of the libraries, used by the build cache
 }
 
 impl ImportData
@@ -71,6 +75,7 @@ pub struct Package
     pub modules: HashMap<String, Module>,
     pub import_data: ImportData,
     pub linker_flags: LinkerFlags,
+    pub instantiation_limits: InstantiationLimits,
 }
 
 impl Package
@@ -83,14 +88,22 @@ impl Package
             import_data: ImportData{
                 imports: ImportMap::new(),
                 libraries: Vec::new(),
+                library_digests: Vec::new(),
             },
             linker_flags: LinkerFlags::default(),
+            instantiation_limits: InstantiationLimits::default(),
         }
     }
 
-    pub fn add_library<R: Read>(&mut self, input: &mut R, dep: &str, deps_dir: &str, target_triplet: &str) -> Result<(), String>
+    pub fn add_library<R: Read>(&mut self, input: &mut R, dep: &str, deps_dir: &str, target_triplet: &str, ignore_version: bool) -> Result<(), String>
     {
-        let export_library = ExportLibrary::load(input)?;
+        let mut data = Vec::new();
+        input.read_to_end(&mut data).map_err(|e| format!("Failed to read the exports of {}: {}", dep, e))?;
+        if let Some(warning) = library_version_warning(&data, dep, ignore_version) {
+            println!("Warning: {}", warning);
+        }
+
+        let export_library = ExportLibrary::load(&mut &data[..])?;
         match export_library.output_type {
             OutputType::StaticLib => {
                 let lib_path = format!("{}/{}/{}/lib{}.a", deps_dir, target_triplet, dep, dep);
@@ -109,21 +122,48 @@ impl Package
         }
 
         self.import_data.libraries.push(export_library);
+        self.import_data.library_digests.push(digest(&data));
+        Ok(())
+    }
+
+    // Every part of a module name has to be an identifier, otherwise the module cannot be imported,
+    // and its symbol and build cache names can clash with those of other modules.
+    fn check_module_name(name: &str, path: &Path) -> CompileResult<()>
+    {
+        match name.split("::").find(|part| !is_identifier(part))
+        {
+            Some(part) => Err(CompileError::Other(
+                format!("{} cannot be used as module {}, because {} is not a valid identifier", path.display(), name, part))),
+            None => Ok(()),
+        }
+    }
+
+    // On case insensitive filesystems, modules whose names only differ in case would share their build cache files
+    fn check_module_name_case(files: &[(String, PathBuf)]) -> CompileResult<()>
+    {
+        let mut lower_case_names: HashMap<String, &str> = HashMap::new();
+        for &(ref name, _) in files {
+            if let Some(other) = lower_case_names.insert(name.to_lowercase(), name) {
+                return Err(CompileError::Other(
+                    format!("The modules {} and {} only differ in case, which does not work on case insensitive filesystems", other, name)));
+            }
+        }
+
         Ok(())
     }
 
-    fn parse_file_tree(&mut self, dir: &Path, namespace: &str, target: &Target) -> CompileResult<()>
+    fn find_source_files(dir: &Path, namespace: &str, files: &mut Vec<(String, PathBuf)>) -> CompileResult<()>
     {
         for entry in dir.read_dir()? {
             if let Ok(entry) = entry {
                 let path = entry.path();
                 if path.is_dir() {
                     let sub_ns = format!("{}::{}", namespace, path.file_stem().expect("Path must have a stem").to_string_lossy());
-                    self.parse_file_tree(&path, &sub_ns, target)?;
+                    Package::find_source_files(&path, &sub_ns, files)?;
                 } else if path.extension() == Some(OsStr::new("mhr")) {
                     let sub_ns = format!("{}::{}", namespace, path.file_stem().expect("Path must have a stem").to_string_lossy());
-                    let module = parse_file(&path, &sub_ns, target)?;
-                    self.modules.insert(sub_ns, module);
+                    Package::check_module_name(&sub_ns, &path)?;
+                    files.push((sub_ns, path));
                 }
             }
         }
@@ -131,22 +171,37 @@ impl Package
         Ok(())
     }
 
-    pub fn parse_files(&mut self, path: &Path, target: &Target) -> CompileResult<()>
+    // The source files of the package, together with the name of the module in them
+    pub fn source_files(&self, path: &Path) -> CompileResult<Vec<(String, PathBuf)>>
     {
+        let mut files = Vec::new();
         if path.exists() && path.is_file() {
-            self.modules.insert(self.name.clone(), parse_file(path, &self.name, target)?);
+            // The module of a single file build is never imported, so any file name will do, like hello-world.mhr
+            files.push((self.name.clone(), path.to_owned()));
         } else {
             if !path.exists() || !path.is_dir() {
                 return Err(CompileError::Other(format!("Cannot find {}.mhr or the directory {}", self.name, self.name)))
             }
-            let namespace = self.name.clone();
-            self.parse_file_tree(path, &namespace, target)?;
+            Package::find_source_files(path, &self.name, &mut files)?;
+            // Sorted, so the same pair of modules is reported no matter in which order the directory is read
+            files.sort();
+            Package::check_module_name_case(&files)?;
+        }
+
+        Ok(files)
+    }
+
+    pub fn parse_files(&mut self, files: &[(String, PathBuf)], target: &Target) -> CompileResult<()>
+    {
+        for &(ref namespace, ref path) in files {
+            let module = parse_file(path, namespace, target)?;
+            self.modules.insert(namespace.clone(), module);
         }
 
         Ok(())
     }
 
-    pub fn type_check(&mut self, target: &Target) -> CompileResult<()>
+    pub fn type_check(&mut self, target: &Target, timings: &mut PassTimings) -> CompileResult<()>
     {
         let mut count = 0;
         while count < self.modules.len() {
@@ -160,7 +215,8 @@ impl Package
 
                 match self.import_data.resolve_module_imports(module) {
                     Either::Left(imports) => {
-                        type_check_module(module, target, &imports)?;
+                        module.instantiations.limits = self.instantiation_limits;
+                        type_check_module(module, target, &imports, timings)?;
                         self.import_data.imports.insert(module.name.clone(), Rc::new(module.get_exported_symbols(target)));
                         count += 1;
                     }
@@ -180,7 +236,22 @@ impl Package
             }
         }
 
-        Ok(())
+        // Each module checks its own exports, but symbol names must also be unique between the modules
+        check_external_functions(self.modules.values())?;
+        check_exported_functions(self.modules.values())
+    }
+
+    // An executable starts at the main function of the module named after the package. When that module
+    // was loaded from the build cache, its main was checked when it was compiled, so it only has to exist.
+    pub fn check_main_function(&self, bc_mod: &ByteCodeModule, target: &Target) -> CompileResult<()>
+    {
+        let main_name = bc_mod.main_function_name();
+        match self.modules.get(&self.name)
+        {
+            Some(module) => check_main_function(module, &main_name, target),
+            None if bc_mod.functions.contains_key(&main_name) => Ok(()),
+            None => Err(no_main_function_error(&main_name)),
+        }
     }
 }
 
@@ -200,3 +271,114 @@ impl TreePrinter for Package
 
 
 
+
+// Libraries built by another version of the compiler might still work, so that is not an error
+fn library_version_warning(exports: &[u8], dep: &str, ignore_version: bool) -> Option<String>
+{
+    if ignore_version {
+        return None;
+    }
+
+    ExportLibrary::load_build_info(&mut &exports[..])
+        .ok()
+        .and_then(|build_info| build_info.version_mismatch(&format!("Library {}", dep)))
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::fs;
+    use ast::IntSize;
+    use buildinfo::{BuildInfo, COMPILER_VERSION};
+    use bytecode::{OptimizationLevel, OverflowMode};
+    use testutil::TempDir;
+
+    struct Fixture
+    {
+        dir: TempDir,
+    }
+
+    impl Fixture
+    {
+        fn new(files: &[&str]) -> Fixture
+        {
+            let dir = TempDir::new("menhir-package");
+            for file in files {
+                dir.write(file, "");
+            }
+            Fixture{dir}
+        }
+
+        fn module_names(&self, name: &str) -> CompileResult<Vec<String>>
+        {
+            let files = Package::new(name).source_files(&self.dir.join(name))?;
+            Ok(files.into_iter().map(|(name, _)| name).collect())
+        }
+    }
+
+    fn error_message(r: CompileResult<Vec<String>>) -> String
+    {
+        match r {
+            Err(CompileError::Other(msg)) => msg,
+            other => panic!("Expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_module_names()
+    {
+        let f = Fixture::new(&["app/main.mhr", "app/net/http_client.mhr", "app/notes.txt"]);
+        assert_eq!(f.module_names("app").unwrap(), vec!["app::main", "app::net::http_client"]);
+
+        let f = Fixture::new(&["app/main.mhr", "app/my-module.test.mhr"]);
+        let msg = error_message(f.module_names("app"));
+        assert!(msg.contains("cannot be used as module app::my-module.test, because my-module.test is not a valid identifier"));
+
+        let f = Fixture::new(&["app/main.mhr", "app/fn/util.mhr"]);
+        assert!(error_message(f.module_names("app")).contains("because fn is not a valid identifier"));
+
+        let f = Fixture::new(&["app/2d/shapes.mhr"]);
+        assert!(error_message(f.module_names("app")).contains("because 2d is not a valid identifier"));
+
+        let f = Fixture::new(&["let.mhr", "hello-world.mhr"]);
+        assert_eq!(Package::new("let").source_files(&f.dir.join("let.mhr")).unwrap().len(), 1);
+        assert_eq!(Package::new("hello-world").source_files(&f.dir.join("hello-world.mhr")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_module_names_differing_in_case()
+    {
+        let f = Fixture::new(&["app/util.mhr", "app/Util.mhr"]);
+        // On a case insensitive filesystem, this is just one file
+        if fs::read_dir(f.dir.join("app")).unwrap().count() == 2 {
+            let msg = error_message(f.module_names("app"));
+            assert!(msg.contains("The modules app::Util and app::util only differ in case"));
+        }
+    }
+
+    #[test]
+    fn test_library_version_warning()
+    {
+        let target = Target::new(IntSize::I64, "x86_64-unknown-linux-gnu");
+        let mut build_info = BuildInfo::new(&target, OptimizationLevel::Normal, OverflowMode::Check);
+        let exports = |build_info: &BuildInfo| {
+            let mut data = Vec::new();
+            ExportLibrary::new(&Package::new("lib"), OutputType::StaticLib, build_info.clone()).save(&mut data).expect("Cannot save exports");
+            data
+        };
+
+        assert_eq!(library_version_warning(&exports(&build_info), "lib", false), None);
+
+        build_info.compiler_version = "0.0.1".into();
+        let data = exports(&build_info);
+        let warning = library_version_warning(&data, "lib", false).expect("Expected a warning");
+        assert!(warning.contains("Library lib was built by version 0.0.1 of the compiler"));
+        assert!(warning.contains(COMPILER_VERSION));
+        assert_eq!(library_version_warning(&data, "lib", true), None);
+
+        let mut pkg = Package::new("app");
+        pkg.add_library(&mut &data[..], "lib", "deps", "x86_64-unknown-linux-gnu", false).expect("Cannot add library");
+        assert_eq!(pkg.import_data.libraries[0].build_info, build_info);
+    }
+}
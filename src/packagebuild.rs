@@ -1,26 +1,47 @@
-use std::fs::{File};
-use std::io::{Read};
+use std::fs::{self, File};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::env;
 use toml;
 
-use ast::{TreePrinter};
-use timer::{time_operation, time_operation_mut};
+use ast::{TreePrinter, InstantiationLimits};
+use typechecker::TypeMap;
+use timer::PassTimings;
 use llvmbackend::TargetMachine;
-use bytecode::{compile_to_byte_code, optimize_module, OptimizationLevel};
-use llvmbackend::{CodeGenOptions, OutputType, llvm_code_generation, link};
+use bytecode::{ByteCodeModule, compile_to_byte_code, optimize_module, eliminate_unused_functions, warn_large_copies, OptimizationLevel, OverflowMode};
+use llvmbackend::{CodeGenOptions, OutputType, EmitKind, llvm_code_generation, link, emit_llvm_file};
 use compileerror::{CompileResult, CompileError};
 use exportlibrary::ExportLibrary;
+use buildinfo::BuildInfo;
 use package::Package;
+use buildcache::{BuildCache, CACHE_DIRECTORY};
+use target::Target;
 
 
 pub struct BuildOptions
 {
-    pub optimize: bool,
+    pub optimization_level: OptimizationLevel,
+    pub overflow_mode: OverflowMode,
+    pub keep_all_functions: bool,
+    pub stack_check: bool,
+    pub emit: EmitKind,
     pub dump_flags: String,
+    pub dump_instantiations: Option<InstantiationDump>,
+    pub instantiation_limits: InstantiationLimits,
     pub target_machine: TargetMachine,
     pub sources_directory: String,
     pub import_directories: Vec<PathBuf>,
+    pub library_directories: Vec<PathBuf>,
+    pub use_cache: bool,
+    pub ignore_version: bool, // Do not warn about libraries built by another version of the compiler
+    pub warn_large_copy: Option<usize>, // Warn about implicit copies of values larger than this number of bytes
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InstantiationDump
+{
+    Tree, // Printed
+    Dot,  // Written to a graphviz file per module in the build directory
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -85,19 +106,70 @@ impl PackageData
         Ok(package)
     }
 
-    pub fn build(&self, build_options: &BuildOptions) -> CompileResult<()>
+    pub fn build(&self, build_options: &BuildOptions) -> CompileResult<PassTimings>
     {
         println!("Compiling for {}", build_options.target_machine.target.triplet);
+        let mut timings = PassTimings::new();
         for t in &self.target {
-            time_operation(2, "Total build time", ||{
-                t.build(build_options)
+            let mut target_timings = PassTimings::new();
+            timings.time_operation(2, "Total build time", ||{
+                t.build(build_options, &mut target_timings)
             })?;
+            timings.merge(target_timings);
         }
 
-        Ok(())
+        Ok(timings)
     }
 }
 
+// Parse, type check and compile a package to bytecode, the modules which did not change since the last build are loaded from the cache
+pub fn compile_package(
+    pkg: &mut Package,
+    sources: &[(String, PathBuf)],
+    mut cache: Option<&mut BuildCache>,
+    target: &Target,
+    overflow_mode: OverflowMode,
+    timings: &mut PassTimings) -> CompileResult<ByteCodeModule>
+{
+    let to_parse = match cache {
+        Some(ref mut cache) => cache.load(sources),
+        None => sources.to_vec(),
+    };
+
+    timings.time("Parsing", || pkg.parse_files(&to_parse, target))?;
+    let cached_modules = match cache {
+        Some(ref mut cache) => cache.take_loaded_modules(pkg),
+        None => Vec::new(),
+    };
+    let from_cache = !cached_modules.is_empty();
+
+    let mut type_check_timings = PassTimings::new();
+    timings.time_operation(2, "Type checking", ||{
+        pkg.type_check(target, &mut type_check_timings)
+    })?;
+    timings.merge(type_check_timings);
+
+    let bc_mod = timings.time_operation(2, "Compile to bytecode", ||{
+        compile_to_byte_code(pkg, cached_modules, target, overflow_mode)
+    })?;
+
+    // The order in which globals are initialized at startup depends on the code of all modules
+    let startup_init = bc_mod.globals.values().any(|g| g.init.is_none());
+    if startup_init && from_cache {
+        pkg.modules.clear();
+        pkg.import_data.imports.clear();
+        return compile_package(pkg, sources, None, target, overflow_mode, timings);
+    }
+
+    if let Some(cache) = cache {
+        if !startup_init {
+            cache.store(pkg, &bc_mod);
+        }
+    }
+
+    Ok(bc_mod)
+}
+
 fn output_file_name(name: &str, output_type: OutputType) -> String
 {
     match output_type {
@@ -109,11 +181,12 @@ fn output_file_name(name: &str, output_type: OutputType) -> String
 
 impl PackageTarget
 {
-    fn find_dependency_in_path(&self, dep: &str, deps_dir: &str, target_triplet: &str, pkg: &mut Package) -> CompileResult<bool>
+    fn find_dependency_in_path(&self, dep: &str, deps_dir: &str, build_options: &BuildOptions, pkg: &mut Package) -> CompileResult<bool>
     {
+        let target_triplet = &build_options.target_machine.target.triplet;
         let path = format!("{}/{}/{}/{}.mhr.exports", deps_dir, target_triplet, dep, dep);
         if let Ok(mut file) = File::open(&path) {
-            if let Ok(_) = pkg.add_library(&mut file, dep, deps_dir, target_triplet) {
+            if let Ok(_) = pkg.add_library(&mut file, dep, deps_dir, target_triplet, build_options.ignore_version) {
                 Ok(true)
             } else {
                 Ok(false)
@@ -126,19 +199,19 @@ impl PackageTarget
     fn find_dependency(&self, dep: &str, build_options: &BuildOptions, pkg: &mut Package) -> CompileResult<()>
     {
         // Always try the build directory first
-        if self.find_dependency_in_path(dep, "build", &build_options.target_machine.target.triplet, pkg)? {
+        if self.find_dependency_in_path(dep, "build", build_options, pkg)? {
             return Ok(())
         }
 
         for import_dir in &build_options.import_directories {
-            if self.find_dependency_in_path(dep, &import_dir.to_string_lossy(), &build_options.target_machine.target.triplet, pkg)? {
+            if self.find_dependency_in_path(dep, &import_dir.to_string_lossy(), build_options, pkg)? {
                 return Ok(())
             }
         }
 
         if let Ok(import_paths) = env::var("MENHIR_IMPORT_DIRS") {
             for path in import_paths.split(':') {
-                if self.find_dependency_in_path(dep, path, &build_options.target_machine.target.triplet, pkg)? {
+                if self.find_dependency_in_path(dep, path, build_options, pkg)? {
                     return Ok(())
                 }
             }
@@ -159,7 +232,7 @@ impl PackageTarget
     }
 
 
-    fn build(&self, build_options: &BuildOptions) -> CompileResult<()>
+    fn build(&self, build_options: &BuildOptions, timings: &mut PassTimings) -> CompileResult<()>
     {
         println!("Building target {}", self.name);
         let single_file = format!("{}/{}.mhr", build_options.sources_directory, self.name);
@@ -177,21 +250,39 @@ impl PackageTarget
         };
 
         let mut pkg = Package::new(&self.name);
+        pkg.instantiation_limits = build_options.instantiation_limits;
         self.find_dependencies(build_options, &mut pkg)?;
-        pkg.parse_files(path, &build_options.target_machine.target)?;
+        let sources = pkg.source_files(path)?;
+        let target = &build_options.target_machine.target;
+        let mut bc_mod = if build_options.use_cache {
+            let mut cache = BuildCache::new(Path::new(CACHE_DIRECTORY), target, build_options.optimization_level, build_options.overflow_mode, &pkg);
+            let bc_mod = compile_package(&mut pkg, &sources, Some(&mut cache), target, build_options.overflow_mode, timings)?;
+            if !cache.loaded_modules().is_empty() {
+                println!("  Loaded {} of {} modules from the build cache", cache.loaded_modules().len(), sources.len());
+            }
+            bc_mod
+        } else {
+            compile_package(&mut pkg, &sources, None, target, build_options.overflow_mode, timings)?
+        };
 
-        time_operation_mut(2, "Type checking", ||{
-            pkg.type_check(&build_options.target_machine.target)
-        })?;
+        // Libraries have no entry point
+        if self.output_type == OutputType::Binary {
+            pkg.check_main_function(&bc_mod, target)?;
+        }
 
         if build_options.dump_flags.contains("ast") || build_options.dump_flags.contains("all") {
             println!("AST: {}", pkg.name);
             pkg.print(0);
         }
 
-        let mut bc_mod = time_operation(2, "Compile to bytecode", ||{
-            compile_to_byte_code(&pkg, &build_options.target_machine.target)
-        })?;
+        if build_options.dump_flags.contains("types") || build_options.dump_flags.contains("all") {
+            for module in pkg.modules.values() {
+                println!("types: {}", module.name);
+                for ts in TypeMap::from_module(module, target).entries() {
+                    println!("  {}", ts);
+                }
+            }
+        }
 
         if build_options.dump_flags.contains("bytecode") || build_options.dump_flags.contains("all") {
             println!("bytecode:");
@@ -200,29 +291,73 @@ impl PackageTarget
             println!("------\n");
         }
 
-        time_operation_mut(2, "Optimization", ||{
-            if build_options.optimize {
-                optimize_module(&mut bc_mod, OptimizationLevel::Normal);
-            } else {
-                optimize_module(&mut bc_mod, OptimizationLevel::Minimal);
+        timings.time_operation(2, "Optimization", ||{
+            if !build_options.keep_all_functions {
+                eliminate_unused_functions(&mut bc_mod);
             }
+
+            optimize_module(&mut bc_mod, build_options.optimization_level);
         });
 
+        // After optimization, so copies which got optimized away are not reported
+        if let Some(threshold) = build_options.warn_large_copy {
+            warn_large_copies(&bc_mod, threshold, target);
+        }
+
+        let build_info = BuildInfo::new(target, build_options.optimization_level, build_options.overflow_mode);
+        bc_mod.build_info = Some(build_info.clone());
+
         let opts = CodeGenOptions{
             dump_ir: build_options.dump_flags.contains("ir") ||  build_options.dump_flags.contains("all"),
             build_dir: format!("build/{}/{}", build_options.target_machine.target.triplet, self.name),
             output_file_name: output_file_name(&self.name, self.output_type),
             output_type: self.output_type,
-            optimize: build_options.optimize,
+            optimize: build_options.optimization_level == OptimizationLevel::Normal,
         };
 
+        // Modules loaded from the build cache were not instantiated in this build, so they are not in the graph
+        match build_options.dump_instantiations
+        {
+            Some(InstantiationDump::Tree) => {
+                for module in pkg.modules.values() {
+                    println!("instantiations: {}", module.name);
+                    module.instantiations.print(1);
+                }
+            },
+
+            Some(InstantiationDump::Dot) => {
+                fs::create_dir_all(&opts.build_dir)?;
+                for module in pkg.modules.values() {
+                    let path = format!("{}/{}.instantiations.dot", opts.build_dir, module.name.replace("::", "."));
+                    println!("  Generating {}", path);
+                    File::create(&path)?.write_all(module.instantiations.to_dot(&module.name).as_bytes())?;
+                }
+            },
+
+            None => (),
+        }
 
 
-        let ctx = time_operation(2, "Code generation", ||{
-            llvm_code_generation(&bc_mod, &build_options.target_machine).map_err(CompileError::Other)
+        let ctx = timings.time_operation(2, "Code generation", ||{
+            llvm_code_generation(&bc_mod, &build_options.target_machine, build_options.stack_check).map_err(CompileError::Other)
         })?;
 
-        time_operation(2, "Linking", ||{
+        if build_options.emit != EmitKind::Object {
+            timings.time_operation(2, "Writing LLVM output", ||{
+                emit_llvm_file(&ctx, &opts, build_options.emit)
+            })?;
+            return Ok(());
+        }
+
+        for dir in &build_options.library_directories {
+            pkg.linker_flags.add_library_path(&dir.to_string_lossy());
+        }
+
+        for lib in bc_mod.libraries() {
+            pkg.linker_flags.add_library(&lib);
+        }
+
+        timings.time_operation(2, "Linking", ||{
             link(&ctx, &opts, &pkg.linker_flags)
         })?;
 
@@ -232,7 +367,7 @@ impl PackageTarget
                 let path = format!("{}/{}.mhr.exports", opts.build_dir, self.name);
                 let mut file = File::create(&path)?;
                 println!("  Generating {}", path);
-                let export_lib = ExportLibrary::new(&pkg, opts.output_type);
+                let export_lib = ExportLibrary::new(&pkg, opts.output_type, build_info);
                 export_lib.save(&mut file)?;
             }
 
@@ -0,0 +1,354 @@
+use std::collections::hash_map::{HashMap, Entry};
+use itertools::free::join;
+use ast::*;
+use compileerror::{CompileResult, CompileError, ErrorData, print_message};
+use errorcodes::ErrorCode;
+use span::Span;
+use target::Target;
+use super::tokenqueue::TokenQueue;
+use super::tokens::TokenKind;
+
+// A top level declaration, which is only added to the module if its cfg is enabled
+pub enum Declaration
+{
+    Function(Function),
+    External(ExternalFunction),
+    Global(GlobalBinding),
+    Type(TypeDeclaration),
+}
+
+impl Declaration
+{
+    fn cfg(&self) -> Option<&Cfg>
+    {
+        match *self
+        {
+            Declaration::Function(ref f) => f.cfg.as_ref(),
+            Declaration::External(ref e) => e.cfg.as_ref(),
+            Declaration::Global(ref g) => g.cfg.as_ref(),
+            Declaration::Type(ref t) => t.cfg(),
+        }
+    }
+}
+
+fn parse_cfg_predicate(tq: &mut TokenQueue) -> CompileResult<Cfg>
+{
+    let (name, span) = tq.expect_identifier()?;
+    match &name[..]
+    {
+        "not" | "any" | "all" if tq.is_next(&TokenKind::OpenParen) => {
+            tq.pop()?;
+            let mut cfgs = Vec::new();
+            while !tq.is_next(&TokenKind::CloseParen)
+            {
+                cfgs.push(parse_cfg_predicate(tq)?);
+                if !tq.is_next(&TokenKind::CloseParen) {
+                    tq.expect(&TokenKind::Comma)?;
+                }
+            }
+            tq.expect(&TokenKind::CloseParen)?;
+
+            match &name[..]
+            {
+                "not" if cfgs.len() == 1 => Ok(Cfg::Not(Box::new(cfgs.remove(0)))),
+                "not" => ErrorCode::InvalidAttribute.result(&span, "not expects exactly one cfg predicate"),
+                "any" => Ok(Cfg::Any(cfgs)),
+                _ => Ok(Cfg::All(cfgs)),
+            }
+        },
+
+        _ => {
+            tq.expect(&TokenKind::Assign(AssignOperator::Assign))?;
+            let (value, value_span) = tq.expect_string()?;
+            let span = span.expanded(value_span.end);
+            if !CFG_KEYS.contains(&&name[..]) {
+                // Unknown keys never match, so the declaration is dropped
+                print_message(&format!("Warning: unknown cfg key {}, expecting one of {}", name, join(CFG_KEYS.iter(), ", ")), &span);
+            }
+            Ok(Cfg::Option{key: name, value, span})
+        },
+    }
+}
+
+pub enum Attribute
+{
+    Cfg(Cfg),
+    Inline(Span),
+    ReprC(Span),
+    Export(Option<String>, Span),
+    Link(LinkLibrary, Span),
+    LinkName(String, Span),
+}
+
+// Parses name = "value" pairs of the link attribute, the library name is required and the kind defaults to dylib
+fn parse_link_library(tq: &mut TokenQueue, span: &Span) -> CompileResult<LinkLibrary>
+{
+    let mut name = None;
+    let mut kind = LinkKind::Dylib;
+    while !tq.is_next(&TokenKind::CloseParen)
+    {
+        let (key, key_span) = tq.expect_identifier()?;
+        tq.expect(&TokenKind::Assign(AssignOperator::Assign))?;
+        let (value, value_span) = tq.expect_string()?;
+        match &key[..]
+        {
+            "name" => name = Some(value),
+            "kind" => {
+                kind = match &value[..]
+                {
+                    "static" => LinkKind::Static,
+                    "dylib" => LinkKind::Dylib,
+                    _ => return ErrorCode::InvalidAttribute.result(&value_span, format!("Unknown link kind {}, expecting static or dylib", value)),
+                };
+            },
+            _ => return ErrorCode::InvalidAttribute.result(&key_span, format!("Unknown link key {}, expecting name or kind", key)),
+        }
+
+        if !tq.is_next(&TokenKind::CloseParen) {
+            tq.expect(&TokenKind::Comma)?;
+        }
+    }
+
+    match name
+    {
+        Some(name) => Ok(LinkLibrary{name, kind}),
+        None => ErrorCode::InvalidAttribute.result(span, "The link attribute requires the name of a library"),
+    }
+}
+
+// Parses [cfg(...)], [inline], [repr(c)], [export("name")], [link(name = "lib")] or [link_name = "symbol"],
+// the leading # has already been popped
+pub fn parse_attribute(tq: &mut TokenQueue) -> CompileResult<Attribute>
+{
+    tq.expect(&TokenKind::OpenBracket)?;
+    let (name, span) = tq.expect_identifier()?;
+    match &name[..]
+    {
+        "cfg" => {
+            tq.expect(&TokenKind::OpenParen)?;
+            let cfg = parse_cfg_predicate(tq)?;
+            tq.expect(&TokenKind::CloseParen)?;
+            tq.expect(&TokenKind::CloseBracket)?;
+            Ok(Attribute::Cfg(cfg))
+        },
+
+        "inline" => {
+            tq.expect(&TokenKind::CloseBracket)?;
+            Ok(Attribute::Inline(span))
+        },
+
+        "repr" => {
+            tq.expect(&TokenKind::OpenParen)?;
+            let (repr, repr_span) = tq.expect_identifier()?;
+            if repr != "c" {
+                return ErrorCode::InvalidAttribute.result(&repr_span, format!("Unknown representation {}, only repr(c) is supported", repr));
+            }
+            tq.expect(&TokenKind::CloseParen)?;
+            let end = tq.expect(&TokenKind::CloseBracket)?;
+            Ok(Attribute::ReprC(span.expanded(end.span.end)))
+        },
+
+        "export" => {
+            let symbol = if tq.is_next(&TokenKind::OpenParen) {
+                tq.pop()?;
+                let (symbol, _) = tq.expect_string()?;
+                tq.expect(&TokenKind::CloseParen)?;
+                Some(symbol)
+            } else {
+                None
+            };
+            let end = tq.expect(&TokenKind::CloseBracket)?;
+            Ok(Attribute::Export(symbol, span.expanded(end.span.end)))
+        },
+
+        "link" => {
+            tq.expect(&TokenKind::OpenParen)?;
+            let lib = parse_link_library(tq, &span)?;
+            tq.expect(&TokenKind::CloseParen)?;
+            let end = tq.expect(&TokenKind::CloseBracket)?;
+            Ok(Attribute::Link(lib, span.expanded(end.span.end)))
+        },
+
+        "link_name" => {
+            tq.expect(&TokenKind::Assign(AssignOperator::Assign))?;
+            let (symbol, _) = tq.expect_string()?;
+            let end = tq.expect(&TokenKind::CloseBracket)?;
+            Ok(Attribute::LinkName(symbol, span.expanded(end.span.end)))
+        },
+
+        _ => ErrorCode::InvalidAttribute.result(&span, format!("Unknown attribute {}", name)),
+    }
+}
+
+// A named definition, used to report collisions between different kinds of definitions
+struct Definition<'a>
+{
+    kind: &'static str,
+    name: &'a str,
+    span: &'a Span,
+}
+
+impl<'a> Definition<'a>
+{
+    fn new(kind: &'static str, name: &'a str, span: &'a Span) -> Definition<'a>
+    {
+        Definition{kind, name, span}
+    }
+
+    // Names are resolved by their last component, so that is what has to be unique
+    fn short_name(&self) -> &'a str
+    {
+        self.name.rsplit("::").next().unwrap_or(self.name)
+    }
+}
+
+fn collision_error(def: &Definition, previous: &Definition) -> CompileError
+{
+    CompileError::Many(vec![
+        ErrorCode::Redefinition.error(def.span, format!("{} {} redefined", def.kind, def.name)),
+        CompileError::Parse(ErrorData::new(previous.span, format!("{} {} previously defined here", previous.kind, previous.name))),
+    ])
+}
+
+fn redefinition_error(kind: &'static str, name: &str, span: &Span, previous: &Span) -> CompileError
+{
+    collision_error(&Definition::new(kind, name, span), &Definition::new(kind, name, previous))
+}
+
+fn add_declaration(module: &mut Module, decl: Declaration) -> CompileResult<()>
+{
+    match decl
+    {
+        Declaration::Function(f) => {
+            if let Some(prev) = module.functions.get(&f.sig.name) {
+                return Err(redefinition_error("Function", &f.sig.name, &f.span, &prev.span));
+            }
+            module.functions.insert(f.sig.name.clone(), f);
+        },
+
+        Declaration::External(e) => {
+            if let Some(prev) = module.externals.get(&e.sig.name) {
+                return Err(redefinition_error("External function", &e.sig.name, &e.span, &prev.span));
+            }
+            module.externals.insert(e.sig.name.clone(), e);
+        },
+
+        Declaration::Global(g) => {
+            if let Some(prev) = module.globals.get(&g.name) {
+                return Err(redefinition_error("Global", &g.name, &g.span, &prev.span));
+            }
+            module.globals.insert(g.name.clone(), g);
+        },
+
+        Declaration::Type(t) => {
+            if let Some(prev) = module.types.get(t.name()) {
+                return Err(redefinition_error("Type", t.name(), &t.span(), &prev.span()));
+            }
+            module.types.insert(t.name().into(), t);
+        },
+    }
+
+    Ok(())
+}
+
+// Add the declarations whose cfg matches the target to the module, and drop the others
+pub fn add_enabled_declarations(module: &mut Module, declarations: Vec<Declaration>, target: &Target) -> CompileResult<()>
+{
+    for decl in declarations
+    {
+        if decl.cfg().map(|cfg| cfg.is_enabled(target)).unwrap_or(true) {
+            add_declaration(module, decl)?;
+        }
+    }
+
+    check_duplicate_definitions(module)
+}
+
+// Report every definition whose name is already used by an earlier definition in the same list
+fn find_collisions(mut defs: Vec<Definition>, errors: &mut Vec<CompileError>)
+{
+    defs.sort_by_key(|d| d.span.start);
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for (idx, def) in defs.iter().enumerate()
+    {
+        match seen.entry(def.short_name())
+        {
+            Entry::Occupied(e) => errors.push(collision_error(def, &defs[*e.get()])),
+            Entry::Vacant(v) => {
+                v.insert(idx);
+            },
+        }
+    }
+}
+
+// Any number of arguments can be discarded with _
+fn check_arguments(args: &[Argument], errors: &mut Vec<CompileError>)
+{
+    find_collisions(args.iter().filter(|a| a.name != "_").map(|a| Definition::new("Argument", &a.name, &a.span)).collect(), errors);
+}
+
+fn check_struct_members(sd: &StructDeclaration, errors: &mut Vec<CompileError>)
+{
+    find_collisions(sd.members.iter().map(|m| Definition::new("Struct member", &m.name, &m.span)).collect(), errors);
+}
+
+// Functions, types, sum type cases, globals and externals share one namespace, so each name
+// may only be defined once. Struct members and function arguments must be unique within their declaration.
+fn check_duplicate_definitions(module: &Module) -> CompileResult<()>
+{
+    let mut errors = Vec::new();
+    let mut defs = Vec::new();
+    for f in module.functions.values()
+    {
+        defs.push(Definition::new("Function", &f.sig.name, &f.span));
+        check_arguments(&f.sig.args, &mut errors);
+    }
+
+    for e in module.externals.values()
+    {
+        defs.push(Definition::new("External function", &e.sig.name, &e.span));
+        check_arguments(&e.sig.args, &mut errors);
+    }
+
+    for g in module.globals.values()
+    {
+        defs.push(Definition::new("Global", &g.name, &g.span));
+    }
+
+    for t in module.types.values()
+    {
+        match *t
+        {
+            TypeDeclaration::Interface(ref i) => defs.push(Definition::new("Interface", &i.name, &i.span)),
+
+            TypeDeclaration::Struct(ref sd) => {
+                defs.push(Definition::new("Struct", &sd.name, &sd.span));
+                check_struct_members(sd, &mut errors);
+            },
+
+            TypeDeclaration::Sum(ref st) => {
+                let sum_type = Definition::new("Sum type", &st.name, &st.span);
+                for c in &st.cases
+                {
+                    // A case may have the same name as its sum type
+                    let case = Definition::new("Sum type case", &c.name, &c.span);
+                    if case.short_name() != sum_type.short_name() {
+                        defs.push(case);
+                    }
+                    if let Some(ref sd) = c.data {
+                        check_struct_members(sd, &mut errors);
+                    }
+                }
+                defs.push(sum_type);
+            },
+        }
+    }
+
+    find_collisions(defs, &mut errors);
+    match errors.len()
+    {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(CompileError::Many(errors)),
+    }
+}
@@ -1,10 +1,15 @@
-use std::io::{Read, BufReader, BufRead};
+use std::io::Read;
 use std::mem;
+use std::str;
 use compileerror::{CompileResult, parse_error_result};
+use errorcodes::ErrorCode;
 use super::tokenqueue::TokenQueue;
 use super::tokens::{TokenKind, Token};
 use ast::{BinaryOperator, UnaryOperator, AssignOperator};
-use span::{Span, Pos};
+use span::{Span, Pos, TAB_WIDTH};
+
+// Some editors start UTF-8 files with a byte order mark, it is not part of the code
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
 
 
 
@@ -13,11 +18,14 @@ enum LexState
 {
     StartOfLine,
     Idle,
+    Hash,
     Comment,
+    DocComment,
     Identifier,
     Number,
     Operator,
     InString,
+    InByteString,
     InChar,
 }
 
@@ -31,6 +39,8 @@ pub struct Lexer
     escape_code: bool,
     file_name: String,
     indent_level: usize,
+    num_tokens: usize,
+    comments: Vec<(usize, Token)>, // With the number of tokens before them
 }
 
 fn is_operator_start(c: char) -> bool
@@ -43,11 +53,66 @@ fn is_operator_start(c: char) -> bool
     false
 }
 
+const OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "%", ">", ">=", "<", "<=", "=", "+=", "-=", "*=", "/=", "&&=", "||=",
+    "==", "!", "!=", "&&", "||", "->", "=>", ":", "::", "|", ".", "..", "..=", "...", "&",
+];
+
+// Whether op is an operator, or the start of one
+fn is_operator_prefix(op: &str) -> bool
+{
+    OPERATORS.iter().any(|o| o.starts_with(op))
+}
+
 fn is_identifier_start(c: char) -> bool
 {
     c.is_alphanumeric() || c == '_'
 }
 
+fn keyword(name: &str) -> Option<TokenKind>
+{
+    let tok = match name
+    {
+        "import" => TokenKind::Import,
+        "match" => TokenKind::Match,
+        "let" => TokenKind::Let,
+        "in" => TokenKind::In,
+        "true" => TokenKind::True,
+        "false" => TokenKind::False,
+        "type" => TokenKind::Type,
+        "struct" => TokenKind::Struct,
+        "enum" => TokenKind::Enum,
+        "if" => TokenKind::If,
+        "else" => TokenKind::Else,
+        "extern" => TokenKind::Extern,
+        "new" => TokenKind::New,
+        "delete" => TokenKind::Delete,
+        "while" => TokenKind::While,
+        "for" => TokenKind::For,
+        "nil" => TokenKind::Nil,
+        "null" => TokenKind::Null,
+        "var" => TokenKind::Var,
+        "as" => TokenKind::BinaryOperator(BinaryOperator::As),
+        "is" => TokenKind::BinaryOperator(BinaryOperator::Is),
+        "interface" => TokenKind::Interface,
+        "fn" => TokenKind::Func,
+        "return" => TokenKind::Return,
+        _ => return None,
+    };
+    Some(tok)
+}
+
+// Whether name is lexed as a single identifier, names of modules have to be, otherwise they cannot be imported
+pub fn is_identifier(name: &str) -> bool
+{
+    let mut chars = name.chars();
+    match chars.next()
+    {
+        Some(c) if is_identifier_start(c) && !c.is_ascii_digit() => chars.all(is_identifier_start) && keyword(name).is_none(),
+        _ => false,
+    }
+}
+
 impl Lexer
 {
     pub fn new(file_name: &str) -> Lexer
@@ -61,6 +126,8 @@ impl Lexer
             escape_code: false,
             file_name: file_name.into(),
             indent_level: 0,
+            num_tokens: 0,
+            comments: Vec::new(),
         }
     }
 
@@ -90,7 +157,7 @@ impl Lexer
                 Ok(())
             }
             ' ' | '\t' => Ok(()),
-            '#' => {self.state = LexState::Comment; Ok(())},
+            '#' => {self.start(c, LexState::Hash); Ok(())},
             ',' => {self.add(TokenKind::Comma, span); Ok(())},
             '(' => {self.add(TokenKind::OpenParen, span); Ok(())},
             ')' => {self.add(TokenKind::CloseParen, span); Ok(())},
@@ -109,49 +176,67 @@ impl Lexer
             ch if is_identifier_start(ch) => {self.start(c, LexState::Identifier); Ok(())},
             ch if is_operator_start(ch) => {self.start(c, LexState::Operator); Ok(())}
             _ => {
-                parse_error_result(&span, format!("Unexpected char {}", c))
+                ErrorCode::UnexpectedToken.result(&span, format!("Unexpected char {}", c))
             }
         }
     }
 
+    // #[ starts an attribute, ## a doc comment, anything else a comment
+    fn hash(&mut self, c: char) -> CompileResult<()>
+    {
+        self.data.clear();
+        if c == '[' {
+            let span = Span::single(&self.file_name, self.token_start_pos);
+            self.add(TokenKind::Hash, span);
+            self.state = LexState::Idle;
+            self.idle(c)
+        } else if c == '#' {
+            self.state = LexState::DocComment;
+            Ok(())
+        } else {
+            self.state = LexState::Comment;
+            self.comment(c)
+        }
+    }
+
     fn comment(&mut self, c: char) -> CompileResult<()>
     {
         if c == '\n' {
+            self.add_comment("#");
             self.start_of_new_line();
+        } else {
+            self.data.push(c);
         }
         Ok(())
     }
 
-    fn add_identifier(&mut self) -> TokenKind
+    // The parser never sees comments, they are only kept for lex
+    fn add_comment(&mut self, prefix: &str)
     {
-        let tok = match &self.data[..]
-        {
-            "import" => TokenKind::Import,
-            "match" => TokenKind::Match,
-            "let" => TokenKind::Let,
-            "in" => TokenKind::In,
-            "true" => TokenKind::True,
-            "false" => TokenKind::False,
-            "type" => TokenKind::Type,
-            "struct" => TokenKind::Struct,
-            "enum" => TokenKind::Enum,
-            "if" => TokenKind::If,
-            "else" => TokenKind::Else,
-            "extern" => TokenKind::Extern,
-            "new" => TokenKind::New,
-            "delete" => TokenKind::Delete,
-            "while" => TokenKind::While,
-            "for" => TokenKind::For,
-            "nil" => TokenKind::Nil,
-            "null" => TokenKind::Null,
-            "var" => TokenKind::Var,
-            "as" => TokenKind::BinaryOperator(BinaryOperator::As),
-            "interface" => TokenKind::Interface,
-            "fn" => TokenKind::Func,
-            "return" => TokenKind::Return,
-            _ => TokenKind::Identifier(mem::replace(&mut self.data, String::new())),
-        };
+        let text = format!("{}{}", prefix, self.data.trim_end());
+        let tok = Token::new(TokenKind::Comment(text), self.current_span());
+        self.comments.push((self.num_tokens, tok));
+    }
+
+    // Doc comments are not tokens, the parser attaches them to the declaration which follows them
+    fn doc_comment(&mut self, c: char) -> CompileResult<()>
+    {
+        if c == '\n' {
+            self.add_comment("##");
+            let span = self.current_span();
+            let data = mem::replace(&mut self.data, String::new());
+            let line = data.strip_prefix(' ').unwrap_or(&data);
+            self.tokens.add_doc_comment(line.trim_end(), span);
+            self.start_of_new_line();
+        } else {
+            self.data.push(c);
+        }
+        Ok(())
+    }
 
+    fn add_identifier(&mut self) -> TokenKind
+    {
+        let tok = keyword(&self.data).unwrap_or_else(|| TokenKind::Identifier(mem::replace(&mut self.data, String::new())));
         self.data.clear();
         tok
     }
@@ -168,7 +253,13 @@ impl Lexer
 
     fn identifier(&mut self, c: char) -> CompileResult<()>
     {
-        if is_identifier_start(c)
+        if c == '"' && self.data == "b"
+        {
+            self.data.clear();
+            self.state = LexState::InByteString;
+            Ok(())
+        }
+        else if is_identifier_start(c)
         {
             self.data.push(c);
             Ok(())
@@ -185,7 +276,31 @@ impl Lexer
 
     fn number(&mut self, c: char) -> CompileResult<()>
     {
-        if c.is_numeric() || c == '.' || c == 'e'
+        if c == '.' && self.data.ends_with('.')
+        {
+            // 0..9, the number ends before the first dot, which starts a range operator
+            self.data.pop();
+            let num = mem::replace(&mut self.data, String::new());
+            let span = Span::new(&self.file_name, self.token_start_pos, Pos::new(self.pos.line, self.pos.offset - 2));
+            self.add(TokenKind::Number(num), span);
+            self.token_start_pos = Pos::new(self.pos.line, self.pos.offset - 1);
+            self.state = LexState::Operator;
+            self.data.push_str("..");
+            Ok(())
+        }
+        else if self.data.ends_with('.') && (c.is_alphabetic() || c == '_')
+        {
+            // t.0.len, the number ends before the dot, which starts a member access
+            self.data.pop();
+            let num = mem::replace(&mut self.data, String::new());
+            let span = Span::new(&self.file_name, self.token_start_pos, Pos::new(self.pos.line, self.pos.offset - 2));
+            self.add(TokenKind::Number(num), span);
+            self.token_start_pos = Pos::new(self.pos.line, self.pos.offset - 1);
+            self.state = LexState::Operator;
+            self.data.push('.');
+            self.operator(c)
+        }
+        else if c.is_numeric() || c == '.' || c == 'e'
         {
             self.data.push(c);
             Ok(())
@@ -231,8 +346,11 @@ impl Lexer
             "::" => Ok(TokenKind::DoubleColon),
             "|" => Ok(TokenKind::Pipe),
             "." => Ok(TokenKind::BinaryOperator(BinaryOperator::Dot)),
+            "..." => Ok(TokenKind::Ellipsis),
+            ".." => Ok(TokenKind::DotDot),
+            "..=" => Ok(TokenKind::DotDotEquals),
             "&" => Ok(TokenKind::Ampersand),
-            _ => parse_error_result(&self.current_single_span(), format!("Invalid operator {}", self.data)),
+            _ => ErrorCode::UnexpectedToken.result(&self.current_single_span(), format!("Invalid operator {}", self.data)),
         }
     }
 
@@ -252,7 +370,17 @@ impl Lexer
         else
         {
             self.data.push(c);
-            Ok(())
+            if is_operator_prefix(&self.data) {
+                return Ok(());
+            }
+
+            // Two operators next to each other, for example >: in Result<int, string>:
+            self.data.pop();
+            let kind = self.data_to_token_kind()?;
+            self.state = LexState::Idle;
+            let span = self.current_span();
+            self.add(kind, span);
+            self.idle(c)
         }
     }
 
@@ -295,6 +423,29 @@ impl Lexer
         Ok(())
     }
 
+    // The escape codes are kept until the end of the literal, and then turned into bytes
+    fn in_byte_string(&mut self, c: char) -> CompileResult<()>
+    {
+        if self.escape_code || c != '"'
+        {
+            self.escape_code = !self.escape_code && c == '\\';
+            self.data.push(c);
+            return Ok(());
+        }
+
+        let mut span = self.current_span();
+        span.end.offset += 1; // Need to include the quote
+        let bytes = match byte_string_contents(&self.data) {
+            Ok(bytes) => bytes,
+            Err(msg) => return ErrorCode::InvalidLiteral.result(&span, msg),
+        };
+
+        self.data.clear();
+        self.add(TokenKind::ByteStringLiteral(bytes), span);
+        self.state = LexState::Idle;
+        Ok(())
+    }
+
     fn in_char(&mut self, c: char) -> CompileResult<()>
     {
         self.in_string_or_char_literal(c, '\'');
@@ -302,8 +453,8 @@ impl Lexer
         {
             let mut span = self.current_span();
             span.end.offset += 1; // Need to include the single quote
-            if self.data.len() != 1 {
-                return parse_error_result(&span, "Invalid char literal");
+            if self.data.chars().count() != 1 {
+                return ErrorCode::InvalidLiteral.result(&span, "Invalid char literal");
             }
 
             let c = self.data.chars().nth(0).expect("Invalid char literal");
@@ -328,7 +479,7 @@ impl Lexer
                 Ok(())
             }
             '\t' => {
-                self.indent_level += 4;
+                self.indent_level += TAB_WIDTH;
                 Ok(())
             }
             _ => {
@@ -347,25 +498,44 @@ impl Lexer
         {
             LexState::StartOfLine => self.start_of_line(c),
             LexState::Idle => self.idle(c),
+            LexState::Hash => self.hash(c),
             LexState::Comment => self.comment(c),
+            LexState::DocComment => self.doc_comment(c),
             LexState::Identifier => self.identifier(c),
             LexState::Number =>  self.number(c),
             LexState::Operator => self.operator(c),
             LexState::InString => self.in_string(c),
+            LexState::InByteString => self.in_byte_string(c),
             LexState::InChar => self.in_char(c),
         }
     }
 
     fn add(&mut self, tok: TokenKind, span: Span)
     {
+        self.num_tokens += 1;
         self.tokens.add(Token::new(tok, span));
     }
 
     pub fn read<Input: Read>(&mut self, input: &mut Input) -> CompileResult<TokenQueue>
     {
-        for line in BufReader::new(input).lines()
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let bom_len = if data.starts_with(UTF8_BOM) {UTF8_BOM.len()} else {0};
+
+        for (line_start, line) in lines(&data[bom_len..])
         {
-            for c in line?.chars()
+            let line = match str::from_utf8(line) {
+                Ok(line) => line,
+                Err(e) => {
+                    let valid = e.valid_up_to();
+                    let column = str::from_utf8(&line[..valid]).map(|prefix| prefix.chars().count()).unwrap_or(0) + 1;
+                    let span = Span::single(&self.file_name, Pos::new(self.pos.line, column));
+                    return parse_error_result(&span, format!("Invalid UTF-8 byte 0x{:02x} at byte offset {}, source files must be UTF-8",
+                        line[valid], bom_len + line_start + valid));
+                }
+            };
+
+            for c in line.chars()
             {
                 self.feed(c)?;
                 self.pos.offset += 1;
@@ -383,6 +553,62 @@ impl Lexer
     }
 }
 
+// All tokens of source, comments included, for tools which need more than the AST, like a formatter.
+// Without the comments, these are exactly the tokens the parser gets.
+pub fn lex(source: &str) -> CompileResult<Vec<Token>>
+{
+    let mut lexer = Lexer::new("");
+    let mut tokens: Vec<Token> = lexer.read(&mut source.as_bytes())?.collect();
+    // Inserting from the back keeps the positions of the earlier comments valid
+    for (idx, comment) in lexer.comments.drain(..).rev() {
+        tokens.insert(idx, comment);
+    }
+    Ok(tokens)
+}
+
+// The bytes of a byte string literal, it has the same escape codes as a string, and \xNN for any byte
+fn byte_string_contents(literal: &str) -> Result<Vec<u8>, String>
+{
+    let mut bytes = Vec::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    let mut buf = [0; 4];
+    while let Some(c) = chars.next()
+    {
+        let escaped = if c == '\\' {chars.next()} else {None};
+        match escaped
+        {
+            None => bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                if digits.len() != 2 || !digits.chars().all(|d| d.is_ascii_hexdigit()) {
+                    return Err(format!("Invalid escape code \\x{} in byte string literal, expecting two hexadecimal digits", digits));
+                }
+                bytes.push(u8::from_str_radix(&digits, 16).expect("Invalid hexadecimal digits"));
+            },
+            Some(e) => bytes.extend_from_slice(e.encode_utf8(&mut buf).as_bytes()),
+        }
+    }
+    Ok(bytes)
+}
+
+// The lines of data and the byte offset at which each of them starts, without their \n or \r\n, like BufRead::lines
+fn lines(data: &[u8]) -> Vec<(usize, &[u8])>
+{
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while start < data.len()
+    {
+        let end = data[start..].iter().position(|&b| b == b'\n').map_or(data.len(), |idx| start + idx);
+        let line = &data[start..end];
+        lines.push((start, if line.ends_with(b"\r") {&line[..line.len() - 1]} else {line}));
+        start = end + 1;
+    }
+    lines
+}
+
 
 
 #[cfg(test)]
@@ -390,7 +616,7 @@ mod tests
 {
     use std::io::Cursor;
     use ast::{BinaryOperator, UnaryOperator, AssignOperator};
-    use parser::lexer::Lexer;
+    use parser::lexer::{Lexer, is_identifier, lex};
     use parser::tokens::*;
     use span::*;
 
@@ -488,4 +714,193 @@ mod tests
             tok(TokenKind::EOF, 2, 1, 2, 1),
         ]);
     }
+
+    #[test]
+    fn test_byte_string()
+    {
+        let mut cursor = Cursor::new(r#"b"a\xff\"π" b """#);
+        let tokens: Vec<Token> = Lexer::new("")
+            .read(&mut cursor)
+            .expect("Lexing failed")
+            .collect();
+
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::ByteStringLiteral(vec![b'a', 0xff, b'"', 0xcf, 0x80]), 1, 1, 1, 11),
+            tok(TokenKind::Identifier("b".into()), 1, 13, 1, 13),
+            tok(TokenKind::StringLiteral("".into()), 1, 15, 1, 16),
+            tok(TokenKind::EOF, 2, 1, 2, 1),
+        ]);
+
+        let mut cursor = Cursor::new(r#"b"\x4""#);
+        assert!(Lexer::new("").read(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn test_chars()
+    {
+        let mut cursor = Cursor::new("'a' '€'");
+        let tokens: Vec<Token> = Lexer::new("")
+            .read(&mut cursor)
+            .expect("Lexing failed")
+            .collect();
+
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::CharLiteral('a'), 1, 1, 1, 3),
+            tok(TokenKind::CharLiteral('€'), 1, 5, 1, 7),
+            tok(TokenKind::EOF, 2, 1, 2, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_adjacent_operators()
+    {
+        let mut cursor = Cursor::new(">: x)? !-");
+        let tokens: Vec<Token> = Lexer::new("")
+            .read(&mut cursor)
+            .expect("Lexing failed")
+            .collect();
+
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::BinaryOperator(BinaryOperator::GreaterThan), 1, 1, 1, 1),
+            tok(TokenKind::Colon, 1, 2, 1, 2),
+            tok(TokenKind::Identifier("x".into()), 1, 4, 1, 4),
+            tok(TokenKind::CloseParen, 1, 5, 1, 5),
+            tok(TokenKind::QuestionMark, 1, 6, 1, 6),
+            tok(TokenKind::UnaryOperator(UnaryOperator::Not), 1, 8, 1, 8),
+            tok(TokenKind::BinaryOperator(BinaryOperator::Sub), 1, 9, 1, 9),
+            tok(TokenKind::EOF, 2, 1, 2, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_ranges()
+    {
+        let mut cursor = Cursor::new("0..10 'a'..='z' 1.5");
+        let tokens: Vec<Token> = Lexer::new("")
+            .read(&mut cursor)
+            .expect("Lexing failed")
+            .collect();
+
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::Number("0".into()), 1, 1, 1, 1),
+            tok(TokenKind::DotDot, 1, 2, 1, 3),
+            tok(TokenKind::Number("10".into()), 1, 4, 1, 5),
+            tok(TokenKind::CharLiteral('a'), 1, 7, 1, 9),
+            tok(TokenKind::DotDotEquals, 1, 10, 1, 12),
+            tok(TokenKind::CharLiteral('z'), 1, 13, 1, 15),
+            tok(TokenKind::Number("1.5".into()), 1, 17, 1, 19),
+            tok(TokenKind::EOF, 2, 1, 2, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_member_access_after_tuple_member()
+    {
+        let mut cursor = Cursor::new("t.0.len");
+        let tokens: Vec<Token> = Lexer::new("")
+            .read(&mut cursor)
+            .expect("Lexing failed")
+            .collect();
+
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::Identifier("t".into()), 1, 1, 1, 1),
+            tok(TokenKind::BinaryOperator(BinaryOperator::Dot), 1, 2, 1, 2),
+            tok(TokenKind::Number("0".into()), 1, 3, 1, 3),
+            tok(TokenKind::BinaryOperator(BinaryOperator::Dot), 1, 4, 1, 4),
+            tok(TokenKind::Identifier("len".into()), 1, 5, 1, 7),
+            tok(TokenKind::EOF, 2, 1, 2, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_attribute_and_comment()
+    {
+        let mut cursor = Cursor::new("#[cfg] # [not an attribute]");
+        let tokens: Vec<Token> = Lexer::new("")
+            .read(&mut cursor)
+            .expect("Lexing failed")
+            .collect();
+
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::Hash, 1, 1, 1, 1),
+            tok(TokenKind::OpenBracket, 1, 2, 1, 2),
+            tok(TokenKind::Identifier("cfg".into()), 1, 3, 1, 5),
+            tok(TokenKind::CloseBracket, 1, 6, 1, 6),
+            tok(TokenKind::EOF, 2, 1, 2, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_lex_comments()
+    {
+        let tokens = lex("# Leading\nlet x = 5 # trailing \n    ## Doc\n#").expect("Lexing failed");
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::Comment("# Leading".into()), 1, 1, 1, 9),
+            tok(TokenKind::Indent(0), 1, 1, 2, 1),
+            tok(TokenKind::Let, 2, 1, 2, 3),
+            tok(TokenKind::Identifier("x".into()), 2, 5, 2, 5),
+            tok(TokenKind::Assign(AssignOperator::Assign), 2, 7, 2, 7),
+            tok(TokenKind::Number("5".into()), 2, 9, 2, 9),
+            tok(TokenKind::Comment("# trailing".into()), 2, 11, 2, 21),
+            tok(TokenKind::Indent(4), 2, 11, 3, 5),
+            tok(TokenKind::Comment("## Doc".into()), 3, 5, 3, 10),
+            tok(TokenKind::Indent(0), 3, 5, 4, 1),
+            tok(TokenKind::Comment("#".into()), 4, 1, 4, 1),
+            tok(TokenKind::EOF, 5, 1, 5, 1),
+        ]);
+
+        // The parser gets the same tokens, without the comments
+        let mut cursor = Cursor::new("# Leading\nlet x = 5 # trailing \n    ## Doc\n#");
+        let parsed: Vec<Token> = Lexer::new("").read(&mut cursor).expect("Lexing failed").collect();
+        let without_comments: Vec<Token> = tokens.into_iter().filter(|t| !matches!(t.kind, TokenKind::Comment(_))).collect();
+        assert_eq!(parsed, without_comments);
+    }
+
+    #[test]
+    fn test_bom_and_crlf()
+    {
+        let mut cursor = Cursor::new(b"\xEF\xBB\xBFlet x\r\n\tfoo\r\n".to_vec());
+        let tokens: Vec<Token> = Lexer::new("")
+            .read(&mut cursor)
+            .expect("Lexing failed")
+            .collect();
+
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::Let, 1, 1, 1, 3),
+            tok(TokenKind::Identifier("x".into()), 1, 5, 1, 5),
+            tok(TokenKind::Indent(4), 1, 5, 2, 2),
+            tok(TokenKind::Identifier("foo".into()), 2, 2, 2, 4),
+            tok(TokenKind::EOF, 3, 1, 3, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_utf8()
+    {
+        let mut cursor = Cursor::new(b"let x = 5\nlet \xC3y = 7\n".to_vec());
+        let err = Lexer::new("").read(&mut cursor).err().expect("Lexing should fail");
+        assert_eq!(format!("{}", err), ":2:5 -> 2:5: Invalid UTF-8 byte 0xc3 at byte offset 14, source files must be UTF-8\n");
+    }
+
+    #[test]
+    fn test_is_identifier()
+    {
+        assert!(is_identifier("blaat"));
+        assert!(is_identifier("_foo_16"));
+        assert!(is_identifier("Http2"));
+        assert!(!is_identifier(""));
+        assert!(!is_identifier("2d"));
+        assert!(!is_identifier("my-module"));
+        assert!(!is_identifier("my.module"));
+        assert!(!is_identifier("import"));
+        assert!(!is_identifier("fn"));
+    }
 }
@@ -1,3 +1,4 @@
+mod cfg;
 mod lexer;
 mod tokens;
 mod tokenqueue;
@@ -6,23 +7,27 @@ mod tokenqueue;
 mod tests;
 
 #[cfg(test)]
-pub use self::tests::{th_expr, th_mod};
+pub use self::tests::{th_expr, th_mod, number};
 
 use std::path::{Path};
 use std::fs;
 use std::io::{Read};
 use std::rc::Rc;
 use std::ops::Deref;
+use either::Either;
 
 use ast::*;
 use timer::time_operation;
-use compileerror::{CompileResult, parse_error_result};
+use compileerror::{CompileResult, parse_error_result, print_message};
+use errorcodes::ErrorCode;
 use span::{Span};
 use target::Target;
 
 use self::tokenqueue::{TokenQueue};
 use self::lexer::{Lexer};
-use self::tokens::{Token, TokenKind};
+pub use self::lexer::{is_identifier, lex};
+pub use self::tokens::{Token, TokenKind};
+use self::cfg::{Declaration, Attribute, parse_attribute, add_enabled_declarations};
 
 fn is_end_of_expression(tok: &Token) -> bool
 {
@@ -33,10 +38,12 @@ fn is_end_of_expression(tok: &Token) -> bool
         TokenKind::Number(_) |
         TokenKind::Identifier(_) |
         TokenKind::StringLiteral(_) |
+        TokenKind::ByteStringLiteral(_) |
         TokenKind::Assign(_) |
         TokenKind::OpenParen |
         TokenKind::OpenBracket |
-        TokenKind::OpenCurly => false,
+        TokenKind::OpenCurly |
+        TokenKind::QuestionMark => false,
         _ => true,
     }
 }
@@ -86,7 +93,7 @@ fn parse_number(tq: &mut TokenQueue, num: &str, span: &Span, target: &Target) ->
     if num.find('.').is_some() || num.find('e').is_some() {
         match num.parse::<f64>() {
             Ok(_) => Ok(Literal::Float(span.clone(), num.into(), FloatSize::F64)),
-            Err(_) => parse_error_result(span, format!("{} is not a valid floating point number", num))
+            Err(_) => ErrorCode::InvalidLiteral.result(span, format!("{} is not a valid floating point number", num))
         }
     } else {
         let force_unsigned = if tq.is_next_identifier("u") {
@@ -99,11 +106,14 @@ fn parse_number(tq: &mut TokenQueue, num: &str, span: &Span, target: &Target) ->
         // Should be an integer
         match num.parse::<u64>() {
             Ok(i) => number_to_literal(i, force_unsigned, span, target.int_size),
-            Err(_) => parse_error_result(span, format!("{} is not a valid integer", num))
+            Err(_) => ErrorCode::InvalidLiteral.result(span, format!("{} is not a valid integer", num))
         }
     }
 }
 
+// [x ; N] is expanded into N elements, so N has to be kept within reason
+const MAX_ARRAY_REPEAT: u64 = 1 << 16;
+
 fn parse_array_literal(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Literal>
 {
     let mut expressions = Vec::new();
@@ -114,7 +124,10 @@ fn parse_array_literal(tq: &mut TokenQueue, span: &Span, indent_level: usize, ta
         {
             // [x ; 4]
             tq.pop()?;
-            let (times, _) = tq.expect_int()?;
+            let (times, times_span) = tq.expect_int()?;
+            if times > MAX_ARRAY_REPEAT {
+                return parse_error_result(&times_span, format!("Array repeat count {} is too large, the maximum is {}", times, MAX_ARRAY_REPEAT));
+            }
             tq.expect(&TokenKind::CloseBracket)?;
             return Ok(array_lit(vec![e; times as usize], span.expanded(tq.pos())));
         }
@@ -180,6 +193,22 @@ fn combine_type_cast(lhs: Expression, destination_type: Type, span: Span) -> Exp
     }
 }
 
+// x is Pattern binds as tight as a cast: a && b is X is a && (b is X)
+fn combine_is(lhs: Expression, pattern: Pattern, span: Span) -> Expression
+{
+    if lhs.is_binary_op()
+    {
+        let bop = lhs.extract_binary_op().expect("Not a binary op");
+        let nrhs = combine_is(bop.right.clone(), pattern, span);
+        let span = Span::merge(&bop.left.span(), &nrhs.span());
+        bin_op(bop.operator, bop.left, nrhs, span)
+    }
+    else
+    {
+        is_expression(lhs, pattern, span)
+    }
+}
+
 fn parse_binary_op_rhs(tq: &mut TokenQueue, mut lhs: Expression, indent_level: usize, target: &Target) -> CompileResult<Expression>
 {
     //use ast::TreePrinter;
@@ -202,6 +231,13 @@ fn parse_binary_op_rhs(tq: &mut TokenQueue, mut lhs: Expression, indent_level: u
             continue;
         }
 
+        if op == BinaryOperator::Is {
+            let pattern = parse_match_case_pattern(tq, indent_level, target)?;
+            let span = lhs.span().expanded(tq.pos());
+            lhs = combine_is(lhs, pattern, span);
+            continue;
+        }
+
 
         let next_tok = tq.pop()?;
         let rhs = parse_expression_start(tq, next_tok, indent_level, target)?;
@@ -240,12 +276,85 @@ fn parse_comma_separated_list<T, P>(tq: &mut TokenQueue, end_token: &TokenKind,
     parse_list(tq, &TokenKind::Comma, end_token, parse_element, indent_level, target)
 }
 
+// An argument is either positional, or passed by name: name: expression
+fn parse_call_argument(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> CompileResult<Either<Expression, NamedArgument>>
+{
+    let is_named = match tq.peek() {
+        Some(&Token{kind: TokenKind::Identifier(_), ..}) => tq.is_next_at(1, &TokenKind::Colon),
+        _ => false,
+    };
+
+    if !is_named {
+        return parse_expression(tq, indent_level, target).map(Either::Left);
+    }
+
+    let (name, name_span) = tq.expect_identifier()?;
+    tq.expect(&TokenKind::Colon)?;
+    let value = parse_expression(tq, indent_level, target)?;
+    let span = name_span.expanded(value.span().end);
+    Ok(Either::Right(NamedArgument{name, value, span}))
+}
+
 fn parse_function_call(tq: &mut TokenQueue, name: NameRef, indent_level: usize, target: &Target) -> CompileResult<Call>
 {
     tq.expect(&TokenKind::OpenParen)?;
-    let args = parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_expression, indent_level, target)?;
+    let elements = parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_call_argument, indent_level, target)?;
+    let mut args = Vec::with_capacity(elements.len());
+    let mut named_args: Vec<NamedArgument> = Vec::new();
+    for element in elements {
+        match element {
+            Either::Left(arg) => {
+                if let Some(na) = named_args.last() {
+                    return parse_error_result(&arg.span(),
+                        format!("Positional arguments must come before named arguments, but this one follows {}", na.name));
+                }
+                args.push(arg);
+            },
+            Either::Right(na) => named_args.push(na),
+        }
+    }
+
     let span = name.span.expanded(tq.pos());
-    Ok(Call::new(name, args, span))
+    let mut call = Call::new(name, args, span);
+    call.named_args = named_args;
+    Ok(call)
+}
+
+// Foo<int, string>(...) is a call with explicit type arguments, look ahead to distinguish it from a comparison
+fn is_type_arg_list_followed_by_call(tq: &TokenQueue) -> bool
+{
+    if !tq.is_next(&TokenKind::BinaryOperator(BinaryOperator::LessThan)) {
+        return false;
+    }
+
+    let mut depth = 1;
+    let mut idx = 1;
+    while let Some(tok) = tq.peek_at(idx)
+    {
+        match tok.kind
+        {
+            TokenKind::BinaryOperator(BinaryOperator::LessThan) => depth += 1,
+            TokenKind::BinaryOperator(BinaryOperator::GreaterThan) => {
+                depth -= 1;
+                if depth == 0 {
+                    return tq.is_next_at(idx + 1, &TokenKind::OpenParen);
+                }
+            },
+            TokenKind::Identifier(_) |
+            TokenKind::Number(_) |
+            TokenKind::Comma |
+            TokenKind::DoubleColon |
+            TokenKind::Dollar |
+            TokenKind::QuestionMark |
+            TokenKind::OpenBracket |
+            TokenKind::CloseBracket |
+            TokenKind::BinaryOperator(BinaryOperator::Mul) => (),
+            _ => return false,
+        }
+        idx += 1;
+    }
+
+    false
 }
 
 fn parse_generic_arg_list(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> CompileResult<Vec<Type>>
@@ -275,8 +384,10 @@ fn to_primitive(name: &str, target: &Target) -> Option<Type>
         "float" | "float32" => Some(Type::Float(FloatSize::F32)),
         "double" | "float64" => Some(Type::Float(FloatSize::F64)),
         "string" => Some(Type::String),
+        "bytes" => Some(slice_type(Type::UInt(IntSize::I8))),
         "bool" => Some(Type::Bool),
         "char" => Some(Type::Char),
+        "thread" => Some(Type::Thread),
         "Self" => Some(Type::SelfType),
         "void" => Some(Type::Void),
         _ => None,
@@ -329,6 +440,18 @@ fn parse_start_of_type(tq: &mut TokenQueue, indent_level: usize, target: &Target
         let ret = parse_type(tq, indent_level, target)?;
         Ok(func_type(args, ret))
     }
+    else if tq.is_next(&TokenKind::OpenParen)
+    {
+        // Tuple: (a, b), a single type between parentheses is just that type
+        let tok = tq.pop()?;
+        let mut member_types = parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_type, indent_level, target)?;
+        match member_types.len()
+        {
+            0 => parse_error_result(&tok.span.expanded(tq.pos()), "A tuple type needs at least two members"),
+            1 => Ok(member_types.remove(0)),
+            _ => Ok(struct_type("", member_types.into_iter().map(|member_type| struct_member("", member_type)).collect())),
+        }
+    }
     else if tq.is_next(&TokenKind::OpenCurly)
     {
         tq.pop()?;
@@ -364,6 +487,19 @@ fn parse_type(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> Comp
         if tq.is_next(&TokenKind::CloseBracket) {
             tq.pop()?;
             typ = slice_type(typ);
+        } else if tq.is_next(&TokenKind::At) {
+            let tok = tq.pop()?;
+            typ = match parse_compiler_call(tq, &tok.span, indent_level, target)? {
+                Expression::CompilerCall(CompilerCall::SizeOf(t, _)) => array_type_with_length_of(typ, ArrayLength::SizeOf(t)),
+                Expression::CompilerCall(CompilerCall::AlignOf(t, _)) => array_type_with_length_of(typ, ArrayLength::AlignOf(t)),
+                _ => return parse_error_result(&tok.span, "Only @size_of and @align_of can be used as array length"),
+            };
+            tq.expect(&TokenKind::CloseBracket)?;
+        } else if let Some(TokenKind::StringLiteral(_)) = tq.peek().map(|tok| tok.kind.clone()) {
+            // The length of a constant string, for example "abc".len
+            let len = parse_expression(tq, indent_level, target)?;
+            typ = array_type_with_length_of(typ, ArrayLength::Constant(Box::new(len)));
+            tq.expect(&TokenKind::CloseBracket)?;
         } else {
             let (len, _span) = tq.expect_int()?;
             typ = array_type(typ, len as usize);
@@ -376,6 +512,11 @@ fn parse_type(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> Comp
 
 fn parse_function_argument(tq: &mut TokenQueue, self_type: &Type, indent_level: usize, target: &Target) -> CompileResult<Argument>
 {
+    if tq.is_next(&TokenKind::Ellipsis) {
+        let tok = tq.pop()?;
+        return parse_error_result(&tok.span, "Only external functions can have variadic arguments");
+    }
+
     let mutable = if tq.is_next(&TokenKind::Var) {
         tq.pop()?;
         true
@@ -397,7 +538,12 @@ fn parse_function_argument(tq: &mut TokenQueue, self_type: &Type, indent_level:
         generic_type(&name) // If the type is not known threat it as generic arg
     };
 
-    Ok(Argument::new(name, typ, mutable, span.expanded(tq.pos())))
+    let mut arg = Argument::new(name, typ, mutable, span.expanded(tq.pos()));
+    if tq.is_next(&TokenKind::Assign(AssignOperator::Assign)) {
+        tq.pop()?;
+        arg.default = Some(parse_expression(tq, indent_level, target)?);
+    }
+    Ok(arg)
 }
 
 fn parse_function_arguments(tq: &mut TokenQueue, self_type: &Type, indent_level: usize, target: &Target) -> CompileResult<Vec<Argument>>
@@ -407,9 +553,27 @@ fn parse_function_arguments(tq: &mut TokenQueue, self_type: &Type, indent_level:
         parse_function_argument(tq, self_type, indent_level, target)
     };
     let args = parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_arg, indent_level, target)?;
+
+    // A call can only leave out arguments at the end
+    if let Some(first_default) = args.iter().position(|a| a.default.is_some()) {
+        if let Some(arg) = args[first_default..].iter().find(|a| a.default.is_none()) {
+            return parse_error_result(&arg.span,
+                format!("Argument {} has no default value, but it comes after argument {} which has one, only trailing arguments can have default values",
+                    arg.name, args[first_default].name));
+        }
+    }
     Ok(args)
 }
 
+// Default values are only used by calls to functions, function values and externals always get all their arguments
+fn no_default_values(args: &[Argument], what: &str) -> CompileResult<()>
+{
+    match args.iter().find(|a| a.default.is_some()) {
+        Some(arg) => parse_error_result(&arg.span, format!("{} cannot have default argument values, argument {} has one", what, arg.name)),
+        None => Ok(()),
+    }
+}
+
 fn parse_function_signature(tq: &mut TokenQueue, self_type: &Type, indent_level: usize, target: &Target) -> CompileResult<FunctionSignature>
 {
     let (name, name_span) = tq.expect_identifier()?;
@@ -425,11 +589,53 @@ fn parse_function_signature(tq: &mut TokenQueue, self_type: &Type, indent_level:
     Ok(sig(&name, ret_type, args, name_span.expanded(sig_span_end)))
 }
 
+// Arguments of an external function, a trailing ... makes the function variadic
+fn parse_external_function_arguments(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> CompileResult<(Vec<Argument>, bool)>
+{
+    tq.expect(&TokenKind::OpenParen)?;
+    let parse_arg = |tq: &mut TokenQueue, indent_level: usize, target: &Target| {
+        if tq.is_next(&TokenKind::Ellipsis) {
+            let tok = tq.pop()?;
+            Ok(Either::Right(tok.span))
+        } else {
+            parse_function_argument(tq, &Type::Unknown, indent_level, target).map(Either::Left)
+        }
+    };
+
+    let elements = parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_arg, indent_level, target)?;
+    let mut args = Vec::with_capacity(elements.len());
+    let mut ellipsis: Option<Span> = None;
+    for element in elements {
+        if let Some(ref span) = ellipsis {
+            return parse_error_result(span, "... must be the last argument of a function");
+        }
+
+        match element {
+            Either::Left(arg) => args.push(arg),
+            Either::Right(span) => ellipsis = Some(span),
+        }
+    }
+
+    Ok((args, ellipsis.is_some()))
+}
+
 fn parse_external_function(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<ExternalFunction>
 {
     tq.expect(&TokenKind::Func)?;
+    let (name, name_span) = tq.expect_identifier()?;
+    let (args, variadic) = parse_external_function_arguments(tq, indent_level, target)?;
+    no_default_values(&args, "External functions")?;
+    let ret_type = if tq.is_next(&TokenKind::Arrow) {
+        tq.pop()?;
+        parse_type(tq, indent_level, target)?
+    } else {
+        Type::Void
+    };
+
+    let mut signature = sig(&name, ret_type, args, name_span.expanded(tq.pos()));
+    signature.variadic = variadic;
     Ok(ExternalFunction::new(
-        parse_function_signature(tq, &Type::Unknown, indent_level, target)?,
+        signature,
         span.expanded(tq.pos()),
     ))
 }
@@ -482,6 +688,18 @@ fn parse_function_declaration(tq: &mut TokenQueue, namespace: &str, span: &Span,
 fn parse_struct_pattern(tq: &mut TokenQueue, name: &str, span: &Span, indent_level: usize, target: &Target) -> CompileResult<StructPattern>
 {
     tq.expect(&TokenKind::OpenCurly)?;
+    parse_struct_pattern_bindings(tq, name, &TokenKind::CloseCurly, span, indent_level, target)
+}
+
+// (a, b) is the same as the anonymous struct pattern {a, b}
+fn parse_tuple_pattern(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<StructPattern>
+{
+    tq.expect(&TokenKind::OpenParen)?;
+    parse_struct_pattern_bindings(tq, "", &TokenKind::CloseParen, span, indent_level, target)
+}
+
+fn parse_struct_pattern_bindings(tq: &mut TokenQueue, name: &str, end_token: &TokenKind, span: &Span, indent_level: usize, target: &Target) -> CompileResult<StructPattern>
+{
     let parse_binding = |tq: &mut TokenQueue, _indent_level: usize, _target: &Target| {
         let mode = if tq.is_next(&TokenKind::BinaryOperator(BinaryOperator::Mul)) {
             tq.pop()?;
@@ -493,20 +711,48 @@ fn parse_struct_pattern(tq: &mut TokenQueue, name: &str, span: &Span, indent_lev
         let (name, _) = tq.expect_identifier()?;
         Ok(StructPatternBinding{name, typ: Type::Unknown, mode})
     };
-    let bindings = parse_comma_separated_list(tq, &TokenKind::CloseCurly, parse_binding, indent_level, target)?;
+    let bindings = parse_comma_separated_list(tq, end_token, parse_binding, indent_level, target)?;
     Ok(struct_pattern(name, bindings, Type::Unknown, span.expanded(tq.pos())))
 }
 
+// start..end or start..=end, when there is no range operator after start it's just a literal
+fn parse_literal_or_range_pattern(tq: &mut TokenQueue, start: Literal, target: &Target) -> CompileResult<Pattern>
+{
+    let inclusive = if tq.is_next(&TokenKind::DotDot) {
+        false
+    } else if tq.is_next(&TokenKind::DotDotEquals) {
+        true
+    } else {
+        return Ok(Pattern::Literal(start));
+    };
+
+    tq.pop()?;
+    let tok = tq.pop()?;
+    let end = match tok.kind
+    {
+        TokenKind::Number(ref num) => parse_number(tq, num, &tok.span, target)?,
+        TokenKind::CharLiteral(c) => Literal::Char(tok.span.clone(), c),
+        _ => return parse_error_result(&tok.span, format!("Expected an integer or char literal at the end of a range pattern, found {}", tok)),
+    };
+
+    let span = start.span().expanded(tok.span.end);
+    Ok(range_pattern(start, end, inclusive, span))
+}
+
 pub fn parse_pattern(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> CompileResult<Pattern>
 {
     let tok = tq.pop()?;
     match tok.kind
     {
-        TokenKind::Number(ref num) => parse_number(tq, num, &tok.span, target).map(Pattern::Literal),
+        TokenKind::Number(ref num) => {
+            let lit = parse_number(tq, num, &tok.span, target)?;
+            parse_literal_or_range_pattern(tq, lit, target)
+        },
         TokenKind::True => Ok(Pattern::Literal(Literal::Bool(tok.span, true))),
         TokenKind::False => Ok(Pattern::Literal(Literal::Bool(tok.span, false))),
-        TokenKind::CharLiteral(c) => Ok(Pattern::Literal(Literal::Char(tok.span, c))),
+        TokenKind::CharLiteral(c) => parse_literal_or_range_pattern(tq, Literal::Char(tok.span, c), target),
         TokenKind::StringLiteral(s) => Ok(Pattern::Literal(Literal::String(tok.span, s))),
+        TokenKind::ByteStringLiteral(b) => Ok(Pattern::Literal(Literal::Bytes(tok.span, b))),
 
         TokenKind::OpenBracket => {
             if tq.is_next(&TokenKind::CloseBracket)
@@ -545,11 +791,33 @@ pub fn parse_pattern(tq: &mut TokenQueue, indent_level: usize, target: &Target)
             Ok(optional_pattern(name, tok.span.expanded(name_span.end)))
         }
 
+        TokenKind::OpenParen => {
+            tq.push_front(tok.clone());
+            parse_tuple_pattern(tq, &tok.span, indent_level, target).map(Pattern::Struct)
+        }
+
         TokenKind::Nil => {
             Ok(Pattern::Nil(tok.span))
         }
 
-        _ => parse_error_result(&tok.span, format!("Unexpected token '{}'", tok)),
+        _ => ErrorCode::UnexpectedToken.result(&tok.span, format!("Unexpected token '{}'", tok)),
+    }
+}
+
+// pattern | pattern | ..., a single pattern is returned as is
+fn parse_match_case_pattern(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> CompileResult<Pattern>
+{
+    let mut alternatives = vec![parse_pattern(tq, indent_level, target)?];
+    while tq.is_next(&TokenKind::Pipe)
+    {
+        tq.pop()?;
+        alternatives.push(parse_pattern(tq, indent_level, target)?);
+    }
+
+    if alternatives.len() == 1 {
+        Ok(alternatives.remove(0))
+    } else {
+        Ok(Pattern::Or(alternatives))
     }
 }
 
@@ -574,7 +842,7 @@ fn parse_match(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &T
     tq.expect(&TokenKind::Colon)?;
 
     let parse_match_case = |tq: &mut TokenQueue, indent_level: usize, target: &Target| {
-        let pattern = parse_pattern(tq, indent_level, target)?;
+        let pattern = parse_match_case_pattern(tq, indent_level, target)?;
         let tok = tq.expect(&TokenKind::FatArrow)?;
         let t = parse_block(tq, &tok.span.file, indent_level, target)?;
         let case_span = pattern.span().expanded(tq.pos());
@@ -589,11 +857,28 @@ fn parse_match(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &T
 fn parse_lambda(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Expression>
 {
     let args = parse_function_arguments(tq, &Type::Unknown, indent_level, target)?;
-    tq.expect(&TokenKind::Arrow)?;
-    let expr = parse_expression(tq, indent_level, target)?;
+    no_default_values(&args, "Lambdas")?;
+    let tok = tq.expect(&TokenKind::Arrow)?;
+    let expr = if tq.is_next_indent_above(indent_level) {
+        parse_block(tq, &tok.span.file, indent_level, target)?
+    } else {
+        parse_expression(tq, indent_level, target)?
+    };
     Ok(lambda(args, expr, span.expanded(tq.pos())))
 }
 
+// fn name(args) -> ret: body, declared inside a block, is a lambda bound to name
+fn parse_local_function(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Expression>
+{
+    let signature = parse_function_signature(tq, &Type::Unknown, indent_level, target)?;
+    no_default_values(&signature.args, "Local functions")?;
+    tq.expect(&TokenKind::Colon)?;
+    let expr = parse_block(tq, &span.file, indent_level, target)?;
+    let func_span = span.expanded(tq.pos());
+    let func = local_function(&signature.name, signature.return_type, signature.args, expr, func_span.clone());
+    Ok(bindings(vec![name_binding(signature.name, func, false, func_span.clone())], func_span))
+}
+
 fn is_end_of_bindings(tq: &mut TokenQueue, indent_level: usize) -> bool
 {
     tq.is_next(&TokenKind::In) ||
@@ -616,13 +901,50 @@ fn parse_bindings(tq: &mut TokenQueue, mutable: bool, indent_level: usize, targe
             let pattern = parse_struct_pattern(tq, "", &span, indent_level, target)?;
             let span = pattern.span.clone();
             (BindingType::Struct(pattern), span)
+        } else if tq.is_next_at(1, &TokenKind::OpenCurly) {
+            let (name, span) = tq.expect_identifier()?;
+            let pattern = parse_struct_pattern(tq, &name, &span, indent_level, target)?;
+            let span = pattern.span.clone();
+            (BindingType::Struct(pattern), span)
+        } else if tq.is_next(&TokenKind::OpenParen) {
+            let span = tq.peek().expect("Unexpected EOF").span.clone();
+            let pattern = parse_tuple_pattern(tq, &span, indent_level, target)?;
+            let span = pattern.span.clone();
+            (BindingType::Struct(pattern), span)
+        } else if tq.is_next(&TokenKind::OpenBracket) {
+            let tok = tq.pop()?;
+            let (head, _) = tq.expect_identifier()?;
+            tq.expect(&TokenKind::Pipe)?;
+            let (tail, _) = tq.expect_identifier()?;
+            tq.expect(&TokenKind::CloseBracket)?;
+            let span = tok.span.expanded(tq.pos());
+            (BindingType::Array(ArrayPattern{head, tail, span: span.clone()}), span)
         } else {
             let (name, span) = tq.expect_identifier()?;
             (BindingType::Name(name), span)
         };
 
+        // let x: type = init is the same as let x = (init : type)
+        let annotation = if tq.is_next(&TokenKind::Colon) {
+            if let BindingType::Name(_) = binding_type {
+                tq.pop()?;
+                Some(parse_type(tq, indent_level, target)?)
+            } else {
+                return parse_error_result(&span, "Only bindings of a name can have a type annotation");
+            }
+        } else {
+            None
+        };
+
         tq.expect(&TokenKind::Assign(AssignOperator::Assign))?;
         let init = parse_expression(tq, indent_level, target)?;
+        let init = match annotation {
+            Some(typ) => {
+                let span = init.span();
+                type_ascription(init, typ, span)
+            },
+            None => init,
+        };
         bindings.push(binding(binding_type, init, mutable, span.expanded(tq.pos())));
         eat_comma(tq)?;
     }
@@ -636,56 +958,170 @@ fn parse_binding(tq: &mut TokenQueue, mutable: bool, span: &Span, indent_level:
     Ok(bindings(b, span.expanded(tq.pos())))
 }
 
+fn parse_else(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Option<Expression>>
+{
+    if tq.is_next(&TokenKind::Indent(indent_level)) {
+        tq.pop_indent()?;
+    }
+
+    if !tq.is_next(&TokenKind::Else) {
+        return Ok(None);
+    }
+
+    tq.expect(&TokenKind::Else)?;
+    let on_false = if tq.is_next(&TokenKind::If) {
+        let tok = tq.expect(&TokenKind::If)?;
+        parse_if(tq, &tok.span, indent_level, target)?
+    } else {
+        parse_block(tq, &span.file, indent_level, target)?
+    };
+    Ok(Some(on_false))
+}
+
+// if let x = a, y = b: on_true else on_false
+fn parse_if_let(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Expression>
+{
+    tq.expect(&TokenKind::Let)?;
+    let mut bindings = Vec::new();
+    loop
+    {
+        let (name, name_span) = tq.expect_identifier()?;
+        tq.expect(&TokenKind::Assign(AssignOperator::Assign))?;
+        let init = parse_expression(tq, indent_level, target)?;
+        bindings.push(if_let_binding(name, init, name_span.expanded(tq.pos())));
+        if !tq.is_next(&TokenKind::Comma) {
+            break;
+        }
+        tq.pop()?;
+    }
+
+    tq.expect(&TokenKind::Colon)?;
+    let on_true = parse_block(tq, &span.file, indent_level, target)?;
+    let on_false = parse_else(tq, span, indent_level, target)?;
+    Ok(if_let_expression(bindings, on_true, on_false, span.expanded(tq.pos())))
+}
+
 fn parse_if(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Expression>
 {
+    if tq.is_next(&TokenKind::Let) {
+        return parse_if_let(tq, span, indent_level, target);
+    }
+
     let cond = parse_expression(tq, indent_level, target)?;
     tq.expect(&TokenKind::Colon)?;
     let on_true = parse_block(tq, &span.file, indent_level, target)?;
-    if tq.is_next(&TokenKind::Indent(indent_level)) {
-        tq.pop_indent()?;
+    match parse_else(tq, span, indent_level, target)?
+    {
+        Some(on_false) => Ok(if_expression(cond, on_true, on_false, span.expanded(tq.pos()))),
+        None => Ok(single_if_expression(cond, on_true, span.expanded(tq.pos()))),
     }
+}
 
-    if tq.is_next(&TokenKind::Else)
+// Generic parameters of a type declaration: <T, U>
+fn parse_generic_param_list(tq: &mut TokenQueue) -> CompileResult<Vec<Type>>
+{
+    let mut params = Vec::new();
+    if !tq.is_next(&TokenKind::BinaryOperator(BinaryOperator::LessThan)) {
+        return Ok(params);
+    }
+
+    tq.pop()?;
+    loop
     {
-        tq.expect(&TokenKind::Else)?;
-        let on_false = if tq.is_next(&TokenKind::If) {
-            let tok = tq.expect(&TokenKind::If)?;
-            parse_if(tq, &tok.span, indent_level, target)?
-        } else {
-            parse_block(tq, &span.file, indent_level, target)?
-        };
+        let (name, span) = tq.expect_identifier()?;
+        let param = generic_type(&name);
+        if params.contains(&param) {
+            return ErrorCode::Redefinition.result(&span, format!("Generic parameter {} is declared twice", name));
+        }
+        params.push(param);
 
-        Ok(if_expression(cond, on_true, on_false, span.expanded(tq.pos())))
+        if tq.is_next(&TokenKind::Comma) {
+            tq.pop()?;
+        } else {
+            tq.expect(&TokenKind::BinaryOperator(BinaryOperator::GreaterThan))?;
+            return Ok(params);
+        }
     }
-    else
+}
+
+// Inside a declaration with generic parameters, T refers to the generic type $T
+fn replace_generic_params(typ: &Type, params: &[Type]) -> Type
+{
+    match *typ
     {
-        Ok(single_if_expression(cond, on_true, span.expanded(tq.pos())))
+        Type::Unresolved(ref ut) => {
+            let generic = generic_type(&ut.name);
+            if ut.generic_args.is_empty() && params.contains(&generic) {
+                generic
+            } else {
+                let generic_args = ut.generic_args.iter().map(|a| replace_generic_params(a, params)).collect();
+                unresolved_type(&ut.name, generic_args)
+            }
+        },
+        Type::Pointer(ref inner) => ptr_type(replace_generic_params(inner, params)),
+        Type::Optional(ref inner) => optional_type(replace_generic_params(inner, params)),
+        Type::Slice(ref st) => slice_type(replace_generic_params(&st.element_type, params)),
+        Type::Array(ref at) => Type::Array(Rc::new(ArrayType{
+            element_type: replace_generic_params(&at.element_type, params),
+            len: at.len,
+            len_of: at.len_of.clone(),
+        })),
+        Type::Func(ref ft) => func_type(
+            ft.args.iter().map(|a| replace_generic_params(a, params)).collect(),
+            replace_generic_params(&ft.return_type, params)
+        ),
+        Type::Struct(ref st) => struct_type(
+            &st.name,
+            st.members.iter().map(|m| struct_member(&m.name, replace_generic_params(&m.typ, params))).collect()
+        ),
+        _ => typ.clone(),
+    }
+}
+
+fn replace_generic_params_in_members(sd: &mut StructDeclaration, params: &[Type])
+{
+    if params.is_empty() {
+        return;
+    }
+
+    for m in &mut sd.members {
+        m.typ = replace_generic_params(&m.typ, params);
     }
 }
 
 fn parse_sum_type(tq: &mut TokenQueue, namespace: &str, span: &Span, indent_level: usize, target: &Target) -> CompileResult<SumTypeDeclaration>
 {
     let (sum_type_name, _) = tq.expect_identifier()?;
+    let generic_params = parse_generic_param_list(tq)?;
     tq.expect(&TokenKind::Colon)?;
 
     let parse_sum_type_case = |tq: &mut TokenQueue, indent_level: usize, target: &Target| {
-        if tq.is_next_at(1, &TokenKind::OpenCurly)
+        let mut case = if tq.is_next_at(1, &TokenKind::OpenCurly)
         {
             let sd = parse_struct_type(tq, &sum_type_name, indent_level, target)?;
             let span = sd.span.clone();
             let name = namespaced(namespace, &sd.name);
-            Ok(sum_type_case_decl(&name, Some(sd), span))
+            sum_type_case_decl(&name, Some(sd), span)
         }
         else
         {
             let (case_name, case_name_span) = tq.expect_identifier()?;
             let name = format!("{}::{}::{}", namespace, sum_type_name, case_name);
-            Ok(sum_type_case_decl(&name, None, case_name_span))
-        }
+            sum_type_case_decl(&name, None, case_name_span)
+        };
+        case.doc = tq.take_doc_comment(case.span.start.line);
+        Ok(case)
     };
-    let cases = parse_indented_block(tq, indent_level, parse_sum_type_case, target)?;
+    let mut cases = parse_indented_block(tq, indent_level, parse_sum_type_case, target)?;
+    for c in &mut cases {
+        if let Some(ref mut sd) = c.data {
+            replace_generic_params_in_members(sd, &generic_params);
+        }
+    }
 
-    Ok(sum_type_decl(&namespaced(namespace, &sum_type_name), cases, span.expanded(tq.pos())))
+    let mut st = sum_type_decl(&namespaced(namespace, &sum_type_name), cases, span.expanded(tq.pos()));
+    st.generic_params = generic_params;
+    Ok(st)
 }
 
 fn namespaced(namespace: &str, name: &str) -> String
@@ -700,12 +1136,18 @@ fn namespaced(namespace: &str, name: &str) -> String
 fn parse_struct_type(tq: &mut TokenQueue, namespace: &str, indent_level: usize, target: &Target) -> CompileResult<StructDeclaration>
 {
     let (name, span) = tq.expect_identifier()?;
+    let generic_params = parse_generic_param_list(tq)?;
 
     let parse_struct_member = |tq: &mut TokenQueue, indent_level: usize, target: &Target| {
         let (member_name, member_name_span) = tq.expect_identifier()?;
+        if member_name == "_" {
+            return parse_error_result(&member_name_span, "`_` cannot be the name of a struct member");
+        }
         tq.expect(&TokenKind::Colon)?;
         let typ = parse_type(tq, indent_level, target)?;
-        Ok(struct_member_declaration(&member_name, typ, member_name_span.expanded(tq.pos())))
+        let mut member = struct_member_declaration(&member_name, typ, member_name_span.expanded(tq.pos()));
+        member.doc = tq.take_doc_comment(member_name_span.start.line);
+        Ok(member)
     };
 
     let members = if tq.is_next(&TokenKind::OpenCurly) {
@@ -716,7 +1158,10 @@ fn parse_struct_type(tq: &mut TokenQueue, namespace: &str, indent_level: usize,
         parse_indented_block(tq, indent_level, parse_struct_member, target)?
     };
 
-    Ok(struct_declaration(&namespaced(namespace, &name), members, span.expanded(tq.pos())))
+    let mut sd = struct_declaration(&namespaced(namespace, &name), members, span.expanded(tq.pos()));
+    replace_generic_params_in_members(&mut sd, &generic_params);
+    sd.generic_params = generic_params;
+    Ok(sd)
 }
 
 fn parse_struct_initializer(tq: &mut TokenQueue, name: &NameRef, indent_level: usize, target: &Target) -> CompileResult<Expression>
@@ -735,6 +1180,11 @@ fn parse_member_access(tq: &mut TokenQueue, left_expr: Expression, indent_level:
     while tq.is_next(&TokenKind::BinaryOperator(BinaryOperator::Dot))
     {
         tq.pop()?;
+        if let Some(TokenKind::Number(_)) = tq.peek().map(|tok| tok.kind.clone()) {
+            left = parse_tuple_member_access(tq, left)?;
+            continue;
+        }
+
         let (name, name_span) = tq.expect_identifier()?;
 
         if tq.is_next(&TokenKind::OpenParen) {
@@ -751,6 +1201,25 @@ fn parse_member_access(tq: &mut TokenQueue, left_expr: Expression, indent_level:
     Ok(left)
 }
 
+// t.0, the lexer turns t.0.1 into a single number 0.1, so that has to be split up again
+fn parse_tuple_member_access(tq: &mut TokenQueue, left_expr: Expression) -> CompileResult<Expression>
+{
+    let tok = tq.pop()?;
+    let mut left = left_expr;
+    if let TokenKind::Number(ref n) = tok.kind {
+        for index in n.split('.') {
+            if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+                return parse_error_result(&tok.span, format!("Invalid tuple member {}", n));
+            }
+
+            let span = left.span().expanded(tok.span.end);
+            left = member_access(left, MemberAccessType::Name(field(index, 0)), span);
+        }
+    }
+
+    Ok(left)
+}
+
 fn check_indent_level(tq: &mut TokenQueue, indent_level: usize) -> CompileResult<usize>
 {
      if let Some((level, indent_span)) = tq.pop_indent()? {
@@ -769,6 +1238,8 @@ fn is_end_of_block(tq: &mut TokenQueue) -> bool
     tq.peek().map(|tok| match tok.kind {
         TokenKind::CloseParen |
         TokenKind::CloseBracket |
+        TokenKind::Colon |
+        TokenKind::Comma |
         TokenKind::Else |
         TokenKind::EOF => true,
         _ => false,
@@ -801,6 +1272,7 @@ fn parse_block(tq: &mut TokenQueue, current_file: &str, indent_level: usize, tar
             let span = e.span().expanded(tq.pos());
 
             let assign_expr = match e {
+                Expression::NameRef(ref nr) if nr.name == "_" && op == AssignOperator::Assign => discard(rhs, span),
                 Expression::NameRef(nr) => assign(op, AssignTarget::Var(nr), rhs, span),
                 Expression::MemberAccess(ma) => assign(op, AssignTarget::MemberAccess(*ma), rhs, span),
                 Expression::Dereference(d) => assign(op, AssignTarget::Dereference(*d), rhs, span),
@@ -861,12 +1333,17 @@ fn parse_compiler_call(tq: &mut TokenQueue, start: &Span, indent_level: usize, t
 {
     let (name, name_span) = tq.expect_identifier()?;
     match &name[..] {
-        "size" => {
+        "size" | "size_of" | "align_of" => {
             tq.expect(&TokenKind::OpenParen)?;
             let typ = parse_type(tq, indent_level, target)?;
             tq.expect(&TokenKind::CloseParen)?;
 
-            Ok(Expression::CompilerCall(CompilerCall::SizeOf(typ, start.expanded(tq.pos()))))
+            let span = start.expanded(tq.pos());
+            if name == "align_of" {
+                Ok(Expression::CompilerCall(CompilerCall::AlignOf(typ, span)))
+            } else {
+                Ok(Expression::CompilerCall(CompilerCall::SizeOf(typ, span)))
+            }
         },
 
         "slice" => {
@@ -885,13 +1362,57 @@ fn parse_compiler_call(tq: &mut TokenQueue, start: &Span, indent_level: usize, t
             }))
         }
 
+        "type_name" => {
+            tq.expect(&TokenKind::OpenParen)?;
+            let e = parse_expression(tq, indent_level, target)?;
+            tq.expect(&TokenKind::CloseParen)?;
+            Ok(Expression::CompilerCall(CompilerCall::TypeName(Box::new(e), start.expanded(tq.pos()))))
+        }
+
+        "static_assert" => {
+            tq.expect(&TokenKind::OpenParen)?;
+            let mut arguments = parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_expression, indent_level, target)?;
+            let span = start.expanded(tq.pos());
+            if arguments.len() != 2 {
+                return parse_error_result(&span, "@static_assert expects a condition and a message");
+            }
+
+            let message = arguments.pop().expect("Missing message");
+            Ok(Expression::CompilerCall(CompilerCall::StaticAssert{
+                cond: Box::new(arguments.pop().expect("Missing condition")),
+                message: Box::new(message),
+                span
+            }))
+        }
+
+        "assert_type" => {
+            tq.expect(&TokenKind::OpenParen)?;
+            let mut arguments = parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_expression, indent_level, target)?;
+            let span = start.expanded(tq.pos());
+            if arguments.len() != 2 {
+                return parse_error_result(&span, "@assert_type expects an expression and a type");
+            }
+
+            let typ = arguments.pop().expect("Missing type");
+            Ok(Expression::CompilerCall(CompilerCall::AssertType{
+                expr: Box::new(arguments.pop().expect("Missing expression")),
+                typ: Box::new(typ),
+                span
+            }))
+        }
+
         _ => parse_error_result(&name_span, format!("Unknown compiler call {}", name))
     }
 }
 
 fn parse_return(tq: &mut TokenQueue, start: &Span, indent_level: usize, target: &Target) -> CompileResult<Expression>
 {
-    if tq.peek().map(|tok| is_end_of_expression(tok)).unwrap_or(true) {
+    let has_value = tq.peek().map(|tok| match tok.kind {
+        TokenKind::Nil | TokenKind::Null | TokenKind::True | TokenKind::False | TokenKind::CharLiteral(_) => true,
+        _ => !is_end_of_expression(tok),
+    }).unwrap_or(false);
+
+    if !has_value {
         Ok(return_expr(Expression::Void, start.clone()))
     } else {
         let expr = parse_expression(tq, indent_level, target)?;
@@ -901,6 +1422,7 @@ fn parse_return(tq: &mut TokenQueue, start: &Span, indent_level: usize, target:
 
 fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize, target: &Target) -> CompileResult<Expression>
 {
+    tq.enter_nested_expression()?;
     let mut lhs = match tok.kind
     {
         TokenKind::Nil => {
@@ -924,7 +1446,11 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
         },
 
         TokenKind::Func => {
-            parse_lambda(tq, &tok.span, indent_level, target)?
+            if tq.is_next(&TokenKind::OpenParen) {
+                parse_lambda(tq, &tok.span, indent_level, target)?
+            } else {
+                parse_local_function(tq, &tok.span, indent_level, target)?
+            }
         },
 
         TokenKind::Match => {
@@ -957,8 +1483,22 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
 
         TokenKind::OpenParen => {
             let inner = parse_block(tq, &tok.span.file, indent_level, target)?;
-            tq.expect(&TokenKind::CloseParen)?;
-            inner
+            if tq.is_next(&TokenKind::Colon) {
+                // (expr : type)
+                tq.pop()?;
+                let typ = parse_type(tq, indent_level, target)?;
+                tq.expect(&TokenKind::CloseParen)?;
+                type_ascription(inner, typ, tok.span.expanded(tq.pos()))
+            } else if tq.is_next(&TokenKind::Comma) {
+                // (a, b) is a tuple, which is an anonymous struct
+                tq.pop()?;
+                let mut members = vec![inner];
+                members.extend(parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_expression, indent_level, target)?);
+                Expression::StructInitializer(struct_initializer("", members, tok.span.expanded(tq.pos())))
+            } else {
+                tq.expect(&TokenKind::CloseParen)?;
+                inner
+            }
         },
 
         TokenKind::OpenCurly => {
@@ -976,6 +1516,13 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
             {
                 Expression::Call(Box::new(parse_function_call(tq, nr, indent_level, target)?))
             }
+            else if is_type_arg_list_followed_by_call(tq)
+            {
+                let type_args = parse_generic_arg_list(tq, indent_level, target)?;
+                let mut call = parse_function_call(tq, nr, indent_level, target)?;
+                call.type_args = type_args;
+                Expression::Call(Box::new(call))
+            }
             else if tq.is_next(&TokenKind::OpenCurly)
             {
                 parse_struct_initializer(tq, &nr, indent_level, target)?
@@ -990,6 +1537,10 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
             Expression::Literal(Literal::String(tok.span, s))
         },
 
+        TokenKind::ByteStringLiteral(b) => {
+            Expression::Literal(Literal::Bytes(tok.span, b))
+        },
+
         TokenKind::Number(n) => {
             parse_number(tq, &n, &tok.span, target).map(Expression::Literal)?
         },
@@ -1031,10 +1582,10 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
             parse_return(tq, &tok.span, indent_level, target)?
         }
 
-        _ => return parse_error_result(&tok.span, format!("Unexpected token '{}'", tok)),
+        _ => return ErrorCode::UnexpectedToken.result(&tok.span, format!("Unexpected token '{}'", tok)),
     };
 
-    while !is_end_of_expression(tq.peek().expect("Unexpected EOF")) {
+    while tq.peek().map(|tok| !is_end_of_expression(tok)).unwrap_or(false) {
         let next = tq.pop()?;
         match next.kind
         {
@@ -1050,6 +1601,11 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
                 lhs = parse_member_access(tq, lhs, indent_level, target)?;
             },
 
+            TokenKind::QuestionMark => {
+                let span = lhs.span().expanded(next.span.end);
+                lhs = propagate(lhs, span);
+            },
+
             _ => {
                 tq.push_front(next);
                 break
@@ -1057,6 +1613,7 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
         }
     }
 
+    tq.exit_nested_expression();
     Ok(lhs)
 }
 
@@ -1071,46 +1628,43 @@ pub fn parse_expression(tq: &mut TokenQueue, indent_level: usize, target: &Targe
     }
 }
 
-fn parse_global_bindings(module: &mut Module, tq: &mut TokenQueue, mutable: bool, indent_level: usize, namespace: &str, target: &Target) -> CompileResult<()>
+fn parse_global_bindings(tq: &mut TokenQueue, mutable: bool, indent_level: usize, namespace: &str, target: &Target) -> CompileResult<Vec<GlobalBinding>>
 {
+    let mut globals = Vec::new();
     while !is_end_of_bindings(tq, indent_level)
     {
         let (name, span) = tq.expect_identifier()?;
+        let typ = if tq.is_next(&TokenKind::Colon) {
+            tq.pop()?;
+            parse_type(tq, indent_level, target)?
+        } else {
+            Type::Unknown
+        };
         tq.expect(&TokenKind::Assign(AssignOperator::Assign))?;
         let init = parse_expression(tq, indent_level, target)?;
-
-        if module.globals.contains_key(&name) {
-            return parse_error_result(&span, format!("Global {} already defined in this module", name));
-        }
-
-        let full_name = namespaced(namespace, &name);
-        module.globals.insert(full_name.clone(), global_binding(full_name, init, mutable, span.expanded(tq.pos())));
+        globals.push(global_binding(namespaced(namespace, &name), typ, init, mutable, span.expanded(tq.pos())));
         eat_comma(tq)?;
     }
 
-    Ok(())
+    Ok(globals)
 }
 
-fn parse_interface(module: &mut Module, tq: &mut TokenQueue, namespace: &str, span: &Span, indent_level: usize, target: &Target) -> CompileResult<()>
+fn parse_interface(tq: &mut TokenQueue, namespace: &str, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Interface>
 {
     let (name, _) = tq.expect_identifier()?;
-    if module.types.contains_key(&name) {
-        return parse_error_result(span, format!("Type {} already defined in this module", name));
-    }
-
     tq.expect(&TokenKind::Colon)?;
     let self_type = ptr_type(Type::SelfType);
 
     let parse_interface_function = |tq: &mut TokenQueue, indent_level: usize, target: &Target| {
         tq.expect(&TokenKind::Func)?;
-        parse_function_signature(tq, &self_type, indent_level, target)
+        let signature = parse_function_signature(tq, &self_type, indent_level, target)?;
+        no_default_values(&signature.args, "Interface functions")?;
+        Ok(signature)
     };
 
     let functions = parse_indented_block(tq, indent_level, parse_interface_function, target)?;
 
-    let name = namespaced(namespace, &name);
-    module.types.insert(name.clone(), TypeDeclaration::Interface(interface(name, functions, span.expanded(tq.pos()))));
-    Ok(())
+    Ok(interface(namespaced(namespace, &name), functions, span.expanded(tq.pos())))
 }
 
 fn parse_import_name(tq: &mut TokenQueue) -> CompileResult<ImportName>
@@ -1144,70 +1698,175 @@ fn parse_module<Input: Read>(
     target: &Target) -> CompileResult<()>
 {
     let mut tq = Lexer::new(file_name).read(input)?;
+    parse_declarations(module, &mut tq, namespace, target)?;
+    for dc in tq.doc_comments() {
+        print_message("Warning: doc comment is not followed by a function, struct, enum, extern or global declaration, it is ignored", &dc.span);
+    }
+    Ok(())
+}
 
-    let add_function = |module: &mut Module, func: Function| -> CompileResult<()> {
-        if module.functions.contains_key(&func.sig.name) {
-            return parse_error_result(&func.span, format!("Function {} redefined", func.sig.name));
-        }
-        module.functions.insert(func.sig.name.clone(), func);
-        Ok(())
-    };
+fn parse_declarations(module: &mut Module, tq: &mut TokenQueue, namespace: &str, target: &Target) -> CompileResult<()>
+{
+    let mut declarations = Vec::new();
+    let mut cfg: Option<Cfg> = None;
+    let mut inline: Option<Span> = None;
+    let mut repr_c: Option<Span> = None;
+    let mut export: Option<(Option<String>, Span)> = None;
+    let mut links: Vec<(LinkLibrary, Span)> = Vec::new();
+    let mut link_name: Option<(String, Span)> = None;
+    // Line of the first attribute in front of a declaration, its doc comment comes before the attributes
+    let mut attribute_line: Option<usize> = None;
 
     let mut indent_level = 0;
     while !tq.is_next(&TokenKind::EOF)
     {
         let tok = tq.pop()?;
+        if let Some(ref inline_span) = inline {
+            match tok.kind
+            {
+                TokenKind::Indent(_) | TokenKind::Hash | TokenKind::Func => (),
+                _ => return ErrorCode::InvalidAttribute.result(inline_span, "The inline attribute is only allowed on functions"),
+            }
+        }
+
+        if let Some((_, ref export_span)) = export {
+            match tok.kind
+            {
+                TokenKind::Indent(_) | TokenKind::Hash | TokenKind::Func => (),
+                _ => return ErrorCode::InvalidAttribute.result(export_span, "The export attribute is only allowed on functions"),
+            }
+        }
+
+        if let Some(ref repr_span) = repr_c {
+            match tok.kind
+            {
+                TokenKind::Indent(_) | TokenKind::Hash | TokenKind::Struct => (),
+                _ => return ErrorCode::InvalidAttribute.result(repr_span, "The repr(c) attribute is only allowed on structs"),
+            }
+        }
+
+        if let Some(&(_, ref link_span)) = links.first() {
+            match tok.kind
+            {
+                TokenKind::Indent(_) | TokenKind::Hash | TokenKind::Extern => (),
+                _ => return ErrorCode::InvalidAttribute.result(link_span, "The link attribute is only allowed on external functions"),
+            }
+        }
+
+        if let Some((_, ref link_name_span)) = link_name {
+            match tok.kind
+            {
+                TokenKind::Indent(_) | TokenKind::Hash | TokenKind::Extern => (),
+                _ => return ErrorCode::InvalidAttribute.result(link_name_span, "The link_name attribute is only allowed on external functions"),
+            }
+        }
+
+        let doc_line = match tok.kind
+        {
+            TokenKind::Indent(_) | TokenKind::Hash => 0,
+            _ => attribute_line.take().unwrap_or(tok.span.start.line),
+        };
+
         match tok.kind
         {
             TokenKind::Indent(level) => {
                 indent_level = level;
             }
 
-            TokenKind::Interface => {
-                parse_interface(module, &mut tq, namespace, &tok.span, indent_level, target)?;
+            TokenKind::Hash => {
+                attribute_line = attribute_line.or(Some(tok.span.start.line));
+                match parse_attribute(tq)?
+                {
+                    Attribute::Cfg(attr_cfg) => {
+                        // Multiple cfg attributes must all be enabled
+                        cfg = match cfg.take()
+                        {
+                            Some(Cfg::All(mut cfgs)) => {
+                                cfgs.push(attr_cfg);
+                                Some(Cfg::All(cfgs))
+                            },
+                            Some(prev) => Some(Cfg::All(vec![prev, attr_cfg])),
+                            None => Some(attr_cfg),
+                        };
+                    },
+
+                    Attribute::Inline(span) => {
+                        inline = Some(span);
+                    },
+
+                    Attribute::ReprC(span) => {
+                        repr_c = Some(span);
+                    },
+
+                    Attribute::Export(symbol, span) => {
+                        export = Some((symbol, span));
+                    },
+
+                    Attribute::Link(lib, span) => {
+                        links.push((lib, span));
+                    },
+
+                    Attribute::LinkName(symbol, span) => {
+                        if let Some((ref prev, _)) = link_name {
+                            if *prev != symbol {
+                                return ErrorCode::InvalidAttribute.result(&span, format!("Conflicting link_name attributes, the function is already linked as {}", prev));
+                            }
+                        }
+                        link_name = Some((symbol, span));
+                    },
+                }
             }
 
-            TokenKind::Let => {
-                parse_global_bindings(module, &mut tq, false, indent_level, namespace, target)?;
+            TokenKind::Interface => {
+                let mut i = parse_interface(tq, namespace, &tok.span, indent_level, target)?;
+                i.cfg = cfg.take();
+                declarations.push(Declaration::Type(TypeDeclaration::Interface(i)));
             }
 
-            TokenKind::Var => {
-                parse_global_bindings(module, &mut tq, true, indent_level, namespace, target)?;
+            TokenKind::Let | TokenKind::Var => {
+                let mutable = tok.kind == TokenKind::Var;
+                let globals = parse_global_bindings(tq, mutable, indent_level, namespace, target)?;
+                let global_cfg = cfg.take();
+                for (idx, mut g) in globals.into_iter().enumerate() {
+                    g.cfg = global_cfg.clone();
+                    g.doc = tq.take_doc_comment(if idx == 0 {doc_line} else {g.span.start.line});
+                    declarations.push(Declaration::Global(g));
+                }
             }
 
             TokenKind::Struct => {
-                let mut sd = parse_struct_type(&mut tq, namespace, indent_level, target)?;
+                let mut sd = parse_struct_type(tq, namespace, indent_level, target)?;
                 sd.span = tok.span.expanded(sd.span.end);
-                if module.types.contains_key(&sd.name) {
-                    return parse_error_result(&sd.span, format!("Type {} redefined", sd.name));
-                }
-                module.types.insert(sd.name.clone(), TypeDeclaration::Struct(sd));
+                sd.cfg = cfg.take();
+                sd.repr_c = repr_c.take().is_some();
+                sd.doc = tq.take_doc_comment(doc_line);
+                declarations.push(Declaration::Type(TypeDeclaration::Struct(sd)));
             }
 
             TokenKind::Enum => {
-                let st = parse_sum_type(&mut tq, namespace, &tok.span, indent_level, target)?;
-                if module.types.contains_key(&st.name) {
-                    return parse_error_result(&st.span, format!("Type {} redefined", st.name));
-                }
-                module.types.insert(st.name.clone(), TypeDeclaration::Sum(st));
+                let mut st = parse_sum_type(tq, namespace, &tok.span, indent_level, target)?;
+                st.cfg = cfg.take();
+                st.doc = tq.take_doc_comment(doc_line);
+                declarations.push(Declaration::Type(TypeDeclaration::Sum(st)));
             }
 
             TokenKind::Type => {
-                panic!("NYI");
+                return parse_error_result(&tok.span, "Type aliases are not supported, declare a struct or an enum instead");
             }
 
             TokenKind::Extern => {
-                let ext_func = parse_external_function(&mut tq, &tok.span, indent_level, target)?;
-                if module.externals.contains_key(&ext_func.sig.name) {
-                    return parse_error_result(&ext_func.span, format!("External function {} redefined", ext_func.sig.name));
-                }
-                module.externals.insert(ext_func.sig.name.clone(), ext_func);
+                let mut ext_func = parse_external_function(tq, &tok.span, indent_level, target)?;
+                ext_func.cfg = cfg.take();
+                ext_func.libraries = links.drain(..).map(|(lib, _)| lib).collect();
+                ext_func.sig.link_name = link_name.take().map(|(symbol, _)| symbol);
+                ext_func.doc = tq.take_doc_comment(doc_line);
+                declarations.push(Declaration::External(ext_func));
             }
 
-            TokenKind::Import => {
+            TokenKind::Import if cfg.is_none() => {
                 loop
                 {
-                    let import = parse_import_name(&mut tq)?;
+                    let import = parse_import_name(tq)?;
                     module.import_names.insert(import);
                     if tq.is_next(&TokenKind::Comma) {
                         tq.pop()?;
@@ -1218,8 +1877,19 @@ fn parse_module<Input: Read>(
             }
 
             TokenKind::Func => {
-                let func = parse_function_declaration(&mut tq, namespace, &tok.span, indent_level, target)?;
-                add_function(module, func)?;
+                let mut func = parse_function_declaration(tq, namespace, &tok.span, indent_level, target)?;
+                func.cfg = cfg.take();
+                func.sig.inline = inline.take().is_some();
+                // Without a symbol name, the function is exported under its name without the namespace
+                let local_name = func.sig.name.rsplit("::").next().unwrap_or(&func.sig.name).to_string();
+                func.sig.export = export.take().map(|(symbol, _)| symbol.unwrap_or(local_name));
+                func.doc = tq.take_doc_comment(doc_line);
+                declarations.push(Declaration::Function(func));
+            }
+
+            _ if cfg.is_some() => {
+                return ErrorCode::InvalidAttribute.result(&tok.span,
+                    format!("Expected fn, let, var, extern, struct, enum or interface after cfg attribute, found token {}", tok));
             }
 
             _ => {
@@ -1229,6 +1899,505 @@ fn parse_module<Input: Read>(
         }
     }
 
+    if cfg.is_some() {
+        let tok = tq.pop()?;
+        return ErrorCode::InvalidAttribute.result(&tok.span, "Expected a declaration after cfg attribute, found EOF");
+    }
+
+    if let Some(ref inline_span) = inline {
+        return ErrorCode::InvalidAttribute.result(inline_span, "Expected a function after inline attribute, found EOF");
+    }
+
+    if let Some((_, ref export_span)) = export {
+        return ErrorCode::InvalidAttribute.result(export_span, "Expected a function after export attribute, found EOF");
+    }
+
+    if let Some(&(_, ref link_span)) = links.first() {
+        return ErrorCode::InvalidAttribute.result(link_span, "Expected an external function after link attribute, found EOF");
+    }
+
+    if let Some((_, ref link_name_span)) = link_name {
+        return ErrorCode::InvalidAttribute.result(link_name_span, "Expected an external function after link_name attribute, found EOF");
+    }
+
+    add_enabled_declarations(module, declarations, target)
+}
+
+// Types and functions which are available in every module, unless the module declares one with the same name
+const PRELUDE: &str = "
+enum Result:
+    Ok{value: $t}
+    Err{error: $e}
+
+extern fn malloc(size: uint) -> *void
+extern fn realloc(ptr: *void, size: uint) -> *void
+extern fn free(ptr: *void)
+extern fn fopen(path: *uint8, mode: *uint8) -> *void
+extern fn fclose(file: *void) -> int32
+extern fn fread(buf: *void, size: uint, count: uint, file: *void) -> uint
+extern fn fwrite(buf: *void, size: uint, count: uint, file: *void) -> uint
+extern fn getchar() -> int32
+
+fn __merge_runs(src: $T[], dst: $T[], start: uint, mid: uint, end: uint, less: fn($T, $T) -> bool):
+    var i = start
+    var j = mid
+    var k = start
+    while k < end:
+        var take_left = j >= end
+        if i < mid && !take_left:
+            take_left = !less(src[j], src[i])
+        if take_left:
+            dst[k] = src[i]
+            i += 1u
+        else
+            dst[k] = src[j]
+            j += 1u
+        k += 1u
+
+fn sort(xs: $T[], less: fn($T, $T) -> bool) -> $T[]:
+    let n = xs.len
+    var src = @slice(malloc(n * @size_of($T)) as *$T, n)
+    var dst = @slice(malloc(n * @size_of($T)) as *$T, n)
+    var i = 0u
+    while i < n:
+        src[i] = xs[i]
+        i += 1u
+
+    var width = 1u
+    while width < n:
+        var start = 0u
+        while start < n:
+            let mid = if start + width < n: start + width else n
+            let end = if start + 2u * width < n: start + 2u * width else n
+            __merge_runs(src, dst, start, mid, end, less)
+            start += 2u * width
+
+        let merged = dst
+        dst = src
+        src = merged
+        width *= 2u
+
+    free(dst.data as *void)
+    src
+
+fn binary_search(xs: $T[], x: $T, less: fn($T, $T) -> bool) -> ?int:
+    var lo = 0u
+    var hi = xs.len
+    while lo < hi:
+        let mid = lo + (hi - lo) / 2u
+        if less(xs[mid], x):
+            lo = mid + 1u
+        else
+            hi = mid
+
+    var found = false
+    if lo < xs.len:
+        found = !less(x, xs[lo])
+    if found: lo as int else nil
+
+fn __c_string(s: string) -> *uint8:
+    var buf = @slice(malloc(s.len + 1u) as *uint8, s.len + 1u)
+    let src = @slice(s.data, s.len)
+    var i = 0u
+    while i < s.len:
+        buf[i] = src[i]
+        i += 1u
+    buf[s.len] = 0
+    buf.data
+
+# Make sure there is room for extra more bytes after the first len bytes
+fn __reserve_bytes(bytes: uint8[], len: uint, extra: uint) -> uint8[]:
+    var capacity = bytes.len
+    while capacity < len + extra:
+        capacity *= 2u
+    if capacity != bytes.len: @slice(realloc(bytes.data as *void, capacity) as *uint8, capacity) else bytes
+
+fn __is_valid_utf8(bytes: uint8[]) -> bool:
+    var valid = true
+    var i = 0u
+    while valid && i < bytes.len:
+        let b = bytes[i] as uint
+        var continuation_bytes = 0u
+        if b >= 128u && b < 194u:
+            valid = false
+        else if b >= 194u && b < 224u:
+            continuation_bytes = 1u
+        else if b >= 224u && b < 240u:
+            continuation_bytes = 2u
+        else if b >= 240u && b < 245u:
+            continuation_bytes = 3u
+        else if b >= 245u:
+            valid = false
+
+        if i + continuation_bytes >= bytes.len:
+            valid = false
+        else
+            var j = 1u
+            while j <= continuation_bytes:
+                let c = bytes[i + j] as uint
+                if c < 128u || c >= 192u:
+                    valid = false
+                j += 1u
+        i += continuation_bytes + 1u
+    valid
+
+fn string_to_bytes(s: string) -> bytes:
+    @slice(s.data, s.len)
+
+# Returns nil if b is not valid UTF-8, otherwise a string sharing the bytes of b
+fn bytes_to_string(b: bytes) -> ?string:
+    if __is_valid_utf8(b): b as string else nil
+
+# Returns nil if the file cannot be read
+fn read_file_bytes(path: string) -> ?bytes:
+    let c_path = __c_string(path)
+    let file = fopen(c_path, \"rb\".data)
+    free(c_path as *void)
+    if file == null:
+        return nil
+
+    let chunk = @slice(malloc(4096u) as *uint8, 4096u)
+    var bytes = @slice(malloc(4096u) as *uint8, 4096u)
+    var len = 0u
+    var done = false
+    while !done:
+        let n = fread(chunk.data as *void, 1u, chunk.len, file)
+        bytes = __reserve_bytes(bytes, len, n)
+        var i = 0u
+        while i < n:
+            bytes[len + i] = chunk[i]
+            i += 1u
+        len += n
+        done = n < chunk.len
+
+    _ = fclose(file)
+    free(chunk.data as *void)
+    @slice(bytes.data, len)
+
+# Returns nil if the file cannot be read, or if its contents are not valid UTF-8
+fn read_file(path: string) -> ?string:
+    if let contents = read_file_bytes(path):
+        if __is_valid_utf8(contents):
+            return contents as string
+        free(contents.data as *void)
+    nil
+
+fn write_file(path: string, contents: string) -> bool:
+    write_file_bytes(path, string_to_bytes(contents))
+
+fn write_file_bytes(path: string, contents: bytes) -> bool:
+    let c_path = __c_string(path)
+    let file = fopen(c_path, \"wb\".data)
+    free(c_path as *void)
+    if file == null:
+        return false
+
+    let written = fwrite(contents.data as *void, 1u, contents.len, file)
+    let closed = fclose(file) == 0
+    written == contents.len && closed
+
+# Reads a line from stdin without the trailing newline, returns nil at the end of the input
+fn read_line() -> ?string:
+    var bytes = @slice(malloc(128u) as *uint8, 128u)
+    var len = 0u
+    var c = getchar()
+    let at_end = c < 0
+    while c >= 0 && c != 10:
+        bytes = __reserve_bytes(bytes, len, 1u)
+        bytes[len] = c as uint8
+        len += 1u
+        c = getchar()
+
+    if at_end:
+        free(bytes.data as *void)
+        return nil
+    @slice(bytes.data, len) as string
+
+struct Map{keys: $K[], values: $V[], states: uint[], len: uint, filled: uint}
+
+fn __map_allocate(var m: *Map<$K, $V>, capacity: uint):
+    m.keys = @slice(malloc(capacity * @size_of($K)) as *$K, capacity)
+    m.values = @slice(malloc(capacity * @size_of($V)) as *$V, capacity)
+    var states = @slice(malloc(capacity * @size_of(uint)) as *uint, capacity)
+    var i = 0u
+    while i < capacity:
+        states[i] = 0u
+        i += 1u
+
+    m.states = states
+    m.len = 0u
+    m.filled = 0u
+
+fn Map.create() -> *Map<$K, $V>:
+    var m = malloc(@size_of(Map<$K, $V>)) as *Map<$K, $V>
+    __map_allocate(m, 8u)
+    m
+
+# A slot is empty (0), in use (1) or removed (2). Returns the slot holding k,
+# or if k is not in the map, the slot where it should be inserted.
+fn __map_find_slot(m: *Map<$K, $V>, k: $K) -> uint:
+    let keys = m.keys
+    let states = m.states
+    let capacity = states.len
+    var idx = hash(k) % capacity
+    var insert_at = capacity
+    var result = capacity
+    while result == capacity:
+        let state = states[idx]
+        if state == 0u:
+            result = if insert_at < capacity: insert_at else idx
+        else if state == 1u && keys[idx] == k:
+            result = idx
+        else
+            if state == 2u && insert_at == capacity:
+                insert_at = idx
+            idx = (idx + 1u) % capacity
+    result
+
+fn __map_grow(var m: *Map<$K, $V>):
+    let keys = @slice(m.keys.data, m.keys.len)
+    let values = @slice(m.values.data, m.values.len)
+    let states = @slice(m.states.data, m.states.len)
+    __map_allocate(m, states.len * 2u)
+    var i = 0u
+    while i < states.len:
+        if states[i] == 1u:
+            insert(m, keys[i], values[i])
+        i += 1u
+
+    free(keys.data as *void)
+    free(values.data as *void)
+    free(states.data as *void)
+
+fn insert(var m: *Map<$K, $V>, k: $K, v: $V):
+    if (m.filled + 1u) * 2u > m.states.len:
+        __map_grow(m)
+
+    let slot = __map_find_slot(m, k)
+    var keys = m.keys
+    var values = m.values
+    var states = m.states
+    if states[slot] != 1u:
+        if states[slot] == 0u:
+            m.filled += 1u
+        m.len += 1u
+        keys[slot] = k
+        states[slot] = 1u
+    values[slot] = v
+
+fn get(m: *Map<$K, $V>, k: $K) -> ?$V:
+    let slot = __map_find_slot(m, k)
+    let states = m.states
+    let values = m.values
+    if states[slot] == 1u: values[slot] else nil
+
+fn remove(var m: *Map<$K, $V>, k: $K) -> bool:
+    let slot = __map_find_slot(m, k)
+    var states = m.states
+    let found = states[slot] == 1u
+    if found:
+        states[slot] = 2u
+        m.len -= 1u
+    found
+
+struct MapIterator{map: *Map<$K, $V>, slot: uint}
+
+# Iterates over the entries of a map as (key, value) tuples, in no particular order
+fn Map.iterate(m: *Map<$K, $V>) -> MapIterator<$K, $V>:
+    MapIterator{m, 0u}
+
+fn MapIterator.next(var it: *MapIterator<$K, $V>) -> ?($K, $V):
+    let states = it.map.states
+    var slot = it.slot
+    var found = false
+    while slot < states.len && !found:
+        found = states[slot] == 1u
+        slot += 1u
+
+    it.slot = slot
+    let keys = it.map.keys
+    let values = it.map.values
+    if found: (keys[slot - 1u], values[slot - 1u]) else nil
+
+extern fn strtod(s: *uint8, end: **uint8) -> float64
+extern fn snprintf(buf: *uint8, n: uint, fmt: *uint8, ...) -> int32
+
+# The byte at index i as a uint, or 0 when i is at or past end
+fn __byte_at(bytes: uint8[], i: uint, end: uint) -> uint:
+    if i < end: bytes[i] as uint else 0u
+
+fn __is_space(c: uint) -> bool:
+    c == 32u || (c >= 9u && c <= 13u)
+
+fn __is_decimal_digit(c: uint) -> bool:
+    c >= 48u && c <= 57u
+
+# The start and end of bytes without leading and trailing whitespace
+fn __trim(bytes: uint8[]) -> (uint, uint):
+    var start = 0u
+    while __is_space(__byte_at(bytes, start, bytes.len)):
+        start += 1u
+
+    # Both sides of && are always evaluated, so bytes[end - 1u] is only read when end > start
+    var end = bytes.len
+    var trailing_space = true
+    while trailing_space:
+        trailing_space = if end > start: __is_space(bytes[end - 1u] as uint) else false
+        if trailing_space:
+            end -= 1u
+    (start, end)
+
+fn __skip_sign(bytes: uint8[], i: uint, end: uint) -> uint:
+    let c = __byte_at(bytes, i, end)
+    if c == 43u || c == 45u: i + 1u else i
+
+fn __skip_digits(bytes: uint8[], i: uint, end: uint) -> uint:
+    var j = i
+    while __is_decimal_digit(__byte_at(bytes, j, end)):
+        j += 1u
+    j
+
+# The digits are accumulated as a negative number, because int64 has one more negative value than positive ones
+fn __parse_int_digits(bytes: uint8[], start: uint, end: uint, negative: bool, max: int64) -> ?int64:
+    if start == end || __skip_digits(bytes, start, end) != end:
+        return nil
+
+    let min = (-max) - 1
+    var value = max - max
+    var i = start
+    while i < end:
+        let c = bytes[i] as uint - 48u
+        let digit = c as int64
+        if value < (min + digit) / 10:
+            return nil
+        value = value * 10 - digit
+        i += 1u
+
+    if negative:
+        return value
+    if value >= -max: -value else nil
+
+# Returns nil if s is not a decimal integer, or if it does not fit in an int64
+fn parse_int(s: string) -> ?int64:
+    let bytes = @slice(s.data, s.len)
+    let (start, end) = __trim(bytes)
+    let negative = __byte_at(bytes, start, end) == 45u
+    __parse_int_digits(bytes, __skip_sign(bytes, start, end), end, negative, 9223372036854775807)
+
+fn __parse_uint_digits(bytes: uint8[], start: uint, end: uint, max: uint64) -> ?uint64:
+    if start == end || __skip_digits(bytes, start, end) != end:
+        return nil
+
+    var value = max - max
+    var i = start
+    while i < end:
+        let c = bytes[i] as uint - 48u
+        let digit = c as uint64
+        if value > (max - digit) / 10u:
+            return nil
+        value = value * 10u + digit
+        i += 1u
+    value
+
+# Returns nil if s is not a decimal integer, if it is negative, or if it does not fit in a uint64
+fn parse_uint(s: string) -> ?uint64:
+    let bytes = @slice(s.data, s.len)
+    let (start, end) = __trim(bytes)
+    if __byte_at(bytes, start, end) == 45u:
+        return nil
+    __parse_uint_digits(bytes, __skip_sign(bytes, start, end), end, 18446744073709551615u)
+
+# Only plain decimal floats are accepted, strtod would also take hex floats, inf and nan
+fn __is_decimal_float(bytes: uint8[], start: uint, end: uint) -> bool:
+    let integer_start = __skip_sign(bytes, start, end)
+    let integer_end = __skip_digits(bytes, integer_start, end)
+    var i = integer_end
+    var digits = integer_end - integer_start
+    if __byte_at(bytes, i, end) == 46u:
+        let fraction_end = __skip_digits(bytes, i + 1u, end)
+        digits += fraction_end - i - 1u
+        i = fraction_end
+
+    let e = __byte_at(bytes, i, end)
+    if digits > 0u && (e == 101u || e == 69u):
+        let exponent_start = __skip_sign(bytes, i + 1u, end)
+        i = __skip_digits(bytes, exponent_start, end)
+        if i == exponent_start:
+            return false
+    digits > 0u && i == end
+
+# Returns nil if s is not a decimal float, or if it is too large to be represented by a float64
+fn parse_float(s: string) -> ?float64:
+    let bytes = @slice(s.data, s.len)
+    let (start, end) = __trim(bytes)
+    if !__is_decimal_float(bytes, start, end):
+        return nil
+
+    let c_string = __c_string(s)
+    let value = strtod(c_string, null)
+    free(c_string as *void)
+    let max = 1.7976931348623157e308
+    if value > max || value < -max:
+        return nil
+    value
+
+# Bases outside 2 to 36 give an empty string
+fn format_int(v: int64, base: uint8) -> string:
+    let b = base as int64
+    if b < 2 || b > 36:
+        return \"\"
+
+    # The digits are taken from the negative value, so the minimum int64 does not overflow
+    let digits = @slice(\"0123456789abcdefghijklmnopqrstuvwxyz\".data, 36u)
+    var buf = @slice(malloc(65u) as *uint8, 65u)
+    var start = 65u
+    var rest = if v > 0: -v else v
+    var done = false
+    while !done:
+        start -= 1u
+        let digit = -(rest % b)
+        buf[start] = digits[digit as uint]
+        rest = rest / b
+        done = rest == 0
+
+    if v < 0:
+        start -= 1u
+        buf[start] = 45
+
+    let len = 65u - start
+    var result = @slice(malloc(len) as *uint8, len)
+    var i = 0u
+    while i < len:
+        result[i] = buf[start + i]
+        i += 1u
+    free(buf.data as *void)
+    result as string
+
+# Formats v with precision digits after the decimal point, like %.*f in C
+fn format_float(v: float64, precision: uint8) -> string:
+    let p = precision as int32
+    let len = snprintf(null, 0u, \"%.*f\".data, p, v) as uint
+    var buf = @slice(malloc(len + 1u) as *uint8, len + 1u)
+    _ = snprintf(buf.data, len + 1u, \"%.*f\".data, p, v)
+    @slice(buf.data, len) as string
+";
+
+fn add_prelude(module: &mut Module, namespace: &str, target: &Target) -> CompileResult<()>
+{
+    let mut prelude = Module::new(namespace);
+    let mut input = PRELUDE.as_bytes();
+    parse_module(&mut prelude, &mut input, namespace, "prelude", target)?;
+    for (name, typ) in prelude.types {
+        module.types.entry(name).or_insert(typ);
+    }
+
+    for (name, func) in prelude.functions {
+        module.functions.entry(name).or_insert(func);
+    }
+
+    for (name, func) in prelude.externals {
+        module.externals.entry(name).or_insert(func);
+    }
     Ok(())
 }
 
@@ -1239,16 +2408,17 @@ pub fn parse_file(file_path: &Path, namespace: &str, target: &Target) -> Compile
         let mut module = Module::new(namespace);
         let mut file = fs::File::open(file_path)?;
         parse_module(&mut module, &mut file, namespace, file_path.to_string_lossy().deref(), target)?;
+        add_prelude(&mut module, namespace, target)?;
         Ok(module)
     })
 }
 
 
-#[cfg(test)]
+#[cfg(any(test, feature = "fuzz"))]
 use package::Package;
 
-#[cfg(test)]
-pub fn parse_str(code: &str, root_namespace: &str, target: &Target) -> CompileResult<Package>
+#[cfg(any(test, feature = "fuzz"))]
+pub fn parse_str<Code: AsRef<[u8]>>(code: Code, root_namespace: &str, target: &Target) -> CompileResult<Package>
 {
     use std::io::Cursor;
     
@@ -1256,6 +2426,7 @@ pub fn parse_str(code: &str, root_namespace: &str, target: &Target) -> CompileRe
     let mut module = Module::new(root_namespace);
     let mut cursor = Cursor::new(code);
     parse_module(&mut module, &mut cursor, root_namespace, "", target)?;
+    add_prelude(&mut module, root_namespace, target)?;
     pkg.modules.insert(root_namespace.into(), module);
     Ok(pkg)
 }
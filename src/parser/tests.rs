@@ -1,9 +1,13 @@
 use std::io::Cursor;
+use std::thread;
 use ast::*;
 use parser::*;
 use super::lexer::Lexer;
 use target::Target;
+use compileerror::{CompileError, ErrorData};
+use errorcodes::ErrorCode;
 use span::{Pos, Span};
+use COMPILER_STACK_SIZE;
 
 fn span(sl: usize, so: usize, el: usize, eo: usize) -> Span
 {
@@ -40,6 +44,17 @@ pub fn th_mod(data: &str, target: &Target) -> Module
     pkg.modules.remove("test").expect("No module named test")
 }
 
+// Functions and externals declared in the code itself, so not coming from the prelude
+fn declared_function_count(md: &Module) -> usize
+{
+    md.functions.values().filter(|f| f.span.file != "prelude").count()
+}
+
+fn declared_external_count(md: &Module) -> usize
+{
+    md.externals.values().filter(|f| f.span.file != "prelude").count()
+}
+
 
 pub fn number(v: i64, span: Span, target: &Target) -> Expression
 {
@@ -77,6 +92,44 @@ fn test_basic_expressions()
     assert!(th_expr("false", &target) == Expression::Literal(Literal::Bool(span(1, 1, 1, 5), false)));
 }
 
+#[test]
+fn test_return_literals()
+{
+    let target = Target::new(IntSize::I32, "");
+    assert!(th_expr("return nil", &target) == return_expr(nil_expr(span(1, 8, 1, 10)), span(1, 1, 1, 10)));
+    assert!(th_expr("return false", &target) == return_expr(Expression::Literal(Literal::Bool(span(1, 8, 1, 12), false)), span(1, 1, 1, 12)));
+    assert!(th_expr("return 'a'", &target) == return_expr(Expression::Literal(Literal::Char(span(1, 8, 1, 10), 'a')), span(1, 1, 1, 10)));
+}
+
+#[test]
+fn test_type_ascription()
+{
+    let target = Target::new(IntSize::I32, "");
+    assert!(th_expr("([] : int[])", &target) == type_ascription(
+        Expression::Literal(array_lit(Vec::new(), span(1, 2, 1, 3))),
+        slice_type(Type::Int(IntSize::I32)),
+        span(1, 1, 1, 12)));
+
+    assert!(th_expr("(a : uint8)", &target) == type_ascription(
+        name_ref("a", span(1, 2, 1, 2)),
+        Type::UInt(IntSize::I8),
+        span(1, 1, 1, 11)));
+
+    // A let binding with a type annotation, is a binding of a type ascription
+    assert!(th_expr("let xs: int[] = [1]", &target) == bindings(
+        vec![
+            name_binding(
+                "xs".into(),
+                type_ascription(
+                    Expression::Literal(array_lit(vec![number(1, span(1, 18, 1, 18), &target)], span(1, 17, 1, 19))),
+                    slice_type(Type::Int(IntSize::I32)),
+                    span(1, 17, 1, 19)),
+                false,
+                span(1, 5, 1, 19))
+        ],
+        span(1, 1, 1, 19)));
+}
+
 #[test]
 fn test_binary_ops()
 {
@@ -151,6 +204,26 @@ fn test_precedence_3()
     ));
 }
 
+#[test]
+fn test_is_precedence()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr("a && b is 5", &target);
+    let bop = match e {
+        Expression::BinaryOp(bop) => bop,
+        _ => panic!("Expecting a binary op"),
+    };
+    assert!(bop.operator == BinaryOperator::And);
+    assert!(bop.left == name_ref("a", span(1, 1, 1, 1)));
+    match bop.right {
+        Expression::Is(ref i) => {
+            assert!(i.inner == name_ref("b", span(1, 6, 1, 6)));
+            assert!(i.pattern == number_pattern(5, span(1, 11, 1, 11), &target));
+        },
+        _ => panic!("Expecting an is expression"),
+    }
+}
+
 #[test]
 fn test_precedence_4()
 {
@@ -463,6 +536,30 @@ fn test_external_function()
     )
 }
 
+#[test]
+fn test_malformed_declarations()
+{
+    let target = Target::new(IntSize::I32, "");
+    let err = parse_str("fn main() -> int: [1; 99999999999][0]", "test", &target).err().expect("Expected an error");
+    assert!(err.to_string().contains("Array repeat count 99999999999 is too large"));
+
+    let err = parse_str("type X = int", "test", &target).err().expect("Expected an error");
+    assert!(err.to_string().contains("Type aliases are not supported"));
+}
+
+#[test]
+fn test_variadic_external_function()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod("extern fn printf(fmt: *uint8, ...) -> int32", &target);
+    let printf = md.externals.get("printf").unwrap();
+    assert!(printf.sig.variadic);
+    assert!(printf.sig.args.len() == 1);
+
+    assert!(parse_str("extern fn printf(..., fmt: *uint8) -> int32", "test", &target).is_err());
+    assert!(parse_str("fn foo(x: int, ...) -> int: x", "test", &target).is_err());
+}
+
 #[test]
 fn test_lambda()
 {
@@ -483,6 +580,52 @@ fn test_lambda()
     ))
 }
 
+#[test]
+fn test_lambda_block_body()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+fn(a) ->
+    let b = a * 2
+    b + 1
+"#, &target);
+    match e {
+        Expression::Lambda(ref l) => {
+            assert!(l.name.is_none());
+            match l.expr {
+                Expression::Block(ref b) => assert_eq!(b.expressions.len(), 2),
+                _ => panic!("Expecting a block"),
+            }
+        },
+        _ => panic!("Expecting a lambda"),
+    }
+}
+
+#[test]
+fn test_local_function()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+fn fact(n: int) -> int:
+    if n <= 1: 1 else n * fact(n - 1)
+"#, &target);
+    let bl = match e {
+        Expression::Bindings(ref bl) => bl,
+        _ => panic!("Expecting a binding"),
+    };
+
+    assert_eq!(bl.bindings.len(), 1);
+    assert_eq!(bl.bindings[0].binding_type, BindingType::Name("fact".into()));
+    match bl.bindings[0].init {
+        Expression::Lambda(ref l) => {
+            assert_eq!(l.name, Some("fact".into()));
+            assert_eq!(l.sig.return_type, Type::Int(IntSize::I32));
+            assert_eq!(l.sig.args[0].typ, Type::Int(IntSize::I32));
+        },
+        _ => panic!("Expecting a lambda"),
+    }
+}
+
 #[test]
 fn test_match()
 {
@@ -504,6 +647,56 @@ match a:
     )
 }
 
+#[test]
+fn test_range_patterns()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+match a:
+    0..10 => 1
+    'a'..='z' => 2
+"#, &target);
+    assert!(e == match_expression(
+        name_ref("a", span(2, 7, 2, 7)),
+        vec![
+            match_case(
+                range_pattern(Literal::Int(span(3, 5, 3, 5), 0, IntSize::I32), Literal::Int(span(3, 8, 3, 9), 10, IntSize::I32), false, span(3, 5, 3, 9)),
+                number(1, span(3, 14, 3, 14), &target),
+                span(3, 5, 3, 14)),
+            match_case(
+                range_pattern(Literal::Char(span(4, 5, 4, 7), 'a'), Literal::Char(span(4, 11, 4, 13), 'z'), true, span(4, 5, 4, 13)),
+                number(2, span(4, 18, 4, 18), &target),
+                span(4, 5, 4, 18)),
+        ],
+        span(2, 1, 4, 18))
+    );
+
+    let target = Target::new(IntSize::I32, "");
+    assert!(parse_str("fn f(a: int) -> int:\n    match a:\n        0..x => 1\n        _ => 2", "test", &target).is_err());
+}
+
+#[test]
+fn test_or_patterns()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+match a:
+    0 | 1 => 1
+    _ => 2
+"#, &target);
+    assert!(e == match_expression(
+        name_ref("a", span(2, 7, 2, 7)),
+        vec![
+            match_case(
+                Pattern::Or(vec![number_pattern(0, span(3, 5, 3, 5), &target), number_pattern(1, span(3, 9, 3, 9), &target)]),
+                number(1, span(3, 14, 3, 14), &target),
+                span(3, 5, 3, 14)),
+            match_case(Pattern::Any(span(4, 5, 4, 5)), number(2, span(4, 10, 4, 10), &target), span(4, 5, 4, 10)),
+        ],
+        span(2, 1, 4, 10))
+    );
+}
+
 #[test]
 fn test_struct()
 {
@@ -542,6 +735,26 @@ struct Point:
     ))
 }
 
+#[test]
+fn test_generic_struct_with_params()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod(r#"
+struct Pair<T, U>:
+    x: T
+    y: *U[]
+"#, &target);
+    let mut expected = struct_declaration(
+        "test::Pair",
+        vec![
+            struct_member_declaration("x", generic_type("T"), span(3, 5, 3, 8)),
+            struct_member_declaration("y", ptr_type(slice_type(generic_type("U"))), span(4, 5, 4, 11)),
+        ],
+        span(2, 1, 4, 11));
+    expected.generic_params = vec![generic_type("T"), generic_type("U")];
+    assert!(*md.types.get("test::Pair").unwrap() == TypeDeclaration::Struct(expected));
+}
+
 #[test]
 fn test_struct_initializer()
 {
@@ -576,6 +789,65 @@ fn test_anonymous_struct_initializer()
     ))
 }
 
+#[test]
+fn test_tuples()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+(6, 7)
+"#, &target);
+    assert!(e == Expression::StructInitializer(struct_initializer(
+        "",
+        vec![
+            number(6, span(2, 2, 2, 2), &target),
+            number(7, span(2, 5, 2, 5), &target),
+        ],
+        span(2, 1, 2, 6))
+    ));
+
+    let e = th_expr("let (q, _) = d", &target);
+    assert!(e == bindings(
+        vec![
+            binding(
+                BindingType::Struct(struct_pattern(
+                    "",
+                    vec![
+                        StructPatternBinding{name: "q".into(), typ: Type::Unknown, mode: StructPatternBindingMode::Value},
+                        StructPatternBinding{name: "_".into(), typ: Type::Unknown, mode: StructPatternBindingMode::Value},
+                    ],
+                    Type::Unknown,
+                    span(1, 5, 1, 10)
+                )),
+                name_ref("d", span(1, 14, 1, 14)),
+                false,
+                span(1, 5, 1, 14)
+            )
+        ],
+        span(1, 1, 1, 14)
+    ));
+
+    // The lexer sees 0.1 as a single number
+    let e = th_expr("t.0.1", &target);
+    assert!(e ==
+        member_access(
+            member_access(
+                name_ref("t", span(1, 1, 1, 1)),
+                MemberAccessType::Name(field("0", 0)),
+                span(1, 1, 1, 5)
+            ),
+            MemberAccessType::Name(field("1", 0)),
+            span(1, 1, 1, 5)
+        )
+    );
+
+    // A single type between parentheses is not a tuple
+    let md = th_mod("fn f(a: (int), b: (int, bool)) -> (int): a", &target);
+    let f = md.functions.get("test::f").expect("Unknown function");
+    assert_eq!(f.sig.args[0].typ, Type::Int(IntSize::I32));
+    assert_eq!(f.sig.args[1].typ, struct_type("", vec![struct_member("", Type::Int(IntSize::I32)), struct_member("", Type::Bool)]));
+    assert_eq!(f.sig.return_type, Type::Int(IntSize::I32));
+}
+
 #[test]
 fn test_member_access()
 {
@@ -738,6 +1010,23 @@ if true: 5 else 10"#, &target);
     ))
 }
 
+#[test]
+fn test_if_let()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+if let x = a, y = b: x else c"#, &target);
+    assert!(e == if_let_expression(
+        vec![
+            if_let_binding("x".into(), name_ref("a", span(2, 12, 2, 12)), span(2, 8, 2, 12)),
+            if_let_binding("y".into(), name_ref("b", span(2, 19, 2, 19)), span(2, 15, 2, 19)),
+        ],
+        name_ref("x", span(2, 22, 2, 22)),
+        Some(name_ref("c", span(2, 29, 2, 29))),
+        span(2, 1, 2, 29)
+    ))
+}
+
 #[test]
 fn test_block()
 {
@@ -756,6 +1045,68 @@ fn test_block()
 }
 
 
+#[test]
+fn test_discard()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod("fn foo() -> int:\n    _ = bar()\n    7", &target);
+    let f = md.functions.get("test::foo").expect("Unknown function");
+    let b = match f.expression {
+        Expression::Block(ref b) => b,
+        _ => panic!("Expected a block"),
+    };
+
+    match b.expressions[0] {
+        Expression::Discard(ref d) => {
+            assert_eq!(d.span, span(2, 5, 2, 13));
+            match d.inner {
+                Expression::Call(ref c) => assert_eq!(c.callee.name, "bar"),
+                _ => panic!("Expected a call"),
+            }
+        },
+        _ => panic!("Expected a discard"),
+    }
+}
+
+#[test]
+fn test_let_destructuring()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr("let Point{x, _} = p", &target);
+    assert!(e == bindings(
+        vec![
+            binding(
+                BindingType::Struct(struct_pattern(
+                    "Point",
+                    vec![
+                        StructPatternBinding{name: "x".into(), typ: Type::Unknown, mode: StructPatternBindingMode::Value},
+                        StructPatternBinding{name: "_".into(), typ: Type::Unknown, mode: StructPatternBindingMode::Value},
+                    ],
+                    Type::Unknown,
+                    span(1, 5, 1, 15)
+                )),
+                name_ref("p", span(1, 19, 1, 19)),
+                false,
+                span(1, 5, 1, 19)
+            )
+        ],
+        span(1, 1, 1, 19)
+    ));
+
+    let e = th_expr("let [head | tail] = a", &target);
+    assert!(e == bindings(
+        vec![
+            binding(
+                BindingType::Array(ArrayPattern{head: "head".into(), tail: "tail".into(), span: span(1, 5, 1, 17)}),
+                name_ref("a", span(1, 21, 1, 21)),
+                false,
+                span(1, 5, 1, 21)
+            )
+        ],
+        span(1, 1, 1, 21)
+    ));
+}
+
 #[test]
 fn test_interface()
 {
@@ -781,3 +1132,353 @@ interface Foo:
         span(2, 1, 3, 31)
     )))
 }
+
+#[test]
+fn test_global_bindings()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod(r#"
+let x = 7
+var counter: int = 0
+"#, &target);
+
+    let x = &md.globals["test::x"];
+    assert!(!x.mutable);
+    assert_eq!(x.typ, Type::Unknown);
+    assert_eq!(x.init, number(7, span(2, 9, 2, 9), &target));
+
+    let counter = &md.globals["test::counter"];
+    assert!(counter.mutable);
+    assert_eq!(counter.typ, target.native_int_type);
+    assert_eq!(counter.init, number(0, span(3, 20, 3, 20), &target));
+}
+
+#[test]
+fn test_call_with_type_args()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr("Map<string, int>()", &target);
+    let mut call = Call::new(name_ref2("Map", span(1, 1, 1, 3)), vec![], span(1, 1, 1, 18));
+    call.type_args = vec![Type::String, target.native_int_type.clone()];
+    assert_eq!(e, Expression::Call(Box::new(call)));
+
+    let e = th_expr("a < b(c)", &target);
+    assert_eq!(e, bin_op(
+        BinaryOperator::LessThan,
+        name_ref("a", span(1, 1, 1, 1)),
+        Expression::Call(Box::new(Call::new(
+            name_ref2("b", span(1, 5, 1, 5)),
+            vec![name_ref("c", span(1, 7, 1, 7))],
+            span(1, 5, 1, 8)
+        ))),
+        span(1, 1, 1, 8),
+    ));
+}
+
+#[test]
+fn test_repr_c_attribute()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod("#[repr(c)]\nstruct Pair:\n    a: int32\n    b: int32\n\nstruct Other:\n    a: int32", &target);
+    let repr_c = |name: &str| match md.types.get(name) {
+        Some(&TypeDeclaration::Struct(ref sd)) => sd.repr_c,
+        _ => panic!("Expected struct {}", name),
+    };
+    assert!(repr_c("test::Pair"));
+    assert!(!repr_c("test::Other"));
+
+    assert!(parse_str("#[repr(c)]\nfn foo() -> int: 0", "test", &target).is_err());
+    assert!(parse_str("#[repr(packed)]\nstruct Foo:\n    a: int", "test", &target).is_err());
+}
+
+#[test]
+fn test_named_arguments()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr("rect(5, height: 20, width: 10)", &target);
+    let mut call = Call::new(name_ref2("rect", span(1, 1, 1, 4)), vec![number(5, span(1, 6, 1, 6), &target)], span(1, 1, 1, 30));
+    call.named_args = vec![
+        NamedArgument{name: "height".into(), value: number(20, span(1, 17, 1, 18), &target), span: span(1, 9, 1, 18)},
+        NamedArgument{name: "width".into(), value: number(10, span(1, 28, 1, 29), &target), span: span(1, 21, 1, 29)},
+    ];
+    assert_eq!(e, Expression::Call(Box::new(call)));
+
+    let err = match parse_str("fn main() -> int: rect(height: 20, 5)", "test", &target) {
+        Err(e) => format!("{}", e),
+        Ok(_) => panic!("Expected a parse error"),
+    };
+    assert!(err.contains("Positional arguments must come before named arguments, but this one follows height"));
+}
+
+#[test]
+fn test_default_argument_values()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod("fn connect(host: string, port: uint16 = 8080, retries: int32 = 3) -> int: 0", &target);
+    let args = &md.functions["test::connect"].sig.args;
+    let default = |idx: usize| match args[idx].default {
+        Some(Expression::Literal(Literal::Int(_, v, _))) => Some(v),
+        None => None,
+        _ => panic!("Expected an integer literal"),
+    };
+    assert_eq!(default(0), None);
+    assert_eq!(default(1), Some(8080));
+    assert_eq!(default(2), Some(3));
+
+    let parse_error = |src: &str| match parse_str(src, "test", &target) {
+        Err(e) => format!("{}", e),
+        Ok(_) => panic!("Expected a parse error"),
+    };
+    assert!(parse_error("fn connect(port: uint16 = 8080, host: string) -> int: 0")
+        .contains("Argument host has no default value, but it comes after argument port which has one, only trailing arguments can have default values"));
+    assert!(parse_error("extern fn abs(v: int = 1) -> int")
+        .contains("External functions cannot have default argument values, argument v has one"));
+    assert!(parse_error("fn main() -> int:\n    let f = fn(a: int = 1) -> a\n    f(2)")
+        .contains("Lambdas cannot have default argument values, argument a has one"));
+}
+
+#[test]
+fn test_deeply_nested_expression()
+{
+    let parser = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(|| {
+        let target = Target::new(IntSize::I32, "");
+        let code = format!("fn main() -> int: {}1{}", "(".repeat(100), ")".repeat(100));
+        assert!(parse_str(&code, "test", &target).is_ok());
+
+        let code = format!("fn main() -> int: {}1{}", "(".repeat(20_000), ")".repeat(20_000));
+        assert!(parse_str(&code, "test", &target).is_err());
+    });
+
+    parser.expect("Unable to start thread").join().expect("Parsing crashed");
+}
+
+#[test]
+fn test_cfg_selects_function_for_target()
+{
+    let code = r#"
+#[cfg(target_os = "linux")]
+fn foo() -> int: 1
+
+#[cfg(not(target_os = "linux"))]
+fn foo() -> int: 2
+"#;
+    let linux = Target::new(IntSize::I64, "x86_64-pc-linux-gnu");
+    let md = th_mod(code, &linux);
+    assert_eq!(declared_function_count(&md), 1);
+    assert_eq!(md.functions["test::foo"].span, span(3, 1, 3, 18));
+
+    let darwin = Target::new(IntSize::I64, "x86_64-apple-darwin");
+    let md = th_mod(code, &darwin);
+    assert_eq!(declared_function_count(&md), 1);
+    assert_eq!(md.functions["test::foo"].span, span(6, 1, 6, 18));
+}
+
+#[test]
+fn test_cfg_feature_gated_extern()
+{
+    let code = r#"
+#[cfg(any(feature = "ncurses", all(feature = "term", target_arch = "x86_64")))]
+extern fn initscr() -> int
+"#;
+    let mut target = Target::new(IntSize::I64, "x86_64-pc-linux-gnu");
+    assert_eq!(declared_external_count(&th_mod(code, &target)), 0);
+
+    target.features.insert("term".into());
+    let md = th_mod(code, &target);
+    let ext = &md.externals["initscr"];
+    assert_eq!(ext.cfg.as_ref().map(|cfg| cfg.to_string()),
+        Some(r#"any(feature = "ncurses", all(feature = "term", target_arch = "x86_64"))"#.into()));
+}
+
+#[test]
+fn test_cfg_duplicate_after_filtering()
+{
+    let code = r#"
+#[cfg(target_os = "linux")]
+fn foo() -> int: 1
+
+#[cfg(target_arch = "x86_64")]
+fn foo() -> int: 2
+"#;
+    let target = Target::new(IntSize::I64, "x86_64-pc-linux-gnu");
+    let err = parse_str(code, "test", &target).err().expect("Expected a redefinition error");
+    assert_eq!(err, CompileError::Many(vec![
+        CompileError::Parse(ErrorData::with_code(&span(6, 1, 6, 18), "Function test::foo redefined", ErrorCode::Redefinition)),
+        CompileError::Parse(ErrorData::new(&span(3, 1, 3, 18), "Function test::foo previously defined here")),
+    ]));
+
+    let target = Target::new(IntSize::I64, "aarch64-unknown-linux-gnu");
+    assert!(parse_str(code, "test", &target).is_ok());
+}
+
+fn redefinition(code: &str, span_a: Span, msg_a: &str, span_b: Span, msg_b: &str)
+{
+    let target = Target::new(IntSize::I64, "");
+    let err = parse_str(code, "test", &target).err().expect("Expected a redefinition error");
+    assert_eq!(err, CompileError::Many(vec![
+        CompileError::Parse(ErrorData::with_code(&span_a, msg_a, ErrorCode::Redefinition)),
+        CompileError::Parse(ErrorData::new(&span_b, msg_b)),
+    ]));
+}
+
+#[test]
+fn test_duplicate_definitions()
+{
+    redefinition("struct Circle:\n    r: int\nfn Circle() -> int: 1",
+        span(3, 1, 3, 21), "Function test::Circle redefined",
+        span(1, 1, 2, 10), "Struct test::Circle previously defined here");
+
+    redefinition("enum Shape:\n    Square\n    Circle\nfn Circle() -> int: 1",
+        span(4, 1, 4, 21), "Function test::Circle redefined",
+        span(3, 5, 3, 10), "Sum type case test::Shape::Circle previously defined here");
+
+    redefinition("let x = 5\nfn x() -> int: 1",
+        span(2, 1, 2, 16), "Function test::x redefined",
+        span(1, 5, 1, 9), "Global test::x previously defined here");
+
+    redefinition("extern fn puts(s: string) -> int\nfn puts() -> int: 1",
+        span(2, 1, 2, 19), "Function test::puts redefined",
+        span(1, 1, 1, 32), "External function puts previously defined here");
+
+    redefinition("struct Point:\n    x: int\n    x: int",
+        span(3, 5, 3, 10), "Struct member x redefined",
+        span(2, 5, 2, 10), "Struct member x previously defined here");
+
+    redefinition("enum Shape:\n    Circle{r: int, r: int}\n    Square",
+        span(2, 20, 2, 25), "Struct member r redefined",
+        span(2, 12, 2, 17), "Struct member r previously defined here");
+
+    redefinition("enum Shape:\n    Square\n    Square",
+        span(3, 5, 3, 10), "Sum type case test::Shape::Square redefined",
+        span(2, 5, 2, 10), "Sum type case test::Shape::Square previously defined here");
+
+    redefinition("fn foo(a: int, a: int) -> int: a",
+        span(1, 16, 1, 21), "Argument a redefined",
+        span(1, 8, 1, 13), "Argument a previously defined here");
+
+    redefinition("extern fn foo(a: int, a: int) -> int",
+        span(1, 23, 1, 28), "Argument a redefined",
+        span(1, 15, 1, 20), "Argument a previously defined here");
+
+    // Discarded arguments don't collide
+    let target = Target::new(IntSize::I64, "");
+    assert!(parse_str("fn foo(_: int, _: bool, b: int) -> int: b", "test", &target).is_ok());
+}
+
+#[test]
+fn test_inline_attribute()
+{
+    let code = r#"
+#[inline]
+fn foo() -> int: 1
+
+fn bar() -> int: 2
+"#;
+    let target = Target::new(IntSize::I64, "");
+    let md = th_mod(code, &target);
+    assert!(md.functions["test::foo"].sig.inline);
+    assert!(!md.functions["test::bar"].sig.inline);
+
+    let err = parse_str("#[inline]\nstruct Foo:\n    x: int\n", "test", &target).err().expect("Expected an error");
+    assert_eq!(err, CompileError::Parse(ErrorData::with_code(&span(1, 3, 1, 8), "The inline attribute is only allowed on functions", ErrorCode::InvalidAttribute)));
+}
+
+#[test]
+fn test_export_attribute()
+{
+    let code = r#"
+#[export("menhir_foo")]
+fn foo() -> int: 1
+
+#[export]
+fn bar() -> int: 2
+
+fn baz() -> int: 3
+"#;
+    let target = Target::new(IntSize::I64, "");
+    let md = th_mod(code, &target);
+    assert_eq!(md.functions["test::foo"].sig.export, Some("menhir_foo".into()));
+    assert_eq!(md.functions["test::bar"].sig.export, Some("bar".into()));
+    assert_eq!(md.functions["test::baz"].sig.export, None);
+
+    let err = parse_str("#[export]\nstruct Foo:\n    x: int\n", "test", &target).err().expect("Expected an error");
+    assert_eq!(err, CompileError::Parse(ErrorData::with_code(&span(1, 3, 1, 9), "The export attribute is only allowed on functions", ErrorCode::InvalidAttribute)));
+}
+
+#[test]
+fn test_link_attributes()
+{
+    let code = r#"
+#[link(name = "m")]
+#[link(name = "z", kind = "static")]
+#[link_name = "sqrt"]
+extern fn square_root(x: float) -> float
+
+extern fn abs(x: int32) -> int32
+"#;
+    let target = Target::new(IntSize::I64, "");
+    let md = th_mod(code, &target);
+    let ext = &md.externals["square_root"];
+    assert_eq!(ext.sig.link_name, Some("sqrt".into()));
+    assert_eq!(ext.libraries, vec![
+        LinkLibrary{name: "m".into(), kind: LinkKind::Dylib},
+        LinkLibrary{name: "z".into(), kind: LinkKind::Static},
+    ]);
+    assert_eq!(md.externals["abs"].sig.link_name, None);
+    assert!(md.externals["abs"].libraries.is_empty());
+
+    let err = parse_str("#[link(name = \"m\")]\nfn foo() -> int: 1\n", "test", &target).err().expect("Expected an error");
+    assert_eq!(err, CompileError::Parse(ErrorData::with_code(&span(1, 3, 1, 19), "The link attribute is only allowed on external functions", ErrorCode::InvalidAttribute)));
+
+    let err = parse_str("#[link(kind = \"static\")]\nextern fn foo() -> int\n", "test", &target).err().expect("Expected an error");
+    assert_eq!(err, CompileError::Parse(ErrorData::with_code(&span(1, 3, 1, 6), "The link attribute requires the name of a library", ErrorCode::InvalidAttribute)));
+
+    let err = parse_str("#[link_name = \"a\"]\n#[link_name = \"b\"]\nextern fn foo() -> int\n", "test", &target).err().expect("Expected an error");
+    assert_eq!(err, CompileError::Parse(ErrorData::with_code(&span(2, 3, 2, 18), "Conflicting link_name attributes, the function is already linked as a", ErrorCode::InvalidAttribute)));
+}
+
+#[test]
+fn test_doc_comments()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod(r#"
+## Adds two numbers,
+## which can be negative
+fn add(a: int, b: int) -> int: a + b
+
+## A point
+#[repr(c)]
+struct Point:
+    ## Horizontal position
+    x: int32
+    y: int32
+
+# Not a doc comment
+fn sub(a: int, b: int) -> int: a - b
+"#, &target);
+
+    assert_eq!(md.functions["test::add"].doc, Some("Adds two numbers,\nwhich can be negative".into()));
+    assert_eq!(md.functions["test::sub"].doc, None);
+    match md.types.get("test::Point") {
+        Some(&TypeDeclaration::Struct(ref sd)) => {
+            assert_eq!(sd.doc, Some("A point".into()));
+            assert_eq!(sd.members[0].doc, Some("Horizontal position".into()));
+            assert_eq!(sd.members[1].doc, None);
+        },
+        _ => panic!("Expected struct Point"),
+    }
+}
+
+#[test]
+fn test_misplaced_doc_comments()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut cursor = Cursor::new("fn add(a: int, b: int) -> int:\n    ## Not a declaration\n    a + b\n\n## At the end");
+    let mut tq = Lexer::new("").read(&mut cursor).expect("Lexing failed");
+    let mut md = Module::new("test");
+    parse_declarations(&mut md, &mut tq, "test", &target).expect("Parsing failed");
+
+    let spans: Vec<Span> = tq.doc_comments().iter().map(|dc| dc.span.clone()).collect();
+    assert_eq!(spans, vec![span(2, 5, 2, 24), span(5, 1, 5, 13)]);
+    assert_eq!(md.functions["test::add"].doc, None);
+}
@@ -1,14 +1,25 @@
 use std::collections::VecDeque;
-use compileerror::{CompileError, CompileResult, ErrorData, parse_error_result};
+use compileerror::{CompileResult, parse_error_result};
+use errorcodes::ErrorCode;
 use super::tokens::{Token, TokenKind};
-use ast::{BinaryOperator, AssignOperator};
+use ast::{BinaryOperator, AssignOperator, MAX_NESTING_DEPTH};
 use span::{Pos, Span};
 
 
+// Consecutive ## comment lines, which document the declaration on the line after them
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DocComment
+{
+    pub text: String,
+    pub span: Span,
+}
+
 pub struct TokenQueue
 {
     tokens: VecDeque<Token>,
+    doc_comments: Vec<DocComment>,
     last_pos: Pos,
+    nesting_depth: usize,
 }
 
 impl TokenQueue
@@ -17,7 +28,9 @@ impl TokenQueue
     {
         TokenQueue{
             tokens: VecDeque::new(),
+            doc_comments: Vec::new(),
             last_pos: Pos::new(1, 1),
+            nesting_depth: 0,
         }
     }
 
@@ -26,11 +39,54 @@ impl TokenQueue
         self.tokens.push_back(tok);
     }
 
+    pub fn add_doc_comment(&mut self, line: &str, span: Span)
+    {
+        if let Some(last) = self.doc_comments.last_mut() {
+            if last.span.end.line + 1 == span.start.line {
+                last.text.push('\n');
+                last.text.push_str(line);
+                last.span.end = span.end;
+                return;
+            }
+        }
+
+        self.doc_comments.push(DocComment{text: line.into(), span: span});
+    }
+
+    // The doc comment which ends on the line before the given one
+    pub fn take_doc_comment(&mut self, line: usize) -> Option<String>
+    {
+        let idx = self.doc_comments.iter().position(|dc| dc.span.end.line + 1 == line)?;
+        Some(self.doc_comments.remove(idx).text)
+    }
+
+    // The doc comments which have not been taken by a declaration
+    pub fn doc_comments(&self) -> &[DocComment]
+    {
+        &self.doc_comments
+    }
+
     pub fn pos(&self) -> Pos
     {
         self.last_pos
     }
 
+    pub fn enter_nested_expression(&mut self) -> CompileResult<()>
+    {
+        self.nesting_depth += 1;
+        if self.nesting_depth > MAX_NESTING_DEPTH {
+            let span = self.peek().map(|tok| tok.span.clone()).unwrap_or_else(|| Span::single("", self.last_pos));
+            parse_error_result(&span, format!("Expression too deeply nested, max {}", MAX_NESTING_DEPTH))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn exit_nested_expression(&mut self)
+    {
+        self.nesting_depth -= 1;
+    }
+
     #[allow(dead_code)]
     pub fn dump(&self)
     {
@@ -51,7 +107,7 @@ impl TokenQueue
             self.last_pos = tok.span.end;
             Ok(tok)
         } else {
-            parse_error_result(&Span::default(), "Unexpected end of file")
+            ErrorCode::UnexpectedToken.result(&Span::default(), "Unexpected end of file")
         }
     }
 
@@ -71,7 +127,7 @@ impl TokenQueue
             if tok.kind == *kind {
                 Ok(tok)
             } else {
-                parse_error_result(&tok.span, format!("Unexpected token {}, expecting {}", tok.kind, kind))
+                ErrorCode::UnexpectedToken.result(&tok.span, format!("Unexpected token {}, expecting {}", tok.kind, kind))
             }
         )
     }
@@ -81,12 +137,12 @@ impl TokenQueue
         let tok = self.pop()?;
         if let TokenKind::Number(ref v) = tok.kind
         {
-            let val = v.parse::<u64>().map_err(|_| CompileError::Parse(ErrorData::new(&tok.span, format!("{} is not a valid integer", v))))?;
+            let val = v.parse::<u64>().map_err(|_| ErrorCode::InvalidLiteral.error(&tok.span, format!("{} is not a valid integer", v)))?;
             Ok((val, tok.span))
         }
         else
         {
-            parse_error_result(&tok.span, format!("Expected integer literal, found {}", tok))
+            ErrorCode::UnexpectedToken.result(&tok.span, format!("Expected integer literal, found {}", tok))
         }
     }
 
@@ -99,7 +155,20 @@ impl TokenQueue
         }
         else
         {
-            parse_error_result(&tok.span, format!("Expected identifier, found {}", tok))
+            ErrorCode::UnexpectedToken.result(&tok.span, format!("Expected identifier, found {}", tok))
+        }
+    }
+
+    pub fn expect_string(&mut self) -> CompileResult<(String, Span)>
+    {
+        let tok = self.pop()?;
+        if let TokenKind::StringLiteral(s) = tok.kind
+        {
+            Ok((s, tok.span))
+        }
+        else
+        {
+            ErrorCode::UnexpectedToken.result(&tok.span, format!("Expected string literal, found {}", tok))
         }
     }
 
@@ -112,7 +181,7 @@ impl TokenQueue
         }
         else
         {
-            parse_error_result(&tok.span, format!("Expected operator, found {}", tok))
+            ErrorCode::UnexpectedToken.result(&tok.span, format!("Expected operator, found {}", tok))
         }
     }
 
@@ -170,6 +239,16 @@ impl TokenQueue
         }
     }
 
+    // Whether an indented block, deeper then indent_level, starts here
+    pub fn is_next_indent_above(&self, indent_level: usize) -> bool
+    {
+        match self.tokens.front()
+        {
+            Some(tok) => if let TokenKind::Indent(level) = tok.kind {level > indent_level} else {false},
+            None => false,
+        }
+    }
+
     pub fn is_in_same_block(&self, indent_level: usize) -> bool
     {
         match self.tokens.front()
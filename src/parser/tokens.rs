@@ -1,6 +1,6 @@
 use std::fmt::{Formatter, Display, Error};
 use span::{Span};
-use ast::{BinaryOperator, UnaryOperator, AssignOperator};
+use ast::{BinaryOperator, UnaryOperator, AssignOperator, escape_bytes};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum TokenKind
@@ -8,6 +8,7 @@ pub enum TokenKind
     Identifier(String),
     Number(String),
     StringLiteral(String),
+    ByteStringLiteral(Vec<u8>),
     CharLiteral(char),
     BinaryOperator(BinaryOperator),
     UnaryOperator(UnaryOperator),
@@ -52,7 +53,12 @@ pub enum TokenKind
     Indent(usize),
     Ampersand,
     At,
+    Hash,
     Return,
+    Ellipsis,
+    DotDot,
+    DotDotEquals,
+    Comment(String),
     EOF,
 }
 
@@ -65,6 +71,7 @@ impl Display for TokenKind
             TokenKind::Identifier(ref s) => write!(fmt, "identifier '{}'", s),
             TokenKind::Number(ref n) => write!(fmt, "number '{}'", n),
             TokenKind::StringLiteral(ref s) => write!(fmt, "string litteral '{}'", s),
+            TokenKind::ByteStringLiteral(ref b) => write!(fmt, "byte string literal '{}'", escape_bytes(b)),
             TokenKind::CharLiteral(c) => write!(fmt, "char literal '{}'", c),
             TokenKind::BinaryOperator(ref op) => write!(fmt, "operator {}", op),
             TokenKind::UnaryOperator(ref op) => write!(fmt, "operator {}", op),
@@ -109,7 +116,12 @@ impl Display for TokenKind
             TokenKind::Indent(lvl) => write!(fmt, "indent {}", lvl),
             TokenKind::Ampersand => write!(fmt, "&"),
             TokenKind::At => write!(fmt, "@"),
+            TokenKind::Hash => write!(fmt, "#"),
             TokenKind::Return => write!(fmt, "return"),
+            TokenKind::Ellipsis => write!(fmt, "..."),
+            TokenKind::DotDot => write!(fmt, ".."),
+            TokenKind::DotDotEquals => write!(fmt, "..="),
+            TokenKind::Comment(ref c) => write!(fmt, "comment '{}'", c),
             TokenKind::EOF => write!(fmt, "EOF"),
         }
     }
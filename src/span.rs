@@ -1,11 +1,14 @@
 use std::cmp;
 use std::fmt;
 
+// How many columns a tab counts for, when it indents a line and when a line is printed in an error message
+pub const TAB_WIDTH: usize = 4;
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Pos
 {
     pub line: usize,
-    pub offset: usize,
+    pub offset: usize, // Column in characters starting at 1, a tab is a single character
 }
 
 impl Pos
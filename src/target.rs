@@ -1,5 +1,11 @@
+use std::collections::HashSet;
 use ast::{IntSize, Type};
 
+pub const TEST_FEATURE: &str = "test";
+
+// Values are stored on the stack or in globals, anything larger than this is a compile error
+pub const DEFAULT_MAX_OBJECT_SIZE: usize = 1 << 30;
+
 #[derive(Debug)]
 pub struct Target
 {
@@ -7,6 +13,8 @@ pub struct Target
     pub native_int_type: Type,
     pub native_uint_type: Type,
     pub triplet: String,
+    pub features: HashSet<String>,
+    pub max_object_size: usize, // In bytes
 }
 
 impl Target
@@ -18,6 +26,25 @@ impl Target
             native_int_type: Type::Int(int_size),
             native_uint_type: Type::UInt(int_size),
             triplet: triplet.into(),
+            features: HashSet::new(),
+            max_object_size: DEFAULT_MAX_OBJECT_SIZE,
         }
     }
+
+    // Triplets look like arch-vendor-os or arch-vendor-os-env
+    pub fn arch(&self) -> &str
+    {
+        self.triplet.split('-').next().unwrap_or("")
+    }
+
+    pub fn os(&self) -> &str
+    {
+        self.triplet.split('-').nth(2).unwrap_or("")
+    }
+
+    // Test builds are compiled with --cfg feature=test
+    pub fn is_test_build(&self) -> bool
+    {
+        self.features.contains(TEST_FEATURE)
+    }
 }
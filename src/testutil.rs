@@ -0,0 +1,44 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// A uniquely named directory for the files of a test, which is removed again when the test is done
+pub struct TempDir
+{
+    path: PathBuf,
+}
+
+impl TempDir
+{
+    pub fn new(prefix: &str) -> TempDir
+    {
+        let path = env::temp_dir().join(format!("{}-{}", prefix, Uuid::new_v4()));
+        fs::create_dir_all(&path).expect("Cannot create temporary directory");
+        TempDir{path}
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf
+    {
+        self.path.join(path)
+    }
+
+    // Creates the file, and the directories it is in
+    pub fn write(&self, file_name: &str, contents: &str) -> PathBuf
+    {
+        let path = self.path.join(file_name);
+        fs::create_dir_all(path.parent().expect("No parent directory")).expect("Cannot create directory");
+        let mut file = File::create(&path).expect("Cannot create file");
+        file.write_all(contents.as_bytes()).expect("Cannot write file");
+        path
+    }
+}
+
+impl Drop for TempDir
+{
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -1,6 +1,13 @@
-use time::SteadyTime;
+use std::cmp::Reverse;
+use time::{SteadyTime, Duration};
 use ast::prefix;
 
+fn format_duration(duration: Duration) -> String
+{
+    let us = duration.num_microseconds().unwrap_or(0) % 1000;
+    format!("{}.{:03} ms", duration.num_milliseconds(), us)
+}
+
 pub fn time_operation<Op, R>(level: usize, op_name: &str, op: Op) -> R
     where Op: Fn() -> R, R: Sized
 {
@@ -8,20 +15,111 @@ pub fn time_operation<Op, R>(level: usize, op_name: &str, op: Op) -> R
     let r = op();
 
     let duration = SteadyTime::now() - start_time;
-    let us = duration.num_microseconds().unwrap_or(0) % 1000;
-    println!("{}{}: {}.{:03} ms", prefix(level), op_name, duration.num_milliseconds(), us);
+    println!("{}{}: {}", prefix(level), op_name, format_duration(duration));
     r
 }
 
 
-pub fn time_operation_mut<Op, R>(level: usize, op_name: &str, mut op: Op) -> R
-    where Op: FnMut() -> R, R: Sized
+#[derive(Debug, Clone)]
+pub struct PassTiming
 {
-    let start_time = SteadyTime::now();
-    let r = op();
+    pub name: String,
+    pub duration: Duration,
+    pub invocations: usize,
+    pub work: usize, // Pass specific amount of work done, for example the number of declarations resolved
+}
 
-    let duration = SteadyTime::now() - start_time;
-    let us = duration.num_microseconds().unwrap_or(0) % 1000;
-    println!("{}{}: {}.{:03} ms", prefix(level), op_name, duration.num_milliseconds(), us);
-    r
-}
\ No newline at end of file
+// Collects the time spent in each compiler pass
+#[derive(Debug, Default)]
+pub struct PassTimings
+{
+    passes: Vec<PassTiming>,
+}
+
+impl PassTimings
+{
+    pub fn new() -> PassTimings
+    {
+        PassTimings{
+            passes: Vec::new(),
+        }
+    }
+
+    fn entry(&mut self, pass: &str) -> &mut PassTiming
+    {
+        if let Some(idx) = self.passes.iter().position(|p| p.name == pass) {
+            return &mut self.passes[idx];
+        }
+
+        self.passes.push(PassTiming{
+            name: pass.into(),
+            duration: Duration::zero(),
+            invocations: 0,
+            work: 0,
+        });
+        self.passes.last_mut().expect("passes cannot be empty")
+    }
+
+    pub fn record(&mut self, pass: &str, duration: Duration)
+    {
+        let entry = self.entry(pass);
+        entry.duration = entry.duration + duration;
+        entry.invocations += 1;
+    }
+
+    pub fn add_work(&mut self, pass: &str, work: usize)
+    {
+        self.entry(pass).work += work;
+    }
+
+    // Time an operation without printing anything
+    pub fn time<Op, R>(&mut self, pass: &str, op: Op) -> R
+        where Op: FnOnce() -> R
+    {
+        let start_time = SteadyTime::now();
+        let r = op();
+        self.record(pass, SteadyTime::now() - start_time);
+        r
+    }
+
+    // Time an operation and print the duration, like time_operation does
+    pub fn time_operation<Op, R>(&mut self, level: usize, pass: &str, op: Op) -> R
+        where Op: FnOnce() -> R
+    {
+        let start_time = SteadyTime::now();
+        let r = op();
+        let duration = SteadyTime::now() - start_time;
+        println!("{}{}: {}", prefix(level), pass, format_duration(duration));
+        self.record(pass, duration);
+        r
+    }
+
+    // Add the timings of another collector to this one
+    pub fn merge(&mut self, other: PassTimings)
+    {
+        for p in other.passes {
+            let entry = self.entry(&p.name);
+            entry.duration = entry.duration + p.duration;
+            entry.invocations += p.invocations;
+            entry.work += p.work;
+        }
+    }
+
+    pub fn passes(&self) -> &[PassTiming]
+    {
+        &self.passes
+    }
+
+    // Print all passes, slowest first
+    pub fn print(&self)
+    {
+        let mut passes: Vec<&PassTiming> = self.passes().iter().collect();
+        passes.sort_by_key(|p| Reverse(p.duration));
+
+        let name_width = passes.iter().map(|p| p.name.len()).max().unwrap_or(0).max(4);
+        println!("{:<width$} {:>14} {:>6} {:>6}", "Pass", "Time", "Count", "Work", width = name_width);
+        for p in passes {
+            println!("{:<width$} {:>14} {:>6} {:>6}", p.name, format_duration(p.duration), p.invocations, p.work, width = name_width);
+        }
+    }
+}
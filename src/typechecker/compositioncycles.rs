@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use itertools::join;
+use ast::{Module, Type, TypeDeclaration, StructMemberDeclaration};
+use compileerror::{CompileResult, CompileError, type_error};
+use span::Span;
+
+// A member of an unresolved type declaration, whose type refers to another unresolved declaration of the module
+struct MemberEdge
+{
+    owner: String,
+    member: String,
+    member_type: Type,
+    span: Span,
+    target: String,
+    by_value: bool, // False when the member only points to the target
+}
+
+// The named types typ refers to, and whether it contains them by value, pointers and slices break a cycle
+fn referenced_names(typ: &Type, by_value: bool, names: &mut Vec<(String, bool)>)
+{
+    match *typ
+    {
+        Type::Unresolved(ref ut) => names.push((ut.name.clone(), by_value)),
+        Type::Array(ref at) => referenced_names(&at.element_type, by_value, names),
+        Type::Optional(ref inner) => referenced_names(inner, by_value, names),
+        Type::Pointer(ref inner) => referenced_names(inner, false, names),
+        Type::Slice(ref st) => referenced_names(&st.element_type, false, names),
+        _ => (),
+    }
+}
+
+fn unresolved_declaration<'a>(module: &'a Module, name: &str) -> Option<&'a TypeDeclaration>
+{
+    module.types.get(name)
+        .or_else(|| module.types.get(&format!("{}::{}", module.name, name)))
+        .filter(|decl| !decl.is_resolved())
+}
+
+fn member_edges(module: &Module) -> Vec<MemberEdge>
+{
+    let mut declarations: Vec<&TypeDeclaration> = module.types.values().filter(|decl| !decl.is_resolved()).collect();
+    declarations.sort_by(|a, b| a.name().cmp(b.name()));
+
+    let mut edges = Vec::new();
+    for decl in declarations
+    {
+        let members: Vec<&StructMemberDeclaration> = match *decl {
+            TypeDeclaration::Struct(ref sd) => sd.members.iter().collect(),
+            TypeDeclaration::Sum(ref st) => st.cases.iter().filter_map(|c| c.data.as_ref()).flat_map(|sd| sd.members.iter()).collect(),
+            TypeDeclaration::Interface(_) => continue,
+        };
+
+        for m in members
+        {
+            let mut names = Vec::new();
+            referenced_names(&m.typ, true, &mut names);
+            for (name, by_value) in names
+            {
+                if let Some(target) = unresolved_declaration(module, &name) {
+                    edges.push(MemberEdge{
+                        owner: decl.name().into(),
+                        member: m.name.clone(),
+                        member_type: m.typ.clone(),
+                        span: m.span.clone(),
+                        target: target.name().into(),
+                        by_value,
+                    });
+                }
+            }
+        }
+    }
+    edges
+}
+
+// Depth first search, every edge back to a declaration on the current path closes a cycle
+fn find_cycles<'a>(node: &str, edges: &[&'a MemberEdge], path: &mut Vec<&'a MemberEdge>, visited: &mut HashSet<String>, cycles: &mut Vec<Vec<&'a MemberEdge>>)
+{
+    visited.insert(node.into());
+    for &e in edges.iter().filter(|e| e.owner == node)
+    {
+        if e.target == node {
+            cycles.push(vec![e]);
+        } else if let Some(start) = path.iter().position(|p| p.owner == e.target) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(e);
+            cycles.push(cycle);
+        } else if !visited.contains(&e.target) {
+            path.push(e);
+            find_cycles(&e.target, edges, path, visited, cycles);
+            path.pop();
+        }
+    }
+}
+
+fn cycles_of<'a>(edges: &[&'a MemberEdge]) -> Vec<Vec<&'a MemberEdge>>
+{
+    let mut visited = HashSet::new();
+    let mut cycles = Vec::new();
+    for e in edges
+    {
+        if !visited.contains(&e.owner) {
+            find_cycles(&e.owner, edges, &mut Vec::new(), &mut visited, &mut cycles);
+        }
+    }
+    cycles
+}
+
+fn cycle_error(cycle: &[&MemberEdge], msg: &str) -> CompileError
+{
+    let chain = join(cycle.iter().map(|e| format!("{}.{}: {}", e.owner, e.member, e.member_type)), " -> ");
+    let members = join(cycle.iter().map(|e| format!("{}: {}.{}", e.span, e.owner, e.member)), "\n  ");
+    type_error(&cycle[0].span, format!("{} forms a cycle, {}:\n  {}", chain, msg, members))
+}
+
+fn cycle_errors(cycles: &[Vec<&MemberEdge>], msg: &str) -> CompileError
+{
+    if cycles.len() == 1 {
+        cycle_error(&cycles[0], msg)
+    } else {
+        CompileError::Many(cycles.iter().map(|c| cycle_error(c, msg)).collect())
+    }
+}
+
+// When the types of a module cannot all be resolved, because some of them contain each other, that gets reported
+// instead of an unknown name for whichever member happens to be resolved last
+pub fn check_composition_cycles(module: &Module) -> CompileResult<()>
+{
+    let edges = member_edges(module);
+    let by_value: Vec<&MemberEdge> = edges.iter().filter(|e| e.by_value).collect();
+    let cycles = cycles_of(&by_value);
+    if !cycles.is_empty() {
+        return Err(cycle_errors(&cycles, "a type cannot contain itself by value"));
+    }
+
+    let all: Vec<&MemberEdge> = edges.iter().collect();
+    let cycles = cycles_of(&all);
+    if !cycles.is_empty() {
+        return Err(cycle_errors(&cycles, "types which refer to themselves through pointers or slices are not supported"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use ast::{Expression, BinaryOp, BinaryOperator, UnaryOperator, Literal, MemberAccess, MemberAccessType, Property, CompilerCall, Visitor, walk_expression};
+use compileerror::CompileResult;
+use target::Target;
+use super::typecheckercontext::TypeCheckerContext;
+
+// Constant strings are string literals and names of constant globals, a folded operation on them is again a literal
+fn constant_string<F>(e: &Expression, constants: &F, target: &Target) -> Option<String>
+    where F: Fn(&str) -> Option<String>
+{
+    match fold(e, constants, target)
+    {
+        Some(Literal::String(_, s)) => Some(s),
+        _ => None,
+    }
+}
+
+// "a" + "b" becomes "ab", and == and != on two constant strings become true or false
+fn fold_binary_op<F>(b: &BinaryOp, constants: &F, target: &Target) -> Option<Literal>
+    where F: Fn(&str) -> Option<String>
+{
+    let left = constant_string(&b.left, constants, target)?;
+    let right = constant_string(&b.right, constants, target)?;
+    match b.operator
+    {
+        BinaryOperator::Add => Some(Literal::String(b.span.clone(), left + &right)),
+        BinaryOperator::Equals => Some(Literal::Bool(b.span.clone(), left == right)),
+        BinaryOperator::NotEquals => Some(Literal::Bool(b.span.clone(), left != right)),
+        _ => None,
+    }
+}
+
+// The len of a constant string is its number of bytes
+fn fold_member_access<F>(sma: &MemberAccess, constants: &F, target: &Target) -> Option<Literal>
+    where F: Fn(&str) -> Option<String>
+{
+    let is_len = match sma.right
+    {
+        MemberAccessType::Property(Property::Len) => true,
+        MemberAccessType::Name(ref field) => field.name == "len",
+        _ => false,
+    };
+
+    if !is_len {
+        return None;
+    }
+
+    let s = constant_string(&sma.left, constants, target)?;
+    Some(Literal::UInt(sma.span.clone(), s.len() as u64, target.int_size))
+}
+
+// The literal gets the span of the whole expression, so errors about it point at the original expression
+fn fold<F>(e: &Expression, constants: &F, target: &Target) -> Option<Literal>
+    where F: Fn(&str) -> Option<String>
+{
+    match *e
+    {
+        Expression::Literal(ref lit @ Literal::String(..)) => Some(lit.clone()),
+        Expression::NameRef(ref nr) => constants(&nr.name).map(|s| Literal::String(nr.span.clone(), s)),
+        Expression::BinaryOp(ref b) => fold_binary_op(b, constants, target),
+        Expression::MemberAccess(ref sma) => fold_member_access(sma, constants, target),
+        _ => None,
+    }
+}
+
+// A boolean expression of constant bools and comparisons of constant strings
+fn constant_bool<F>(e: &Expression, constants: &F, target: &Target) -> Option<bool>
+    where F: Fn(&str) -> Option<String>
+{
+    match *e
+    {
+        Expression::Literal(Literal::Bool(_, v)) => Some(v),
+        Expression::UnaryOp(ref u) if u.operator == UnaryOperator::Not => constant_bool(&u.expression, constants, target).map(|v| !v),
+        Expression::BinaryOp(ref b) => {
+            if let Some(Literal::Bool(_, v)) = fold_binary_op(b, constants, target) {
+                return Some(v);
+            }
+
+            let left = constant_bool(&b.left, constants, target)?;
+            let right = constant_bool(&b.right, constants, target)?;
+            match b.operator
+            {
+                BinaryOperator::And => Some(left && right),
+                BinaryOperator::Or => Some(left || right),
+                BinaryOperator::Equals => Some(left == right),
+                BinaryOperator::NotEquals => Some(left != right),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+// The literal an expression made up of only literals evaluates to, when it can be computed at compile time
+pub fn fold_constants(e: &Expression, target: &Target) -> Option<Literal>
+{
+    fold(e, &|_: &str| None, target)
+}
+
+// The value of an expression which is known at compile time: a literal, a negated number or bool, or a folded string operation
+pub fn constant_value(e: &Expression, target: &Target) -> Option<Literal>
+{
+    match *e
+    {
+        Expression::Literal(Literal::Array(_)) => None,
+        Expression::Literal(ref lit) => Some(lit.clone()),
+        Expression::UnaryOp(ref u) => match (u.operator, constant_value(&u.expression, target)?) {
+            (UnaryOperator::Sub, Literal::Int(_, v, int_size)) => Some(Literal::Int(u.span.clone(), v.checked_neg()?, int_size)),
+            (UnaryOperator::Sub, Literal::Float(_, ref v, float_size)) if v.starts_with('-') => Some(Literal::Float(u.span.clone(), v[1..].into(), float_size)),
+            (UnaryOperator::Sub, Literal::Float(_, v, float_size)) => Some(Literal::Float(u.span.clone(), format!("-{}", v), float_size)),
+            (UnaryOperator::Not, Literal::Bool(_, v)) => Some(Literal::Bool(u.span.clone(), !v)),
+            _ => None,
+        },
+        _ => fold_constants(e, target),
+    }
+}
+
+// Folding of string operations during type checking, names of constant globals are replaced by their value
+pub fn fold_binary_op_in_context(ctx: &TypeCheckerContext, b: &BinaryOp, target: &Target) -> Option<Literal>
+{
+    fold_binary_op(b, &|name: &str| ctx.get_constant(name), target)
+}
+
+pub fn fold_member_access_in_context(ctx: &TypeCheckerContext, sma: &MemberAccess, target: &Target) -> Option<Literal>
+{
+    fold_member_access(sma, &|name: &str| ctx.get_constant(name), target)
+}
+
+// The length of an array type given by a constant expression, for example int["abc".len]
+pub fn array_length_in_context(ctx: &TypeCheckerContext, e: &Expression, target: &Target) -> Option<usize>
+{
+    match fold(e, &|name: &str| ctx.get_constant(name), target)
+    {
+        Some(Literal::UInt(_, len, _)) => Some(len as usize),
+        _ => None,
+    }
+}
+
+pub fn constant_string_in_context(ctx: &TypeCheckerContext, e: &Expression, target: &Target) -> Option<String>
+{
+    constant_string(e, &|name: &str| ctx.get_constant(name), target)
+}
+
+pub fn constant_bool_in_context(ctx: &TypeCheckerContext, e: &Expression, target: &Target) -> Option<bool>
+{
+    constant_bool(e, &|name: &str| ctx.get_constant(name), target)
+}
+
+// An @type_name of an expression with a generic type, is only folded in the instances of a generic function
+struct GenericTypeNameFinder
+{
+    found: bool,
+}
+
+impl Visitor for GenericTypeNameFinder
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
+        if let Expression::CompilerCall(CompilerCall::TypeName(..)) = *e {
+            self.found = true;
+            return Ok(());
+        }
+        walk_expression(self, e)
+    }
+}
+
+pub fn has_generic_type_name(e: &Expression) -> bool
+{
+    let mut finder = GenericTypeNameFinder{found: false};
+    let _ = finder.visit_expression(e);
+    finder.found
+}
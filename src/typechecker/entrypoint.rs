@@ -0,0 +1,35 @@
+use ast::{Module, Type};
+use compileerror::{CompileResult, CompileError};
+use errorcodes::ErrorCode;
+use target::Target;
+
+pub fn no_main_function_error(main_name: &str) -> CompileError
+{
+    CompileError::Other(format!("No main function found, an executable starts at {}, did you mean to compile with --lib?", main_name))
+}
+
+// The main function of an executable must be declared as fn main() -> int, fn main() -> uint or fn main() -> void,
+// its return value is the exit code of the program, a main which returns void exits with 0
+pub fn check_main_function(module: &Module, main_name: &str, target: &Target) -> CompileResult<()>
+{
+    let main = match module.functions.get(main_name) {
+        Some(main) => main,
+        None => return Err(no_main_function_error(main_name)),
+    };
+
+    let sig = &main.sig;
+    if main.is_generic() {
+        return ErrorCode::InvalidMainFunction.result(&sig.span, "main must not be generic");
+    }
+
+    if !sig.args.is_empty() {
+        return ErrorCode::InvalidMainFunction.result(&sig.span, format!("main takes {} arguments but must take none, command line arguments are not supported", sig.args.len()));
+    }
+
+    if sig.return_type != target.native_int_type && sig.return_type != target.native_uint_type && sig.return_type != Type::Void {
+        return ErrorCode::InvalidMainFunction.result(&sig.span, format!("main returns {} but must return {}, {} or void",
+            sig.return_type, target.native_int_type, target.native_uint_type));
+    }
+
+    Ok(())
+}
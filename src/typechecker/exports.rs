@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use ast::{ExternalFunction, Function, Module, Type};
+use compileerror::{CompileResult, CompileError};
+use errorcodes::ErrorCode;
+
+// Exported functions are called from C, so arguments and return values must be passed the same way as in C.
+// Aggregates are passed by pointer, so of the types with a C representation, only the ones passed by value are allowed.
+fn is_c_passable(typ: &Type) -> bool
+{
+    typ.is_c_representable() && typ.pass_by_value()
+}
+
+fn is_c_identifier(name: &str) -> bool
+{
+    let mut chars = name.chars();
+    match chars.next()
+    {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn check_exported_function(f: &Function, symbol: &str) -> CompileResult<()>
+{
+    if f.is_generic() {
+        return ErrorCode::InvalidCInterface.result(&f.sig.span, format!("Generic function {} cannot be exported", f.sig.name));
+    }
+
+    if !is_c_identifier(symbol) {
+        return ErrorCode::InvalidCInterface.result(&f.sig.span,
+            format!("{} is not a valid C symbol name, use #[export(\"name\")] to export {} under another name", symbol, f.sig.name));
+    }
+
+    if let Some(arg) = f.sig.args.iter().find(|arg| !is_c_passable(&arg.typ)) {
+        return ErrorCode::InvalidCInterface.result(&arg.span,
+            format!("Argument {} of exported function {} has type {}, which cannot be passed from C, \
+                only integers, floats, chars, bools and pointers are allowed", arg.name, f.sig.name, arg.typ));
+    }
+
+    if f.sig.return_type != Type::Void && !is_c_passable(&f.sig.return_type) {
+        return ErrorCode::InvalidCInterface.result(&f.sig.span,
+            format!("Exported function {} returns a {}, which cannot be returned to C, \
+                only void, integers, floats, chars, bools and pointers are allowed", f.sig.name, f.sig.return_type));
+    }
+
+    Ok(())
+}
+
+// Check the signatures of the exported functions in a set of modules, and that no two of them use the same symbol name
+pub fn check_exported_functions<'a, I>(modules: I) -> CompileResult<()>
+    where I: Iterator<Item=&'a Module>
+{
+    let mut exported: Vec<(&str, &Function)> = modules
+        .flat_map(|m| m.functions.values())
+        .filter_map(|f| f.sig.export.as_ref().map(|symbol| (&symbol[..], f)))
+        .collect();
+    // Sorted, so the error for a duplicate is always reported on the same one of the two
+    exported.sort_by(|a, b| (&a.1.span.file, a.1.span.start).cmp(&(&b.1.span.file, b.1.span.start)));
+
+    let mut symbols: HashMap<&str, &Function> = HashMap::new();
+    let mut errors = Vec::new();
+    for (symbol, f) in exported {
+        check_exported_function(f, symbol)?;
+        if let Some(prev) = symbols.insert(symbol, f) {
+            errors.push(ErrorCode::InvalidCInterface.error(&f.sig.span,
+                format!("Exported symbol {} is used by both {} ({}) and {} ({})", symbol, prev.sig.name, prev.sig.span, f.sig.name, f.sig.span)));
+        }
+    }
+
+    match errors.len()
+    {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(CompileError::Many(errors)),
+    }
+}
+
+// The C symbol an external function is declared as
+fn external_symbol(f: &ExternalFunction) -> &str
+{
+    f.sig.link_name.as_ref().unwrap_or(&f.sig.name)
+}
+
+// Check the link names of the external functions in a set of modules. Externals are not namespaced,
+// so the same name must always refer to the same symbol, and no two externals may share a symbol.
+pub fn check_external_functions<'a, I>(modules: I) -> CompileResult<()>
+    where I: Iterator<Item=&'a Module>
+{
+    let mut externals: Vec<&ExternalFunction> = modules.flat_map(|m| m.externals.values()).collect();
+    // Sorted, so the error for a conflict is always reported on the same one of the two
+    externals.sort_by(|a, b| (&a.span.file, a.span.start).cmp(&(&b.span.file, b.span.start)));
+
+    let mut names: HashMap<&str, &ExternalFunction> = HashMap::new();
+    let mut symbols: HashMap<&str, &ExternalFunction> = HashMap::new();
+    let mut errors = Vec::new();
+    for f in externals {
+        if let Some(ref link_name) = f.sig.link_name {
+            if !is_c_identifier(link_name) {
+                return ErrorCode::InvalidCInterface.result(&f.sig.span, format!("{} is not a valid C symbol name, it cannot be the link_name of {}", link_name, f.sig.name));
+            }
+        }
+
+        let symbol = external_symbol(f);
+        if let Some(prev) = names.insert(&f.sig.name, f) {
+            if external_symbol(prev) != symbol {
+                errors.push(ErrorCode::InvalidCInterface.error(&f.sig.span,
+                    format!("External function {} is linked as {} ({}) and as {} ({})", f.sig.name, external_symbol(prev), prev.sig.span, symbol, f.sig.span)));
+            }
+            continue;
+        }
+
+        if let Some(prev) = symbols.insert(symbol, f) {
+            errors.push(ErrorCode::InvalidCInterface.error(&f.sig.span,
+                format!("Symbol {} is linked by both {} ({}) and {} ({})", symbol, prev.sig.name, prev.sig.span, f.sig.name, f.sig.span)));
+        }
+    }
+
+    match errors.len()
+    {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(CompileError::Many(errors)),
+    }
+}
@@ -0,0 +1,139 @@
+// Type checker fixtures: a source file which is type checked as a test build, with `#~ ERROR <text>` at the end
+// of each line which must give an error containing <text>. Errors without such a marker fail the fixture.
+use std::fs;
+use std::path::Path;
+use ast::IntSize;
+use compileerror::{CompileError, CompileResult};
+use errorcodes::ErrorCode;
+use parser::parse_str;
+use target::{Target, TEST_FEATURE};
+use timer::PassTimings;
+use super::entrypoint::check_main_function;
+
+const ERROR_MARKER: &str = "#~ ERROR ";
+
+struct Diagnostic
+{
+    line: Option<usize>, // None for errors without a span in the fixture
+    msg: String,
+    code: Option<ErrorCode>,
+}
+
+// Line numbers start at 1, like in spans
+fn expected_errors(source: &str) -> Vec<(usize, String)>
+{
+    source.lines()
+        .enumerate()
+        .filter_map(|(idx, line)| line.find(ERROR_MARKER).map(|pos| (idx + 1, line[pos + ERROR_MARKER.len()..].trim().to_string())))
+        .collect()
+}
+
+fn diagnostics(err: &CompileError, out: &mut Vec<Diagnostic>)
+{
+    match *err
+    {
+        CompileError::Parse(ref ed) |
+        CompileError::Type(ref ed) |
+        CompileError::UnknownName(ref ed) if ed.span.file.is_empty() => {
+            out.push(Diagnostic{line: Some(ed.span.start.line), msg: ed.msg.clone(), code: ed.code})
+        },
+        CompileError::Many(ref errors) => {
+            for e in errors {
+                diagnostics(e, out);
+            }
+        },
+        _ => out.push(Diagnostic{line: None, msg: err.to_string().trim_end().to_string(), code: None}),
+    }
+}
+
+fn type_check_fixture(source: &str) -> CompileResult<()>
+{
+    let mut target = Target::new(IntSize::I32, "");
+    target.features.insert(TEST_FEATURE.into());
+    let mut pkg = parse_str(source, "test", &target)?;
+    pkg.type_check(&target, &mut PassTimings::new())?;
+
+    // A fixture with a main function is checked like an executable
+    match pkg.modules.get("test") {
+        Some(module) if module.functions.contains_key("test::main") => check_main_function(module, "test::main", &target),
+        _ => Ok(()),
+    }
+}
+
+// The codes of the errors a source file gives, for checking the examples of the error code explanations
+pub fn fixture_error_codes(source: &str) -> Vec<ErrorCode>
+{
+    let mut actual = Vec::new();
+    if let Err(e) = type_check_fixture(source) {
+        diagnostics(&e, &mut actual);
+    }
+    actual.iter().filter_map(|d| d.code).collect()
+}
+
+// Returns every expected error which did not show up and every error which was not expected
+pub fn check_fixture(name: &str, source: &str) -> Result<(), String>
+{
+    let mut actual = Vec::new();
+    if let Err(e) = type_check_fixture(source) {
+        diagnostics(&e, &mut actual);
+    }
+
+    let mut problems = Vec::new();
+    for (line, text) in expected_errors(source)
+    {
+        if let Some(idx) = actual.iter().position(|d| d.line == Some(line) && d.msg.contains(&text)) {
+            actual.remove(idx);
+            continue;
+        }
+
+        // A stale annotation, the error is still there but on another line
+        match actual.iter().position(|d| d.msg.contains(&text)) {
+            Some(idx) => {
+                let moved = actual.remove(idx).line.map(|l| l.to_string()).unwrap_or_else(|| "?".into());
+                problems.push(format!("{}:{}: expected an error containing `{}`, but it is reported on line {}, move the annotation",
+                    name, line, text, moved));
+            },
+            None => problems.push(format!("{}:{}: expected an error containing `{}`, but there is none", name, line, text)),
+        }
+    }
+
+    for d in actual
+    {
+        match d.line {
+            Some(line) => problems.push(format!("{}:{}: unexpected error: {}", name, line, d.msg)),
+            None => problems.push(format!("{}: unexpected error: {}", name, d.msg)),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+// Checks all .mhr files in dir, and returns the problems of all fixtures which fail
+pub fn check_fixture_dir(dir: &str) -> Result<(), String>
+{
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read {}: {}", dir, e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map(|ext| ext == "mhr").unwrap_or(false))
+        .collect();
+    paths.sort();
+
+    let failures: Vec<String> = paths.iter()
+        .filter_map(|path| check_fixture_file(path).err())
+        .collect();
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join("\n"))
+    }
+}
+
+pub fn check_fixture_file(path: &Path) -> Result<(), String>
+{
+    let source = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    check_fixture(&path.to_string_lossy(), &source)
+}
@@ -0,0 +1,84 @@
+use ast::{Expression, BinaryOp, BinaryOperator, Literal, Module, Type, Visitor, walk_expression};
+use compileerror::{CompileResult, print_message};
+use span::Span;
+use target::Target;
+
+// Collects the == and != comparisons between two floats, which almost never do what was intended because of rounding errors
+struct FloatEqualityFinder<'a>
+{
+    target: &'a Target,
+    spans: Vec<Span>,
+}
+
+fn is_zero_literal(e: &Expression) -> bool
+{
+    match *e
+    {
+        Expression::Literal(Literal::Float(_, ref v, _)) => v.parse::<f64>().map(|v| v == 0.0).unwrap_or(false),
+        _ => false,
+    }
+}
+
+// x != x is the way to check for NaN
+fn is_nan_check(b: &BinaryOp) -> bool
+{
+    match (&b.left, &b.right)
+    {
+        (&Expression::NameRef(ref l), &Expression::NameRef(ref r)) => l.name == r.name,
+        _ => false,
+    }
+}
+
+fn is_float_equality(b: &BinaryOp, target: &Target) -> bool
+{
+    (b.operator == BinaryOperator::Equals || b.operator == BinaryOperator::NotEquals) &&
+        b.left.get_type(target.int_size).is_float() &&
+        b.right.get_type(target.int_size).is_float() &&
+        !is_zero_literal(&b.left) &&
+        !is_zero_literal(&b.right) &&
+        !is_nan_check(b)
+}
+
+impl<'a> Visitor for FloatEqualityFinder<'a>
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
+        match *e
+        {
+            Expression::BinaryOp(ref b) if is_float_equality(b, self.target) => self.spans.push(b.span.clone()),
+
+            // An ascription to bool, (a == b : bool), states that the exact comparison is intended
+            Expression::TypeAscription(ref t) if t.typ == Type::Bool => {
+                if let Expression::BinaryOp(ref b) = t.inner {
+                    self.visit_expression(&b.left)?;
+                    return self.visit_expression(&b.right);
+                }
+            },
+
+            _ => (),
+        }
+
+        walk_expression(self, e)
+    }
+}
+
+pub fn float_equality_comparisons(e: &Expression, target: &Target) -> Vec<Span>
+{
+    let mut finder = FloatEqualityFinder{target: target, spans: Vec::new()};
+    let _ = finder.visit_expression(e);
+    finder.spans
+}
+
+pub fn warn_about_float_equality(module: &Module, target: &Target)
+{
+    // Instances of the same generic function share the spans of its body, so each comparison is only reported once
+    let mut spans: Vec<Span> = module.functions.values()
+        .flat_map(|f| float_equality_comparisons(&f.expression, target))
+        .collect();
+    spans.sort_by(|a, b| (&a.file, a.start).cmp(&(&b.file, b.start)));
+    spans.dedup();
+
+    for span in spans {
+        print_message("Warning: comparing floats with == or != is unreliable because of rounding errors, use approx_eq(a, b, epsilon) instead", &span);
+    }
+}
@@ -1,5 +1,6 @@
 use ast::*;
-use compileerror::{CompileResult, type_error_result};
+use compileerror::CompileResult;
+use errorcodes::ErrorCode;
 use span::Span;
 use super::typecheckercontext::TypeCheckerContext;
 use super::instantiate::make_concrete;
@@ -8,7 +9,7 @@ pub fn add(mapping: &mut GenericMapping, from: &Type, to: &Type, span: &Span) ->
 {
     if let Some(prev_arg_type) = mapping.insert(from.clone(), to.clone()) {
         if prev_arg_type != *to {
-            return type_error_result(span, format!("Generic argument {} mismatch, expecting type {}, not {}", from, prev_arg_type, to));
+            return ErrorCode::CannotInferType.result(span, format!("Generic argument {} mismatch, expecting type {}, not {}", from, prev_arg_type, to));
         }
     }
 
@@ -27,7 +28,7 @@ pub fn fill_in_generics(ctx: &TypeCheckerContext, actual: &Type, generic: &Type,
     }
 
     let map_err = || {
-        type_error_result(span, format!("Cannot map argument type {} on type {}", actual, new_generic))
+        ErrorCode::CannotInferType.result(span, format!("Cannot map argument type {} on type {}", actual, new_generic))
     };
 
     match (&new_generic, actual)
@@ -90,7 +91,7 @@ pub fn fill_in_generics(ctx: &TypeCheckerContext, actual: &Type, generic: &Type,
                 new_members.push(struct_member(&aa.name, nt));
             }
 
-            Ok(struct_type(&actual_st.name, new_members))
+            Ok(struct_type_with_members(actual_st, new_members))
         },
 
         (&Type::Sum(ref generic_st), &Type::Sum(ref actual_st)) => {
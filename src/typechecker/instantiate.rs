@@ -41,7 +41,8 @@ fn matches_function_signature(expected: &Type, actual: &Type, concrete_type: &Ty
     }
 }
 
-fn satisfies_interface(ctx: &TypeCheckerContext, concrete_type: &Type, interface: &Type) -> Result<(), String>
+// Returns the names of the functions implementing the interface, in the order of the interface's functions
+pub fn satisfies_interface(ctx: &TypeCheckerContext, concrete_type: &Type, interface: &Type) -> Result<Vec<String>, String>
 {
     let it = if let Type::Interface(ref it) = *interface {
         it
@@ -50,14 +51,16 @@ fn satisfies_interface(ctx: &TypeCheckerContext, concrete_type: &Type, interface
     };
 
     let concrete_type_name = concrete_type.name();
+    let mut methods = Vec::with_capacity(it.functions.len());
     for func in &it.functions {
         let r = ctx.resolve(&format!("{}.{}", concrete_type_name, func.name))
             .ok_or_else(|| format!("No method {} found on type {}", func.name, concrete_type_name))?;
 
         matches_function_signature(&func.typ, &r.typ, concrete_type, interface, &func.name)?;
+        methods.push(r.name);
     }
 
-    Ok(())
+    Ok(methods)
 }
 
 fn check_interface_constraints(ctx: &TypeCheckerContext, generic: &Type, concrete: &Type) -> Result<Type, String>
@@ -122,7 +125,7 @@ fn make_concrete_type(ctx: &TypeCheckerContext, mapping: &GenericMapping, generi
                 members.push(struct_member(&m.name, make_concrete_type(ctx, mapping, &m.typ)?));
             }
 
-            struct_type(&st.name, members)
+            struct_type_with_members(st, members)
         },
 
         Type::Sum(ref st) => {
@@ -173,6 +176,15 @@ fn substitute_bindings(ctx: &TypeCheckerContext, generic_args: &GenericMapping,
                     b.span.clone()
                 )
             },
+
+            BindingType::Array(ref a) => {
+                binding(
+                    BindingType::Array(a.clone()),
+                    binding_expr,
+                    b.mutable,
+                    b.span.clone()
+                )
+            },
         };
         bindings.push(new_binding);
     }
@@ -219,10 +231,26 @@ fn substitute_pattern(ctx: &TypeCheckerContext, generic_args: &GenericMapping, p
             Ok(Pattern::Name(new_nr))
         },
 
+        Pattern::Binding(ref b) => {
+            Ok(Pattern::Binding(BindingPattern{
+                name: b.name.clone(),
+                typ: make_concrete(ctx, generic_args, &b.typ, &b.span)?,
+                span: b.span.clone(),
+            }))
+        },
+
         Pattern::Literal(Literal::Array(ref al)) => {
             substitute_array_literal(ctx, generic_args, al).map(Pattern::Literal)
         },
 
+        Pattern::Or(ref alternatives) => {
+            let mut new_alternatives = Vec::with_capacity(alternatives.len());
+            for a in alternatives {
+                new_alternatives.push(substitute_pattern(ctx, generic_args, a)?);
+            }
+            Ok(Pattern::Or(new_alternatives))
+        },
+
         _ => Ok(p.clone()),
     }
 }
@@ -243,7 +271,15 @@ fn substitute_call(ctx: &TypeCheckerContext, generic_args: &GenericMapping, c: &
         new_args.push(substitute_expr(ctx, generic_args, a)?);
     }
 
-    Ok(Call::new(c.callee.clone(), new_args, c.span.clone()))
+    let mut new_c = Call::new(c.callee.clone(), new_args, c.span.clone());
+    for na in &c.named_args {
+        new_c.named_args.push(NamedArgument{
+            name: na.name.clone(),
+            value: substitute_expr(ctx, generic_args, &na.value)?,
+            span: na.span.clone(),
+        });
+    }
+    Ok(new_c)
 }
 
 fn substitute_name_ref(ctx: &TypeCheckerContext, generic_args: &GenericMapping, nr: &NameRef) -> CompileResult<NameRef>
@@ -313,7 +349,13 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
                 )
             }
             let expr = substitute_expr(ctx, generic_args, &l.expr)?;
-            Ok(lambda(args, expr, l.span.clone()))
+            match l.name {
+                Some(ref name) => {
+                    let ret = make_concrete(ctx, generic_args, &l.sig.return_type, &l.span)?;
+                    Ok(local_function(name, ret, args, expr, l.span.clone()))
+                },
+                None => Ok(lambda(args, expr, l.span.clone())),
+            }
         },
 
         Expression::Match(ref m) => {
@@ -328,18 +370,41 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
             Ok(match_expression(target, cases, m.span.clone()))
         },
 
+        Expression::Is(ref i) => {
+            let inner = substitute_expr(ctx, generic_args, &i.inner)?;
+            let pattern = substitute_pattern(ctx, generic_args, &i.pattern)?;
+            Ok(is_expression(inner, pattern, i.span.clone()))
+        },
+
         Expression::Bindings(ref l) => {
             let nb = substitute_bindings(ctx, generic_args, &l.bindings)?;
             Ok(bindings(nb, l.span.clone()))
         },
 
         Expression::If(ref i) => {
-            Ok(if_expression(
-                substitute_expr(ctx, generic_args, &i.condition)?,
-                substitute_expr(ctx, generic_args, &i.on_true)?,
-                substitute_expr(ctx, generic_args, &i.on_true)?,
-                i.span.clone(),
-            ))
+            let condition = substitute_expr(ctx, generic_args, &i.condition)?;
+            let on_true = substitute_expr(ctx, generic_args, &i.on_true)?;
+            match i.on_false {
+                Some(ref on_false) => {
+                    let on_false = substitute_expr(ctx, generic_args, on_false)?;
+                    Ok(if_expression(condition, on_true, on_false, i.span.clone()))
+                },
+                None => Ok(single_if_expression(condition, on_true, i.span.clone())),
+            }
+        },
+
+        Expression::IfLet(ref i) => {
+            let mut nb = Vec::with_capacity(i.bindings.len());
+            for b in &i.bindings {
+                let init = substitute_expr(ctx, generic_args, &b.init)?;
+                nb.push(if_let_binding(b.name.clone(), init, b.span.clone()));
+            }
+            let on_true = substitute_expr(ctx, generic_args, &i.on_true)?;
+            let on_false = match i.on_false {
+                Some(ref on_false) => Some(substitute_expr(ctx, generic_args, on_false)?),
+                None => None,
+            };
+            Ok(if_let_expression(nb, on_true, on_false, i.span.clone()))
         },
 
         Expression::Block(ref b) => {
@@ -450,7 +515,12 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
 
         Expression::ToOptional(ref t) => {
             let inner = substitute_expr(ctx, generic_args, &t.inner)?;
-            Ok(to_optional(inner, t.optional_type.clone()))
+            Ok(to_optional(inner, make_concrete(ctx, generic_args, &t.optional_type, &t.inner.span())?))
+        },
+
+        Expression::ToInterface(ref t) => {
+            let inner = substitute_expr(ctx, generic_args, &t.inner)?;
+            Ok(to_interface(inner, t.interface_type.clone()))
         },
 
         Expression::Cast(ref t) => {
@@ -458,6 +528,11 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
             Ok(type_cast(inner, make_concrete(ctx, generic_args, &t.destination_type, &t.span)?, t.span.clone()))
         },
 
+        Expression::TypeAscription(ref t) => {
+            let inner = substitute_expr(ctx, generic_args, &t.inner)?;
+            Ok(type_ascription(inner, make_concrete(ctx, generic_args, &t.typ, &t.span)?, t.span.clone()))
+        },
+
         Expression::Void => Ok(Expression::Void),
 
         Expression::CompilerCall(CompilerCall::SizeOf(ref t, ref span)) => {
@@ -465,6 +540,11 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
             Ok(Expression::CompilerCall(CompilerCall::SizeOf(new_t, span.clone())))
         }
 
+        Expression::CompilerCall(CompilerCall::AlignOf(ref t, ref span)) => {
+            let new_t = make_concrete(ctx, generic_args, t, span)?;
+            Ok(Expression::CompilerCall(CompilerCall::AlignOf(new_t, span.clone())))
+        }
+
         Expression::CompilerCall(CompilerCall::Slice{ref data, ref len, ref typ, ref span}) => {
             let new_data = substitute_expr(ctx, generic_args, data)?;
             let new_len = substitute_expr(ctx, generic_args, len)?;
@@ -477,6 +557,29 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
             }))
         },
 
+        Expression::CompilerCall(CompilerCall::TypeName(ref e, ref span)) => {
+            let new_e = substitute_expr(ctx, generic_args, e)?;
+            Ok(Expression::CompilerCall(CompilerCall::TypeName(Box::new(new_e), span.clone())))
+        },
+
+        Expression::CompilerCall(CompilerCall::StaticAssert{ref cond, ref message, ref span}) => {
+            let new_cond = substitute_expr(ctx, generic_args, cond)?;
+            Ok(Expression::CompilerCall(CompilerCall::StaticAssert{
+                cond: Box::new(new_cond),
+                message: message.clone(),
+                span: span.clone(),
+            }))
+        },
+
+        Expression::CompilerCall(CompilerCall::AssertType{ref expr, ref typ, ref span}) => {
+            let new_expr = substitute_expr(ctx, generic_args, expr)?;
+            Ok(Expression::CompilerCall(CompilerCall::AssertType{
+                expr: Box::new(new_expr),
+                typ: typ.clone(),
+                span: span.clone(),
+            }))
+        },
+
         Expression::IndexOperation(ref iop) => {
             let target = substitute_expr(ctx, generic_args, &iop.target)?;
             let index_expr = substitute_expr(ctx, generic_args, &iop.index_expr)?;
@@ -487,6 +590,16 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
             let e = substitute_expr(ctx, generic_args, &r.expression)?;
             Ok(return_expr(e, r.span.clone()))
         }
+
+        Expression::Propagate(ref p) => {
+            let inner = substitute_expr(ctx, generic_args, &p.inner)?;
+            Ok(propagate(inner, p.span.clone()))
+        }
+
+        Expression::Discard(ref d) => {
+            let inner = substitute_expr(ctx, generic_args, &d.inner)?;
+            Ok(discard(inner, d.span.clone()))
+        }
     }
 }
 
@@ -507,8 +620,14 @@ pub fn instantiate(ctx: &TypeCheckerContext, func: &Function, generic_args: &Gen
         args: args,
         span: func.sig.span.clone(),
         typ: func_type(arg_types, return_type),
+        variadic: func.sig.variadic,
+        inline: func.sig.inline,
+        export: None,
+        link_name: None,
     };
 
     let body = substitute_expr(ctx, generic_args, &func.expression)?;
-    Ok(Function::new(sig, func.public, body, func.span.clone()))
+    let mut instance = Function::new(sig, func.public, body, func.span.clone());
+    instance.doc = func.doc.clone();
+    Ok(instance)
 }
\ No newline at end of file
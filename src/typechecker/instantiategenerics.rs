@@ -1,10 +1,13 @@
 use std::collections::HashMap;
+use std::mem;
 use ast::*;
 use target::Target;
 use super::instantiate::instantiate;
 use super::typecheck::type_check_function;
 use super::typecheckercontext::{TypeCheckerContext, ImportSymbolResolver};
-use compileerror::{CompileResult, unknown_name_result};
+use super::typeresolver::check_signature_void_types;
+use compileerror::{CompileResult, CompileError, type_error};
+use errorcodes::ErrorCode;
 
 
 type FunctionMap = HashMap<String, Function>;
@@ -15,78 +18,132 @@ fn do_instantiation(
     func: &Function,
     module: &Module,
     call: &Call,
+    caller: &str,
     target: &Target) -> CompileResult<()>
 {
     let name = new_func_name(&func.sig.name, &call.generic_args);
     if !new_functions.contains_key(&name) && !module.functions.contains_key(&name) {
         let mut new_func = instantiate(ctx, func, &call.generic_args)?;
-        type_check_function(ctx, &mut new_func, target)?;
+        check_signature_void_types(&new_func.sig)
+            .and_then(|_| type_check_function(ctx, &mut new_func, target))
+            .map_err(|e| instantiation_error(e, &name, call, caller))?;
         new_functions.insert(name, new_func);
     }
 
     Ok(())
 }
 
-fn resolve_generic_call(ctx: &mut TypeCheckerContext, new_functions: &mut FunctionMap, imports: &ImportMap, module: &Module, call: &Call, target: &Target) -> CompileResult<()>
+// Errors in an instance are reported together with the call which instantiated it, and the function containing that call,
+// which is itself an instance when the instantiation is nested
+fn instantiation_error(e: CompileError, name: &str, call: &Call, caller: &str) -> CompileError
+{
+    let note = type_error(&call.span, format!("{} is instantiated here, in {}", name, caller));
+    match e
+    {
+        CompileError::Many(mut errors) => {
+            errors.push(note);
+            CompileError::Many(errors)
+        },
+        e => CompileError::Many(vec![e, note]),
+    }
+}
+
+fn resolve_generic_call(ctx: &mut TypeCheckerContext, new_functions: &mut FunctionMap, imports: &ImportMap, module: &Module, call: &Call, caller: &str, target: &Target) -> CompileResult<()>
 {
     if let Some(func) = module.functions.get(&call.callee.name) {
-        return do_instantiation(ctx, new_functions, func, module, call, target)
+        return do_instantiation(ctx, new_functions, func, module, call, caller, target)
     }
 
     for import in imports.values() {
         if let Some(func) = import.generics.get(&call.callee.name) {
             let mut ctx = TypeCheckerContext::new(ImportSymbolResolver::ExternalImport(import));
-            return do_instantiation(&mut ctx, new_functions, func, module, call, target);
+            return do_instantiation(&mut ctx, new_functions, func, module, call, caller, target);
         }
     }
 
-    unknown_name_result(&call.span, format!("Unknown function {}", call.callee.name))
+    ErrorCode::UnknownName.result(&call.span, format!("Unknown function {}", call.callee.name))
 }
 
-fn resolve_generics(ctx: &mut TypeCheckerContext, new_functions: &mut FunctionMap, imports: &ImportMap, module: &Module, e: &Expression, target: &Target) -> CompileResult<()>
+struct GenericCallResolver<'a, 'b: 'a>
 {
-    let mut rg = |e: &Expression| {
+    ctx: &'a mut TypeCheckerContext<'b>,
+    new_functions: &'a mut FunctionMap,
+    graph: &'a mut InstantiationGraph,
+    imports: &'a ImportMap,
+    module: &'a Module,
+    caller: &'a str,
+    target: &'a Target,
+}
+
+impl<'a, 'b> Visitor for GenericCallResolver<'a, 'b>
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
         if let Expression::Call(ref c) = *e {
             if !c.generic_args.is_empty() {
-                resolve_generic_call(ctx, new_functions, imports, module, c, target)?;
+                let name = new_func_name(&c.callee.name, &c.generic_args);
+                self.graph.add(self.caller, &name, &c.callee.name, &c.span)?;
+                resolve_generic_call(self.ctx, self.new_functions, self.imports, self.module, c, self.caller, self.target)?;
             }
         }
-        Ok(())
-    };
-    e.visit(&mut rg)
+
+        walk_expression(self, e)
+    }
 }
 
-fn replace_generic_calls(e: &mut Expression) -> CompileResult<()>
+struct GenericCallReplacer;
+
+impl MutVisitor for GenericCallReplacer
 {
-    let mut replace_calls = |e: &mut Expression| {
-        if let Expression::Call(ref mut call) =  *e {
+    fn visit_expression_mut(&mut self, e: &mut Expression) -> CompileResult<()>
+    {
+        if let Expression::Call(ref mut call) = *e {
             if !call.generic_args.is_empty() {
                 call.callee.name = new_func_name(&call.callee.name, &call.generic_args);
             }
         }
-        Ok(())
-    };
 
-    e.visit_mut(&mut replace_calls)
+        walk_expression_mut(self, e)
+    }
 }
 
-/*
-    Instantiate all generics
-*/
-pub fn instantiate_generics(module: &mut Module, ctx: &mut TypeCheckerContext, imports: &ImportMap, target: &Target) -> CompileResult<()>
+fn resolve_generic_calls(module: &Module, graph: &mut InstantiationGraph, ctx: &mut TypeCheckerContext, imports: &ImportMap, target: &Target) -> CompileResult<FunctionMap>
 {
     let mut new_functions = FunctionMap::new();
     for f in module.functions.values()
     {
         if !f.generics_resolved && !f.is_generic() {
-            resolve_generics(ctx, &mut new_functions, imports, module, &f.expression, target)?;
+            let mut resolver = GenericCallResolver{
+                ctx,
+                new_functions: &mut new_functions,
+                graph,
+                imports,
+                module,
+                caller: &f.sig.name,
+                target,
+            };
+            resolver.visit_expression(&f.expression)?;
         }
     }
 
+    Ok(new_functions)
+}
+
+/*
+    Instantiate all generics
+*/
+pub fn instantiate_generics(module: &mut Module, ctx: &mut TypeCheckerContext, imports: &ImportMap, target: &Target) -> CompileResult<()>
+{
+    // The functions of the module are borrowed while resolving the calls, so the graph is taken out of the module
+    let mut graph = mem::replace(&mut module.instantiations, InstantiationGraph::default());
+    let new_functions = resolve_generic_calls(module, &mut graph, ctx, imports, target);
+    module.instantiations = graph;
+    let new_functions = new_functions?;
+
     for f in module.functions.values_mut()
     {
         if !f.generics_resolved && !f.is_generic() {
-            replace_generic_calls(&mut f.expression)?;
+            GenericCallReplacer.visit_expression_mut(&mut f.expression)?;
             f.generics_resolved = true;
         }
     }
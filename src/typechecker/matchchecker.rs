@@ -1,15 +1,16 @@
 use std::collections::HashSet;
 use std::ops::Deref;
-use ast::{Type, SumTypeCaseIndexOf, MatchExpression, Pattern, Literal};
+use ast::{Type, SumTypeCaseIndexOf, MatchExpression, MatchCase, Pattern, Literal, literal_value};
 use compileerror::*;
+use errorcodes::ErrorCode;
 
 fn check_any_match(m: &MatchExpression) -> CompileResult<bool>
 {
     let mut any_match_seen = false;
     for (idx, c) in m.cases.iter().enumerate() {
-        if let Pattern::Any(ref span) = c.pattern {
+        if c.pattern.is_catch_all() {
             if idx != m.cases.len() - 1 {
-                return type_error_result(span, "A pattern match with _ must always be the last one in a match statement");
+                return ErrorCode::DuplicatePattern.result(&c.pattern.span(), "A pattern match with _ or a binding must always be the last one in a match statement");
             } else {
                 any_match_seen = true;
             }
@@ -24,29 +25,31 @@ fn check_array_match_is_exhaustive(m: &MatchExpression, any_match_seen: bool) ->
     let mut head_tail_seen = false;
 
     for c in &m.cases {
-        match c.pattern {
-            Pattern::EmptyArray(_) => {
-                if empty_array_seen {
-                    return type_error_result(&c.span, "Duplicate pattern match, pattern match for [] already exists");
-                } else {
-                    empty_array_seen = true;
-                }
-            },
-            Pattern::Array(_) => {
-                if head_tail_seen {
-                    return type_error_result(&c.span, "Duplicate pattern match, pattern match already exists");
-                } else {
-                    head_tail_seen = true;
-                }
-            },
-            _ => (),
+        for p in c.pattern.alternatives() {
+            match *p {
+                Pattern::EmptyArray(_) => {
+                    if empty_array_seen {
+                        return ErrorCode::DuplicatePattern.result(&c.span, "Duplicate pattern match, pattern match for [] already exists");
+                    } else {
+                        empty_array_seen = true;
+                    }
+                },
+                Pattern::Array(_) => {
+                    if head_tail_seen {
+                        return ErrorCode::DuplicatePattern.result(&c.span, "Duplicate pattern match, pattern match already exists");
+                    } else {
+                        head_tail_seen = true;
+                    }
+                },
+                _ => (),
+            }
         }
     }
 
     if any_match_seen || (empty_array_seen && head_tail_seen) {
         Ok(())
     } else {
-        type_error_result(&m.span, "Incomplete pattern match")
+        ErrorCode::IncompleteMatch.result(&m.span, "Incomplete pattern match")
     }
 }
 
@@ -57,7 +60,7 @@ fn check_sum_match_is_exhaustive<ST: SumTypeCaseIndexOf>(m: &MatchExpression, st
     let add_to_indices = |idx: Option<usize>, name: &str, indexes: &mut HashSet<usize>| {
         let idx = idx.expect("Internal Compiler Error: cannot determine index of sum type case");
         if indexes.contains(&idx) {
-            type_error_result(&m.span, format!("Duplicate pattern match for {}", name))
+            ErrorCode::DuplicatePattern.result(&m.span, format!("Duplicate pattern match for {}", name))
         } else {
             indexes.insert(idx);
             Ok(())
@@ -65,47 +68,52 @@ fn check_sum_match_is_exhaustive<ST: SumTypeCaseIndexOf>(m: &MatchExpression, st
     };
 
     for c in &m.cases {
-        match c.pattern
-        {
-            Pattern::Name(ref nr) => {
-                add_to_indices(st.index_of(&nr.name), &nr.name, &mut indexes)?;
-            },
-            Pattern::Struct(ref s) => {
-                add_to_indices(st.index_of(&s.name), &s.name, &mut indexes)?;
-            },
-            _ => (),
+        for p in c.pattern.alternatives() {
+            match *p
+            {
+                Pattern::Name(ref nr) => {
+                    add_to_indices(st.index_of(&nr.name), &nr.name, &mut indexes)?;
+                },
+                Pattern::Struct(ref s) => {
+                    add_to_indices(st.index_of(&s.name), &s.name, &mut indexes)?;
+                },
+                _ => (),
+            }
         }
     }
 
     if !any_match_seen && indexes.len() != st.num_cases() {
-        return type_error_result(&m.span, "Incomplete pattern match, not all cases are handled");
+        return ErrorCode::IncompleteMatch.result(&m.span, "Incomplete pattern match, not all cases are handled");
     }
     Ok(())
 }
 
-fn check_bool_match_is_exhaustive(m: &MatchExpression) -> CompileResult<()>
+// true and false together are complete, so no _ is needed then
+fn check_bool_match_is_exhaustive(m: &MatchExpression, any_match_seen: bool) -> CompileResult<()>
 {
     let mut true_seen = false;
     let mut false_seen = false;
     for c in &m.cases {
-        if let Pattern::Literal(Literal::Bool(_, v)) = c.pattern
-        {
-            if v {
-                if true_seen {
-                    return type_error_result(&c.span, "Duplicate pattern match, pattern match for true already exists");
+        for p in c.pattern.alternatives() {
+            if let Pattern::Literal(Literal::Bool(_, v)) = *p
+            {
+                if v {
+                    if true_seen {
+                        return ErrorCode::DuplicatePattern.result(&c.span, "Duplicate pattern match, pattern match for true already exists");
+                    } else {
+                        true_seen = true;
+                    }
+                } else if false_seen {
+                    return ErrorCode::DuplicatePattern.result(&c.span, "Duplicate pattern match, pattern match for false already exists");
                 } else {
-                    true_seen = true;
+                    false_seen = true;
                 }
-            } else if false_seen {
-                return type_error_result(&c.span, "Duplicate pattern match, pattern match for false already exists");
-            } else {
-                false_seen = true;
             }
         }
     }
 
-    if !true_seen || !false_seen {
-        type_error_result(&m.span, "Incomplete pattern match, not all boolean values are matched against")
+    if !any_match_seen && (!true_seen || !false_seen) {
+        ErrorCode::IncompleteMatch.result(&m.span, "Incomplete pattern match, not all boolean values are matched against")
     } else {
         Ok(())
     }
@@ -116,29 +124,84 @@ fn check_optional_match_is_exhaustive(m: &MatchExpression) -> CompileResult<()>
     let mut optional_seen = false;
     let mut nil_seen = false;
     for c in &m.cases {
-        match c.pattern
-        {
-            Pattern::Optional(_) => optional_seen = true,
-            Pattern::Nil(_) => nil_seen = true,
-            Pattern::Any(_) => return Ok(()),
-            _ => (),
+        for p in c.pattern.alternatives() {
+            match *p
+            {
+                Pattern::Optional(_) => optional_seen = true,
+                Pattern::Nil(_) => nil_seen = true,
+                Pattern::Any(_) | Pattern::Binding(_) => return Ok(()),
+                _ => (),
+            }
         }
     }
 
     if !optional_seen || !nil_seen {
-        type_error_result(&m.span, "Incomplete pattern match, not all possible optionals are matched again")
+        ErrorCode::IncompleteMatch.result(&m.span, "Incomplete pattern match, not all possible optionals are matched again")
     } else {
         Ok(())
     }
 }
 
+// The integer or char values matched by a literal or range pattern, as an inclusive range
+fn matched_values(p: &Pattern) -> Option<(i128, i128)>
+{
+    match *p
+    {
+        Pattern::Literal(ref lit) => literal_value(lit).map(|v| (v, v)),
+        Pattern::Range(ref r) => r.bounds(),
+        _ => None,
+    }
+}
+
+fn is_covered(covered: &[(i128, i128)], first: i128, last: i128) -> bool
+{
+    let mut sorted = covered.to_vec();
+    sorted.sort();
+    let mut next = first; // The first value which is not known to be covered
+    for (start, end) in sorted
+    {
+        if start > next {
+            break;
+        }
+
+        if end >= next {
+            next = end + 1;
+        }
+    }
+
+    next > last
+}
+
+// Literal and range cases which can never match, because the cases before them match all their values
+pub fn unreachable_cases(m: &MatchExpression) -> Vec<&MatchCase>
+{
+    let mut covered = Vec::new();
+    let mut unreachable = Vec::new();
+    for c in &m.cases
+    {
+        let values: Option<Vec<(i128, i128)>> = c.pattern.alternatives().iter().map(matched_values).collect();
+        if let Some(values) = values {
+            if values.iter().all(|&(first, last)| is_covered(&covered, first, last)) {
+                unreachable.push(c);
+            } else {
+                covered.extend(values);
+            }
+        }
+    }
+
+    unreachable
+}
+
 pub fn check_match_is_exhaustive(m: &MatchExpression, target_type: &Type) -> CompileResult<()>
 {
     let any_match_seen = check_any_match(m)?;
+    for c in unreachable_cases(m) {
+        print_message("Warning: unreachable pattern, all its values are matched by earlier patterns", &c.pattern.span());
+    }
 
     match *target_type
     {
-        Type::Array(_) | Type::Slice(_) => {
+        Type::String | Type::Array(_) | Type::Slice(_) => {
             check_array_match_is_exhaustive(m, any_match_seen)
         },
 
@@ -151,15 +214,19 @@ pub fn check_match_is_exhaustive(m: &MatchExpression, target_type: &Type) -> Com
         },
 
         Type::Struct(_) => {
-            if !m.cases.is_empty() {
-                type_error_result(&m.span, "Duplicate pattern match, structs can only have one pattern match")
+            if m.cases.iter().map(|c| c.pattern.alternatives().len()).sum::<usize>() > 1 {
+                ErrorCode::DuplicatePattern.result(&m.span, "Duplicate pattern match, structs can only have one pattern match")
             } else {
                 Ok(())
             }
         },
 
         Type::Bool => {
-            check_bool_match_is_exhaustive(m)
+            check_bool_match_is_exhaustive(m, any_match_seen)
+        },
+
+        Type::Char if !any_match_seen => {
+            ErrorCode::IncompleteMatch.result(&m.span, "Incomplete pattern match, a match on a char needs a _ or a binding as last case")
         },
 
         Type::Optional(_) => {
@@ -168,7 +235,7 @@ pub fn check_match_is_exhaustive(m: &MatchExpression, target_type: &Type) -> Com
 
         _ => {
             if !any_match_seen {
-                type_error_result(&m.span, format!("Incomplete pattern match for type {}", target_type))
+                ErrorCode::IncompleteMatch.result(&m.span, format!("Incomplete pattern match for type {}", target_type))
             } else {
                 Ok(())
             }
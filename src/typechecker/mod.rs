@@ -4,8 +4,22 @@ mod instantiate;
 mod instantiategenerics;
 mod genericmapper;
 mod matchchecker;
+mod floatequality;
+mod unreachable;
+mod objectsize;
+mod exports;
+mod constantfolding;
+mod suggestions;
 mod typeresolver;
+mod compositioncycles;
+mod entrypoint;
+mod typemap;
+#[cfg(test)]
+mod fixtures;
 #[cfg(test)]
 mod tests;
 
 pub use self::typecheck::{type_check_module};
+pub use self::exports::{check_exported_functions, check_external_functions};
+pub use self::entrypoint::{check_main_function, no_main_function_error};
+pub use self::typemap::{TypeMap, TypedSpan};
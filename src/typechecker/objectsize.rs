@@ -0,0 +1,92 @@
+use ast::{Expression, Function, Module, Type, Visitor, walk_expression};
+use compileerror::{CompileError, CompileResult};
+use errorcodes::ErrorCode;
+use layout::size_within;
+use span::Span;
+use target::Target;
+
+// Only arrays make a type large, all other types are as large as their declaration
+fn has_array(typ: &Type) -> bool
+{
+    match *typ
+    {
+        Type::Array(_) => true,
+        Type::Struct(ref st) => st.members.iter().any(|m| has_array(&m.typ)),
+        Type::Sum(ref st) => st.cases.iter().any(|c| has_array(&c.typ)),
+        Type::Optional(ref inner) => has_array(inner),
+        _ => false,
+    }
+}
+
+fn check_size(typ: &Type, span: &Span, target: &Target) -> CompileResult<()>
+{
+    if !has_array(typ) || typ.is_generic() || size_within(typ, target.max_object_size, target).is_some() {
+        return Ok(());
+    }
+
+    ErrorCode::ObjectTooLarge.result(span,
+        format!("A value of type {} is larger than the maximum object size of {} bytes", typ, target.max_object_size))
+}
+
+struct ObjectSizeChecker<'a>
+{
+    target: &'a Target,
+}
+
+impl<'a> Visitor for ObjectSizeChecker<'a>
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
+        check_size(&e.get_type(self.target.int_size), &e.span(), self.target)?;
+        walk_expression(self, e)
+    }
+}
+
+// Only the first value which is too large is reported, the others usually have the same type
+fn check_function(f: &Function, target: &Target) -> CompileResult<()>
+{
+    for arg in &f.sig.args {
+        check_size(&arg.typ, &arg.span, target)?;
+    }
+
+    check_size(&f.sig.return_type, &f.sig.span, target)?;
+    ObjectSizeChecker{target}.visit_expression(&f.expression)
+}
+
+// Values live on the stack or in globals, where a huge array crashes the program, and computing its size
+// can overflow in code generation, so values larger than the maximum object size of the target are an error
+pub fn check_object_sizes(module: &Module, target: &Target) -> CompileResult<()>
+{
+    let mut errors = Vec::new();
+    let mut globals: Vec<_> = module.globals.values().collect();
+    globals.sort_by(|a, b| a.name.cmp(&b.name));
+    for g in globals {
+        if let Err(e) = check_size(&g.typ, &g.span, target).and_then(|_| ObjectSizeChecker{target}.visit_expression(&g.init)) {
+            errors.push(e);
+        }
+    }
+
+    let mut functions: Vec<&Function> = module.functions.values().collect();
+    functions.sort_by(|a, b| a.sig.name.cmp(&b.sig.name));
+    for f in functions {
+        if let Err(e) = check_function(f, target) {
+            errors.push(e);
+        }
+    }
+
+    let mut externals: Vec<_> = module.externals.values().collect();
+    externals.sort_by(|a, b| a.sig.name.cmp(&b.sig.name));
+    for ext in externals {
+        for arg in &ext.sig.args {
+            if let Err(e) = check_size(&arg.typ, &arg.span, target) {
+                errors.push(e);
+            }
+        }
+    }
+
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(CompileError::Many(errors)),
+    }
+}
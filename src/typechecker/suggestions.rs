@@ -0,0 +1,82 @@
+use itertools::free::join;
+
+// Levenshtein distance, ignoring case
+fn edit_distance(a: &str, b: &str) -> usize
+{
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb {0} else {1};
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// Without namespace, unless the name we are looking for has one
+fn comparable_name<'a>(name: &str, candidate: &'a str) -> &'a str
+{
+    if name.contains("::") {
+        candidate
+    } else {
+        candidate.rsplit("::").next().unwrap_or(candidate)
+    }
+}
+
+// At most max candidates which are close enough to name, nearest first
+pub fn closest_names<'a, I>(name: &str, candidates: I, max: usize) -> Vec<String>
+    where I: Iterator<Item=&'a str>
+{
+    let threshold = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &str)> = candidates
+        .map(|c| comparable_name(name, c))
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|&(distance, _)| distance <= threshold)
+        .collect();
+    close.sort();
+    close.dedup();
+    close.into_iter().take(max).map(|(_, c)| c.to_string()).collect()
+}
+
+pub fn with_suggestions(msg: String, suggestions: &[String]) -> String
+{
+    let quoted = join(suggestions.iter().map(|s| format!("`{}`", s)), ", ");
+    match suggestions.len()
+    {
+        0 => msg,
+        1 => format!("{}, did you mean {}?", msg, quoted),
+        _ => format!("{}, did you mean one of {}?", msg, quoted),
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_edit_distance()
+    {
+        assert_eq!(edit_distance("foo", "foo"), 0);
+        assert_eq!(edit_distance("foo", "Foo"), 0);
+        assert_eq!(edit_distance("fooo", "foo"), 1);
+        assert_eq!(edit_distance("lenght", "length"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_closest_names()
+    {
+        let candidates = ["test::foo", "test::bar", "test::fob", "test::something"];
+        assert_eq!(closest_names("fooo", candidates.iter().cloned(), 1), vec!["foo"]);
+        assert_eq!(closest_names("fo", candidates.iter().cloned(), 3), vec!["fob", "foo"]);
+        assert!(closest_names("xyz", candidates.iter().cloned(), 3).is_empty());
+        assert_eq!(closest_names("test::bat", candidates.iter().cloned(), 1), vec!["test::bar"]);
+    }
+}
@@ -1,9 +1,20 @@
-use parser::{th_expr, th_mod};
+use std::thread;
+use parser::{th_expr, th_mod, number};
 use super::typecheck::{type_check_expression, type_check_module};
 use super::typecheckercontext::{TypeCheckerContext, ImportSymbolResolver};
-use ast::{IntSize, Type, ImportMap};
+use super::matchchecker::unreachable_cases;
+use super::floatequality::float_equality_comparisons;
+use super::unreachable::unreachable_code;
+use super::typemap::TypeMap;
+use super::entrypoint::check_main_function;
+use super::fixtures::{check_fixture, check_fixture_dir, fixture_error_codes};
+use ast::{IntSize, Type, ImportMap, BinaryOperator, Expression, Call, Literal, bin_op, array_type, slice_type};
 use compileerror::{CompileResult};
+use errorcodes::ERROR_CODES;
 use target::Target;
+use timer::PassTimings;
+use span::{Span, Pos};
+use COMPILER_STACK_SIZE;
 
 
 fn type_check(expr: &str) -> CompileResult<Type>
@@ -23,7 +34,7 @@ fn type_check_mod(expr: &str) -> CompileResult<()>
     let target = Target::new(IntSize::I32, "");
 	let mut md = th_mod(expr, &target);
 	let imports = ImportMap::new();
-	let r = type_check_module(&mut md, &target, &imports);
+	let r = type_check_module(&mut md, &target, &imports, &mut PassTimings::new());
 	println!("result: {:?}", r);
 	r
 }
@@ -97,6 +108,82 @@ fn foo(x: int) -> int:
 "#).is_ok());
 }
 
+#[test]
+fn test_string_match()
+{
+    assert!(type_check_mod(r#"
+fn foo(s: string) -> int:
+    match s:
+        "foo" => 1
+        [] => 0
+        [c | rest] => 1 + foo(rest)
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+fn foo(s: string) -> char:
+    match s:
+        [] => 'a'
+        [c | rest] => c
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+fn foo(s: string) -> int:
+    match s:
+        [] => 0
+        [c | rest] => c
+"#).is_err());
+
+    assert!(type_check_mod(r#"
+fn foo(s: string) -> int:
+    match s:
+        "foo" => 1
+        [] => 0
+"#).is_err());
+}
+
+#[test]
+fn test_sum_type_constructors()
+{
+    assert!(type_check_mod(r#"
+enum Shape:
+    Square{side: int}
+    Empty
+
+fn make(ctor: fn(int) -> Shape, v: int) -> Shape: ctor(v)
+
+fn foo() -> Shape: make(Square, 4)
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+enum Shape:
+    Rect{w: int, h: int}
+    Empty
+
+fn foo() -> Shape: Rect(4, 5)
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+enum Shape:
+    Rect{w: int, h: int}
+    Empty
+
+fn foo() -> Shape: Rect(4)
+"#).is_err());
+
+    assert!(type_check_mod(r#"
+enum Option:
+    Some{value: $a}
+    None
+
+fn unwrap_or(opt: Option<$a>, def: $a) -> $a:
+    match opt:
+        Some{v} => v
+        None => def
+
+fn foo(a: int) -> int: unwrap_or(Some(a), 0)
+"#).is_ok());
+}
+
 #[test]
 fn test_let()
 {
@@ -113,6 +200,45 @@ fn test_let()
 "#).is_ok());
 }
 
+#[test]
+fn test_let_destructuring()
+{
+    assert!(type_check_mod(r#"
+struct Point:
+    x: int
+    y: int
+
+fn foo(p: Point) -> int:
+    let Point{x, _} = p
+    x
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+struct Point:
+    x: int
+    y: int
+
+struct Size:
+    w: int
+    h: int
+
+fn foo(p: Point) -> int:
+    let Size{w, h} = p
+    w + h
+"#).is_err());
+
+    assert!(type_check_mod(r#"
+fn foo(a: int[]) -> int:
+    let [head | tail] = a
+    head
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+fn foo(a: int) -> int:
+    let [head | tail] = a
+    head
+"#).is_err());
+}
 
 #[test]
 fn test_mutability()
@@ -157,3 +283,1994 @@ fn test_mutability()
         "#).is_ok()
 	);
 }
+
+#[test]
+fn test_variadic_call()
+{
+    assert!(type_check_mod(r#"
+extern fn printf(fmt: *uint8, ...) -> int32
+
+fn main() -> int32:
+    printf("%d %f %s %c".data, 5, 3.5, "foo", 'a')
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+extern fn printf(fmt: *uint8, ...) -> int32
+
+fn main() -> int32:
+    printf()
+"#).is_err());
+
+    assert!(type_check_mod(r#"
+extern fn printf(fmt: *uint8, ...) -> int32
+
+struct Point:
+    x: int
+    y: int
+
+fn main() -> int32:
+    printf("%d".data, Point{1, 2})
+"#).is_err());
+
+    assert!(type_check_mod(r#"
+extern fn printf(fmt: *uint8, ...) -> int32
+
+fn main() -> int32:
+    printf("%d".data, [1, 2, 3])
+"#).is_err());
+}
+
+#[test]
+fn test_deeply_nested_expression()
+{
+    let checker = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(|| {
+        let target = Target::new(IntSize::I32, "");
+        let imports = ImportMap::new();
+        let mut ctx = TypeCheckerContext::new(ImportSymbolResolver::ImportMap(&imports));
+        let mut e = number(1, Span::default(), &target);
+        for _ in 0..100_000 {
+            e = bin_op(BinaryOperator::Add, e, number(1, Span::default(), &target), Span::default());
+        }
+
+        assert!(type_check_expression(&mut ctx, &mut e, None, &target).is_err());
+    });
+
+    checker.expect("Unable to start thread").join().expect("Type checking crashed");
+}
+
+#[test]
+fn test_type_check_timings()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+struct Point:
+    x: int
+    y: int
+
+fn a(x: $t) -> $t: b(x)
+fn b(x: $t) -> $t: c(x)
+fn c(x: $t) -> $t: x
+
+fn main() -> int:
+    let p = Point{1, 2}
+    a(p.x) + a(p.y)
+"#, &target);
+    let imports = ImportMap::new();
+    let mut timings = PassTimings::new();
+    assert!(type_check_module(&mut md, &target, &imports, &mut timings).is_ok());
+
+    // Every generic instantiation in the call chain needs another iteration
+    let pass = |name: &str| timings.passes().iter().find(|p| p.name == name).cloned();
+    assert!(pass("Type checking (iteration 3)").is_some());
+    assert!(pass("Generic instantiation (iteration 3)").is_some());
+
+    // Later iterations only resolve the newly instantiated functions
+    let first = pass("Type resolution (iteration 1)").map(|p| p.work).unwrap_or(0);
+    let second = pass("Type resolution (iteration 2)").map(|p| p.work).unwrap_or(0);
+    assert!(second < first);
+}
+
+#[test]
+fn test_result_propagation()
+{
+    assert!(type_check_mod(r#"
+fn div(a: int, b: int) -> Result<int, string>:
+    if b == 0:
+        return Err{"division by zero"}
+    Ok{a / b}
+
+fn calc(a: int, b: int) -> Result<int, string>:
+    let x = div(a, b)? + 1
+    Ok{x}
+"#).is_ok());
+
+    // Not allowed in a function which does not return a Result
+    assert!(type_check_mod(r#"
+fn div(a: int, b: int) -> Result<int, string>:
+    Ok{a / b}
+
+fn calc(a: int, b: int) -> int:
+    div(a, b)?
+"#).is_err());
+
+    // The error types must be the same
+    assert!(type_check_mod(r#"
+fn div(a: int, b: int) -> Result<int, string>:
+    Ok{a / b}
+
+fn calc(a: int, b: int) -> Result<int, int>:
+    Ok{div(a, b)?}
+"#).is_err());
+
+    // Only on a Result
+    assert!(type_check_mod(r#"
+fn calc(a: int) -> Result<int, string>:
+    Ok{a?}
+"#).is_err());
+}
+
+fn type_check_error(src: &str) -> String
+{
+    format!("{}", type_check_mod(src).expect_err("Expected a type check error"))
+}
+
+#[test]
+fn test_did_you_mean()
+{
+    let err = type_check_error(r#"
+fn square(a: int) -> int: a * a
+
+fn main() -> int: squaree(4)
+"#);
+    assert!(err.contains("Unknown call squaree, did you mean `square`?"));
+
+    let err = type_check_error(r#"
+struct Point:
+    x: int
+    y: int
+    xy: int
+
+fn main() -> int:
+    let p = Point{1, 2, 3}
+    p.X
+"#);
+    assert!(err.contains("Unknown struct member X, did you mean one of `x`, `xy`, `y`?"));
+
+    let err = type_check_error(r#"
+fn square(a: int) -> int: a * a
+
+fn main() -> int: cube(4)
+"#);
+    assert!(err.contains("Unknown call cube"));
+    assert!(!err.contains("did you mean"));
+}
+
+#[test]
+fn test_wrapping_arithmetic()
+{
+    assert!(type_check_mod(r#"
+fn f(a: uint8, b: uint8) -> uint8: wrapping_mul(a, b)
+fn main() -> int: wrapping_sub(wrapping_add(4, 5), 1)
+"#).is_ok());
+}
+
+#[test]
+fn test_char_builtins()
+{
+    assert!(type_check_mod(r#"
+fn classify(c: char) -> bool: is_digit(c) || is_alpha(c) || is_whitespace(to_lower(c))
+fn code(c: char) -> uint32: char_to_int(to_upper(c))
+fn back() -> ?char: int_to_char(65)
+fn main() -> int: 7
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    let x = to_upper("a")
+    7
+"#).is_err());
+}
+
+#[test]
+fn test_approx_eq()
+{
+    assert!(type_check_mod(r#"
+fn close(a: float64, b: float64) -> bool: approx_eq(a, b, 0.000001)
+fn close32(a: float, b: float, epsilon: float) -> bool: approx_eq(a, b, epsilon)
+fn main() -> int: 7
+"#).is_ok());
+}
+
+#[test]
+fn test_min_max_abs_clamp()
+{
+    assert!(type_check_mod(r#"
+fn small(a: int8, b: int8) -> int8: max(a, b)
+fn low(a: uint16) -> uint16: min(a, 7)
+fn high(a: uint16) -> uint16: max(7, a)
+fn limit(a: int64) -> int64: clamp(a, -10, 10)
+fn magnitude(a: float) -> float: abs(a)
+fn unit(a: float64) -> float64: clamp(a, -1.0, 1.0)
+fn main() -> int: abs(-7)
+"#).is_ok());
+}
+
+#[test]
+fn test_slice_builtins()
+{
+    assert!(type_check_mod(r#"
+fn duplicate(xs: string[], var buf: string[]) -> int: copy(buf, xs)
+fn clear(xs: $T[], value: $T): fill(xs, value)
+fn shift(xs: uint8[], n: int): copy_within(xs, 0, n, 2)
+fn main() -> int:
+    var a = [1, 2, 3]
+    var b = [0, 0]
+    fill(a, 7)
+    copy(b, a)
+"#).is_ok());
+}
+
+#[test]
+fn test_spawn_join()
+{
+    assert!(type_check_mod(r#"
+var count = 0
+
+fn work():
+    count += 1
+
+fn start() -> thread: spawn(work)
+
+fn main() -> int:
+    let threads = [start(), spawn(work)]
+    join(threads[0])
+    join(threads[1])
+    count
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn double(a: int) -> int: a * 2
+
+fn main() -> int:
+    let t = spawn(double)
+    7
+"#);
+    assert!(err.contains("Expecting an expression of type () -> void or something convertible to, but found one of type (int32) -> int32"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    join(7)
+    7
+"#);
+    assert!(err.contains("Expecting an expression of type thread or something convertible to, but found one of type int32"));
+}
+
+#[test]
+fn test_float_equality_warnings()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+fn eq(a: float64, b: float64) -> bool: a == b
+fn ne(a: float, b: float) -> bool: a + b != b
+fn zero(a: float64) -> bool: a == 0.0 || 0.0 != a
+fn nan(a: float64) -> bool: a != a
+fn exact(a: float64, b: float64) -> bool: (a == b : bool)
+fn ints(a: int, b: int) -> bool: a == b
+fn approx(a: float64, b: float64) -> bool: approx_eq(a, b, 0.001)
+"#, &target);
+    assert!(type_check_module(&mut md, &target, &ImportMap::new(), &mut PassTimings::new()).is_ok());
+
+    let warnings = |name: &str| float_equality_comparisons(&md.functions[&format!("test::{}", name)].expression, &target);
+    assert_eq!(warnings("eq"), vec![Span::new("", Pos::new(2, 40), Pos::new(2, 45))]);
+    assert_eq!(warnings("ne"), vec![Span::new("", Pos::new(3, 36), Pos::new(3, 45))]);
+    for name in &["zero", "nan", "exact", "ints", "approx"] {
+        assert!(warnings(name).is_empty(), "unexpected warning in {}", name);
+    }
+}
+
+#[test]
+fn test_return_in_value_position()
+{
+    assert!(type_check_mod(r#"
+fn classify(x: int, y: int) -> int:
+    let v = match x:
+        0 => 10
+        1 => match y:
+            0 => return 2
+            _ => 20
+        _ => return 3
+    let w = if y < 0: return -1 else y * 2
+    v + w
+
+fn sign(x: int) -> int:
+    if x < 0:
+        return -1
+    else
+        return 1
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn half(x: int) -> int:
+    let v = if x < 0: return "negative" else x / 2
+    v
+"#);
+    assert!(err.contains("Expecting an expression of type int32 or something convertible to, but found one of type string"));
+
+    let err = type_check_error(r#"
+fn half(x: int) -> int:
+    if x < 0:
+        return
+    x / 2
+"#);
+    assert!(err.contains("Expecting an expression of type int32 or something convertible to, but found one of type void"));
+}
+
+#[test]
+fn test_return_in_lambda()
+{
+    // The return leaves the lambda, so it must have the return type of the lambda, not that of describe
+    assert!(type_check_mod(r#"
+fn apply(x: int, f: fn(int) -> int) -> int: f(x)
+
+fn describe(n: int) -> bool:
+    let doubled = apply(n, fn(x) -> if x > 3: return x * 2 else x)
+    doubled > 6
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn apply(x: int, f: fn(int) -> int) -> int: f(x)
+
+fn describe(n: int) -> bool:
+    let doubled = apply(n, fn(x) -> if x > 3: return true else x)
+    doubled > 6
+"#);
+    assert!(err.contains("Expecting an expression of type int32 or something convertible to, but found one of type bool"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let f = fn() -> return 5
+    0
+"#);
+    assert!(err.contains("Cannot return from a lambda with an unknown return type, pass the lambda where its type is known"));
+}
+
+#[test]
+fn test_unreachable_code_warnings()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+fn early(x: int) -> int:
+    if x > 0:
+        return 1
+        x + 1
+    return 2
+    x
+fn late(x: int) -> int:
+    if x > 0:
+        return 1
+    x
+"#, &target);
+    assert!(type_check_module(&mut md, &target, &ImportMap::new(), &mut PassTimings::new()).is_ok());
+
+    let warnings = |name: &str| unreachable_code(&md.functions[&format!("test::{}", name)].expression);
+    let lines: Vec<usize> = warnings("early").iter().map(|s| s.start.line).collect();
+    assert_eq!(lines, vec![5, 7]);
+    assert!(warnings("late").is_empty());
+}
+
+#[test]
+fn test_local_function()
+{
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    fn fact(n: int) -> int:
+        if n <= 1: 1 else n * fact(n - 1)
+    fact(5)
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    fn fact(n) -> int:
+        if n <= 1: 1 else n * fact(n - 1)
+    fact(5)
+"#);
+    assert!(err.contains("Cannot infer the type of argument n of local function fact"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    fn inc(n: int) -> bool: n + 1
+    7
+"#);
+    assert!(err.contains("Local function inc has return type bool, but it is returning an expression of type int32"));
+}
+
+#[test]
+fn test_operator_functions()
+{
+    assert!(type_check_mod(r#"
+struct Point:
+    x: int
+    y: int
+
+fn Point.op_equals(a: Point, b: Point) -> bool: a.x == b.x && a.y == b.y
+fn Point.op_less(a: Point, b: Point) -> bool: a.x < b.x
+
+fn min(a: $T, b: $T) -> $T:
+    if a < b: a else b
+
+fn main() -> int:
+    let p = Point{1, 2}
+    let m = min(p, Point{0, 3})
+    if p == m: 1 else m.y
+"#).is_ok());
+
+    let err = type_check_error(r#"
+struct Point:
+    x: int
+    y: int
+
+fn Point.op_equals(a: Point, b: int) -> bool: a.x == b
+
+fn main() -> int:
+    let p = Point{1, 2}
+    if p == p: 1 else 0
+"#);
+    assert!(err.contains("Operator == on test::Point requires test::Point.op_equals to have type (test::Point, test::Point) -> bool, but it has type (test::Point, int32) -> bool"));
+
+    let err = type_check_error(r#"
+struct Point:
+    x: int
+    y: int
+
+fn main() -> int:
+    let p = Point{1, 2}
+    if p < p: 1 else 0
+"#);
+    assert!(err.contains("Operator < is not supported on test::Point, it requires a function test::Point.op_less with type (test::Point, test::Point) -> bool"));
+
+    let err = type_check_error(r#"
+struct Point:
+    x: int
+    y: int
+
+fn min(a: $T, b: $T) -> $T:
+    if a < b: a else b
+
+fn main() -> int:
+    let p = min(Point{1, 2}, Point{3, 4})
+    p.x
+"#);
+    assert!(err.contains("requires a function test::Point.op_less"));
+}
+
+#[test]
+fn test_global_variables()
+{
+    assert!(type_check_mod(r#"
+let total = sum()
+var counter: int = total
+let a = 4
+let b: int = 5
+
+fn sum() -> int: a + b
+
+fn inc() -> int:
+    counter += 1
+    counter
+"#).is_ok());
+
+    let err = type_check_error(r#"
+let a: int = true
+"#);
+    assert!(err.contains("Expecting an expression of type int32 or something convertible to, but found one of type bool"));
+
+    let err = type_check_error(r#"
+let total = 6
+
+fn reset():
+    total = 0
+"#);
+    assert!(err.contains("Attempting to modify non mutable variable test::total"));
+}
+
+#[test]
+fn test_modifying_array_elements()
+{
+    assert!(type_check_mod(r#"
+struct Entry:
+    keys: int[2]
+
+var grid = [[0, 0], [0, 0]]
+var entries = [Entry{[1, 2]}]
+
+fn set(var row: int[2], xs: int[]):
+    grid[1][0] = 5
+    entries[0].keys[1] = 3
+    row[1] = 6
+    xs[0] = 7
+"#).is_ok());
+
+    let err = type_check_error(r#"
+let TABLE = [1, 2, 3]
+
+fn reset():
+    TABLE[0] = 0
+"#);
+    assert!(err.contains("5:5 -> 5:12: Attempting to modify an element of a non mutable array"));
+
+    let err = type_check_error(r#"
+struct Entry:
+    keys: int[2]
+
+let ENTRIES = [Entry{[1, 2]}]
+
+fn reset():
+    ENTRIES[0].keys[1] = 0
+"#);
+    assert!(err.contains("Attempting to modify an element of a non mutable array"));
+
+    let err = type_check_error(r#"
+fn first(row: int[2]):
+    row[0] = 1
+"#);
+    assert!(err.contains("Attempting to modify an element of a non mutable array"));
+}
+
+#[test]
+fn test_prelude_sort_and_search()
+{
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    let xs = sort([3, 1, 2], fn(a, b) -> a < b)
+    binary_search(xs, 2, fn(a, b) -> a < b) || -1
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+fn less(a: int, b: int) -> bool: a < b
+
+fn main() -> int:
+    binary_search([3, 1, 2], "x", less) || -1
+"#).is_err());
+}
+
+#[test]
+fn test_prelude_map()
+{
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    let m = Map<string, int>()
+    insert(m, "one", 1)
+    let removed = remove(m, "two")
+    (get(m, "one") || 0) + m.len as int
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let m = Map<float, int>()
+    0
+"#);
+    assert!(err.contains("Type float32 cannot be used as the key of a Map"));
+
+    let err = type_check_error(r#"
+fn main() -> uint: hash(1.5)
+"#);
+    assert!(err.contains("Type float64 is not hashable"));
+
+    let err = type_check_error(r#"
+fn square(a: int) -> int: a * a
+
+fn main() -> int: square<int>(4)
+"#);
+    assert!(err.contains("Explicit type arguments are only allowed when constructing a type"));
+}
+
+#[test]
+fn test_prelude_file_io()
+{
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    let ok = write_file("out.txt", "data")
+    let contents = read_file("out.txt") || ""
+    let line = read_line() || ""
+    if ok: contents.len as int + line.len as int else 0
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let ok = write_file("out.txt", 7)
+    0
+"#);
+    assert!(err.contains("Expecting an expression of type string"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let s = [1, 2] as string
+    0
+"#);
+    assert!(err.contains("Cast from type"));
+}
+
+#[test]
+fn test_prelude_number_conversions()
+{
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    if let i = parse_int(" -42 "), u = parse_uint("42"), f = parse_float("1.5e3"):
+        let s = format_int(i, 16)
+        let t = format_float(f, 2)
+        if s.len == t.len && u > 0: 1 else 0
+    else
+        0
+"#).is_ok());
+
+    // A value can be returned as an optional by name
+    assert!(type_check_mod(r#"
+fn wrap(x: int) -> ?int: x
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let s = format_int(7, 300)
+    0
+"#);
+    assert!(err.contains("Expecting an expression of type uint8"));
+}
+
+#[test]
+fn test_generic_type_params()
+{
+    assert!(type_check_mod(r#"
+struct Pair<T>{first: T, second: T}
+
+enum Maybe<T>:
+    Just{value: T}
+    Nothing
+
+fn total(p: Pair<Maybe<int>>) -> int:
+    match p.first:
+        Just{v} => v
+        Nothing => 0
+
+fn main() -> int: total(Pair{Just{4}, Nothing})
+"#).is_ok());
+
+    let err = type_check_error(r#"
+struct Pair<T>{first: T, second: T}
+
+fn first(p: Pair<int, int>) -> int: p.first
+"#);
+    assert!(err.contains("Type Pair expects 1 generic arguments, but 2 were given"));
+
+    let err = type_check_error(r#"
+struct Pair<T, U>{first: T, second: T}
+"#);
+    assert!(err.contains("Generic parameter $U of test::Pair is not used by any of its members"));
+}
+
+#[test]
+fn test_generic_inference_errors()
+{
+    let err = type_check_error(r#"
+fn pick(a: $T, b: int, c: $T) -> $T: a
+
+fn main() -> int: pick(1, 2, "x")
+"#);
+    assert!(err.contains("4:30 -> 4:32: Cannot infer $T: argument 1 requires $T = int32, but argument 3 requires $T = string"));
+    assert!(err.contains("4:24 -> 4:24: Argument 1 requires $T = int32"));
+
+    let err = type_check_error(r#"
+fn none(a: int) -> ?$T: nil
+
+fn main() -> int:
+    let x = none(1)
+    0
+"#);
+    assert!(err.contains("Cannot infer $T from the provided arguments of test::none, consider an explicit annotation"));
+}
+
+#[test]
+fn test_named_arguments()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+fn make_rect(x: int, y: int, width: int, height: int) -> int: x
+
+fn main() -> int:
+    make_rect(width: 10, height: 20, x: 5, y: 6) + make_rect(1, 2, height: 3, width: 4)
+"#, &target);
+    assert!(type_check_module(&mut md, &target, &ImportMap::new(), &mut PassTimings::new()).is_ok());
+    let f = md.functions.get("test::main").expect("Unknown function");
+    let (full, mixed) = match f.expression {
+        Expression::BinaryOp(ref op) => match (&op.left, &op.right) {
+            (&Expression::Call(ref full), &Expression::Call(ref mixed)) => (full.clone(), mixed.clone()),
+            _ => panic!("Expected two calls"),
+        },
+        _ => panic!("Expected a binary operation"),
+    };
+
+    let values = |c: &Call| -> Vec<i64> {
+        assert!(c.named_args.is_empty());
+        c.args.iter().map(|a| match *a {
+            Expression::Literal(Literal::Int(_, v, _)) => v,
+            _ => panic!("Expected an integer literal"),
+        }).collect()
+    };
+    assert_eq!(values(&full), vec![5, 6, 10, 20]);
+    assert_eq!(values(&mixed), vec![1, 2, 4, 3]);
+
+    let err = type_check_error(r#"
+fn make_rect(x: int, y: int, width: int, height: int) -> int: x
+
+fn main() -> int: make_rect(1, 2, width: 3, width: 4)
+"#);
+    assert!(err.contains("4:45 -> 4:52: Argument width is passed more than once"));
+    assert!(err.contains("4:35 -> 4:42: Argument width is first passed here"));
+    assert!(err.contains("2:30 -> 2:39: Parameter width is declared here"));
+
+    let err = type_check_error(r#"
+fn make_rect(x: int, y: int, width: int, height: int) -> int: x
+
+fn main() -> int: make_rect(1, 2, depth: 3, width: 4)
+"#);
+    assert!(err.contains("4:35 -> 4:42: test::make_rect has no parameter named depth"));
+
+    let err = type_check_error(r#"
+fn make_rect(x: int, y: int, width: int, height: int) -> int: x
+
+fn main() -> int: make_rect(1, width: 3)
+"#);
+    assert!(err.contains("Attempting to call test::make_rect with 2 arguments, but it needs 4, missing arguments: y, height"));
+
+    let err = type_check_error(r#"
+fn make_rect(x: int, y: int, width: int, height: int) -> int: x
+
+fn main() -> int: make_rect(1, 2)
+"#);
+    assert!(err.contains("Attempting to call test::make_rect with 2 arguments, but it needs 4, missing arguments: width, height"));
+
+    let err = type_check_error(r#"
+extern fn abs(v: int) -> int
+
+fn main() -> int: abs(v: 3)
+"#);
+    assert!(err.contains("Cannot pass argument v by name to abs, named arguments are only allowed in calls to functions"));
+}
+
+#[test]
+fn test_composition_cycles()
+{
+    let err = type_check_error(r#"
+struct Node:
+    value: int
+    next: Node
+"#);
+    assert!(err.contains("4:5 -> 4:14: test::Node.next: Node forms a cycle, a type cannot contain itself by value"));
+
+    let err = type_check_error(r#"
+struct A:
+    b: B
+
+struct B:
+    id: int
+    c: C[2]
+
+struct C:
+    a: A
+"#);
+    assert!(err.contains("3:5 -> 3:8: test::A.b: B -> test::B.c: C[2] -> test::C.a: A forms a cycle, a type cannot contain itself by value"));
+    assert!(err.contains("3:5 -> 3:8: test::A.b\n"));
+    assert!(err.contains("7:5 -> 7:11: test::B.c\n"));
+    assert!(err.contains("10:5 -> 10:8: test::C.a\n"));
+    assert!(!err.contains("Unknown"));
+
+    let err = type_check_error(r#"
+enum Tree:
+    Leaf{n: int}
+    Node{left: Tree, right: Tree}
+"#);
+    assert!(err.contains("test::Tree.left: Tree forms a cycle"));
+    assert!(err.contains("test::Tree.right: Tree forms a cycle"));
+
+    let err = type_check_error(r#"
+struct List:
+    head: *Item
+
+struct Item:
+    value: int
+    list: *List
+"#);
+    assert!(err.contains("test::Item.list: *List -> test::List.head: *Item forms a cycle, types which refer to themselves through pointers or slices are not supported"));
+
+    // Pointers to types declared later are fine
+    assert!(type_check_mod(r#"
+struct Outer:
+    first: Inner
+    next: *Inner
+
+struct Inner:
+    n: int
+
+fn first(o: Outer) -> int: o.first.n
+"#).is_ok());
+
+    let err = type_check_error(r#"
+struct Outer:
+    inner: Inner
+"#);
+    assert!(err.contains("Unknown struct member type Inner"));
+}
+
+#[test]
+fn test_repr_c_structs()
+{
+    let err = type_check_error(r#"
+struct Pair:
+    a: int32
+    b: int32
+
+extern fn swap(p: *Pair) -> int32
+"#);
+    assert!(err.contains("6:16 -> 6:23: Argument p of external function swap uses struct test::Pair, which has no C layout, add #[repr(c)] to its declaration"));
+
+    let err = type_check_error(r#"
+#[repr(c)]
+struct Named:
+    id: int32
+    name: string
+"#);
+    assert!(err.contains("5:5 -> 5:16: Member name of repr(c) struct test::Named has type string, which has no C representation"));
+
+    let check_x86_64 = |src: &str| {
+        let target = Target::new(IntSize::I64, "x86_64-pc-linux-gnu");
+        let mut md = th_mod(src, &target);
+        type_check_module(&mut md, &target, &ImportMap::new(), &mut PassTimings::new()).map_err(|e| format!("{}", e))
+    };
+
+    let pair = "#[repr(c)]\nstruct Pair:\n    a: int32\n    b: int32\n";
+    assert!(check_x86_64(&format!("{}extern fn swap(p: Pair) -> Pair", pair)).is_ok());
+    let err = check_x86_64(&format!("{}extern fn sum(a: int, b: int, c: int, d: int, e: int, f: int, p: Pair) -> int32", pair)).unwrap_err();
+    assert!(err.contains("Argument p of external function sum is passed on the stack by the C calling convention, which is not supported"));
+
+    let err = type_check_error(&format!("{}extern fn swap(p: Pair) -> Pair", pair));
+    assert!(err.contains("External function swap passes a struct by value, which is only supported on x86-64 System V targets"));
+}
+
+#[test]
+fn test_unused_values()
+{
+    let err = type_check_error(r#"
+fn main() -> int:
+    let x = 3
+    x == 3
+    x
+"#);
+    assert!(err.contains("4:5 -> 4:10: The value of this expression (type bool) is not used, discard it explicitly with _ = expression"));
+
+    let err = type_check_error(r#"
+fn f() -> int: 5
+
+fn main() -> int:
+    var i = 0
+    while i < 3:
+        i += 1
+        if i > 1: f() else 2
+    i
+"#);
+    assert!(err.contains("The value of this expression (type int32) is not used"));
+
+    assert!(type_check_mod(r#"
+fn f() -> int: 5
+
+fn main() -> int:
+    var i = 0
+    _ = f()
+    while i < 3:
+        i += 1
+        if i > 1:
+            _ = f()
+        else
+            i += 1
+    i + f()
+"#).is_ok());
+}
+
+#[test]
+fn test_range_patterns()
+{
+    assert!(type_check_mod(r#"
+fn classify(c: char) -> int:
+    match c:
+        'a'..='z' => 1
+        '0'..'9' => 2
+        '9' => 3
+        _ => 0
+
+fn bucket(n: int) -> int:
+    match n:
+        0..10 => 1
+        10..=19 => 2
+        _ => 3
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn bucket(n: int) -> int:
+    match n:
+        10..=0 => 1
+        _ => 2
+"#);
+    assert!(err.contains("4:9 -> 4:14: Range pattern is empty, its start must come before its end"));
+
+    let err = type_check_error(r#"
+fn bucket(n: int) -> int:
+    match n:
+        5..5 => 1
+        _ => 2
+"#);
+    assert!(err.contains("Range pattern is empty, its start must come before its end"));
+
+    let err = type_check_error(r#"
+fn bucket(n: int) -> int:
+    match n:
+        'a'..='z' => 1
+        _ => 2
+"#);
+    assert!(err.contains("Pattern match of type char, cannot match with an expression of type int32"));
+
+    let err = type_check_error(r#"
+fn bucket(n: int) -> int:
+    match n:
+        0..10 => 1
+"#);
+    assert!(err.contains("Incomplete pattern match for type int32"));
+}
+
+#[test]
+fn test_binding_patterns()
+{
+    assert!(type_check_mod(r#"
+enum Day:
+    Monday
+    Tuesday
+    Sunday
+
+fn weekday(d: Day) -> int:
+    match d:
+        Sunday => 0
+        other => if other == Monday: 1 else 2
+
+fn shout(s: string) -> string:
+    match s:
+        "" => "!"
+        word => word
+
+fn twice(x: int) -> int:
+    match x + 1:
+        0 => 0
+        x => x * 2
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn foo(x: int) -> int:
+    match x:
+        n => n
+        0 => 1
+"#);
+    assert!(err.contains("A pattern match with _ or a binding must always be the last one in a match statement"));
+
+    let err = type_check_error(r#"
+fn foo(x: int) -> bool:
+    match x:
+        n => n
+"#);
+    assert!(err.contains("Function test::foo has return type bool, but it is returning an expression of type int32"));
+}
+
+#[test]
+fn test_unreachable_range_cases()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+match n:
+    0..10 => 1
+    10..=19 => 2
+    5 => 3
+    15..25 => 4
+    3..=18 => 5
+    24..27 => 6
+    _ => 7
+"#, &target);
+    let m = match e {
+        Expression::Match(ref m) => m,
+        _ => panic!("Expected a match"),
+    };
+
+    let unreachable: Vec<i64> = unreachable_cases(m).iter()
+        .map(|c| match c.to_execute {
+            Expression::Literal(ref l) => ::ast::literal_value(l).expect("Expected an integer") as i64,
+            _ => panic!("Expected a literal"),
+        })
+        .collect();
+    assert_eq!(unreachable, vec![3, 5]);
+}
+
+#[test]
+fn test_or_patterns()
+{
+    assert!(type_check_mod(r#"
+enum Color:
+    Red
+    Green
+    Blue
+
+enum Shape:
+    Circle{r: int}
+    Square{r: int}
+    Rect{w: int, h: int}
+
+fn small(n: int) -> bool:
+    match n:
+        0 | 1 | 2 => true
+        _ => false
+
+fn warm(c: Color) -> bool:
+    match c:
+        Red => true
+        Green | Blue => false
+
+fn size(s: Shape) -> int:
+    match s:
+        Circle{r} | Square{r} => r
+        Rect{w, h} => w * h
+"#).is_ok());
+
+    let err = type_check_error(r#"
+enum Color:
+    Red
+    Green
+    Blue
+
+fn warm(c: Color) -> bool:
+    match c:
+        Red | Green => true
+"#);
+    assert!(err.contains("Incomplete pattern match, not all cases are handled"));
+
+    let err = type_check_error(r#"
+enum Shape:
+    Circle{r: int}
+    Rect{w: int, h: int}
+
+fn size(s: Shape) -> int:
+    match s:
+        Circle{r} | Rect{w, h} => r
+"#);
+    assert!(err.contains("8:21 -> 8:30: w is bound in only some of the alternatives of the pattern, every alternative must bind the same names"));
+
+    let err = type_check_error(r#"
+enum Value:
+    Number{v: int}
+    Text{v: string}
+
+fn size(x: Value) -> int:
+    match x:
+        Number{v} | Text{v} => 0
+"#);
+    assert!(err.contains("v has type int32 in the first alternative of the pattern, but type string in this one"));
+}
+
+#[test]
+fn test_empty_array_literals()
+{
+    assert!(type_check_mod(r#"
+struct Bag{items: int[], n: int}
+
+fn count(xs: int32[]) -> uint: xs.len
+
+fn main() -> int:
+    let b = Bag{[], 3}
+    let e = ([] : int32[])
+    let n = count([]) + count(e) + b.items.len
+    n as int + b.n + (7 : int)
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let xs = []
+    0
+"#);
+    assert!(err.contains("Cannot infer the element type of an empty array literal, add an annotation"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let s = ("abc" : int)
+    0
+"#);
+    assert!(err.contains("or something convertible to, but found one of type string"));
+}
+
+#[test]
+fn test_if_let()
+{
+    assert!(type_check_mod(r#"
+fn half(v: int) -> ?int: if v % 2 == 0: v / 2 else nil
+
+fn quarter(v: int) -> int:
+    if let x = half(v), y = half(x):
+        y
+    else
+        0
+
+fn main() -> int:
+    var n = quarter(8)
+    if let v = half(n):
+        n = v
+    n
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn half(v: int) -> ?int: if v % 2 == 0: v / 2 else nil
+
+fn main() -> int:
+    if let x = half(4), y = half(6):
+        x + y
+    else
+        "none"
+"#);
+    assert!(err.contains("then and else expression of an if expression need to be of the same type, then has type int32, else has type string"));
+
+    let err = type_check_error(r#"
+fn half(v: int) -> ?int: if v % 2 == 0: v / 2 else nil
+
+fn main() -> int:
+    if let x = half(4), y = half(6):
+        x + y
+    else
+        x
+"#);
+    assert!(err.contains("Unknown name x"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    if let x = 4:
+        x
+    else
+        0
+"#);
+    assert!(err.contains("if let expects an optional, but x has type int32"));
+}
+
+#[test]
+fn test_tuples()
+{
+    assert!(type_check_mod(r#"
+fn divmod(a: int, b: int) -> (int, int): (a / b, a % b)
+
+fn main() -> int:
+    let (q, r) = divmod(7, 2)
+    let t = divmod(9, 4)
+    let pairs = [(1, true), (2, false)]
+    let same = t == (2, 1) && pairs[0] != (1, false)
+    match t:
+        (a, b) => if same: q + r + a + b + pairs[1].0 else t.1
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let t = (1, true)
+    t.2
+"#);
+    assert!(err.contains("Tuple {int32, bool} has no member 2, it only has 2 members"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let t = (1, true)
+    t.first
+"#);
+    assert!(err.contains("Tuple {int32, bool} has no member named first, tuple members are accessed by position (.0 to .1)"));
+
+    let err = type_check_error(r#"
+struct Point:
+    x: int
+    y: int
+
+fn main() -> bool: (1, Point{1, 2}) == (1, Point{1, 2})
+"#);
+    assert!(err.contains("Operator == is not supported on {int32, {x: int32, y: int32}}"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    match 7:
+        (a, b) => a
+"#);
+    assert!(err.contains("Tuple pattern cannot match an expression of type int32"));
+}
+
+fn type_check_and_fold(expr: &str) -> Expression
+{
+    let target = Target::new(IntSize::I32, "");
+    let imports = ImportMap::new();
+    let mut ctx = TypeCheckerContext::new(ImportSymbolResolver::ImportMap(&imports));
+    let mut e = th_expr(expr, &target);
+    type_check_expression(&mut ctx, &mut e, None, &target).expect("Type check failed");
+    e
+}
+
+#[test]
+fn test_constant_string_folding()
+{
+    let target = Target::new(IntSize::I32, "");
+    let concat = r#""héllo, " + "wörld""#;
+    match type_check_and_fold(concat) {
+        Expression::Literal(Literal::String(span, s)) => {
+            assert_eq!(s, "héllo, wörld");
+            assert_eq!(span, th_expr(concat, &target).span());
+        },
+        e => panic!("Expected a string literal, got {:?}", e),
+    }
+
+    match type_check_and_fold(r#""ü€".len"#) {
+        Expression::Literal(Literal::UInt(_, 5, IntSize::I32)) => (),
+        e => panic!("Expected an uint literal, got {:?}", e),
+    }
+
+    match type_check_and_fold(r#"("a" + "b") == "ab""#) {
+        Expression::Literal(Literal::Bool(_, true)) => (),
+        e => panic!("Expected a bool literal, got {:?}", e),
+    }
+
+    assert!(type_check_mod(r#"
+let GREETING = "hello, " + "world"
+let SIZE: uint = GREETING.len
+
+fn answer(s: string) -> int:
+    match s:
+        GREETING => 1
+        _ => 0
+
+fn main() -> int:
+    let a = ([1, 2, 3] : int["abc".len])
+    answer("hello, world") + a[2]
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    let a = ([1, 2] : int["abc".len])
+    a[0]
+"#).is_err());
+
+    // A runtime value is not folded
+    let err = type_check_error(r#"
+fn greet(name: string) -> string: "hello, " + name
+
+fn main() -> int: greet("world").len as int
+"#);
+    assert!(err.contains("Operator + is not supported on string"));
+
+    let err = type_check_error(r#"
+var greeting = "hello"
+
+fn main() -> uint: (greeting + "!").len
+"#);
+    assert!(err.contains("Operator + is not supported on string"));
+}
+
+#[test]
+fn test_type_map()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+fn inc(x: int) -> int: x + 1
+fn sum(v: int[]) -> int: 0
+fn main() -> int: inc(inc(sum([1, 2, 3])))
+"#, &target);
+    assert!(type_check_module(&mut md, &target, &ImportMap::new(), &mut PassTimings::new()).is_ok());
+
+    let tm = TypeMap::from_module(&md, &target);
+    let int_type = target.native_int_type.clone();
+    let hover = |line: usize, col: usize| {
+        let (span, typ) = tm.type_at("", line, col).expect("No type at position");
+        (span.start, typ)
+    };
+
+    // Every nesting level of the call chain
+    assert_eq!(hover(4, 20), (Pos::new(4, 19), int_type.clone()));
+    assert_eq!(hover(4, 24), (Pos::new(4, 23), int_type.clone()));
+    assert_eq!(hover(4, 28), (Pos::new(4, 27), int_type.clone()));
+    assert_eq!(hover(4, 35), (Pos::new(4, 35), int_type.clone()));
+
+    // Literals and name refs
+    assert_eq!(hover(2, 24), (Pos::new(2, 24), int_type.clone()));
+    assert_eq!(hover(2, 26), (Pos::new(2, 24), int_type.clone()));
+    assert_eq!(hover(2, 28), (Pos::new(2, 28), int_type.clone()));
+
+    // The array literal is converted to a slice when passed to sum
+    let converted = tm.entry_at("", 4, 31).expect("No type at position");
+    assert_eq!(converted.span.start, Pos::new(4, 31));
+    assert_eq!(converted.typ, array_type(int_type.clone(), 3));
+    assert!(converted.is_synthetic());
+    assert_eq!(converted.conversion, Some(slice_type(int_type.clone())));
+    assert!(!tm.entry_at("", 4, 35).unwrap().is_synthetic());
+
+    assert!(tm.type_at("", 1, 1).is_none());
+    assert!(tm.type_at("other.mhr", 4, 20).is_none());
+}
+
+#[test]
+fn test_type_name_and_static_assert()
+{
+    assert!(type_check_mod(r#"
+struct Point:
+    x: int
+    y: int
+
+fn describe(x: $T) -> int:
+    @static_assert(@type_name(x) == "int32", "describe expects an int")
+    1
+
+fn main() -> int:
+    @static_assert(@type_name(Point{1, 2}) == "test::Point", "Point has the wrong name")
+    @static_assert(!(@type_name(7u) != "uint32") && true, "unsigned")
+    describe(4)
+"#).is_ok());
+
+    // The generic instance for bool fails the assertion
+    let err = type_check_error(r#"
+fn describe(x: $T) -> int:
+    @static_assert(@type_name(x) == "int32", "describe expects an int")
+    1
+
+fn main() -> int: describe(true)
+"#);
+    assert!(err.contains("Static assertion failed: describe expects an int"));
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    @static_assert(@type_name(4) == "string", "not a " + "string")
+    0
+"#);
+    assert!(err.contains("Static assertion failed: not a string"));
+
+    // Constant globals are folded in the condition and the message
+    let err = type_check_error(r#"
+let MODE = "release"
+let MESSAGE = "only debug builds"
+
+fn main() -> int:
+    @static_assert(MODE == "release", "not a release build")
+    @static_assert(MODE == "debug", MESSAGE)
+    0
+"#);
+    assert!(err.contains("Static assertion failed: only debug builds"));
+
+    let err = type_check_error(r#"
+fn check(n: int) -> int:
+    @static_assert(n > 0, "positive")
+    n
+"#);
+    assert!(err.contains("The condition of @static_assert must be a constant bool expression"));
+}
+
+#[test]
+fn test_discard_name_is_not_a_value()
+{
+    let err = type_check_error(r#"
+fn main() -> int:
+    let _ = 5
+    _ + 1
+"#);
+    assert!(err.contains("`_` is not a value"));
+
+    assert!(type_check_mod(r#"
+fn third(_: int, _: bool, c: int) -> int: c
+
+fn main() -> int:
+    let _ = third(1, true, 2)
+    var n = 0
+    for _ in [1, 2]:
+        n += 1
+    n
+"#).is_ok());
+}
+
+#[test]
+fn test_exported_functions()
+{
+    assert!(type_check_mod(r#"
+#[export("menhir_add")]
+fn add(a: int32, b: int32) -> int32: a + b
+
+#[export]
+fn clear(var p: *int):
+    *p = 0
+"#).is_ok());
+
+    let err = type_check_error(r#"
+#[export]
+fn add(a: int32, b: int32) -> int32: a + b
+
+#[export("add")]
+fn plus(a: int32, b: int32) -> int32: a + b
+"#);
+    assert!(err.contains("Exported symbol add is used by both test::add"));
+    assert!(err.contains("and test::plus"));
+
+    let err = type_check_error(r#"
+#[export]
+fn length(s: string) -> int: s.len as int
+"#);
+    assert!(err.contains("Argument s of exported function test::length has type string, which cannot be passed from C"));
+}
+
+#[test]
+fn test_external_link_names()
+{
+    assert!(type_check_mod(r#"
+#[link_name = "sqrt"]
+extern fn square_root(x: double) -> double
+
+fn root() -> double: square_root(16.0)
+"#).is_ok());
+
+    let err = type_check_error(r#"
+#[link_name = "sqrt"]
+extern fn square_root(x: double) -> double
+
+extern fn sqrt(x: float) -> float
+"#);
+    assert!(err.contains("Symbol sqrt is linked by both square_root"));
+    assert!(err.contains("and sqrt"));
+
+    let err = type_check_error(r#"
+#[link_name = "not a symbol"]
+extern fn foo() -> int32
+"#);
+    assert!(err.contains("not a symbol is not a valid C symbol name, it cannot be the link_name of foo"));
+}
+
+#[test]
+fn test_interface_values()
+{
+    assert!(type_check_mod(r#"
+interface Shape:
+    fn area(self) -> int
+
+struct Square:
+    side: int
+
+fn Square.area(self) -> int: self.side * self.side
+
+fn double_area(s: Shape) -> int: s.area() * 2
+
+fn main() -> int:
+    let sq = Square{3}
+    double_area(sq) + double_area(&sq)
+"#).is_ok());
+
+    let err = type_check_error(r#"
+interface Shape:
+    fn area(self) -> int
+
+struct Circle:
+    radius: int
+
+fn area(s: Shape) -> int: s.area()
+
+fn main() -> int: area(Circle{2})
+"#);
+    assert!(err.contains("Type test::Circle does not implement the interface test::Shape: No method area found on type test::Circle"));
+
+    let err = type_check_error(r#"
+interface Comparable:
+    fn less(self, other: *Self) -> bool
+
+struct Num:
+    n: int
+
+fn Num.less(self, other: *Num) -> bool: self.n < other.n
+
+fn first(c: Comparable) -> bool: true
+
+fn check() -> bool: first(Num{1})
+"#);
+    assert!(err.contains("Interface test::Comparable cannot be used as the type of a value, because its function less uses Self"));
+}
+
+#[test]
+fn test_iterators()
+{
+    assert!(type_check_mod(r#"
+struct Countdown:
+    n: int
+
+fn Countdown.next(var self) -> ?int:
+    if self.n == 0:
+        return nil
+    self.n -= 1
+    self.n + 1
+
+struct Bag:
+    size: int
+
+fn Bag.iterate(self) -> Countdown: Countdown{self.size}
+
+fn main() -> int:
+    var sum = 0
+    for i in Countdown{4}:
+        for j in Bag{i}:
+            sum += i * j
+    sum
+"#).is_ok());
+
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    let m = Map<string, int>()
+    insert(m, "one", 1)
+    var total = 0
+    for entry in m:
+        total += entry.1 + entry.0.len as int
+    total
+"#).is_ok());
+
+    let err = type_check_error(r#"
+struct Point{x: int, y: int}
+
+fn main():
+    for p in Point{1, 2}:
+        _ = p
+"#);
+    assert!(err.contains("5:5 -> "));
+    assert!(err.contains("Cannot iterate over expressions of type test::Point, it needs an iterate or a next member function"));
+
+    let err = type_check_error(r#"
+struct Counter{n: int}
+
+fn Counter.next(var self) -> int:
+    self.n += 1
+    self.n
+
+fn main():
+    for i in Counter{0}:
+        _ = i
+"#);
+    assert!(err.contains("The next member function of test::Counter must return an optional"));
+}
+
+#[test]
+fn test_void_values()
+{
+    assert!(type_check_mod(r#"
+var counter = 0
+
+fn tick():
+    counter += 1
+
+fn apply(f: fn() -> void):
+    f()
+
+fn main() -> int:
+    tick()
+    let _ = tick()
+    apply(tick)
+    if counter > 1: tick()
+    counter
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn tick():
+    _ = 1
+
+fn main() -> int:
+    let x = tick()
+    0
+"#);
+    assert!(err.contains("6:5 -> "));
+    assert!(err.contains("Expression has no value, it cannot be bound to x"));
+
+    let err = type_check_error(r#"
+fn tick():
+    _ = 1
+
+let global = tick()
+"#);
+    assert!(err.contains("Expression has no value, it cannot be bound to global"));
+
+    let err = type_check_error(r#"
+fn tick():
+    _ = 1
+
+fn main():
+    let a = [tick(), tick()]
+"#);
+    assert!(err.contains("6:14 -> "));
+    assert!(err.contains("Expression has no value, it cannot be an array element"));
+
+    let err = type_check_error(r#"
+struct Empty:
+    nothing: void
+"#);
+    assert!(err.contains("3:5 -> "));
+    assert!(err.contains("Member nothing of test::Empty has type void, but void has no values"));
+
+    let err = type_check_error(r#"
+fn ignore(v: void):
+    _ = 1
+"#);
+    assert!(err.contains("Argument v of test::ignore has type void, but void has no values"));
+
+    let err = type_check_error(r#"
+fn nothing() -> ?void: nil
+"#);
+    assert!(err.contains("test::nothing returns ?void, but void has no values"));
+
+    let err = type_check_error(r#"
+fn first(xs: void[]):
+    _ = 1
+"#);
+    assert!(err.contains("Argument xs of test::first has type void[], but void has no values"));
+
+    let err = type_check_error(r#"
+fn tick():
+    _ = 1
+
+fn maybe(c: bool):
+    if c: tick() else nil
+"#);
+    assert!(err.contains("One branch of the if expression has no value"));
+
+    // The void argument is only found when twice is instantiated, so the error points at the call doing that
+    let err = type_check_error(r#"
+fn tick():
+    _ = 1
+
+fn id(x: $T) -> $T: x
+
+fn twice(x: $T) -> $T: id(id(x))
+
+fn main():
+    twice(tick())
+"#);
+    assert!(err.contains("Argument x of test::twice<void> has type void, but void has no values"));
+    assert!(err.contains("10:5 -> "));
+    assert!(err.contains("test::twice<void> is instantiated here, in test::main"));
+}
+
+#[test]
+fn test_default_arguments()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+fn connect(host: string, port: uint16 = 8080, retries: int32 = -3) -> int32: retries
+
+fn main() -> int:
+    connect("a") + connect("b", 80) + connect("c", retries: 5)
+"#, &target);
+    assert!(type_check_module(&mut md, &target, &ImportMap::new(), &mut PassTimings::new()).is_ok());
+
+    let mut calls = Vec::new();
+    let f = md.functions.get("test::main").expect("Unknown function");
+    if let Expression::BinaryOp(ref outer) = f.expression {
+        if let (&Expression::BinaryOp(ref inner), &Expression::Call(ref c)) = (&outer.left, &outer.right) {
+            if let (&Expression::Call(ref a), &Expression::Call(ref b)) = (&inner.left, &inner.right) {
+                calls = vec![a.clone(), b.clone(), c.clone()];
+            }
+        }
+    }
+    assert_eq!(calls.len(), 3);
+
+    // Every call gets all arguments, left out ones become the default value
+    let values = |c: &Call| -> Vec<String> {
+        c.args.iter().map(|a| match *a {
+            Expression::Literal(Literal::Int(_, v, _)) => v.to_string(),
+            Expression::Literal(Literal::UInt(_, v, _)) => v.to_string(),
+            Expression::Literal(Literal::String(_, ref s)) => s.clone(),
+            _ => panic!("Expected a literal"),
+        }).collect()
+    };
+    assert_eq!(values(&calls[0]), vec!["a", "8080", "-3"]);
+    assert_eq!(values(&calls[1]), vec!["b", "80", "-3"]);
+    assert_eq!(values(&calls[2]), vec!["c", "8080", "5"]);
+
+    // A generic function keeps its default in every instance
+    assert!(type_check_mod(r#"
+fn scale(v: $T, times: int = 2) -> int: times
+
+fn main() -> int: scale(1) + scale(true, 3) + scale(2, times: 4)
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn connect(host: string, port: uint16 = 8080) -> int: 0
+
+fn main() -> int: connect()
+"#);
+    assert!(err.contains("Attempting to call test::connect with 0 arguments, but it needs at least 1 and at most 2, missing required arguments: host"));
+
+    let err = type_check_error(r#"
+fn connect(host: string, port: uint16 = 8080) -> int: 0
+
+fn main() -> int: connect("a", 1, 2)
+"#);
+    assert!(err.contains("Attempting to call test::connect with 3 arguments, but it needs at least 1 and at most 2"));
+
+    let err = type_check_error(r#"
+fn next_port() -> uint16: 8080
+
+fn connect(host: string, port: uint16 = next_port()) -> int: 0
+"#);
+    assert!(err.contains("4:41 -> "));
+    assert!(err.contains("Default value of argument port of test::connect must be a constant expression"));
+
+    let err = type_check_error(r#"
+fn connect(host: string, port: uint16 = "8080") -> int: 0
+"#);
+    assert!(err.contains("Default value of argument port of test::connect has type string, but the argument has type uint16"));
+
+    let err = type_check_error(r#"
+fn pick(a: $T, b: $T = 1) -> $T: a
+"#);
+    assert!(err.contains("Argument b of test::pick has generic type $T, so it cannot have a default value"));
+}
+
+#[test]
+fn test_instantiation_graph()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+fn a(x: $t) -> $t: b(x)
+fn b(x: $t) -> $t: c(x)
+fn c(x: $t) -> $t: x
+
+fn main() -> int: a(1) + a(2) + c(3)
+"#, &target);
+    let imports = ImportMap::new();
+    assert!(type_check_module(&mut md, &target, &imports, &mut PassTimings::new()).is_ok());
+
+    let graph = &md.instantiations;
+    assert_eq!(graph.len(), 3);
+    let depth = |name: &str| graph.get(name).map(|i| i.depth).unwrap_or(0);
+    assert_eq!(depth("test::a<int32>"), 1);
+    assert_eq!(depth("test::b<int32>"), 2);
+    assert_eq!(depth("test::c<int32>"), 1);
+    assert_eq!(graph.chain("test::b<int32>"), vec!["test::main", "test::a<int32>", "test::b<int32>"]);
+
+    // main is checked before the instances it requires, so c<int32> is first required by main,
+    // b<int32> requiring it as well only adds an edge
+    let c = graph.get("test::c<int32>").expect("No instance for c");
+    assert_eq!(c.generic, "test::c");
+    assert_eq!(c.root, "test::c<int32>");
+    assert_eq!(graph.chain("test::c<int32>"), vec!["test::main", "test::c<int32>"]);
+
+    let edges: Vec<(&str, &str)> = graph.edges().map(|(from, to, _)| (from, to)).collect();
+    assert_eq!(edges, vec![
+        ("test::a<int32>", "test::b<int32>"),
+        ("test::b<int32>", "test::c<int32>"),
+        ("test::main", "test::a<int32>"),
+        ("test::main", "test::c<int32>"),
+    ]);
+    assert_eq!(graph.required_by("test::main").collect::<Vec<_>>(), vec!["test::a<int32>", "test::c<int32>"]);
+
+    let dot = graph.to_dot("test");
+    assert!(dot.starts_with("digraph \"test\" {"));
+    assert!(dot.contains("\"test::main\" -> \"test::a<int32>\""));
+}
+
+#[test]
+fn test_instantiation_limits()
+{
+    // Every instance of nest requires an instance for an array of the previous type
+    let src = r#"
+fn nest(x: $T, n: int) -> int:
+    if n == 0:
+        return 0
+    nest([x], n - 1)
+
+fn main() -> int: nest(1, 3)
+"#;
+
+    let target = Target::new(IntSize::I32, "");
+    let imports = ImportMap::new();
+    let mut md = th_mod(src, &target);
+    md.instantiations.limits.max_depth = 8;
+    let err = format!("{}", type_check_module(&mut md, &target, &imports, &mut PassTimings::new()).expect_err("Expected a type check error"));
+    assert!(err.contains("exceeds the limit of 8 nested generic instantiations, the chain of instantiations is:"));
+    assert!(err.contains("\n  test::main\n  test::nest<int32>\n  test::nest<int32[1]>\n"));
+    assert!(err.contains("test::nest<int32> is instantiated here, in test::main"));
+    assert_eq!(md.instantiations.len(), 8);
+
+    let mut md = th_mod(src, &target);
+    md.instantiations.limits.max_per_root = 5;
+    let err = format!("{}", type_check_module(&mut md, &target, &imports, &mut PassTimings::new()).expect_err("Expected a type check error"));
+    assert!(err.contains("exceeds the limit of 5 generic instantiations required by test::nest<int32>"));
+}
+
+fn slice_of_int() -> Type
+{
+    slice_type(Type::Int(IntSize::I32))
+}
+
+#[test]
+fn test_array_to_slice_function_return()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+let EVENS = [2, 4, 6]
+
+fn evens() -> int[]: EVENS
+
+fn main() -> int: evens().len as int
+"#, &target);
+    let imports = ImportMap::new();
+    assert!(type_check_module(&mut md, &target, &imports, &mut PassTimings::new()).is_ok());
+
+    // The conversion has its type filled in, without the body being type checked again
+    let f = md.functions.get("test::evens").expect("Unknown function");
+    assert_eq!(f.expression.get_type(IntSize::I32), slice_of_int());
+}
+
+#[test]
+fn test_array_to_slice_struct_member()
+{
+    assert!(type_check_mod(r#"
+struct Bag:
+    items: int[]
+
+fn main() -> int:
+    let b = Bag{[1, 2, 3]}
+    b.items.len as int
+"#).is_ok());
+}
+
+#[test]
+fn test_array_to_slice_let_annotation()
+{
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    let xs: int[] = [1, 2, 3]
+    let ys: int[] = []
+    xs.len as int + ys.len as int
+"#).is_ok());
+
+    let err = type_check_error(r#"
+fn main() -> int:
+    let x: string = 7
+    0
+"#);
+    assert!(err.contains("Expecting an expression of type string or something convertible to, but found one of type int32"));
+}
+
+#[test]
+fn test_array_to_slice_branches()
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+fn pick(n: int, xs: int[]) -> int[]:
+    match n:
+        0 => [1, 2, 3]
+        1 => [4, 5]
+        _ => xs
+
+fn choose(c: bool, xs: int[]) -> int[]:
+    if c: [1, 2, 3] else xs
+
+fn main() -> int: pick(0, [1]).len as int + choose(true, [2]).len as int
+"#, &target);
+    let imports = ImportMap::new();
+    assert!(type_check_module(&mut md, &target, &imports, &mut PassTimings::new()).is_ok());
+    for name in &["test::pick", "test::choose"] {
+        let f = md.functions.get(*name).expect("Unknown function");
+        assert_eq!(f.expression.get_type(IntSize::I32), slice_of_int());
+    }
+
+    // Arrays of different lengths become a slice as well
+    assert!(type_check_mod(r#"
+fn main() -> int:
+    let xs = if true: [1, 2] else [3]
+    xs.len as int
+"#).is_ok());
+}
+
+#[test]
+fn test_array_to_slice_element_type_mismatch()
+{
+    let err = type_check_error(r#"
+fn main() -> int:
+    let xs: int[] = [1.5, 2.5]
+    0
+"#);
+    assert!(err.contains("Expecting an expression of type int32[] or something convertible to, but found one of type float64[2]"));
+
+    let err = type_check_error(r#"
+fn floats() -> float64[]: [1, 2]
+"#);
+    assert!(err.contains("Function test::floats has return type float64[], but it is returning an expression of type int32[2]"));
+
+    let err = type_check_error(r#"
+fn pick(n: int, xs: int[]) -> int[]:
+    match n:
+        0 => [true]
+        _ => xs
+"#);
+    assert!(err.contains("Expressions in match statements must return the same type"));
+}
+
+fn check_main(src: &str) -> CompileResult<()>
+{
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(src, &target);
+    type_check_module(&mut md, &target, &ImportMap::new(), &mut PassTimings::new())?;
+    check_main_function(&md, "test::main", &target)
+}
+
+#[test]
+fn test_main_function()
+{
+    assert!(check_main("fn main() -> int: 0").is_ok());
+    assert!(check_main("fn main() -> void:\n    return").is_ok());
+    assert!(check_main("fn main() -> uint: 0u").is_ok());
+
+    let msg = |src: &str| format!("{}", check_main(src).expect_err("Expected an invalid main"));
+    assert!(msg("fn main() -> float64: 1.0").contains("main returns float64 but must return int32, uint32 or void"));
+    assert!(msg("fn main(x: int) -> int: x").contains("main takes 1 arguments but must take none, command line arguments are not supported"));
+    assert!(msg("fn main(x: $T) -> int: 0").contains("main must not be generic"));
+    assert_eq!(msg("fn start() -> int: 0"), "No main function found, an executable starts at test::main, did you mean to compile with --lib?\n");
+}
+
+// The errors of wrong calls to builtin functions are in testcode/typecheck/builtin_arguments.mhr
+#[test]
+fn test_fixtures()
+{
+    let checker = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(|| check_fixture_dir("testcode/typecheck"));
+    if let Err(problems) = checker.expect("Unable to start thread").join().expect("Type checking crashed") {
+        panic!("Type checker fixtures failed:\n{}", problems);
+    }
+}
+
+#[test]
+fn test_fixture_annotations()
+{
+    let checker = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(|| {
+        let both = "fn f() -> int: y #~ ERROR Unknown name y\nfn g() -> int: z #~ ERROR Unknown name z\n";
+        assert!(check_fixture("both", both).is_ok());
+
+        // The error of g moved to the next line
+        let moved = "fn f() -> int: y #~ ERROR Unknown name y\nfn g() -> int: #~ ERROR Unknown name z\n    z\n";
+        assert_eq!(check_fixture("moved", moved),
+            Err("moved:2: expected an error containing `Unknown name z`, but it is reported on line 3, move the annotation".into()));
+
+        let missing = "fn f() -> int: 7 #~ ERROR Unknown name y\n";
+        assert_eq!(check_fixture("missing", missing), Err("missing:1: expected an error containing `Unknown name y`, but there is none".into()));
+
+        let unexpected = "fn f() -> int: y\n";
+        let problems = check_fixture("unexpected", unexpected).expect_err("Unexpected error not reported");
+        assert!(problems.starts_with("unexpected:1: unexpected error: Unknown name y"));
+    });
+    checker.expect("Unable to start thread").join().expect("Fixture checks failed");
+}
+
+// Every error code has an example which gives that error, and a fix for it which type checks
+#[test]
+fn test_error_code_examples()
+{
+    let checker = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(|| {
+        for &code in ERROR_CODES
+        {
+            let e = code.explanation();
+            let codes = fixture_error_codes(e.example);
+            assert!(codes.contains(&code), "The example of {} gives the errors {:?}", e.code, codes);
+            if let Err(problems) = check_fixture(e.code, e.fix) {
+                panic!("The fix of {} does not type check:\n{}", e.code, problems);
+            }
+        }
+    });
+    checker.expect("Unable to start thread").join().expect("Error code examples failed");
+}
+
+#[test]
+fn test_assert_type_only_in_test_builds()
+{
+    let err = type_check_error(r#"
+fn main() -> int:
+    @assert_type(7, "int32")
+    0
+"#);
+    assert!(err.contains("@assert_type is only allowed in test builds, compile with --cfg feature=test"));
+}
@@ -1,14 +1,24 @@
 use std::ops::Deref;
+use std::iter;
+use std::collections::HashMap;
 use ast::*;
-use compileerror::{CompileResult, CompileError, type_error, unknown_type_result, unknown_name, type_error_result};
-use super::typecheckercontext::{TypeCheckerContext, ImportSymbolResolver};
+use compileerror::{CompileResult, CompileError, type_error, unknown_type_result, type_error_result};
+use errorcodes::ErrorCode;
+use super::typecheckercontext::{TypeCheckerContext, ImportSymbolResolver, NameKind};
+use super::suggestions::{closest_names, with_suggestions};
 use super::instantiategenerics::instantiate_generics;
-use super::typeresolver::{resolve_type, resolve_types, TypeResolved};
+use super::typeresolver::{resolve_type, resolve_types, check_generic_arg_count, collect_generic_params, TypeResolved, VOID_HAS_NO_VALUES};
 use super::matchchecker::check_match_is_exhaustive;
+use super::floatequality::warn_about_float_equality;
+use super::unreachable::warn_about_unreachable_code;
+use super::objectsize::check_object_sizes;
+use super::exports::{check_exported_functions, check_external_functions};
+use super::constantfolding::{fold_binary_op_in_context, fold_member_access_in_context, constant_bool_in_context, constant_string_in_context, has_generic_type_name};
 use super::genericmapper::fill_in_generics;
-use super::instantiate::make_concrete;
-use target::Target;
+use super::instantiate::{make_concrete, satisfies_interface};
+use target::{Target, TEST_FEATURE};
 use span::Span;
+use timer::PassTimings;
 
 #[derive(Debug)]
 enum TypeCheckAction
@@ -41,14 +51,30 @@ fn replace_by(e: Expression) -> TypeCheckResult
     Ok(TypeCheckAction::ReplaceBy(e))
 }
 
+// All implicit conversions go through here, so they apply the same way everywhere an expression is expected to have a type.
+// The conversion is complete, so it can be used where the expression cannot be type checked again.
+fn coerce(ctx: &TypeCheckerContext, expr: &Expression, from_type: &Type, to_type: &Type, target: &Target) -> CompileResult<Option<Expression>>
+{
+    let mut converted = match to_type.convert(from_type, expr) {
+        Some(converted) => converted,
+        None => return Ok(None),
+    };
+
+    if let Expression::ToInterface(ref mut t) = converted {
+        resolve_interface_methods(ctx, t, target.int_size)?;
+    }
+    Ok(Some(converted))
+}
+
 fn convert_type(ctx: &mut TypeCheckerContext, dst_type: &Type, src_type: &Type, expr: &mut Expression, target: &Target) -> CompileResult<()>
 {
-    if *dst_type == *src_type {
+    // An expression which always returns has no value to convert
+    if *dst_type == *src_type || expr.diverges() {
         return Ok(());
     }
 
     let mut converted = false;
-    if let Some(new_expression) = dst_type.convert(src_type, expr) {
+    if let Some(new_expression) = coerce(ctx, expr, src_type, dst_type, target)? {
         *expr = new_expression;
         converted = true;
     }
@@ -59,18 +85,76 @@ fn convert_type(ctx: &mut TypeCheckerContext, dst_type: &Type, src_type: &Type,
             converted = true;
         }
     }
-    
+
+    // A negative literal like -1 is a negation of a literal, which can be converted to any signed type
+    if let (&Type::Int(_), &mut Expression::UnaryOp(ref mut u)) | (&Type::Float(_), &mut Expression::UnaryOp(ref mut u)) = (dst_type, &mut *expr) {
+        let new_lit = match (u.operator, &u.expression) {
+            (UnaryOperator::Sub, &Expression::Literal(ref lit)) => lit.try_convert(dst_type),
+            _ => None,
+        };
+
+        if let Some(new_lit) = new_lit {
+            u.expression = Expression::Literal(new_lit);
+            converted = true;
+        }
+    }
+
     if converted {
         assert_eq!(type_check_expression(ctx, expr, None, target)?, *dst_type);
         Ok(())
     } else {
-        type_error_result(
+        ErrorCode::TypeMismatch.result(
             &expr.span(),
             format!("Expecting an expression of type {} or something convertible to, but found one of type {}", dst_type, src_type))
     }
 }
 
 
+fn uses_self_type(typ: &Type) -> bool
+{
+    match *typ
+    {
+        Type::SelfType => true,
+        Type::Pointer(ref inner) |
+        Type::Optional(ref inner) => uses_self_type(inner),
+        Type::Array(ref at) => uses_self_type(&at.element_type),
+        Type::Slice(ref st) => uses_self_type(&st.element_type),
+        _ => false,
+    }
+}
+
+// Functions of an interface value are called with the value as self, and there is nothing to pass or return as Self
+fn check_interface_value_type(it: &InterfaceType, span: &Span) -> CompileResult<()>
+{
+    for func in &it.functions {
+        if func.args.first().map(|a| a.typ != ptr_type(Type::SelfType)).unwrap_or(true) {
+            return type_error_result(span,
+                format!("Interface {} cannot be used as the type of a value, because its function {} has no self argument", it.name, func.name));
+        }
+
+        if func.args.iter().skip(1).any(|a| uses_self_type(&a.typ)) || uses_self_type(&func.return_type) {
+            return type_error_result(span,
+                format!("Interface {} cannot be used as the type of a value, because its function {} uses Self", it.name, func.name));
+        }
+    }
+    Ok(())
+}
+
+// Check that the type converted to an interface implements it, and find the functions which do
+fn resolve_interface_methods(ctx: &TypeCheckerContext, t: &mut ToInterface, int_size: IntSize) -> CompileResult<()>
+{
+    let inner_type = t.inner.get_type(int_size);
+    let concrete_type = inner_type.get_pointer_element_type().unwrap_or(&inner_type);
+    if let Type::Interface(ref it) = t.interface_type {
+        check_interface_value_type(it, &t.inner.span())?;
+    }
+
+    t.methods = satisfies_interface(ctx, concrete_type, &t.interface_type)
+        .map_err(|msg| type_error(&t.inner.span(),
+            format!("Type {} does not implement the interface {}: {}", concrete_type.name(), t.interface_type.name(), msg)))?;
+    Ok(())
+}
+
 fn type_check_unary_op(ctx: &mut TypeCheckerContext, u: &mut UnaryOp, target: &Target) -> TypeCheckResult
 {
     let e_type = type_check_expression(ctx, &mut u.expression, None, target)?;
@@ -83,7 +167,7 @@ fn type_check_unary_op(ctx: &mut TypeCheckerContext, u: &mut UnaryOp, target: &T
     {
         UnaryOperator::Sub => {
             if !e_type.is_numeric() {
-                type_error_result(&u.span, format!("Unary operator {} expects a numeric expression", u.operator))
+                ErrorCode::UnsupportedOperator.result(&u.span, format!("Unary operator {} expects a numeric expression", u.operator))
             } else {
                 u.typ = e_type.clone();
                 valid(e_type)
@@ -102,18 +186,25 @@ fn type_check_unary_op(ctx: &mut TypeCheckerContext, u: &mut UnaryOp, target: &T
 
 fn type_check_with_conversion(ctx: &mut TypeCheckerContext, e: &mut Expression, expected_type: &Type, target: &Target) -> CompileResult<()>
 {
-    let typ = type_check_expression(ctx, e, None, target)?;
+    // null and array literals take the type they are expected to have, everything else gets converted afterwards
+    let type_hint = match *e {
+        Expression::Literal(Literal::NullPtr(..)) | Expression::Literal(Literal::Array(_)) => Some(expected_type),
+        _ => None,
+    };
+    let typ = type_check_expression(ctx, e, type_hint, target)?;
     convert_type(ctx, expected_type, &typ, e, target)
 }
 
 fn basic_bin_op_checks(ctx: &mut TypeCheckerContext, b: &mut BinaryOp, left_type: Type, right_type: Type, target: &Target) -> CompileResult<()>
 {
     if left_type != right_type {
-        let result = type_check_with_conversion(ctx, &mut b.right, &left_type, target)
-            .or(type_check_with_conversion(ctx, &mut b.left, &right_type, target));
+        let result = match type_check_with_conversion(ctx, &mut b.right, &left_type, target) {
+            Ok(()) => Ok(()),
+            Err(_) => type_check_with_conversion(ctx, &mut b.left, &right_type, target),
+        };
 
         if !result.is_ok() {
-            return type_error_result(
+            return ErrorCode::UnsupportedOperator.result(
                 &b.span,
                 format!("Operator {} expects operands of the same type (left type: {}, right type: {})", b.operator, left_type, right_type))
         }
@@ -121,18 +212,126 @@ fn basic_bin_op_checks(ctx: &mut TypeCheckerContext, b: &mut BinaryOp, left_type
 
     let new_left_type = b.left.get_type(target.int_size);
     if !new_left_type.is_binary_operator_supported(b.operator) {
-        type_error_result(&b.span, format!("Operator {} is not supported on {}", b.operator, new_left_type))
+        ErrorCode::UnsupportedOperator.result(&b.span, format!("Operator {} is not supported on {}", b.operator, new_left_type))
     } else {
         Ok(())
     }
 }
 
+// Types by name instead of by structure, to keep error messages readable
+fn type_description(typ: &Type) -> String
+{
+    match *typ
+    {
+        Type::Func(ref ft) => {
+            let args: Vec<String> = ft.args.iter().map(|a| a.name()).collect();
+            format!("({}) -> {}", args.join(", "), ft.return_type.name())
+        },
+        _ => typ.name(),
+    }
+}
+
+/*
+    Operators on structs and sum types are implemented by functions named after the type:
+    T.op_add, T.op_sub, T.op_mul, T.op_div and T.op_mod with type (T, T) -> T,
+    T.op_equals and T.op_less with type (T, T) -> bool.
+    !=, >, <= and >= are derived from op_equals and op_less.
+    Tuples have no name, they are compared member by member.
+*/
+fn user_defined_operator(ctx: &TypeCheckerContext, b: &BinaryOp, left_type: &Type, right_type: &Type) -> CompileResult<Option<Expression>>
+{
+    match *left_type {
+        Type::Struct(ref st) if !st.name.is_empty() => (),
+        Type::Sum(_) => (),
+        _ => return Ok(None),
+    }
+
+    // Function name, whether the operands are swapped and whether the result is negated
+    let (func_name, swap, negate) = match b.operator {
+        BinaryOperator::Add => ("op_add", false, false),
+        BinaryOperator::Sub => ("op_sub", false, false),
+        BinaryOperator::Mul => ("op_mul", false, false),
+        BinaryOperator::Div => ("op_div", false, false),
+        BinaryOperator::Mod => ("op_mod", false, false),
+        BinaryOperator::Equals => ("op_equals", false, false),
+        BinaryOperator::NotEquals => ("op_equals", false, true),
+        BinaryOperator::LessThan => ("op_less", false, false),
+        BinaryOperator::GreaterThan => ("op_less", true, false),
+        BinaryOperator::LessThanEquals => ("op_less", true, true),
+        BinaryOperator::GreaterThanEquals => ("op_less", false, true),
+        _ => return Ok(None),
+    };
+
+    let return_type = if func_name == "op_equals" || func_name == "op_less" {Type::Bool} else {left_type.clone()};
+    let expected_type = func_type(vec![left_type.clone(), left_type.clone()], return_type);
+    let type_name = left_type.name();
+    let name = format!("{}.{}", type_name, func_name);
+    let resolved = match ctx.resolve(&name) {
+        Some(resolved) => resolved,
+        None => return ErrorCode::UnsupportedOperator.result(&b.span,
+            format!("Operator {} is not supported on {}, it requires a function {} with type {}",
+                b.operator, type_name, name, type_description(&expected_type))),
+    };
+
+    if resolved.typ != expected_type {
+        return ErrorCode::UnsupportedOperator.result(&b.span,
+            format!("Operator {} on {} requires {} to have type {}, but it has type {}",
+                b.operator, type_name, name, type_description(&expected_type), type_description(&resolved.typ)));
+    }
+
+    if *left_type != *right_type {
+        return ErrorCode::UnsupportedOperator.result(&b.span,
+            format!("Operator {} expects operands of the same type (left type: {}, right type: {})", b.operator, left_type, right_type));
+    }
+
+    let args = if swap {vec![b.right.clone(), b.left.clone()]} else {vec![b.left.clone(), b.right.clone()]};
+    let call = Expression::Call(Box::new(Call::new(NameRef::new(resolved.name, b.span.clone()), args, b.span.clone())));
+    if negate {
+        Ok(Some(unary_op(UnaryOperator::Not, call, b.span.clone())))
+    } else {
+        Ok(Some(call))
+    }
+}
+
+fn is_null_literal(e: &Expression) -> bool
+{
+    matches!(*e, Expression::Literal(Literal::NullPtr(..)))
+}
+
 fn type_check_binary_op(ctx: &mut TypeCheckerContext, b: &mut BinaryOp, target: &Target) -> TypeCheckResult
 {
-    let left_type = type_check_expression(ctx, &mut b.left, None, target)?;
-    let right_type = type_check_expression(ctx, &mut b.right, None, target)?;
+    // null gets its type from the other operand
+    let (left_type, right_type) = if is_null_literal(&b.left) && !is_null_literal(&b.right) {
+        let right_type = type_check_expression(ctx, &mut b.right, None, target)?;
+        (type_check_expression(ctx, &mut b.left, Some(&right_type), target)?, right_type)
+    } else {
+        let left_type = type_check_expression(ctx, &mut b.left, None, target)?;
+        let right_hint = if is_null_literal(&b.right) {Some(&left_type)} else {None};
+        let right_type = type_check_expression(ctx, &mut b.right, right_hint, target)?;
+        (left_type, right_type)
+    };
     if left_type.is_generic() || right_type.is_generic() {
-        return valid(left_type);
+        // The operator gets checked when the function is instantiated
+        return match b.operator
+        {
+            BinaryOperator::LessThan |
+            BinaryOperator::GreaterThan |
+            BinaryOperator::LessThanEquals |
+            BinaryOperator::GreaterThanEquals |
+            BinaryOperator::Equals |
+            BinaryOperator::NotEquals => valid(Type::Bool),
+            _ => valid(left_type),
+        };
+    }
+
+    if let Some(call) = user_defined_operator(ctx, b, &left_type, &right_type)? {
+        return replace_by(call);
+    }
+
+    if left_type == Type::String && right_type == Type::String {
+        if let Some(lit) = fold_binary_op_in_context(ctx, b, target) {
+            return replace_by(Expression::Literal(lit));
+        }
     }
 
     match b.operator
@@ -142,6 +341,10 @@ fn type_check_binary_op(ctx: &mut TypeCheckerContext, b: &mut BinaryOp, target:
         BinaryOperator::Mul |
         BinaryOperator::Div |
         BinaryOperator::Mod => {
+            if left_type.is_pointer() || right_type.is_pointer() {
+                return ErrorCode::UnsupportedOperator.result(&b.span,
+                    format!("Operator {} is not supported on pointers, there is no pointer arithmetic, index a slice instead", b.operator));
+            }
             basic_bin_op_checks(ctx, b, left_type, right_type, target)?;
             b.typ = b.left.get_type(target.int_size);
             valid(b.typ.clone())
@@ -192,24 +395,58 @@ fn type_check_binary_op(ctx: &mut TypeCheckerContext, b: &mut BinaryOp, target:
             b.typ = Type::Bool;
             valid(Type::Bool)
         },
-        _ => type_error_result(&b.span, format!("Operator {} is not a binary operator", b.operator))
+        _ => ErrorCode::UnsupportedOperator.result(&b.span, format!("Operator {} is not a binary operator", b.operator))
     }
 }
 
-fn type_check_array_literal(ctx: &mut TypeCheckerContext, a: &mut ArrayLiteral, target: &Target) -> TypeCheckResult
+fn type_check_array_literal(ctx: &mut TypeCheckerContext, a: &mut ArrayLiteral, type_hint: Option<&Type>, target: &Target) -> TypeCheckResult
 {
     if a.elements.is_empty() {
-        a.array_type = array_type(target.native_uint_type.clone(), 0);
-        return valid(a.array_type.clone());
+        if a.array_type != Type::Unknown {
+            return valid(a.array_type.clone());
+        }
+
+        // An empty array literal has no elements to get a type from, so it takes the one it's expected to have
+        let element_type = match type_hint {
+            Some(&Type::Array(ref at)) => Some(at.element_type.clone()),
+            Some(&Type::Slice(ref st)) => Some(st.element_type.clone()),
+            _ => None,
+        };
+
+        return match element_type {
+            Some(ref et) if !et.is_generic() && *et != Type::Unknown => {
+                a.array_type = array_type(et.clone(), 0);
+                valid(a.array_type.clone())
+            },
+            _ => ErrorCode::CannotInferType.result(&a.span, "Cannot infer the element type of an empty array literal, add an annotation, for example ([] : int[])"),
+        };
     }
 
+    // Values of different types can be elements of an array of an interface they all implement
+    let interface_type = match type_hint {
+        Some(&Type::Array(ref at)) => Some(&at.element_type),
+        Some(&Type::Slice(ref st)) => Some(&st.element_type),
+        _ => None,
+    }.filter(|et| if let Type::Interface(_) = **et {true} else {false});
+
     let mut array_element_type = Type::Unknown;
     for e in &mut a.elements {
-        let t = type_check_expression(ctx, e, None, target)?;
+        let mut t = type_check_expression(ctx, e, None, target)?;
+        if t == Type::Void {
+            return ErrorCode::NoValue.result(&e.span(), "Expression has no value, it cannot be an array element");
+        }
+
+        if let Some(it) = interface_type {
+            if t != *it {
+                convert_type(ctx, it, &t, e, target)?;
+                t = it.clone();
+            }
+        }
+
         if array_element_type == Type::Unknown {
             array_element_type = t;
         } else if array_element_type != t {
-            return type_error_result(&e.span(), "Array elements must have the same type");
+            return ErrorCode::TypeMismatch.result(&e.span(), "Array elements must have the same type");
         }
     }
 
@@ -217,20 +454,52 @@ fn type_check_array_literal(ctx: &mut TypeCheckerContext, a: &mut ArrayLiteral,
     if a.array_type == Type::Unknown {
         a.array_type = array_type;
     } else if a.array_type != array_type {
-        return type_error_result(&a.span, format!("Array has type {}, but elements have type {}", a.array_type, array_type))
+        return ErrorCode::TypeMismatch.result(&a.span, format!("Array has type {}, but elements have type {}", a.array_type, array_type))
     }
 
     valid(a.array_type.clone())
 }
 
+// Error for an argument whose type conflicts with the generic arguments fixed by an earlier argument,
+// shows both arguments
+fn generic_conflict_error(ctx: &TypeCheckerContext, ft: &FuncType, c: &Call, fixed_by: &HashMap<Type, usize>, idx: usize, arg_type: &Type) -> Option<CompileError>
+{
+    let span = c.args[idx].span();
+    let mut own_mapping = GenericMapping::new();
+    fill_in_generics(ctx, arg_type, &ft.args[idx], &mut own_mapping, &span).ok()?;
+    for (param, typ) in &own_mapping
+    {
+        let first = match fixed_by.get(param) {
+            Some(&first) if first != idx => first,
+            _ => continue,
+        };
+
+        let fixed_type = c.generic_args.get(param)?;
+        if fixed_type == typ {
+            continue;
+        }
+
+        return Some(CompileError::Many(vec![
+            ErrorCode::CannotInferType.error(&span, format!("Cannot infer {}: argument {} requires {} = {}, but argument {} requires {} = {}",
+                param, first + 1, param, fixed_type, idx + 1, param, typ)),
+            type_error(&c.args[first].span(), format!("Argument {} requires {} = {}", first + 1, param, fixed_type)),
+        ]));
+    }
+
+    None
+}
+
 fn resolve_generic_args_in_call(ctx: &mut TypeCheckerContext, ft: &FuncType, c: &mut Call, target: &Target) -> CompileResult<Vec<Type>>
 {
     let mut arg_types = Vec::with_capacity(c.args.len());
     let mut count = c.generic_args.len();
+    // The argument which fixed each generic argument, those fixed before the arguments are checked are left out
+    let mut fixed_by = HashMap::new();
+    let already_fixed: Vec<Type> = c.generic_args.keys().cloned().collect();
     loop
     {
         arg_types.clear();
-        for (arg, expected_arg_type) in c.args.iter_mut().zip(ft.args.iter())
+        for (idx, (arg, expected_arg_type)) in c.args.iter_mut().zip(ft.args.iter()).enumerate()
         {
             let expected_arg_type = make_concrete(ctx, &c.generic_args, expected_arg_type, &arg.span())?;
             let arg_type = type_check_expression(ctx, arg, Some(&expected_arg_type), target)?;
@@ -238,6 +507,9 @@ fn resolve_generic_args_in_call(ctx: &mut TypeCheckerContext, ft: &FuncType, c:
 
             if expected_arg_type.is_generic() {
                 fill_in_generics(ctx, &arg_type, &expected_arg_type, &mut c.generic_args, &arg.span())?;
+                for param in c.generic_args.keys().filter(|p| !already_fixed.contains(p)) {
+                    fixed_by.entry(param.clone()).or_insert(idx);
+                }
             }
             arg_types.push(arg_type);
         }
@@ -248,25 +520,483 @@ fn resolve_generic_args_in_call(ctx: &mut TypeCheckerContext, ft: &FuncType, c:
         count = c.generic_args.len();
     }
 
+    for (idx, arg) in c.args.iter().enumerate().take(ft.args.len())
+    {
+        if !ft.args[idx].is_generic() {
+            continue;
+        }
+
+        let expected_arg_type = make_concrete(ctx, &c.generic_args, &ft.args[idx], &arg.span())?;
+        if expected_arg_type == arg_types[idx] || expected_arg_type.convert(&arg_types[idx], arg).is_some() {
+            continue;
+        }
+
+        if let Some(e) = generic_conflict_error(ctx, ft, c, &fixed_by, idx, &arg_types[idx]) {
+            return Err(e);
+        }
+    }
+
+    // No progress is made anymore, so generic parameters which are still unknown cannot be inferred,
+    // unless the call is in a generic function and passes on its own generic parameters
+    let mut params = Vec::new();
+    for t in ft.args.iter().chain(iter::once(&ft.return_type)) {
+        collect_generic_params(t, &mut params);
+    }
+
+    let mut passed_on = Vec::new();
+    for t in &arg_types {
+        collect_generic_params(t, &mut passed_on);
+    }
+
+    for param in params.iter().filter(|p| !passed_on.contains(p))
+    {
+        match c.generic_args.get(param)
+        {
+            Some(typ) if *typ != Type::Unknown => (),
+            _ => return ErrorCode::CannotInferType.result(&c.span,
+                format!("Cannot infer {} from the provided arguments of {}, consider an explicit annotation", param, c.callee.name)),
+        }
+    }
+
     Ok(arg_types)
 }
 
 
+// A sum type case with data, used as a function, refers to the constructor of that case
+fn resolve_sum_case_constructor(ctx: &TypeCheckerContext, resolved: &Symbol) -> Option<Symbol>
+{
+    if let Type::Sum(ref st) = resolved.typ {
+        let idx = st.index_of(&resolved.name)?;
+        if let Type::Struct(_) = st.cases[idx].typ {
+            return ctx.resolve(&sum_type_case_constructor_name(&resolved.name));
+        }
+    }
+
+    None
+}
+
+// The extra arguments of a variadic function are passed like C does, so only primitives and pointers are allowed.
+// Strings are passed as a pointer to their data.
+fn type_check_variadic_args(ctx: &mut TypeCheckerContext, args: &mut [Expression], callee: &str, target: &Target) -> CompileResult<()>
+{
+    for arg in args {
+        let arg_type = type_check_expression(ctx, arg, None, target)?;
+        match arg_type
+        {
+            Type::Int(_) |
+            Type::UInt(_) |
+            Type::Float(_) |
+            Type::Char |
+            Type::Bool |
+            Type::Enum(_) |
+            Type::Pointer(_) => (),
+
+            Type::String => {
+                let span = arg.span();
+                let data = member_access(arg.clone(), MemberAccessType::Property(Property::Data), span);
+                *arg = data;
+                type_check_expression(ctx, arg, None, target)?;
+            }
+
+            _ => return ErrorCode::InvalidCInterface.result(&arg.span(),
+                format!("Cannot pass an argument of type {} to the variadic function {}, only primitive types and pointers are allowed", arg_type, callee)),
+        }
+    }
+
+    Ok(())
+}
+
+// Unknown name error, with a suggestion if there is a similar name
+fn unknown_name_error(ctx: &TypeCheckerContext, span: &Span, msg: String, name: &str, kind: NameKind) -> CompileError
+{
+    let suggestions = closest_names(name, ctx.visible_names(kind).into_iter(), 1);
+    ErrorCode::UnknownName.error(span, with_suggestions(msg, &suggestions))
+}
+
+fn type_check_builtin_call(ctx: &mut TypeCheckerContext, c: &mut Call, builtin: Builtin, target: &Target) -> TypeCheckResult
+{
+    c.builtin = Some(builtin);
+    named_args_not_allowed(c, builtin.name())?;
+    match builtin.signature()
+    {
+        Some((arg_types, return_type)) => {
+            if c.args.len() != arg_types.len() {
+                return ErrorCode::WrongArgumentCount.result(&c.span,
+                    format!("Attempting to call {} with {} arguments, but it needs {}", builtin.name(), c.args.len(), arg_types.len()));
+            }
+
+            for (arg, arg_type) in c.args.iter_mut().zip(arg_types.iter()) {
+                type_check_with_conversion(ctx, arg, arg_type, target)?;
+            }
+
+            c.return_type = return_type.clone();
+            valid(return_type)
+        },
+
+        None if builtin == Builtin::Hash => {
+            if c.args.len() != 1 {
+                return ErrorCode::WrongArgumentCount.result(&c.span,
+                    format!("Attempting to call {} with {} arguments, but it needs 1", builtin.name(), c.args.len()));
+            }
+
+            let typ = type_check_expression(ctx, &mut c.args[0], None, target)?;
+            if !typ.is_hashable() && !typ.is_generic() {
+                return ErrorCode::InvalidBuiltinArgument.result(&c.args[0].span(),
+                    format!("Type {} is not hashable, only integers, chars, bools, enums and strings can be hashed", typ));
+            }
+
+            c.return_type = target.native_uint_type.clone();
+            valid(c.return_type.clone())
+        },
+
+        None if builtin == Builtin::ApproxEq => {
+            if c.args.len() != 3 {
+                return ErrorCode::WrongArgumentCount.result(&c.span,
+                    format!("Attempting to call {} with {} arguments, but it needs 3", builtin.name(), c.args.len()));
+            }
+
+            let left_type = type_check_expression(ctx, &mut c.args[0], None, target)?;
+            let right_type = type_check_expression(ctx, &mut c.args[1], None, target)?;
+            if left_type != right_type {
+                let (left, right) = c.args.split_at_mut(1);
+                type_check_with_conversion(ctx, &mut right[0], &left_type, target)
+                    .or_else(|_| type_check_with_conversion(ctx, &mut left[0], &right_type, target))
+                    .map_err(|_| ErrorCode::InvalidBuiltinArgument.error(&c.span,
+                        format!("{} expects arguments of the same type (first: {}, second: {})", builtin.name(), left_type, right_type)))?;
+            }
+
+            let typ = c.args[0].get_type(target.int_size);
+            if !typ.is_float() {
+                return ErrorCode::InvalidBuiltinArgument.result(&c.span, format!("{} expects float arguments, not {}", builtin.name(), typ));
+            }
+
+            type_check_with_conversion(ctx, &mut c.args[2], &typ, target)?;
+            c.return_type = Type::Bool;
+            valid(Type::Bool)
+        },
+
+        None if builtin == Builtin::Min || builtin == Builtin::Max || builtin == Builtin::Abs || builtin == Builtin::Clamp => {
+            let num_args = match builtin {
+                Builtin::Abs => 1,
+                Builtin::Clamp => 3,
+                _ => 2,
+            };
+
+            if c.args.len() != num_args {
+                return ErrorCode::WrongArgumentCount.result(&c.span,
+                    format!("Attempting to call {} with {} arguments, but it needs {}", builtin.name(), c.args.len(), num_args));
+            }
+
+            let typ = unify_argument_types(ctx, c, builtin.name(), target)?;
+            if !typ.is_numeric() {
+                return ErrorCode::InvalidBuiltinArgument.result(&c.span, format!("{} expects numeric arguments, not {}", builtin.name(), typ));
+            }
+
+            c.return_type = typ.clone();
+            valid(typ)
+        },
+
+        None if builtin == Builtin::Copy || builtin == Builtin::Fill || builtin == Builtin::CopyWithin || builtin == Builtin::Subslice => {
+            type_check_slice_builtin_call(ctx, c, builtin, target)
+        },
+
+        None => {
+            if c.args.len() != 2 {
+                return ErrorCode::WrongArgumentCount.result(&c.span,
+                    format!("Attempting to call {} with {} arguments, but it needs 2", builtin.name(), c.args.len()));
+            }
+
+            let left_type = type_check_expression(ctx, &mut c.args[0], None, target)?;
+            let right_type = type_check_expression(ctx, &mut c.args[1], None, target)?;
+            if left_type != right_type {
+                let (left, right) = c.args.split_at_mut(1);
+                type_check_with_conversion(ctx, &mut right[0], &left_type, target)
+                    .or_else(|_| type_check_with_conversion(ctx, &mut left[0], &right_type, target))
+                    .map_err(|_| ErrorCode::InvalidBuiltinArgument.error(&c.span,
+                        format!("{} expects arguments of the same type (first: {}, second: {})", builtin.name(), left_type, right_type)))?;
+            }
+
+            let typ = c.args[0].get_type(target.int_size);
+            if !typ.is_integer() {
+                return ErrorCode::InvalidBuiltinArgument.result(&c.span, format!("{} expects integer arguments, not {}", builtin.name(), typ));
+            }
+
+            c.return_type = typ.clone();
+            valid(typ)
+        },
+    }
+}
+
+// copy(dst, src), fill(dst, value), copy_within(s, from, to, count) and subslice(s, start, end), an array as first
+// argument is converted to a slice, the other arguments depend on its element type
+fn type_check_slice_builtin_call(ctx: &mut TypeCheckerContext, c: &mut Call, builtin: Builtin, target: &Target) -> TypeCheckResult
+{
+    let num_args = match builtin {
+        Builtin::CopyWithin => 4,
+        Builtin::Subslice => 3,
+        _ => 2,
+    };
+    if c.args.len() != num_args {
+        return ErrorCode::WrongArgumentCount.result(&c.span,
+            format!("Attempting to call {} with {} arguments, but it needs {}", builtin.name(), c.args.len(), num_args));
+    }
+
+    let typ = type_check_expression(ctx, &mut c.args[0], None, target)?;
+    let element_type = match typ
+    {
+        Type::Array(ref at) => at.element_type.clone(),
+        Type::Slice(ref st) => st.element_type.clone(),
+        _ => return ErrorCode::InvalidBuiltinArgument.result(&c.args[0].span(), format!("{} expects an array or a slice, not {}", builtin.name(), typ)),
+    };
+
+    let slice = slice_type(element_type.clone());
+    convert_type(ctx, &slice, &typ, &mut c.args[0], target)?;
+    c.return_type = match builtin
+    {
+        Builtin::Copy => {
+            type_check_with_conversion(ctx, &mut c.args[1], &slice, target)?;
+            target.native_int_type.clone()
+        },
+
+        Builtin::Fill => {
+            type_check_with_conversion(ctx, &mut c.args[1], &element_type, target)?;
+            Type::Void
+        },
+
+        _ => {
+            for arg in &mut c.args[1..] {
+                type_check_with_conversion(ctx, arg, &target.native_int_type, target)?;
+            }
+            if builtin == Builtin::Subslice {slice} else {Type::Void}
+        },
+    };
+
+    valid(c.return_type.clone())
+}
+
+// Converts all arguments of a call to the type of one of them, trying the type of each argument in turn,
+// so literals adapt to the other arguments: max(x, 0) and max(0, x) both use the type of x
+fn unify_argument_types(ctx: &mut TypeCheckerContext, c: &mut Call, callee: &str, target: &Target) -> CompileResult<Type>
+{
+    let mut types = Vec::with_capacity(c.args.len());
+    for arg in &mut c.args {
+        types.push(type_check_expression(ctx, arg, None, target)?);
+    }
+
+    for typ in &types {
+        let mut args = c.args.clone();
+        if args.iter_mut().zip(types.iter()).all(|(arg, arg_type)| convert_type(ctx, typ, arg_type, arg, target).is_ok()) {
+            c.args = args;
+            return Ok(typ.clone());
+        }
+    }
+
+    let names: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+    ErrorCode::InvalidBuiltinArgument.result(&c.span, format!("{} expects arguments of the same type, not {}", callee, names.join(", ")))
+}
+
+// Calling a struct type, calls the create function of that type: Foo<int>() is Foo.create() returning a Foo<int> or a pointer to one
+fn resolve_type_constructor(ctx: &TypeCheckerContext, c: &Call, resolved: &Symbol, target: &Target) -> CompileResult<(Symbol, Type)>
+{
+    let constructor = ctx.resolve(&format!("{}.create", resolved.name))
+        .ok_or_else(|| ErrorCode::NotCallable.error(&c.span, format!("Type {} cannot be constructed, it has no create function", c.callee.name)))?;
+
+    let mut typ = unresolved_type(&resolved.name, c.type_args.clone());
+    if resolve_type(ctx, &mut typ, target) == TypeResolved::No {
+        check_generic_arg_count(ctx, &typ, &c.span)?;
+        return type_error_result(&c.span, format!("Unable to resolve type {}", typ));
+    }
+
+    // Map is the hash map of the prelude, its keys must be hashable
+    if resolved.span.file == "prelude" && resolved.name.ends_with("::Map") {
+        if let Some(key_type) = c.type_args.first() {
+            let mut key_type = key_type.clone();
+            resolve_type(ctx, &mut key_type, target);
+            if !key_type.is_hashable() {
+                return ErrorCode::InvalidBuiltinArgument.result(&c.span,
+                    format!("Type {} cannot be used as the key of a Map, only integers, chars, bools, enums and strings are hashable", key_type));
+            }
+        }
+    }
+
+    Ok((constructor, typ))
+}
+
+// Only calls to functions can use named arguments, builtins, externals and function values need positional arguments
+fn named_args_not_allowed(c: &Call, callee: &str) -> CompileResult<()>
+{
+    match c.named_args.first() {
+        Some(na) => ErrorCode::InvalidNamedArgument.result(&na.span,
+            format!("Cannot pass argument {} by name to {}, named arguments are only allowed in calls to functions, not to externals, builtins or function values", na.name, callee)),
+        None => Ok(()),
+    }
+}
+
+// Arity error, which lists the parameters without an argument if their names are known,
+// parameters with a default value never need one, so they are not listed
+fn wrong_argument_count_error(c: &Call, callee: &Symbol, ft: &FuncType, num_args: usize, passed: &[bool]) -> CompileError
+{
+    let args_known = callee.args.len() == ft.args.len();
+    let required = if args_known {callee.args.iter().filter(|a| a.default.is_none()).count()} else {ft.args.len()};
+    let msg = if ft.variadic {
+        format!("Attempting to call {} with {} arguments, but it needs at least {}", c.callee.name, num_args, ft.args.len())
+    } else if required < ft.args.len() {
+        format!("Attempting to call {} with {} arguments, but it needs at least {} and at most {}", c.callee.name, num_args, required, ft.args.len())
+    } else {
+        format!("Attempting to call {} with {} arguments, but it needs {}", c.callee.name, num_args, ft.args.len())
+    };
+
+    let missing: Vec<&str> = callee.args.iter()
+        .enumerate()
+        .filter(|&(idx, a)| !passed.get(idx).cloned().unwrap_or(false) && a.default.is_none())
+        .map(|(_, a)| &a.name[..])
+        .collect();
+
+    if missing.is_empty() || !args_known {
+        ErrorCode::WrongArgumentCount.error(&c.span, msg)
+    } else if required < ft.args.len() {
+        ErrorCode::WrongArgumentCount.error(&c.span, format!("{}, missing required arguments: {}", msg, missing.join(", ")))
+    } else {
+        ErrorCode::WrongArgumentCount.error(&c.span, format!("{}, missing arguments: {}", msg, missing.join(", ")))
+    }
+}
+
+// Parameters without an argument get their default value, slots holds the arguments in declaration order
+fn fill_in_default_args(slots: &mut [Option<Expression>], callee: &Symbol)
+{
+    for (slot, arg) in slots.iter_mut().zip(callee.args.iter()) {
+        if slot.is_none() {
+            *slot = arg.default.clone();
+        }
+    }
+}
+
+// Move the named arguments of a call into args, in the order in which the callee declares them,
+// so the rest of the compiler only has to deal with positional arguments
+fn pass_named_args_by_position(c: &mut Call, callee: &Symbol, ft: &FuncType) -> CompileResult<()>
+{
+    if c.named_args.is_empty() {
+        return Ok(());
+    }
+
+    if callee.args.len() != ft.args.len() {
+        return named_args_not_allowed(c, &c.callee.name);
+    }
+
+    let num_args = c.args.len() + c.named_args.len();
+    if c.args.len() >= ft.args.len() {
+        let passed = vec![true; ft.args.len()];
+        return Err(wrong_argument_count_error(c, callee, ft, num_args, &passed));
+    }
+
+    let mut slots: Vec<Option<Expression>> = c.args.drain(..).map(Some).collect();
+    slots.resize(ft.args.len(), None);
+    let mut passed_by_name: Vec<Option<Span>> = vec![None; ft.args.len()];
+    for na in c.named_args.drain(..)
+    {
+        let idx = match callee.args.iter().position(|a| a.name == na.name) {
+            Some(idx) => idx,
+            None => return Err(CompileError::Many(vec![
+                ErrorCode::InvalidNamedArgument.error(&na.span, format!("{} has no parameter named {}", c.callee.name, na.name)),
+                type_error(&callee.span, format!("{} is declared here", c.callee.name)),
+            ])),
+        };
+
+        let declared_here = type_error(&callee.args[idx].span, format!("Parameter {} is declared here", na.name));
+        if let Some(ref first) = passed_by_name[idx] {
+            return Err(CompileError::Many(vec![
+                ErrorCode::InvalidNamedArgument.error(&na.span, format!("Argument {} is passed more than once", na.name)),
+                type_error(first, format!("Argument {} is first passed here", na.name)),
+                declared_here,
+            ]));
+        }
+
+        if let Some(ref positional) = slots[idx] {
+            return Err(CompileError::Many(vec![
+                ErrorCode::InvalidNamedArgument.error(&na.span, format!("Argument {} is passed by name, but it is already passed as positional argument {}", na.name, idx + 1)),
+                type_error(&positional.span(), format!("Argument {} is first passed here", na.name)),
+                declared_here,
+            ]));
+        }
+
+        passed_by_name[idx] = Some(na.span);
+        slots[idx] = Some(na.value);
+    }
+
+    let passed: Vec<bool> = slots.iter().map(|s| s.is_some()).collect();
+    fill_in_default_args(&mut slots, callee);
+    if slots.iter().any(|s| s.is_none()) {
+        return Err(wrong_argument_count_error(c, callee, ft, num_args, &passed));
+    }
+
+    c.args = slots.into_iter().flatten().collect();
+    Ok(())
+}
+
 fn type_check_call(ctx: &mut TypeCheckerContext, c: &mut Call, target: &Target) -> TypeCheckResult
 {
-    let resolved = ctx.resolve(&c.callee.name)
-        .ok_or_else(|| unknown_name(&c.callee.span, format!("Unknown call {}", c.callee.name)))?;
+    let mut resolved = match ctx.resolve(&c.callee.name)
+    {
+        Some(resolved) => resolved,
+        None => match Builtin::from_name(&c.callee.name)
+        {
+            Some(builtin) => return type_check_builtin_call(ctx, c, builtin, target),
+            None => return Err(unknown_name_error(ctx, &c.callee.span, format!("Unknown call {}", c.callee.name), &c.callee.name, NameKind::Value)),
+        },
+    };
+    if let Some(constructor) = resolve_sum_case_constructor(ctx, &resolved) {
+        resolved = constructor;
+    }
 
-    c.callee.name = resolved.name;
+    let constructed_type = match resolved.typ {
+        Type::Struct(_) => {
+            let (constructor, typ) = resolve_type_constructor(ctx, c, &resolved, target)?;
+            resolved = constructor;
+            Some(typ)
+        },
+        _ if !c.type_args.is_empty() =>
+            return ErrorCode::NotCallable.result(&c.span, format!("Explicit type arguments are only allowed when constructing a type, and {} is not a type", c.callee.name)),
+        _ => None,
+    };
+
+    c.callee.name = resolved.name.clone();
     if let Type::Func(ref ft) = resolved.typ
     {
-        if ft.args.len() != c.args.len() {
-            return type_error_result(&c.span,
-                format!("Attempting to call {} with {} arguments, but it needs {}", c.callee.name, c.args.len(), ft.args.len()));
+        pass_named_args_by_position(c, &resolved, ft)?;
+        if !ft.variadic && c.args.len() < ft.args.len() && resolved.args.len() == ft.args.len() {
+            let num_args = c.args.len();
+            let mut slots: Vec<Option<Expression>> = c.args.drain(..).map(Some).collect();
+            slots.resize(ft.args.len(), None);
+            fill_in_default_args(&mut slots, &resolved);
+            if slots.iter().any(|s| s.is_none()) {
+                let passed: Vec<bool> = (0..ft.args.len()).map(|idx| idx < num_args).collect();
+                return Err(wrong_argument_count_error(c, &resolved, ft, num_args, &passed));
+            }
+            c.args = slots.into_iter().flatten().collect();
+        }
+
+        if (ft.variadic && c.args.len() < ft.args.len()) || (!ft.variadic && ft.args.len() != c.args.len()) {
+            let passed: Vec<bool> = (0..ft.args.len()).map(|idx| idx < c.args.len()).collect();
+            return Err(wrong_argument_count_error(c, &resolved, ft, c.args.len(), &passed));
+        }
+
+        if ft.variadic {
+            type_check_variadic_args(ctx, &mut c.args[ft.args.len()..], &c.callee.name, target)?;
+        }
+
+        if let Some(ref typ) = constructed_type {
+            let generic_type = match ft.return_type {
+                Type::Pointer(ref inner) => inner.deref().clone(),
+                ref rt => rt.clone(),
+            };
+
+            if generic_type.is_generic() {
+                fill_in_generics(ctx, typ, &generic_type, &mut c.generic_args, &c.span)?;
+            }
         }
 
         let arg_types = resolve_generic_args_in_call(ctx, ft, c, target)?;
-        for (idx, arg) in c.args.iter_mut().enumerate()
+        for (idx, arg) in c.args.iter_mut().enumerate().take(ft.args.len())
         {
             let expected_arg_type = make_concrete(ctx, &c.generic_args, &ft.args[idx], &arg.span())?;
             let arg_type = &arg_types[idx];
@@ -282,7 +1012,7 @@ fn type_check_call(ctx: &mut TypeCheckerContext, c: &mut Call, target: &Target)
     }
     else
     {
-        type_error_result(&c.span, format!("{} is not callable", c.callee.name))
+        ErrorCode::NotCallable.result(&c.span, format!("{} is not callable", c.callee.name))
     }
 }
 
@@ -294,10 +1024,12 @@ pub fn type_check_function(ctx: &mut TypeCheckerContext, fun: &mut Function, tar
         ctx.add(Symbol::new(&arg.name, &arg.typ, arg.mutable, &arg.span, SymbolType::Normal))?;
     }
 
+    ctx.start_naming_lambdas(&fun.sig.name);
     let et = match type_check_expression(ctx, &mut fun.expression, Some(&fun.sig.return_type), target)
     {
         Err(CompileError::UnknownType(ref name, ref expected_type)) => {
             update_binding_type(ctx, &mut fun.expression, name, expected_type, target)?;
+            ctx.start_naming_lambdas(&fun.sig.name);
             type_check_expression(ctx, &mut fun.expression, Some(&fun.sig.return_type), target)?
         },
         Err(e) => return Err(e),
@@ -305,11 +1037,11 @@ pub fn type_check_function(ctx: &mut TypeCheckerContext, fun: &mut Function, tar
     };
 
     ctx.exit_scope();
-    if et != fun.sig.return_type {
-        if let Some(expression) = fun.sig.return_type.convert(&et, &fun.expression) {
+    if et != fun.sig.return_type && !fun.expression.diverges() {
+        if let Some(expression) = coerce(ctx, &fun.expression, &et, &fun.sig.return_type, target)? {
             fun.expression = expression;
         } else {
-            return type_error_result(&fun.span, format!("Function {} has return type {}, but it is returning an expression of type {}",
+            return ErrorCode::TypeMismatch.result(&fun.span, format!("Function {} has return type {}, but it is returning an expression of type {}",
                 fun.sig.name, fun.sig.return_type, et));
         }
     }
@@ -334,142 +1066,300 @@ fn is_result_mutable(ctx: &TypeCheckerContext, e: &Expression) -> bool
     }
 }
 
-fn type_check_match(ctx: &mut TypeCheckerContext, m: &mut MatchExpression, target: &Target) -> TypeCheckResult
+// The elements of an array are part of its value, so they can only be modified when the array is mutable,
+// the elements of a slice can always be modified
+fn is_array_mutable(ctx: &TypeCheckerContext, e: &Expression, int_size: IntSize) -> bool
 {
-    let target_type = type_check_expression(ctx, &mut m.target, None, target)?;
-    let target_is_mutable = is_result_mutable(ctx, &m.target);
-    let mut return_type = Type::Unknown;
+    match *e {
+        Expression::IndexOperation(ref iop) => {
+            if let Type::Array(_) = iop.target.get_type(int_size) {
+                is_array_mutable(ctx, &iop.target, int_size)
+            } else {
+                true
+            }
+        },
+        Expression::MemberAccess(ref ma) => is_array_mutable(ctx, &ma.left, int_size),
+        _ => is_result_mutable(ctx, e),
+    }
+}
 
-    for c in &mut m.cases
+fn type_check_range_pattern(r: &RangePattern, target_type: &Type) -> CompileResult<()>
+{
+    for lit in &[&r.start, &r.end]
+    {
+        let typ = lit.get_type();
+        if literal_value(lit).is_none() {
+            return ErrorCode::InvalidPattern.result(&lit.span(), format!("Range patterns need integer or char literals, not {}", typ));
+        }
+
+        if !target_type.is_matchable(&typ) {
+            return ErrorCode::InvalidPattern.result(&lit.span(), format!("Pattern match of type {}, cannot match with an expression of type {}", typ, target_type));
+        }
+    }
+
+    if r.bounds().is_none() {
+        return ErrorCode::InvalidPattern.result(&r.span, "Range pattern is empty, its start must come before its end");
+    }
+
+    Ok(())
+}
+
+// A name in a pattern is an enum or sum type case when it resolves to one, otherwise it binds the matched value
+fn is_case_name(ctx: &TypeCheckerContext, name: &str) -> bool
+{
+    match ctx.resolve(name) {
+        Some(Symbol{typ: Type::Enum(ref et), ref name, ..}) => et.index_of(name).is_some(),
+        Some(Symbol{typ: Type::Sum(ref st), ref name, ..}) => st.index_of(name).is_some(),
+        _ => false,
+    }
+}
+
+// Type check a pattern against the type of the matched expression, the names it binds are added to the current scope
+fn type_check_pattern(ctx: &mut TypeCheckerContext, p: &mut Pattern, target_type: &Type, target_is_mutable: bool, target: &Target) -> CompileResult<()>
+{
+    // A constant string global is matched by its value
+    let constant = match *p {
+        Pattern::Name(ref nr) => ctx.get_constant(&nr.name).map(|value| Pattern::Literal(Literal::String(nr.span.clone(), value))),
+        _ => None,
+    };
+
+    if let Some(constant) = constant {
+        *p = constant;
+    }
+
+    let binding = match *p {
+        Pattern::Name(ref nr) if !nr.name.contains("::") && !is_case_name(ctx, &nr.name) => Some(binding_pattern(&nr.name, nr.span.clone())),
+        _ => None,
+    };
+
+    if let Some(binding) = binding {
+        *p = binding;
+    }
+
+    let match_span = p.span();
+    match *p
     {
-        let infer_case_type = |ctx: &mut TypeCheckerContext, e: &mut Expression, return_type: &Type| {
-            let tt = type_check_expression(ctx, e, None, target)?;
-            if *return_type != Type::Unknown && *return_type != tt {
-                type_error_result(&e.span(), "Expressions in match statements must return the same type")
+        Pattern::EmptyArray(ref ap) => {
+            if !target_type.is_sequence() {
+                return ErrorCode::InvalidPattern.result(&ap.span, format!("Attempting to pattern match an expression of type {}, with an empty array", target_type));
+            }
+            Ok(())
+        },
+
+        Pattern::Array(ref ap) => {
+            if !target_type.is_sequence() {
+                return ErrorCode::InvalidPattern.result(&ap.span, format!("Attempting to pattern match an expression of type {}, with an array", target_type));
+            }
+
+            let element_type = target_type.get_element_type().expect("target_type is not an array type");
+            // The tail of a string is again a string
+            let tail_type = if *target_type == Type::String {
+                Type::String
             } else {
-                Ok(tt)
+                slice_type(element_type.clone())
+            };
+
+            ctx.add(Symbol::new(&ap.head, &element_type, false, &ap.span, SymbolType::Normal))?;
+            ctx.add(Symbol::new(&ap.tail, &tail_type, false, &ap.span, SymbolType::Normal))
+        },
+
+        Pattern::Name(ref mut nr) => {
+            type_check_name(ctx, nr, Some(target_type))?;
+            if nr.typ != *target_type {
+                return ErrorCode::InvalidPattern.result(&match_span,
+                    format!("Cannot pattern match an expression of type {} with an expression of type {}",
+                        target_type, nr.typ));
             }
-        };
 
-        let match_span = c.pattern.span();
-        let case_type = match c.pattern
-        {
-            Pattern::EmptyArray(ref ap) => {
-                if !target_type.is_sequence() {
-                    return type_error_result(&ap.span, format!("Attempting to pattern match an expression of type {}, with an empty array", target_type));
-                }
-                infer_case_type(ctx, &mut c.to_execute, &return_type)?
-            },
+            match nr.typ
+            {
+                Type::Sum(ref st) => {
+                    let idx = st.index_of(&nr.name).expect("Internal Compiler Error: cannot determine index of sum type case");
+                    let case = &st.cases[idx];
+                    if case.typ == target.native_uint_type {
+                        Ok(())
+                    } else {
+                        ErrorCode::InvalidPattern.result(&match_span, "Invalid pattern match, match should be with an empty sum case")
+                    }
+                },
+                Type::Enum(_) => Ok(()),
+                _ => ErrorCode::InvalidPattern.result(&match_span, "Invalid pattern match"),
+            }
+        },
 
-            Pattern::Array(ref ap) => {
-                if !target_type.is_sequence() {
-                    return type_error_result(&ap.span, format!("Attempting to pattern match an expression of type {}, with an array", target_type));
-                }
+        Pattern::Literal(Literal::Array(ref mut al)) => {
+            let m_type = type_check_array_literal(ctx, al, None, target)?.unwrap();
+            if !target_type.is_matchable(&m_type) {
+                return ErrorCode::InvalidPattern.result(&al.span, format!("Pattern match of type {}, cannot match with an expression of type {}",
+                    m_type, target_type));
+            }
+            Ok(())
+        },
 
-                let element_type = target_type.get_element_type().expect("target_type is not an array type");
+        Pattern::Literal(ref mut lit)  => {
+            // Give the literal the type of the expression it is matched against, so it is known after type checking
+            if let Some(converted) = lit.try_convert(target_type) {
+                *lit = converted;
+            }
 
-                ctx.enter_scope(None);
-                ctx.add(Symbol::new(&ap.head, &element_type, false, &ap.span, SymbolType::Normal))?;
-                ctx.add(Symbol::new(&ap.tail, &slice_type(element_type.clone()), false, &ap.span, SymbolType::Normal))?;
-                let ct = infer_case_type(ctx, &mut c.to_execute, &return_type)?;
-                ctx.exit_scope();
-                ct
-            },
+            let m_type = lit.get_type();
+            if !target_type.is_matchable(&m_type) {
+                return ErrorCode::InvalidPattern.result(&lit.span(), format!("Pattern match of type {}, cannot match with an expression of type {}",
+                    m_type, target_type));
+            }
+            Ok(())
+        },
 
-            Pattern::Name(ref mut nr) => {
-                type_check_name(ctx, nr, Some(&target_type))?;
-                if nr.typ != target_type {
-                    return type_error_result(&match_span,
-                        format!("Cannot pattern match an expression of type {} with an expression of type {}",
-                            target_type, nr.typ));
-                }
+        Pattern::Range(ref r) => type_check_range_pattern(r, target_type),
 
-                match nr.typ
-                {
-                    Type::Sum(ref st) => {
-                        let idx = st.index_of(&nr.name).expect("Internal Compiler Error: cannot determine index of sum type case");
-                        let case = &st.cases[idx];
-                        if case.typ == target.native_uint_type {
-                            infer_case_type(ctx, &mut c.to_execute, &return_type)?
-                        } else {
-                            return type_error_result(&match_span, "Invalid pattern match, match should be with an empty sum case");
-                        }
-                    },
-                    Type::Enum(_) => {
-                        infer_case_type(ctx, &mut c.to_execute, &return_type)?
-                    },
-                    _ => {
-                        return type_error_result(&match_span, "Invalid pattern match");
-                    }
-                }
-            },
+        Pattern::Struct(ref mut p) => {
+            type_check_struct_pattern(ctx, p, target_type, target_is_mutable)?;
+            if p.typ != *target_type {
+                return ErrorCode::InvalidPattern.result(&match_span,
+                    format!("Cannot pattern match an expression of type {} with an expression of type {}",
+                        target_type, p.typ));
+            }
+            Ok(())
+        },
 
-            Pattern::Literal(Literal::Array(ref mut al)) => {
-                let m_type = type_check_array_literal(ctx, al, target)?.unwrap();
-                if !target_type.is_matchable(&m_type) {
-                    return type_error_result(&al.span, format!("Pattern match of type {}, cannot match with an expression of type {}",
-                        m_type, target_type));
-                }
+        Pattern::Any(_) => Ok(()),
 
-                infer_case_type(ctx, &mut c.to_execute, &return_type)?
-            },
+        Pattern::Binding(ref mut b) => {
+            b.typ = target_type.clone();
+            ctx.add(Symbol::new(&b.name, &b.typ, false, &b.span, SymbolType::Normal))
+        },
 
-            Pattern::Literal(ref lit)  => {
-                let m_type = lit.get_type();
-                if !target_type.is_matchable(&m_type) {
-                    return type_error_result(&c.pattern.span(), format!("Pattern match of type {}, cannot match with an expression of type {}",
-                        m_type, target_type));
-                }
+        Pattern::Nil(ref span) => {
+            if !target_type.is_optional() {
+                return ErrorCode::InvalidPattern.result(span,
+                    format!("Cannot match type {} to nil, only optionals can be matched to nil", target_type));
+            }
+            Ok(())
+        },
 
-                infer_case_type(ctx, &mut c.to_execute, &return_type)?
-            },
+        Pattern::Optional(ref mut o) => {
+            if !target_type.is_optional() {
+                return ErrorCode::InvalidPattern.result(&o.span,
+                    format!("Cannot match type {} to optional pattern", target_type));
+            }
 
-            Pattern::Struct(ref mut p) => {
-                ctx.enter_scope(None);
-                type_check_struct_pattern(ctx, p, target_is_mutable)?;
-                if p.typ != target_type {
-                    return type_error_result(&match_span,
-                        format!("Cannot pattern match an expression of type {} with an expression of type {}",
-                            target_type, p.typ));
-                }
+            o.inner_type = target_type.get_element_type().expect("Optional type expected");
+            ctx.add(Symbol::new(&o.binding, &o.inner_type, target_is_mutable, &o.span, SymbolType::Normal))
+        },
 
-                let ct = infer_case_type(ctx, &mut c.to_execute, &return_type)?;
-                ctx.exit_scope();
-                ct
-            },
+        Pattern::Or(ref mut alternatives) => type_check_or_pattern(ctx, alternatives, target_type, target_is_mutable, target),
+    }
+}
 
-            Pattern::Any(_) => {
-                infer_case_type(ctx, &mut c.to_execute, &return_type)?
-            },
+// The case body runs whichever alternative matched, so every alternative has to bind the same names with the same types
+fn type_check_or_pattern(ctx: &mut TypeCheckerContext, alternatives: &mut [Pattern], target_type: &Type, target_is_mutable: bool, target: &Target) -> CompileResult<()>
+{
+    let mut first_bindings: Option<Vec<Symbol>> = None;
+    for a in alternatives.iter_mut()
+    {
+        ctx.enter_scope(None);
+        let checked = type_check_pattern(ctx, a, target_type, target_is_mutable, target);
+        ctx.exit_scope();
+        checked?;
 
-            Pattern::Nil(ref span) => {
-                if !target_type.is_optional() {
-                    return type_error_result(span,
-                        format!("Cannot match type {} to nil, only optionals can be matched to nil", target_type));
-                }
+        let bindings = or_pattern_bindings(a, target_is_mutable)?;
+        match first_bindings
+        {
+            Some(ref first) => check_same_bindings(first, &bindings, &a.span())?,
+            None => first_bindings = Some(bindings),
+        }
+    }
 
-                infer_case_type(ctx, &mut c.to_execute, &return_type)?
-            },
+    for symbol in first_bindings.unwrap_or_default() {
+        ctx.add(symbol)?;
+    }
+    Ok(())
+}
 
-            Pattern::Optional(ref mut o) => {
-                if !target_type.is_optional() {
-                    return type_error_result(&o.span,
-                        format!("Cannot match type {} to optional pattern", target_type));
-                }
+// The names bound by one alternative of an or-pattern, only struct patterns can bind names there
+fn or_pattern_bindings(p: &Pattern, target_is_mutable: bool) -> CompileResult<Vec<Symbol>>
+{
+    match *p
+    {
+        Pattern::Struct(ref sp) => {
+            Ok(sp.bindings.iter()
+                .filter(|b| b.name != "_")
+                .map(|b| {
+                    let mutable = target_is_mutable && b.mode == StructPatternBindingMode::Pointer;
+                    Symbol::new(&b.name, &b.typ, mutable, &sp.span, SymbolType::Normal)
+                })
+                .collect())
+        },
+        Pattern::Binding(ref b) => ErrorCode::InvalidPattern.result(&b.span,
+            format!("{} binds the whole matched value, which is not allowed in an alternative of an or-pattern", b.name)),
+        Pattern::Array(ref ap) => ErrorCode::InvalidPattern.result(&ap.span, "Array patterns which bind a head and tail are not allowed in an alternative of an or-pattern"),
+        Pattern::Optional(ref o) => ErrorCode::InvalidPattern.result(&o.span, "Optional patterns are not allowed in an alternative of an or-pattern"),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn check_same_bindings(first: &[Symbol], bindings: &[Symbol], span: &Span) -> CompileResult<()>
+{
+    let only_in_some = |name: &str| ErrorCode::InvalidPattern.result(span,
+        format!("{} is bound in only some of the alternatives of the pattern, every alternative must bind the same names", name));
 
-                o.inner_type = target_type.get_element_type().expect("Optional type expected");
-                ctx.enter_scope(None);
-                ctx.add(Symbol::new(&o.binding, &o.inner_type, target_is_mutable, &o.span, SymbolType::Normal))?;
-                let ct = infer_case_type(ctx, &mut c.to_execute, &return_type)?;
-                ctx.exit_scope();
-                ct
+    for b in bindings
+    {
+        match first.iter().find(|f| f.name == b.name)
+        {
+            None => return only_in_some(&b.name),
+            Some(f) if f.typ != b.typ => {
+                return ErrorCode::InvalidPattern.result(span,
+                    format!("{} has type {} in the first alternative of the pattern, but type {} in this one", b.name, f.typ, b.typ));
             },
+            Some(_) => (),
+        }
+    }
+
+    match first.iter().find(|f| !bindings.iter().any(|b| b.name == f.name))
+    {
+        Some(f) => only_in_some(&f.name),
+        None => Ok(()),
+    }
+}
+
+fn type_check_match(ctx: &mut TypeCheckerContext, m: &mut MatchExpression, target: &Target) -> TypeCheckResult
+{
+    let target_type = type_check_expression(ctx, &mut m.target, None, target)?;
+    let target_is_mutable = is_result_mutable(ctx, &m.target);
+    let mut return_type = Type::Unknown;
+    let mut case_types = Vec::with_capacity(m.cases.len());
+
+    for c in &mut m.cases
+    {
+        ctx.enter_scope(None);
+        type_check_pattern(ctx, &mut c.pattern, &target_type, target_is_mutable, target)?;
+        let case_type = type_check_expression(ctx, &mut c.to_execute, None, target)?;
+        ctx.exit_scope();
+
+        // A case which always returns has no value, so it doesn't affect the type of the match
+        return_type = if c.to_execute.diverges() {
+            return_type
+        } else if return_type == Type::Unknown {
+            case_type.clone()
+        } else {
+            common_branch_type(&return_type, &case_type)
+                .ok_or_else(|| ErrorCode::MatchArmTypeMismatch.error(&c.to_execute.span(), "Expressions in match statements must return the same type"))?
         };
+        case_types.push(case_type);
+    }
+
+    // The bindings of the patterns are out of scope, so the cases cannot be type checked again after converting them
+    if return_type == Type::Unknown {
+        return_type = Type::Void;
+    }
 
-        if return_type == Type::Unknown {
-            return_type = case_type;
-        } else if return_type != case_type {
-            return type_error_result(&m.span, "Cases of match statements must return the same type");
+    for (c, case_type) in m.cases.iter_mut().zip(case_types.iter()) {
+        if *case_type != return_type && !c.to_execute.diverges() {
+            match coerce(ctx, &c.to_execute, case_type, &return_type, target)? {
+                Some(converted) => c.to_execute = converted,
+                None => return ErrorCode::MatchArmTypeMismatch.result(&c.to_execute.span(), "Expressions in match statements must return the same type"),
+            }
         }
     }
 
@@ -478,30 +1368,101 @@ fn type_check_match(ctx: &mut TypeCheckerContext, m: &mut MatchExpression, targe
     valid(return_type)
 }
 
-fn type_check_lambda_body(ctx: &mut TypeCheckerContext, m: &mut Lambda, target: &Target) -> TypeCheckResult
+// The span of the first name a pattern binds
+fn pattern_binding_span(p: &Pattern) -> Option<Span>
+{
+    match *p
+    {
+        Pattern::Binding(ref b) => Some(b.span.clone()),
+        Pattern::Array(ref ap) => Some(ap.span.clone()),
+        Pattern::Optional(ref o) if o.binding != "_" => Some(o.span.clone()),
+        Pattern::Struct(ref sp) if sp.bindings.iter().any(|b| b.name != "_") => Some(sp.span.clone()),
+        Pattern::Or(ref alternatives) => alternatives.iter().filter_map(pattern_binding_span).next(),
+        _ => None,
+    }
+}
+
+// x is Pattern, nothing is in scope after it, so the pattern cannot bind names
+fn type_check_is(ctx: &mut TypeCheckerContext, i: &mut IsExpression, target: &Target) -> TypeCheckResult
 {
+    let target_type = type_check_expression(ctx, &mut i.inner, None, target)?;
     ctx.enter_scope(None);
+    let checked = type_check_pattern(ctx, &mut i.pattern, &target_type, false, target);
+    ctx.exit_scope();
+    checked?;
+
+    if let Some(span) = pattern_binding_span(&i.pattern) {
+        return ErrorCode::InvalidPattern.result(&span, "A pattern after is cannot bind names, use _ instead, or a match or if let to use the value");
+    }
+
+    valid(Type::Bool)
+}
+
+fn type_check_lambda_body(ctx: &mut TypeCheckerContext, m: &mut Lambda, target: &Target) -> TypeCheckResult
+{
+    // A return in the body returns from the lambda, so it needs the return type, which is only known from a type hint
+    let return_type = if m.sig.return_type.is_generic() {Type::Unknown} else {m.sig.return_type.clone()};
+    ctx.enter_lambda_scope(return_type);
     for arg in &mut m.sig.args {
         ctx.add(Symbol::new(&arg.name, &arg.typ, false, &arg.span, SymbolType::Normal))?;
     }
 
-    let return_type = type_check_expression(ctx, &mut m.expr, None, target)?;
-    ctx.exit_scope();
-    m.set_return_type(return_type);
+    let return_type = type_check_expression(ctx, &mut m.expr, None, target)?;
+    ctx.exit_scope();
+    if !m.expr.diverges() {
+        m.set_return_type(return_type);
+    }
+    valid(m.sig.typ.clone())
+}
+
+// Local functions can call themselves, so their type must be known before the body is checked
+fn type_check_local_function(ctx: &mut TypeCheckerContext, m: &mut Lambda, name: &str, target: &Target) -> TypeCheckResult
+{
+    for arg in &m.sig.args {
+        if arg.typ.is_generic() {
+            return ErrorCode::CannotInferType.result(&arg.span,
+                format!("Cannot infer the type of argument {} of local function {}, local functions need type annotations on all arguments", arg.name, name));
+        }
+    }
+
+    m.sig.name = ctx.next_lambda_name(name);
+    m.sig.typ = m.sig.get_type();
+
+    ctx.enter_scope(Some(m.sig.return_type.clone()));
+    ctx.add(Symbol::new(name, &m.sig.typ, false, &m.span, SymbolType::Normal))?;
+    for arg in &m.sig.args {
+        ctx.add(Symbol::new(&arg.name, &arg.typ, arg.mutable, &arg.span, SymbolType::Normal))?;
+    }
+
+    let body_type = type_check_expression(ctx, &mut m.expr, Some(&m.sig.return_type), target);
+    ctx.exit_scope();
+    let body_type = body_type?;
+    if body_type != m.sig.return_type && !m.expr.diverges() {
+        if let Some(expression) = coerce(ctx, &m.expr, &body_type, &m.sig.return_type, target)? {
+            m.expr = expression;
+        } else {
+            return ErrorCode::TypeMismatch.result(&m.span, format!("Local function {} has return type {}, but it is returning an expression of type {}",
+                name, m.sig.return_type, body_type));
+        }
+    }
+
     valid(m.sig.typ.clone())
 }
 
 fn type_check_lambda(ctx: &mut TypeCheckerContext, m: &mut Lambda, type_hint: Option<&Type>, target: &Target) -> TypeCheckResult
 {
+    if let Some(name) = m.name.clone() {
+        return type_check_local_function(ctx, m, &name, target);
+    }
+
+    m.sig.name = ctx.next_lambda_name(LAMBDA_NAME);
     match type_hint
     {
         Some(typ) => {
-            use uuid::{Uuid};
-            m.sig.name = format!("lambda-{}", Uuid::new_v4()); // Add a uuid, so we don't get name clashes
             m.apply_type(typ)?;
             let infered_type = type_check_lambda_body(ctx, m, target)?.unwrap();
             if infered_type != *typ {
-                return type_error_result(&m.span, format!("Lambda body has the wrong type, expecting {}, got {}", typ, infered_type));
+                return ErrorCode::TypeMismatch.result(&m.span, format!("Lambda body has the wrong type, expecting {}, got {}", typ, infered_type));
             }
 
             valid(infered_type)
@@ -549,15 +1510,25 @@ fn is_instantiation_of(concrete_type: &Type, generic_type: &Type) -> bool
 fn type_check_name(ctx: &mut TypeCheckerContext, nr: &mut NameRef, type_hint: Option<&Type>) -> TypeCheckResult
 {
     if nr.name == "_" {
-        return valid(Type::Unknown);
+        return ErrorCode::NoValue.result(&nr.span, "`_` is not a value, it can only be used to discard one");
     }
 
     if !nr.typ.is_unknown() && !nr.typ.is_generic() {
         return valid(nr.typ.clone()); // We have already determined the type
     }
 
-    let resolved = ctx.resolve(&nr.name)
-        .ok_or_else(|| unknown_name(&nr.span, format!("Unknown name {}", nr.name)))?;
+    let mut resolved = ctx.resolve(&nr.name)
+        .ok_or_else(|| unknown_name_error(ctx, &nr.span, format!("Unknown name {}", nr.name), &nr.name, NameKind::Value))?;
+    let wants_function = match type_hint {
+        None | Some(&Type::Func(_)) => true,
+        _ => false,
+    };
+
+    if wants_function {
+        if let Some(constructor) = resolve_sum_case_constructor(ctx, &resolved) {
+            resolved = constructor;
+        }
+    }
     nr.name = resolved.name;
 
     if let Some(typ) = type_hint {
@@ -571,12 +1542,12 @@ fn type_check_name(ctx: &mut TypeCheckerContext, nr: &mut NameRef, type_hint: Op
         }
 
         if !resolved.typ.is_generic() && !typ.is_generic() && !resolved.typ.is_convertible(typ) {
-            return type_error_result(&nr.span, format!("Type mismatch: expecting {}, but {} has type {}", typ, nr.name, resolved.typ));
+            return ErrorCode::TypeMismatch.result(&nr.span, format!("Type mismatch: expecting {}, but {} has type {}", typ, nr.name, resolved.typ));
         }
 
         if resolved.typ.is_generic() && !typ.is_generic() {
             if !is_instantiation_of(typ, &resolved.typ) {
-                type_error_result(&nr.span, format!("Type mismatch: {} is not a valid instantiation of {}", typ, resolved.typ))
+                ErrorCode::TypeMismatch.result(&nr.span, format!("Type mismatch: {} is not a valid instantiation of {}", typ, resolved.typ))
             } else {
                 nr.typ = typ.clone();
                 valid(nr.typ.clone())
@@ -615,13 +1586,17 @@ fn add_struct_bindings(ctx: &mut TypeCheckerContext, b: &mut StructPattern, stru
     Ok(())
 }
 
-fn type_check_binding(ctx: &mut TypeCheckerContext, b: &mut Binding, target: &Target) -> TypeCheckResult
+// statement_span is the span of the whole let or var statement, or of the binding when there are several
+fn type_check_binding(ctx: &mut TypeCheckerContext, b: &mut Binding, statement_span: &Span, target: &Target) -> TypeCheckResult
 {
     b.typ = type_check_expression(ctx, &mut b.init, None, target)?;
 
     match b.binding_type
     {
         BindingType::Name(ref name) => {
+            if b.typ == Type::Void && name != "_" {
+                return ErrorCode::NoValue.result(statement_span, format!("Expression has no value, it cannot be bound to {}", name));
+            }
             ctx.add(Symbol::new(name, &b.typ, b.mutable, &b.span, SymbolType::Normal))?;
         },
 
@@ -631,16 +1606,37 @@ fn type_check_binding(ctx: &mut TypeCheckerContext, b: &mut Binding, target: &Ta
             if let Type::Struct(ref st) = b.typ
             {
                 if st.members.len() != s.bindings.len() {
-                    return type_error_result(&s.span,
+                    return ErrorCode::InvalidPattern.result(&s.span,
                         format!("Wrong number of members in struct binding (expecting {}, found {})",
                             st.members.len(), s.bindings.len()));
                 }
 
+                if !s.name.is_empty() {
+                    let resolved = ctx.resolve(&s.name).ok_or_else(|| unknown_name_error(ctx, &s.span, format!("Unknown struct {}", s.name), &s.name, NameKind::Type))?;
+                    if resolved.name != st.name {
+                        return ErrorCode::InvalidPattern.result(&s.span,
+                            format!("Cannot bind an expression of type {} to a struct pattern of type {}", b.typ, s.name));
+                    }
+                    s.name = resolved.name;
+                }
+
                 add_struct_bindings(ctx, s, st, false)?;
             }
             else
             {
-                return type_error_result(&b.init.span(), "Expression does not return a struct type");
+                return ErrorCode::InvalidPattern.result(&b.init.span(), "Expression does not return a struct type");
+            }
+        },
+
+        BindingType::Array(ref a) => {
+            match b.typ
+            {
+                Type::Array(_) | Type::Slice(_) => {
+                    let element_type = b.typ.get_element_type().expect("Expecting an array or slice type");
+                    ctx.add(Symbol::new(&a.head, &element_type, false, &a.span, SymbolType::Normal))?;
+                    ctx.add(Symbol::new(&a.tail, &slice_type(element_type), false, &a.span, SymbolType::Normal))?;
+                },
+                _ => return ErrorCode::InvalidPattern.result(&b.init.span(), format!("Cannot bind an expression of type {} to an array pattern", b.typ)),
             }
         },
     }
@@ -648,25 +1644,85 @@ fn type_check_binding(ctx: &mut TypeCheckerContext, b: &mut Binding, target: &Ta
     valid(b.typ.clone())
 }
 
-fn update_binding_type(ctx: &mut TypeCheckerContext, e: &mut Expression, name: &str, expected_type: &Type, target: &Target) -> CompileResult<()>
+// Type checks the initializer of the binding named name again, with the expected type as hint
+struct BindingTypeUpdater<'a, 'b: 'a>
 {
-    let mut update_binding = |e: &mut Expression| {
-        if let Expression::Bindings(ref mut bl) = *e {
-            for b in &mut bl.bindings {
-                if let BindingType::Name(ref b_name) = b.binding_type
-                {
-                    if *b_name == *name {
-                        // It's one we know, so lets try again with a proper type hint
-                        b.typ = type_check_expression(ctx, &mut b.init, Some(&expected_type), target)?;
-                        ctx.update(Symbol::new(b_name, &b.typ, b.mutable, &b.span, SymbolType::Normal));
-                        return Ok(())
-                    }
-                }
+    ctx: &'a mut TypeCheckerContext<'b>,
+    name: &'a str,
+    expected_type: &'a Type,
+    target: &'a Target,
+}
+
+impl<'a, 'b> MutVisitor for BindingTypeUpdater<'a, 'b>
+{
+    fn visit_binding_mut(&mut self, b: &mut Binding) -> CompileResult<()>
+    {
+        if let BindingType::Name(ref b_name) = b.binding_type
+        {
+            if *b_name == *self.name {
+                // It's one we know, so lets try again with a proper type hint
+                b.typ = type_check_expression(self.ctx, &mut b.init, Some(self.expected_type), self.target)?;
+                self.ctx.update(Symbol::new(b_name, &b.typ, b.mutable, &b.span, SymbolType::Normal));
             }
         }
-        Ok(())
+
+        walk_binding_mut(self, b)
+    }
+}
+
+fn update_binding_type(ctx: &mut TypeCheckerContext, e: &mut Expression, name: &str, expected_type: &Type, target: &Target) -> CompileResult<()>
+{
+    let mut updater = BindingTypeUpdater{ctx, name, expected_type, target};
+    updater.visit_expression_mut(e)
+}
+
+// The type two branches of an if or a match are converted to: their own type when they have the same type, an optional
+// when one of them can be converted to an optional of the other, and a slice when they are arrays or slices of the same element type
+fn common_branch_type(a: &Type, b: &Type) -> Option<Type>
+{
+    if a == b {
+        Some(a.clone())
+    } else if (*a == Type::Void && b.is_optional()) || (*b == Type::Void && a.is_optional()) {
+        None
+    } else if a.is_optional_of(b) || a.is_optional_of(&Type::Unknown) {
+        Some(optional_type(b.clone()))
+    } else if b.is_optional_of(a) || b.is_optional_of(&Type::Unknown) {
+        Some(optional_type(a.clone()))
+    } else {
+        match (a, b)
+        {
+            (&Type::Array(_), &Type::Slice(_)) |
+            (&Type::Slice(_), &Type::Array(_)) |
+            (&Type::Array(_), &Type::Array(_)) if a.get_element_type() == b.get_element_type() => a.get_element_type().map(slice_type),
+            _ => None,
+        }
+    }
+}
+
+// The then and else part of an if must have the same type, unless one of them can be converted to the type of the other,
+// a branch which always returns has no value, so the if gets the type of the other one
+fn if_branches_type(on_true: &Expression, on_true_type: &Type, on_false: Option<(&Expression, &Type)>, span: &Span) -> CompileResult<Type>
+{
+    let (on_false, on_false_type) = match on_false {
+        Some(branch) => branch,
+        None if *on_true_type == Type::Void || on_true.diverges() => return Ok(Type::Void),
+        None => return ErrorCode::IfBranchTypeMismatch.result(span, format!("If expressions without an else part, must return void (type of then part is {})", on_true_type)),
     };
-    e.visit_mut(&mut update_binding)
+
+    if on_true.diverges() {
+        return Ok(on_false_type.clone());
+    } else if on_false.diverges() {
+        return Ok(on_true_type.clone());
+    }
+
+    if (*on_true_type == Type::Void && on_false_type.is_optional_of(&Type::Unknown)) ||
+        (*on_false_type == Type::Void && on_true_type.is_optional_of(&Type::Unknown)) {
+        return ErrorCode::IfBranchTypeMismatch.result(span, "One branch of the if expression has no value, so the nil in the other one cannot be an optional of it");
+    }
+
+    common_branch_type(on_true_type, on_false_type).ok_or_else(|| ErrorCode::IfBranchTypeMismatch.error(span,
+        format!("then and else expression of an if expression need to be of the same type, then has type {}, else has type {}", on_true_type, on_false_type)
+    ))
 }
 
 fn type_check_if(ctx: &mut TypeCheckerContext, i: &mut IfExpression, type_hint: Option<&Type>, target: &Target) -> TypeCheckResult
@@ -674,56 +1730,64 @@ fn type_check_if(ctx: &mut TypeCheckerContext, i: &mut IfExpression, type_hint:
     type_check_with_conversion(ctx, &mut i.condition, &Type::Bool, target)?;
 
     let on_true_type = type_check_expression(ctx, &mut i.on_true, type_hint.clone(), target)?;
-    let on_false_type = if let Some(ref mut expr) = i.on_false {
-        type_check_expression(ctx, expr, type_hint.clone(), target)?
-    } else {
-        Type::Void
+    let on_false_type = match i.on_false {
+        Some(ref mut expr) => Some(type_check_expression(ctx, expr, type_hint.clone(), target)?),
+        None => None,
     };
 
-    if on_true_type != on_false_type
-    {
-        if i.on_false.is_none()
-        {
-            type_error_result(&i.span, format!("If expressions without an else part, must return void (type of then part is {})", on_true_type))
-        }
-        else if on_true_type.is_optional_of(&on_false_type) || on_true_type.is_optional_of(&Type::Unknown)
-        {
-            let optional_type = optional_type(on_false_type);
-            if let Some(ref mut expr) = i.on_false {
-                type_check_with_conversion(ctx, expr, &optional_type, target)?;
-            }
-            type_check_with_conversion(ctx, &mut i.on_true, &optional_type, target)?;
-            i.typ = optional_type.clone();
-            valid(optional_type)
-        }
-        else if on_false_type.is_optional_of(&on_true_type) || on_false_type.is_optional_of(&Type::Unknown)
-        {
-            let optional_type = optional_type(on_true_type);
-            if let Some(ref mut expr) = i.on_false {
-                type_check_with_conversion(ctx, expr, &optional_type, target)?;
-            }
-            type_check_with_conversion(ctx, &mut i.on_true, &optional_type, target)?;
-            i.typ = optional_type.clone();
-            valid(optional_type)
-        }
-        else
-        {
-            type_error_result(&i.span,
-                format!("then and else expression of an if expression need to be of the same type, then has type {}, else has type {}", on_true_type, on_false_type)
-            )
-        }
+    let typ = if_branches_type(&i.on_true, &on_true_type, i.on_false.as_ref().zip(on_false_type.as_ref()), &i.span)?;
+    convert_type(ctx, &typ, &on_true_type, &mut i.on_true, target)?;
+    if let (Some(ref mut expr), Some(ref on_false_type)) = (i.on_false.as_mut(), on_false_type) {
+        convert_type(ctx, &typ, on_false_type, expr, target)?;
     }
-    else
+    i.typ = typ.clone();
+    valid(typ)
+}
+
+fn type_check_if_let_bindings(ctx: &mut TypeCheckerContext, i: &mut IfLetExpression, on_false_type: Option<&Type>, type_hint: Option<&Type>, target: &Target) -> CompileResult<Type>
+{
+    for b in &mut i.bindings
     {
-        i.typ = on_true_type;
-        valid(on_false_type)
+        let typ = type_check_expression(ctx, &mut b.init, None, target)?;
+        b.inner_type = match typ.get_element_type() {
+            Some(ref inner_type) if typ.is_optional() => inner_type.clone(),
+            _ => return ErrorCode::TypeMismatch.result(&b.init.span(), format!("if let expects an optional, but {} has type {}", b.name, typ)),
+        };
+        ctx.add(Symbol::new(&b.name, &b.inner_type, false, &b.span, SymbolType::Normal))?;
+    }
+
+    let on_true_type = type_check_expression(ctx, &mut i.on_true, type_hint, target)?;
+    let typ = if_branches_type(&i.on_true, &on_true_type, i.on_false.as_ref().zip(on_false_type), &i.span)?;
+    // Converting the then part type checks it again, so the bindings must still be in scope
+    convert_type(ctx, &typ, &on_true_type, &mut i.on_true, target)?;
+    Ok(typ)
+}
+
+fn type_check_if_let(ctx: &mut TypeCheckerContext, i: &mut IfLetExpression, type_hint: Option<&Type>, target: &Target) -> TypeCheckResult
+{
+    // The else part is checked first, so it cannot see the bindings
+    let on_false_type = match i.on_false {
+        Some(ref mut expr) => Some(type_check_expression(ctx, expr, type_hint, target)?),
+        None => None,
+    };
+
+    // Each binding is visible in the initializers of the bindings after it and in the then part
+    ctx.enter_scope(None);
+    let typ = type_check_if_let_bindings(ctx, i, on_false_type.as_ref(), type_hint, target);
+    ctx.exit_scope();
+    let typ = typ?;
+
+    if let (Some(ref mut expr), Some(ref on_false_type)) = (i.on_false.as_mut(), on_false_type) {
+        convert_type(ctx, &typ, on_false_type, expr, target)?;
     }
+    i.typ = typ.clone();
+    valid(typ)
 }
 
 fn type_check_struct_members_in_initializer(ctx: &mut TypeCheckerContext, st: &StructType, si: &mut StructInitializer, target: &Target) -> CompileResult<Type>
 {
     if st.members.len() != si.member_initializers.len() {
-        return type_error_result(&si.span,
+        return ErrorCode::WrongArgumentCount.result(&si.span,
             format!("Type {} has {} members, but attempting to initialize {} members", si.struct_name, st.members.len(), si.member_initializers.len()));
     }
 
@@ -731,30 +1795,32 @@ fn type_check_struct_members_in_initializer(ctx: &mut TypeCheckerContext, st: &S
 
     for (idx, (member, mi)) in st.members.iter().zip(si.member_initializers.iter_mut()).enumerate()
     {
-        let t = type_check_expression(ctx, mi, Some(&member.typ), target)?;
+        // Generic arguments filled in by earlier members, help to type the next members
+        let hint = if member.typ.is_generic() {
+            make_concrete(ctx, &si.generic_args, &member.typ, &mi.span())?
+        } else {
+            member.typ.clone()
+        };
+
+        let t = type_check_expression(ctx, mi, Some(&hint), target)?;
         let expected_type = if member.typ.is_generic() {
             fill_in_generics(ctx, &t, &member.typ, &mut si.generic_args, &mi.span())?
         } else {
             member.typ.clone()
         };
 
-        if t != expected_type
-        {
-            if let Some(new_mi) = expected_type.convert(&t, mi) {
-                *mi = new_mi;
-            } else {
-                return type_error_result(
-                    &mi.span(),
-                    format!("Attempting to initialize member {} with type '{}', expecting an expression of type '{}'",
-                            idx, t, expected_type)
-                );
-            }
+        if t != expected_type && convert_type(ctx, &expected_type, &t, mi, target).is_err() {
+            return ErrorCode::TypeMismatch.result(
+                &mi.span(),
+                format!("Attempting to initialize member {} with type '{}', expecting an expression of type '{}'",
+                        idx, t, expected_type)
+            );
         }
 
         new_members.push(struct_member(&member.name, expected_type));
     }
 
-    Ok(struct_type(&st.name, new_members))
+    Ok(struct_type_with_members(st, new_members))
 }
 
 fn type_check_anonymous_struct_initializer(ctx: &mut TypeCheckerContext, si: &mut StructInitializer, target: &Target) -> TypeCheckResult
@@ -769,13 +1835,14 @@ fn type_check_anonymous_struct_initializer(ctx: &mut TypeCheckerContext, si: &mu
     valid(si.typ.clone())
 }
 
-fn type_check_struct_initializer(ctx: &mut TypeCheckerContext, si: &mut StructInitializer, target: &Target) -> TypeCheckResult
+fn type_check_struct_initializer(ctx: &mut TypeCheckerContext, si: &mut StructInitializer, type_hint: Option<&Type>, target: &Target) -> TypeCheckResult
 {
     if si.struct_name.is_empty() {
         return type_check_anonymous_struct_initializer(ctx, si, target);
     }
 
-    let resolved = ctx.resolve(&si.struct_name).ok_or_else(|| unknown_name(&si.span, format!("Unknown struct {}", si.struct_name)))?;
+    let resolved = ctx.resolve(&si.struct_name)
+        .ok_or_else(|| unknown_name_error(ctx, &si.span, format!("Unknown struct {}", si.struct_name), &si.struct_name, NameKind::Type))?;
     si.struct_name = resolved.name;
     match resolved.typ
     {
@@ -785,19 +1852,26 @@ fn type_check_struct_initializer(ctx: &mut TypeCheckerContext, si: &mut StructIn
         },
         Type::Sum(ref st) => {
             let idx = st.index_of(&si.struct_name).expect("Internal Compiler Error: cannot determine index of sum type case");
+            // If we expect a concrete instance of a generic sum type, use the types of the expected cases
+            let hint_cases = match type_hint {
+                Some(&Type::Sum(ref hst)) if resolved.typ.is_generic() && same_sum_type_cases(st, hst) => Some(&hst.cases),
+                _ => None,
+            };
+
             let mut sum_type_cases = Vec::with_capacity(st.cases.len());
             for (i, case) in st.cases.iter().enumerate()
             {
+                let case_typ = hint_cases.map(|cases| &cases[i].typ).unwrap_or(&case.typ);
                 let typ = if i == idx
                 {
-                    match case.typ
+                    match *case_typ
                     {
                         Type::Struct(ref s) => type_check_struct_members_in_initializer(ctx, s, si, target)?,
                         Type::Int(p) => Type::Int(p),
                         _ => return type_error_result(&si.span, "Invalid sum type case"),
                     }
                 } else {
-                    case.typ.clone()
+                    case_typ.clone()
                 };
                 sum_type_cases.push(sum_type_case(&case.name, typ))
             }
@@ -819,13 +1893,65 @@ fn type_check_struct_initializer(ctx: &mut TypeCheckerContext, si: &mut StructIn
 
 
 
+fn same_sum_type_cases(a: &SumType, b: &SumType) -> bool
+{
+    a.cases.len() == b.cases.len() && a.cases.iter().zip(b.cases.iter()).all(|(x, y)| x.name == y.name)
+}
+
+fn type_check_propagate(ctx: &mut TypeCheckerContext, p: &mut Propagate, target: &Target) -> TypeCheckResult
+{
+    let inner_type = type_check_expression(ctx, &mut p.inner, None, target)?;
+    let ((_, ok_type), (_, err_type)) = match result_cases(&inner_type) {
+        Some(cases) => cases,
+        None => return ErrorCode::InvalidTryOperator.result(&p.span, format!("The ? operator can only be applied to a Result, not to an expression of type {}", inner_type)),
+    };
+
+    let return_type = match ctx.get_function_return_type() {
+        Some(return_type) => return_type,
+        None => return ErrorCode::InvalidTryOperator.result(&p.span, "The ? operator can only be used inside a function"),
+    };
+
+    match result_cases(&return_type) {
+        Some((_, (_, ref return_err_type))) if *return_err_type == err_type => (),
+        Some((_, (_, return_err_type))) => {
+            return ErrorCode::InvalidTryOperator.result(&p.span,
+                format!("The ? operator cannot return an error of type {} from a function returning errors of type {}", err_type, return_err_type));
+        },
+        None => {
+            return ErrorCode::InvalidTryOperator.result(&p.span,
+                format!("The ? operator can only be used in a function returning a Result, not {}", return_type));
+        },
+    }
+
+    p.typ = ok_type;
+    p.return_type = return_type;
+    valid(p.typ.clone())
+}
+
 fn find_member_type(members: &[StructMember], member_name: &str, span: &Span) -> CompileResult<(usize, Type)>
 {
     members.iter()
         .enumerate()
         .find(|&(_, m)| m.name == *member_name)
         .map(|(idx, m)| (idx, m.typ.clone()))
-        .ok_or_else(|| unknown_name(span, format!("Unknown struct member {}", member_name)))
+        .ok_or_else(|| {
+            let suggestions = closest_names(member_name, members.iter().map(|m| &m.name[..]), 3);
+            ErrorCode::UnknownName.error(span, with_suggestions(format!("Unknown struct member {}", member_name), &suggestions))
+        })
+}
+
+// Tuple members are accessed by position: t.0, t.1, ...
+fn find_tuple_member_type(tuple: &Type, st: &StructType, member_name: &str, span: &Span) -> CompileResult<(usize, Type)>
+{
+    match member_name.parse::<usize>()
+    {
+        Ok(idx) if idx < st.members.len() => Ok((idx, st.members[idx].typ.clone())),
+        Ok(idx) => ErrorCode::NoSuchMember.result(span,
+            format!("Tuple {} has no member {}, it only has {} members", tuple, idx, st.members.len())),
+        Err(_) => ErrorCode::NoSuchMember.result(span,
+            format!("Tuple {} has no member named {}, tuple members are accessed by position (.0 to .{})",
+                tuple, member_name, st.members.len().saturating_sub(1))),
+    }
 }
 
 fn member_call_to_call(left: &Expression, call: &Call, int_size: IntSize) -> Expression
@@ -839,15 +1965,9 @@ fn member_call_to_call(left: &Expression, call: &Call, int_size: IntSize) -> Exp
 
     args.push(first_arg);
     args.extend(call.args.iter().cloned());
-    Expression::Call(
-        Box::new(
-            Call::new(
-                call.callee.clone(),
-                args,
-                call.span.clone(),
-            )
-        )
-    )
+    let mut new_call = Call::new(call.callee.clone(), args, call.span.clone());
+    new_call.named_args = call.named_args.clone();
+    Expression::Call(Box::new(new_call))
 }
 
 fn type_check_generic_member_call(ctx: &mut TypeCheckerContext, call: &mut Call, gt: &GenericType) -> CompileResult<Type>
@@ -867,10 +1987,10 @@ fn type_check_generic_member_call(ctx: &mut TypeCheckerContext, call: &mut Call,
     {
         GenericType::Any(ref name) => {
             let interface = ctx.resolve(name)
-                .ok_or_else(|| type_error(&call.span, format!("Type {} is not an interface", name)))?;
+                .ok_or_else(|| ErrorCode::NotCallable.error(&call.span, format!("Type {} is not an interface", name)))?;
 
             call.return_type = check_interface(&interface.typ, call)
-                .ok_or_else(|| type_error(&call.span, format!("Interface {} has no member function named {}", interface.name, call.callee.name)))?;
+                .ok_or_else(|| ErrorCode::NotCallable.error(&call.span, format!("Interface {} has no member function named {}", interface.name, call.callee.name)))?;
             Ok(call.return_type.clone())
         },
 
@@ -882,11 +2002,33 @@ fn type_check_generic_member_call(ctx: &mut TypeCheckerContext, call: &mut Call,
                 }
             }
 
-            type_error_result(&call.span, format!("No member function named {}", call.callee.name))
+            ErrorCode::NotCallable.result(&call.span, format!("No member function named {}", call.callee.name))
         }
     }
 }
 
+// Call through the vtable of an interface value, self is passed by the code generator
+fn type_check_interface_member_call(ctx: &mut TypeCheckerContext, call: &mut Call, it: &InterfaceType, target: &Target) -> CompileResult<Type>
+{
+    check_interface_value_type(it, &call.span)?;
+    let func = it.functions.iter()
+        .find(|f| f.name == call.callee.name)
+        .ok_or_else(|| ErrorCode::NotCallable.error(&call.span, format!("Interface {} has no member function named {}", it.name, call.callee.name)))?;
+
+    let num_args = func.args.len() - 1;
+    if call.args.len() != num_args {
+        return ErrorCode::WrongArgumentCount.result(&call.span,
+            format!("Attempting to call {}.{} with {} arguments, but it needs {}", it.name, func.name, call.args.len(), num_args));
+    }
+
+    for (arg, expected) in call.args.iter_mut().zip(func.args.iter().skip(1)) {
+        type_check_with_conversion(ctx, arg, &expected.typ, target)?;
+    }
+
+    call.return_type = func.return_type.clone();
+    Ok(call.return_type.clone())
+}
+
 fn to_static_function_call(ctx: &mut TypeCheckerContext, sma: &MemberAccess) -> Option<Call> 
 {
     if let Expression::NameRef(ref nr) = sma.left {
@@ -896,13 +2038,9 @@ fn to_static_function_call(ctx: &mut TypeCheckerContext, sma: &MemberAccess) ->
                 if let Type::Func(_) = rn.typ {
                     let name_span = nr.span.expanded(call.callee.span.end);
                     let full_span = name_span.expanded(call.span.end);
-                    return Some(
-                        Call::new(
-                            NameRef::new(call_name, name_span), 
-                            call.args.clone(),
-                            full_span,
-                        )
-                    )
+                    let mut static_call = Call::new(NameRef::new(call_name, name_span), call.args.clone(), full_span);
+                    static_call.named_args = call.named_args.clone();
+                    return Some(static_call)
                 }
             }
         }
@@ -925,6 +2063,12 @@ fn type_check_member_access(ctx: &mut TypeCheckerContext, sma: &mut MemberAccess
         return replace_by(Expression::Call(Box::new(call)))
     }
 
+    if left_type == Type::String {
+        if let Some(lit) = fold_member_access_in_context(ctx, sma, target) {
+            return replace_by(Expression::Literal(lit));
+        }
+    }
+
     let (typ, new_right) = match (&mut sma.right, left_type_ref)
     {
         (&mut MemberAccessType::Property(Property::Len), &Type::Slice(_)) |
@@ -938,6 +2082,12 @@ fn type_check_member_access(ctx: &mut TypeCheckerContext, sma: &mut MemberAccess
         (&mut MemberAccessType::Property(Property::Data), &Type::Slice(ref st)) =>
             (ptr_type(st.element_type.clone()), None),
 
+        (&mut MemberAccessType::Name(ref mut field), &Type::Struct(ref st)) if st.name.is_empty() => {
+            let (member_idx, member_type) = find_tuple_member_type(left_type_ref, st, &field.name, &sma.span)?;
+            field.index = member_idx;
+            (member_type, None)
+        },
+
         (&mut MemberAccessType::Name(ref mut field), &Type::Struct(ref st)) => {
             let (member_idx, member_type) = find_member_type(&st.members, &field.name, &sma.span)?;
             field.index = member_idx;
@@ -950,7 +2100,7 @@ fn type_check_member_access(ctx: &mut TypeCheckerContext, sma: &mut MemberAccess
             if let Some((typ, member_access_type)) = left_type.get_property_type(&field.name, target) {
                 (typ, Some(member_access_type))
             } else {
-                return type_error_result(
+                return ErrorCode::NoSuchMember.result(
                     &sma.span,
                     format!("Type '{}' has no property named '{}'", left_type, field.name)
                 );
@@ -970,11 +2120,17 @@ fn type_check_member_access(ctx: &mut TypeCheckerContext, sma: &mut MemberAccess
         },
 
         (&mut MemberAccessType::Call(ref mut call), &Type::Generic(ref gt)) => {
+            named_args_not_allowed(call, &call.callee.name)?;
             (type_check_generic_member_call(ctx, call, gt)?, None)
         },
 
+        (&mut MemberAccessType::Call(ref mut call), &Type::Interface(ref it)) => {
+            named_args_not_allowed(call, &call.callee.name)?;
+            (type_check_interface_member_call(ctx, call, it, target)?, None)
+        },
+
         _ => {
-            return type_error_result(
+            return ErrorCode::NoSuchMember.result(
                 &sma.span,
                 format!("Cannot determine type of member access ({})", left_type_ref)
             );
@@ -988,15 +2144,39 @@ fn type_check_member_access(ctx: &mut TypeCheckerContext, sma: &mut MemberAccess
     valid(sma.typ.clone())
 }
 
-fn type_check_struct_pattern(ctx: &mut TypeCheckerContext, p: &mut StructPattern, target_is_mutable: bool) -> CompileResult<()>
+fn type_check_struct_pattern(ctx: &mut TypeCheckerContext, p: &mut StructPattern, target_type: &Type, target_is_mutable: bool) -> CompileResult<()>
 {
     if !p.typ.is_unknown() {
         return Ok(());
     }
 
-    let resolved = ctx.resolve(&p.name).ok_or_else(|| unknown_name(&p.span, format!("Unknown struct {}", p.name)))?;
+    if p.name.is_empty() {
+        return match *target_type
+        {
+            Type::Struct(ref st) if st.name.is_empty() => {
+                if st.members.len() != p.bindings.len() {
+                    return ErrorCode::InvalidPattern.result(&p.span,
+                        format!("Wrong number of bindings in pattern match (expecting {}, found {})",
+                            st.members.len(), p.bindings.len()));
+                }
+
+                add_struct_bindings(ctx, p, st, target_is_mutable)?;
+                p.typ = target_type.clone();
+                Ok(())
+            },
+            _ => ErrorCode::InvalidPattern.result(&p.span, format!("Tuple pattern cannot match an expression of type {}", target_type)),
+        };
+    }
+
+    let resolved = ctx.resolve(&p.name).ok_or_else(|| unknown_name_error(ctx, &p.span, format!("Unknown struct {}", p.name), &p.name, NameKind::Type))?;
     p.name = resolved.name.clone();
-    match resolved.typ
+    // Matching on a concrete instance of a generic sum type
+    let typ = match (&resolved.typ, target_type) {
+        (&Type::Sum(ref st), &Type::Sum(ref tst)) if resolved.typ.is_generic() && same_sum_type_cases(st, tst) => target_type,
+        _ => &resolved.typ,
+    };
+
+    match *typ
     {
         Type::Sum(ref st) => {
             let idx = st.index_of(&p.name).expect("Internal Compiler Error: cannot determine index of sum type case");
@@ -1005,7 +2185,7 @@ fn type_check_struct_pattern(ctx: &mut TypeCheckerContext, p: &mut StructPattern
             {
                 Type::Struct(ref s) => {
                     if s.members.len() != p.bindings.len() {
-                        type_error_result(&p.span,
+                        ErrorCode::InvalidPattern.result(&p.span,
                             format!("Wrong number of bindings in pattern match (expecting {}, found {})",
                                 s.members.len(), p.bindings.len()))
                     } else {
@@ -1014,7 +2194,7 @@ fn type_check_struct_pattern(ctx: &mut TypeCheckerContext, p: &mut StructPattern
                         Ok(())
                     }
                 },
-                _ => type_error_result(&p.span, "Attempting to pattern match a normal sum type case with a struct"),
+                _ => ErrorCode::InvalidPattern.result(&p.span, "Attempting to pattern match a normal sum type case with a struct"),
             }
         },
 
@@ -1023,7 +2203,51 @@ fn type_check_struct_pattern(ctx: &mut TypeCheckerContext, p: &mut StructPattern
             p.typ = Type::Struct(st.clone());
             Ok(())
         },
-        _ => type_error_result(&p.span, "Struct pattern is only allowed for structs and sum types containing structs")
+        _ => ErrorCode::InvalidPattern.result(&p.span, "Struct pattern is only allowed for structs and sum types containing structs")
+    }
+}
+
+// The value of an expression in statement position is thrown away, which is almost always a mistake,
+// so that has to be done explicitly with _ = expr. For blocks, ifs and matches it is the value of their
+// final expressions which is thrown away.
+fn check_value_is_used(e: &Expression, int_size: IntSize) -> CompileResult<()>
+{
+    match *e
+    {
+        // Whatever comes after a return is never evaluated
+        _ if e.diverges() => Ok(()),
+
+        Expression::Block(ref b) => match b.expressions.last() {
+            Some(last) => check_value_is_used(last, int_size),
+            None => Ok(()),
+        },
+
+        Expression::If(ref i) => {
+            check_value_is_used(&i.on_true, int_size)?;
+            i.on_false.as_ref().map(|f| check_value_is_used(f, int_size)).unwrap_or(Ok(()))
+        },
+
+        Expression::IfLet(ref i) => {
+            check_value_is_used(&i.on_true, int_size)?;
+            i.on_false.as_ref().map(|f| check_value_is_used(f, int_size)).unwrap_or(Ok(()))
+        },
+
+        Expression::Match(ref m) => {
+            for c in &m.cases {
+                check_value_is_used(&c.to_execute, int_size)?;
+            }
+            Ok(())
+        },
+
+        Expression::Bindings(_) |
+        Expression::Return(_) => Ok(()),
+
+        _ => match e.get_type(int_size)
+        {
+            Type::Void | Type::Unknown => Ok(()),
+            typ => ErrorCode::UnusedValue.result(&e.span(),
+                format!("The value of this expression (type {}) is not used, discard it explicitly with _ = expression", typ)),
+        },
     }
 }
 
@@ -1036,6 +2260,8 @@ fn type_check_block(ctx: &mut TypeCheckerContext, b: &mut Block, type_hint: Opti
         let typ = type_check_expression(ctx, e, type_hint, target)?;
         if idx == num - 1 {
             b.typ = typ;
+        } else {
+            check_value_is_used(e, target.int_size)?;
         }
     }
 
@@ -1087,7 +2313,7 @@ fn type_check_dereference(ctx: &mut TypeCheckerContext, a: &mut DereferenceExpre
         a.typ = inner.deref().clone();
         valid(a.typ.clone())
     } else {
-        type_error_result(&a.span, "Attempting to dereference a non pointer type expression")
+        ErrorCode::UnsupportedOperator.result(&a.span, "Attempting to dereference a non pointer type expression")
     }
 }
 
@@ -1097,14 +2323,14 @@ fn type_check_index_operation(ctx: &mut TypeCheckerContext, iop: &mut IndexOpera
     let index_type = type_check_expression(ctx, &mut iop.index_expr, None, target)?;
     match index_type {
         Type::Int(_) | Type::UInt(_) => (),
-        _ => return type_error_result(&iop.span, format!("An expression of type {}, cannot be used to index something. Only integers are supported.", index_type))
+        _ => return ErrorCode::UnsupportedOperator.result(&iop.span, format!("An expression of type {}, cannot be used to index something. Only integers are supported.", index_type))
     }
 
     let typ = match target_type {
         Type::Pointer(ref inner) => inner.deref().clone(),
         Type::Slice(ref st) => st.element_type.clone(),
         Type::Array(ref at) => at.element_type.clone(),
-        _ => return type_error_result(&iop.span, format!("Cannot an index an expression of type {}", target_type)),
+        _ => return ErrorCode::UnsupportedOperator.result(&iop.span, format!("Cannot an index an expression of type {}", target_type)),
     };
 
     iop.typ = typ.clone();
@@ -1144,7 +2370,7 @@ fn type_check_assign(ctx: &mut TypeCheckerContext, a: &mut Assign, target: &Targ
         AssignTarget::Var(ref mut nr) => {
             type_check_name(ctx, nr, None)?;
             if !ctx.resolve(&nr.name).map(|rn| rn.mutable).unwrap_or(false) {
-                return type_error_result(&nr.span, format!("Attempting to modify non mutable variable {}", nr.name));
+                return ErrorCode::ImmutableModification.result(&nr.span, format!("Attempting to modify non mutable variable {}", nr.name));
             }
             nr.typ.clone()
         }
@@ -1152,7 +2378,7 @@ fn type_check_assign(ctx: &mut TypeCheckerContext, a: &mut Assign, target: &Targ
         AssignTarget::MemberAccess(ref mut ma) => {
             type_check_member_access(ctx, ma, target)?;
             if !is_result_mutable(ctx, &ma.left) {
-                return type_error_result(&ma.span, "Attempting to modify non mutable expression");
+                return ErrorCode::ImmutableModification.result(&ma.span, "Attempting to modify non mutable expression");
             }
             ma.typ.clone()
         }
@@ -1160,13 +2386,19 @@ fn type_check_assign(ctx: &mut TypeCheckerContext, a: &mut Assign, target: &Targ
         AssignTarget::Dereference(ref mut d) => {
             type_check_dereference(ctx, d, target)?;
             if !is_result_mutable(ctx, &d.inner) {
-                return type_error_result(&d.span, "Attempting to modify non mutable expression");
+                return ErrorCode::ImmutableModification.result(&d.span, "Attempting to modify non mutable expression");
             }
             d.typ.clone()
         }
 
         AssignTarget::IndexOperation(ref mut iop) => {
-            type_check_index_operation(ctx, iop, target)?
+            let typ = type_check_index_operation(ctx, iop, target)?;
+            if let Type::Array(_) = iop.target.get_type(target.int_size) {
+                if !is_array_mutable(ctx, &iop.target, target.int_size) {
+                    return ErrorCode::ImmutableModification.result(&iop.span, "Attempting to modify an element of a non mutable array");
+                }
+            }
+            typ
         }
     };
 
@@ -1178,14 +2410,14 @@ fn type_check_assign(ctx: &mut TypeCheckerContext, a: &mut Assign, target: &Targ
         AssignOperator::Mul |
         AssignOperator::Div => {
             if !dst_type.is_numeric() {
-                return type_error_result(&a.span, format!("Operator {} is only supported on numeric types", a.operator));
+                return ErrorCode::UnsupportedOperator.result(&a.span, format!("Operator {} is only supported on numeric types", a.operator));
             }
         }
 
         AssignOperator::And |
         AssignOperator::Or => {
             if dst_type != Type::Bool {
-                return type_error_result(&a.span, format!("Operator {} is only supported on booleans", a.operator))
+                return ErrorCode::UnsupportedOperator.result(&a.span, format!("Operator {} is only supported on booleans", a.operator))
             }
         }
     }
@@ -1197,39 +2429,121 @@ fn type_check_while(ctx: &mut TypeCheckerContext, w: &mut WhileLoop, target: &Ta
 {
     type_check_with_conversion(ctx, &mut w.cond, &Type::Bool, target)?;
     type_check_expression(ctx, &mut w.body, None, target)?;
+    check_value_is_used(&w.body, target.int_size)?;
     valid(Type::Void)
 }
 
+// The member function of a named struct or sum type, or of a pointer to one, like a member call would call it
+fn member_function(ctx: &TypeCheckerContext, typ: &Type, member: &str) -> Option<Symbol>
+{
+    let name = match *typ
+    {
+        Type::Pointer(ref inner) => return member_function(ctx, inner, member),
+        Type::Struct(ref st) if !st.name.is_empty() => format!("{}.{}", st.name, member),
+        Type::Sum(ref st) => format!("{}.{}", st.name, member),
+        _ => return None,
+    };
+
+    ctx.resolve(&name).and_then(|s| if let Type::Func(_) = s.typ {Some(s)} else {None})
+}
+
+fn member_call(left: Expression, member: &str, span: &Span) -> Expression
+{
+    let call = Call::new(NameRef::new(member.into(), span.clone()), Vec::new(), span.clone());
+    member_access(left, MemberAccessType::Call(Box::new(call)), span.clone())
+}
+
+// A collection with an iterate member function is iterated over by the iterator it returns,
+// an iterator has a next member function, returning nil when there are no more elements.
+// Returns the element type, and the iterator is available under the iterator name of the loop.
+fn type_check_iterator(ctx: &mut TypeCheckerContext, f: &mut ForLoop, typ: Type, target: &Target) -> CompileResult<Type>
+{
+    let iterator_type = if member_function(ctx, &typ, "iterate").is_some() {
+        let iterable = member_call(f.iterable.clone(), "iterate", &f.iterable.span());
+        f.iterable = iterable;
+        let iterator_type = type_check_expression(ctx, &mut f.iterable, None, target)?;
+        if member_function(ctx, &iterator_type, "next").is_none() {
+            return ErrorCode::NotIterable.result(&f.span,
+                format!("Cannot iterate over expressions of type {}, the {} returned by its iterate function has no next member function",
+                    type_name(&typ), type_name(&iterator_type)));
+        }
+        iterator_type
+    } else if member_function(ctx, &typ, "next").is_some() {
+        typ
+    } else {
+        return ErrorCode::NotIterable.result(&f.span,
+            format!("Cannot iterate over expressions of type {}, it needs an iterate or a next member function", type_name(&typ)));
+    };
+
+    let iterator_name = f.iterator_name();
+    ctx.add(Symbol::new(&iterator_name, &iterator_type, true, &f.span, SymbolType::Normal))?;
+    let mut next = member_call(Expression::NameRef(NameRef::new(iterator_name, f.span.clone())), "next", &f.span);
+    let next_type = type_check_expression(ctx, &mut next, None, target)?;
+    f.next = Some(next);
+    match next_type
+    {
+        Type::Optional(ref inner) => Ok(inner.deref().clone()),
+        _ => ErrorCode::NotIterable.result(&f.span,
+            format!("The next member function of {} must return an optional, but it returns {}", type_name(&iterator_type), type_name(&next_type))),
+    }
+}
+
 fn type_check_for(ctx: &mut TypeCheckerContext, f: &mut ForLoop, target: &Target) -> TypeCheckResult
 {
     let typ = type_check_expression(ctx, &mut f.iterable, None, target)?;
-    match typ
+    ctx.enter_scope(None);
+    let element_type = match typ
     {
-        // Iterable
+        // Sequences are indexed
         Type::String | Type::Array(_) | Type::Slice(_) => {
-            ctx.enter_scope(None);
-            let element_type = if let Some(et) = typ.get_element_type() {
+            f.next = None;
+            if let Some(et) = typ.get_element_type() {
                 et
             } else {
-                return type_error_result(&f.span, format!("Cannot determine type of {}", f.loop_variable))
-            };
-
-            f.loop_variable_type = element_type.clone();
-            ctx.add(Symbol::new(&f.loop_variable, &element_type, false, &f.span, SymbolType::Normal))?;
-            type_check_expression(ctx, &mut f.body, None, target)?;
-            valid(Type::Void)
+                return ErrorCode::CannotInferType.result(&f.span, format!("Cannot determine type of {}", f.loop_variable))
+            }
         },
-        _ => type_error_result(&f.span, format!("Cannot iterate over expressions of type {}", typ)),
+        _ => type_check_iterator(ctx, f, typ, target)?,
+    };
+
+    f.loop_variable_type = element_type.clone();
+    ctx.add(Symbol::new(&f.loop_variable, &element_type, false, &f.span, SymbolType::Normal))?;
+    type_check_expression(ctx, &mut f.body, None, target)?;
+    check_value_is_used(&f.body, target.int_size)?;
+    ctx.exit_scope();
+    valid(Type::Void)
+}
+
+fn type_check_type_ascription(ctx: &mut TypeCheckerContext, t: &mut TypeAscription, target: &Target) -> TypeCheckResult
+{
+    if resolve_type(ctx, &mut t.typ, target) == TypeResolved::No {
+        check_generic_arg_count(ctx, &t.typ, &t.span)?;
+        return type_error_result(&t.span, format!("Unable to resolve type {}", t.typ));
+    }
+
+    if t.typ != Type::Void && t.typ.contains_void_value() {
+        return ErrorCode::NoValue.result(&t.span, format!("Cannot use type {}, {}", t.typ, VOID_HAS_NO_VALUES));
     }
+
+    let inner_type = type_check_expression(ctx, &mut t.inner, Some(&t.typ), target)?;
+    convert_type(ctx, &t.typ, &inner_type, &mut t.inner, target)?;
+    valid(t.typ.clone())
 }
 
 fn type_check_cast(ctx: &mut TypeCheckerContext, c: &mut TypeCast, target: &Target) -> TypeCheckResult
 {
+    if resolve_type(ctx, &mut c.destination_type, target) == TypeResolved::No {
+        check_generic_arg_count(ctx, &c.destination_type, &c.span)?;
+        return type_error_result(&c.span, format!("Unable to resolve type {}", c.destination_type));
+    }
+
     let inner_type = type_check_expression(ctx, &mut c.inner, None, target)?;
     match (inner_type, &c.destination_type)
     {
+        (Type::Int(_), &Type::Int(_)) |
         (Type::Int(_), &Type::UInt(_)) |
         (Type::Int(_), &Type::Float(_)) |
+        (Type::UInt(_), &Type::UInt(_)) |
         (Type::UInt(_), &Type::Int(_)) |
         (Type::UInt(_), &Type::Float(_)) |
         (Type::Float(_), &Type::Int(_)) |
@@ -1238,15 +2552,30 @@ fn type_check_cast(ctx: &mut TypeCheckerContext, c: &mut TypeCast, target: &Targ
         (Type::Pointer(ref from), &Type::Pointer(_)) if *from.deref() == Type::Void => valid(c.destination_type.clone()),
         (Type::Pointer(_), &Type::Bool) => valid(Type::Bool),
         (Type::Array(ref at), &Type::Pointer(ref to)) if at.element_type == *to.deref() => valid(c.destination_type.clone()),
-        (inner_type, _) => type_error_result(&c.span, format!("Cast from type {} to type {} is not allowed", inner_type, c.destination_type))
+        (Type::Slice(ref st), &Type::String) if st.element_type == Type::UInt(IntSize::I8) => valid(Type::String),
+        (inner_type, _) => ErrorCode::InvalidCast.result(&c.span, format!("Cast from type {} to type {} is not allowed", inner_type, c.destination_type))
+    }
+}
+
+// Named types are known by their name, the others by how they are written
+fn type_name(typ: &Type) -> String
+{
+    match *typ
+    {
+        Type::Struct(ref st) if !st.name.is_empty() => st.name.clone(),
+        Type::Sum(ref st) => st.name.clone(),
+        Type::Enum(ref et) => et.name.clone(),
+        _ => format!("{}", typ),
     }
 }
 
 fn type_check_compiler_call(ctx: &mut TypeCheckerContext, cc: &mut CompilerCall, type_hint: Option<&Type>, target: &Target) -> TypeCheckResult
 {
     match *cc {
-        CompilerCall::SizeOf(ref mut typ, ref span) => {
-            if resolve_type(ctx, typ) == TypeResolved::No {
+        CompilerCall::SizeOf(ref mut typ, ref span) |
+        CompilerCall::AlignOf(ref mut typ, ref span) => {
+            if resolve_type(ctx, typ, target) == TypeResolved::No {
+                check_generic_arg_count(ctx, typ, span)?;
                 type_error_result(span, format!("Unable to resolve type {}", typ))
             } else {
                 valid(target.native_uint_type.clone())
@@ -1266,7 +2595,49 @@ fn type_check_compiler_call(ctx: &mut TypeCheckerContext, cc: &mut CompilerCall,
                 *typ = slice_type(inner.deref().clone());
                 valid(typ.clone())
             } else {
-                type_error_result(span, format!("The first argument of @slice, must be a pointer, not a {}", data_type))
+                ErrorCode::InvalidBuiltinArgument.result(span, format!("The first argument of @slice, must be a pointer, not a {}", data_type))
+            }
+        }
+
+        // The expression is only type checked, it is replaced by the name of its type
+        CompilerCall::TypeName(ref mut e, ref span) => {
+            let typ = type_check_expression(ctx, e, None, target)?;
+            if typ.is_generic() {
+                valid(Type::String)
+            } else {
+                replace_by(Expression::Literal(Literal::String(span.clone(), type_name(&typ))))
+            }
+        }
+
+        CompilerCall::StaticAssert{ref mut cond, ref message, ref span} => {
+            let message = constant_string_in_context(ctx, message, target)
+                .ok_or_else(|| ErrorCode::StaticAssertionFailed.error(&message.span(), "The message of @static_assert must be a constant string"))?;
+            type_check_with_conversion(ctx, cond, &Type::Bool, target)?;
+            match constant_bool_in_context(ctx, cond, target)
+            {
+                Some(true) => replace_by(Expression::Void),
+                Some(false) => ErrorCode::StaticAssertionFailed.result(span, format!("Static assertion failed: {}", message)),
+                None if has_generic_type_name(cond) => valid(Type::Void),
+                None => ErrorCode::StaticAssertionFailed.result(&cond.span(), "The condition of @static_assert must be a constant bool expression"),
+            }
+        }
+
+        // Checked once the type is known, in generic functions that is after instantiation
+        CompilerCall::AssertType{ref mut expr, ref typ, ref span} => {
+            if !target.is_test_build() {
+                return ErrorCode::TypeAssertionFailed.result(span, format!("@assert_type is only allowed in test builds, compile with --cfg feature={}", TEST_FEATURE));
+            }
+
+            let typ = constant_string_in_context(ctx, typ, target)
+                .ok_or_else(|| ErrorCode::TypeAssertionFailed.error(&typ.span(), "The type of @assert_type must be a constant string"))?;
+
+            let expr_type = type_check_expression(ctx, expr, None, target)?;
+            if expr_type.is_generic() {
+                valid(Type::Void)
+            } else if type_name(&expr_type) == typ {
+                replace_by(Expression::Void)
+            } else {
+                ErrorCode::TypeAssertionFailed.result(span, format!("Type assertion failed: expected type {}, but the expression has type {}", typ, type_name(&expr_type)))
             }
         }
     }
@@ -1275,17 +2646,17 @@ fn type_check_compiler_call(ctx: &mut TypeCheckerContext, cc: &mut CompilerCall,
 fn type_check_literal(ctx: &mut TypeCheckerContext, lit: &mut Literal, type_hint: Option<&Type>, target: &Target) -> TypeCheckResult
 {
     match *lit {
-        Literal::Array(ref mut a) => type_check_array_literal(ctx, a, target),
+        Literal::Array(ref mut a) => type_check_array_literal(ctx, a, type_hint, target),
 
+        // Like an empty array literal, null takes the type it's expected to have
         Literal::NullPtr(ref span, ref mut typ) => {
-            if let Some(&Type::Pointer(ref inner_type)) = type_hint {
-                *typ = inner_type.deref().clone();
-                valid(ptr_type(typ.clone()))
-            } else if *typ != Type::Unknown {
-                type_error_result(span, "Unable to determine type of null expression")
-            } else {
-                valid(ptr_type(typ.clone()))
+            match type_hint {
+                Some(&Type::Pointer(ref inner_type)) => *typ = inner_type.deref().clone(),
+                _ if *typ == Type::Unknown => return ErrorCode::CannotInferType.result(span,
+                    "Cannot infer the type of null, use it where a pointer is expected, for example (null : *int)"),
+                _ => (),
             }
+            valid(ptr_type(typ.clone()))
         }
 
         _ => {
@@ -1307,6 +2678,18 @@ fn type_check_literal(ctx: &mut TypeCheckerContext, lit: &mut Literal, type_hint
 
 
 pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, type_hint: Option<&Type>, target: &Target) -> CompileResult<Type>
+{
+    if !ctx.enter_nested_expression() {
+        ctx.exit_nested_expression();
+        return ErrorCode::LimitExceeded.result(&e.span(), format!("Expression too deeply nested, max {}", MAX_NESTING_DEPTH));
+    }
+
+    let r = type_check_nested_expression(ctx, e, type_hint, target);
+    ctx.exit_nested_expression();
+    r
+}
+
+fn type_check_nested_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, type_hint: Option<&Type>, target: &Target) -> CompileResult<Type>
 {
     let type_check_result = match *e
     {
@@ -1316,16 +2699,20 @@ pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, t
         Expression::Call(ref mut c) => type_check_call(ctx, c, target),
         Expression::NameRef(ref mut nr) => type_check_name(ctx, nr, type_hint),
         Expression::Match(ref mut m) => type_check_match(ctx, m, target),
+        Expression::Is(ref mut i) => type_check_is(ctx, i, target),
         Expression::Lambda(ref mut l) => type_check_lambda(ctx, l, type_hint, target),
         Expression::Bindings(ref mut l) => {
+            let single = l.bindings.len() == 1;
             for b in &mut l.bindings {
-                type_check_binding(ctx, b, target)?;
+                let statement_span = if single {l.span.clone()} else {b.span.clone()};
+                type_check_binding(ctx, b, &statement_span, target)?;
             }
             valid(Type::Void)
         },
         Expression::If(ref mut i) => type_check_if(ctx, i, type_hint, target),
+        Expression::IfLet(ref mut i) => type_check_if_let(ctx, i, type_hint, target),
         Expression::Block(ref mut b) => type_check_block(ctx, b, type_hint, target),
-        Expression::StructInitializer(ref mut si) => type_check_struct_initializer(ctx, si, target),
+        Expression::StructInitializer(ref mut si) => type_check_struct_initializer(ctx, si, type_hint, target),
         Expression::MemberAccess(ref mut sma) => type_check_member_access(ctx, sma, target),
         Expression::New(ref mut n) => type_check_new(ctx, n, type_hint, target),
         Expression::Delete(ref mut d) => type_check_delete(ctx, d, type_hint, target),
@@ -1347,7 +2734,7 @@ pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, t
         Expression::OptionalToBool(ref mut inner) => {
             let inner_type = type_check_expression(ctx, inner, None, target)?;
             if !inner_type.is_optional() {
-                type_error_result(&inner.span(), "Expecting optional type")
+                ErrorCode::TypeMismatch.result(&inner.span(), "Expecting optional type")
             } else {
                 valid(Type::Bool)
             }
@@ -1356,17 +2743,32 @@ pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, t
             type_check_expression(ctx, &mut t.inner, None, target)?;
             valid(t.optional_type.clone())
         },
+        Expression::ToInterface(ref mut t) => {
+            type_check_expression(ctx, &mut t.inner, None, target)?;
+            resolve_interface_methods(ctx, t, target.int_size)?;
+            valid(t.interface_type.clone())
+        },
         Expression::Cast(ref mut t) => type_check_cast(ctx, t, target),
+        Expression::TypeAscription(ref mut t) => type_check_type_ascription(ctx, t, target),
         Expression::CompilerCall(ref mut cc) => type_check_compiler_call(ctx, cc, type_hint, target),
         Expression::IndexOperation(ref mut iop) => valid(type_check_index_operation(ctx, iop, target)?),
         Expression::Return(ref mut r) => {
-            if let Some(return_type) = ctx.get_function_return_type() {
-                type_check_with_conversion(ctx, &mut r.expression, &return_type, target)?;
-                valid(Type::Void)
-            } else {
-                type_error_result(&r.span, "return expression outside of a function")
+            match ctx.get_function_return_type()
+            {
+                Some(Type::Unknown) => ErrorCode::CannotInferType.result(&r.span, "Cannot return from a lambda with an unknown return type, pass the lambda where its type is known"),
+                Some(return_type) => {
+                    let typ = type_check_expression(ctx, &mut r.expression, Some(&return_type), target)?;
+                    convert_type(ctx, &return_type, &typ, &mut r.expression, target)?;
+                    valid(Type::Void)
+                },
+                None => type_error_result(&r.span, "return expression outside of a function"),
             }
         },
+        Expression::Propagate(ref mut p) => type_check_propagate(ctx, p, target),
+        Expression::Discard(ref mut d) => {
+            type_check_expression(ctx, &mut d.inner, None, target)?;
+            valid(Type::Void)
+        },
     };
 
     match type_check_result
@@ -1380,33 +2782,106 @@ pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, t
     }
 }
 
-pub fn type_check_module(module: &mut Module, target: &Target, imports: &ImportMap) -> CompileResult<()>
+fn type_check_global(ctx: &mut TypeCheckerContext, global: &mut GlobalBinding, target: &Target) -> CompileResult<()>
 {
-    loop {
-        let mut ctx = TypeCheckerContext::new(ImportSymbolResolver::ImportMap(imports));
-        resolve_types(&mut ctx, module, target)?;
+    ctx.start_naming_lambdas(&global.name);
+    if global.typ == Type::Unknown {
+        global.typ = type_check_expression(ctx, &mut global.init, None, target)?;
+        if global.typ == Type::Void {
+            let name = global.name.rsplit("::").next().unwrap_or(&global.name);
+            return ErrorCode::NoValue.result(&global.span, format!("Expression has no value, it cannot be bound to {}", name));
+        }
+    } else {
+        let init_type = type_check_expression(ctx, &mut global.init, Some(&global.typ), target)?;
+        convert_type(ctx, &global.typ, &init_type, &mut global.init, target)?;
+    }
 
-        for global in module.globals.values_mut() {
-            if global.typ == Type::Unknown {
-                global.typ = type_check_expression(&mut ctx, &mut global.init, None, target)?;
-                ctx.add(Symbol::new(&global.name, &global.typ, global.mutable, &global.span, SymbolType::Global))?;
-            }
+    ctx.add(Symbol::new(&global.name, &global.typ, global.mutable, &global.span, SymbolType::Global))?;
+    if !global.mutable {
+        if let Expression::Literal(Literal::String(_, ref value)) = global.init {
+            ctx.add_constant(&global.name, value);
         }
+    }
+    global.type_checked = true;
+    Ok(())
+}
 
-        for f in module.functions.values_mut() {
-            if !f.type_checked {
-                type_check_function(&mut ctx, f, target)?;
+// Initializers can refer to other globals, so keep going as long as one of them can be type checked
+fn type_check_globals(ctx: &mut TypeCheckerContext, module: &mut Module, target: &Target) -> CompileResult<()>
+{
+    loop {
+        let mut progress = false;
+        let mut first_error = None;
+        for global in module.globals.values_mut().filter(|g| !g.type_checked) {
+            match type_check_global(ctx, global, target)
+            {
+                Ok(()) => progress = true,
+                Err(e) => if first_error.is_none() {
+                    first_error = Some(e);
+                },
             }
         }
 
+        match first_error
+        {
+            None => return Ok(()),
+            Some(e) => if !progress {
+                return Err(e);
+            },
+        }
+    }
+}
+
+pub fn type_check_module(module: &mut Module, target: &Target, imports: &ImportMap, timings: &mut PassTimings) -> CompileResult<()>
+{
+    // The context is kept across iterations, so already resolved types and functions are not processed again
+    let mut ctx = TypeCheckerContext::new(ImportSymbolResolver::ImportMap(imports));
+    let mut iteration = 0;
+    loop {
+        iteration += 1;
+        let resolve_pass = format!("Type resolution (iteration {})", iteration);
+        let work = timings.time(&resolve_pass, || resolve_types(&mut ctx, module, target))?;
+        timings.add_work(&resolve_pass, work);
+
+        timings.time(&format!("Type checking (iteration {})", iteration), || -> CompileResult<()> {
+            type_check_globals(&mut ctx, module, target)?;
+
+            // Keep going after an error, so all functions with errors get reported
+            let mut functions: Vec<&mut Function> = module.functions.values_mut().filter(|f| !f.type_checked).collect();
+            functions.sort_by(|a, b| a.sig.name.cmp(&b.sig.name));
+            let mut errors = Vec::new();
+            for f in functions {
+                if let Err(e) = type_check_function(&mut ctx, f, target) {
+                    ctx.leave_all_scopes();
+                    errors.push(e);
+                }
+            }
+
+            match errors.len() {
+                0 => Ok(()),
+                1 => Err(errors.remove(0)),
+                _ => Err(CompileError::Many(errors)),
+            }
+        })?;
+
         let count = module.functions.len();
-        instantiate_generics(module, &mut ctx, imports, target)?;
+        let instantiation_pass = format!("Generic instantiation (iteration {})", iteration);
+        timings.time(&instantiation_pass, || {
+            instantiate_generics(module, &mut ctx, imports, target)
+        })?;
+        timings.add_work(&instantiation_pass, module.functions.len() - count);
+
         // As long as we are adding new generic functions, we need to type check the module again
         if count == module.functions.len() {
             break;
         }
     }
 
+    check_object_sizes(module, target)?;
+    check_external_functions(iter::once(&*module))?;
+    check_exported_functions(iter::once(&*module))?;
+    warn_about_float_equality(module, target);
+    warn_about_unreachable_code(module);
     module.type_checked = true;
     Ok(())
 }
\ No newline at end of file
@@ -2,10 +2,36 @@ use std::collections::hash_map::{HashMap, Entry};
 use ast::*;
 use compileerror::*;
 
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum NameKind
+{
+    Value,  // Variables, functions and sum type cases
+    Type,   // Structs, sum types, interfaces and sum type cases
+}
+
+fn is_name_of_kind(symbol: &Symbol, kind: NameKind) -> bool
+{
+    let (is_type, is_case) = match symbol.typ
+    {
+        Type::Struct(ref st) => (st.name == symbol.name, false),
+        Type::Interface(ref it) => (it.name == symbol.name, false),
+        Type::Sum(ref st) => (st.name == symbol.name, st.index_of(&symbol.name).is_some()),
+        Type::Enum(ref et) => (et.name == symbol.name, et.index_of(&symbol.name).is_some()),
+        _ => (false, false),
+    };
+
+    match kind
+    {
+        NameKind::Value => is_case || !is_type,
+        NameKind::Type => is_case || is_type,
+    }
+}
+
 struct Scope
 {
     symbols: HashMap<String, Symbol>,
     function_return_type: Option<Type>,
+    lambda_return_type: Option<Type>, // Lambdas see the names of the function they are in, but return from themselves
 }
 
 
@@ -16,6 +42,7 @@ impl Scope
         Scope {
             symbols: HashMap::new(),
             function_return_type,
+            lambda_return_type: None,
         }
     }
 
@@ -36,6 +63,11 @@ impl Scope
         None
     }
 
+    fn names(&self, kind: NameKind) -> impl Iterator<Item=&str>
+    {
+        self.symbols.values().filter(move |s| is_name_of_kind(s, kind)).map(|s| &s.name[..])
+    }
+
     fn add(&mut self, symbol: Symbol) -> CompileResult<()>
     {
         match self.symbols.entry(symbol.name.clone()) {
@@ -82,6 +114,20 @@ impl<'a> ImportSymbolResolver<'a>
             }
         }
     }
+
+    fn names(&self, kind: NameKind) -> Vec<&str>
+    {
+        let imports: Vec<&Import> = match *self {
+            ImportSymbolResolver::ImportMap(imports) => imports.values().map(|i| &**i).collect(),
+            ImportSymbolResolver::ExternalImport(import) => vec![import],
+        };
+
+        imports.into_iter()
+            .flat_map(|i| i.symbols.values())
+            .filter(|s| is_name_of_kind(s, kind))
+            .map(|s| &s.name[..])
+            .collect()
+    }
 }
 
 pub struct TypeCheckerContext<'a>
@@ -90,6 +136,15 @@ pub struct TypeCheckerContext<'a>
     globals: Scope,
     externals: Scope,
     import_resolver: ImportSymbolResolver<'a>,
+    nesting_depth: usize,
+    generic_params: HashMap<String, Vec<Type>>,
+    // Lambdas and local functions are named after the function or global they are in, and their position in it
+    enclosing_name: String,
+    lambda_counter: usize,
+    // Values of immutable globals initialized with a constant string, so they can be folded into expressions using them
+    constants: HashMap<String, String>,
+    // Types declared in the module, which hide imported types with the same name, also before they are resolved
+    module_types: Vec<String>,
 }
 
 impl<'a> TypeCheckerContext<'a>
@@ -100,10 +155,43 @@ impl<'a> TypeCheckerContext<'a>
             stack: Vec::new(),
             globals: Scope::new(None),
             externals: Scope::new(None),
-            import_resolver: isr
+            import_resolver: isr,
+            nesting_depth: 0,
+            generic_params: HashMap::new(),
+            enclosing_name: String::new(),
+            lambda_counter: 0,
+            constants: HashMap::new(),
+            module_types: Vec::new(),
         }
     }
 
+    // Must be called before each type check of a function body or global initializer,
+    // so checking the same code again gives its lambdas the same names
+    pub fn start_naming_lambdas(&mut self, enclosing_name: &str)
+    {
+        self.enclosing_name = enclosing_name.into();
+        self.lambda_counter = 0;
+    }
+
+    pub fn next_lambda_name(&mut self, name: &str) -> String
+    {
+        let lambda_name = nested_function_name(&self.enclosing_name, name, self.lambda_counter);
+        self.lambda_counter += 1;
+        lambda_name
+    }
+
+    // Returns false if the maximum nesting depth is exceeded
+    pub fn enter_nested_expression(&mut self) -> bool
+    {
+        self.nesting_depth += 1;
+        self.nesting_depth <= MAX_NESTING_DEPTH
+    }
+
+    pub fn exit_nested_expression(&mut self)
+    {
+        self.nesting_depth -= 1;
+    }
+
     pub fn update(&mut self, symbol: Symbol)
     {
         self.stack.last_mut().expect("Empty stack").update(symbol)
@@ -114,11 +202,25 @@ impl<'a> TypeCheckerContext<'a>
         self.stack.push(Scope::new(function_return_type));
     }
 
+    pub fn enter_lambda_scope(&mut self, return_type: Type)
+    {
+        let mut scope = Scope::new(None);
+        scope.lambda_return_type = Some(return_type);
+        self.stack.push(scope);
+    }
+
     pub fn exit_scope(&mut self)
     {
         self.stack.pop();
     }
 
+    // After an error in a function, the scopes it entered are still there, so drop them before checking the next one
+    pub fn leave_all_scopes(&mut self)
+    {
+        self.stack.clear();
+        self.nesting_depth = 0;
+    }
+
     pub fn resolve(&self, name: &str) -> Option<Symbol>
     {
         for sf in self.stack.iter().rev() {
@@ -142,9 +244,51 @@ impl<'a> TypeCheckerContext<'a>
         self.import_resolver.resolve(name)
     }
 
+    // All names which resolve can find from the current scope
+    pub fn visible_names(&self, kind: NameKind) -> Vec<&str>
+    {
+        let mut names = Vec::new();
+        for sf in self.stack.iter().rev() {
+            names.extend(sf.names(kind));
+            if sf.function_return_type.is_some() {
+                break;
+            }
+        }
+
+        names.extend(self.globals.names(kind));
+        names.extend(self.externals.names(kind));
+        names.extend(self.import_resolver.names(kind));
+        if kind == NameKind::Value {
+            names.extend(BUILTINS.iter().map(|b| b.name()));
+        }
+        names
+    }
+
+    // Whether a symbol with exactly this name was added at module level
+    pub fn is_module_symbol(&self, name: &str) -> bool
+    {
+        self.globals.symbols.contains_key(name)
+    }
+
+    pub fn set_module_types(&mut self, names: Vec<String>)
+    {
+        self.module_types = names;
+    }
+
+    // Whether the name is one of a type declared in the module, which hasn't been resolved yet
+    pub fn is_unresolved_module_type(&self, name: &str) -> bool
+    {
+        let name_with_double_colons = format!("::{}", name);
+        self.module_types.iter()
+            .any(|t| (t == name || t.ends_with(&name_with_double_colons)) && !self.globals.symbols.contains_key(t))
+    }
+
     pub fn add(&mut self, symbol: Symbol) -> CompileResult<()>
     {
         match symbol.symbol_type {
+            // Binding to _ discards the value, so there is nothing to add
+            SymbolType::Normal if symbol.name == "_" => Ok(()),
+
             SymbolType::Normal => {
                 if let Some(ref mut sf) = self.stack.last_mut() {
                     sf.add(symbol)
@@ -163,11 +307,36 @@ impl<'a> TypeCheckerContext<'a>
         }
     }
 
+    // Types declared with explicit generic parameters: struct Foo<T, U>
+    pub fn add_generic_params(&mut self, type_name: &str, params: Vec<Type>)
+    {
+        self.generic_params.insert(type_name.into(), params);
+    }
+
+    pub fn get_generic_params(&self, type_name: &str) -> Option<&Vec<Type>>
+    {
+        self.generic_params.get(type_name)
+    }
+
+    pub fn add_constant(&mut self, name: &str, value: &str)
+    {
+        self.constants.insert(name.into(), value.into());
+    }
+
+    // Constants are stored under the full name of the global, a local binding with the same name hides it
+    pub fn get_constant(&self, name: &str) -> Option<String>
+    {
+        match self.resolve(name) {
+            Some(Symbol{symbol_type: SymbolType::Global, name: ref full_name, ..}) => self.constants.get(full_name).cloned(),
+            _ => None,
+        }
+    }
+
     pub fn get_function_return_type(&self) -> Option<Type>
     {
         for sf in self.stack.iter().rev() {
-            if sf.function_return_type.is_some() {
-                return sf.function_return_type.clone();
+            if let Some(typ) = sf.lambda_return_type.as_ref().or(sf.function_return_type.as_ref()) {
+                return Some(typ.clone());
             }
         }
 
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fmt;
+use ast::{Block, Expression, Module, Pattern, Type, Visitor, walk_expression, walk_pattern};
+use compileerror::CompileResult;
+use span::{Span, Pos};
+use target::Target;
+
+// The type of the expression at a span, when the compiler converted the expression, conversion is the type it was converted to
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TypedSpan
+{
+    pub span: Span,
+    pub typ: Type,
+    pub conversion: Option<Type>,
+}
+
+impl TypedSpan
+{
+    pub fn is_synthetic(&self) -> bool
+    {
+        self.conversion.is_some()
+    }
+
+    fn contains(&self, pos: Pos) -> bool
+    {
+        self.span.start <= pos && pos <= self.span.end
+    }
+}
+
+impl fmt::Display for TypedSpan
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
+    {
+        match self.conversion
+        {
+            Some(ref to) => write!(f, "{}: {} (converted to {})", self.span, self.typ, to),
+            None => write!(f, "{}: {}", self.span, self.typ),
+        }
+    }
+}
+
+// Maps source positions to the types of the expressions at those positions, per file the spans are sorted on their start
+#[derive(Debug, Default)]
+pub struct TypeMap
+{
+    files: HashMap<String, Vec<TypedSpan>>,
+}
+
+// Converting to void, wraps the expression in a block which ends with a void expression
+fn is_void_conversion(b: &Block) -> bool
+{
+    b.typ == Type::Void && b.expressions.len() == 2 && b.expressions[1] == Expression::Void && b.expressions[0].span() == b.span
+}
+
+// The expression a compiler inserted conversion wraps, conversions keep the span of the original expression
+fn converted_expression(e: &Expression) -> Option<&Expression>
+{
+    match *e
+    {
+        Expression::ArrayToSlice(ref ats) => Some(&ats.inner),
+        Expression::ToOptional(ref t) => Some(&t.inner),
+        Expression::ToInterface(ref t) => Some(&t.inner),
+        Expression::OptionalToBool(ref inner) => Some(inner),
+        Expression::Cast(ref c) if c.span == c.inner.span() => Some(&c.inner),
+        Expression::Block(ref b) if is_void_conversion(b) => Some(&b.expressions[0]),
+        _ => None,
+    }
+}
+
+struct TypeCollector<'a>
+{
+    target: &'a Target,
+    spans: Vec<TypedSpan>,
+}
+
+impl<'a> TypeCollector<'a>
+{
+    fn add(&mut self, span: Span, typ: Type, conversion: Option<Type>)
+    {
+        if typ != Type::Unknown && !typ.is_generic() {
+            self.spans.push(TypedSpan{span, typ, conversion});
+        }
+    }
+}
+
+impl<'a> Visitor for TypeCollector<'a>
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
+        if *e == Expression::Void {
+            return Ok(());
+        }
+
+        // A hover shows what was written, so a conversion is recorded as the type of the original expression
+        let mut inner = e;
+        let mut conversion = None;
+        while let Some(converted) = converted_expression(inner) {
+            conversion = Some(e.get_type(self.target.int_size));
+            inner = converted;
+        }
+
+        self.add(inner.span(), inner.get_type(self.target.int_size), conversion);
+        walk_expression(self, inner)
+    }
+
+    fn visit_pattern(&mut self, p: &Pattern) -> CompileResult<()>
+    {
+        match *p
+        {
+            Pattern::Literal(ref lit) => self.add(lit.span(), lit.get_type(), None),
+            Pattern::Name(ref nr) => self.add(nr.span.clone(), nr.typ.clone(), None),
+            Pattern::Binding(ref b) => self.add(b.span.clone(), b.typ.clone(), None),
+            Pattern::Struct(ref s) => self.add(s.span.clone(), s.typ.clone(), None),
+            _ => (),
+        }
+        walk_pattern(self, p)
+    }
+}
+
+impl TypeMap
+{
+    pub fn from_module(module: &Module, target: &Target) -> TypeMap
+    {
+        let mut collector = TypeCollector{target, spans: Vec::new()};
+        for glob in module.globals.values() {
+            let _ = collector.visit_expression(&glob.init);
+        }
+
+        // Generic functions are only type checked through their instances
+        for f in module.functions.values().filter(|f| !f.is_generic()) {
+            let _ = collector.visit_expression(&f.expression);
+        }
+
+        let mut tm = TypeMap::default();
+        for ts in collector.spans {
+            tm.files.entry(ts.span.file.clone()).or_insert_with(Vec::new).push(ts);
+        }
+
+        for spans in tm.files.values_mut() {
+            // Stable, so of two expressions with the same span, the one visited last (the nested one) stays last
+            spans.sort_by_key(|ts| ts.span.start);
+        }
+        tm
+    }
+
+    // The innermost expression containing a position, lines and columns start at 1
+    pub fn entry_at(&self, file: &str, line: usize, col: usize) -> Option<&TypedSpan>
+    {
+        let pos = Pos::new(line, col);
+        let spans = self.files.get(file)?;
+        let candidates = spans.iter().take_while(|ts| ts.span.start <= pos).filter(|ts| ts.contains(pos));
+        let mut innermost: Option<&TypedSpan> = None;
+        for ts in candidates {
+            innermost = match innermost
+            {
+                Some(cur) if cur.span.start == ts.span.start && cur.span.end < ts.span.end => Some(cur),
+                _ => Some(ts),
+            };
+        }
+        innermost
+    }
+
+    pub fn type_at(&self, file: &str, line: usize, col: usize) -> Option<(Span, Type)>
+    {
+        self.entry_at(file, line, col).map(|ts| (ts.span.clone(), ts.typ.clone()))
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item=&TypedSpan>
+    {
+        self.files.values().flat_map(|spans| spans.iter())
+    }
+}
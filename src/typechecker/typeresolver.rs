@@ -1,9 +1,17 @@
 use std::collections::HashSet;
+use std::iter;
 use std::ops::Deref;
+use std::rc::Rc;
 use ast::*;
 use target::Target;
-use compileerror::{CompileResult, unknown_name_result};
+use layout::{CPassing, layout_of, is_x86_64_sysv, c_calling_convention};
+use compileerror::{CompileResult, type_error_result};
+use errorcodes::ErrorCode;
+use span::Span;
 use super::typecheckercontext::TypeCheckerContext;
+use super::instantiate::make_concrete;
+use super::constantfolding::{constant_value, array_length_in_context};
+use super::compositioncycles::check_composition_cycles;
 
 #[derive(Eq, PartialEq, Debug)]
 pub enum TypeResolved
@@ -19,20 +27,83 @@ enum ResolveMode
     Forced,
 }
 
-fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type) -> (Option<Type>, TypeResolved)
+// All generic types in typ, in the order they are first encountered
+pub fn collect_generic_params(typ: &Type, params: &mut Vec<Type>)
+{
+    match *typ
+    {
+        Type::Generic(_) if !params.contains(typ) => params.push(typ.clone()),
+        Type::Array(ref at) => collect_generic_params(&at.element_type, params),
+        Type::Slice(ref st) => collect_generic_params(&st.element_type, params),
+        Type::Pointer(ref inner) |
+        Type::Optional(ref inner) => collect_generic_params(inner, params),
+        Type::Func(ref ft) => {
+            for a in &ft.args {
+                collect_generic_params(a, params);
+            }
+            collect_generic_params(&ft.return_type, params);
+        },
+        Type::Struct(ref st) => {
+            for m in &st.members {
+                collect_generic_params(&m.typ, params);
+            }
+        },
+        Type::Sum(ref st) => {
+            for c in &st.cases {
+                collect_generic_params(&c.typ, params);
+            }
+        },
+        _ => (),
+    }
+}
+
+fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type, target: &Target) -> (Option<Type>, TypeResolved)
 {
     match *typ
     {
         Type::Unresolved(ref ut) => {
+            // Wait until it is resolved, instead of taking an imported type with the same name
+            if ctx.is_unresolved_module_type(&ut.name) {
+                return (None, TypeResolved::No);
+            }
+
             if let Some(r) = ctx.resolve(&ut.name) {
-                (Some(r.typ.clone()), TypeResolved::Yes)
+                if ut.generic_args.is_empty() || !r.typ.is_generic() {
+                    return (Some(r.typ.clone()), TypeResolved::Yes);
+                }
+
+                // Foo<int, string>, the generic arguments are mapped on the generic parameters of Foo,
+                // or when they are not declared, on the generic types in the order they appear in Foo
+                let params = match ctx.get_generic_params(&r.name) {
+                    Some(declared) if declared.len() != ut.generic_args.len() => return (None, TypeResolved::No),
+                    Some(declared) => declared.clone(),
+                    None => {
+                        let mut params = Vec::new();
+                        collect_generic_params(&r.typ, &mut params);
+                        params
+                    },
+                };
+
+                let mut mapping = GenericMapping::new();
+                for (param, arg) in params.into_iter().zip(ut.generic_args.iter()) {
+                    match resolve_type_helper(ctx, arg, target) {
+                        (Some(typ), TypeResolved::Yes) => {mapping.insert(param, typ);},
+                        (None, TypeResolved::Yes) => {mapping.insert(param, arg.clone());},
+                        _ => return (None, TypeResolved::No),
+                    }
+                }
+
+                match make_concrete(ctx, &mapping, &r.typ, &Span::default()) {
+                    Ok(typ) => (Some(typ), TypeResolved::Yes),
+                    Err(_) => (None, TypeResolved::No),
+                }
             } else {
                 (None, TypeResolved::No)
             }
         },
 
         Type::Pointer(ref inner) => {
-            let r = resolve_type_helper(ctx, inner);
+            let r = resolve_type_helper(ctx, inner, target);
             if let (Some(typ), TypeResolved::Yes) = r {
                 (Some(ptr_type(typ)), TypeResolved::Yes)
             } else {
@@ -40,6 +111,82 @@ fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type) -> (Option<Type>, T
             }
         },
 
+        Type::Optional(ref inner) => {
+            let r = resolve_type_helper(ctx, inner, target);
+            if let (Some(typ), TypeResolved::Yes) = r {
+                (Some(optional_type(typ)), TypeResolved::Yes)
+            } else {
+                r
+            }
+        },
+
+        Type::Slice(ref st) => {
+            let r = resolve_type_helper(ctx, &st.element_type, target);
+            if let (Some(typ), TypeResolved::Yes) = r {
+                (Some(slice_type(typ)), TypeResolved::Yes)
+            } else {
+                r
+            }
+        },
+
+        Type::Array(ref at) => {
+            let element_type = match resolve_type_helper(ctx, &at.element_type, target) {
+                (Some(typ), TypeResolved::Yes) => typ,
+                (None, TypeResolved::Yes) => at.element_type.clone(),
+                _ => return (None, TypeResolved::No),
+            };
+
+            let len = match at.len_of {
+                Some(ArrayLength::SizeOf(ref t)) |
+                Some(ArrayLength::AlignOf(ref t)) => {
+                    let t = match resolve_type_helper(ctx, t, target) {
+                        (Some(typ), TypeResolved::Yes) => typ,
+                        (None, TypeResolved::Yes) => t.clone(),
+                        _ => return (None, TypeResolved::No),
+                    };
+
+                    // The layout of generic types is only known after instantiation
+                    if t.is_generic() {
+                        return (None, TypeResolved::No);
+                    }
+
+                    let layout = layout_of(&t, target);
+                    if let Some(ArrayLength::SizeOf(_)) = at.len_of {layout.size} else {layout.alignment}
+                },
+                Some(ArrayLength::Constant(ref e)) => match array_length_in_context(ctx, e, target) {
+                    Some(len) => len,
+                    None => return (None, TypeResolved::No),
+                },
+                None => at.len,
+            };
+
+            (Some(array_type(element_type, len)), TypeResolved::Yes)
+        },
+
+        Type::Func(ref ft) => {
+            let resolve_or_keep = |t: &Type| {
+                match resolve_type_helper(ctx, t, target)
+                {
+                    (Some(typ), TypeResolved::Yes) => Some(typ),
+                    (None, TypeResolved::Yes) => Some(t.clone()),
+                    _ => None,
+                }
+            };
+
+            let mut args = Vec::with_capacity(ft.args.len());
+            for arg in &ft.args {
+                match resolve_or_keep(arg) {
+                    Some(typ) => args.push(typ),
+                    None => return (None, TypeResolved::No),
+                }
+            }
+
+            match resolve_or_keep(&ft.return_type) {
+                Some(ret) => (Some(func_type(args, ret)), TypeResolved::Yes),
+                None => (None, TypeResolved::No),
+            }
+        },
+
         Type::Generic(ref gt) => {
             match *gt.deref()
             {
@@ -56,7 +203,7 @@ fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type) -> (Option<Type>, T
                 GenericType::Restricted(ref interfaces) => {
                     let mut new_interfaces = Vec::new();
                     for interface in interfaces {
-                        let r = resolve_type_helper(ctx, interface);
+                        let r = resolve_type_helper(ctx, interface, target);
                         if let (Some(typ), TypeResolved::Yes) = r {
                             new_interfaces.push(typ);
                         } else {
@@ -73,9 +220,56 @@ fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type) -> (Option<Type>, T
     }
 }
 
-pub fn resolve_type(ctx: &TypeCheckerContext, typ: &mut Type) -> TypeResolved
+// Gives a better error than an unknown type, when a type gets the wrong number of generic arguments
+pub fn check_generic_arg_count(ctx: &TypeCheckerContext, typ: &Type, span: &Span) -> CompileResult<()>
 {
-    match resolve_type_helper(ctx, typ)
+    match *typ
+    {
+        Type::Unresolved(ref ut) => {
+            for arg in &ut.generic_args {
+                check_generic_arg_count(ctx, arg, span)?;
+            }
+
+            if ut.generic_args.is_empty() {
+                return Ok(());
+            }
+
+            match ctx.resolve(&ut.name).and_then(|r| ctx.get_generic_params(&r.name)) {
+                Some(params) if params.len() != ut.generic_args.len() => type_error_result(
+                    span,
+                    format!("Type {} expects {} generic arguments, but {} were given", ut.name, params.len(), ut.generic_args.len())),
+                _ => Ok(()),
+            }
+        },
+
+        Type::Pointer(ref inner) |
+        Type::Optional(ref inner) => check_generic_arg_count(ctx, inner, span),
+        Type::Array(ref at) => check_generic_arg_count(ctx, &at.element_type, span),
+        Type::Slice(ref st) => check_generic_arg_count(ctx, &st.element_type, span),
+        Type::Func(ref ft) => {
+            for arg in &ft.args {
+                check_generic_arg_count(ctx, arg, span)?;
+            }
+            check_generic_arg_count(ctx, &ft.return_type, span)
+        },
+        _ => Ok(()),
+    }
+}
+
+// A generic parameter which is not used by any member, can never be inferred
+fn check_generic_params_are_used(name: &str, params: &[Type], typ: &Type, span: &Span) -> CompileResult<()>
+{
+    let mut used = Vec::new();
+    collect_generic_params(typ, &mut used);
+    match params.iter().find(|p| !used.contains(p)) {
+        Some(unused) => type_error_result(span, format!("Generic parameter {} of {} is not used by any of its members", unused, name)),
+        None => Ok(()),
+    }
+}
+
+pub fn resolve_type(ctx: &TypeCheckerContext, typ: &mut Type, target: &Target) -> TypeResolved
+{
+    match resolve_type_helper(ctx, typ, target)
     {
         (Some(resolved_typ), TypeResolved::Yes) => {
             *typ = resolved_typ;
@@ -85,34 +279,77 @@ pub fn resolve_type(ctx: &TypeCheckerContext, typ: &mut Type) -> TypeResolved
     }
 }
 
-fn resolve_function_args_and_ret_type(ctx: &mut TypeCheckerContext, sig: &mut FunctionSignature, mode: ResolveMode) -> CompileResult<TypeResolved>
+pub const VOID_HAS_NO_VALUES: &str = "void has no values, it can only be used as a return type or behind a pointer";
+
+// Arguments cannot be void, and the return type can only be void as a whole
+pub fn check_signature_void_types(sig: &FunctionSignature) -> CompileResult<()>
+{
+    if let Some(arg) = sig.args.iter().find(|a| a.typ.contains_void_value()) {
+        return ErrorCode::NoValue.result(&arg.span, format!("Argument {} of {} has type {}, but {}", arg.name, sig.name, arg.typ, VOID_HAS_NO_VALUES));
+    }
+
+    if sig.return_type != Type::Void && sig.return_type.contains_void_value() {
+        return ErrorCode::NoValue.result(&sig.span, format!("{} returns {}, but {}", sig.name, sig.return_type, VOID_HAS_NO_VALUES));
+    }
+
+    Ok(())
+}
+
+// Default values must be constants of the type of their argument, they are replaced by the folded constant,
+// so every call which leaves out the argument gets the same value
+fn resolve_default_values(sig: &mut FunctionSignature, target: &Target) -> CompileResult<()>
+{
+    let name = sig.name.clone();
+    for arg in &mut sig.args {
+        let lit = match arg.default {
+            Some(ref default) if arg.typ.is_generic() =>
+                return ErrorCode::TypeMismatch.result(&default.span(),
+                    format!("Argument {} of {} has generic type {}, so it cannot have a default value", arg.name, name, arg.typ)),
+            Some(ref default) => constant_value(default, target)
+                .ok_or_else(|| ErrorCode::TypeMismatch.error(&default.span(), format!("Default value of argument {} of {} must be a constant expression", arg.name, name)))?,
+            None => continue,
+        };
+
+        let lit = if lit.get_type() == arg.typ {
+            lit
+        } else {
+            lit.try_convert(&arg.typ).ok_or_else(|| ErrorCode::TypeMismatch.error(&lit.span(),
+                format!("Default value of argument {} of {} has type {}, but the argument has type {}", arg.name, name, lit.get_type(), arg.typ)))?
+        };
+        arg.default = Some(Expression::Literal(lit));
+    }
+    Ok(())
+}
+
+fn resolve_function_args_and_ret_type(ctx: &mut TypeCheckerContext, sig: &mut FunctionSignature, mode: ResolveMode, target: &Target) -> CompileResult<TypeResolved>
 {
     if sig.typ != Type::Unknown {
         return Ok(TypeResolved::Yes);
     }
 
-    if resolve_type(ctx, &mut sig.return_type) == TypeResolved::No {
-        return unknown_name_result(&sig.span, format!("Unknown function return type {}", sig.return_type));
+    if resolve_type(ctx, &mut sig.return_type, target) == TypeResolved::No {
+        check_generic_arg_count(ctx, &sig.return_type, &sig.span)?;
+        return ErrorCode::UnknownName.result(&sig.span, format!("Unknown function return type {}", sig.return_type));
     }
 
-    let mut args = Vec::with_capacity(sig.args.len());
     for ref mut arg in &mut sig.args {
-        if resolve_type(ctx, &mut arg.typ) == TypeResolved::No {
+        if resolve_type(ctx, &mut arg.typ, target) == TypeResolved::No {
+            check_generic_arg_count(ctx, &arg.typ, &arg.span)?;
             if mode == ResolveMode::Lazy {
                 return Ok(TypeResolved::No);
             } else {
-                return unknown_name_result(&arg.span, format!("Unknown function argument type {}", arg.typ));
+                return ErrorCode::UnknownName.result(&arg.span, format!("Unknown function argument type {}", arg.typ));
             }
         }
-
-        args.push(arg.typ.clone());
     }
 
-    sig.typ = func_type(args, sig.return_type.clone());
+    check_signature_void_types(sig)?;
+    resolve_default_values(sig, target)?;
+    sig.typ = sig.get_type();
     Ok(TypeResolved::Yes)
 }
 
-fn resolve_struct_member_types(ctx: &mut TypeCheckerContext, sd: &mut StructDeclaration, mode: ResolveMode) -> CompileResult<TypeResolved>
+fn resolve_struct_member_types(ctx: &mut TypeCheckerContext, sd: &mut StructDeclaration, mode: ResolveMode, target: &Target) -> CompileResult<TypeResolved>
 {
     if sd.typ != Type::Unknown {
         return Ok(TypeResolved::Yes);
@@ -121,21 +358,99 @@ fn resolve_struct_member_types(ctx: &mut TypeCheckerContext, sd: &mut StructDecl
     let mut member_types = Vec::with_capacity(sd.members.len());
     for m in &mut sd.members
     {
-        if resolve_type(ctx, &mut m.typ) == TypeResolved::No {
+        if resolve_type(ctx, &mut m.typ, target) == TypeResolved::No {
+            check_generic_arg_count(ctx, &m.typ, &m.span)?;
             if mode == ResolveMode::Lazy {
                 return Ok(TypeResolved::No);
             } else {
-                return unknown_name_result(&m.span, format!("Unknown struct member type {}", m.typ));
+                return ErrorCode::UnknownName.result(&m.span, format!("Unknown struct member type {}", m.typ));
             }
         }
 
+        if m.typ.contains_void_value() {
+            return ErrorCode::NoValue.result(&m.span, format!("Member {} of {} has type {}, but {}", m.name, sd.name, m.typ, VOID_HAS_NO_VALUES));
+        }
+
         member_types.push(struct_member(&m.name, m.typ.clone()));
     }
 
-    sd.typ = struct_type(&sd.name, member_types);
+    if sd.repr_c {
+        if let Some(m) = sd.members.iter().find(|m| !m.typ.is_c_representable()) {
+            return ErrorCode::InvalidCInterface.result(&m.span,
+                format!("Member {} of repr(c) struct {} has type {}, which has no C representation, \
+                    only integers, floats, chars, bools, pointers, arrays and other repr(c) structs are allowed", m.name, sd.name, m.typ));
+        }
+    }
+
+    sd.typ = Type::Struct(Rc::new(StructType{
+        name: sd.name.clone(),
+        members: member_types,
+        repr_c: sd.repr_c,
+    }));
     Ok(TypeResolved::Yes)
 }
 
+// The struct an external function argument or return value refers to, by value or through pointers
+fn referenced_struct(typ: &Type) -> Option<&StructType>
+{
+    match *typ {
+        Type::Struct(ref st) => Some(st),
+        Type::Pointer(ref inner) => referenced_struct(inner),
+        _ => None,
+    }
+}
+
+fn no_c_layout_error(st: &StructType) -> String
+{
+    if st.name.is_empty() {
+        "a tuple, which has no C layout, use a repr(c) struct instead".into()
+    } else {
+        format!("struct {}, which has no C layout, add #[repr(c)] to its declaration", st.name)
+    }
+}
+
+// Structs passed to C must have a C layout, and those passed by value must follow the C calling convention
+fn check_external_function_types(sig: &FunctionSignature, target: &Target) -> CompileResult<()>
+{
+    for arg in &sig.args {
+        match referenced_struct(&arg.typ) {
+            Some(st) if !st.repr_c => return ErrorCode::InvalidCInterface.result(&arg.span,
+                format!("Argument {} of external function {} uses {}", arg.name, sig.name, no_c_layout_error(st))),
+            _ => (),
+        }
+    }
+
+    match referenced_struct(&sig.return_type) {
+        Some(st) if !st.repr_c => return ErrorCode::InvalidCInterface.result(&sig.span,
+            format!("External function {} returns {}", sig.name, no_c_layout_error(st))),
+        _ => (),
+    }
+
+    let is_struct = |t: &Type| if let Type::Struct(_) = *t {true} else {false};
+    let by_value = sig.args.iter().map(|a| &a.typ).chain(iter::once(&sig.return_type)).any(|t| is_struct(t));
+    if !by_value {
+        return Ok(());
+    }
+
+    if !is_x86_64_sysv(target) {
+        return ErrorCode::InvalidCInterface.result(&sig.span,
+            format!("External function {} passes a struct by value, which is only supported on x86-64 System V targets, pass a pointer instead", sig.name));
+    }
+
+    if sig.variadic {
+        return ErrorCode::InvalidCInterface.result(&sig.span, format!("Variadic external function {} cannot take or return structs by value", sig.name));
+    }
+
+    let arg_types: Vec<Type> = sig.args.iter().map(|a| a.typ.clone()).collect();
+    let (passing, _) = c_calling_convention(&arg_types, &sig.return_type, target);
+    match sig.args.iter().zip(passing.iter()).find(|&(_, p)| *p == CPassing::Memory) {
+        Some((arg, _)) => ErrorCode::InvalidCInterface.result(&arg.span,
+            format!("Argument {} of external function {} is passed on the stack by the C calling convention, which is not supported, pass a pointer to it instead",
+                arg.name, sig.name)),
+        None => Ok(()),
+    }
+}
+
 fn resolve_sum_case_types(ctx: &mut TypeCheckerContext, st: &mut SumTypeDeclaration, mode: ResolveMode, target: &Target) -> CompileResult<TypeResolved>
 {
     if st.typ != Type::Unknown {
@@ -147,7 +462,7 @@ fn resolve_sum_case_types(ctx: &mut TypeCheckerContext, st: &mut SumTypeDeclarat
     {
         if let Some(ref mut sd) = c.data
         {
-            if resolve_struct_member_types(ctx, sd, mode)? == TypeResolved::No
+            if resolve_struct_member_types(ctx, sd, mode, target)? == TypeResolved::No
             {
                 return Ok(TypeResolved::No);
             }
@@ -175,7 +490,7 @@ fn resolve_sum_case_types(ctx: &mut TypeCheckerContext, st: &mut SumTypeDeclarat
     Ok(TypeResolved::Yes)
 }
 
-fn resolve_interface_types(ctx: &mut TypeCheckerContext, i: &mut Interface, mode: ResolveMode) -> CompileResult<TypeResolved>
+fn resolve_interface_types(ctx: &mut TypeCheckerContext, i: &mut Interface, mode: ResolveMode, target: &Target) -> CompileResult<TypeResolved>
 {
     if i.typ != Type::Unknown {
         return Ok(TypeResolved::Yes);
@@ -185,7 +500,7 @@ fn resolve_interface_types(ctx: &mut TypeCheckerContext, i: &mut Interface, mode
     let mut functions = Vec::new();
     for func in &mut i.functions
     {
-        if resolve_function_args_and_ret_type(ctx, func, mode)? == TypeResolved::No {
+        if resolve_function_args_and_ret_type(ctx, func, mode, target)? == TypeResolved::No {
             return Ok(TypeResolved::No);
         }
 
@@ -211,10 +526,16 @@ fn resolve_all_types(ctx: &mut TypeCheckerContext, module: &mut Module, mode: Re
     let mut num_resolved = 0;
     for typ in module.types.values_mut()
     {
+        if typ.is_resolved() {
+            // Resolved and added in an earlier pass
+            num_resolved += 1;
+            continue;
+        }
+
         match *typ
         {
             TypeDeclaration::Interface(ref mut i) => {
-                if resolve_interface_types(ctx, i, mode)? == TypeResolved::Yes
+                if resolve_interface_types(ctx, i, mode, target)? == TypeResolved::Yes
                 {
                     ctx.add(Symbol::new(&i.name, &i.typ, false, &i.span, SymbolType::Normal))?;
                     num_resolved += 1;
@@ -222,8 +543,12 @@ fn resolve_all_types(ctx: &mut TypeCheckerContext, module: &mut Module, mode: Re
             },
 
             TypeDeclaration::Struct(ref mut s) => {
-                if resolve_struct_member_types(ctx, s, mode)? == TypeResolved::Yes
+                if resolve_struct_member_types(ctx, s, mode, target)? == TypeResolved::Yes
                 {
+                    if !s.generic_params.is_empty() {
+                        check_generic_params_are_used(&s.name, &s.generic_params, &s.typ, &s.span)?;
+                        ctx.add_generic_params(&s.name, s.generic_params.clone());
+                    }
                     ctx.add(Symbol::new(&s.name, &s.typ, false, &s.span, SymbolType::Normal))?;
                     num_resolved += 1;
                 }
@@ -232,6 +557,10 @@ fn resolve_all_types(ctx: &mut TypeCheckerContext, module: &mut Module, mode: Re
             TypeDeclaration::Sum(ref mut s) => {
                 if resolve_sum_case_types(ctx, s, mode, target)? == TypeResolved::Yes
                 {
+                    if !s.generic_params.is_empty() {
+                        check_generic_params_are_used(&s.name, &s.generic_params, &s.typ, &s.span)?;
+                        ctx.add_generic_params(&s.name, s.generic_params.clone());
+                    }
                     ctx.add(Symbol::new(&s.name, &s.typ, false, &s.span, SymbolType::Normal))?;
                     match s.typ
                     {
@@ -263,8 +592,49 @@ fn resolve_all_types(ctx: &mut TypeCheckerContext, module: &mut Module, mode: Re
     Ok(num_resolved)
 }
 
-pub fn resolve_types(ctx: &mut TypeCheckerContext, module: &mut Module, target: &Target) -> CompileResult<()>
+// Each sum type case with data gets a function which constructs it, so the case can be used as a function value
+fn add_sum_type_constructors(module: &mut Module)
 {
+    let mut constructors = Vec::new();
+    for typ in module.types.values()
+    {
+        if let TypeDeclaration::Sum(ref s) = *typ
+        {
+            for c in &s.cases
+            {
+                let sd = match c.data {
+                    Some(ref sd) => sd,
+                    None => continue,
+                };
+
+                let name = sum_type_case_constructor_name(&c.name);
+                if module.functions.contains_key(&name) {
+                    continue;
+                }
+
+                let args = sd.members.iter()
+                    .map(|m| Argument::new(m.name.clone(), m.typ.clone(), false, m.span.clone()))
+                    .collect();
+                let member_initializers = sd.members.iter()
+                    .map(|m| Expression::NameRef(NameRef::new(m.name.clone(), m.span.clone())))
+                    .collect();
+                let body = Expression::StructInitializer(struct_initializer(&c.name, member_initializers, c.span.clone()));
+                let constructor_sig = sig(&name, s.typ.clone(), args, c.span.clone());
+                constructors.push(Function::new(constructor_sig, true, body, c.span.clone()));
+            }
+        }
+    }
+
+    for f in constructors {
+        module.functions.insert(f.sig.name.clone(), f);
+    }
+}
+
+// Returns the number of declarations which were resolved, declarations resolved in an earlier call are skipped
+pub fn resolve_types(ctx: &mut TypeCheckerContext, module: &mut Module, target: &Target) -> CompileResult<usize>
+{
+    ctx.set_module_types(module.types.keys().cloned().collect());
+    let mut work = module.types.values().filter(|t| !t.is_resolved()).count();
     let mut num_resolved = 0;
     loop
     {
@@ -274,21 +644,50 @@ pub fn resolve_types(ctx: &mut TypeCheckerContext, module: &mut Module, target:
         if num_resolved == module.types.len() {
             break;
         } else if already_resolved == num_resolved {
-            // We weren't able to resolve any in this pass, so something is missing
+            // We weren't able to resolve any in this pass, so types contain each other, or something is missing
+            check_composition_cycles(module)?;
             resolve_all_types(ctx, module, ResolveMode::Forced, target)?;
             break;
         }
     }
 
+    add_sum_type_constructors(module);
+
     for f in module.functions.values_mut() {
-        resolve_function_args_and_ret_type(ctx, &mut f.sig, ResolveMode::Forced)?;
-        ctx.add(Symbol::new(&f.sig.name, &f.sig.typ, false, &f.sig.span, SymbolType::Normal))?;
+        if ctx.is_module_symbol(&f.sig.name) {
+            continue;
+        }
+
+        resolve_function_args_and_ret_type(ctx, &mut f.sig, ResolveMode::Forced, target)?;
+        ctx.add(Symbol::new(&f.sig.name, &f.sig.typ, false, &f.sig.span, SymbolType::Normal).with_args(&f.sig.args))?;
+        work += 1;
     }
 
     for f in module.externals.values_mut() {
-        resolve_function_args_and_ret_type(ctx, &mut f.sig, ResolveMode::Forced)?;
+        if ctx.is_module_symbol(&f.sig.name) {
+            continue;
+        }
+
+        resolve_function_args_and_ret_type(ctx, &mut f.sig, ResolveMode::Forced, target)?;
+        check_external_function_types(&f.sig, target)?;
         ctx.add(Symbol::new(&f.sig.name, &f.sig.typ, false, &f.sig.span, SymbolType::Normal))?;
+        work += 1;
     }
 
-    Ok(())
+    for g in module.globals.values_mut() {
+        if g.type_checked {
+            continue;
+        }
+
+        if resolve_type(ctx, &mut g.typ, target) == TypeResolved::No {
+            check_generic_arg_count(ctx, &g.typ, &g.span)?;
+            return ErrorCode::UnknownName.result(&g.span, format!("Unknown type {} of global {}", g.typ, g.name));
+        }
+
+        if g.typ.contains_void_value() {
+            return ErrorCode::NoValue.result(&g.span, format!("Global {} has type {}, but {}", g.name, g.typ, VOID_HAS_NO_VALUES));
+        }
+    }
+
+    Ok(work)
 }
@@ -0,0 +1,48 @@
+use ast::{Expression, Module, Visitor, walk_expression};
+use compileerror::{CompileResult, print_message};
+use span::Span;
+
+// Collects the first expression after a return in each block, the rest of the block is never executed
+struct UnreachableCodeFinder
+{
+    spans: Vec<Span>,
+}
+
+impl Visitor for UnreachableCodeFinder
+{
+    fn visit_expression(&mut self, e: &Expression) -> CompileResult<()>
+    {
+        if let Expression::Block(ref b) = *e {
+            if let Some(idx) = b.expressions.iter().position(|e| e.diverges()) {
+                if let Some(next) = b.expressions.get(idx + 1) {
+                    self.spans.push(next.span());
+                }
+            }
+        }
+
+        walk_expression(self, e)
+    }
+}
+
+// Outer blocks are visited before the blocks nested in them, so sort the spans in the order of the source
+pub fn unreachable_code(e: &Expression) -> Vec<Span>
+{
+    let mut finder = UnreachableCodeFinder{spans: Vec::new()};
+    let _ = finder.visit_expression(e);
+    finder.spans.sort_by_key(|s| s.start);
+    finder.spans
+}
+
+pub fn warn_about_unreachable_code(module: &Module)
+{
+    // Instances of the same generic function share the spans of its body, so each block is only reported once
+    let mut spans: Vec<Span> = module.functions.values()
+        .flat_map(|f| unreachable_code(&f.expression))
+        .collect();
+    spans.sort_by(|a, b| (&a.file, a.start).cmp(&(&b.file, b.start)));
+    spans.dedup();
+
+    for span in spans {
+        print_message("Warning: unreachable code, it comes after a return", &span);
+    }
+}